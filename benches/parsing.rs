@@ -0,0 +1,153 @@
+use corrodedweb::Server;
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::prelude::*;
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Once;
+use std::thread;
+use std::time::Duration;
+
+/// Counts the allocations of the whole process, server threads included
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const PORT: u16 = 7999;
+
+const REQUEST: &str = "GET /users/42/posts?page=3&sort=desc HTTP/1.1\r\n\
+                       Host: localhost\r\n\
+                       User-Agent: criterion\r\n\
+                       Accept: text/html\r\n\
+                       Accept-Encoding: gzip, deflate\r\n\
+                       Connection: close\r\n\r\n";
+
+static SERVER: Once = Once::new();
+
+fn spawn_server() {
+    SERVER.call_once(start_server);
+}
+
+fn routed_server() -> Server {
+    let mut server = Server::new();
+    server.get("/users/:id/posts", |request, mut response| {
+        let id = request.path_param_str("id").unwrap_or_default();
+        let _ = response.write(id);
+    });
+    server.post("/users/:id/posts", |_request, _response| {});
+    server.get("/about", |_request, _response| {});
+    server
+}
+
+fn start_server() {
+    let server = routed_server();
+    let handle = server.handle();
+    thread::spawn(move || {
+        server.start_server(PORT).unwrap();
+    });
+    handle.wait_until_ready(Duration::from_secs(10)).unwrap();
+}
+
+/// Returns how many allocations `run` makes on average over `runs` calls,
+/// after a few to warm up pools and caches
+fn allocations_per_run<F: FnMut()>(runs: usize, mut run: F) -> f64 {
+    for _ in 0..10 {
+        run();
+    }
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    for _ in 0..runs {
+        run();
+    }
+    (ALLOCATIONS.load(Ordering::Relaxed) - before) as f64 / runs as f64
+}
+
+fn send_request() {
+    let mut stream = TcpStream::connect(("127.0.0.1", PORT)).unwrap();
+    stream.write_all(REQUEST.as_bytes()).unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+}
+
+/// Measures a request handled in memory, which leaves out the network and
+/// is all parsing, routing and writing the response
+fn in_memory(c: &mut Criterion) {
+    let server = routed_server();
+    let request = || {
+        server
+            .test_request("GET", "/users/42/posts?page=3&sort=desc")
+            .header("User-Agent", "criterion")
+            .header("Accept", "text/html")
+            .header("Accept-Encoding", "gzip, deflate")
+            .send()
+    };
+    println!(
+        "parsing/in_memory_get: {:.1} allocations per request",
+        allocations_per_run(1000, || {
+            request();
+        })
+    );
+
+    let mut group = c.benchmark_group("parsing");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("in_memory_get", |b| b.iter(request));
+    group.finish();
+}
+
+/// Measures a full loopback round trip, which is dominated by request
+/// parsing and route lookup since the handler does almost nothing
+///
+/// The allocations counted include those of the client and of accepting
+/// the connection.
+fn round_trip(c: &mut Criterion) {
+    spawn_server();
+    println!(
+        "parsing/routed_get: {:.1} allocations per request",
+        allocations_per_run(1000, send_request)
+    );
+
+    let mut group = c.benchmark_group("parsing");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("routed_get", |b| b.iter(send_request));
+    group.finish();
+}
+
+/// Measures eight clients hitting cheap handlers at the same time, which
+/// shows contention on state shared between the workers
+fn concurrent_round_trips(c: &mut Criterion) {
+    const CLIENTS: u64 = 8;
+    spawn_server();
+
+    let mut group = c.benchmark_group("parsing");
+    group.throughput(Throughput::Elements(CLIENTS));
+    group.bench_function("concurrent_routed_get", |b| {
+        b.iter(|| {
+            let clients: Vec<_> = (0..CLIENTS).map(|_| thread::spawn(send_request)).collect();
+            for client in clients {
+                client.join().unwrap();
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, in_memory, round_trip, concurrent_round_trips);
+criterion_main!(benches);
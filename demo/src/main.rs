@@ -1,6 +1,8 @@
 use corrodedweb::*;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 fn main() {
     let mut server = Server::new();
@@ -8,25 +10,9 @@ fn main() {
     server.use_index_of(true);
     server.set_logger("server.log");
 
-    server.get("/parameter_demo/", |request, mut response| {
-        let _ = response.set_status_code(200);
-        let _ = response.write(
-            "<html>
-            Servus<br><br>
-            QUERY Parameters
-            <ul>",
-        );
-        for (k, v) in request.get_query_parameters().iter() {
-            let _ = response.write(&format!("<li><b>{}</b> {}</li>", k, v));
-        }
-        let _ = response.write(
-            "</ul><br>
-            <form action='' method='POST'>
-            First name: <input type='text' name='fname'><br><br>
-            Last name: <input type='text' name='lname'><br><br>
-            <input type='submit' value='Submit'></form>
-            </html>",
-        );
+    server.get("/parameter_demo/", parameter_demo);
+    server.get("/demo/", |_request, mut response| {
+        let _ = response.redirect("/parameter_demo/");
     });
 
     let counter = Arc::new(AtomicUsize::new(0));
@@ -34,10 +20,28 @@ fn main() {
     server.get("/counter/", move |_request, mut response| {
         let _ = response.set_status_code(200);
         let old = counter.load(Ordering::SeqCst);
-        let _ = response.write(&format!("<h1>{}</h1>", old));
+        let _ = write!(response, "<h1>{}</h1>", old);
         counter.store(old + 1, Ordering::SeqCst);
     });
 
+    // Open http://localhost:7878/clock/ with `new EventSource(...)` or curl
+    server.get("/clock/", |_request, mut response| {
+        let mut events = match response.start_event_stream() {
+            Ok(events) => events,
+            Err(_) => return,
+        };
+        loop {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs());
+            if events.send_event("time", &now.to_string()).is_err() {
+                // The browser tab was closed
+                return;
+            }
+            thread::sleep(Duration::from_secs(1));
+        }
+    });
+
     server.post("/parameter_demo/", |request, mut response| {
         let _ = response.set_status_code(200);
         let _ = response.write(
@@ -46,16 +50,16 @@ fn main() {
             POST Parameters
             <ul>",
         );
-        for (k, v) in request.get_post_parameters().iter() {
-            let _ = response.write(&format!("<li><b>{}</b> {}</li>", k, v));
+        for (k, v) in request.form_pairs() {
+            let _ = write!(response, "<li><b>{}</b> {}</li>", k, v);
         }
         let _ = response.write(
             "</ul><br>
             QUERY Parameters
             <ul>",
         );
-        for (k, v) in request.get_query_parameters().iter() {
-            let _ = response.write(&format!("<li><b>{}</b> {}</li>", k, v));
+        for (k, v) in request.query_pairs() {
+            let _ = write!(response, "<li><b>{}</b> {}</li>", k, v);
         }
         let _ = response.write(
             "<ul>
@@ -72,5 +76,55 @@ fn main() {
         println!("Hello");
     });
 
-    server.start_server(7878);
+    server.start_server(7878).unwrap();
+}
+
+/// Lists the query parameters in the order they were sent, with a form to
+/// POST more
+fn parameter_demo(request: Request, mut response: Response) {
+    let _ = response.set_status_code(200);
+    let _ = write!(
+        response,
+        "<html>
+        Servus<br><br>
+        {} {} {} from {:?}<br><br>
+        QUERY Parameters
+        <ul>",
+        request.method(),
+        request.full_url().as_deref().unwrap_or(request.path()),
+        request.http_version(),
+        request.client_ip()
+    );
+    for (k, v) in request.query_pairs() {
+        let _ = write!(response, "<li><b>{}</b> {}</li>", k, v);
+    }
+    let _ = response.write(
+        "</ul><br>
+        <form action='' method='POST'>
+        First name: <input type='text' name='fname'><br><br>
+        Last name: <input type='text' name='lname'><br><br>
+        <input type='submit' value='Submit'></form>
+        </html>",
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parameter_demo() {
+        let request = Request::builder()
+            .path("/parameter_demo/")
+            .query("b=2&a=1")
+            .build();
+        let (response, captured) = Response::capture();
+        parameter_demo(request, response);
+        let (status, _headers, body) = captured.into_parts();
+        assert_eq!(status, 200);
+        let body = String::from_utf8(body).unwrap();
+        let b = body.find("<li><b>b</b> 2</li>").unwrap();
+        let a = body.find("<li><b>a</b> 1</li>").unwrap();
+        assert!(b < a);
+    }
 }
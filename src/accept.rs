@@ -0,0 +1,270 @@
+/// One media range of an `Accept` header, like `text/*;q=0.5`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaRange {
+    main_type: String,
+    subtype: String,
+    parameters: Vec<(String, String)>,
+    /// The q-value in thousandths, so comparisons are exact
+    quality: u16,
+}
+
+impl MediaRange {
+    /// Parses a range like `text/html;level=1;q=0.7`, `None` if it is not
+    /// of the form `type/subtype` or has an invalid q-value
+    fn parse(range: &str) -> Option<Self> {
+        let mut parts = range.split(';');
+        let (main_type, subtype) = parts.next()?.trim().split_once('/')?;
+        let (main_type, subtype) = (main_type.trim(), subtype.trim());
+        if main_type.is_empty() || subtype.is_empty() || (main_type == "*" && subtype != "*") {
+            return None;
+        }
+        let mut parameters = Vec::new();
+        let mut quality = 1000;
+        for parameter in parts {
+            let (name, value) = match parameter.split_once('=') {
+                Some((name, value)) => (name.trim(), value.trim().trim_matches('"')),
+                None => continue,
+            };
+            if name.eq_ignore_ascii_case("q") {
+                quality = parse_quality(value)?;
+                // Anything after q are accept-extensions, not media type
+                // parameters
+                break;
+            }
+            parameters.push((name.to_ascii_lowercase(), String::from(value)));
+        }
+        Some(MediaRange {
+            main_type: main_type.to_ascii_lowercase(),
+            subtype: subtype.to_ascii_lowercase(),
+            parameters,
+            quality,
+        })
+    }
+
+    /// Returns the range without parameters, e.g. `text/*`
+    pub fn media_type(&self) -> String {
+        format!("{}/{}", self.main_type, self.subtype)
+    }
+
+    /// Returns the media type parameters besides `q`, names in lowercase
+    pub fn parameters(&self) -> &[(String, String)] {
+        &self.parameters
+    }
+
+    /// Returns the q-value, between 0 and 1
+    pub fn quality(&self) -> f32 {
+        f32::from(self.quality) / 1000.0
+    }
+
+    /// Returns whether `media` falls into this range
+    fn matches(&self, media: &MediaRange) -> bool {
+        (self.main_type == "*" || self.main_type == media.main_type)
+            && (self.subtype == "*" || self.subtype == media.subtype)
+            && self.parameters.iter().all(|(name, value)| {
+                media
+                    .parameters
+                    .iter()
+                    .any(|(other, other_value)| other == name && other_value == value)
+            })
+    }
+
+    /// More specific ranges override less specific ones, e.g. `text/html`
+    /// overrides `text/*`, which overrides `*/*`
+    fn specificity(&self) -> (bool, bool, usize) {
+        (
+            self.main_type != "*",
+            self.subtype != "*",
+            self.parameters.len(),
+        )
+    }
+}
+
+/// Parses a q-value like `0.5` into thousandths
+pub(crate) fn parse_quality(value: &str) -> Option<u16> {
+    let (whole, fraction) = value.split_once('.').unwrap_or((value, ""));
+    if fraction.len() > 3 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let fraction = format!("{:0<3}", fraction).parse::<u16>().ok()?;
+    match whole {
+        "0" => Some(fraction),
+        "1" if fraction == 0 => Some(1000),
+        _ => None,
+    }
+}
+
+/// The media ranges of an `Accept` header, returned by `Request::accepts`
+///
+/// A request without the header accepts anything.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AcceptList {
+    /// `None` without an `Accept` header
+    ranges: Option<Vec<MediaRange>>,
+}
+
+impl AcceptList {
+    /// Parses the value of an `Accept` header, skipping invalid ranges
+    pub fn parse(header: Option<&str>) -> Self {
+        let ranges = header
+            .filter(|header| !header.trim().is_empty())
+            .map(|header| header.split(',').filter_map(MediaRange::parse).collect());
+        AcceptList { ranges }
+    }
+
+    /// Returns the media ranges in the order they were sent, empty if there
+    /// was no `Accept` header
+    pub fn ranges(&self) -> &[MediaRange] {
+        self.ranges.as_deref().unwrap_or(&[])
+    }
+
+    /// Returns how much the client wants `media_type`, e.g. `text/html` or
+    /// `text/html;level=1`, between 0 (not at all) and 1
+    ///
+    /// The q-value of the most specific range matching it counts, as RFC
+    /// 7231 section 5.3.2 describes.
+    pub fn quality(&self, media_type: &str) -> f32 {
+        f32::from(self.quality_of(media_type)) / 1000.0
+    }
+
+    /// Returns the entry of `offered` the client wants most, or `None` if it
+    /// accepts none of them
+    ///
+    /// Among equally wanted types the earlier entry wins, so `offered` is
+    /// the order the server prefers.
+    pub fn preferred<'a>(&self, offered: &[&'a str]) -> Option<&'a str> {
+        let mut best: Option<(&str, u16)> = None;
+        for media_type in offered {
+            let quality = self.quality_of(media_type);
+            if quality > 0 && best.is_none_or(|(_, best)| quality > best) {
+                best = Some((media_type, quality));
+            }
+        }
+        best.map(|(media_type, _)| media_type)
+    }
+
+    fn quality_of(&self, media_type: &str) -> u16 {
+        let ranges = match &self.ranges {
+            Some(ranges) => ranges,
+            None => return 1000,
+        };
+        let media = match MediaRange::parse(media_type) {
+            Some(media) => media,
+            None => return 0,
+        };
+        ranges
+            .iter()
+            .filter(|range| range.matches(&media))
+            .max_by_key(|range| range.specificity())
+            .map_or(0, |range| range.quality)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rfc_7231_quality() {
+        // The example of RFC 7231 section 5.3.2
+        let accept = AcceptList::parse(Some(
+            "text/*;q=0.3, text/html;q=0.7, text/html;level=1, text/html;level=2;q=0.4, */*;q=0.5",
+        ));
+        let expected = [
+            ("text/html;level=1", 1.0),
+            ("text/html", 0.7),
+            ("text/plain", 0.3),
+            ("image/jpeg", 0.5),
+            ("text/html;level=2", 0.4),
+            ("text/html;level=3", 0.7),
+        ];
+        for (media_type, quality) in expected.iter() {
+            assert_eq!(accept.quality(media_type), *quality, "{}", media_type);
+        }
+    }
+
+    #[test]
+    fn test_rfc_7231_preferred() {
+        // "I prefer audio/basic, but send me any audio type if it is the
+        // best available after an 80% markdown in quality"
+        let accept = AcceptList::parse(Some("audio/*; q=0.2, audio/basic"));
+        assert_eq!(
+            accept.preferred(&["audio/mpeg", "audio/basic"]),
+            Some("audio/basic")
+        );
+        assert_eq!(accept.preferred(&["audio/mpeg"]), Some("audio/mpeg"));
+        assert_eq!(accept.preferred(&["video/mp4"]), None);
+
+        // "text/html and text/x-c are the equally preferred media types, but
+        // if they do not exist, then send the text/x-dvi representation, and
+        // if that does not exist, send the text/plain representation"
+        let accept = AcceptList::parse(Some(
+            "text/plain; q=0.5, text/html,\r\n       text/x-dvi; q=0.8, text/x-c",
+        ));
+        assert_eq!(
+            accept.preferred(&["text/plain", "text/x-dvi", "text/x-c", "text/html"]),
+            Some("text/x-c")
+        );
+        assert_eq!(
+            accept.preferred(&["text/plain", "text/x-dvi"]),
+            Some("text/x-dvi")
+        );
+        assert_eq!(accept.preferred(&["text/plain"]), Some("text/plain"));
+    }
+
+    #[test]
+    fn test_preferred() {
+        let offered = ["text/html", "application/json"];
+        // Browsers
+        let accept = AcceptList::parse(Some(
+            "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8",
+        ));
+        assert_eq!(accept.preferred(&offered), Some("text/html"));
+        // API clients
+        let accept = AcceptList::parse(Some("application/json"));
+        assert_eq!(accept.preferred(&offered), Some("application/json"));
+        // curl sends */*, the server's order decides
+        let accept = AcceptList::parse(Some("*/*"));
+        assert_eq!(accept.preferred(&offered), Some("text/html"));
+        // Specificity before q-value: JSON is explicitly excluded
+        let accept = AcceptList::parse(Some("*/*;q=0.9, application/json;q=0"));
+        assert_eq!(
+            accept.preferred(&["application/json", "text/html"]),
+            Some("text/html")
+        );
+        // Case-insensitive
+        let accept = AcceptList::parse(Some("Application/JSON"));
+        assert_eq!(accept.preferred(&offered), Some("application/json"));
+    }
+
+    #[test]
+    fn test_missing_and_invalid() {
+        let anything = AcceptList::parse(None);
+        assert_eq!(anything.quality("image/png"), 1.0);
+        assert_eq!(
+            anything.preferred(&["text/html", "application/json"]),
+            Some("text/html")
+        );
+        assert!(anything.ranges().is_empty());
+        assert_eq!(AcceptList::parse(Some("  ")), anything);
+
+        let accept = AcceptList::parse(Some("text/html;q=2, nonsense, */html, image/png;q=0.123"));
+        assert_eq!(accept.ranges().len(), 1);
+        assert_eq!(accept.ranges()[0].media_type(), "image/png");
+        assert_eq!(accept.ranges()[0].quality(), 0.123);
+        assert_eq!(accept.preferred(&["text/html"]), None);
+        assert_eq!(accept.preferred(&[]), None);
+    }
+
+    #[test]
+    fn test_parse_quality() {
+        assert_eq!(parse_quality("1"), Some(1000));
+        assert_eq!(parse_quality("1.000"), Some(1000));
+        assert_eq!(parse_quality("0"), Some(0));
+        assert_eq!(parse_quality("0.5"), Some(500));
+        assert_eq!(parse_quality("0.05"), Some(50));
+        assert_eq!(parse_quality("1.5"), None);
+        assert_eq!(parse_quality("0.1234"), None);
+        assert_eq!(parse_quality("-0.5"), None);
+        assert_eq!(parse_quality("high"), None);
+    }
+}
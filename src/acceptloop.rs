@@ -0,0 +1,110 @@
+use crate::logger::Logger;
+use std::io;
+use std::io::ErrorKind;
+use std::time::{Duration, Instant};
+
+/// How long an accept loop pauses after an error it cannot retry right away
+pub(crate) const ACCEPT_BACKOFF: Duration = Duration::from_millis(10);
+
+/// What an accept loop does after accepting a connection failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AcceptAction {
+    /// Accept again right away, e.g. after a signal interrupted the call
+    Retry,
+    /// Pause for `ACCEPT_BACKOFF` before accepting again, e.g. while the
+    /// process is out of file descriptors
+    Backoff,
+    /// The listener is unusable, stop serving
+    Fatal,
+}
+
+/// Returns what to do after accepting failed with an error of `kind`
+pub(crate) fn action_for(kind: ErrorKind) -> AcceptAction {
+    match kind {
+        // The client gave up before the connection was accepted
+        ErrorKind::Interrupted
+        | ErrorKind::WouldBlock
+        | ErrorKind::ConnectionAborted
+        | ErrorKind::ConnectionReset => AcceptAction::Retry,
+        // The socket is not listening (anymore)
+        ErrorKind::InvalidInput => AcceptAction::Fatal,
+        // EMFILE and ENFILE have no kind of their own, and like network
+        // errors Linux passes on they are expected to go away
+        _ => AcceptAction::Backoff,
+    }
+}
+
+/// Handles the errors of one accept loop, so running out of file
+/// descriptors neither spins the loop nor floods the log
+#[derive(Debug, Default)]
+pub(crate) struct AcceptErrors {
+    last_warning: Option<Instant>,
+}
+
+impl AcceptErrors {
+    /// Logs `error` and returns what the loop should do
+    ///
+    /// Errors the loop backs off from are logged at most once a second.
+    pub(crate) fn handle(&mut self, error: &io::Error, logger: &Option<Logger>) -> AcceptAction {
+        let action = action_for(error.kind());
+        match action {
+            AcceptAction::Retry => {}
+            AcceptAction::Backoff => {
+                if self
+                    .last_warning
+                    .is_none_or(|last| last.elapsed() >= Duration::from_secs(1))
+                {
+                    Logger::warning(logger, &format!("Could not accept connection: {}", error));
+                    self.last_warning = Some(Instant::now());
+                }
+            }
+            AcceptAction::Fatal => {
+                Logger::warning(
+                    logger,
+                    &format!("Could not accept connections, stopping: {}", error),
+                );
+            }
+        }
+        action
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_action_for() {
+        assert_eq!(action_for(ErrorKind::Interrupted), AcceptAction::Retry);
+        assert_eq!(
+            action_for(ErrorKind::ConnectionAborted),
+            AcceptAction::Retry
+        );
+        assert_eq!(action_for(ErrorKind::InvalidInput), AcceptAction::Fatal);
+        assert_eq!(action_for(ErrorKind::OutOfMemory), AcceptAction::Backoff);
+        // What EMFILE and ENFILE turn into
+        let emfile = io::Error::from_raw_os_error(24);
+        assert_eq!(action_for(emfile.kind()), AcceptAction::Backoff);
+        assert_eq!(action_for(ErrorKind::Other), AcceptAction::Backoff);
+    }
+
+    #[test]
+    fn test_warnings_are_rate_limited() {
+        let dir = std::env::temp_dir().join("corrodedweb_accept_errors");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("accept.log");
+        let logger = Some(Logger::new(path.to_str().unwrap()));
+
+        let mut errors = AcceptErrors::default();
+        let emfile = io::Error::other("Too many open files");
+        for _ in 0..100 {
+            assert_eq!(errors.handle(&emfile, &logger), AcceptAction::Backoff);
+        }
+        let interrupted = io::Error::from(ErrorKind::Interrupted);
+        assert_eq!(errors.handle(&interrupted, &logger), AcceptAction::Retry);
+        Logger::flush(&logger);
+        let log = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(log.matches("Could not accept connection").count(), 1);
+    }
+}
@@ -0,0 +1,208 @@
+use crate::logger::Logger;
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// The name of the file holding the directives of its directory
+pub(crate) const ACCESS_FILE: &str = ".corroded";
+
+/// The directives in effect for a directory
+///
+/// Every directive is optional, one a directory does not set is inherited
+/// from its parent.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct AccessRules {
+    /// `deny from all` or `allow from all`
+    pub deny: Option<bool>,
+    /// `index on` or `index off`, whether directories get a listing
+    pub index: Option<bool>,
+    /// `require auth` or `require none`
+    pub require_auth: Option<bool>,
+    /// `header Name: value`, added to every response
+    pub headers: Vec<(String, String)>,
+}
+
+impl AccessRules {
+    /// Parses the content of an access file, failing on the first line that
+    /// is no directive. Empty lines and lines starting with `#` are skipped.
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let mut rules = AccessRules::default();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || format!("line {}: invalid directive {:?}", number + 1, line);
+            let (directive, argument) = line
+                .split_once(char::is_whitespace)
+                .map_or((line, ""), |(directive, argument)| {
+                    (directive, argument.trim())
+                });
+            if directive.eq_ignore_ascii_case("header") {
+                let (name, value) = argument.split_once(':').ok_or_else(invalid)?;
+                let (name, value) = (name.trim(), value.trim());
+                if crate::server::check_header(name, value).is_err() {
+                    return Err(invalid());
+                }
+                rules.set_header(name, value);
+                continue;
+            }
+            let argument = argument
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ")
+                .to_ascii_lowercase();
+            match (directive.to_ascii_lowercase().as_str(), argument.as_str()) {
+                ("deny", "from all") => rules.deny = Some(true),
+                ("allow", "from all") => rules.deny = Some(false),
+                ("index", "on") => rules.index = Some(true),
+                ("index", "off") => rules.index = Some(false),
+                ("require", "auth") => rules.require_auth = Some(true),
+                ("require", "none") => rules.require_auth = Some(false),
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(rules)
+    }
+
+    /// Applies the directives of a subdirectory on top of these
+    fn inherit(&mut self, deeper: &AccessRules) {
+        self.deny = deeper.deny.or(self.deny);
+        self.index = deeper.index.or(self.index);
+        self.require_auth = deeper.require_auth.or(self.require_auth);
+        for (name, value) in &deeper.headers {
+            self.set_header(name, value);
+        }
+    }
+
+    fn set_header(&mut self, name: &str, value: &str) {
+        self.headers
+            .retain(|(other, _)| !other.eq_ignore_ascii_case(name));
+        self.headers.push((String::from(name), String::from(value)));
+    }
+}
+
+/// The modification time and length of an access file, which decide
+/// whether it has to be parsed again
+type Version = (Option<SystemTime>, u64);
+
+/// What an access file said, or why it could not be read
+type Parsed = Result<AccessRules, String>;
+
+/// Reads the access files of directories and remembers what they said
+/// until they change
+#[derive(Debug, Default)]
+pub(crate) struct AccessFiles {
+    parsed: Mutex<HashMap<PathBuf, (Version, Parsed)>>,
+}
+
+impl AccessFiles {
+    /// Returns the rules for `directory`, collected from the access files of
+    /// `root` down to it
+    ///
+    /// Fails if one of the files cannot be read or parsed, the error is
+    /// logged whenever the file is read.
+    pub fn rules(
+        &self,
+        root: &Path,
+        directory: &Path,
+        logger: &Option<Logger>,
+    ) -> Result<AccessRules, String> {
+        let mut rules = self.read(root, logger)?;
+        let mut current = root.to_path_buf();
+        let relative = directory
+            .strip_prefix(root)
+            .unwrap_or_else(|_| Path::new(""));
+        for component in relative.components() {
+            current.push(component);
+            rules.inherit(&self.read(&current, logger)?);
+        }
+        Ok(rules)
+    }
+
+    /// Returns the directives of the access file in `directory`, parsing it
+    /// only if it changed since the last time
+    fn read(&self, directory: &Path, logger: &Option<Logger>) -> Parsed {
+        let path = directory.join(ACCESS_FILE);
+        let metadata = match fs::metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::NotADirectory) => {
+                return Ok(AccessRules::default())
+            }
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+        let version = (metadata.modified().ok(), metadata.len());
+        if let Some((parsed, result)) = self.parsed.lock().unwrap().get(directory) {
+            if *parsed == version {
+                return result.clone();
+            }
+        }
+        let result = fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|content| AccessRules::parse(&content))
+            .map_err(|e| format!("{}: {}", path.display(), e));
+        if let Err(e) = &result {
+            Logger::warning(logger, &format!("Not serving the directory of {}", e));
+        }
+        self.parsed
+            .lock()
+            .unwrap()
+            .insert(directory.to_path_buf(), (version, result.clone()));
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let rules = AccessRules::parse(
+            "# Private\n\ndeny  from   all\nIndex Off\nrequire auth\nheader X-Foo: bar: baz\n",
+        )
+        .unwrap();
+        assert_eq!(
+            rules,
+            AccessRules {
+                deny: Some(true),
+                index: Some(false),
+                require_auth: Some(true),
+                headers: vec![(String::from("X-Foo"), String::from("bar: baz"))],
+            }
+        );
+        assert_eq!(AccessRules::parse("").unwrap(), AccessRules::default());
+        for invalid in [
+            "deny",
+            "deny from some",
+            "index maybe",
+            "header X-Foo",
+            "header X Foo: bar",
+            "allow from all\nredirect /",
+        ] {
+            assert!(AccessRules::parse(invalid).is_err(), "{}", invalid);
+        }
+        assert!(AccessRules::parse("allow from all\nredirect /")
+            .unwrap_err()
+            .starts_with("line 2:"));
+    }
+
+    #[test]
+    fn test_inherit() {
+        let mut rules = AccessRules::parse("deny from all\nheader X-A: 1\nheader X-B: 1").unwrap();
+        rules.inherit(&AccessRules::parse("allow from all\nindex off\nheader x-b: 2").unwrap());
+        assert_eq!(rules.deny, Some(false));
+        assert_eq!(rules.index, Some(false));
+        assert_eq!(rules.require_auth, None);
+        assert_eq!(
+            rules.headers,
+            vec![
+                (String::from("X-A"), String::from("1")),
+                (String::from("x-b"), String::from("2")),
+            ]
+        );
+    }
+}
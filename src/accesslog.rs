@@ -0,0 +1,267 @@
+use crate::httpdate;
+use crate::reader;
+use crate::server::Request;
+use crate::stream::{Stream, Tallied};
+use crate::transfer;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::prelude::*;
+use std::net::IpAddr;
+use std::path::Path;
+use std::str;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How much of a response head is looked at for its status
+const MAX_NOTED_HEAD: usize = 64 * 1024;
+
+/// Writes a line per answered request in the Combined Log Format, see
+/// `Server::set_access_log`
+#[derive(Clone)]
+pub(crate) struct AccessLog {
+    file: Arc<Mutex<File>>,
+}
+
+/// What was written of a response, noted while it is written
+#[derive(Debug, Default)]
+pub(crate) struct Tally {
+    /// The head so far, until it is complete
+    head: Vec<u8>,
+    /// Set once the final head is complete, interim 1xx ones skipped
+    status: Option<u32>,
+    /// The bytes written after the final head
+    body_len: u64,
+    /// Set if the client closed the connection while it was written to
+    aborted: bool,
+}
+
+/// A request whose line is written once it is answered
+pub(crate) struct Pending {
+    client: Option<IpAddr>,
+    request_line: String,
+    referer: Option<String>,
+    user_agent: Option<String>,
+    received: SystemTime,
+    started: Instant,
+    tally: Arc<Mutex<Tally>>,
+}
+
+impl AccessLog {
+    /// Opens the log at `path` for appending, creating it if needed
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(AccessLog {
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+
+    /// Starts the entry of `request`, read since `started`, whose response
+    /// is noted in `tally`
+    pub fn start(
+        request: &Request,
+        request_line: String,
+        started: Instant,
+        tally: Arc<Mutex<Tally>>,
+    ) -> Pending {
+        Pending {
+            client: request.client_ip(),
+            request_line,
+            referer: request.get_header("Referer").map(String::from),
+            user_agent: request.get_header("User-Agent").map(String::from),
+            received: SystemTime::now(),
+            started,
+            tally,
+        }
+    }
+
+    /// Writes the line of a request that was answered
+    pub fn finish(&self, pending: Pending) {
+        let line = {
+            let tally = pending.tally.lock().unwrap();
+            format_line(
+                &pending,
+                tally.status,
+                tally.body_len,
+                tally.aborted,
+                pending.started.elapsed(),
+            )
+        };
+        if let Ok(mut file) = self.file.lock() {
+            if let Err(e) = file.write_all(line.as_bytes()) {
+                eprintln!("Couldn't write to access log: {}", e);
+            }
+        }
+    }
+}
+
+impl Tally {
+    /// Returns the stream a response is to be written to so it is noted in
+    /// the returned tally
+    pub fn wrap(stream: Stream) -> (Arc<Mutex<Tally>>, Stream) {
+        let tally = Arc::new(Mutex::new(Tally::default()));
+        let stream = Stream::Tallied(Tallied {
+            inner: Box::new(stream),
+            tally: tally.clone(),
+        });
+        (tally, stream)
+    }
+
+    /// Returns the status of the final head, `None` until it was written
+    /// or if it had none
+    pub fn status(&self) -> Option<u32> {
+        self.status.filter(|status| *status != 0)
+    }
+
+    /// Returns how many bytes were written after the final head
+    pub fn body_len(&self) -> u64 {
+        self.body_len
+    }
+
+    /// Notes a write that failed, a client abort if the client went away
+    pub fn note_error(&mut self, error: &io::Error) {
+        if transfer::is_disconnect(error) {
+            self.aborted = true;
+        }
+    }
+
+    /// Notes bytes of the response that were written
+    pub fn note(&mut self, written: &[u8]) {
+        if self.status.is_some() {
+            self.body_len += written.len() as u64;
+            return;
+        }
+        if self.head.len() >= MAX_NOTED_HEAD {
+            return;
+        }
+        self.head.extend_from_slice(written);
+        while let Some(len) = reader::head_len(&self.head) {
+            if self.head.get(9) != Some(&b'1') {
+                let status = self
+                    .head
+                    .get(9..12)
+                    .and_then(|code| str::from_utf8(code).ok());
+                self.status = Some(status.and_then(|code| code.parse().ok()).unwrap_or(0));
+                self.body_len = (self.head.len() - len) as u64;
+                self.head = Vec::new();
+                return;
+            }
+            self.head.drain(..len);
+        }
+    }
+}
+
+/// Returns the line of an answered request ending in a newline,
+/// `%h %l %u %t "%r" %>s %b "%{Referer}i" "%{User-agent}i" %D` as Apache
+/// puts it, the duration in microseconds, followed by `client-abort` if the
+/// client went away before the response was written
+fn format_line(
+    pending: &Pending,
+    status: Option<u32>,
+    body_len: u64,
+    aborted: bool,
+    took: Duration,
+) -> String {
+    let client = pending
+        .client
+        .map_or_else(|| String::from("-"), |ip| ip.to_string());
+    let status = status
+        .filter(|status| *status != 0)
+        .map_or_else(|| String::from("-"), |status| status.to_string());
+    let body_len = match body_len {
+        0 => String::from("-"),
+        len => len.to_string(),
+    };
+    format!(
+        "{} - - [{}] \"{}\" {} {} \"{}\" \"{}\" {}{}\n",
+        client,
+        httpdate::format_log_date(pending.received),
+        escape(&pending.request_line),
+        status,
+        body_len,
+        pending
+            .referer
+            .as_deref()
+            .map_or_else(|| String::from("-"), escape),
+        pending
+            .user_agent
+            .as_deref()
+            .map_or_else(|| String::from("-"), escape),
+        took.as_micros(),
+        if aborted { " client-abort" } else { "" }
+    )
+}
+
+/// Escapes quotes, backslashes and control characters the way Apache does,
+/// so a client cannot break a line or a quoted field
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\x{:02x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    fn pending(referer: Option<&str>) -> Pending {
+        Pending {
+            client: Some(IpAddr::from([192, 0, 2, 7])),
+            request_line: String::from("GET /a\"b HTTP/1.1"),
+            referer: referer.map(String::from),
+            user_agent: Some(String::from("curl/8.0\n")),
+            received: UNIX_EPOCH + Duration::from_secs(971_185_336),
+            started: Instant::now(),
+            tally: Arc::default(),
+        }
+    }
+
+    #[test]
+    fn test_format_line() {
+        assert_eq!(
+            format_line(
+                &pending(Some("http://a.example/")),
+                Some(200),
+                42,
+                false,
+                Duration::from_micros(1500)
+            ),
+            "192.0.2.7 - - [10/Oct/2000:13:42:16 +0000] \"GET /a\\\"b HTTP/1.1\" 200 42 \
+             \"http://a.example/\" \"curl/8.0\\x0a\" 1500\n"
+        );
+        assert_eq!(
+            format_line(&pending(None), None, 0, false, Duration::from_micros(3)),
+            "192.0.2.7 - - [10/Oct/2000:13:42:16 +0000] \"GET /a\\\"b HTTP/1.1\" - - \
+             \"-\" \"curl/8.0\\x0a\" 3\n"
+        );
+        assert_eq!(
+            format_line(
+                &pending(None),
+                Some(200),
+                512,
+                true,
+                Duration::from_micros(7)
+            ),
+            "192.0.2.7 - - [10/Oct/2000:13:42:16 +0000] \"GET /a\\\"b HTTP/1.1\" 200 512 \
+             \"-\" \"curl/8.0\\x0a\" 7 client-abort\n"
+        );
+    }
+
+    #[test]
+    fn test_tally() {
+        let mut tally = Tally::default();
+        tally.note(b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 404 Not");
+        assert_eq!(tally.status, None);
+        tally.note(b" Found\r\nContent-Length: 5\r\n\r\nab");
+        tally.note(b"cde");
+        assert_eq!(tally.status, Some(404));
+        assert_eq!(tally.body_len, 5);
+    }
+}
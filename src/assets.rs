@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+use std::time::SystemTime;
+
+/// How many hex digits of the content hash go into a file name
+const HASH_LEN: usize = 10;
+
+/// The static files whose URLs carry a hash of their content, see
+/// `Server::enable_asset_fingerprinting`
+///
+/// Hashes are computed when first asked for and again once the length or
+/// modification time of a file changed.
+#[derive(Debug, Default)]
+pub(crate) struct Assets {
+    /// The path prefix of the fingerprinted files, ending in `/`
+    prefix: RwLock<Option<String>>,
+    hashes: Mutex<HashMap<PathBuf, Fingerprint>>,
+}
+
+#[derive(Debug)]
+struct Fingerprint {
+    len: u64,
+    modified: Option<SystemTime>,
+    hash: String,
+}
+
+impl Assets {
+    /// Fingerprints the files below `prefix`
+    pub fn enable(&self, prefix: &str) {
+        let mut prefix = String::from(prefix);
+        if !prefix.ends_with('/') {
+            prefix.push('/');
+        }
+        *self.prefix.write().unwrap() = Some(prefix);
+    }
+
+    /// Returns whether `path` is below the fingerprinted prefix
+    pub fn covers(&self, path: &str) -> bool {
+        self.prefix
+            .read()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|prefix| path.starts_with(prefix.as_str()))
+    }
+
+    /// Returns the hash of the content of `file`
+    pub fn hash(&self, file: &Path) -> io::Result<String> {
+        let metadata = file.metadata()?;
+        let modified = metadata.modified().ok();
+        if let Some(known) = self.hashes.lock().unwrap().get(file) {
+            if known.len == metadata.len() && known.modified == modified {
+                return Ok(known.hash.clone());
+            }
+        }
+        // Hashed without the lock, a file hashed twice meanwhile is harmless
+        let hash = content_hash(File::open(file)?)?;
+        self.hashes.lock().unwrap().insert(
+            file.to_path_buf(),
+            Fingerprint {
+                len: metadata.len(),
+                modified,
+                hash: hash.clone(),
+            },
+        );
+        Ok(hash)
+    }
+}
+
+/// Returns the first `HASH_LEN` hex digits of the FNV-1a hash of `content`
+fn content_hash(mut content: impl Read) -> io::Result<String> {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut piece = [0; 8 * 1024];
+    loop {
+        let read = match content.read(&mut piece) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        for byte in &piece[..read] {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    Ok(format!("{:016x}", hash)[..HASH_LEN].to_string())
+}
+
+/// Puts `hash` into the file name of `path` before its extension, like
+/// `/static/app.css` to `/static/app.<hash>.css`
+pub(crate) fn fingerprinted(path: &str, hash: &str) -> String {
+    let name_start = path.rfind('/').map_or(0, |slash| slash + 1);
+    match path[name_start..].rfind('.') {
+        Some(dot) if dot > 0 => {
+            let dot = name_start + dot;
+            format!("{}.{}{}", &path[..dot], hash, &path[dot..])
+        }
+        _ => format!("{}.{}", path, hash),
+    }
+}
+
+/// Takes the hash out of a path made by `fingerprinted`, returning the path
+/// of the file and the hash
+pub(crate) fn split_fingerprint(path: &str) -> Option<(String, &str)> {
+    let name_start = path.rfind('/').map_or(0, |slash| slash + 1);
+    let (dir, name) = path.split_at(name_start);
+    let mut parts = name.rsplitn(3, '.');
+    let last = parts.next()?;
+    let before = parts.next()?;
+    if let Some(stem) = parts.next() {
+        if is_hash(before) && !stem.is_empty() {
+            return Some((format!("{}{}.{}", dir, stem, last), before));
+        }
+    }
+    let stem = &name[..name.len() - last.len() - 1];
+    if is_hash(last) && !stem.is_empty() {
+        return Some((format!("{}{}", dir, stem), last));
+    }
+    None
+}
+
+fn is_hash(part: &str) -> bool {
+    part.len() == HASH_LEN
+        && part
+            .bytes()
+            .all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fingerprinted_names() {
+        let cases = [
+            ("/static/app.css", "/static/app.0123456789.css"),
+            ("/static/lib.min.js", "/static/lib.min.0123456789.js"),
+            ("/static/LICENSE", "/static/LICENSE.0123456789"),
+            ("/static/.env", "/static/.env.0123456789"),
+            ("/static/v1.2/a", "/static/v1.2/a.0123456789"),
+        ];
+        for (path, hashed) in &cases {
+            assert_eq!(fingerprinted(path, "0123456789"), *hashed);
+            assert_eq!(
+                split_fingerprint(hashed),
+                Some((String::from(*path), "0123456789"))
+            );
+        }
+        assert_eq!(split_fingerprint("/static/app.css"), None);
+        assert_eq!(split_fingerprint("/static/app.0123456789ab.css"), None);
+        assert_eq!(split_fingerprint("/static/app.012345678G.css"), None);
+        assert_eq!(split_fingerprint("/static/.0123456789"), None);
+    }
+
+    #[test]
+    fn test_hash_follows_content() {
+        let file = std::env::temp_dir().join("corrodedweb_asset_hash.css");
+        std::fs::write(&file, "body {}").unwrap();
+        let assets = Assets::default();
+        let first = assets.hash(&file).unwrap();
+        assert_eq!(first.len(), HASH_LEN);
+        assert_eq!(assets.hash(&file).unwrap(), first);
+        std::fs::write(&file, "body { margin: 0 }").unwrap();
+        assert_ne!(assets.hash(&file).unwrap(), first);
+        let _ = std::fs::remove_file(&file);
+
+        assert!(!assets.covers("/static/app.css"));
+        assets.enable("/static");
+        assert!(assets.covers("/static/app.css"));
+        assert!(!assets.covers("/staticfiles/app.css"));
+    }
+}
@@ -0,0 +1,449 @@
+use crate::acceptloop::{AcceptAction, AcceptErrors, ACCEPT_BACKOFF};
+use crate::bufferpool::BUFFER_SIZE;
+use crate::logger::Logger;
+use crate::metrics::Metrics;
+use crate::reader::{
+    body_continues, check_head, head_len, is_delimited, request_len, HeaderLimits, ReadError,
+    RequestTimeouts,
+};
+use crate::server::{next_connection_id, KeepAlive, HEADERS_TOO_LARGE, REQUEST_TIMEOUT};
+use crate::shutdown::ShutdownHandle;
+use crate::transfer;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, Semaphore};
+
+/// Runs one request, given as the bytes the client sent, through the server
+/// and returns the response it wrote
+pub(crate) type Exchange = Arc<dyn Fn(Vec<u8>, Connection) -> Vec<u8> + Send + Sync>;
+
+/// What the server needs to know about the connection a request came in on
+pub(crate) struct Connection {
+    pub peer_addr: Option<SocketAddr>,
+    pub id: u64,
+    /// How many more requests the connection takes after this one
+    pub remaining_requests: usize,
+    /// Delivers the rest of a body longer than was read with the head
+    pub body_feed: Option<BodyFeed>,
+}
+
+/// What the handler side of a `BodyFeed` asks the connection for
+enum Demand {
+    /// The next bytes that arrive
+    More,
+    /// Nothing more, the body was read to its end, with the bytes that were
+    /// read beyond it
+    Done(Vec<u8>),
+}
+
+/// Hands the rest of a request body from the connection to the blocking
+/// thread running the handler, a piece whenever it asks for one
+///
+/// Nothing is read from the connection until asked for, so a large upload
+/// never piles up in memory. Dropping the feed without `finish` closes the
+/// connection after the response, as its next request cannot be told apart
+/// from the rest of the body.
+pub(crate) struct BodyFeed {
+    demands: mpsc::UnboundedSender<Demand>,
+    pieces: mpsc::Receiver<io::Result<Vec<u8>>>,
+}
+
+impl BodyFeed {
+    /// Waits for the next bytes from the connection, empty once the client
+    /// closed it
+    pub fn next_chunk(&mut self) -> io::Result<Vec<u8>> {
+        if self.demands.send(Demand::More).is_err() {
+            return Ok(Vec::new());
+        }
+        self.pieces
+            .blocking_recv()
+            .unwrap_or_else(|| Ok(Vec::new()))
+    }
+
+    /// Tells that the body was read to its end and `leftover` starts the
+    /// next request, so the connection can continue
+    pub fn finish(self, leftover: Vec<u8>) {
+        let _ = self.demands.send(Demand::Done(leftover));
+    }
+}
+
+/// The connection side of a `BodyFeed`, reading for it until it is done
+struct BodyPump {
+    tcp: TcpStream,
+    /// Bytes that arrived together with the request
+    pending: Vec<u8>,
+    demands: mpsc::UnboundedReceiver<Demand>,
+    pieces: mpsc::Sender<io::Result<Vec<u8>>>,
+}
+
+/// Returns the two sides of a feed for the rest of a body on `tcp`, starting
+/// with the `pending` bytes already read
+fn body_feed(tcp: TcpStream, pending: Vec<u8>) -> (BodyFeed, BodyPump) {
+    let (demand, demands) = mpsc::unbounded_channel();
+    let (piece, pieces) = mpsc::channel(1);
+    let feed = BodyFeed {
+        demands: demand,
+        pieces,
+    };
+    let pump = BodyPump {
+        tcp,
+        pending,
+        demands,
+        pieces: piece,
+    };
+    (feed, pump)
+}
+
+impl BodyPump {
+    /// Reads whenever the feed asks, returning the connection and the start
+    /// of the next request, `None` if the body was not read to its end
+    async fn run(mut self) -> (TcpStream, Option<Vec<u8>>) {
+        while let Some(demand) = self.demands.recv().await {
+            let piece = match demand {
+                Demand::Done(leftover) => return (self.tcp, Some(leftover)),
+                Demand::More if !self.pending.is_empty() => Ok(std::mem::take(&mut self.pending)),
+                Demand::More => {
+                    let mut piece = Vec::with_capacity(BUFFER_SIZE);
+                    self.tcp.read_buf(&mut piece).await.map(|_| piece)
+                }
+            };
+            if self.pieces.send(piece).await.is_err() {
+                break;
+            }
+        }
+        (self.tcp, None)
+    }
+}
+
+/// Serves a whole connection on a blocking thread
+pub(crate) type Fallback = Arc<dyn Fn(std::net::TcpStream) + Send + Sync>;
+
+/// Accepts connections and does their I/O on tokio, handlers run on the
+/// blocking pool
+///
+/// Requests are read completely before the handler runs and the response is
+/// collected before it is written, so a slow client only costs a task, not a
+/// thread. Bodies longer than a read buffer are the exception, the handler
+/// gets their rest through a `BodyFeed` while it runs. Connections stay open for further requests as long as both sides
+/// allow it.
+pub(crate) struct AsyncListener {
+    pub exchange: Exchange,
+    /// Takes over connections that need the blocking server, e.g. for TLS
+    pub fallback: Option<Fallback>,
+    pub max_connections: Option<usize>,
+    /// Answers connections beyond `max_connections` with 503 instead of
+    /// letting them wait
+    pub reject_when_full: bool,
+    /// How many handlers may run at the same time
+    pub workers: usize,
+    pub shutdown: ShutdownHandle,
+    pub timeouts: RequestTimeouts,
+    pub header_limits: HeaderLimits,
+    /// How long connections stay open between requests and for how many
+    pub keep_alive: KeepAlive,
+    pub metrics: Metrics,
+    /// How long running requests may take to finish after a shutdown
+    pub shutdown_deadline: Duration,
+    /// The answer for connections the listener turns away
+    pub service_unavailable: Arc<str>,
+    pub logger: Option<Logger>,
+}
+
+impl AsyncListener {
+    /// Serves connections from `listener` until a shutdown, returning how
+    /// many requests were still running at the deadline
+    pub fn run(self, listener: std::net::TcpListener) -> io::Result<usize> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_io()
+            .enable_time()
+            .max_blocking_threads(self.workers)
+            .build()?;
+        let still_running = runtime.block_on(Arc::new(self).accept_loop(listener));
+        // Idle connections are closed, abandoned handlers are not waited for
+        runtime.shutdown_background();
+        still_running
+    }
+
+    async fn accept_loop(self: Arc<Self>, listener: std::net::TcpListener) -> io::Result<usize> {
+        listener.set_nonblocking(true)?;
+        let listener = TcpListener::from_std(listener)?;
+        let slots = self
+            .max_connections
+            .map(|max| Arc::new(Semaphore::new(max)));
+        let mut accept_errors = AcceptErrors::default();
+
+        while !self.shutdown.is_shutting_down() {
+            let deferred = match &slots {
+                Some(slots) if !self.reject_when_full => {
+                    Some(slots.clone().acquire_owned().await.unwrap())
+                }
+                _ => None,
+            };
+
+            let tcp = match listener.accept().await {
+                Ok((tcp, _)) => tcp,
+                Err(e) => match accept_errors.handle(&e, &self.logger) {
+                    AcceptAction::Retry => continue,
+                    AcceptAction::Backoff => {
+                        tokio::time::sleep(ACCEPT_BACKOFF).await;
+                        continue;
+                    }
+                    AcceptAction::Fatal => {
+                        self.shutdown.fail(&e);
+                        break;
+                    }
+                },
+            };
+            if self.shutdown.is_shutting_down() {
+                // Most likely the connection waking up this loop
+                break;
+            }
+
+            let slot = match (deferred, &slots) {
+                (Some(slot), _) => Some(slot),
+                (None, Some(slots)) => match slots.clone().try_acquire_owned() {
+                    Ok(slot) => Some(slot),
+                    Err(_) => {
+                        Logger::info(&self.logger, "Connection limit reached, answering 503");
+                        self.metrics.count_service_unavailable();
+                        tokio::spawn(reject(tcp, self.service_unavailable.clone()));
+                        continue;
+                    }
+                },
+                (None, None) => None,
+            };
+
+            match &self.fallback {
+                Some(fallback) => {
+                    let tcp = match tcp.into_std().and_then(|tcp| {
+                        tcp.set_nonblocking(false)?;
+                        Ok(tcp)
+                    }) {
+                        Ok(tcp) => tcp,
+                        Err(e) => {
+                            Logger::warning(&self.logger, &format!("Error: {}", e));
+                            continue;
+                        }
+                    };
+                    let fallback = fallback.clone();
+                    let in_flight = self.shutdown.begin_request();
+                    tokio::task::spawn_blocking(move || {
+                        fallback(tcp);
+                        drop(slot);
+                        drop(in_flight);
+                    });
+                }
+                None => {
+                    let listener = self.clone();
+                    let accepted = Instant::now();
+                    tokio::spawn(async move {
+                        if let Err(e) = listener.serve(tcp, accepted).await {
+                            Logger::info(&listener.logger, &format!("Connection failed: {}", e));
+                        }
+                        drop(slot);
+                    });
+                }
+            }
+        }
+
+        // Handlers need this thread to send their responses, so the waiting
+        // must not block it. Clients connecting meanwhile are answered with
+        // 503 instead of being refused.
+        let deadline = Instant::now() + self.shutdown_deadline;
+        while self.shutdown.active_requests() > 0 && Instant::now() < deadline {
+            let accepted = tokio::time::timeout(Duration::from_millis(10), listener.accept()).await;
+            if let Ok(Ok((tcp, _))) = accepted {
+                self.metrics.count_service_unavailable();
+                tokio::spawn(reject(tcp, self.service_unavailable.clone()));
+            }
+        }
+        Ok(self.shutdown.active_requests())
+    }
+
+    /// Answers requests on a connection until either side wants to stop
+    async fn serve(&self, mut tcp: TcpStream, accepted: Instant) -> io::Result<()> {
+        let peer_addr = tcp.peer_addr().ok();
+        let connection_id = next_connection_id();
+        let mut served = 0;
+        // Bytes of the next request that arrived together with the last one
+        let mut pending = Vec::new();
+        // The first request is timed from the accept, later ones from their
+        // first byte, however long the connection was idle before
+        let mut first_request = Some(accepted);
+        loop {
+            let started = match first_request.take() {
+                Some(accepted) => accepted,
+                None => {
+                    if pending.is_empty() {
+                        pending.reserve(BUFFER_SIZE);
+                        let idle = self.keep_alive.idle_timeout;
+                        match tokio::time::timeout(idle, tcp.read_buf(&mut pending)).await {
+                            Ok(Ok(0)) | Err(_) => return Ok(()),
+                            Ok(Ok(_)) => {}
+                            Ok(Err(e)) => return Err(e),
+                        }
+                    }
+                    Instant::now()
+                }
+            };
+            let read = read_request(
+                &mut tcp,
+                &mut pending,
+                self.timeouts,
+                self.header_limits,
+                started,
+            )
+            .await;
+            let request = match read {
+                Ok(Some(request)) => request,
+                Ok(None) => return Ok(()),
+                Err(ReadError::TimedOut) => {
+                    self.metrics.count_request_timeout();
+                    Logger::info(
+                        &self.logger,
+                        &format!("Request from {:?} timed out, answering 408", peer_addr),
+                    );
+                    reject(tcp, REQUEST_TIMEOUT).await;
+                    return Ok(());
+                }
+                Err(ReadError::HeadTooLarge(reason)) => {
+                    Logger::info(
+                        &self.logger,
+                        &format!(
+                            "Request from {:?} has a {}, answering 431",
+                            peer_addr, reason
+                        ),
+                    );
+                    reject(tcp, HEADERS_TOO_LARGE).await;
+                    return Ok(());
+                }
+                Err(ReadError::Io(e)) => return Err(e),
+            };
+            let in_flight = self.shutdown.begin_request();
+            let exchange = self.exchange.clone();
+            served += 1;
+            let mut connection = Connection {
+                peer_addr,
+                id: connection_id,
+                remaining_requests: self.keep_alive.max_requests.saturating_sub(served),
+                body_feed: None,
+            };
+            let mut body_complete = true;
+            let response = if request_len(&request).is_some() && body_continues(&request) {
+                // The rest of a long body is read while the handler asks for it
+                let (feed, pump) = body_feed(tcp, std::mem::take(&mut pending));
+                connection.body_feed = Some(feed);
+                let pump = tokio::spawn(pump.run());
+                let response =
+                    tokio::task::spawn_blocking(move || exchange(request, connection)).await;
+                let (returned, leftover) = pump.await.map_err(io::Error::other)?;
+                tcp = returned;
+                match leftover {
+                    Some(leftover) => pending = leftover,
+                    None => body_complete = false,
+                }
+                response
+            } else {
+                tokio::task::spawn_blocking(move || exchange(request, connection)).await
+            }
+            .map_err(io::Error::other)?;
+            if let Err(e) = tcp.write_all(&response).await {
+                if transfer::is_disconnect(&e) {
+                    Logger::debug(
+                        &self.logger,
+                        &format!(
+                            "Client aborted the response on connection {}: {}",
+                            connection_id, e
+                        ),
+                    );
+                    return Ok(());
+                }
+                return Err(e);
+            }
+            drop(in_flight);
+
+            // The server answered with `Connection: close` unless both it and
+            // the client want to continue
+            if !body_complete {
+                // The rest of the body may still be on its way
+                close(tcp).await;
+                return Ok(());
+            }
+            if !is_delimited(&response) || self.shutdown.is_shutting_down() {
+                let _ = tcp.shutdown().await;
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Reads the next request, returning `None` once the client closed the
+/// connection in between requests
+///
+/// Like `reader::read_request`, the head must arrive within `timeouts.head`
+/// of `started` and the body within `timeouts.body` after it, and the head
+/// must stay within `limits`.
+async fn read_request(
+    tcp: &mut TcpStream,
+    pending: &mut Vec<u8>,
+    timeouts: RequestTimeouts,
+    limits: HeaderLimits,
+    started: Instant,
+) -> Result<Option<Vec<u8>>, ReadError> {
+    let mut deadline = started + timeouts.head;
+    let mut head_complete = false;
+    loop {
+        if !head_complete {
+            check_head(pending, limits).map_err(ReadError::HeadTooLarge)?;
+            if head_len(pending).is_some() {
+                head_complete = true;
+                deadline = Instant::now() + timeouts.body;
+            }
+        }
+        if let Some(len) = request_len(pending) {
+            return Ok(Some(pending.drain(..len).collect()));
+        }
+        pending.reserve(BUFFER_SIZE);
+        let read = tokio::time::timeout_at(deadline.into(), tcp.read_buf(pending))
+            .await
+            .map_err(|_| ReadError::TimedOut)??;
+        if read == 0 {
+            // Whatever came before the end is handled like the blocking
+            // server would
+            if pending.is_empty() {
+                return Ok(None);
+            }
+            return Ok(Some(std::mem::take(pending)));
+        }
+    }
+}
+
+/// Answers a connection with `response` and closes it, e.g. with 503 beyond
+/// the connection limit
+async fn reject(mut tcp: TcpStream, response: impl AsRef<str>) {
+    if tcp.write_all(response.as_ref().as_bytes()).await.is_err() {
+        return;
+    }
+    close(tcp).await;
+}
+
+/// Closes a connection whose request may not have been read completely
+///
+/// The request is drained for a moment, closing a socket with unread bytes
+/// makes the kernel reset the connection, destroying the answer.
+async fn close(mut tcp: TcpStream) {
+    let _ = tcp.shutdown().await;
+    let mut discard = [0; 1024];
+    let drain = async {
+        while let Ok(read) = tcp.read(&mut discard).await {
+            if read == 0 {
+                break;
+            }
+        }
+    };
+    let _ = tokio::time::timeout(Duration::from_millis(100), drain).await;
+}
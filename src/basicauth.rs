@@ -0,0 +1,180 @@
+use std::sync::Arc;
+
+/// Decides whether a user name and password are valid
+pub(crate) type Validator = Arc<dyn Fn(&str, &str) -> bool + Send + Sync>;
+
+/// A path prefix that only clients with valid credentials may request, see
+/// `Server::protect`
+#[derive(Clone)]
+pub(crate) struct Protection {
+    /// Without a trailing slash, empty for all paths
+    prefix: String,
+    validator: Validator,
+}
+
+impl Protection {
+    pub fn new(prefix: &str, validator: Validator) -> Self {
+        Protection {
+            prefix: String::from(prefix.trim_end_matches('/')),
+            validator,
+        }
+    }
+
+    /// Returns whether `path` is the prefix or below it
+    pub fn covers(&self, path: &str) -> bool {
+        path.strip_prefix(&self.prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    }
+
+    /// Returns whether this protects the same paths as `other`
+    pub fn same_prefix(&self, other: &Protection) -> bool {
+        self.prefix == other.prefix
+    }
+
+    /// Returns how specific the prefix is, the longest covering one decides
+    pub fn specificity(&self) -> usize {
+        self.prefix.len()
+    }
+
+    /// Returns the user name if `authorization` carries valid credentials
+    pub fn authenticate(&self, authorization: Option<&str>) -> Option<String> {
+        let (user, password) = credentials(authorization?)?;
+        (self.validator)(&user, &password).then_some(user)
+    }
+
+    /// Returns the `WWW-Authenticate` header value asking for credentials,
+    /// with the prefix as realm so browsers keep them apart
+    pub fn challenge(&self) -> String {
+        let realm = if self.prefix.is_empty() {
+            "/"
+        } else {
+            &self.prefix
+        };
+        format!("Basic realm=\"{}\", charset=\"UTF-8\"", realm)
+    }
+}
+
+/// Returns a validator accepting exactly `users`, pairs of user name and
+/// password
+pub(crate) fn users(users: &[(&str, &str)]) -> Validator {
+    let users: Vec<(String, String)> = users
+        .iter()
+        .map(|(user, password)| (String::from(*user), String::from(*password)))
+        .collect();
+    Arc::new(move |user, password| {
+        // Every entry is compared, so the time taken tells nothing
+        users
+            .iter()
+            .fold(false, |valid, (known_user, known_password)| {
+                let matches = same_secret(user, known_user) & same_secret(password, known_password);
+                valid | matches
+            })
+    })
+}
+
+/// Compares two secrets in a time that only depends on their lengths
+fn same_secret(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |differs, (a, b)| differs | (a ^ b))
+            == 0
+}
+
+/// Returns the user name and password of an `Authorization` header using
+/// the Basic scheme
+///
+/// The password is everything after the first colon, so it may contain
+/// colons itself.
+fn credentials(authorization: &str) -> Option<(String, String)> {
+    let (scheme, encoded) = authorization.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("Basic") {
+        return None;
+    }
+    let decoded = String::from_utf8(decode_base64(encoded.trim())?).ok()?;
+    let (user, password) = decoded.split_once(':')?;
+    Some((String::from(user), String::from(password)))
+}
+
+/// Decodes base64 with the standard alphabet, padding is optional
+fn decode_base64(encoded: &str) -> Option<Vec<u8>> {
+    let encoded = encoded.trim_end_matches('=');
+    if encoded.len() % 4 == 1 {
+        return None;
+    }
+    let mut decoded = Vec::with_capacity(encoded.len() * 3 / 4);
+    let mut bits = 0u32;
+    let mut bit_count = 0;
+    for byte in encoded.bytes() {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        bits = (bits << 6) | u32::from(value);
+        bit_count += 6;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            decoded.push((bits >> bit_count) as u8);
+            bits &= (1 << bit_count) - 1;
+        }
+    }
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_base64() {
+        assert_eq!(decode_base64("YWxpY2U6czNjcmV0").unwrap(), b"alice:s3cret");
+        assert_eq!(decode_base64("YTpi").unwrap(), b"a:b");
+        assert_eq!(decode_base64("YTpiYw==").unwrap(), b"a:bc");
+        assert_eq!(decode_base64("YTpiYw").unwrap(), b"a:bc");
+        assert_eq!(decode_base64("").unwrap(), b"");
+        assert!(decode_base64("YTpi?").is_none());
+        assert!(decode_base64("YTpiY").is_none());
+        assert!(decode_base64("YT pi").is_none());
+    }
+
+    #[test]
+    fn test_credentials() {
+        assert_eq!(
+            credentials("Basic YWxpY2U6czNjcmV0"),
+            Some((String::from("alice"), String::from("s3cret")))
+        );
+        // dXNlcjpwYTpzcw== is user:pa:ss
+        assert_eq!(
+            credentials("basic  dXNlcjpwYTpzcw=="),
+            Some((String::from("user"), String::from("pa:ss")))
+        );
+        assert_eq!(credentials("Bearer YWxpY2U6czNjcmV0"), None);
+        // YWxpY2U= has no colon
+        assert_eq!(credentials("Basic YWxpY2U="), None);
+        assert_eq!(credentials("Basic"), None);
+    }
+
+    #[test]
+    fn test_protection() {
+        let admin = Protection::new("/admin/", users(&[("alice", "s3cret"), ("bob", "pw")]));
+        assert!(admin.covers("/admin"));
+        assert!(admin.covers("/admin/users/"));
+        assert!(!admin.covers("/administrator/"));
+        assert_eq!(
+            admin.authenticate(Some("Basic YWxpY2U6czNjcmV0")),
+            Some(String::from("alice"))
+        );
+        // alice:wrong
+        assert_eq!(admin.authenticate(Some("Basic YWxpY2U6d3Jvbmc=")), None);
+        assert_eq!(admin.authenticate(None), None);
+        assert_eq!(
+            admin.challenge(),
+            "Basic realm=\"/admin\", charset=\"UTF-8\""
+        );
+        assert!(Protection::new("/", users(&[])).covers("/anything"));
+    }
+}
@@ -0,0 +1,470 @@
+use crate::bufferpool::BUFFER_SIZE;
+use crate::headers::Headers;
+use crate::reader;
+use crate::stream::Stream;
+use std::fmt;
+use std::io;
+use std::io::prelude::*;
+use std::sync::{Arc, Mutex};
+
+/// How much of a body its handler left unread is read and dropped, so the
+/// connection can take the next request; connections with more left close
+const MAX_DRAINED: u64 = 1024 * 1024;
+
+/// How long the size line of a chunk may be, extensions included
+const MAX_CHUNK_LINE: usize = 1024;
+
+/// Reads the body of a request as it arrives, see `Request::body_reader`
+pub struct BodyReader {
+    source: Source,
+}
+
+enum Source {
+    /// The body was read together with the head
+    Buffered(io::Cursor<Vec<u8>>),
+    /// The body is still arriving on the connection
+    Incoming(IncomingBody),
+}
+
+impl BodyReader {
+    /// Returns a reader of a body that is in memory completely
+    pub(crate) fn buffered(body: Vec<u8>) -> Self {
+        BodyReader {
+            source: Source::Buffered(io::Cursor::new(body)),
+        }
+    }
+}
+
+impl Read for BodyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.source {
+            Source::Buffered(body) => body.read(buf),
+            Source::Incoming(body) => body.0.lock().unwrap().read(buf),
+        }
+    }
+}
+
+impl fmt::Debug for BodyReader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let incoming = matches!(self.source, Source::Incoming(_));
+        f.debug_struct("BodyReader")
+            .field("incoming", &incoming)
+            .finish()
+    }
+}
+
+/// How the end of a body is found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Framing {
+    /// By its `Content-Length`, with how many bytes are left
+    Length(u64),
+    /// By `Transfer-Encoding: chunked`, with where the decoding is
+    Chunked(Chunk),
+}
+
+/// Where the decoding of a chunked body is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Chunk {
+    /// Before the size line of the next chunk
+    Size,
+    /// Within a chunk, with how many bytes of it are left
+    Data(u64),
+    /// Before the line break ending a chunk
+    DataEnd,
+    /// After the last chunk, before the trailers ending the body
+    Trailers,
+    Done,
+}
+
+impl Framing {
+    /// Returns how the body of a request with `headers` is framed, `None` if
+    /// the request has no body beyond what arrived together with its head
+    pub fn of(headers: &Headers) -> Option<Framing> {
+        if headers
+            .get("Transfer-Encoding")
+            .is_some_and(reader::is_chunked)
+        {
+            return Some(Framing::Chunked(Chunk::Size));
+        }
+        headers
+            .get("Content-Length")
+            .and_then(|len| len.parse().ok())
+            .map(Framing::Length)
+    }
+
+    fn is_done(self) -> bool {
+        matches!(self, Framing::Length(0) | Framing::Chunked(Chunk::Done))
+    }
+}
+
+/// The body of a request that is read from its connection while the handler
+/// runs, shared between the handler's `BodyReader` and the server, which
+/// takes care of what the handler left
+#[derive(Clone)]
+pub(crate) struct IncomingBody(Arc<Mutex<Incoming>>);
+
+struct Incoming {
+    /// Body bytes that were read from the connection but not handed out,
+    /// starting with those read together with the head
+    buffered: Vec<u8>,
+    position: usize,
+    /// `None` once the request was answered
+    stream: Option<Arc<Mutex<Stream>>>,
+    framing: Framing,
+    /// How many bytes a chunked body may have, see
+    /// `Server::set_max_body_size`
+    limit: Option<u64>,
+    /// How many bytes the chunks so far announced
+    announced: u64,
+}
+
+impl IncomingBody {
+    /// Returns the body continuing with `buffered` on `stream`
+    ///
+    /// Reading a chunked body fails once its chunks exceed `limit`, bodies
+    /// with a `Content-Length` are checked before they are read.
+    pub fn new(
+        buffered: Vec<u8>,
+        stream: Arc<Mutex<Stream>>,
+        framing: Framing,
+        limit: Option<u64>,
+    ) -> Self {
+        IncomingBody(Arc::new(Mutex::new(Incoming {
+            buffered,
+            position: 0,
+            stream: Some(stream),
+            framing,
+            limit,
+            announced: 0,
+        })))
+    }
+
+    /// Returns a reader handing out the body from where it was read to
+    pub fn reader(&self) -> BodyReader {
+        BodyReader {
+            source: Source::Incoming(self.clone()),
+        }
+    }
+
+    /// Returns whether reading failed because the chunks exceeded the limit
+    pub fn exceeded(&self) -> bool {
+        let incoming = self.0.lock().unwrap();
+        incoming
+            .limit
+            .is_some_and(|limit| incoming.announced > limit)
+    }
+
+    /// Lets go of the connection once the request was answered
+    ///
+    /// On a connection that is kept open for further requests, the rest of
+    /// the body is read up to `MAX_DRAINED` bytes. Whatever was read beyond
+    /// the body is handed back as the start of the next request, to the tokio
+    /// listener or, for `keep_alive` requests of the blocking server, as the
+    /// return value. If the body could not be read to its end, the
+    /// connection closes.
+    pub fn finish(&self, keep_alive: bool) -> Option<Vec<u8>> {
+        let mut incoming = self.0.lock().unwrap();
+        #[cfg(feature = "tokio")]
+        incoming.hand_back();
+        let leftover = match keep_alive {
+            true => incoming.leftover(),
+            false => None,
+        };
+        incoming.stream = None;
+        leftover
+    }
+}
+
+impl Incoming {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.framing {
+                Framing::Length(remaining) => {
+                    let read = self.read_data(buf, remaining)?;
+                    self.framing = Framing::Length(remaining - read as u64);
+                    return Ok(read);
+                }
+                Framing::Chunked(Chunk::Size) => {
+                    let line = self.read_line()?;
+                    let size = line.split(';').next().unwrap_or("").trim();
+                    let size = u64::from_str_radix(size, 16)
+                        .map_err(|_| invalid_data(&format!("invalid chunk size {:?}", size)))?;
+                    self.announced = self.announced.saturating_add(size);
+                    if let Some(limit) = self.limit.filter(|&limit| self.announced > limit) {
+                        return Err(invalid_data(&format!("body larger than {} bytes", limit)));
+                    }
+                    self.framing = Framing::Chunked(if size == 0 {
+                        Chunk::Trailers
+                    } else {
+                        Chunk::Data(size)
+                    });
+                }
+                Framing::Chunked(Chunk::Data(remaining)) => {
+                    let read = self.read_data(buf, remaining)?;
+                    let left = remaining - read as u64;
+                    self.framing = Framing::Chunked(if left == 0 {
+                        Chunk::DataEnd
+                    } else {
+                        Chunk::Data(left)
+                    });
+                    return Ok(read);
+                }
+                Framing::Chunked(Chunk::DataEnd) => {
+                    if !self.read_line()?.is_empty() {
+                        return Err(invalid_data("chunk longer than its size"));
+                    }
+                    self.framing = Framing::Chunked(Chunk::Size);
+                }
+                Framing::Chunked(Chunk::Trailers) => {
+                    // Trailers are not passed on
+                    if self.read_line()?.is_empty() {
+                        self.framing = Framing::Chunked(Chunk::Done);
+                    }
+                }
+                Framing::Chunked(Chunk::Done) => return Ok(0),
+            }
+        }
+    }
+
+    /// Reads no more than `remaining` bytes of body data
+    fn read_data(&mut self, buf: &mut [u8], remaining: u64) -> io::Result<usize> {
+        let len = remaining.min(buf.len() as u64) as usize;
+        if len == 0 {
+            return Ok(0);
+        }
+        if self.position == self.buffered.len() {
+            let read = self.read_stream(&mut buf[..len])?;
+            return match read {
+                0 => Err(unexpected_eof()),
+                read => Ok(read),
+            };
+        }
+        let buffered = &self.buffered[self.position..];
+        let read = len.min(buffered.len());
+        buf[..read].copy_from_slice(&buffered[..read]);
+        self.position += read;
+        Ok(read)
+    }
+
+    /// Reads a line of the chunked framing, without its line break
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut line = Vec::new();
+        loop {
+            if self.position == self.buffered.len() {
+                self.fill()?;
+            }
+            let buffered = &self.buffered[self.position..];
+            match buffered.iter().position(|&byte| byte == b'\n') {
+                Some(end) => {
+                    line.extend_from_slice(&buffered[..end]);
+                    self.position += end + 1;
+                    break;
+                }
+                None => {
+                    line.extend_from_slice(buffered);
+                    self.position = self.buffered.len();
+                }
+            }
+            if line.len() > MAX_CHUNK_LINE {
+                return Err(invalid_data("chunk size line too long"));
+            }
+        }
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        String::from_utf8(line).map_err(|_| invalid_data("chunk size line is not UTF-8"))
+    }
+
+    /// Replaces the buffered bytes, which were all handed out, with the
+    /// next ones from the connection
+    fn fill(&mut self) -> io::Result<()> {
+        let mut buffered = std::mem::take(&mut self.buffered);
+        buffered.resize(BUFFER_SIZE, 0);
+        let read = self.read_stream(&mut buffered);
+        buffered.truncate(*read.as_ref().unwrap_or(&0));
+        self.buffered = buffered;
+        self.position = 0;
+        match read? {
+            0 => Err(unexpected_eof()),
+            _ => Ok(()),
+        }
+    }
+
+    fn read_stream(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &self.stream {
+            Some(stream) => stream.lock().unwrap().read(buf),
+            None => Err(io::Error::other(
+                "the request was answered, its body is gone",
+            )),
+        }
+    }
+
+    /// Drains a body arriving through the tokio listener and hands what was
+    /// read beyond it back to the listener, see `IncomingBody::finish`
+    #[cfg(feature = "tokio")]
+    fn hand_back(&mut self) {
+        let stream = match &self.stream {
+            Some(stream) => stream.clone(),
+            None => return,
+        };
+        let fed = matches!(
+            stream.lock().unwrap().untallied(),
+            Stream::Memory(memory) if memory.body_feed.is_some()
+        );
+        if !fed {
+            return;
+        }
+        let drained = self.drain(MAX_DRAINED);
+        let mut stream = stream.lock().unwrap();
+        if let Stream::Memory(memory) = stream.untallied_mut() {
+            // Dropping the feed without finishing it closes the connection
+            if let (Some(feed), true) = (memory.body_feed.take(), drained) {
+                let mut leftover = self.buffered.split_off(self.position);
+                leftover.extend_from_slice(memory.unread_input());
+                feed.finish(leftover);
+            }
+        }
+    }
+
+    /// Drains a body arriving on a connection the blocking server keeps open
+    /// and returns what was read beyond it, see `IncomingBody::finish`
+    fn leftover(&mut self) -> Option<Vec<u8>> {
+        let kept_open = match &self.stream {
+            Some(stream) => matches!(stream.lock().unwrap().untallied(), Stream::KeptOpen(_)),
+            None => false,
+        };
+        if !kept_open || !self.drain(MAX_DRAINED) {
+            return None;
+        }
+        Some(self.buffered.split_off(self.position))
+    }
+
+    /// Reads and drops the rest of the body, returning whether it ended
+    /// within `limit` bytes
+    fn drain(&mut self, limit: u64) -> bool {
+        let mut scratch = vec![0; BUFFER_SIZE];
+        let mut drained = 0;
+        while !self.framing.is_done() {
+            match self.read(&mut scratch) {
+                Ok(read) if drained + (read as u64) <= limit => drained += read as u64,
+                _ => return false,
+            }
+        }
+        true
+    }
+}
+
+fn unexpected_eof() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "the client closed the connection before the body was complete",
+    )
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::MemoryStream;
+
+    fn incoming(buffered: &[u8], rest: &[u8], framing: Framing) -> IncomingBody {
+        limited(buffered, rest, framing, None)
+    }
+
+    fn limited(buffered: &[u8], rest: &[u8], framing: Framing, limit: Option<u64>) -> IncomingBody {
+        let stream = Stream::Memory(MemoryStream {
+            input: io::Cursor::new(rest.to_vec()),
+            output: Arc::default(),
+            encrypted: false,
+            remaining_requests: 0,
+            sni_hostname: None,
+            #[cfg(feature = "tls")]
+            client_certificate: None,
+            #[cfg(feature = "tokio")]
+            body_feed: None,
+        });
+        IncomingBody::new(
+            buffered.to_vec(),
+            Arc::new(Mutex::new(stream)),
+            framing,
+            limit,
+        )
+    }
+
+    fn read_all(body: &IncomingBody) -> io::Result<Vec<u8>> {
+        let mut read = Vec::new();
+        body.reader().read_to_end(&mut read)?;
+        Ok(read)
+    }
+
+    #[test]
+    fn test_length() {
+        let body = incoming(b"hello ", b"world and more", Framing::Length(11));
+        assert_eq!(read_all(&body).unwrap(), b"hello world");
+        let body = incoming(b"hello", b" wor", Framing::Length(11));
+        let error = read_all(&body).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_chunked() {
+        let chunked = Framing::Chunked(Chunk::Size);
+        let body = incoming(
+            b"5\r\nhello\r\n6;ext=1\r",
+            b"\n world\r\n0\r\nX-Trailer: 1\r\n\r\nGET",
+            chunked,
+        );
+        assert_eq!(read_all(&body).unwrap(), b"hello world");
+        assert_eq!(
+            body.0.lock().unwrap().framing,
+            Framing::Chunked(Chunk::Done)
+        );
+
+        let body = incoming(b"zz\r\n", b"", chunked);
+        assert_eq!(
+            read_all(&body).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        let body = incoming(b"2\r\nabc\r\n0\r\n\r\n", b"", chunked);
+        assert_eq!(
+            read_all(&body).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        let body = incoming(b"5\r\nhel", b"", chunked);
+        assert_eq!(
+            read_all(&body).unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+
+        let within = b"5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n";
+        let body = limited(within, b"", chunked, Some(11));
+        assert_eq!(read_all(&body).unwrap(), b"hello world");
+        let body = limited(within, b"", chunked, Some(10));
+        let error = read_all(&body).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "body larger than 10 bytes");
+    }
+
+    #[test]
+    fn test_framing() {
+        let mut headers = Headers::new();
+        assert_eq!(Framing::of(&headers), None);
+        headers.set("Content-Length", "12");
+        assert_eq!(Framing::of(&headers), Some(Framing::Length(12)));
+        headers.set("Transfer-Encoding", "gzip, Chunked");
+        assert_eq!(Framing::of(&headers), Some(Framing::Chunked(Chunk::Size)));
+    }
+
+    #[test]
+    fn test_finished() {
+        let body = incoming(b"hel", b"lo", Framing::Length(5));
+        let mut reader = body.reader();
+        let mut start = [0; 3];
+        reader.read_exact(&mut start).unwrap();
+        assert_eq!(body.finish(false), None);
+        assert!(reader.read(&mut start).is_err());
+    }
+}
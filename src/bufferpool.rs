@@ -0,0 +1,105 @@
+use std::cell::RefCell;
+use std::ops::{Deref, DerefMut};
+
+/// Size of every pooled buffer, which is also the largest request head
+/// plus body that is read
+pub(crate) const BUFFER_SIZE: usize = 8 * 1024;
+
+/// How many idle buffers each worker thread keeps
+const MAX_POOLED: usize = 4;
+
+thread_local! {
+    static POOL: RefCell<Vec<Vec<u8>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// A read buffer checked out of the current thread's pool, returned when
+/// dropped
+///
+/// Returned buffers are not cleared. Callers must only ever look at the bytes
+/// they read into the buffer themselves, so nothing of an earlier request
+/// leaks into a later one.
+pub(crate) struct PooledBuffer {
+    buffer: Vec<u8>,
+}
+
+impl PooledBuffer {
+    /// Takes an idle buffer or allocates a new one if there is none
+    pub fn take() -> Self {
+        let buffer = POOL
+            .with(|pool| pool.borrow_mut().pop())
+            .unwrap_or_else(|| vec![0; BUFFER_SIZE]);
+        PooledBuffer { buffer }
+    }
+
+    /// Makes the buffer `additional` bytes larger, for requests with a head
+    /// that does not fit
+    ///
+    /// Grown buffers are freed instead of going back to the pool.
+    pub fn grow(&mut self, additional: usize) {
+        let len = self.buffer.len() + additional;
+        self.buffer.resize(len, 0);
+    }
+}
+
+impl Deref for PooledBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.buffer
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        let buffer = std::mem::take(&mut self.buffer);
+        POOL.with(|pool| {
+            let mut pool = pool.borrow_mut();
+            if pool.len() < MAX_POOLED && buffer.len() == BUFFER_SIZE {
+                pool.push(buffer);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reuse() {
+        let mut first = PooledBuffer::take();
+        assert_eq!(first.len(), BUFFER_SIZE);
+        first[..5].copy_from_slice(b"first");
+        let address = first.as_ptr();
+        drop(first);
+
+        let second = PooledBuffer::take();
+        assert_eq!(second.as_ptr(), address);
+        assert_eq!(second.len(), BUFFER_SIZE);
+    }
+
+    #[test]
+    fn test_grown_buffers_are_freed() {
+        // Start from an empty pool, earlier tests on this thread may have
+        // left buffers
+        POOL.with(|pool| pool.borrow_mut().clear());
+        let mut buffer = PooledBuffer::take();
+        buffer.grow(BUFFER_SIZE);
+        assert_eq!(buffer.len(), 2 * BUFFER_SIZE);
+        drop(buffer);
+        assert_eq!(POOL.with(|pool| pool.borrow().len()), 0);
+    }
+
+    #[test]
+    fn test_pool_is_bounded() {
+        let buffers: Vec<_> = (0..MAX_POOLED + 2).map(|_| PooledBuffer::take()).collect();
+        drop(buffers);
+        assert_eq!(POOL.with(|pool| pool.borrow().len()), MAX_POOLED);
+    }
+}
@@ -0,0 +1,377 @@
+use crate::config::ConfigError;
+use crate::server::{ConnectionLimitPolicy, Server};
+#[cfg(any(feature = "tls", feature = "native-tls"))]
+use crate::tls::TlsConfig;
+use std::fs::OpenOptions;
+#[cfg(any(feature = "tls", feature = "native-tls"))]
+use std::path::Path;
+use std::time::Duration;
+
+/// Configures a `Server` and checks every setting before the server exists,
+/// obtained with `Server::builder`
+///
+/// Unlike the setters of `Server`, which log and carry on, the first setting
+/// that cannot be used makes `build` fail with the name of the setting and
+/// what is wrong with it. Routes are registered on the built server.
+///
+/// # Example
+///
+/// ```
+/// use corrodedweb::Server;
+/// use std::time::Duration;
+/// let s = Server::builder()
+///     .port(8080)
+///     .workers(16)
+///     .request_head_timeout(Duration::from_secs(5))
+///     .build()
+///     .unwrap();
+/// assert_eq!(s.port(), Some(8080));
+/// ```
+pub struct ServerBuilder {
+    server: Server,
+    /// The first setting that failed, later ones are not checked
+    error: Option<ConfigError>,
+}
+
+impl ServerBuilder {
+    pub(crate) fn new() -> Self {
+        ServerBuilder {
+            server: Server::new(),
+            error: None,
+        }
+    }
+
+    /// Returns the server, or why one of the settings cannot be used
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let error = Server::builder().workers(0).build().err().unwrap();
+    /// assert_eq!(error.to_string(), "workers: must be at least 1");
+    /// ```
+    pub fn build(self) -> Result<Server, ConfigError> {
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(self.server),
+        }
+    }
+
+    /// Sets the port `start` listens on, see `Server::set_port`
+    pub fn port(self, port: u16) -> Self {
+        self.check("port", |server| {
+            if port == 0 {
+                return Err(String::from("must be between 1 and 65535"));
+            }
+            server.set_port(port);
+            Ok(())
+        })
+    }
+
+    /// Serves the files below `path`, which must be an existing directory
+    pub fn document_root(self, path: &str) -> Self {
+        self.check("document_root", |server| {
+            if server.set_document_root(path) {
+                Ok(())
+            } else {
+                Err(format!("{} is no directory", path))
+            }
+        })
+    }
+
+    /// Logs to the file at `path`, which must be writable
+    pub fn logger(self, path: &str) -> Self {
+        self.check("log_file", |server| {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| format!("{}: {}", path, e))?;
+            server.set_logger(path);
+            Ok(())
+        })
+    }
+
+    /// Sets how many requests are handled at the same time, see
+    /// `Server::set_workers`
+    pub fn workers(self, workers: usize) -> Self {
+        self.check("workers", |server| {
+            if workers == 0 {
+                return Err(String::from("must be at least 1"));
+            }
+            server.set_workers(workers);
+            Ok(())
+        })
+    }
+
+    /// Serves the routes of `module` below `prefix`, see `Server::mount`
+    pub fn mount(self, prefix: &str, module: Server) -> Self {
+        self.check("mount", |server| {
+            if !prefix.starts_with('/') {
+                return Err(format!("{} does not start with /", prefix));
+            }
+            server.mount(prefix, module);
+            Ok(())
+        })
+    }
+
+    /// Shows a list of files for directories without index file
+    pub fn index_of(mut self, index_of: bool) -> Self {
+        self.server.use_index_of(index_of);
+        self
+    }
+
+    /// Lists names starting with a dot, see `Server::show_hidden`
+    pub fn show_hidden(mut self, show: bool) -> Self {
+        self.server.show_hidden(show);
+        self
+    }
+
+    /// Serves static files through links leading out of their directory,
+    /// see `Server::follow_symlinks`
+    pub fn follow_symlinks(mut self, follow: bool) -> Self {
+        self.server.follow_symlinks(follow);
+        self
+    }
+
+    /// Sets the files served for requests of a directory, see
+    /// `Server::set_index_files`
+    pub fn index_files(self, names: &[&str]) -> Self {
+        self.check("index_files", |server| {
+            if !server.set_index_files(names) {
+                return Err(String::from("must be plain file names"));
+            }
+            Ok(())
+        })
+    }
+
+    /// Sets the `Cache-Control` of static files, see
+    /// `Server::set_cache_control`
+    pub fn cache_control(self, value: &str) -> Self {
+        self.check("cache_control", |server| {
+            if !server.set_cache_control(value) {
+                return Err(String::from("must be a valid header value"));
+            }
+            Ok(())
+        })
+    }
+
+    /// Adds a header to every response, see `Server::set_default_header`
+    pub fn default_header(self, name: &str, value: &str) -> Self {
+        self.check("default_headers", |server| {
+            if server.set_default_header(name, value) {
+                Ok(())
+            } else {
+                Err(format!("{}: {} is no valid header", name, value))
+            }
+        })
+    }
+
+    /// Sets how long a client may take to send the request head, see
+    /// `Server::set_request_head_timeout`
+    pub fn request_head_timeout(self, timeout: Duration) -> Self {
+        self.check("request_head_timeout", |server| {
+            non_zero(timeout)?;
+            server.set_request_head_timeout(timeout);
+            Ok(())
+        })
+    }
+
+    /// Sets how long a client may take to send the request body, see
+    /// `Server::set_request_body_timeout`
+    pub fn request_body_timeout(self, timeout: Duration) -> Self {
+        self.check("request_body_timeout", |server| {
+            non_zero(timeout)?;
+            server.set_request_body_timeout(timeout);
+            Ok(())
+        })
+    }
+
+    /// Sets how long running requests may take to finish after a shutdown
+    pub fn shutdown_deadline(mut self, deadline: Duration) -> Self {
+        self.server.set_shutdown_deadline(deadline);
+        self
+    }
+
+    /// Limits the connections served at the same time and sets what happens
+    /// to those beyond
+    pub fn max_connections(self, max: usize, policy: ConnectionLimitPolicy) -> Self {
+        self.check("max_connections", |server| {
+            if max == 0 {
+                return Err(String::from("must be at least 1"));
+            }
+            server.set_max_connections(max);
+            server.set_connection_limit_policy(policy);
+            Ok(())
+        })
+    }
+
+    /// Limits the connections waiting for a worker, beyond the limit the
+    /// policy of `max_connections` applies
+    pub fn max_queued_connections(mut self, max: usize) -> Self {
+        self.server.set_max_queued_connections(max);
+        self
+    }
+
+    /// Sets how many header lines a request may have
+    pub fn max_header_count(self, count: usize) -> Self {
+        self.check("max_header_count", |server| {
+            if count == 0 {
+                return Err(String::from("must be at least 1"));
+            }
+            server.set_max_header_count(count);
+            Ok(())
+        })
+    }
+
+    /// Sets how many bytes a single header line may have
+    pub fn max_header_line_length(self, length: usize) -> Self {
+        self.check("max_header_line_length", |server| {
+            if length == 0 {
+                return Err(String::from("must be at least 1"));
+            }
+            server.set_max_header_line_length(length);
+            Ok(())
+        })
+    }
+
+    /// Sets how many bytes the head of a request may have in total
+    pub fn max_header_bytes(self, bytes: usize) -> Self {
+        self.check("max_header_bytes", |server| {
+            if bytes == 0 {
+                return Err(String::from("must be at least 1"));
+            }
+            server.set_max_header_bytes(bytes);
+            Ok(())
+        })
+    }
+
+    /// Sets how many bytes the body of a request may have
+    pub fn max_body_size(mut self, bytes: u64) -> Self {
+        self.server.set_max_body_size(bytes);
+        self
+    }
+
+    /// Speaks HTTPS with a configuration loaded already
+    #[cfg(any(feature = "tls", feature = "native-tls"))]
+    pub fn tls(mut self, config: TlsConfig) -> Self {
+        self.server.set_tls(config);
+        self
+    }
+
+    /// Speaks HTTPS with the PEM encoded certificate chain and private key
+    /// in the given files, which must parse
+    #[cfg(feature = "tls")]
+    pub fn tls_pem_files<P: AsRef<Path>, Q: AsRef<Path>>(self, cert_path: P, key_path: Q) -> Self {
+        self.check("tls", |server| {
+            let config =
+                TlsConfig::from_pem_files(cert_path, key_path).map_err(|e| e.to_string())?;
+            server.set_tls(config);
+            Ok(())
+        })
+    }
+
+    /// Speaks HTTPS with the PKCS#12 archive at `path`, which must open with
+    /// `password`
+    #[cfg(feature = "native-tls")]
+    pub fn tls_pkcs12_file<P: AsRef<Path>>(self, path: P, password: &str) -> Self {
+        self.check("tls", |server| {
+            let config = TlsConfig::from_pkcs12_file(path, password).map_err(|e| e.to_string())?;
+            server.set_tls(config);
+            Ok(())
+        })
+    }
+
+    /// Applies a setting unless an earlier one failed, remembering the
+    /// failure under `key`
+    fn check<F>(mut self, key: &str, apply: F) -> Self
+    where
+        F: FnOnce(&mut Server) -> Result<(), String>,
+    {
+        if self.error.is_none() {
+            if let Err(message) = apply(&mut self.server) {
+                self.error = Some(ConfigError::Invalid {
+                    key: String::from(key),
+                    message,
+                });
+            }
+        }
+        self
+    }
+}
+
+fn non_zero(duration: Duration) -> Result<(), String> {
+    if duration.is_zero() {
+        Err(String::from("must be longer than zero"))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(builder: ServerBuilder) -> String {
+        builder.build().err().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_build() {
+        let server = Server::builder()
+            .port(8080)
+            .document_root(".")
+            .workers(4)
+            .max_header_count(20)
+            .build()
+            .unwrap();
+        assert_eq!(server.port(), Some(8080));
+        assert!(server.get_document_root().is_some());
+    }
+
+    #[test]
+    fn test_validation() {
+        assert_eq!(
+            error(Server::builder().port(0)),
+            "port: must be between 1 and 65535"
+        );
+        assert_eq!(
+            error(Server::builder().document_root("/does/not/exist")),
+            "document_root: /does/not/exist is no directory"
+        );
+        assert_eq!(
+            error(Server::builder().workers(0)),
+            "workers: must be at least 1"
+        );
+        assert_eq!(
+            error(Server::builder().index_files(&["index.html", "../index.html"])),
+            "index_files: must be plain file names"
+        );
+        assert_eq!(
+            error(Server::builder().request_head_timeout(Duration::from_secs(0))),
+            "request_head_timeout: must be longer than zero"
+        );
+        assert!(
+            error(Server::builder().logger("/does/not/exist/corroded.log"))
+                .starts_with("log_file: /does/not/exist/corroded.log: ")
+        );
+        assert_eq!(
+            error(Server::builder().mount("blog", Server::new())),
+            "mount: blog does not start with /"
+        );
+        // The first failure is reported
+        assert_eq!(
+            error(Server::builder().workers(0).port(0)),
+            "workers: must be at least 1"
+        );
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn test_tls_files() {
+        assert!(
+            error(Server::builder().tls_pem_files("missing.pem", "missing.key"))
+                .starts_with("tls: ")
+        );
+    }
+}
@@ -0,0 +1,97 @@
+use crate::accept;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io;
+use std::io::prelude::*;
+
+/// Bodies smaller than this go out as they are, gzip would barely shrink
+/// them
+pub(crate) const MIN_SIZE: usize = 1024;
+
+/// Returns whether an `Accept-Encoding` header allows gzip, which it does
+/// if `gzip` or `*` is listed without a q-value of 0
+pub(crate) fn accepts_gzip(accept_encoding: &str) -> bool {
+    let mut wildcard = None;
+    for coding in accept_encoding.split(',') {
+        let mut parts = coding.split(';');
+        let name = parts.next().unwrap_or("").trim();
+        let quality = parts
+            .filter_map(|parameter| parameter.split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+            .map_or(Some(1000), |(_, value)| accept::parse_quality(value.trim()));
+        let accepted = quality.is_some_and(|quality| quality > 0);
+        if name.eq_ignore_ascii_case("gzip") || name.eq_ignore_ascii_case("x-gzip") {
+            return accepted;
+        }
+        if name == "*" {
+            wildcard = Some(accepted);
+        }
+    }
+    wildcard.unwrap_or(false)
+}
+
+/// Returns whether bodies of `media_type` shrink when compressed, which
+/// images other than SVG, archives, audio and video do not
+pub(crate) fn compressible(media_type: &str) -> bool {
+    let essence = media_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+    essence.starts_with("text/")
+        || essence == "application/json"
+        || essence == "application/javascript"
+        || essence == "application/xml"
+        || essence == "image/svg+xml"
+        || essence.ends_with("+json")
+        || essence.ends_with("+xml")
+}
+
+/// Returns `body` compressed with gzip
+pub(crate) fn gzip(body: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::with_capacity(body.len() / 2), Compression::default());
+    encoder.write_all(body)?;
+    encoder.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+
+    #[test]
+    fn test_accepts_gzip() {
+        assert!(accepts_gzip("gzip, deflate, br"));
+        assert!(accepts_gzip("br;q=1.0, GZIP;q=0.5"));
+        assert!(accepts_gzip("*"));
+        assert!(!accepts_gzip("gzip;q=0, *"));
+        assert!(!accepts_gzip("*;q=0"));
+        assert!(!accepts_gzip("deflate, br"));
+        assert!(!accepts_gzip("identity"));
+        assert!(!accepts_gzip("gzip;q=2"));
+    }
+
+    #[test]
+    fn test_compressible() {
+        assert!(compressible("text/html; charset=utf-8"));
+        assert!(compressible("application/json"));
+        assert!(compressible("image/svg+xml"));
+        assert!(compressible("application/ld+json"));
+        assert!(!compressible("image/png"));
+        assert!(!compressible("application/zip"));
+        assert!(!compressible("application/octet-stream"));
+    }
+
+    #[test]
+    fn test_gzip() {
+        let body = "All work and no play makes Jack a dull boy. ".repeat(100);
+        let compressed = gzip(body.as_bytes()).unwrap();
+        assert!(compressed.len() < body.len() / 10);
+        let mut decompressed = String::new();
+        GzDecoder::new(&compressed[..])
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, body);
+    }
+}
@@ -0,0 +1,1218 @@
+use crate::server::{
+    ConnectionLimitPolicy, CorsConfig, DuplicateRoutePolicy, HstsConfig, KeepAlive, ProxyConfig,
+    Server, StaticPrecedence, TrailingSlashPolicy,
+};
+#[cfg(any(feature = "tls", feature = "native-tls"))]
+use crate::tls::TlsConfig;
+use crate::Level;
+use std::convert::TryFrom;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::fs::OpenOptions;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use toml::{Table, Value};
+
+/// Why a configuration file could not be loaded
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The file could not be read
+    Read { path: PathBuf, message: String },
+    /// The file is not valid TOML
+    Syntax { message: String },
+    /// A key has a value of the wrong type or one that cannot be used, e.g.
+    /// a document root that does not exist
+    Invalid { key: String, message: String },
+    /// An environment variable has a value that cannot be used
+    Env { variable: String, message: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Read { path, message } => {
+                write!(f, "could not read {}: {}", path.display(), message)
+            }
+            ConfigError::Syntax { message } => write!(f, "invalid TOML: {}", message),
+            ConfigError::Invalid { key, message } => write!(f, "{}: {}", key, message),
+            ConfigError::Env { variable, message } => {
+                write!(f, "environment variable {}: {}", variable, message)
+            }
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+/// Reads a configuration file and creates a server from it, also returning
+/// the keys it did not know
+pub(crate) fn load(path: &Path) -> Result<(Server, Vec<String>), ConfigError> {
+    let content = fs::read_to_string(path).map_err(|e| ConfigError::Read {
+        path: path.to_path_buf(),
+        message: e.to_string(),
+    })?;
+    let table: Table = content
+        .parse()
+        .map_err(|e: toml::de::Error| ConfigError::Syntax {
+            message: e.to_string(),
+        })?;
+    let mut server = Server::new();
+    let unknown = apply(&mut server, &Section::root(&table))?;
+    Ok((server, unknown))
+}
+
+/// Keys of the top level table
+const ROOT_KEYS: &[&str] = &[
+    "port",
+    "bind",
+    "log_file",
+    "log_level",
+    "access_log",
+    "document_root",
+    "index_of",
+    "show_hidden",
+    "follow_symlinks",
+    "template_root",
+    "reload_templates",
+    "index_files",
+    "default_charset",
+    "default_headers",
+    "cache_control",
+    "error_pages",
+    "favicon",
+    "force_download_extensions",
+    "inline_extensions",
+    "mime_types",
+    "ignore_favicon",
+    #[cfg(feature = "mmap")]
+    "mmap",
+    "stat_cache",
+    "static_cache",
+    "route_listing",
+    "case_insensitive_routes",
+    "method_override",
+    "duplicate_routes",
+    "static_precedence",
+    "trailing_slash",
+    "max_connections",
+    "max_queued_connections",
+    "workers",
+    "connection_limit_policy",
+    "proxy_protocol",
+    "trusted_proxies",
+    "rate_limit",
+    "rate_limit_exempt",
+    "request_head_timeout",
+    "request_body_timeout",
+    "write_timeout",
+    "max_header_count",
+    "max_header_line_length",
+    "max_header_bytes",
+    "max_body_size",
+    "keep_alive",
+    "strict_parsing",
+    "phase_timings",
+    "server_timing",
+    "sessions",
+    "session_expiry",
+    "handler_timeout",
+    "canonical_host",
+    "shutdown_deadline",
+    "retry_after",
+    #[cfg(feature = "signals")]
+    "shutdown_on_signals",
+    #[cfg(feature = "access-files")]
+    "access_files",
+    #[cfg(feature = "gzip")]
+    "compression",
+    #[cfg(any(feature = "tls", feature = "native-tls"))]
+    "tls",
+    "https_redirect",
+    "hsts",
+    "cors",
+    "proxy",
+];
+
+/// Applies every setting of the file through the setters of `server` and
+/// returns the full paths of unknown keys
+fn apply(server: &mut Server, root: &Section) -> Result<Vec<String>, ConfigError> {
+    // First, so everything after is logged
+    if let Some(log_file) = root.string("log_file")? {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(log_file)
+            .map_err(|e| root.invalid("log_file", &e.to_string()))?;
+        server.set_logger(log_file);
+    }
+    if let Some(level) = root.string("log_level")? {
+        let level = parse_level(level).ok_or_else(|| {
+            root.invalid(
+                "log_level",
+                "expected \"debug\", \"info\", \"warning\" or \"error\"",
+            )
+        })?;
+        server.set_log_level(level);
+    }
+    let mut unknown = root.unknown_keys(ROOT_KEYS);
+
+    if let Some(access_log) = root.string("access_log")? {
+        if !server.set_access_log(access_log) {
+            return Err(root.invalid("access_log", "file cannot be opened"));
+        }
+    }
+
+    if let Some(port) = root.integer("port")? {
+        server.set_port(port);
+    }
+    // One address or a list of them, like "0.0.0.0:8080"
+    let binds = match root.value("bind") {
+        Some(Value::String(addr)) => vec![(String::from("bind"), addr.as_str())],
+        Some(Value::Array(_)) => root
+            .strings("bind")?
+            .unwrap_or_default()
+            .into_iter()
+            .enumerate()
+            .map(|(i, addr)| (format!("bind[{}]", i), addr))
+            .collect(),
+        Some(value) => {
+            return Err(root.expected("bind", "an address or an array of them", value));
+        }
+        None => Vec::new(),
+    };
+    for (key, addr) in binds {
+        if !server.add_listen_addr(addr) {
+            return Err(root.invalid(&key, "not an address to listen on"));
+        }
+    }
+    if let Some(document_root) = root.string("document_root")? {
+        if !server.set_document_root(document_root) {
+            return Err(root.invalid("document_root", "directory does not exist"));
+        }
+    }
+    if let Some(index_of) = root.bool("index_of")? {
+        server.use_index_of(index_of);
+    }
+    if let Some(show) = root.bool("show_hidden")? {
+        server.show_hidden(show);
+    }
+    if let Some(follow) = root.bool("follow_symlinks")? {
+        server.follow_symlinks(follow);
+    }
+    if let Some(reload) = root.bool("reload_templates")? {
+        server.reload_templates(reload);
+    }
+    if let Some(template_root) = root.string("template_root")? {
+        if !server.set_template_root(template_root) {
+            return Err(root.invalid("template_root", "directory does not exist"));
+        }
+    }
+    if let Some(names) = root.strings("index_files")? {
+        if !server.set_index_files(&names) {
+            return Err(root.invalid("index_files", "must be plain file names"));
+        }
+    }
+    match root.value("default_charset") {
+        Some(Value::Boolean(false)) => server.set_default_charset(None),
+        Some(Value::String(charset)) => server.set_default_charset(Some(charset)),
+        Some(value) => {
+            return Err(root.expected("default_charset", "a string or false", value));
+        }
+        None => {}
+    }
+    if let Some(headers) = root.table("default_headers")? {
+        for name in headers.table.keys() {
+            let value = headers.string(name)?.unwrap_or_default();
+            if !server.set_default_header(name, value) {
+                return Err(headers.invalid(name, "invalid header"));
+            }
+        }
+    }
+    if let Some(value) = root.string("cache_control")? {
+        if !server.set_cache_control(value) {
+            return Err(root.invalid("cache_control", "invalid header value"));
+        }
+    }
+    if let Some(pages) = root.table("error_pages")? {
+        for status in pages.table.keys() {
+            let page = pages.string(status)?.unwrap_or_default();
+            match status.parse() {
+                Ok(code) if server.set_error_page(code, page) => {}
+                _ => return Err(pages.invalid(status, "invalid error page")),
+            }
+        }
+    }
+    if let Some(favicon) = root.string("favicon")? {
+        if !server.set_favicon(favicon) {
+            return Err(root.invalid("favicon", "file cannot be read"));
+        }
+    }
+    if let Some(ignore) = root.bool("ignore_favicon")? {
+        server.ignore_favicon(ignore);
+    }
+    if let Some(extensions) = root.strings("force_download_extensions")? {
+        server.force_download_extensions(&extensions);
+    }
+    if let Some(extensions) = root.strings("inline_extensions")? {
+        server.inline_extensions(&extensions);
+    }
+    if let Some(types) = root.table("mime_types")? {
+        for extension in types.table.keys() {
+            let media_type = types.string(extension)?.unwrap_or_default();
+            if !server.add_mime_type(extension, media_type) {
+                return Err(types.invalid(extension, "invalid media type"));
+            }
+        }
+    }
+    #[cfg(feature = "mmap")]
+    {
+        if let Some(mmap) = root.bool("mmap")? {
+            server.use_mmap(mmap);
+        }
+    }
+    match root.value("stat_cache") {
+        Some(Value::Table(_)) => {
+            let section = root.table("stat_cache")?.unwrap();
+            let ttl = section
+                .duration("ttl")?
+                .unwrap_or(crate::statcache::DEFAULT_TTL);
+            let capacity = section
+                .integer("capacity")?
+                .unwrap_or(crate::statcache::DEFAULT_CAPACITY);
+            server.set_stat_cache(ttl, capacity);
+            unknown.extend(section.unknown_keys(&["ttl", "capacity"]));
+        }
+        Some(_) if root.bool("stat_cache")? == Some(true) => server.enable_stat_cache(),
+        _ => {}
+    }
+    match root.value("static_cache") {
+        Some(Value::Table(_)) => {
+            let section = root.table("static_cache")?.unwrap();
+            let max_bytes = section
+                .integer("max_bytes")?
+                .ok_or_else(|| section.invalid("max_bytes", "missing"))?;
+            let max_file_bytes = section
+                .integer("max_file_bytes")?
+                .unwrap_or(crate::filecache::DEFAULT_MAX_FILE_SIZE);
+            server.set_static_cache(max_bytes, max_file_bytes);
+            unknown.extend(section.unknown_keys(&["max_bytes", "max_file_bytes"]));
+        }
+        Some(_) => {
+            if let Some(max_bytes) = root.integer("static_cache")? {
+                server.enable_static_cache(max_bytes);
+            }
+        }
+        None => {}
+    }
+    if let Some(path) = root.string("route_listing")? {
+        server.enable_route_listing(path);
+    }
+    if let Some(case_insensitive) = root.bool("case_insensitive_routes")? {
+        server.set_case_insensitive_routes(case_insensitive);
+    }
+    if let Some(allow) = root.bool("method_override")? {
+        server.allow_method_override(allow);
+    }
+    if let Some(policy) = root.string("duplicate_routes")? {
+        server.set_duplicate_route_policy(match policy {
+            "replace" => DuplicateRoutePolicy::Replace,
+            "panic" => DuplicateRoutePolicy::Panic,
+            "ignore" => DuplicateRoutePolicy::Ignore,
+            _ => {
+                return Err(root.invalid(
+                    "duplicate_routes",
+                    "expected \"replace\", \"panic\" or \"ignore\"",
+                ))
+            }
+        });
+    }
+    if let Some(precedence) = root.string("static_precedence")? {
+        server.set_static_precedence(match precedence {
+            "routes_first" => StaticPrecedence::RoutesFirst,
+            "static_first" => StaticPrecedence::StaticFirst,
+            _ => {
+                return Err(root.invalid(
+                    "static_precedence",
+                    "expected \"routes_first\" or \"static_first\"",
+                ))
+            }
+        });
+    }
+    if let Some(policy) = root.string("trailing_slash")? {
+        server.set_trailing_slash_policy(match policy {
+            "match" => TrailingSlashPolicy::Match,
+            "redirect" => TrailingSlashPolicy::Redirect,
+            "strict" => TrailingSlashPolicy::Strict,
+            _ => {
+                return Err(root.invalid(
+                    "trailing_slash",
+                    "expected \"match\", \"redirect\" or \"strict\"",
+                ))
+            }
+        });
+    }
+    if let Some(max) = root.integer("max_connections")? {
+        server.set_max_connections(max);
+    }
+    if let Some(max) = root.integer("max_queued_connections")? {
+        server.set_max_queued_connections(max);
+    }
+    if let Some(workers) = root.integer("workers")? {
+        if !server.set_workers(workers) {
+            return Err(root.invalid("workers", "must be at least 1"));
+        }
+    }
+    if let Some(policy) = root.string("connection_limit_policy")? {
+        server.set_connection_limit_policy(match policy {
+            "defer" => ConnectionLimitPolicy::Defer,
+            "reject" => ConnectionLimitPolicy::Reject,
+            _ => {
+                return Err(root.invalid(
+                    "connection_limit_policy",
+                    "expected \"defer\" or \"reject\"",
+                ))
+            }
+        });
+    }
+    if let Some(enabled) = root.bool("proxy_protocol")? {
+        server.enable_proxy_protocol(enabled);
+    }
+    if let Some(proxies) = root.strings("trusted_proxies")? {
+        for proxy in proxies {
+            if !server.trust_proxy(proxy) {
+                return Err(root.invalid(
+                    "trusted_proxies",
+                    &format!("{} is not an IP address or network", proxy),
+                ));
+            }
+        }
+    }
+    if let Some(limit) = root.integer("rate_limit")? {
+        server.set_rate_limit(limit);
+    }
+    if let Some(clients) = root.strings("rate_limit_exempt")? {
+        for client in clients {
+            if !server.exempt_from_rate_limit(client) {
+                return Err(root.invalid(
+                    "rate_limit_exempt",
+                    &format!("{} is not an IP address or network", client),
+                ));
+            }
+        }
+    }
+    if let Some(timeout) = root.duration("request_head_timeout")? {
+        server.set_request_head_timeout(timeout);
+    }
+    if let Some(timeout) = root.duration("request_body_timeout")? {
+        server.set_request_body_timeout(timeout);
+    }
+    if let Some(timeout) = root.duration("write_timeout")? {
+        server.set_write_timeout(timeout);
+    }
+    if let Some(count) = root.integer("max_header_count")? {
+        server.set_max_header_count(count);
+    }
+    if let Some(length) = root.integer("max_header_line_length")? {
+        server.set_max_header_line_length(length);
+    }
+    if let Some(bytes) = root.integer("max_header_bytes")? {
+        server.set_max_header_bytes(bytes);
+    }
+    if let Some(bytes) = root.integer("max_body_size")? {
+        server.set_max_body_size(bytes);
+    }
+    match root.value("keep_alive") {
+        Some(Value::Table(_)) => {
+            let section = root.table("keep_alive")?.unwrap();
+            let defaults = KeepAlive::default();
+            let keep_alive = KeepAlive {
+                idle_timeout: section
+                    .duration("idle_timeout")?
+                    .unwrap_or(defaults.idle_timeout),
+                max_requests: section
+                    .integer("max_requests")?
+                    .unwrap_or(defaults.max_requests),
+            };
+            if !server.set_keep_alive(keep_alive) {
+                return Err(root.invalid(
+                    "keep_alive",
+                    "max_requests and idle_timeout must not be zero",
+                ));
+            }
+            unknown.extend(section.unknown_keys(&["idle_timeout", "max_requests"]));
+        }
+        Some(_) if root.bool("keep_alive")? == Some(false) => {
+            server.set_keep_alive(KeepAlive::disabled());
+        }
+        _ => {}
+    }
+    if let Some(strict) = root.bool("strict_parsing")? {
+        server.set_strict_parsing(strict);
+    }
+    if let Some(enabled) = root.bool("phase_timings")? {
+        server.set_phase_timings(enabled);
+    }
+    if let Some(enabled) = root.bool("server_timing")? {
+        server.set_server_timing(enabled);
+    }
+    if let Some(true) = root.bool("sessions")? {
+        server.enable_sessions();
+    }
+    if let Some(expiry) = root.duration("session_expiry")? {
+        server.set_session_expiry(expiry);
+    }
+    if let Some(timeout) = root.duration("handler_timeout")? {
+        server.set_handler_timeout(timeout);
+    }
+    if let Some(host) = root.string("canonical_host")? {
+        if !server.set_canonical_host(host) {
+            return Err(root.invalid("canonical_host", "expected a host name"));
+        }
+    }
+    if let Some(deadline) = root.duration("shutdown_deadline")? {
+        server.set_shutdown_deadline(deadline);
+    }
+    if let Some(retry_after) = root.duration("retry_after")? {
+        server.set_retry_after(retry_after);
+    }
+    #[cfg(feature = "signals")]
+    {
+        if let Some(enabled) = root.bool("shutdown_on_signals")? {
+            server.shutdown_on_signals(enabled);
+        }
+    }
+    #[cfg(feature = "access-files")]
+    {
+        if let Some(enabled) = root.bool("access_files")? {
+            server.use_access_files(enabled);
+        }
+    }
+    #[cfg(feature = "gzip")]
+    {
+        if let Some(enabled) = root.bool("compression")? {
+            server.enable_compression(enabled);
+        }
+    }
+
+    #[cfg(any(feature = "tls", feature = "native-tls"))]
+    {
+        if let Some(tls) = root.table("tls")? {
+            unknown.extend(apply_tls(server, &tls)?);
+        }
+    }
+    if let Some(redirect) = root.table("https_redirect")? {
+        let port = redirect
+            .integer("port")?
+            .ok_or_else(|| redirect.invalid("port", "missing"))?;
+        server.redirect_http_to_https(port, redirect.string("authority")?);
+        unknown.extend(redirect.unknown_keys(&["port", "authority"]));
+    }
+    if let Some(hsts) = root.table("hsts")? {
+        server.enable_hsts(HstsConfig {
+            max_age: hsts
+                .duration("max_age")?
+                .ok_or_else(|| hsts.invalid("max_age", "missing"))?,
+            include_subdomains: hsts.bool("include_subdomains")?.unwrap_or(false),
+            preload: hsts.bool("preload")?.unwrap_or(false),
+        });
+        unknown.extend(hsts.unknown_keys(&["max_age", "include_subdomains", "preload"]));
+    }
+    if let Some(cors) = root.table("cors")? {
+        let owned = |values: Option<Vec<&str>>| {
+            values
+                .unwrap_or_default()
+                .into_iter()
+                .map(String::from)
+                .collect()
+        };
+        let config = CorsConfig {
+            allowed_origins: owned(cors.strings("allowed_origins")?),
+            allowed_methods: owned(cors.strings("allowed_methods")?),
+            allowed_headers: owned(cors.strings("allowed_headers")?),
+            allow_credentials: cors.bool("allow_credentials")?.unwrap_or(false),
+            max_age: cors.duration("max_age")?,
+        };
+        if config.allow_credentials && config.allowed_origins.iter().any(|o| o == "*") {
+            return Err(cors.invalid(
+                "allow_credentials",
+                "cannot be true when every origin is allowed",
+            ));
+        }
+        if !server.enable_cors(config) {
+            return Err(root.invalid("cors", "has a value that cannot be sent in a header"));
+        }
+        unknown.extend(cors.unknown_keys(&[
+            "allowed_origins",
+            "allowed_methods",
+            "allowed_headers",
+            "allow_credentials",
+            "max_age",
+        ]));
+    }
+    for proxy in root.tables("proxy")?.unwrap_or_default() {
+        let prefix = proxy
+            .string("prefix")?
+            .ok_or_else(|| proxy.invalid("prefix", "missing"))?;
+        let upstream = proxy
+            .string("upstream")?
+            .ok_or_else(|| proxy.invalid("upstream", "missing"))?;
+        let defaults = ProxyConfig::default();
+        let config = ProxyConfig {
+            strip_prefix: proxy.bool("strip_prefix")?.unwrap_or(defaults.strip_prefix),
+            timeout: proxy.duration("timeout")?.unwrap_or(defaults.timeout),
+        };
+        if !server.proxy_with(prefix, upstream, config) {
+            return Err(proxy.invalid("upstream", "not a plain HTTP URL"));
+        }
+        unknown.extend(proxy.unknown_keys(&["prefix", "upstream", "strip_prefix", "timeout"]));
+    }
+
+    Ok(unknown)
+}
+
+/// Sets up TLS from the `tls` table, returning its unknown keys
+#[cfg(any(feature = "tls", feature = "native-tls"))]
+fn apply_tls(server: &mut Server, tls: &Section) -> Result<Vec<String>, ConfigError> {
+    let mut known = vec!["reload_interval"];
+    let mut config = None;
+    #[cfg(feature = "tls")]
+    {
+        known.extend(["cert", "key"]);
+        if let Some(cert) = tls.string("cert")? {
+            let key = tls
+                .string("key")?
+                .ok_or_else(|| tls.invalid("key", "missing"))?;
+            let loaded = TlsConfig::from_pem_files(cert, key)
+                .map_err(|e| tls.invalid("cert", &e.to_string()))?;
+            config = Some(loaded);
+        }
+    }
+    #[cfg(feature = "native-tls")]
+    {
+        known.extend(["pkcs12", "password"]);
+        if let (None, Some(pkcs12)) = (&config, tls.string("pkcs12")?) {
+            let password = tls.string("password")?.unwrap_or("");
+            let loaded = TlsConfig::from_pkcs12_file(pkcs12, password)
+                .map_err(|e| tls.invalid("pkcs12", &e.to_string()))?;
+            config = Some(loaded);
+        }
+    }
+    match config {
+        Some(config) => server.set_tls(config),
+        None => return Err(tls.invalid("", "no certificate configured")),
+    }
+    if let Some(interval) = tls.duration("reload_interval")? {
+        server.set_tls_reload_interval(interval);
+    }
+    Ok(tls.unknown_keys(&known))
+}
+
+/// Prefix of the environment variables read by `apply_env`
+const ENV_PREFIX: &str = "CORRODED_";
+
+/// Environment variables read by `apply_env`, besides `PORT`
+const ENV_VARS: &[&str] = &[
+    "CORRODED_PORT",
+    "CORRODED_BIND",
+    "CORRODED_DOC_ROOT",
+    "CORRODED_INDEX_OF",
+    "CORRODED_LOG_FILE",
+    "CORRODED_LOG_LEVEL",
+    "CORRODED_WORKERS",
+    "CORRODED_MAX_CONNECTIONS",
+    "CORRODED_SHUTDOWN_DEADLINE",
+];
+
+/// Applies the `CORRODED_*` environment variables and `PORT` through the
+/// setters of `server` and returns the unknown `CORRODED_*` variables
+pub(crate) fn apply_env(server: &mut Server) -> Result<Vec<String>, ConfigError> {
+    if let Some(log_file) = env_var("CORRODED_LOG_FILE")? {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&log_file)
+            .map_err(|e| env_invalid("CORRODED_LOG_FILE", &e.to_string()))?;
+        server.set_logger(&log_file);
+    }
+    if let Some(level) = env_var("CORRODED_LOG_LEVEL")? {
+        let level = parse_level(&level).ok_or_else(|| {
+            env_invalid(
+                "CORRODED_LOG_LEVEL",
+                &format!("expected debug, info, warning or error, found {:?}", level),
+            )
+        })?;
+        server.set_log_level(level);
+    }
+    // The variable set by Heroku, Cloud Run and the like, more specific
+    // settings win
+    let port = match env_parse("CORRODED_PORT", "a port number")? {
+        Some(port) => Some(port),
+        None => env_parse("PORT", "a port number")?,
+    };
+    if let Some(port) = port {
+        server.set_port(port);
+    }
+    // Several addresses are separated by commas
+    if let Some(binds) = env_var("CORRODED_BIND")? {
+        for addr in binds.split(',').map(str::trim) {
+            if !server.add_listen_addr(addr) {
+                return Err(env_invalid(
+                    "CORRODED_BIND",
+                    &format!("cannot listen on {:?}", addr),
+                ));
+            }
+        }
+    }
+    if let Some(document_root) = env_var("CORRODED_DOC_ROOT")? {
+        if !server.set_document_root(&document_root) {
+            return Err(env_invalid("CORRODED_DOC_ROOT", "directory does not exist"));
+        }
+    }
+    if let Some(index_of) = env_bool("CORRODED_INDEX_OF")? {
+        server.use_index_of(index_of);
+    }
+    if let Some(workers) = env_parse("CORRODED_WORKERS", "a number of workers")? {
+        if !server.set_workers(workers) {
+            return Err(env_invalid("CORRODED_WORKERS", "must be at least 1"));
+        }
+    }
+    if let Some(max) = env_parse("CORRODED_MAX_CONNECTIONS", "a number")? {
+        server.set_max_connections(max);
+    }
+    if let Some(deadline) = env_var("CORRODED_SHUTDOWN_DEADLINE")? {
+        server.set_shutdown_deadline(parse_duration(&deadline).ok_or_else(|| {
+            env_invalid(
+                "CORRODED_SHUTDOWN_DEADLINE",
+                &format!("expected a duration, found {:?}", deadline),
+            )
+        })?);
+    }
+
+    Ok(env::vars_os()
+        .filter_map(|(name, _)| name.into_string().ok())
+        .filter(|name| name.starts_with(ENV_PREFIX) && !ENV_VARS.contains(&name.as_str()))
+        .collect())
+}
+
+fn env_invalid(variable: &str, message: &str) -> ConfigError {
+    ConfigError::Env {
+        variable: String::from(variable),
+        message: String::from(message),
+    }
+}
+
+/// Returns the value of a variable, treating an empty one as unset
+fn env_var(variable: &str) -> Result<Option<String>, ConfigError> {
+    match env::var(variable) {
+        Ok(value) if value.is_empty() => Ok(None),
+        Ok(value) => Ok(Some(value)),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(env::VarError::NotUnicode(_)) => Err(env_invalid(variable, "not valid UTF-8")),
+    }
+}
+
+fn env_parse<T: FromStr>(variable: &str, expected: &str) -> Result<Option<T>, ConfigError> {
+    match env_var(variable)? {
+        Some(value) => value.trim().parse().map(Some).map_err(|_| {
+            env_invalid(
+                variable,
+                &format!("expected {}, found {:?}", expected, value),
+            )
+        }),
+        None => Ok(None),
+    }
+}
+
+fn env_bool(variable: &str) -> Result<Option<bool>, ConfigError> {
+    match env_var(variable)?.as_deref() {
+        Some("1") | Some("true") => Ok(Some(true)),
+        Some("0") | Some("false") => Ok(Some(false)),
+        Some(value) => Err(env_invalid(
+            variable,
+            &format!("expected true or false, found {:?}", value),
+        )),
+        None => Ok(None),
+    }
+}
+
+/// Parses the name of a log level, in any case
+fn parse_level(value: &str) -> Option<Level> {
+    match value.to_ascii_lowercase().as_str() {
+        "debug" => Some(Level::Debug),
+        "info" => Some(Level::Info),
+        "warning" | "warn" => Some(Level::Warning),
+        "error" => Some(Level::Error),
+        _ => None,
+    }
+}
+
+/// Parses a duration given in seconds or like `1h 30m`
+fn parse_duration(value: &str) -> Option<Duration> {
+    match value.parse() {
+        Ok(seconds) => Some(Duration::from_secs(seconds)),
+        Err(_) => humantime::parse_duration(value).ok(),
+    }
+}
+
+/// A table of the file together with its path, for error messages
+struct Section<'a> {
+    /// Empty for the top level, e.g. `tls` or `proxy[1]` otherwise
+    path: String,
+    table: &'a Table,
+}
+
+impl<'a> Section<'a> {
+    fn root(table: &'a Table) -> Self {
+        Section {
+            path: String::new(),
+            table,
+        }
+    }
+
+    /// Returns the full path of `key`, like `tls.cert`
+    fn key_path(&self, key: &str) -> String {
+        match (self.path.is_empty(), key.is_empty()) {
+            (true, _) => String::from(key),
+            (false, true) => self.path.clone(),
+            (false, false) => format!("{}.{}", self.path, key),
+        }
+    }
+
+    fn invalid(&self, key: &str, message: &str) -> ConfigError {
+        ConfigError::Invalid {
+            key: self.key_path(key),
+            message: String::from(message),
+        }
+    }
+
+    /// Complains about a value of the wrong type
+    fn expected(&self, key: &str, expected: &str, value: &Value) -> ConfigError {
+        self.invalid(
+            key,
+            &format!("expected {}, found {}", expected, value.type_str()),
+        )
+    }
+
+    fn value(&self, key: &str) -> Option<&'a Value> {
+        self.table.get(key)
+    }
+
+    fn bool(&self, key: &str) -> Result<Option<bool>, ConfigError> {
+        match self.value(key) {
+            None => Ok(None),
+            Some(Value::Boolean(value)) => Ok(Some(*value)),
+            Some(value) => Err(self.expected(key, "a boolean", value)),
+        }
+    }
+
+    fn string(&self, key: &str) -> Result<Option<&'a str>, ConfigError> {
+        match self.value(key) {
+            None => Ok(None),
+            Some(Value::String(value)) => Ok(Some(value)),
+            Some(value) => Err(self.expected(key, "a string", value)),
+        }
+    }
+
+    /// Returns an integer that has to fit into `T`
+    fn integer<T: TryFrom<i64>>(&self, key: &str) -> Result<Option<T>, ConfigError> {
+        match self.value(key) {
+            None => Ok(None),
+            Some(Value::Integer(value)) => T::try_from(*value)
+                .map(Some)
+                .map_err(|_| self.invalid(key, &format!("{} is out of range", value))),
+            Some(value) => Err(self.expected(key, "an integer", value)),
+        }
+    }
+
+    /// Returns a duration given in seconds or as a string like `1h 30m`
+    fn duration(&self, key: &str) -> Result<Option<Duration>, ConfigError> {
+        match self.value(key) {
+            None => Ok(None),
+            Some(Value::Integer(_)) => Ok(self.integer(key)?.map(Duration::from_secs)),
+            Some(Value::String(value)) => humantime::parse_duration(value)
+                .map(Some)
+                .map_err(|e| self.invalid(key, &format!("invalid duration: {}", e))),
+            Some(value) => Err(self.expected(key, "a duration", value)),
+        }
+    }
+
+    fn strings(&self, key: &str) -> Result<Option<Vec<&'a str>>, ConfigError> {
+        match self.value(key) {
+            None => Ok(None),
+            Some(Value::Array(values)) => values
+                .iter()
+                .enumerate()
+                .map(|(i, value)| match value {
+                    Value::String(value) => Ok(value.as_str()),
+                    _ => Err(self.expected(&format!("{}[{}]", key, i), "a string", value)),
+                })
+                .collect::<Result<_, _>>()
+                .map(Some),
+            Some(value) => Err(self.expected(key, "an array of strings", value)),
+        }
+    }
+
+    fn table(&self, key: &str) -> Result<Option<Section<'a>>, ConfigError> {
+        match self.value(key) {
+            None => Ok(None),
+            Some(Value::Table(table)) => Ok(Some(Section {
+                path: self.key_path(key),
+                table,
+            })),
+            Some(value) => Err(self.expected(key, "a table", value)),
+        }
+    }
+
+    /// Returns an array of tables, written as `[[key]]`
+    fn tables(&self, key: &str) -> Result<Option<Vec<Section<'a>>>, ConfigError> {
+        match self.value(key) {
+            None => Ok(None),
+            Some(Value::Array(values)) => values
+                .iter()
+                .enumerate()
+                .map(|(i, value)| {
+                    let path = format!("{}[{}]", key, i);
+                    match value {
+                        Value::Table(table) => Ok(Section {
+                            path: self.key_path(&path),
+                            table,
+                        }),
+                        _ => Err(self.expected(&path, "a table", value)),
+                    }
+                })
+                .collect::<Result<_, _>>()
+                .map(Some),
+            Some(value) => Err(self.expected(key, "an array of tables", value)),
+        }
+    }
+
+    /// Returns the full paths of all keys not in `known`
+    fn unknown_keys(&self, known: &[&str]) -> Vec<String> {
+        self.table
+            .keys()
+            .filter(|key| !known.contains(&key.as_str()))
+            .map(|key| self.key_path(key))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Mutex, MutexGuard};
+
+    /// Serializes tests that read or change environment variables
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    fn lock_env() -> MutexGuard<'static, ()> {
+        ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn parse(content: &str) -> Result<Server, ConfigError> {
+        let table: Table = content.parse().unwrap();
+        let mut server = Server::new();
+        apply(&mut server, &Section::root(&table)).map(|_| server)
+    }
+
+    fn invalid_key(content: &str) -> String {
+        match parse(content) {
+            Err(ConfigError::Invalid { key, .. }) => key,
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("{} was accepted", content),
+        }
+    }
+
+    #[test]
+    fn test_from_config_file() {
+        let _env = lock_env();
+        let dir = std::env::temp_dir().join("corrodedweb_config");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("public/docs")).unwrap();
+        fs::write(dir.join("public/docs/404.html"), "lost").unwrap();
+        fs::write(dir.join("public/docs/app.conf"), "key = value").unwrap();
+        let log = dir.join("corroded.log");
+        let path = dir.join("corroded.toml");
+        fs::write(
+            &path,
+            format!(
+                r#"
+port = 8080
+bind = ["127.0.0.1:8081", "[::1]:8081"]
+log_file = "{log}"
+log_level = "info"
+document_root = "{root}"
+index_of = true
+route_listing = "/routes/"
+method_override = true
+trusted_proxies = ["10.0.0.0/8"]
+request_head_timeout = 5
+request_body_timeout = "2m"
+max_header_count = 50
+shutdown_deadline = "5s"
+unknown_top = 1
+
+[default_headers]
+X-Frame-Options = "DENY"
+
+[error_pages]
+404 = "docs/404.html"
+
+[mime_types]
+conf = "text/plain"
+
+[stat_cache]
+ttl = "500ms"
+capacity = 16
+
+[hsts]
+max_age = "365d"
+typo = true
+
+[[proxy]]
+prefix = "/api/"
+upstream = "http://127.0.0.1:9000"
+strip_prefix = true
+"#,
+                log = log.display(),
+                root = dir.join("public/").display()
+            ),
+        )
+        .unwrap();
+
+        let mut server = Server::from_config_file(&path).unwrap();
+        assert_eq!(server.port(), Some(8080));
+        assert_eq!(
+            server.listen_addrs(),
+            [
+                "127.0.0.1:8081".parse().unwrap(),
+                "[::1]:8081".parse().unwrap()
+            ]
+        );
+        assert_eq!(server.log_level(), Some(Level::Info));
+        assert_eq!(server.get_document_root(), Some(dir.join("public/")));
+        let listing = server.test_request("GET", "/docs/").send();
+        assert!(listing.text().contains("docs"));
+        let routes = server.test_request("GET", "/routes/").send();
+        assert_eq!(routes.status(), 200);
+        assert_eq!(routes.header("X-Frame-Options"), Some("DENY"));
+        let missing = server.test_request("GET", "/missing.html").send();
+        assert_eq!((missing.status(), missing.text().as_str()), (404, "lost"));
+        let conf = server.test_request("GET", "/docs/app.conf").send();
+        assert_eq!(
+            conf.header("Content-Type"),
+            Some("text/plain; charset=utf-8")
+        );
+
+        let log = fs::read_to_string(&log).unwrap();
+        assert!(log.contains("Unknown configuration key unknown_top"));
+        // Debug messages are left out at the info level
+        assert!(!log.contains("DEBUG"), "{}", log);
+        assert!(log.contains("Unknown configuration key hsts.typo"));
+
+        // Setters called afterwards win
+        server.set_port(9090);
+        assert_eq!(server.port(), Some(9090));
+    }
+
+    #[test]
+    fn test_invalid_values() {
+        assert_eq!(invalid_key("port = \"80\""), "port");
+        assert_eq!(invalid_key("port = -1"), "port");
+        assert_eq!(invalid_key("port = 70000"), "port");
+        assert_eq!(invalid_key("index_of = 1"), "index_of");
+        assert_eq!(invalid_key("show_hidden = 1"), "show_hidden");
+        assert_eq!(invalid_key("follow_symlinks = \"yes\""), "follow_symlinks");
+        assert_eq!(
+            invalid_key("template_root = \"/nonexistent/\""),
+            "template_root"
+        );
+        assert_eq!(
+            invalid_key("rate_limit_exempt = [\"nowhere\"]"),
+            "rate_limit_exempt"
+        );
+        assert_eq!(
+            invalid_key("access_log = \"/does/not/exist/access.log\""),
+            "access_log"
+        );
+        assert_eq!(
+            invalid_key("cache_control = \"max-age=1\\nX: 1\""),
+            "cache_control"
+        );
+        assert_eq!(
+            invalid_key("index_files = [\"docs/index.html\"]"),
+            "index_files"
+        );
+        assert_eq!(invalid_key("workers = 0"), "workers");
+        assert_eq!(invalid_key("bind = 8080"), "bind");
+        assert_eq!(invalid_key("bind = \"nowhere\""), "bind");
+        assert_eq!(
+            invalid_key("bind = [\"127.0.0.1:80\", \"nowhere:port\"]"),
+            "bind[1]"
+        );
+        assert_eq!(invalid_key("bind = [\"127.0.0.1:80\", 80]"), "bind[1]");
+        assert_eq!(invalid_key("log_level = \"loud\""), "log_level");
+        assert_eq!(invalid_key("log_level = 1"), "log_level");
+        assert_eq!(
+            invalid_key("max_queued_connections = -1"),
+            "max_queued_connections"
+        );
+        assert_eq!(invalid_key("max_body_size = -1"), "max_body_size");
+        assert_eq!(invalid_key("static_cache = true"), "static_cache");
+        assert_eq!(
+            invalid_key("[static_cache]\nmax_file_bytes = 1024"),
+            "static_cache.max_bytes"
+        );
+        assert_eq!(
+            invalid_key("request_head_timeout = true"),
+            "request_head_timeout"
+        );
+        assert_eq!(invalid_key("handler_timeout = \"soon\""), "handler_timeout");
+        assert_eq!(invalid_key("sessions = \"yes\""), "sessions");
+        assert_eq!(invalid_key("session_expiry = \"soon\""), "session_expiry");
+        assert_eq!(invalid_key("default_charset = true"), "default_charset");
+        assert_eq!(
+            invalid_key("[default_headers]\nX-Count = 1"),
+            "default_headers.X-Count"
+        );
+        assert_eq!(invalid_key("favicon = \"/does/not/exist.ico\""), "favicon");
+        assert_eq!(
+            invalid_key("[error_pages]\n200 = \"ok.html\""),
+            "error_pages.200"
+        );
+        assert_eq!(
+            invalid_key("[mime_types]\nmd = \"markdown\""),
+            "mime_types.md"
+        );
+        assert_eq!(
+            invalid_key("document_root = \"/does/not/exist\""),
+            "document_root"
+        );
+        assert_eq!(
+            invalid_key("trusted_proxies = [\"10.0.0.1\", 2]"),
+            "trusted_proxies[1]"
+        );
+        assert_eq!(
+            invalid_key("trusted_proxies = [\"nonsense\"]"),
+            "trusted_proxies"
+        );
+        assert_eq!(
+            invalid_key("duplicate_routes = \"keep\""),
+            "duplicate_routes"
+        );
+        assert_eq!(
+            invalid_key("static_precedence = \"files\""),
+            "static_precedence"
+        );
+        assert_eq!(invalid_key("trailing_slash = \"add\""), "trailing_slash");
+        assert_eq!(
+            invalid_key("[cors]\nallowed_origins = [\"*\"]\nallow_credentials = true"),
+            "cors.allow_credentials"
+        );
+        assert_eq!(
+            invalid_key("shutdown_deadline = \"soon\""),
+            "shutdown_deadline"
+        );
+        assert_eq!(
+            invalid_key("[stat_cache]\ncapacity = true"),
+            "stat_cache.capacity"
+        );
+        assert_eq!(invalid_key("keep_alive = 1"), "keep_alive");
+        assert_eq!(invalid_key("[keep_alive]\nmax_requests = 0"), "keep_alive");
+        assert_eq!(invalid_key("[hsts]\npreload = true"), "hsts.max_age");
+        assert_eq!(
+            invalid_key("[[proxy]]\nprefix = \"/a/\"\nupstream = \"http://a\"\n[[proxy]]\nprefix = \"/b/\"\nupstream = 5"),
+            "proxy[1].upstream"
+        );
+    }
+
+    #[test]
+    fn test_env_overrides() {
+        let _env = lock_env();
+        let path = std::env::temp_dir().join("corrodedweb_config_env.toml");
+        fs::write(&path, "port = 8080\nindex_of = false").unwrap();
+
+        env::set_var("PORT", "5000");
+        let mut server = Server::new();
+        server.load_env_config().unwrap();
+        assert_eq!(server.port(), Some(5000));
+
+        env::set_var("CORRODED_PORT", "6000");
+        env::set_var("CORRODED_INDEX_OF", "true");
+        env::set_var("CORRODED_DOC_ROOT", "");
+        env::set_var("CORRODED_BIND", "127.0.0.1:6001, [::1]:6001");
+        env::set_var("CORRODED_LOG_LEVEL", "WARNING");
+        env::set_var("CORRODED_WORKERS", "3");
+        let mut server = Server::from_config_file(&path).unwrap();
+        assert_eq!(server.port(), Some(6000));
+        assert_eq!(server.get_document_root(), None);
+        assert_eq!(
+            server.listen_addrs(),
+            [
+                "127.0.0.1:6001".parse().unwrap(),
+                "[::1]:6001".parse().unwrap()
+            ]
+        );
+        assert_eq!(server.log_level(), Some(Level::Warning));
+        assert_eq!(server.workers(), 3);
+        server.set_port(7000);
+        assert_eq!(server.port(), Some(7000));
+
+        let invalid = [
+            ("CORRODED_PORT", "http"),
+            ("CORRODED_INDEX_OF", "maybe"),
+            ("CORRODED_DOC_ROOT", "/does/not/exist"),
+            ("CORRODED_SHUTDOWN_DEADLINE", "soon"),
+            ("CORRODED_BIND", "127.0.0.1:80,nowhere:port"),
+            ("CORRODED_LOG_LEVEL", "loud"),
+            ("CORRODED_WORKERS", "many"),
+            ("CORRODED_WORKERS", "0"),
+        ];
+        for (variable, value) in invalid.iter() {
+            let previous = env::var(variable).ok();
+            env::set_var(variable, value);
+            match Server::new().load_env_config() {
+                Err(ConfigError::Env { variable: name, .. }) => assert_eq!(&name, variable),
+                other => panic!("{}={} gave {:?}", variable, value, other),
+            }
+            match previous {
+                Some(previous) => env::set_var(variable, previous),
+                None => env::remove_var(variable),
+            }
+        }
+        let message = {
+            env::set_var("PORT", "-1");
+            env::remove_var("CORRODED_PORT");
+            Server::new().load_env_config().unwrap_err().to_string()
+        };
+        assert_eq!(
+            message,
+            "environment variable PORT: expected a port number, found \"-1\""
+        );
+
+        for variable in &[
+            "PORT",
+            "CORRODED_PORT",
+            "CORRODED_INDEX_OF",
+            "CORRODED_DOC_ROOT",
+            "CORRODED_BIND",
+            "CORRODED_LOG_LEVEL",
+            "CORRODED_WORKERS",
+        ] {
+            env::remove_var(variable);
+        }
+    }
+
+    #[test]
+    fn test_read_errors() {
+        let missing = Server::from_config_file("/does/not/exist.toml");
+        assert!(matches!(missing, Err(ConfigError::Read { .. })));
+
+        let path = std::env::temp_dir().join("corrodedweb_config_syntax.toml");
+        fs::write(&path, "port = ").unwrap();
+        let message = Server::from_config_file(&path).err().unwrap().to_string();
+        assert!(message.starts_with("invalid TOML"), "{}", message);
+    }
+}
@@ -0,0 +1,66 @@
+use std::sync::{Arc, Mutex};
+
+/// Decides between a handler finishing and its timeout, whichever comes
+/// first, see `Server::set_handler_timeout`
+///
+/// Clones share the decision.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Watch {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum State {
+    #[default]
+    Running,
+    Finished,
+    Abandoned,
+}
+
+impl Watch {
+    /// Records that the handler returned, returning false if it was
+    /// abandoned before
+    pub fn finish(&self) -> bool {
+        self.decide(State::Finished)
+    }
+
+    /// Records that the handler ran out of time, returning false if it
+    /// finished before
+    pub fn abandon(&self) -> bool {
+        self.decide(State::Abandoned)
+    }
+
+    /// Returns whether the handler ran out of time, so what it writes goes
+    /// nowhere
+    pub fn is_abandoned(&self) -> bool {
+        *self.state.lock().unwrap() == State::Abandoned
+    }
+
+    fn decide(&self, outcome: State) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if *state != State::Running {
+            return false;
+        }
+        *state = outcome;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_first_decision_wins() {
+        let watch = Watch::default();
+        assert!(!watch.is_abandoned());
+        assert!(watch.clone().abandon());
+        assert!(!watch.finish());
+        assert!(watch.is_abandoned());
+
+        let watch = Watch::default();
+        assert!(watch.finish());
+        assert!(!watch.abandon());
+        assert!(!watch.is_abandoned());
+    }
+}
@@ -0,0 +1,188 @@
+use crate::logger::Logger;
+use std::fmt::Write;
+use std::net::SocketAddr;
+
+/// How many body bytes a dump shows unless configured otherwise
+pub(crate) const DEFAULT_BODY_LIMIT: usize = 256;
+
+/// The headers whose values a dump hides unless unredacted
+const REDACTED_HEADERS: [&str; 4] = [
+    "Authorization",
+    "Proxy-Authorization",
+    "Cookie",
+    "Set-Cookie",
+];
+
+/// How the development dump of every request and response is done, see
+/// `Server::set_debug_dump`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct DumpSettings {
+    pub enabled: bool,
+    /// How many body bytes are shown
+    pub body_limit: usize,
+    /// Whether credentials and cookies are shown
+    pub unredacted: bool,
+}
+
+impl Default for DumpSettings {
+    fn default() -> Self {
+        DumpSettings {
+            enabled: false,
+            body_limit: DEFAULT_BODY_LIMIT,
+            unredacted: false,
+        }
+    }
+}
+
+/// Logs what went over one connection at debug level
+#[derive(Clone)]
+pub(crate) struct Dump {
+    logger: Option<Logger>,
+    settings: DumpSettings,
+    /// The head of a response whose body is streamed, logged once the body
+    /// is complete
+    streamed_head: Option<String>,
+    preview: Vec<u8>,
+    streamed_len: u64,
+}
+
+impl Dump {
+    /// Returns a dump logging to `logger`, or None if dumps are disabled
+    pub fn new(logger: &Option<Logger>, settings: DumpSettings) -> Option<Self> {
+        if !settings.enabled || logger.is_none() {
+            return None;
+        }
+        Some(Dump {
+            logger: logger.clone(),
+            settings,
+            streamed_head: None,
+            preview: Vec::new(),
+            streamed_len: 0,
+        })
+    }
+
+    /// Logs the head and body of a request as they were received
+    pub fn request(&self, peer_addr: Option<SocketAddr>, head: &str, body: &[u8]) {
+        let from = peer_addr.map_or_else(|| String::from("unknown"), |peer| peer.to_string());
+        self.log(
+            &format!("Request from {}", from),
+            head,
+            body,
+            body.len() as u64,
+        );
+    }
+
+    /// Logs a response sent in one piece, `head` ending with the empty line
+    pub fn response(&self, head: &str, body: &[u8]) {
+        self.log("Response", head, body, body.len() as u64);
+    }
+
+    /// Logs a response whose body of `len` bytes is not at hand, e.g. a
+    /// static file
+    pub fn response_without_body(&self, head: &str, len: u64) {
+        self.log("Response", head, &[], len);
+    }
+
+    /// Remembers the head of a response whose body follows in parts
+    pub fn start_stream(&mut self, head: &str) {
+        self.streamed_head = Some(String::from(head));
+    }
+
+    /// Counts a part of a streamed body, keeping what the preview shows
+    pub fn stream(&mut self, part: &[u8]) {
+        let room = self.settings.body_limit.saturating_sub(self.preview.len());
+        self.preview
+            .extend_from_slice(&part[..part.len().min(room)]);
+        self.streamed_len += part.len() as u64;
+    }
+
+    /// Logs a streamed response once its body is complete
+    pub fn finish_stream(&mut self) {
+        if let Some(head) = self.streamed_head.take() {
+            self.log("Response", &head, &self.preview, self.streamed_len);
+        }
+    }
+
+    fn log(&self, title: &str, head: &str, body: &[u8], len: u64) {
+        let mut message = format!("DUMP {}\n", title);
+        for (index, line) in head.trim_end().split("\r\n").enumerate() {
+            match line.split_once(':') {
+                Some((name, _)) if index > 0 && self.hidden(name.trim()) => {
+                    let _ = writeln!(message, "{}: [redacted]", name);
+                }
+                _ => {
+                    let _ = writeln!(message, "{}", line);
+                }
+            }
+        }
+        let shown = &body[..body.len().min(self.settings.body_limit)];
+        if len == 0 {
+            message.push_str("No body");
+        } else if shown.is_empty() {
+            let _ = write!(message, "Body of {} bytes not shown", len);
+        } else if shown.len() as u64 == len {
+            let _ = write!(message, "Body of {} bytes:\n{}", len, hexdump(shown));
+        } else {
+            let _ = write!(
+                message,
+                "Body of {} bytes, the first {}:\n{}",
+                len,
+                shown.len(),
+                hexdump(shown)
+            );
+        }
+        Logger::debug(&self.logger, message.trim_end());
+    }
+
+    fn hidden(&self, name: &str) -> bool {
+        !self.settings.unredacted
+            && REDACTED_HEADERS
+                .iter()
+                .any(|redacted| redacted.eq_ignore_ascii_case(name))
+    }
+}
+
+/// Shows bytes like `hexdump -C` does, sixteen per line with their offset
+/// and the printable ones as text
+pub(crate) fn hexdump(bytes: &[u8]) -> String {
+    let mut dump = String::new();
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        let _ = write!(dump, "{:08x} ", line * 16);
+        for column in 0..16 {
+            if column == 8 {
+                dump.push(' ');
+            }
+            match chunk.get(column) {
+                Some(byte) => {
+                    let _ = write!(dump, " {:02x}", byte);
+                }
+                None => dump.push_str("   "),
+            }
+        }
+        dump.push_str("  |");
+        dump.extend(chunk.iter().map(|&byte| {
+            if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '.'
+            }
+        }));
+        dump.push_str("|\n");
+    }
+    dump
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hexdump() {
+        assert_eq!(hexdump(b""), "");
+        assert_eq!(
+            hexdump(b"GET / HTTP/1.1\r\nHost"),
+            "00000000  47 45 54 20 2f 20 48 54  54 50 2f 31 2e 31 0d 0a  |GET / HTTP/1.1..|\n\
+             00000010  48 6f 73 74                                       |Host|\n"
+        );
+    }
+}
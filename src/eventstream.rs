@@ -0,0 +1,125 @@
+use crate::server::Response;
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Pushes Server-Sent Events to the client over a response that stays open,
+/// see `Response::start_event_stream`
+///
+/// Every event is flushed right away. Once the client went away sending
+/// fails, which is how a handler pushing events in a loop learns that it
+/// can stop. The stream ends when the handler returns.
+pub struct EventStream<'a> {
+    response: &'a mut Response,
+    /// How long the stream may stay silent before `wait` sends a comment
+    keep_alive: Option<Duration>,
+    last_sent: Instant,
+}
+
+impl<'a> EventStream<'a> {
+    pub(crate) fn new(response: &'a mut Response) -> Self {
+        EventStream {
+            response,
+            keep_alive: None,
+            last_sent: Instant::now(),
+        }
+    }
+
+    /// Sends an event of type `name` that carries `data`, which the browser
+    /// hands to listeners added with `addEventListener(name, ...)`
+    ///
+    /// Fails without sending anything if `name` contains a line break, and
+    /// once the client is gone.
+    pub fn send_event(&mut self, name: &str, data: &str) -> io::Result<()> {
+        if name.contains(['\r', '\n']) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "event names cannot contain line breaks",
+            ));
+        }
+        self.send(&frame(Some(name), data))
+    }
+
+    /// Sends an event without a type carrying `data`, which the browser
+    /// hands to `onmessage`
+    ///
+    /// Fails once the client is gone.
+    pub fn send_data(&mut self, data: &str) -> io::Result<()> {
+        self.send(&frame(None, data))
+    }
+
+    /// Lets `wait` send a comment whenever the stream was silent for
+    /// `interval`, so proxies and clients do not take it for dead, off by
+    /// default
+    pub fn set_keep_alive(&mut self, interval: Duration) {
+        self.keep_alive = Some(interval);
+    }
+
+    /// Sleeps for `duration` between events, sending keep-alive comments
+    /// meanwhile if `set_keep_alive` is on
+    ///
+    /// Fails as soon as a keep-alive comment cannot be sent because the
+    /// client is gone.
+    pub fn wait(&mut self, duration: Duration) -> io::Result<()> {
+        let until = Instant::now() + duration;
+        loop {
+            let now = Instant::now();
+            let keep_alive = self
+                .keep_alive
+                .map(|interval| self.last_sent + interval)
+                .filter(|due| *due < until);
+            match keep_alive {
+                Some(due) if due <= now => self.send(": keep-alive\n\n")?,
+                Some(due) => thread::sleep(due - now),
+                None => {
+                    thread::sleep(until.saturating_duration_since(now));
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    fn send(&mut self, frame: &str) -> io::Result<()> {
+        self.response.write(frame)?;
+        self.response.flush()?;
+        self.last_sent = Instant::now();
+        Ok(())
+    }
+}
+
+/// Returns the lines of an event ending in the empty line, with a `data`
+/// line per line of `data` since a line break would end the field
+fn frame(name: Option<&str>, data: &str) -> String {
+    let mut frame = String::with_capacity(data.len() + 16);
+    if let Some(name) = name {
+        frame.push_str("event: ");
+        frame.push_str(name);
+        frame.push('\n');
+    }
+    for line in data.split("\r\n").flat_map(|line| line.split(['\r', '\n'])) {
+        frame.push_str("data: ");
+        frame.push_str(line);
+        frame.push('\n');
+    }
+    frame.push('\n');
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame() {
+        assert_eq!(frame(None, "hello"), "data: hello\n\n");
+        assert_eq!(
+            frame(Some("tick"), "12:00:00"),
+            "event: tick\ndata: 12:00:00\n\n"
+        );
+        assert_eq!(
+            frame(None, "one\ntwo\r\nthree\rfour"),
+            "data: one\ndata: two\ndata: three\ndata: four\n\n"
+        );
+        assert_eq!(frame(None, ""), "data: \n\n");
+    }
+}
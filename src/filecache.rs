@@ -0,0 +1,173 @@
+use crate::statcache::FileStat;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+/// Files larger than this are streamed instead of cached unless configured
+/// otherwise
+pub(crate) const DEFAULT_MAX_FILE_SIZE: u64 = 1024 * 1024;
+
+/// Keeps the contents of static files in memory, evicting the least
+/// recently used ones once they take more than a byte budget, see
+/// `Server::enable_static_cache`
+///
+/// The cache is shared by all workers. Hits only take the read lock, so
+/// workers serving cached files do not wait for each other.
+pub(crate) struct FileCache {
+    max_size: u64,
+    max_file_size: u64,
+    /// Counts lookups, entries remember when they were last used
+    clock: AtomicU64,
+    entries: RwLock<Entries>,
+}
+
+#[derive(Default)]
+struct Entries {
+    /// Keyed by canonical path
+    by_path: HashMap<PathBuf, Entry>,
+    /// The bytes of all contents
+    size: u64,
+}
+
+struct Entry {
+    modified: SystemTime,
+    contents: Arc<[u8]>,
+    last_used: AtomicU64,
+}
+
+impl FileCache {
+    pub fn new(max_size: u64, max_file_size: u64) -> Self {
+        FileCache {
+            max_size,
+            max_file_size: max_file_size.min(max_size),
+            clock: AtomicU64::new(0),
+            entries: RwLock::default(),
+        }
+    }
+
+    /// Returns the contents of the file at the canonical `path`, opened as
+    /// `file` with the metadata `stat`, reading and keeping them on a miss
+    ///
+    /// Entries whose file was modified since are read again. Returns `None`
+    /// for files too large to be cached, without a modification time to
+    /// tell whether they changed, or that cannot be read completely.
+    pub fn contents(&self, path: &Path, file: &mut File, stat: &FileStat) -> Option<Arc<[u8]>> {
+        let modified = stat.modified?;
+        if stat.is_dir || stat.len > self.max_file_size {
+            return None;
+        }
+        let now = self.clock.fetch_add(1, Ordering::Relaxed);
+        if let Some(entry) = self.entries.read().unwrap().by_path.get(path) {
+            if entry.modified == modified && entry.contents.len() as u64 == stat.len {
+                entry.last_used.store(now, Ordering::Relaxed);
+                return Some(entry.contents.clone());
+            }
+        }
+        // Read without holding the lock, so other files are served meanwhile
+        let mut contents = Vec::with_capacity(stat.len as usize);
+        file.seek(SeekFrom::Start(0)).ok()?;
+        file.take(stat.len).read_to_end(&mut contents).ok()?;
+        if contents.len() as u64 != stat.len {
+            // Truncated while it was read
+            return None;
+        }
+        let contents: Arc<[u8]> = contents.into();
+        self.insert(path, modified, contents.clone(), now);
+        Some(contents)
+    }
+
+    /// Keeps `contents`, evicting the least recently used entries until they
+    /// fit the budget
+    fn insert(&self, path: &Path, modified: SystemTime, contents: Arc<[u8]>, now: u64) {
+        let mut entries = self.entries.write().unwrap();
+        if let Some(stale) = entries.by_path.remove(path) {
+            entries.size -= stale.contents.len() as u64;
+        }
+        let len = contents.len() as u64;
+        while entries.size + len > self.max_size {
+            let oldest = entries
+                .by_path
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used.load(Ordering::Relaxed))
+                .map(|(path, _)| path.clone());
+            match oldest.and_then(|oldest| entries.by_path.remove(&oldest)) {
+                Some(evicted) => entries.size -= evicted.contents.len() as u64,
+                None => return,
+            }
+        }
+        entries.size += len;
+        entries.by_path.insert(
+            path.to_path_buf(),
+            Entry {
+                modified,
+                contents,
+                last_used: AtomicU64::new(now),
+            },
+        );
+    }
+
+    /// Returns the cached paths and how many bytes they take
+    #[cfg(test)]
+    fn cached(&self) -> (Vec<PathBuf>, u64) {
+        let entries = self.entries.read().unwrap();
+        let mut paths: Vec<PathBuf> = entries.by_path.keys().cloned().collect();
+        paths.sort();
+        (paths, entries.size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::Duration;
+
+    /// Writes `contents` to `name` in `dir` and opens it
+    fn open(dir: &Path, name: &str, contents: &str) -> (PathBuf, File, FileStat) {
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        let file = File::open(&path).unwrap();
+        let stat = FileStat::from(&file.metadata().unwrap());
+        (path, file, stat)
+    }
+
+    #[test]
+    fn test_file_cache() {
+        let dir = std::env::temp_dir().join("corrodedweb_file_cache");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let cache = FileCache::new(10, 6);
+
+        let (a, mut file, stat) = open(&dir, "a", "aaaa");
+        assert_eq!(&*cache.contents(&a, &mut file, &stat).unwrap(), b"aaaa");
+        // Served from memory although the open file is at its end
+        assert_eq!(&*cache.contents(&a, &mut file, &stat).unwrap(), b"aaaa");
+
+        let (b, mut file, stat) = open(&dir, "b", "bbbbb");
+        assert_eq!(&*cache.contents(&b, &mut file, &stat).unwrap(), b"bbbbb");
+        assert_eq!(cache.cached(), (vec![a.clone(), b.clone()], 9));
+
+        let (big, mut file, stat) = open(&dir, "big", "ggggggg");
+        assert!(cache.contents(&big, &mut file, &stat).is_none());
+
+        // Using a makes b the least recently used
+        let (a, mut file, stat) = open(&dir, "a", "aaaa");
+        cache.contents(&a, &mut file, &stat).unwrap();
+        let (c, mut file, stat) = open(&dir, "c", "cc");
+        cache.contents(&c, &mut file, &stat).unwrap();
+        assert_eq!(cache.cached(), (vec![a.clone(), c.clone()], 6));
+
+        // A file modified since is read again
+        let (a, mut file, mut stat) = open(&dir, "a", "AAAAAA");
+        stat.modified = stat
+            .modified
+            .map(|modified| modified + Duration::from_secs(1));
+        assert_eq!(&*cache.contents(&a, &mut file, &stat).unwrap(), b"AAAAAA");
+        assert_eq!(cache.cached(), (vec![a, c], 8));
+    }
+}
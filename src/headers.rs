@@ -0,0 +1,385 @@
+use std::iter::FromIterator;
+
+/// Headers that may appear only once in a message. Adding one of them again
+/// replaces the old value.
+const SINGLE_VALUE: &[&str] = &[
+    "age",
+    "authorization",
+    "content-length",
+    "content-location",
+    "content-range",
+    "content-type",
+    "date",
+    "etag",
+    "expires",
+    "host",
+    "last-modified",
+    "location",
+    "retry-after",
+    "server",
+];
+
+/// Headers whose values must never be joined with commas, since the values
+/// contain commas themselves
+const NEVER_JOINED: &[&str] = &["set-cookie"];
+
+/// The headers of a request or response
+///
+/// Names are case-insensitive for lookups but kept as they were added.
+/// A header may appear several times, e.g. `Set-Cookie`, and iteration
+/// follows the order the headers were added in.
+///
+/// # Example
+///
+/// ```
+/// use corrodedweb::Headers;
+/// let mut headers = Headers::new();
+/// headers.append("Accept", "text/html");
+/// headers.append("accept", "application/json");
+/// assert_eq!(headers.get("ACCEPT"), Some("text/html"));
+/// assert_eq!(headers.get_all("Accept").count(), 2);
+/// assert_eq!(
+///     headers.get_joined("Accept").as_deref(),
+///     Some("text/html, application/json")
+/// );
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Headers {
+    entries: Vec<(String, String)>,
+}
+
+impl Headers {
+    /// Returns an empty set of headers
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Returns an empty set of headers with room for `capacity` of them
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        Headers {
+            entries: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Returns the first value of the header `name`
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns all values of the header `name` in the order they were added
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns all values of the header `name` joined with `, `, which means
+    /// the same as separate headers for most of them
+    ///
+    /// `Set-Cookie` values contain commas themselves, so `None` is returned
+    /// for it, use `get_all` instead.
+    pub fn get_joined(&self, name: &str) -> Option<String> {
+        if is_listed(NEVER_JOINED, name) {
+            return None;
+        }
+        let values: Vec<&str> = self.get_all(name).collect();
+        if values.is_empty() {
+            None
+        } else {
+            Some(values.join(", "))
+        }
+    }
+
+    /// Returns whether the header `name` is present
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Adds a value for the header `name`, keeping the ones it already has
+    ///
+    /// Headers that may only appear once, like `Content-Length` or
+    /// `Content-Type`, are replaced instead.
+    pub fn append(&mut self, name: &str, value: &str) {
+        if is_listed(SINGLE_VALUE, name) {
+            self.set(name, value);
+        } else {
+            self.entries.push((String::from(name), String::from(value)));
+        }
+    }
+
+    /// Sets the header `name` to `value`, replacing all values it had
+    ///
+    /// The header keeps its position if it was present.
+    pub fn set(&mut self, name: &str, value: &str) {
+        match self
+            .entries
+            .iter()
+            .position(|(header, _)| header.eq_ignore_ascii_case(name))
+        {
+            Some(first) => {
+                self.entries[first] = (String::from(name), String::from(value));
+                let mut index = 0;
+                self.entries.retain(|(header, _)| {
+                    index += 1;
+                    index - 1 <= first || !header.eq_ignore_ascii_case(name)
+                });
+            }
+            None => self.entries.push((String::from(name), String::from(value))),
+        }
+    }
+
+    /// Removes all values of the header `name` and returns the first one
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        let first = self.get(name).map(String::from);
+        self.entries
+            .retain(|(header, _)| !header.eq_ignore_ascii_case(name));
+        first
+    }
+
+    /// Iterates over all names and values in the order they were added
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    /// Returns the lowercase options of the `Connection` header, e.g.
+    /// `close`, `keep-alive` or names of further hop-by-hop headers
+    pub(crate) fn connection_options(&self) -> Vec<String> {
+        self.get_all("Connection")
+            .flat_map(|value| value.split(','))
+            .map(|option| option.trim().to_ascii_lowercase())
+            .filter(|option| !option.is_empty())
+            .collect()
+    }
+
+    /// Removes the headers the `Connection` header names, which concern only
+    /// the connection to the client and not the request
+    pub(crate) fn remove_connection_options(&mut self) {
+        let options = self.connection_options();
+        self.entries.retain(|(header, _)| {
+            !options
+                .iter()
+                .any(|option| option.eq_ignore_ascii_case(header))
+        });
+    }
+
+    /// Adds request header names to the `Vary` header, keeping the names it
+    /// lists already and leaving out duplicates
+    ///
+    /// All values end up in a single `Vary` header. A `*` stays alone, as it
+    /// covers every header.
+    pub(crate) fn merge_vary<'a, I>(&mut self, names: I)
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut merged: Vec<String> = Vec::new();
+        let mut add = |name: &str| {
+            let name = name.trim();
+            if !name.is_empty() && !merged.iter().any(|known| known.eq_ignore_ascii_case(name)) {
+                merged.push(String::from(name));
+            }
+        };
+        for value in self.get_all("Vary") {
+            value.split(',').for_each(&mut add);
+        }
+        names.into_iter().for_each(add);
+        if merged.is_empty() {
+            return;
+        }
+        if merged.iter().any(|name| name == "*") {
+            merged = vec![String::from("*")];
+        }
+        self.set("Vary", &merged.join(", "));
+    }
+
+    /// Returns how many values there are, counting repeated headers
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns whether there are no headers
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl FromIterator<(String, String)> for Headers {
+    fn from_iter<I: IntoIterator<Item = (String, String)>>(iter: I) -> Self {
+        let mut headers = Headers::new();
+        for (name, value) in iter {
+            headers.append(&name, &value);
+        }
+        headers
+    }
+}
+
+fn is_listed(list: &[&str], name: &str) -> bool {
+    list.iter().any(|header| header.eq_ignore_ascii_case(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(entries: &[(&str, &str)]) -> Headers {
+        entries
+            .iter()
+            .map(|(name, value)| (String::from(*name), String::from(*value)))
+            .collect()
+    }
+
+    #[test]
+    fn test_lookup_is_case_insensitive() {
+        let headers = headers(&[("Content-Type", "text/html"), ("X-Request-ID", "42")]);
+        assert_eq!(headers.get("content-type"), Some("text/html"));
+        assert_eq!(headers.get("CONTENT-TYPE"), Some("text/html"));
+        assert_eq!(headers.get("x-request-id"), Some("42"));
+        assert!(headers.contains("X-REQUEST-ID"));
+        assert_eq!(headers.get("Content"), None);
+        assert!(!headers.contains("Accept"));
+    }
+
+    #[test]
+    fn test_original_casing_and_order() {
+        let headers = headers(&[
+            ("Host", "example.com"),
+            ("accept", "*/*"),
+            ("X-Custom", "a"),
+            ("USER-AGENT", "curl"),
+            ("x-custom", "b"),
+        ]);
+        let entries: Vec<_> = headers.iter().collect();
+        assert_eq!(
+            entries,
+            vec![
+                ("Host", "example.com"),
+                ("accept", "*/*"),
+                ("X-Custom", "a"),
+                ("USER-AGENT", "curl"),
+                ("x-custom", "b"),
+            ]
+        );
+        // Iterating again gives the same order
+        assert_eq!(headers.iter().collect::<Vec<_>>(), entries);
+        assert_eq!(headers.len(), 5);
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let mut headers = headers(&[
+            ("Set-Cookie", "a=1; Expires=Wed, 21 Oct 2026 07:28:00 GMT"),
+            ("Accept", "text/html"),
+            ("set-cookie", "b=2"),
+            ("Accept", "*/*;q=0.8"),
+        ]);
+        assert_eq!(
+            headers.get_all("Set-Cookie").collect::<Vec<_>>(),
+            vec!["a=1; Expires=Wed, 21 Oct 2026 07:28:00 GMT", "b=2"]
+        );
+        assert_eq!(
+            headers.get("set-cookie"),
+            Some("a=1; Expires=Wed, 21 Oct 2026 07:28:00 GMT")
+        );
+        assert_eq!(
+            headers.get_joined("accept").as_deref(),
+            Some("text/html, */*;q=0.8")
+        );
+        assert_eq!(headers.get_joined("Set-Cookie"), None);
+        assert_eq!(headers.get_joined("Missing"), None);
+        assert_eq!(headers.get_all("Missing").count(), 0);
+
+        assert_eq!(
+            headers.remove("SET-COOKIE").as_deref(),
+            Some("a=1; Expires=Wed, 21 Oct 2026 07:28:00 GMT")
+        );
+        assert!(!headers.contains("set-cookie"));
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers.remove("set-cookie"), None);
+    }
+
+    #[test]
+    fn test_single_value_headers() {
+        let mut headers = Headers::new();
+        headers.append("Content-Length", "10");
+        headers.append("Link", "</a.css>; rel=preload");
+        headers.append("content-length", "20");
+        headers.append("Link", "</b.js>; rel=preload");
+        assert_eq!(
+            headers.get_all("Content-Length").collect::<Vec<_>>(),
+            vec!["20"]
+        );
+        assert_eq!(headers.get_all("Link").count(), 2);
+        // The replaced header stays in front
+        assert_eq!(headers.iter().next(), Some(("content-length", "20")));
+    }
+
+    #[test]
+    fn test_set() {
+        let mut headers = headers(&[
+            ("Vary", "Accept"),
+            ("Cache-Control", "no-cache"),
+            ("vary", "Cookie"),
+            ("X-Last", "1"),
+        ]);
+        headers.set("VARY", "*");
+        assert_eq!(
+            headers.iter().collect::<Vec<_>>(),
+            vec![
+                ("VARY", "*"),
+                ("Cache-Control", "no-cache"),
+                ("X-Last", "1")
+            ]
+        );
+        headers.set("X-New", "2");
+        assert_eq!(headers.iter().last(), Some(("X-New", "2")));
+        assert!(!headers.is_empty());
+        assert!(Headers::new().is_empty());
+    }
+
+    #[test]
+    fn test_connection_options() {
+        let mut headers = headers(&[
+            ("Connection", "Keep-Alive, X-Trace"),
+            ("Keep-Alive", "timeout=5"),
+            ("X-Trace", "1"),
+            ("connection", " , upgrade"),
+            ("Upgrade", "h2c"),
+            ("Accept", "*/*"),
+        ]);
+        assert_eq!(
+            headers.connection_options(),
+            vec!["keep-alive", "x-trace", "upgrade"]
+        );
+        headers.remove_connection_options();
+        assert_eq!(
+            headers.iter().map(|(name, _)| name).collect::<Vec<_>>(),
+            vec!["Connection", "connection", "Accept"]
+        );
+        assert!(Headers::new().connection_options().is_empty());
+    }
+
+    #[test]
+    fn test_merge_vary() {
+        let mut empty = Headers::new();
+        empty.merge_vary(vec![]);
+        assert!(!empty.contains("Vary"));
+        empty.merge_vary(vec!["Accept-Encoding"]);
+        assert_eq!(empty.get("Vary"), Some("Accept-Encoding"));
+
+        let mut listed = headers(&[("vary", "Cookie,  accept"), ("Vary", "User-Agent")]);
+        listed.merge_vary(vec!["Accept", "Origin", "accept-encoding", "origin"]);
+        assert_eq!(
+            listed.get_all("Vary").collect::<Vec<_>>(),
+            vec!["Cookie, accept, User-Agent, Origin, accept-encoding"]
+        );
+
+        let mut everything = headers(&[("Vary", "*")]);
+        everything.merge_vary(vec!["Accept"]);
+        assert_eq!(everything.get("Vary"), Some("*"));
+    }
+}
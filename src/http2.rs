@@ -0,0 +1,303 @@
+use bytes::Bytes;
+use h2::server::SendResponse;
+use h2::RecvStream;
+use rustls::ServerConnection;
+use std::future::poll_fn;
+use std::io;
+use std::io::prelude::*;
+use std::net::TcpStream;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Runs one request, given as HTTP/1.1 bytes, through the server and returns
+/// the HTTP/1.1 response it wrote
+pub(crate) type Exchange = Arc<dyn Fn(Vec<u8>) -> Vec<u8> + Send + Sync>;
+
+/// Headers that only make sense for a single HTTP/1.1 connection and must
+/// not appear in an HTTP/2 response
+const CONNECTION_HEADERS: [&str; 5] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Serves an HTTP/2 connection whose TLS handshake is already done until the
+/// client goes away
+///
+/// Every stream is translated into an HTTP/1.1 request for `exchange`, which
+/// runs on tokio's blocking pool since handlers may block. The answer is
+/// translated back and sent with HTTP/2 flow control.
+pub(crate) fn serve(
+    connection: ServerConnection,
+    tcp: TcpStream,
+    exchange: Exchange,
+) -> io::Result<()> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .build()?;
+    runtime.block_on(async move {
+        tcp.set_nonblocking(true)?;
+        let io = TlsIo {
+            connection,
+            tcp: tokio::net::TcpStream::from_std(tcp)?,
+            outgoing: Vec::new(),
+        };
+        let mut h2 = h2::server::handshake(io).await.map_err(io::Error::other)?;
+        while let Some(accepted) = h2.accept().await {
+            let (request, respond) = accepted.map_err(io::Error::other)?;
+            tokio::spawn(handle_stream(request, respond, exchange.clone()));
+        }
+        Ok(())
+    })
+}
+
+/// Answers a single HTTP/2 stream
+async fn handle_stream(
+    request: http::Request<RecvStream>,
+    mut respond: SendResponse<Bytes>,
+    exchange: Exchange,
+) -> Result<(), h2::Error> {
+    let (parts, mut body) = request.into_parts();
+    let mut content = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        let _ = body.flow_control().release_capacity(chunk.len());
+        content.extend_from_slice(&chunk);
+    }
+
+    let head_only = parts.method == http::Method::HEAD;
+    let request = to_http1_request(&parts, &content);
+    let response = tokio::task::spawn_blocking(move || exchange(request))
+        .await
+        .ok()
+        .and_then(|raw| from_http1_response(&raw));
+    let (response, content) = match response {
+        Some(response) => response,
+        None => {
+            let response = http::Response::builder()
+                .status(http::StatusCode::INTERNAL_SERVER_ERROR)
+                .body(())
+                .unwrap();
+            respond.send_response(response, true)?;
+            return Ok(());
+        }
+    };
+
+    let end_of_stream = head_only || content.is_empty();
+    let mut send = respond.send_response(response, end_of_stream)?;
+    if end_of_stream {
+        return Ok(());
+    }
+    let mut rest = Bytes::from(content);
+    while !rest.is_empty() {
+        send.reserve_capacity(rest.len());
+        let capacity = match poll_fn(|cx| send.poll_capacity(cx)).await {
+            Some(capacity) => capacity?,
+            None => return Ok(()),
+        };
+        let chunk = rest.split_to(capacity.min(rest.len()));
+        send.send_data(chunk, rest.is_empty())?;
+    }
+    Ok(())
+}
+
+/// Writes the request the way an HTTP/1.1 client would have sent it
+fn to_http1_request(parts: &http::request::Parts, body: &[u8]) -> Vec<u8> {
+    let target = parts
+        .uri
+        .path_and_query()
+        .map(|target| target.as_str())
+        .unwrap_or("/");
+    let mut request = format!("{} {} HTTP/1.1\r\n", parts.method, target);
+    if !parts.headers.contains_key(http::header::HOST) {
+        if let Some(authority) = parts.uri.authority() {
+            request.push_str(&format!("Host: {}\r\n", authority));
+        }
+    }
+    for (name, value) in &parts.headers {
+        if let Ok(value) = value.to_str() {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+    }
+    if !body.is_empty() && !parts.headers.contains_key(http::header::CONTENT_LENGTH) {
+        request.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    request.push_str("\r\n");
+
+    let mut request = request.into_bytes();
+    request.extend_from_slice(body);
+    request
+}
+
+/// Splits an HTTP/1.1 response into the HTTP/2 head and the body
+///
+/// Returns `None` if the server wrote nothing usable.
+fn from_http1_response(raw: &[u8]) -> Option<(http::Response<()>, Vec<u8>)> {
+    let end = raw.windows(4).position(|window| window == b"\r\n\r\n")?;
+    let head = std::str::from_utf8(&raw[..end]).ok()?;
+    let mut body = raw[end + 4..].to_vec();
+
+    let mut lines = head.split("\r\n");
+    let status = lines.next()?.split(' ').nth(1)?.parse::<u16>().ok()?;
+    let mut response = http::Response::builder().status(status);
+    for line in lines {
+        let (name, value) = match line.split_once(':') {
+            Some(header) => header,
+            None => continue,
+        };
+        let name = name.trim().to_lowercase();
+        let value = value.trim();
+        if CONNECTION_HEADERS.contains(&name.as_str()) {
+            continue;
+        }
+        if name == "content-length" {
+            if let Ok(len) = value.parse::<usize>() {
+                body.truncate(len);
+            }
+        }
+        response = response.header(name, value);
+    }
+    Some((response.body(()).ok()?, body))
+}
+
+/// An established rustls connection driven by tokio
+///
+/// The handshake already happened on the blocking socket, so this only has
+/// to move records between rustls and the socket.
+struct TlsIo {
+    connection: ServerConnection,
+    tcp: tokio::net::TcpStream,
+    /// Encrypted bytes rustls produced that the socket did not take yet
+    outgoing: Vec<u8>,
+}
+
+impl TlsIo {
+    /// Writes everything rustls wants to send to the socket
+    fn poll_send_records(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        loop {
+            while self.outgoing.is_empty() && self.connection.wants_write() {
+                self.connection.write_tls(&mut self.outgoing)?;
+            }
+            if self.outgoing.is_empty() {
+                return Poll::Ready(Ok(()));
+            }
+            let written = ready!(Pin::new(&mut self.tcp).poll_write(cx, &self.outgoing))?;
+            if written == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.outgoing.drain(..written);
+        }
+    }
+}
+
+impl AsyncRead for TlsIo {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            // Alerts and key updates produced while reading go out as well
+            let _ = this.poll_send_records(cx)?;
+            match this.connection.reader().read(buf.initialize_unfilled()) {
+                Ok(read) => {
+                    buf.advance(read);
+                    return Poll::Ready(Ok(()));
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+
+            let mut records = [0; 16 * 1024];
+            let mut records = ReadBuf::new(&mut records);
+            ready!(Pin::new(&mut this.tcp).poll_read(cx, &mut records))?;
+            let mut received = records.filled();
+            if received.is_empty() {
+                return Poll::Ready(Ok(()));
+            }
+            while !received.is_empty() {
+                this.connection.read_tls(&mut received)?;
+                this.connection
+                    .process_new_packets()
+                    .map_err(io::Error::other)?;
+            }
+        }
+    }
+}
+
+impl AsyncWrite for TlsIo {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            let written = this.connection.writer().write(buf)?;
+            if written > 0 || buf.is_empty() {
+                let _ = this.poll_send_records(cx)?;
+                return Poll::Ready(Ok(written));
+            }
+            // rustls buffers a limited amount of plaintext, make room first
+            ready!(this.poll_send_records(cx))?;
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.connection.writer().flush()?;
+        ready!(this.poll_send_records(cx))?;
+        Pin::new(&mut this.tcp).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.connection.send_close_notify();
+        ready!(this.poll_send_records(cx))?;
+        Pin::new(&mut this.tcp).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_http1_request() {
+        let (parts, ()) = http::Request::post("https://localhost:8443/echo/?a=1")
+            .header("content-type", "application/x-www-form-urlencoded")
+            .body(())
+            .unwrap()
+            .into_parts();
+        assert_eq!(
+            to_http1_request(&parts, b"b=2"),
+            b"POST /echo/?a=1 HTTP/1.1\r\n\
+              Host: localhost:8443\r\n\
+              content-type: application/x-www-form-urlencoded\r\n\
+              Content-Length: 3\r\n\
+              \r\n\
+              b=2"
+            .to_vec()
+        );
+    }
+
+    #[test]
+    fn test_from_http1_response() {
+        let (response, body) = from_http1_response(
+            b"HTTP/1.1 404 NOT FOUND\r\nConnection: close\r\nContent-Length: 4\r\nX-Test: yes\r\n\r\ngone!",
+        )
+        .unwrap();
+        assert_eq!(response.status(), http::StatusCode::NOT_FOUND);
+        assert!(!response.headers().contains_key("connection"));
+        assert_eq!(response.headers()["x-test"], "yes");
+        assert_eq!(body, b"gone");
+
+        assert!(from_http1_response(b"").is_none());
+        assert!(from_http1_response(b"HTTP/1.1 200 OK\r\n").is_none());
+    }
+}
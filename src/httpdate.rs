@@ -0,0 +1,440 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+const LONG_WEEKDAYS: [&str; 7] = [
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+    "Sunday",
+];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+const SECONDS_PER_DAY: i64 = 86_400;
+
+/// A point in time split into its calendar parts, always in GMT
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct DateTime {
+    year: i64,
+    /// 1 to 12
+    month: u32,
+    /// 1 to 31
+    day: u32,
+    hour: u32,
+    minute: u32,
+    second: u32,
+}
+
+impl DateTime {
+    fn from_unix(seconds: i64) -> Self {
+        let days = seconds.div_euclid(SECONDS_PER_DAY);
+        let time = seconds.rem_euclid(SECONDS_PER_DAY) as u32;
+        let (year, month, day) = civil_from_days(days);
+        DateTime {
+            year,
+            month,
+            day,
+            hour: time / 3600,
+            minute: time / 60 % 60,
+            second: time % 60,
+        }
+    }
+
+    /// Returns the seconds since the Unix epoch, or `None` if a field is out
+    /// of range, like the 30th of February
+    fn to_unix(self) -> Option<i64> {
+        if self.month < 1
+            || self.month > 12
+            || self.day < 1
+            || self.day > days_in_month(self.year, self.month)
+            || self.hour > 23
+            || self.minute > 59
+            // 60 would be a leap second, which SystemTime can't represent
+            || self.second > 59
+        {
+            return None;
+        }
+        let days = days_from_civil(self.year, self.month, self.day);
+        Some(
+            days * SECONDS_PER_DAY
+                + i64::from(self.hour) * 3600
+                + i64::from(self.minute) * 60
+                + i64::from(self.second),
+        )
+    }
+
+    fn weekday(self) -> usize {
+        // The 1st of January 1970 was a Thursday
+        (days_from_civil(self.year, self.month, self.day) + 3).rem_euclid(7) as usize
+    }
+}
+
+/// Formats `time` as an IMF-fixdate like `Sun, 06 Nov 1994 08:49:37 GMT`,
+/// the format of `Date`, `Last-Modified` and cookie `Expires`, RFC 7231
+/// section 7.1.1.1
+///
+/// Fractions of a second are cut off.
+pub(crate) fn format_http_date(time: SystemTime) -> String {
+    let date = DateTime::from_unix(unix_seconds(time));
+    format!(
+        "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[date.weekday()],
+        date.day,
+        MONTHS[date.month as usize - 1],
+        date.year,
+        date.hour,
+        date.minute,
+        date.second
+    )
+}
+
+/// Formats `time` like `10/Oct/2000:13:55:36 +0000`, the timestamp of the
+/// Common Log Format
+pub(crate) fn format_log_date(time: SystemTime) -> String {
+    let date = DateTime::from_unix(unix_seconds(time));
+    format!(
+        "{:02}/{}/{:04}:{:02}:{:02}:{:02} +0000",
+        date.day,
+        MONTHS[date.month as usize - 1],
+        date.year,
+        date.hour,
+        date.minute,
+        date.second
+    )
+}
+
+/// Returns the whole seconds since the Unix epoch, rounded down
+fn unix_seconds(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(after) => after.as_secs() as i64,
+        // Round down before the epoch as well
+        Err(before) => -(before.duration().as_secs_f64().ceil() as i64),
+    }
+}
+
+/// Parses an HTTP date in any of the three formats RFC 7231 section 7.1.1.1
+/// requires recipients to accept:
+///
+/// * IMF-fixdate: `Sun, 06 Nov 1994 08:49:37 GMT`
+/// * RFC 850: `Sunday, 06-Nov-94 08:49:37 GMT`
+/// * asctime: `Sun Nov  6 08:49:37 1994`
+///
+/// Returns `None` for anything else, including dates that don't exist.
+pub(crate) fn parse_http_date(value: &str) -> Option<SystemTime> {
+    let value = value.trim();
+    let date = parse_imf_fixdate(value)
+        .or_else(|| parse_rfc850(value))
+        .or_else(|| parse_asctime(value))?;
+    let seconds = date.to_unix()?;
+    let offset = Duration::from_secs(seconds.unsigned_abs());
+    if seconds >= 0 {
+        UNIX_EPOCH.checked_add(offset)
+    } else {
+        UNIX_EPOCH.checked_sub(offset)
+    }
+}
+
+fn parse_imf_fixdate(value: &str) -> Option<DateTime> {
+    let (weekday, rest) = value.split_once(", ")?;
+    if !WEEKDAYS.contains(&weekday) {
+        return None;
+    }
+    let mut parts = rest.split(' ');
+    let day = parse_digits(parts.next()?, 2)?;
+    let month = parse_month(parts.next()?)?;
+    let year = parse_digits(parts.next()?, 4)?;
+    let (hour, minute, second) = parse_time(parts.next()?)?;
+    if parts.next()? != "GMT" || parts.next().is_some() {
+        return None;
+    }
+    Some(DateTime {
+        year: i64::from(year),
+        month,
+        day,
+        hour,
+        minute,
+        second,
+    })
+}
+
+fn parse_rfc850(value: &str) -> Option<DateTime> {
+    let (weekday, rest) = value.split_once(", ")?;
+    if !LONG_WEEKDAYS.contains(&weekday) {
+        return None;
+    }
+    let mut parts = rest.split(' ');
+    let mut date = parts.next()?.split('-');
+    let day = parse_digits(date.next()?, 2)?;
+    let month = parse_month(date.next()?)?;
+    let short_year = parse_digits(date.next()?, 2)?;
+    if date.next().is_some() {
+        return None;
+    }
+    let (hour, minute, second) = parse_time(parts.next()?)?;
+    if parts.next()? != "GMT" || parts.next().is_some() {
+        return None;
+    }
+    Some(DateTime {
+        year: expand_year(short_year, current_year()),
+        month,
+        day,
+        hour,
+        minute,
+        second,
+    })
+}
+
+fn parse_asctime(value: &str) -> Option<DateTime> {
+    let weekday = value.get(..3)?;
+    if !WEEKDAYS.contains(&weekday) {
+        return None;
+    }
+    let month = parse_month(value.get(4..7)?)?;
+    // The day is padded with a space, e.g. `Nov  6`
+    let day = match value.get(7..10)?.as_bytes() {
+        [b' ', b' ', _] => parse_digits(&value[9..10], 1)?,
+        [b' ', _, _] => parse_digits(&value[8..10], 2)?,
+        _ => return None,
+    };
+    let mut parts = value.get(10..)?.strip_prefix(' ')?.split(' ');
+    let (hour, minute, second) = parse_time(parts.next()?)?;
+    let year = parse_digits(parts.next()?, 4)?;
+    if parts.next().is_some() || value.get(3..4)? != " " {
+        return None;
+    }
+    Some(DateTime {
+        year: i64::from(year),
+        month,
+        day,
+        hour,
+        minute,
+        second,
+    })
+}
+
+/// Parses `hh:mm:ss`
+fn parse_time(value: &str) -> Option<(u32, u32, u32)> {
+    let mut parts = value.split(':');
+    let hour = parse_digits(parts.next()?, 2)?;
+    let minute = parse_digits(parts.next()?, 2)?;
+    let second = parse_digits(parts.next()?, 2)?;
+    if parts.next().is_some() {
+        return None;
+    }
+    Some((hour, minute, second))
+}
+
+/// Parses exactly `len` ASCII digits
+fn parse_digits(value: &str, len: usize) -> Option<u32> {
+    if value.len() != len || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    value.parse().ok()
+}
+
+/// Returns the month number of a name like `Nov`, which is case-sensitive
+fn parse_month(name: &str) -> Option<u32> {
+    MONTHS
+        .iter()
+        .position(|month| *month == name)
+        .map(|index| index as u32 + 1)
+}
+
+/// Turns the two-digit year of an RFC 850 date into a full one, the latest
+/// year with those digits that is at most 50 years in the future, RFC 7231
+/// section 7.1.1.1
+fn expand_year(short_year: u32, current_year: i64) -> i64 {
+    let year = current_year - current_year.rem_euclid(100) + i64::from(short_year);
+    if year + 100 <= current_year + 50 {
+        year + 100
+    } else if year > current_year + 50 {
+        year - 100
+    } else {
+        year
+    }
+}
+
+fn current_year() -> i64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs() as i64);
+    DateTime::from_unix(now).year
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Returns the days since the 1st of January 1970 for a date of the
+/// proleptic Gregorian calendar, the algorithm of Howard Hinnant's
+/// `days_from_civil`
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month = i64::from(month);
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// The inverse of `days_from_civil`, returns year, month and day
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(seconds: i64) -> SystemTime {
+        if seconds >= 0 {
+            UNIX_EPOCH + Duration::from_secs(seconds as u64)
+        } else {
+            UNIX_EPOCH - Duration::from_secs(seconds.unsigned_abs())
+        }
+    }
+
+    #[test]
+    fn test_rfc_7231_examples() {
+        let expected = Some(at(784_111_777));
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), expected);
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), expected);
+        assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), expected);
+        assert_eq!(
+            format_http_date(at(784_111_777)),
+            "Sun, 06 Nov 1994 08:49:37 GMT"
+        );
+    }
+
+    #[test]
+    fn test_format_log_date() {
+        assert_eq!(
+            format_log_date(at(971_185_336)),
+            "10/Oct/2000:13:42:16 +0000"
+        );
+        assert_eq!(format_log_date(at(-1)), "31/Dec/1969:23:59:59 +0000");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let times = [
+            0,
+            59,
+            86_399,
+            951_782_400,   // 29 Feb 2000, 2000 is a leap year
+            1_709_164_800, // 29 Feb 2024
+            1_792_571_280, // 21 Oct 2026
+            4_107_542_399, // 28 Feb 2100, 2100 is not a leap year
+            4_107_542_400, // 01 Mar 2100
+            253_402_300_799,
+        ];
+        for seconds in times.iter() {
+            let time = at(*seconds);
+            let formatted = format_http_date(time);
+            assert_eq!(parse_http_date(&formatted), Some(time), "{}", formatted);
+        }
+        assert_eq!(format_http_date(at(0)), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(
+            format_http_date(at(951_782_400)),
+            "Tue, 29 Feb 2000 00:00:00 GMT"
+        );
+        assert_eq!(
+            format_http_date(at(4_107_542_400)),
+            "Mon, 01 Mar 2100 00:00:00 GMT"
+        );
+        assert_eq!(
+            format_http_date(at(253_402_300_799)),
+            "Fri, 31 Dec 9999 23:59:59 GMT"
+        );
+    }
+
+    #[test]
+    fn test_before_epoch() {
+        assert_eq!(format_http_date(at(-1)), "Wed, 31 Dec 1969 23:59:59 GMT");
+        assert_eq!(
+            parse_http_date("Wed, 31 Dec 1969 23:59:59 GMT"),
+            Some(at(-1))
+        );
+        assert_eq!(
+            parse_http_date("Sat, 01 Jan 0000 00:00:00 GMT").map(format_http_date),
+            Some(String::from("Sat, 01 Jan 0000 00:00:00 GMT"))
+        );
+        // Fractions round down, also before the epoch
+        assert_eq!(
+            format_http_date(UNIX_EPOCH - Duration::from_millis(500)),
+            "Wed, 31 Dec 1969 23:59:59 GMT"
+        );
+        assert_eq!(
+            format_http_date(UNIX_EPOCH + Duration::from_millis(1500)),
+            "Thu, 01 Jan 1970 00:00:01 GMT"
+        );
+    }
+
+    #[test]
+    fn test_invalid_dates() {
+        let invalid = [
+            "",
+            "yesterday",
+            "Sun, 06 Nov 1994 08:49:37 UTC",
+            "Sun, 6 Nov 1994 08:49:37 GMT",
+            "Sun, 06 nov 1994 08:49:37 GMT",
+            "Sun, 06 Nov 94 08:49:37 GMT",
+            "Sun, 06 Nov 1994 8:49:37 GMT",
+            "Sun, 06 Nov 1994 08:49:37 GMT extra",
+            "Foo, 06 Nov 1994 08:49:37 GMT",
+            "Tue, 29 Feb 2100 00:00:00 GMT",
+            "Sat, 31 Jun 2024 00:00:00 GMT",
+            "Sun, 06 Nov 1994 24:00:00 GMT",
+            "Sat, 31 Dec 2016 23:59:60 GMT",
+            "Sun, 06-Nov-94 08:49:37 GMT",
+            "Sunday, 06-Nov-1994 08:49:37 GMT",
+            "Sun Nov 6 08:49:37 1994",
+            "Sun Nov  6 08:49:37 94",
+            "Sun Nov 06 08:49:37 1994 GMT",
+        ];
+        for value in invalid.iter() {
+            assert_eq!(parse_http_date(value), None, "{}", value);
+        }
+        assert!(parse_http_date(" Sun, 06 Nov 1994 08:49:37 GMT ").is_some());
+        assert!(parse_http_date("Sun Nov 16 08:49:37 1994").is_some());
+    }
+
+    #[test]
+    fn test_expand_year() {
+        assert_eq!(expand_year(94, 2026), 1994);
+        assert_eq!(expand_year(26, 2026), 2026);
+        assert_eq!(expand_year(76, 2026), 2076);
+        assert_eq!(expand_year(77, 2026), 1977);
+        assert_eq!(expand_year(0, 2026), 2000);
+        assert_eq!(expand_year(20, 2080), 2120);
+        assert_eq!(expand_year(31, 2080), 2031);
+    }
+}
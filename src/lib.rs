@@ -11,6 +11,18 @@
 //! is specified e.g. `moodle.htwg-konstanz.de/moodle/`. These will be index files
 //! like index.html or index.txt.
 //!
+//! `handle()` returns a handle that switches the document root while the
+//! server runs, e.g. to activate a new release without restarting.
+//!
+//! `serve_static(...)` serves further directories below path prefixes next
+//! to the document root, e.g. `/downloads/` from a data directory.
+//!
+//! `enable_static_cache(...)` keeps small, frequently requested files in
+//! memory, so they are served without reading the disk.
+//!
+//! Symbolic links leading out of the document root are refused with `403`
+//! unless `follow_symlinks(true)` allows them, `..` in paths never is.
+//!
 //! ### User Defined Routing
 //! Users can specify callbacks which will be called when the application receives
 //! a request to the specified route (endpoint) and HTTP method. With `post(...)`
@@ -21,16 +33,245 @@
 //! })
 //! ```
 //!
+//! Routes can capture path segments with parameters (`/users/:id/`) and match
+//! whole subtrees with a trailing wildcard (`/files/*`). When several routes
+//! match, exact routes win over parameterized ones, routes with more literal
+//! segments win over those with fewer, wildcards come last and registration
+//! order breaks remaining ties.
+//!
+//! A request for `/counter` is answered by the route `/counter/` and the
+//! other way around, `set_trailing_slash_policy(...)` redirects to the
+//! registered path or insists on exact matches instead.
+//!
+//! Named routes, e.g. `get_named("user", "/users/:id/", ...)`, let
+//! `url_for("user", &[("id", "42")])` build their paths, so links and
+//! redirects follow the routes.
+//!
+//! A handler can keep its response open and push Server-Sent Events with
+//! `response.start_event_stream()`, e.g. for a live dashboard.
+//! Output whose length is not known up front goes out chunk by chunk after
+//! `response.start_chunked()`.
+//!
+//! `set_template_root(...)` lets handlers answer with
+//! `response.render("page.html", &context)`, which fills `{{ name }}` tags
+//! with HTML-escaped values and `{{{ name }}}` tags with raw ones.
+//!
+//! `before(...)` hooks annotate every request, e.g. with
+//! `request.set_extension(...)`, and `after(...)` hooks observe how it was
+//! answered and how long that took. `set_server_timing(true)` sends the time
+//! taken along as a `Server-Timing` header.
+//!
+//! `manage(...)` shares a value, e.g. a connection pool or a counter, with
+//! all handlers, which fetch it by its type with `request.state::<T>()`.
+//!
+//! ### Configuration
+//! `Server::from_config_file(...)` reads the settings from a TOML file, so
+//! they can change without recompiling. `CORRODED_*` environment variables
+//! and `PORT` override the file, setters called afterwards win over both.
+//! ```ignore
+//! let mut corroded = Server::from_config_file("corroded.toml")?;
+//! corroded.start()?;
+//! ```
+//!
+//! `Server::builder()` checks settings given in code the same way, so a
+//! missing document root or an impossible port fails before anything runs.
+//! ```ignore
+//! let mut corroded = Server::builder().document_root("public").port(8080).build()?;
+//! ```
+//!
+//! A `Logger` set to `LogFormat::Json` writes one JSON object per line,
+//! with fields such as the method, path, status and duration of each
+//! request kept apart for log aggregators.
+//!
+//! ### Testing
+//! `test_request(...)` runs a request through the routes of a server in
+//! memory, so handlers can be tested without ports, threads or timing.
+//! ```ignore
+//! let response = corroded.test_request("GET", "/home/").send();
+//! assert_eq!(response.status(), 200);
+//! ```
+//!
+//! Handlers written as functions can be called directly with a request from
+//! `Request::builder()` and a response from `Response::capture()`.
+//!
+//! ### Reverse Proxy
+//! `proxy(...)` forwards everything below a path prefix to another HTTP
+//! server, e.g. an API backend next to a static frontend.
+//! ```ignore
+//! corroded.proxy("/api/", "http://127.0.0.1:9000");
+//! ```
+//!
+//! ### HTTPS
+//! With the `tls` feature enabled, `set_tls(...)` makes the server speak HTTPS
+//! using a PEM encoded certificate and key. The `native-tls` feature uses the
+//! system's TLS library instead and reads a PKCS#12 archive.
+//!
+//! The `http2` feature additionally offers HTTP/2 to rustls clients via ALPN.
+//! Handlers are the same for both protocols, clients that do not ask for
+//! HTTP/2 keep using HTTP/1.1.
+//!
 //! ### Multithreading
 //! For seamless usage of functionality multithreading is indispensable.
 //! Corrodedweb itself is multithreaded.
+//!
+//! With the `tokio` feature enabled, accepting connections and reading and
+//! writing them happens on tokio instead, and connections are kept open
+//! between requests. Handlers still run on a small set of threads, so
+//! thousands of idle clients cost no threads.
+//!
+//! `set_max_connections(...)` caps how many clients are served at once and
+//! `set_rate_limit(...)` how many requests each address may make per
+//! minute, answering the rest with `429 Too Many Requests`.
+//!
+//! ### Shutdown
+//! `shutdown_handle()` returns a handle that makes `start_server` stop
+//! accepting connections, give running requests time to finish and return.
+//! With the `signals` feature, `shutdown_on_signals(true)` does this on
+//! SIGINT and SIGTERM, e.g. for `docker stop`, or Ctrl-C on Windows. If a
+//! listening socket breaks, the server stops by itself and the handle's
+//! `error()` tells why.
+//!
+//! ### Compression
+//! With the `gzip` feature, `enable_compression(true)` compresses text
+//! responses and directory listings of at least 1 KB for clients that
+//! accept gzip.
+//!
+//! ### Access Files
+//! With the `access-files` feature, `use_access_files(true)` lets a
+//! `.corroded` file in a served directory deny access, turn listings off,
+//! require authentication or add headers for that directory and the ones
+//! below it.
+//!
+//! With the `systemd` feature on Unix, a server started by systemd socket
+//! activation serves the sockets systemd passed to it, so it can be
+//! restarted without refusing connections.
 
+/// Negotiates media types with the Accept header
+mod accept;
+/// Keeps accept loops running through errors
+mod acceptloop;
+/// Reads the per-directory `.corroded` files
+#[cfg(feature = "access-files")]
+mod accessfile;
+/// Writes the access log in the Combined Log Format
+mod accesslog;
+/// Puts content hashes into the URLs of static files
+mod assets;
+/// Does connection I/O on tokio
+#[cfg(feature = "tokio")]
+mod asynclistener;
+/// Checks the credentials of HTTP Basic authentication
+mod basicauth;
+/// Reads request bodies while they arrive
+mod body;
+/// Reuses read buffers across requests
+mod bufferpool;
+/// Checks server settings before the server is created
+mod builder;
+/// Compresses response bodies with gzip
+#[cfg(feature = "gzip")]
+mod compression;
+/// Reads the server settings from a TOML file
+mod config;
+/// Parses cookies sent by clients and formats the ones sent to them
+mod cookie;
+/// Tells handlers that finished in time from those that did not
+mod deadline;
+/// Logs requests and responses in full while developing
+mod dump;
+/// Pushes Server-Sent Events to clients
+mod eventstream;
+/// Keeps the contents of static files in memory
+mod filecache;
+/// Stores request and response headers
+mod headers;
+/// Speaks HTTP/2 over TLS
+#[cfg(feature = "http2")]
+mod http2;
+/// Formats and parses the dates of HTTP headers
+mod httpdate;
+/// Resolves where the server listens
+mod listenaddr;
+/// Lists the files of directories without an index file
+mod listing;
 /// Logs everything
 mod logger;
+/// Parses `Content-Type` headers
+mod mediatype;
+/// Counts what happens while the server runs
+mod metrics;
+/// Knows the media types of static files
+mod mime;
+/// Reads `multipart/form-data` bodies and saves their files
+mod multipart;
+/// Catches panics of handlers and tells where they happened
+mod panics;
+/// Decodes percent-encoded paths and form data
+mod percent;
+/// Forwards requests to upstream servers
+mod proxy;
+/// Reads the client address a load balancer puts in front of a connection
+mod proxyprotocol;
+/// Parses Range headers and frames the ranges of static files
+mod range;
+/// Limits the requests per minute of each client
+mod ratelimit;
+/// Reads requests off connections within their deadlines
+mod reader;
+/// Matches request paths against registered routes
+mod router;
 /// The main module
 mod server;
+/// Keeps values of clients across their requests
+mod session;
+/// Stops the server and waits for running requests
+mod shutdown;
+/// Remembers which static files do not exist
+mod statcache;
+/// Abstracts over plain and encrypted connections
+mod stream;
+/// Takes over listening sockets from systemd
+#[cfg(all(unix, feature = "systemd"))]
+mod systemd;
+/// Fills values into HTML templates
+mod template;
+/// Runs requests through a server in memory, for tests
+mod testclient;
 /// Manages workers of the webserver
 mod threadpool;
+/// Measures the phases of handling a request
+mod timing;
+/// Serves HTTPS
+#[cfg(any(feature = "tls", feature = "native-tls"))]
+mod tls;
+/// Copies static files to the client
+mod transfer;
 
-pub use logger::Logger;
-pub use server::Server;
+pub use accept::{AcceptList, MediaRange};
+pub use body::BodyReader;
+pub use builder::ServerBuilder;
+pub use config::ConfigError;
+pub use cookie::{CookieOptions, SameSite};
+pub use eventstream::EventStream;
+pub use headers::Headers;
+pub use listenaddr::ListenAddr;
+pub use logger::{Level, LogFormat, Logger};
+pub use mediatype::MediaType;
+pub use metrics::Metrics;
+pub use multipart::{Multipart, SaveOptions, SavedFile, UploadedFile};
+pub use server::{
+    ConnectionLimitPolicy, CorsConfig, DuplicateRoutePolicy, FullDebug, HstsConfig, KeepAlive,
+    ListenerInfo, Next, NoMatch, ParamError, Protocol, ProxyConfig, Request, RequestSummary,
+    Response, ResponseAlreadyCompleted, ResponseMeta, RouteBuilder, RouteInfo, Server,
+    ServerHandle, StaleAssetPolicy, StartupInfo, StaticOptions, StaticPrecedence, Timeout,
+    TrailingSlashPolicy,
+};
+pub use session::Session;
+pub use shutdown::ShutdownHandle;
+pub use testclient::{CapturedResponse, RequestBuilder, TestRequest, TestResponse};
+pub use threadpool::ThreadPool;
+pub use timing::PhaseTimings;
+#[cfg(feature = "tls")]
+pub use tls::{ClientCertificate, TlsConfigBuilder};
+#[cfg(any(feature = "tls", feature = "native-tls"))]
+pub use tls::{TlsConfig, TlsError};
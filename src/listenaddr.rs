@@ -0,0 +1,78 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, ToSocketAddrs};
+
+/// Where `Server::start_server` listens
+///
+/// A bare port listens on the loopback interface, like it always did.
+/// Everything `std::net::ToSocketAddrs` accepts works as well, e.g.
+/// `"0.0.0.0:8080"`, `("::", 8080)` or `"localhost:8080"`. Names are
+/// resolved once when binding, the first address that can be bound is used.
+pub trait ListenAddr {
+    /// Returns the addresses to try binding, in order
+    fn listen_addrs(&self) -> io::Result<Vec<SocketAddr>>;
+}
+
+impl ListenAddr for u16 {
+    fn listen_addrs(&self) -> io::Result<Vec<SocketAddr>> {
+        Ok(vec![SocketAddr::from((Ipv4Addr::LOCALHOST, *self))])
+    }
+}
+
+/// Implements `ListenAddr` through `ToSocketAddrs`
+///
+/// A blanket implementation would conflict with the one for `u16`.
+macro_rules! listen_addr_via_to_socket_addrs {
+    ($($type:ty),*) => {
+        $(
+            impl ListenAddr for $type {
+                fn listen_addrs(&self) -> io::Result<Vec<SocketAddr>> {
+                    resolve(self)
+                }
+            }
+        )*
+    };
+}
+
+listen_addr_via_to_socket_addrs!(
+    &str,
+    String,
+    SocketAddr,
+    SocketAddrV4,
+    SocketAddrV6,
+    (&str, u16),
+    (String, u16),
+    (IpAddr, u16),
+    (Ipv4Addr, u16),
+    (Ipv6Addr, u16),
+    &[SocketAddr]
+);
+
+fn resolve<A: ToSocketAddrs + ?Sized>(addr: &A) -> io::Result<Vec<SocketAddr>> {
+    let addrs: Vec<SocketAddr> = addr.to_socket_addrs()?.collect();
+    if addrs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the address resolved to nothing",
+        ));
+    }
+    Ok(addrs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listen_addrs() {
+        let loopback: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+        assert_eq!(8080u16.listen_addrs().unwrap(), [loopback]);
+        assert_eq!("127.0.0.1:8080".listen_addrs().unwrap(), [loopback]);
+        assert_eq!(("127.0.0.1", 8080).listen_addrs().unwrap(), [loopback]);
+        assert_eq!(
+            (Ipv4Addr::UNSPECIFIED, 80).listen_addrs().unwrap(),
+            ["0.0.0.0:80".parse::<SocketAddr>().unwrap()]
+        );
+        assert!("127.0.0.1".listen_addrs().is_err());
+        assert!("127.0.0.1:70000".listen_addrs().is_err());
+    }
+}
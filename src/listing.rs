@@ -0,0 +1,197 @@
+#[cfg(feature = "access-files")]
+use crate::accessfile;
+use crate::httpdate;
+use crate::percent;
+use crate::server::escape_html;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// An entry of a listed directory
+struct Entry {
+    name: String,
+    is_dir: bool,
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+/// Returns the page listing `directory`, which was requested as `path`,
+/// see `Server::use_index_of`
+///
+/// Directories come first, then files, each sorted by name. Links are
+/// relative to `path`, so they work at any depth and below any prefix the
+/// directory is served at. Entries that cannot be read are left out, as
+/// are names starting with a dot unless `show_hidden`. Fails if the
+/// directory itself cannot be read.
+pub(crate) fn render(directory: &Path, path: &str, show_hidden: bool) -> io::Result<String> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(_) => continue,
+        };
+        // A name that is no UTF-8 could not be linked to
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        if !show_hidden && name.starts_with('.') {
+            continue;
+        }
+        #[cfg(feature = "access-files")]
+        {
+            if name == accessfile::ACCESS_FILE {
+                continue;
+            }
+        }
+        // Symbolic links are listed as what they point to, broken ones not
+        let metadata = match fs::metadata(entry.path()) {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+        entries.push(Entry {
+            name,
+            is_dir: metadata.is_dir(),
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+        });
+    }
+    entries.sort_by(|a, b| {
+        b.is_dir
+            .cmp(&a.is_dir)
+            .then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase()))
+            .then_with(|| a.name.cmp(&b.name))
+    });
+
+    // Without a trailing slash, relative links resolve against the parent
+    let (base, parent) = if path.ends_with('/') {
+        (String::new(), "../")
+    } else {
+        let last = path.rsplit('/').next().unwrap_or("");
+        (format!("{}/", percent::encode(last)), "./")
+    };
+    let title = escape_html(path);
+    let mut page = format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Index of {}</title></head>\n\
+         <body><h1>Index of {}</h1>\n<table>\n\
+         <tr><th>Name</th><th>Size</th><th>Modified</th></tr>\n",
+        title, title
+    );
+    if path != "/" {
+        page.push_str(&format!(
+            "<tr><td><a href=\"{}\">../</a></td><td>-</td><td>-</td></tr>\n",
+            parent
+        ));
+    }
+    for entry in &entries {
+        let slash = if entry.is_dir { "/" } else { "" };
+        let size = if entry.is_dir {
+            String::from("-")
+        } else {
+            format_size(entry.len)
+        };
+        let modified = entry
+            .modified
+            .map_or_else(|| String::from("-"), httpdate::format_http_date);
+        page.push_str(&format!(
+            "<tr><td><a href=\"{}{}{}\">{}{}</a></td><td>{}</td><td>{}</td></tr>\n",
+            base,
+            percent::encode(&entry.name),
+            slash,
+            escape_html(&entry.name),
+            slash,
+            size,
+            modified
+        ));
+    }
+    page.push_str("</table>\n<hr> powered by corrodedweb</body></html>\n");
+    Ok(page)
+}
+
+/// Returns `len` bytes in the largest binary unit that keeps it at least 1
+fn format_size(len: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if len < 1024 {
+        return format!("{} B", len);
+    }
+    let mut size = len as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(5 * 1024 * 1024), "5.0 MiB");
+        assert_eq!(format_size(u64::MAX), "16777216.0 TiB");
+    }
+
+    #[test]
+    fn test_render() {
+        let dir = std::env::temp_dir().join("corrodedweb_listing");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub/zeta")).unwrap();
+        fs::create_dir(dir.join("sub/Alpha")).unwrap();
+        fs::write(dir.join("sub/<script>.txt"), "x").unwrap();
+        fs::write(dir.join("sub/b c.bin"), vec![0; 2048]).unwrap();
+        fs::write(dir.join("sub/.hidden"), "").unwrap();
+        let modified = UNIX_EPOCH + Duration::from_secs(971_185_336);
+        for name in ["zeta", "Alpha", "<script>.txt", "b c.bin", ".hidden"] {
+            File::open(dir.join("sub").join(name))
+                .and_then(|file| file.set_modified(modified))
+                .unwrap();
+        }
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(dir.join("sub/missing"), dir.join("sub/broken")).unwrap();
+
+        let date = "Tue, 10 Oct 2000 13:42:16 GMT";
+        assert_eq!(
+            render(&dir.join("sub"), "/files/sub/", false).unwrap(),
+            format!(
+                "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Index of /files/sub/</title></head>\n\
+                 <body><h1>Index of /files/sub/</h1>\n<table>\n\
+                 <tr><th>Name</th><th>Size</th><th>Modified</th></tr>\n\
+                 <tr><td><a href=\"../\">../</a></td><td>-</td><td>-</td></tr>\n\
+                 <tr><td><a href=\"Alpha/\">Alpha/</a></td><td>-</td><td>{date}</td></tr>\n\
+                 <tr><td><a href=\"zeta/\">zeta/</a></td><td>-</td><td>{date}</td></tr>\n\
+                 <tr><td><a href=\"%3Cscript%3E.txt\">&lt;script&gt;.txt</a></td><td>1 B</td><td>{date}</td></tr>\n\
+                 <tr><td><a href=\"b%20c.bin\">b c.bin</a></td><td>2.0 KiB</td><td>{date}</td></tr>\n\
+                 </table>\n<hr> powered by corrodedweb</body></html>\n",
+                date = date
+            )
+        );
+
+        // Without a trailing slash the links name the directory
+        let page = render(&dir.join("sub"), "/sub", true).unwrap();
+        assert!(page.contains("<a href=\"./\">../</a>"), "{}", page);
+        assert!(
+            page.contains("<a href=\"sub/Alpha/\">Alpha/</a>"),
+            "{}",
+            page
+        );
+        assert!(
+            page.contains("<a href=\"sub/.hidden\">.hidden</a>"),
+            "{}",
+            page
+        );
+
+        let page = render(&dir, "/", false).unwrap();
+        assert!(!page.contains("../"), "{}", page);
+        assert!(page.contains("<a href=\"sub/\">sub/</a>"), "{}", page);
+
+        assert!(render(&dir.join("missing"), "/missing/", false).is_err());
+    }
+}
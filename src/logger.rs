@@ -1,15 +1,76 @@
+use std::fs;
 use std::fs::File;
 use std::fs::OpenOptions;
+use std::io;
 use std::io::prelude::*;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::time::SystemTime;
 
-use humantime;
+/// How severe a logged message is, see `Logger::set_level`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    Debug,
+    Info,
+    Warning,
+    Error,
+}
+
+impl Level {
+    fn label(self) -> &'static str {
+        match self {
+            Level::Debug => "DEBUG",
+            Level::Info => "INFO",
+            Level::Warning => "WARNING",
+            Level::Error => "ERROR",
+        }
+    }
+
+    /// The name in JSON lines
+    fn name(self) -> &'static str {
+        match self {
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Warning => "warning",
+            Level::Error => "error",
+        }
+    }
+}
+
+/// How a logger writes its lines, see `Logger::set_format`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LogFormat {
+    /// `LEVEL (timestamp): message key=value`, the default
+    Text,
+    /// A JSON object per line, like `{"ts":"2000-10-10T13:55:36Z",
+    /// "level":"info","msg":"message","fields":{"key":"value"}}`
+    Json,
+}
+
+/// Where the lines of a logger go
+enum Sink {
+    File(File),
+    Rotating(Rotating),
+    Writer(Box<dyn Write + Send>),
+}
+
+/// A log file that is moved aside once it grows too large, see
+/// `Logger::new_with_rotation`
+struct Rotating {
+    path: PathBuf,
+    file: File,
+    /// The size of `file`
+    len: u64,
+    max_bytes: u64,
+    keep_files: usize,
+}
 
 /// A logger instance is represented here
 pub struct Logger {
-    file: Arc<Mutex<File>>,
+    sink: Arc<Mutex<Sink>>,
+    level: Level,
+    format: LogFormat,
+    also_stderr: bool,
 }
 
 impl Logger {
@@ -18,7 +79,7 @@ impl Logger {
     /// # Arguments
     ///
     /// * `path` - A string slice that holds the absolute or relative
-    /// path to the log file
+    ///   path to the log file
     ///
     /// # Example
     ///
@@ -32,14 +93,124 @@ impl Logger {
 
         let file = OpenOptions::new()
             .create(true)
-            .write(true)
             .append(true)
             .open(path)
             .unwrap();
 
-        let file = Arc::new(Mutex::new(file));
+        Logger::with_sink(Sink::File(file))
+    }
+
+    /// Returns a Logger appending to the file at `path` until it would grow
+    /// beyond `max_bytes`
+    ///
+    /// The full file is then renamed to `path.1`, the one before to `path.2`
+    /// and so on up to `keep_files` old files, older ones are deleted, and
+    /// logging goes on in a new file at `path`. Clones of the logger follow
+    /// to the new file.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use corrodedweb::Logger;
+    /// // server.log and up to 5 old files of 10 MB each
+    /// let l = Logger::new_with_rotation("./server.log", 10 * 1024 * 1024, 5);
+    /// ```
+    pub fn new_with_rotation(path: &str, max_bytes: u64, keep_files: usize) -> Logger {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        let len = file.metadata().map_or(0, |metadata| metadata.len());
+
+        Logger::with_sink(Sink::Rotating(Rotating {
+            path: PathBuf::from(path),
+            file,
+            len,
+            max_bytes,
+            keep_files,
+        }))
+    }
+
+    /// Returns a Logger writing its lines to `writer`, e.g. a socket or a
+    /// buffer
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Logger;
+    /// let l = Logger::new_with_writer(Box::new(std::io::sink()));
+    /// ```
+    pub fn new_with_writer(writer: Box<dyn Write + Send>) -> Logger {
+        Logger::with_sink(Sink::Writer(writer))
+    }
+
+    /// Returns a Logger writing to standard output, for containers that
+    /// collect what their processes print
+    pub fn to_stdout() -> Logger {
+        Logger::new_with_writer(Box::new(io::stdout()))
+    }
+
+    /// Returns a Logger writing to standard error
+    pub fn to_stderr() -> Logger {
+        Logger::new_with_writer(Box::new(io::stderr()))
+    }
+
+    fn with_sink(sink: Sink) -> Logger {
+        Logger {
+            sink: Arc::new(Mutex::new(sink)),
+            level: Level::Debug,
+            format: LogFormat::Text,
+            also_stderr: false,
+        }
+    }
+
+    /// Leaves out messages less severe than `level`, by default everything
+    /// is logged
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::{Level, Logger, Server};
+    /// let mut l = Logger::to_stdout();
+    /// l.set_level(Level::Info);
+    /// let mut s = Server::new();
+    /// s.use_logger(l);
+    /// ```
+    pub fn set_level(&mut self, level: Level) {
+        self.level = level;
+    }
 
-        Logger { file }
+    /// Returns the least severe level that is logged
+    pub fn level(&self) -> Level {
+        self.level
+    }
+
+    /// Writes lines in `format`, e.g. JSON for a log aggregator, plain text
+    /// by default
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::{LogFormat, Logger, Server};
+    /// let mut l = Logger::to_stdout();
+    /// l.set_format(LogFormat::Json);
+    /// let mut s = Server::new();
+    /// s.use_logger(l);
+    /// ```
+    pub fn set_format(&mut self, format: LogFormat) {
+        self.format = format;
+    }
+
+    /// Returns how lines are written
+    pub fn format(&self) -> LogFormat {
+        self.format
+    }
+
+    /// Writes every line to standard error as well, e.g. to see them while
+    /// developing and keep them in a file
+    pub fn also_to_stderr(&mut self, also: bool) {
+        self.also_stderr = also;
     }
 
     pub fn debug(logger: &Option<Logger>, message: &str) {
@@ -60,12 +231,64 @@ impl Logger {
         }
     }
 
-    /// Creates a Debug information and passes it to write_to_file
+    pub fn error(logger: &Option<Logger>, message: &str) {
+        if let Some(logger) = logger {
+            logger._error(message);
+        }
+    }
+
+    /// Logs `message` with `fields` of context, e.g. `&[("path", path)]`,
+    /// which JSON lines keep apart and text lines append as `key=value`
+    pub fn debug_kv(logger: &Option<Logger>, message: &str, fields: &[(&str, &str)]) {
+        if let Some(logger) = logger {
+            logger.log_with(Level::Debug, message, fields);
+        }
+    }
+
+    /// Logs `message` with `fields` of context, see `debug_kv`
+    pub fn info_kv(logger: &Option<Logger>, message: &str, fields: &[(&str, &str)]) {
+        if let Some(logger) = logger {
+            logger.log_with(Level::Info, message, fields);
+        }
+    }
+
+    /// Logs `message` with `fields` of context, see `debug_kv`
+    pub fn warning_kv(logger: &Option<Logger>, message: &str, fields: &[(&str, &str)]) {
+        if let Some(logger) = logger {
+            logger.log_with(Level::Warning, message, fields);
+        }
+    }
+
+    /// Logs `message` with `fields` of context, see `debug_kv`
+    pub fn error_kv(logger: &Option<Logger>, message: &str, fields: &[(&str, &str)]) {
+        if let Some(logger) = logger {
+            logger.log_with(Level::Error, message, fields);
+        }
+    }
+
+    /// Makes sure everything logged so far reached the disk
+    pub fn flush(logger: &Option<Logger>) {
+        if let Some(logger) = logger {
+            if let Ok(mut sink) = logger.sink.lock() {
+                match &mut *sink {
+                    Sink::File(file) | Sink::Rotating(Rotating { file, .. }) => {
+                        let _ = file.flush();
+                        let _ = file.sync_data();
+                    }
+                    Sink::Writer(writer) => {
+                        let _ = writer.flush();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Creates a Debug information and logs it
     ///
     /// # Arguments
     ///
     /// * `message` - A reference to a string slice containing the
-    /// log message
+    ///   log message
     ///
     /// # Example
     ///
@@ -75,22 +298,15 @@ impl Logger {
     /// l.debug("This is the debug message");
     /// ```
     pub fn _debug(&self, message: &str) -> String {
-        // Todo pass optional vec with args for debug information
-        let mut msg = String::from("DEBUG (");
-        let sys_time = self.get_sys_time();
-        msg.push_str(&sys_time.as_str());
-        msg.push_str("): ");
-        msg.push_str(message);
-        self.write_to_file(&msg);
-        sys_time
+        self.log(Level::Debug, message)
     }
 
-    /// Creates a Info information and passes it to write_to_file
+    /// Creates a Info information and logs it
     ///
     /// # Arguments
     ///
     /// * `message` - A reference to a string slice containing the
-    /// log message
+    ///   log message
     ///
     /// # Example
     ///
@@ -100,21 +316,15 @@ impl Logger {
     /// l.info("This is the info message");
     /// ```
     pub fn _info(&self, message: &str) -> String {
-        let mut msg = String::from("INFO (");
-        let sys_time = self.get_sys_time();
-        msg.push_str(&sys_time.as_str());
-        msg.push_str("): ");
-        msg.push_str(message);
-        self.write_to_file(&msg);
-        sys_time
+        self.log(Level::Info, message)
     }
 
-    /// Creates a Warning information and passes it to write_to_file
+    /// Creates a Warning information and logs it
     ///
     /// # Arguments
     ///
     /// * `message` - A reference to a string slice containing the
-    /// log message
+    ///   log message
     ///
     /// # Example
     ///
@@ -124,21 +334,61 @@ impl Logger {
     /// l.warning("This is the warning message");
     /// ```
     pub fn _warning(&self, message: &str) -> String {
-        let mut msg = String::from("WARNING (");
+        self.log(Level::Warning, message)
+    }
+
+    /// Creates an Error information and logs it
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - A reference to a string slice containing the
+    ///   log message
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use corrodedweb::logger;
+    /// let l = logger::Logger::new("./test.log");
+    /// l.error("This is the error message");
+    /// ```
+    pub fn _error(&self, message: &str) -> String {
+        self.log(Level::Error, message)
+    }
+
+    /// Writes `message` if `level` is logged and returns the time it was
+    /// logged at
+    fn log(&self, level: Level, message: &str) -> String {
+        self.log_with(level, message, &[])
+    }
+
+    /// Writes `message` and `fields` if `level` is logged and returns the
+    /// time it was logged at
+    fn log_with(&self, level: Level, message: &str, fields: &[(&str, &str)]) -> String {
         let sys_time = self.get_sys_time();
-        msg.push_str(&sys_time.as_str());
-        msg.push_str("): ");
-        msg.push_str(message);
-        self.write_to_file(&msg);
+        if level >= self.level {
+            let line = match self.format {
+                LogFormat::Text => text_line(level, &sys_time, message, fields),
+                LogFormat::Json => json_line(level, &sys_time, message, fields),
+            };
+            self.write_line(&line);
+        }
         sys_time
     }
 
-    fn write_to_file(&self, _message: &str) {
-        if let Ok(mut file) = self.file.lock() {
-            if let Err(e) = writeln!(file, "{}", _message) {
-                eprintln!("Couldn't write to file: {}", e);
+    fn write_line(&self, line: &str) {
+        if let Ok(mut sink) = self.sink.lock() {
+            let written = match &mut *sink {
+                Sink::File(file) => writeln!(file, "{}", line),
+                Sink::Rotating(rotating) => rotating.write_line(line),
+                Sink::Writer(writer) => writeln!(writer, "{}", line),
+            };
+            if let Err(e) = written {
+                eprintln!("Couldn't write to log: {}", e);
             }
         }
+        if self.also_stderr {
+            eprintln!("{}", line);
+        }
     }
 
     fn get_sys_time(&self) -> String {
@@ -148,10 +398,115 @@ impl Logger {
     }
 }
 
+fn text_line(level: Level, time: &str, message: &str, fields: &[(&str, &str)]) -> String {
+    let mut line = format!("{} ({}): {}", level.label(), time, message);
+    for (key, value) in fields {
+        // Quoted where a space would make it look like several fields
+        if value.is_empty() || value.contains(char::is_whitespace) || value.contains('"') {
+            line.push_str(&format!(" {}={:?}", key, value));
+        } else {
+            line.push_str(&format!(" {}={}", key, value));
+        }
+    }
+    line
+}
+
+fn json_line(level: Level, time: &str, message: &str, fields: &[(&str, &str)]) -> String {
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|(key, value)| format!("{}:{}", json_string(key), json_string(value)))
+        .collect();
+    format!(
+        "{{\"ts\":{},\"level\":\"{}\",\"msg\":{},\"fields\":{{{}}}}}",
+        json_string(time),
+        level.name(),
+        json_string(message),
+        fields.join(",")
+    )
+}
+
+/// Returns `value` as a JSON string, quoted and escaped
+fn json_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c < ' ' => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+impl Rotating {
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.len > 0 && self.len + len > self.max_bytes {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.len += len;
+        Ok(())
+    }
+
+    /// Shifts the old files up by one and starts a new file
+    fn rotate(&mut self) -> io::Result<()> {
+        let _ = self.file.flush();
+        if self.keep_files == 0 {
+            remove_if_exists(&self.path)?;
+        } else {
+            remove_if_exists(&numbered(&self.path, self.keep_files))?;
+            for number in (1..self.keep_files).rev() {
+                rename_if_exists(
+                    &numbered(&self.path, number),
+                    &numbered(&self.path, number + 1),
+                )?;
+            }
+            fs::rename(&self.path, numbered(&self.path, 1))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.len = 0;
+        Ok(())
+    }
+}
+
+/// Returns `path` with `.number` appended
+fn numbered(path: &Path, number: usize) -> PathBuf {
+    let mut numbered = path.as_os_str().to_owned();
+    numbered.push(format!(".{}", number));
+    PathBuf::from(numbered)
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+fn rename_if_exists(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
 impl Clone for Logger {
     fn clone(&self) -> Self {
         Logger {
-            file: self.file.clone(),
+            sink: self.sink.clone(),
+            level: self.level,
+            format: self.format,
+            also_stderr: self.also_stderr,
         }
     }
 }
@@ -163,7 +518,118 @@ mod tests {
     use std::io::BufReader;
     use std::path::Path;
 
+    /// A writer whose lines the test reads back
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_levels() {
+        let captured = Captured::default();
+        let mut logger = Logger::new_with_writer(Box::new(captured.clone()));
+        assert_eq!(logger.level(), Level::Debug);
+        logger.set_level(Level::Warning);
+        let logger = Some(logger);
+        Logger::debug(&logger, "left out");
+        Logger::info(&logger, "left out");
+        Logger::warning(&logger, "disk almost full");
+        Logger::error(&logger, "disk full");
+        Logger::flush(&logger);
+
+        let written = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines.len(), 2, "{}", written);
+        assert!(lines[0].starts_with("WARNING ("), "{}", lines[0]);
+        assert!(lines[0].ends_with("): disk almost full"), "{}", lines[0]);
+        assert!(lines[1].starts_with("ERROR ("), "{}", lines[1]);
+        assert!(lines[1].ends_with("): disk full"), "{}", lines[1]);
+    }
+
     #[test]
+    fn test_formats() {
+        let captured = Captured::default();
+        let mut logger = Logger::new_with_writer(Box::new(captured.clone()));
+        assert_eq!(logger.format(), LogFormat::Text);
+        let fields = [("path", "/a b/\"x\""), ("status", "404")];
+        logger._info("plain");
+        Logger::warning_kv(&Some(logger.clone()), "Not found", &fields);
+        logger.set_format(LogFormat::Json);
+        let logger = Some(logger);
+        Logger::info_kv(&logger, "Not found\n\u{1}", &fields);
+        Logger::error(&logger, "no fields");
+
+        let written = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines.len(), 4, "{}", written);
+        assert!(lines[0].ends_with("): plain"), "{}", lines[0]);
+        assert!(
+            lines[1].ends_with("): Not found path=\"/a b/\\\"x\\\"\" status=404"),
+            "{}",
+            lines[1]
+        );
+
+        let entry: serde_json::Value = serde_json::from_str(lines[2]).unwrap();
+        let ts = entry["ts"].as_str().unwrap();
+        assert!(humantime::parse_rfc3339(ts).is_ok(), "{}", ts);
+        assert_eq!(entry["level"], "info");
+        assert_eq!(entry["msg"], "Not found\n\u{1}");
+        assert_eq!(entry["fields"]["path"], "/a b/\"x\"");
+        assert_eq!(entry["fields"]["status"], "404");
+        assert_eq!(entry.as_object().unwrap().len(), 4);
+
+        let entry: serde_json::Value = serde_json::from_str(lines[3]).unwrap();
+        assert_eq!(entry["level"], "error");
+        assert_eq!(entry["msg"], "no fields");
+        assert_eq!(entry["fields"], serde_json::json!({}));
+    }
+
+    #[test]
+    fn test_rotation() {
+        let dir = std::env::temp_dir().join("corrodedweb_log_rotation");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("server.log");
+        let file_name = |number: usize| match number {
+            0 => path.clone(),
+            number => numbered(&path, number),
+        };
+
+        // Every line is 49 bytes, two fit into a file
+        let logger = Logger::new_with_rotation(path.to_str().unwrap(), 120, 2);
+        let clone = logger.clone();
+        for number in 1..=7 {
+            let logger = if number % 2 == 0 { &logger } else { &clone };
+            logger._info(&format!("line {} of the tests", number));
+        }
+
+        let lines = |number| {
+            let content = fs::read_to_string(file_name(number)).unwrap();
+            content
+                .lines()
+                .map(|line| {
+                    assert_eq!(line.len(), 48, "{}", line);
+                    line.split("): ").nth(1).unwrap().to_owned()
+                })
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(lines(0), ["line 7 of the tests"]);
+        assert_eq!(lines(1), ["line 5 of the tests", "line 6 of the tests"]);
+        assert_eq!(lines(2), ["line 3 of the tests", "line 4 of the tests"]);
+        assert!(!file_name(3).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[allow(clippy::bool_assert_comparison, dropping_references)]
     fn test_logger() {
         // test creation of logger
         let logger = Logger::new("./test.log");
@@ -283,5 +749,44 @@ mod tests {
             }
             None => panic!("Something went wrong"),
         };
+
+        drop(message);
+        drop(compare_msg);
+        drop(sys_time);
+
+        let message = "This Test Message";
+        let mut sys_time = String::new();
+
+        // Test if file is created when non existant
+        if !Path::new("./test.log").exists() {
+            assert_eq!(Path::new("./test.log").exists(), false);
+            sys_time.push_str(logger._error(message).as_str());
+            assert_eq!(Path::new("./test.log").exists(), true);
+        } else {
+            sys_time.push_str(logger._error(message).as_str());
+            assert_eq!(Path::new("./test.log").exists(), true);
+        }
+
+        // Create compare_msg to compare to line written in test.log
+        let mut compare_msg = String::from("ERROR (");
+        compare_msg.push_str(sys_time.as_str());
+        compare_msg.push_str("): ");
+        compare_msg.push_str(message);
+
+        // Test if last line equals to message written to test.log
+        let file = File::open("./test.log").expect("Opening file");
+        let content = BufReader::new(&file);
+        let lines = content.lines();
+        match lines.last() {
+            Some(last_line) => {
+                match last_line {
+                    Ok(extracted_message) => {
+                        assert_eq!(compare_msg, extracted_message);
+                    }
+                    Err(_) => panic!("Something went wrong"),
+                };
+            }
+            None => panic!("Something went wrong"),
+        };
     }
 }
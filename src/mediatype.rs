@@ -0,0 +1,184 @@
+/// A parsed `Content-Type` like `multipart/form-data; boundary="a b"`,
+/// returned by `Request::content_type`
+///
+/// Type, subtype and parameter names are kept in lowercase, parameter
+/// values as sent without their quotes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaType {
+    main_type: String,
+    subtype: String,
+    parameters: Vec<(String, String)>,
+}
+
+impl MediaType {
+    /// Parses a header value like `text/html; charset=utf-8`, `None` if it
+    /// is not of the form `type/subtype`
+    pub fn parse(value: &str) -> Option<Self> {
+        let (media_type, parameters) = value.split_once(';').unwrap_or((value, ""));
+        let (main_type, subtype) = media_type.trim().split_once('/')?;
+        let (main_type, subtype) = (main_type.trim(), subtype.trim());
+        let token = |part: &str| {
+            !part.is_empty() && !part.contains(|c: char| c.is_whitespace() || "/\"".contains(c))
+        };
+        if !token(main_type) || !token(subtype) {
+            return None;
+        }
+        Some(MediaType {
+            main_type: main_type.to_ascii_lowercase(),
+            subtype: subtype.to_ascii_lowercase(),
+            parameters: parse_parameters(parameters),
+        })
+    }
+
+    /// Returns the type without parameters, e.g. `text/html`
+    pub fn media_type(&self) -> String {
+        format!("{}/{}", self.main_type, self.subtype)
+    }
+
+    /// Returns the part before the slash, e.g. `text`
+    pub fn main_type(&self) -> &str {
+        &self.main_type
+    }
+
+    /// Returns the part after the slash, e.g. `html`
+    pub fn subtype(&self) -> &str {
+        &self.subtype
+    }
+
+    /// Returns whether this is `media_type`, ignoring case and parameters
+    ///
+    /// A subtype of `*` matches any, like `text/*`.
+    pub fn is(&self, media_type: &str) -> bool {
+        match media_type.split_once('/') {
+            Some((main_type, subtype)) => {
+                main_type.trim().eq_ignore_ascii_case(&self.main_type)
+                    && (subtype.trim() == "*" || subtype.trim().eq_ignore_ascii_case(&self.subtype))
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the parameters in the order they were sent
+    pub fn parameters(&self) -> &[(String, String)] {
+        &self.parameters
+    }
+
+    /// Returns the value of the parameter `name`, the first one if it was
+    /// sent several times
+    pub fn parameter(&self, name: &str) -> Option<&str> {
+        self.parameters
+            .iter()
+            .find(|(known, _)| known.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the `charset` parameter
+    pub fn charset(&self) -> Option<&str> {
+        self.parameter("charset")
+    }
+
+    /// Returns the `boundary` parameter of multipart types
+    pub fn boundary(&self) -> Option<&str> {
+        self.parameter("boundary")
+    }
+}
+
+/// Parses parameters like `charset=utf-8; boundary="a;b"`, with names in
+/// lowercase and values unquoted
+///
+/// Also used for other headers with parameters, like the
+/// `Content-Disposition` of multipart bodies. Parameters without a value are
+/// skipped.
+pub(crate) fn parse_parameters(mut rest: &str) -> Vec<(String, String)> {
+    let mut parameters = Vec::new();
+    loop {
+        rest = rest.trim_start_matches(|c: char| c == ';' || c.is_whitespace());
+        if rest.is_empty() {
+            return parameters;
+        }
+        let name_end = rest.find(['=', ';']).unwrap_or(rest.len());
+        let name = rest[..name_end].trim().to_ascii_lowercase();
+        rest = &rest[name_end..];
+        let after = match rest.strip_prefix('=') {
+            Some(after) => after.trim_start(),
+            None => continue,
+        };
+        let (value, next) = parse_value(after);
+        rest = next;
+        if !name.is_empty() {
+            parameters.push((name, value));
+        }
+    }
+}
+
+/// Parses a token or quoted string, returning it and what follows it from
+/// the next `;` on
+fn parse_value(value: &str) -> (String, &str) {
+    let quoted = match value.strip_prefix('"') {
+        Some(quoted) => quoted,
+        None => {
+            let end = value.find(';').unwrap_or(value.len());
+            return (String::from(value[..end].trim_end()), &value[end..]);
+        }
+    };
+    let mut parsed = String::new();
+    let mut chars = quoted.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\\' => parsed.extend(chars.next().map(|(_, c)| c)),
+            '"' => {
+                let after = &quoted[index + 1..];
+                return (parsed, after.find(';').map_or("", |end| &after[end..]));
+            }
+            c => parsed.push(c),
+        }
+    }
+    (parsed, "")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let media_type =
+            MediaType::parse("Multipart/Form-Data; Boundary=\"a;b \\\"c\\\"\" ; charset=UTF-8")
+                .unwrap();
+        assert_eq!(media_type.media_type(), "multipart/form-data");
+        assert_eq!(media_type.main_type(), "multipart");
+        assert_eq!(media_type.subtype(), "form-data");
+        assert_eq!(media_type.boundary(), Some("a;b \"c\""));
+        assert_eq!(media_type.charset(), Some("UTF-8"));
+        assert!(media_type.is("multipart/form-data"));
+        assert!(media_type.is("MULTIPART/*"));
+        assert!(!media_type.is("multipart/mixed"));
+        assert!(!media_type.is("multipart"));
+
+        let media_type = MediaType::parse("text/plain").unwrap();
+        assert_eq!(media_type.parameters(), &[]);
+        assert_eq!(media_type.charset(), None);
+
+        for invalid in ["", "text", "/plain", "text/", "text/pl ain", "a/b/c"] {
+            assert_eq!(MediaType::parse(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_parse_parameters() {
+        let pair = |name: &str, value: &str| (String::from(name), String::from(value));
+        assert_eq!(
+            parse_parameters(" name=\"photo\"; filename=\"a;b.jpg\" x; lone; ;size = 10"),
+            vec![
+                pair("name", "photo"),
+                pair("filename", "a;b.jpg"),
+                pair("size", "10")
+            ]
+        );
+        assert_eq!(
+            parse_parameters("a=\"unterminated; b=1"),
+            vec![pair("a", "unterminated; b=1")]
+        );
+        assert_eq!(parse_parameters("=x; a=\"\""), vec![pair("a", "")]);
+    }
+}
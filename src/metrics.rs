@@ -0,0 +1,105 @@
+use crate::threadpool::JobCounts;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Counts events of a running server, obtained with `Server::metrics`
+///
+/// The counters can be read from any thread while the server runs, e.g. to
+/// export them to a monitoring system. Clones share the counters.
+///
+/// # Example
+///
+/// ```
+/// use corrodedweb::Server;
+/// let s = Server::new();
+/// let metrics = s.metrics();
+/// // s.start_server(7878).unwrap();
+/// assert_eq!(metrics.request_timeouts(), 0);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    counters: Arc<Counters>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    request_timeouts: AtomicU64,
+    service_unavailable: AtomicU64,
+    handler_timeouts: AtomicU64,
+    zombie_handlers: AtomicU64,
+    /// Kept up to date by the worker threads of the blocking server
+    jobs: Arc<JobCounts>,
+}
+
+impl Metrics {
+    /// Returns how many clients were answered with `408 Request Timeout`
+    /// because they sent their request too slowly
+    pub fn request_timeouts(&self) -> u64 {
+        self.counters.request_timeouts.load(Ordering::Relaxed)
+    }
+
+    /// Returns how many clients were turned away with `503 Service
+    /// Unavailable`, beyond the connection limit or during a shutdown
+    pub fn service_unavailable(&self) -> u64 {
+        self.counters.service_unavailable.load(Ordering::Relaxed)
+    }
+
+    /// Returns how many requests were answered with `504 Gateway Timeout`
+    /// because their handler took longer than its timeout
+    pub fn handler_timeouts(&self) -> u64 {
+        self.counters.handler_timeouts.load(Ordering::Relaxed)
+    }
+
+    /// Returns how many handlers that ran out of time are still running,
+    /// each of them holding a thread until it returns
+    pub fn zombie_handlers(&self) -> u64 {
+        self.counters.zombie_handlers.load(Ordering::Relaxed)
+    }
+
+    /// Returns how many worker threads of the blocking server are busy with
+    /// a connection, see `Server::set_workers`
+    pub fn busy_workers(&self) -> usize {
+        self.counters.jobs.active()
+    }
+
+    /// Returns how many accepted connections wait for a worker thread of the
+    /// blocking server, see `Server::set_max_queued_connections`
+    pub fn queued_connections(&self) -> usize {
+        self.counters.jobs.queued()
+    }
+
+    /// Returns the counts the worker threads keep up to date
+    #[cfg(not(feature = "tokio"))]
+    pub(crate) fn jobs(&self) -> Arc<JobCounts> {
+        self.counters.jobs.clone()
+    }
+
+    pub(crate) fn count_request_timeout(&self) {
+        self.counters
+            .request_timeouts
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn count_service_unavailable(&self) {
+        self.counters
+            .service_unavailable
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a handler that ran out of time and keeps running as a zombie
+    pub(crate) fn count_handler_timeout(&self) {
+        self.counters
+            .handler_timeouts
+            .fetch_add(1, Ordering::Relaxed);
+        self.counters
+            .zombie_handlers
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a zombie handler that finally returned
+    pub(crate) fn count_zombie_returned(&self) {
+        self.counters
+            .zombie_handlers
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+}
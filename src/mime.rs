@@ -0,0 +1,130 @@
+use std::path::Path;
+
+/// The media type of static files whose extension is not known
+pub(crate) const UNKNOWN: &str = "application/octet-stream";
+
+/// Media types of static files by extension
+const TYPES: &[(&str, &str)] = &[
+    ("css", "text/css"),
+    ("csv", "text/csv"),
+    ("gif", "image/gif"),
+    ("htm", "text/html"),
+    ("html", "text/html"),
+    ("ico", "image/x-icon"),
+    ("jpeg", "image/jpeg"),
+    ("jpg", "image/jpeg"),
+    ("js", "text/javascript"),
+    ("json", "application/json"),
+    ("md", "text/markdown"),
+    ("mjs", "text/javascript"),
+    ("mp4", "video/mp4"),
+    ("pdf", "application/pdf"),
+    ("png", "image/png"),
+    ("svg", "image/svg+xml"),
+    ("txt", "text/plain"),
+    ("wasm", "application/wasm"),
+    ("webp", "image/webp"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("xml", "application/xml"),
+    ("zip", "application/zip"),
+];
+
+/// Returns the media type of a static file from its extension
+pub(crate) fn from_path(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?;
+    TYPES
+        .iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(extension))
+        .map(|(_, media_type)| *media_type)
+}
+
+/// Returns whether the content of `media_type` is text that a charset
+/// applies to
+pub(crate) fn is_text(media_type: &str) -> bool {
+    let essence = media_type.split(';').next().unwrap_or("").trim();
+    let lower = essence.to_ascii_lowercase();
+    lower.starts_with("text/")
+        || lower == "application/json"
+        || lower == "application/javascript"
+        || lower == "application/xml"
+        || lower.ends_with("+json")
+        || lower.ends_with("+xml")
+}
+
+/// Appends `charset` to a text media type that declares none
+pub(crate) fn with_charset(media_type: &str, charset: Option<&str>) -> String {
+    let declared = media_type.split(';').skip(1).any(|parameter| {
+        parameter
+            .trim()
+            .to_ascii_lowercase()
+            .starts_with("charset=")
+    });
+    match charset {
+        Some(charset) if is_text(media_type) && !declared => {
+            format!("{}; charset={}", media_type, charset)
+        }
+        _ => String::from(media_type),
+    }
+}
+
+/// Returns whether the start of a file can be declared as UTF-8
+///
+/// A character cut off at the end of `prefix` is fine, anything else that
+/// is not UTF-8, including the byte order marks of UTF-16, is not.
+pub(crate) fn looks_like_utf8(prefix: &[u8]) -> bool {
+    if prefix.starts_with(&[0xFF, 0xFE]) || prefix.starts_with(&[0xFE, 0xFF]) {
+        return false;
+    }
+    match std::str::from_utf8(prefix) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_path() {
+        assert_eq!(from_path(Path::new("a/index.html")), Some("text/html"));
+        assert_eq!(from_path(Path::new("LOGO.PNG")), Some("image/png"));
+        assert_eq!(from_path(Path::new("Makefile")), None);
+        assert_eq!(from_path(Path::new("archive.unknown")), None);
+    }
+
+    #[test]
+    fn test_with_charset() {
+        let utf8 = Some("utf-8");
+        assert_eq!(
+            with_charset("text/plain", utf8),
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(
+            with_charset("application/json", utf8),
+            "application/json; charset=utf-8"
+        );
+        assert_eq!(
+            with_charset("application/ld+json", utf8),
+            "application/ld+json; charset=utf-8"
+        );
+        assert_eq!(
+            with_charset("text/html; Charset=ISO-8859-1", utf8),
+            "text/html; Charset=ISO-8859-1"
+        );
+        assert_eq!(with_charset("image/png", utf8), "image/png");
+        assert_eq!(with_charset("text/plain", None), "text/plain");
+    }
+
+    #[test]
+    fn test_looks_like_utf8() {
+        assert!(looks_like_utf8(b"plain ascii"));
+        assert!(looks_like_utf8("Grüße".as_bytes()));
+        assert!(looks_like_utf8(b"\xEF\xBB\xBFwith BOM"));
+        // "ü" cut in half at the end of the prefix
+        assert!(looks_like_utf8(b"Gr\xC3"));
+        assert!(!looks_like_utf8(b"Gr\xFC\xDFe"));
+        assert!(!looks_like_utf8(b"\xFF\xFEa\x00"));
+    }
+}
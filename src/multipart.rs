@@ -0,0 +1,489 @@
+use crate::body::BodyReader;
+use crate::mediatype::{self, MediaType};
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How many body bytes are read at once
+const READ_SIZE: usize = 8 * 1024;
+
+/// How long the headers of a part may be
+const MAX_PART_HEAD: usize = 16 * 1024;
+
+/// How long the extension kept from a client's filename may be
+const MAX_EXTENSION: usize = 16;
+
+/// Tells apart files saved within the same nanosecond
+static NEXT_UPLOAD: AtomicU64 = AtomicU64::new(0);
+
+/// Reads the parts of a `multipart/form-data` body one after the other as
+/// they arrive, see `Request::multipart`
+pub struct Multipart {
+    body: BodyReader,
+    /// A line break, `--` and the boundary, which ends every part
+    delimiter: Vec<u8>,
+    /// Read from the body but not handed out yet
+    buffer: Vec<u8>,
+    /// Set while the data of a part is read
+    in_part: bool,
+    /// Set once the closing delimiter was read
+    finished: bool,
+}
+
+impl Multipart {
+    /// Returns the parts of `body`, which are separated by `boundary`
+    pub(crate) fn new(body: BodyReader, boundary: &str) -> Self {
+        Multipart {
+            body,
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            // The first delimiter needs no line break before it
+            buffer: b"\r\n".to_vec(),
+            in_part: true,
+            finished: false,
+        }
+    }
+
+    /// Returns the next part, `None` after the last one
+    ///
+    /// Whatever was left unread of the previous part is skipped.
+    pub fn next_part(&mut self) -> io::Result<Option<UploadedFile<'_>>> {
+        let mut skipped = [0; READ_SIZE];
+        while self.read_data(&mut skipped)? > 0 {}
+        if self.finished {
+            return Ok(None);
+        }
+        // The delimiter is followed by `--` after the last part, by a line
+        // break otherwise
+        self.fill_to(2)?;
+        if self.buffer.starts_with(b"--") {
+            self.finished = true;
+            return Ok(None);
+        }
+        let head_end = loop {
+            if let Some(end) = find(&self.buffer, b"\r\n\r\n") {
+                break end;
+            }
+            if self.buffer.len() > MAX_PART_HEAD {
+                return Err(invalid_data("part headers too long"));
+            }
+            let len = self.buffer.len();
+            self.fill_to(len + 1)?;
+        };
+        let head = String::from_utf8_lossy(&self.buffer[..head_end]).into_owned();
+        self.buffer.drain(..head_end + 4);
+        self.in_part = true;
+
+        let mut file = UploadedFile {
+            multipart: self,
+            name: String::new(),
+            filename: None,
+            content_type: None,
+        };
+        for line in head.split("\r\n").skip(1) {
+            let (header, value) = match line.split_once(':') {
+                Some(header) => header,
+                None => continue,
+            };
+            if header.trim().eq_ignore_ascii_case("Content-Disposition") {
+                let parameters = value.split_once(';').map_or("", |(_, rest)| rest);
+                for (name, value) in mediatype::parse_parameters(parameters) {
+                    match name.as_str() {
+                        "name" => file.name = value,
+                        "filename" => file.filename = Some(value),
+                        _ => {}
+                    }
+                }
+            } else if header.trim().eq_ignore_ascii_case("Content-Type") {
+                file.content_type = Some(String::from(value.trim()));
+            }
+        }
+        Ok(Some(file))
+    }
+
+    /// Reads data of the current part, 0 once it ended
+    fn read_data(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.in_part || buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            // Bytes that cannot be the start of a delimiter are data
+            let data = match find(&self.buffer, &self.delimiter) {
+                Some(0) => {
+                    self.buffer.drain(..self.delimiter.len());
+                    self.in_part = false;
+                    return Ok(0);
+                }
+                Some(start) => start,
+                None => (self.buffer.len() + 1).saturating_sub(self.delimiter.len()),
+            };
+            if data > 0 {
+                let len = data.min(buf.len());
+                buf[..len].copy_from_slice(&self.buffer[..len]);
+                self.buffer.drain(..len);
+                return Ok(len);
+            }
+            let len = self.buffer.len();
+            self.fill_to(len + 1)?;
+        }
+    }
+
+    /// Reads until at least `len` bytes are buffered
+    fn fill_to(&mut self, len: usize) -> io::Result<()> {
+        while self.buffer.len() < len {
+            let mut piece = [0; READ_SIZE];
+            let read = self.body.read(&mut piece)?;
+            if read == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "the body ended before its closing boundary",
+                ));
+            }
+            self.buffer.extend_from_slice(&piece[..read]);
+        }
+        Ok(())
+    }
+}
+
+/// A part of a `multipart/form-data` body, usually an uploaded file, whose
+/// data is read from the body as it arrives
+///
+/// The data can be read through `io::Read` or saved with `save_to`.
+pub struct UploadedFile<'a> {
+    multipart: &'a mut Multipart,
+    name: String,
+    filename: Option<String>,
+    content_type: Option<String>,
+}
+
+/// How `UploadedFile::save_with` and `Request::save_files_with` store files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SaveOptions {
+    /// How large a single file may get, larger ones fail to save
+    pub max_size: u64,
+    /// Whether each file is synced to the disk before it counts as saved
+    pub sync: bool,
+}
+
+impl Default for SaveOptions {
+    /// At most 100 MiB per file, not synced
+    fn default() -> Self {
+        SaveOptions {
+            max_size: 100 * 1024 * 1024,
+            sync: false,
+        }
+    }
+}
+
+/// A file saved by `Request::save_files`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SavedFile {
+    /// The name of the form field
+    pub name: String,
+    /// The filename the client sent, not to be used as a path
+    pub original_filename: Option<String>,
+    pub content_type: Option<String>,
+    /// Where the file was saved, under a name of the server's choosing
+    pub path: PathBuf,
+    pub len: u64,
+}
+
+impl UploadedFile<'_> {
+    /// Returns the name of the form field
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the filename the client sent, `None` for plain form fields
+    ///
+    /// The name is not checked in any way and may contain a path like
+    /// `../../evil.sh`, `save_to` never uses it for one.
+    pub fn original_filename(&self) -> Option<&str> {
+        self.filename.as_deref()
+    }
+
+    /// Returns the `Content-Type` of the part, if the client sent one
+    pub fn content_type(&self) -> Option<&str> {
+        self.content_type.as_deref()
+    }
+
+    /// Saves the data to a new file in `dir` with the default
+    /// `SaveOptions` and returns its path
+    ///
+    /// See `save_with`.
+    pub fn save_to<P: AsRef<Path>>(&mut self, dir: P) -> io::Result<PathBuf> {
+        self.save_with(dir, &SaveOptions::default())
+            .map(|(path, _)| path)
+    }
+
+    /// Saves the data to a new file in `dir`, returning its path and length
+    ///
+    /// The file gets a unique name that keeps only a short alphanumeric
+    /// extension of the client's filename, so it never ends up outside of
+    /// `dir` or replaces another file. The data is streamed to the file as
+    /// it arrives. If it is larger than `options.max_size` or cannot be read
+    /// or written, the partial file is removed again.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::{SaveOptions, Server};
+    /// let mut s = Server::new();
+    /// s.post("/avatar/", |mut request, mut response| {
+    ///     let saved = request.multipart().and_then(|mut multipart| {
+    ///         let options = SaveOptions {
+    ///             max_size: 1024 * 1024,
+    ///             ..SaveOptions::default()
+    ///         };
+    ///         match multipart.next_part()? {
+    ///             Some(mut file) => file.save_with("/tmp", &options).map(Some),
+    ///             None => Ok(None),
+    ///         }
+    ///     });
+    ///     match saved {
+    ///         Ok(Some((path, len))) => {
+    ///             let _ = response.write(&format!("{} bytes in {}", len, path.display()));
+    ///         }
+    ///         Ok(None) => {
+    ///             let _ = response.set_status_code(400);
+    ///         }
+    ///         Err(e) => {
+    ///             let _ = response.set_status_code(400);
+    ///             let _ = response.write(&e.to_string());
+    ///         }
+    ///     }
+    /// });
+    /// ```
+    pub fn save_with<P: AsRef<Path>>(
+        &mut self,
+        dir: P,
+        options: &SaveOptions,
+    ) -> io::Result<(PathBuf, u64)> {
+        let extension = self.filename.as_deref().and_then(extension);
+        let (path, file) = create_unique(dir.as_ref(), extension)?;
+        match self.copy_to(file, options) {
+            Ok(len) => Ok((path, len)),
+            Err(e) => {
+                let _ = fs::remove_file(&path);
+                Err(e)
+            }
+        }
+    }
+
+    fn copy_to(&mut self, mut file: File, options: &SaveOptions) -> io::Result<u64> {
+        let mut piece = [0; READ_SIZE];
+        let mut len = 0;
+        loop {
+            let read = self.read(&mut piece)?;
+            if read == 0 {
+                break;
+            }
+            len += read as u64;
+            if len > options.max_size {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("the file is larger than {} bytes", options.max_size),
+                ));
+            }
+            file.write_all(&piece[..read])?;
+        }
+        if options.sync {
+            file.sync_all()?;
+        }
+        Ok(len)
+    }
+}
+
+impl Read for UploadedFile<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.multipart.read_data(buf)
+    }
+}
+
+/// Saves every part with a filename to `dir`, removing all of them again if
+/// one fails, see `Request::save_files`
+pub(crate) fn save_files(
+    multipart: &mut Multipart,
+    dir: &Path,
+    options: &SaveOptions,
+) -> io::Result<Vec<SavedFile>> {
+    let mut saved: Vec<SavedFile> = Vec::new();
+    let result = (|| {
+        while let Some(mut file) = multipart.next_part()? {
+            if file.filename.is_none() {
+                continue;
+            }
+            let (path, len) = file.save_with(dir, options)?;
+            saved.push(SavedFile {
+                name: file.name.clone(),
+                original_filename: file.filename.clone(),
+                content_type: file.content_type.clone(),
+                path,
+                len,
+            });
+        }
+        Ok(())
+    })();
+    if let Err(e) = result {
+        for file in &saved {
+            let _ = fs::remove_file(&file.path);
+        }
+        return Err(e);
+    }
+    Ok(saved)
+}
+
+/// Returns the boundary of a `multipart/form-data` content type
+pub(crate) fn boundary(content_type: &MediaType) -> Option<&str> {
+    if !content_type.is("multipart/form-data") {
+        return None;
+    }
+    content_type
+        .boundary()
+        .filter(|boundary| !boundary.is_empty())
+}
+
+/// Returns the extension of a client's filename if it is short and
+/// alphanumeric, so it is safe to keep
+fn extension(filename: &str) -> Option<&str> {
+    let basename = filename.rsplit(['/', '\\']).next()?;
+    let (stem, extension) = basename.rsplit_once('.')?;
+    let safe = !stem.is_empty()
+        && !extension.is_empty()
+        && extension.len() <= MAX_EXTENSION
+        && extension.bytes().all(|byte| byte.is_ascii_alphanumeric());
+    if safe {
+        Some(extension)
+    } else {
+        None
+    }
+}
+
+/// Creates a new file with a name nobody else uses in `dir`
+fn create_unique(dir: &Path, extension: Option<&str>) -> io::Result<(PathBuf, File)> {
+    loop {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_nanos());
+        let mut name = format!(
+            "upload-{:x}-{}",
+            nanos,
+            NEXT_UPLOAD.fetch_add(1, Ordering::Relaxed)
+        );
+        if let Some(extension) = extension {
+            name.push('.');
+            name.push_str(extension);
+        }
+        let path = dir.join(name);
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn multipart(body: &str) -> Multipart {
+        Multipart::new(BodyReader::buffered(body.as_bytes().to_vec()), "XyZ")
+    }
+
+    #[test]
+    fn test_parts() {
+        let large = "a".repeat(READ_SIZE - 20) + "\r\n--XyX is no boundary";
+        let body = format!(
+            "preamble\r\n--XyZ\r\n\
+             Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+             Holiday\r\n--XyZ\r\n\
+             Content-Disposition: form-data; name=\"photo\"; filename=\"a;b \\\"1\\\".jpg\"\r\n\
+             Content-Type: image/jpeg\r\n\r\n\
+             {}\r\n--XyZ--\r\nepilogue",
+            large
+        );
+        let mut multipart = multipart(&body);
+
+        let mut title = multipart.next_part().unwrap().unwrap();
+        assert_eq!((title.name(), title.original_filename()), ("title", None));
+        let mut text = String::new();
+        title.read_to_string(&mut text).unwrap();
+        assert_eq!(text, "Holiday");
+
+        let mut photo = multipart.next_part().unwrap().unwrap();
+        assert_eq!(photo.name(), "photo");
+        assert_eq!(photo.original_filename(), Some("a;b \"1\".jpg"));
+        assert_eq!(photo.content_type(), Some("image/jpeg"));
+        let mut data = String::new();
+        photo.read_to_string(&mut data).unwrap();
+        assert_eq!(data, large);
+        assert!(multipart.next_part().unwrap().is_none());
+        assert!(multipart.next_part().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_unread_parts_are_skipped() {
+        let mut multipart = multipart(
+            "--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n1\r\n\
+             --XyZ\r\nContent-Disposition: form-data; name=b\r\n\r\n2\r\n--XyZ--",
+        );
+        assert_eq!(multipart.next_part().unwrap().unwrap().name(), "a");
+        let mut b = multipart.next_part().unwrap().unwrap();
+        assert_eq!(b.name(), "b");
+        let mut data = String::new();
+        b.read_to_string(&mut data).unwrap();
+        assert_eq!(data, "2");
+    }
+
+    #[test]
+    fn test_truncated() {
+        let mut multipart =
+            multipart("--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n1");
+        let mut a = multipart.next_part().unwrap().unwrap();
+        let error = a.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_boundary() {
+        let boundary_of = |content_type| {
+            let content_type = MediaType::parse(content_type).unwrap();
+            boundary(&content_type).map(String::from)
+        };
+        assert_eq!(
+            boundary_of("multipart/form-data; boundary=\"a b\""),
+            Some(String::from("a b"))
+        );
+        assert_eq!(
+            boundary_of("Multipart/Form-Data;charset=utf-8; boundary=XyZ"),
+            Some(String::from("XyZ"))
+        );
+        assert_eq!(boundary_of("multipart/form-data; boundary=\"\""), None);
+        assert_eq!(boundary_of("multipart/form-data"), None);
+        assert_eq!(boundary_of("text/plain; boundary=XyZ"), None);
+    }
+
+    #[test]
+    fn test_extension() {
+        assert_eq!(extension("photo.JPG"), Some("JPG"));
+        assert_eq!(extension("../../evil.sh"), Some("sh"));
+        assert_eq!(extension("C:\\Users\\me\\notes.txt"), Some("txt"));
+        assert_eq!(extension(".bashrc"), None);
+        assert_eq!(extension("archive.tar/"), None);
+        assert_eq!(extension("x.p/h\\p"), None);
+        assert_eq!(extension("x.a b"), None);
+    }
+}
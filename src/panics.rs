@@ -0,0 +1,104 @@
+use std::any::Any;
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Once;
+
+thread_local! {
+    /// Set while this thread runs a handler through `catch`
+    static CATCHING: Cell<bool> = const { Cell::new(false) };
+    /// Where the last panic of a handler on this thread happened
+    static CAUGHT: RefCell<Option<(Option<String>, Backtrace)>> = const { RefCell::new(None) };
+}
+
+static HOOK: Once = Once::new();
+
+/// A panic of a handler, with where it happened
+pub(crate) struct HandlerPanic {
+    message: String,
+    /// The file, line and column of the panic
+    location: Option<String>,
+    backtrace: Option<Backtrace>,
+}
+
+impl fmt::Display for HandlerPanic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some(location) = &self.location {
+            write!(f, "\nat {}", location)?;
+        }
+        match &self.backtrace {
+            Some(backtrace) if backtrace.status() == BacktraceStatus::Captured => {
+                write!(f, "\nBacktrace:\n{}", backtrace)
+            }
+            _ => write!(f, "\nRun with RUST_BACKTRACE=1 for a backtrace"),
+        }
+    }
+}
+
+/// Runs `handler`, returning its panic instead of unwinding further
+///
+/// While a handler runs, the panic hook keeps the location and backtrace
+/// for the log entry instead of printing them. Panics elsewhere go to the
+/// hook that was installed before.
+pub(crate) fn catch<F: FnOnce()>(handler: F) -> Result<(), HandlerPanic> {
+    HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if CATCHING.with(Cell::get) {
+                let location = info.location().map(ToString::to_string);
+                CAUGHT.with(|caught| *caught.borrow_mut() = Some((location, Backtrace::capture())));
+            } else {
+                previous(info);
+            }
+        }));
+    });
+    // Handlers may run others, e.g. through `Server::test_request`
+    let outer = CATCHING.with(|catching| catching.replace(true));
+    let result = panic::catch_unwind(AssertUnwindSafe(handler));
+    CATCHING.with(|catching| catching.set(outer));
+    result.map_err(|payload| {
+        let (location, backtrace) = match CAUGHT.with(|caught| caught.borrow_mut().take()) {
+            Some((location, backtrace)) => (location, Some(backtrace)),
+            None => (None, None),
+        };
+        HandlerPanic {
+            message: message(payload.as_ref()),
+            location,
+            backtrace,
+        }
+    })
+}
+
+/// Returns the message a panic was started with
+fn message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        String::from(*message)
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        String::from("Box<dyn Any>")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catch() {
+        assert!(catch(|| {}).is_ok());
+        let caught = catch(|| panic!("broken {}", 42)).err().unwrap();
+        assert_eq!(caught.message, "broken 42");
+        assert!(caught.location.unwrap().starts_with("src/panics.rs:"));
+        let caught = catch(|| {
+            assert!(catch(|| panic!("inner")).is_err());
+            panic!("outer");
+        })
+        .err()
+        .unwrap();
+        assert_eq!(caught.message, "outer");
+        assert!(!CATCHING.with(Cell::get));
+    }
+}
@@ -0,0 +1,94 @@
+/// Decodes `%XX` escapes in a request path
+///
+/// Escapes that are not two hex digits are kept as they are, bytes that do
+/// not form UTF-8 become U+FFFD.
+pub(crate) fn decode(encoded: &str) -> String {
+    decode_bytes(encoded, false)
+}
+
+/// Decodes a key or value of `application/x-www-form-urlencoded` data,
+/// where `+` stands for a space
+pub(crate) fn decode_form(encoded: &str) -> String {
+    decode_bytes(encoded, true)
+}
+
+fn decode_bytes(encoded: &str, plus_is_space: bool) -> String {
+    if !encoded.contains('%') && (!plus_is_space || !encoded.contains('+')) {
+        return String::from(encoded);
+    }
+    let bytes = encoded.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => match (
+                bytes.get(i + 1).and_then(hex),
+                bytes.get(i + 2).and_then(hex),
+            ) {
+                (Some(high), Some(low)) => {
+                    decoded.push(high << 4 | low);
+                    i += 3;
+                    continue;
+                }
+                _ => decoded.push(b'%'),
+            },
+            b'+' if plus_is_space => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Encodes everything but the unreserved characters of RFC 3986, e.g. for
+/// a value put into a path segment
+pub(crate) fn encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+fn hex(digit: &u8) -> Option<u8> {
+    (*digit as char).to_digit(16).map(|value| value as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode("/my%20folder/file.txt"), "/my folder/file.txt");
+        assert_eq!(decode("/J%C3%BCrgen"), "/Jürgen");
+        assert_eq!(decode("/a+b"), "/a+b");
+        assert_eq!(decode("/100%25"), "/100%");
+        assert_eq!(decode("/%ZZ/%4"), "/%ZZ/%4");
+        assert_eq!(decode("/%e2%82%ac"), "/€");
+        assert_eq!(decode("/%FF"), "/\u{FFFD}");
+        assert_eq!(decode("/plain"), "/plain");
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode("plain-name_1.0~"), "plain-name_1.0~");
+        assert_eq!(encode("a b/c?d#e"), "a%20b%2Fc%3Fd%23e");
+        assert_eq!(encode("Jürgen"), "J%C3%BCrgen");
+        assert_eq!(decode(&encode("50% off & more")), "50% off & more");
+    }
+
+    #[test]
+    fn test_decode_form() {
+        assert_eq!(decode_form("hello+world"), "hello world");
+        assert_eq!(decode_form("J%C3%BCrgen"), "Jürgen");
+        assert_eq!(decode_form("1%2B1%3D2"), "1+1=2");
+        assert_eq!(decode_form("50%25+off"), "50% off");
+        assert_eq!(decode_form("%"), "%");
+    }
+}
@@ -0,0 +1,474 @@
+use std::io;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// How many idle connections to each upstream are kept for reuse
+const MAX_IDLE: usize = 8;
+
+/// Longest response head accepted from an upstream
+const MAX_HEAD_LEN: usize = 64 * 1024;
+
+/// Headers that only concern a single connection and are never forwarded
+const HOP_BY_HOP: [&str; 7] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Why a request could not be forwarded
+#[derive(Debug)]
+pub(crate) enum ProxyError {
+    /// The upstream could not be reached or sent no usable response, nothing
+    /// was written to the client
+    BadGateway(io::Error),
+    /// The upstream did not answer in time, nothing was written to the client
+    Timeout,
+    /// The response broke off after part of it reached the client
+    Interrupted(io::Error),
+}
+
+impl ProxyError {
+    /// Classifies an error that happened before the client got anything
+    fn before_response(error: io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => ProxyError::Timeout,
+            _ => ProxyError::BadGateway(error),
+        }
+    }
+}
+
+/// A plain HTTP server requests are forwarded to, with a pool of idle
+/// connections to it
+pub(crate) struct Upstream {
+    /// Host and port, also sent as the `Host` header
+    authority: String,
+    /// Path prepended to every forwarded path, without a trailing slash
+    base_path: String,
+    timeout: Duration,
+    idle: Mutex<Vec<TcpStream>>,
+}
+
+impl Upstream {
+    /// Parses a URL like `http://127.0.0.1:9000` or `http://backend/v1`
+    ///
+    /// Returns `None` for anything but plain HTTP URLs.
+    pub fn parse(url: &str, timeout: Duration) -> Option<Self> {
+        let rest = url.strip_prefix("http://")?;
+        let (authority, base_path) = match rest.find('/') {
+            Some(slash) => rest.split_at(slash),
+            None => (rest, ""),
+        };
+        let valid = |c: char| c.is_ascii_graphic() && !"@?#".contains(c);
+        if authority.is_empty() || !authority.chars().all(valid) {
+            return None;
+        }
+        let has_port = match authority.rfind(']') {
+            Some(bracket) => authority[bracket..].contains(':'),
+            None => authority.contains(':'),
+        };
+        let authority = if has_port {
+            String::from(authority)
+        } else {
+            format!("{}:80", authority)
+        };
+        Some(Upstream {
+            authority,
+            base_path: String::from(base_path.trim_end_matches('/')),
+            timeout,
+            idle: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Builds the request sent upstream from the client's request line parts,
+    /// header lines and body
+    ///
+    /// Hop-by-hop headers are dropped, `forwarded` headers replace the ones
+    /// the client sent with the same name.
+    pub fn request(
+        &self,
+        method: &str,
+        path: &str,
+        query: Option<&str>,
+        header_lines: &str,
+        forwarded: &[(&str, String)],
+        body: &[u8],
+    ) -> Vec<u8> {
+        let mut request = format!("{} {}{}", method, self.base_path, path);
+        if let Some(query) = query {
+            request.push('?');
+            request.push_str(query);
+        }
+        request.push_str(" HTTP/1.1\r\n");
+        request.push_str(&format!("Host: {}\r\n", self.authority));
+
+        let connection_headers = listed_in_connection(header_lines);
+        for line in header_lines.split("\r\n") {
+            let name = match line.split_once(':') {
+                Some((name, _)) => name.trim().to_lowercase(),
+                None => continue,
+            };
+            let replaced = ["host", "content-length", "expect"].contains(&name.as_str())
+                || forwarded
+                    .iter()
+                    .any(|(forwarded, _)| forwarded.eq_ignore_ascii_case(&name));
+            if !replaced && !is_hop_by_hop(&name, &connection_headers) {
+                request.push_str(line);
+                request.push_str("\r\n");
+            }
+        }
+        for (name, value) in forwarded {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        if !body.is_empty() {
+            request.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+        request.push_str("\r\n");
+
+        let mut request = request.into_bytes();
+        request.extend_from_slice(body);
+        request
+    }
+
+    /// Sends `request` upstream and relays the response to `client`, adding
+    /// `extra_headers`
+    ///
+    /// The response is passed on as it arrives, with its framing intact. A
+    /// pooled connection the upstream closed in the meantime is retried once
+    /// on a new one.
+    pub fn forward<W: Write>(
+        &self,
+        request: &[u8],
+        head_only: bool,
+        extra_headers: &[(String, String)],
+        client: &mut W,
+    ) -> Result<(), ProxyError> {
+        let pooled = self.idle.lock().unwrap().pop();
+        let (mut upstream, head) = match pooled {
+            Some(tcp) => match exchange(tcp, request) {
+                Ok(Some(response)) => response,
+                // Closed while idle, any other error is the upstream's fault
+                Ok(None) => self.exchange_fresh(request)?,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::ConnectionReset | io::ErrorKind::BrokenPipe
+                    ) =>
+                {
+                    self.exchange_fresh(request)?
+                }
+                Err(e) => return Err(ProxyError::before_response(e)),
+            },
+            None => self.exchange_fresh(request)?,
+        };
+
+        let status_line = head.lines.first().map(String::as_str).unwrap_or("");
+        let status = status_line.split(' ').nth(1).unwrap_or("");
+        let mut response = format!(
+            "HTTP/1.1 {}\r\n",
+            status_line.split_once(' ').map_or("", |(_, rest)| rest)
+        );
+        let header_lines = head.lines[1..].join("\r\n");
+        let connection_headers = listed_in_connection(&header_lines);
+        let mut content_length = None;
+        let mut chunked = false;
+        let mut sent = Vec::new();
+        for line in &head.lines[1..] {
+            let (name, value) = match line.split_once(':') {
+                Some((name, value)) => (name.trim().to_lowercase(), value.trim()),
+                None => continue,
+            };
+            sent.push(name.clone());
+            match name.as_str() {
+                "content-length" => {
+                    let len = value.parse().map_err(|_| {
+                        ProxyError::BadGateway(invalid("invalid upstream Content-Length"))
+                    })?;
+                    content_length = Some(len);
+                }
+                "transfer-encoding" => {
+                    chunked = value.to_lowercase().trim_end().ends_with("chunked");
+                }
+                _ => {}
+            }
+            // The body is relayed as it is framed, so its framing headers stay
+            if name == "transfer-encoding" || !is_hop_by_hop(&name, &connection_headers) {
+                response.push_str(line);
+                response.push_str("\r\n");
+            }
+        }
+        let without_body =
+            head_only || status.starts_with('1') || status == "204" || status == "304";
+        // Chunked encoding wins over a length, as it does for every client
+        let framing = match (content_length, chunked) {
+            _ if without_body => Framing::Length(0),
+            (_, true) => Framing::Chunked,
+            (Some(len), false) => Framing::Length(len),
+            (None, false) => Framing::UntilClose,
+        };
+        // A body ending with the connection ends the client's connection too
+        if matches!(framing, Framing::UntilClose) {
+            response.push_str("Connection: close\r\n");
+        }
+        // Like a handler, the upstream wins over the server's own headers
+        for (name, value) in extra_headers {
+            let connection = name.eq_ignore_ascii_case("Connection");
+            if (connection && matches!(framing, Framing::UntilClose))
+                || (!connection && sent.iter().any(|sent| sent.eq_ignore_ascii_case(name)))
+            {
+                continue;
+            }
+            response.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        response.push_str("\r\n");
+        client
+            .write_all(response.as_bytes())
+            .map_err(ProxyError::Interrupted)?;
+        relay_body(&mut upstream, &framing, client).map_err(ProxyError::Interrupted)?;
+        client.flush().map_err(ProxyError::Interrupted)?;
+
+        let reusable = head.keep_alive && !matches!(framing, Framing::UntilClose);
+        if reusable && upstream.buffer().is_empty() {
+            let mut idle = self.idle.lock().unwrap();
+            if idle.len() < MAX_IDLE {
+                idle.push(upstream.into_inner());
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends `request` over a new connection
+    fn exchange_fresh(
+        &self,
+        request: &[u8],
+    ) -> Result<(BufReader<TcpStream>, ResponseHead), ProxyError> {
+        let tcp = self.connect().map_err(ProxyError::before_response)?;
+        match exchange(tcp, request) {
+            Ok(Some(response)) => Ok(response),
+            Ok(None) => Err(ProxyError::BadGateway(invalid(
+                "upstream closed the connection without a response",
+            ))),
+            Err(e) => Err(ProxyError::before_response(e)),
+        }
+    }
+
+    fn connect(&self) -> io::Result<TcpStream> {
+        let mut last_error = invalid("upstream host did not resolve");
+        for address in self.authority.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&address, self.timeout) {
+                Ok(tcp) => {
+                    tcp.set_read_timeout(Some(self.timeout))?;
+                    tcp.set_write_timeout(Some(self.timeout))?;
+                    return Ok(tcp);
+                }
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+}
+
+/// The status line and header lines of a response
+struct ResponseHead {
+    lines: Vec<String>,
+    /// Whether the upstream allows further requests on the connection
+    keep_alive: bool,
+}
+
+/// How the end of a response body is recognized
+enum Framing {
+    Length(u64),
+    Chunked,
+    UntilClose,
+}
+
+/// Writes the request and reads the response head
+///
+/// Returns `None` if the connection was closed before any of the response
+/// arrived.
+fn exchange(
+    mut tcp: TcpStream,
+    request: &[u8],
+) -> io::Result<Option<(BufReader<TcpStream>, ResponseHead)>> {
+    tcp.write_all(request)?;
+    let mut reader = BufReader::new(tcp);
+    let mut lines = Vec::new();
+    let mut len = 0;
+    loop {
+        let mut line = Vec::new();
+        let read = (&mut reader)
+            .take((MAX_HEAD_LEN - len) as u64 + 1)
+            .read_until(b'\n', &mut line)?;
+        if read == 0 {
+            if lines.is_empty() {
+                return Ok(None);
+            }
+            return Err(invalid("upstream response head is incomplete"));
+        }
+        len += read;
+        if len > MAX_HEAD_LEN {
+            return Err(invalid("upstream response head is too long"));
+        }
+        let line = String::from_utf8(line).map_err(|_| invalid("upstream head is not UTF-8"))?;
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            break;
+        }
+        lines.push(String::from(line));
+    }
+    if !lines[0].starts_with("HTTP/1.") {
+        return Err(invalid("upstream did not answer with HTTP/1.x"));
+    }
+
+    let closes = listed_in_connection(&lines[1..].join("\r\n"))
+        .iter()
+        .any(|option| option == "close");
+    let keep_alive = lines[0].starts_with("HTTP/1.1 ") && !closes;
+    Ok(Some((reader, ResponseHead { lines, keep_alive })))
+}
+
+/// Copies the response body as it arrives, chunked bodies including their
+/// chunk framing
+fn relay_body<R: BufRead, W: Write>(
+    upstream: &mut R,
+    framing: &Framing,
+    client: &mut W,
+) -> io::Result<()> {
+    match framing {
+        Framing::Length(len) => {
+            let copied = io::copy(&mut upstream.take(*len), client)?;
+            if copied < *len {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+        Framing::UntilClose => {
+            io::copy(upstream, client)?;
+        }
+        Framing::Chunked => loop {
+            let size_line = read_line(upstream)?;
+            client.write_all(&size_line)?;
+            let size = std::str::from_utf8(&size_line)
+                .ok()
+                .and_then(|line| line.trim().split(';').next())
+                .and_then(|size| u64::from_str_radix(size.trim(), 16).ok())
+                .ok_or_else(|| invalid("invalid chunk size"))?;
+            if size == 0 {
+                // Trailers up to the empty line
+                loop {
+                    let line = read_line(upstream)?;
+                    client.write_all(&line)?;
+                    if line == b"\r\n" || line == b"\n" {
+                        return Ok(());
+                    }
+                }
+            }
+            // The chunk and the line break after it
+            let chunk_len = size + 2;
+            if io::copy(&mut upstream.take(chunk_len), client)? < chunk_len {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        },
+    }
+    Ok(())
+}
+
+/// Reads a line including its line break, failing if the upstream closed
+/// before
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut line = Vec::new();
+    reader
+        .take(MAX_HEAD_LEN as u64)
+        .read_until(b'\n', &mut line)?;
+    if !line.ends_with(b"\n") {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(line)
+}
+
+/// Returns the lowercase options of the `Connection` header, which name
+/// further headers that must not be forwarded
+fn listed_in_connection(header_lines: &str) -> Vec<String> {
+    header_lines
+        .split("\r\n")
+        .filter_map(|line| line.split_once(':'))
+        .filter(|(name, _)| name.trim().eq_ignore_ascii_case("connection"))
+        .flat_map(|(_, value)| value.split(','))
+        .map(|option| option.trim().to_lowercase())
+        .collect()
+}
+
+fn is_hop_by_hop(name: &str, connection_headers: &[String]) -> bool {
+    HOP_BY_HOP.contains(&name) || connection_headers.iter().any(|header| header == name)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn upstream(url: &str) -> Option<Upstream> {
+        Upstream::parse(url, Duration::from_secs(1))
+    }
+
+    #[test]
+    fn test_parse() {
+        let parsed = upstream("http://127.0.0.1:9000").unwrap();
+        assert_eq!(parsed.authority, "127.0.0.1:9000");
+        assert_eq!(parsed.base_path, "");
+
+        let parsed = upstream("http://backend/v1/").unwrap();
+        assert_eq!(parsed.authority, "backend:80");
+        assert_eq!(parsed.base_path, "/v1");
+
+        assert_eq!(upstream("http://[::1]").unwrap().authority, "[::1]:80");
+        assert_eq!(upstream("http://[::1]:81").unwrap().authority, "[::1]:81");
+        assert!(upstream("https://backend").is_none());
+        assert!(upstream("http://").is_none());
+        assert!(upstream("http://user@backend").is_none());
+    }
+
+    #[test]
+    fn test_request() {
+        let request = upstream("http://127.0.0.1:9000/v1").unwrap().request(
+            "POST",
+            "/users",
+            Some("page=2"),
+            "Host: example.com\r\nConnection: keep-alive, X-Secret\r\nX-Secret: 1\r\n\
+             Content-Type: text/plain\r\nContent-Length: 5\r\nX-Forwarded-For: 10.0.0.1",
+            &[("X-Forwarded-For", String::from("10.0.0.1, 192.0.2.1"))],
+            b"hello",
+        );
+        assert_eq!(
+            String::from_utf8(request).unwrap(),
+            "POST /v1/users?page=2 HTTP/1.1\r\n\
+             Host: 127.0.0.1:9000\r\n\
+             Content-Type: text/plain\r\n\
+             X-Forwarded-For: 10.0.0.1, 192.0.2.1\r\n\
+             Content-Length: 5\r\n\
+             \r\n\
+             hello"
+        );
+    }
+
+    #[test]
+    fn test_relay_chunked_body() {
+        let body = b"4;ext=1\r\nWiki\r\n5\r\npedia\r\n0\r\nExpires: never\r\n\r\nnext";
+        let mut upstream = &body[..];
+        let mut client = Vec::new();
+        relay_body(&mut upstream, &Framing::Chunked, &mut client).unwrap();
+        assert_eq!(client, &body[..body.len() - 4]);
+        assert_eq!(upstream, b"next");
+
+        let mut truncated = &b"4\r\nWi"[..];
+        assert!(relay_body(&mut truncated, &Framing::Chunked, &mut Vec::new()).is_err());
+    }
+}
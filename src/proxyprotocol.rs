@@ -0,0 +1,191 @@
+use std::io;
+use std::io::prelude::*;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+/// First bytes of every version 2 header
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Longest version 1 header including the line break
+const V1_MAX_LEN: usize = 107;
+
+/// Reads the PROXY protocol header, version 1 or 2, from the start of a
+/// connection
+///
+/// Exactly the header is consumed, so the request that follows can be read
+/// as usual. Returns the address of the original client, or `None` if the
+/// proxy does not tell, e.g. for its own health checks. A missing or
+/// malformed header is an `InvalidData` error.
+pub(crate) fn read_header<R: Read>(reader: &mut R) -> io::Result<Option<SocketAddr>> {
+    // Both versions are at least this long
+    let mut start = [0; 12];
+    reader.read_exact(&mut start)?;
+    if start == V2_SIGNATURE {
+        read_v2(reader)
+    } else if start.starts_with(b"PROXY ") {
+        read_v1(reader, &start)
+    } else {
+        Err(invalid("no PROXY protocol header"))
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Reads the rest of a text header like
+/// `PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n`
+fn read_v1<R: Read>(reader: &mut R, start: &[u8]) -> io::Result<Option<SocketAddr>> {
+    let mut line = start.to_vec();
+    // Byte by byte, anything after the line break belongs to the request
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LEN {
+            return Err(invalid("PROXY header too long"));
+        }
+        let mut byte = [0];
+        reader.read_exact(&mut byte)?;
+        line.push(byte[0]);
+    }
+    let line = std::str::from_utf8(&line[..line.len() - 2])
+        .map_err(|_| invalid("PROXY header is not ASCII"))?;
+
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", family, source, _destination, source_port, _destination_port] => {
+            let ip: IpAddr = source
+                .parse()
+                .map_err(|_| invalid("invalid PROXY source address"))?;
+            let port: u16 = source_port
+                .parse()
+                .map_err(|_| invalid("invalid PROXY source port"))?;
+            match (*family, ip) {
+                ("TCP4", IpAddr::V4(_)) | ("TCP6", IpAddr::V6(_)) => {
+                    Ok(Some(SocketAddr::new(ip, port)))
+                }
+                _ => Err(invalid("PROXY address does not match its family")),
+            }
+        }
+        _ => Err(invalid("malformed PROXY header")),
+    }
+}
+
+/// Reads the rest of a binary header after the signature
+fn read_v2<R: Read>(reader: &mut R) -> io::Result<Option<SocketAddr>> {
+    let mut fixed = [0; 4];
+    reader.read_exact(&mut fixed)?;
+    let [version_command, family, len_high, len_low] = fixed;
+    let mut addresses = vec![0; u16::from_be_bytes([len_high, len_low]) as usize];
+    reader.read_exact(&mut addresses)?;
+
+    if version_command >> 4 != 2 {
+        return Err(invalid("unsupported PROXY protocol version"));
+    }
+    match version_command & 0x0f {
+        // LOCAL, the proxy connected on its own behalf
+        0 => return Ok(None),
+        1 => {}
+        _ => return Err(invalid("unsupported PROXY command")),
+    }
+
+    let port = |at: usize| u16::from_be_bytes([addresses[at], addresses[at + 1]]);
+    // The low nibble is the transport, TCP or UDP, which does not matter here
+    match family >> 4 {
+        0x1 if addresses.len() >= 12 => {
+            let mut ip = [0; 4];
+            ip.copy_from_slice(&addresses[..4]);
+            Ok(Some(SocketAddr::new(Ipv4Addr::from(ip).into(), port(8))))
+        }
+        0x2 if addresses.len() >= 36 => {
+            let mut ip = [0; 16];
+            ip.copy_from_slice(&addresses[..16]);
+            Ok(Some(SocketAddr::new(Ipv6Addr::from(ip).into(), port(32))))
+        }
+        // Unspecified or unix sockets carry no usable client address
+        0x0 | 0x3 => Ok(None),
+        _ => Err(invalid("malformed PROXY addresses")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses `input` and returns the address and what was left unread
+    fn parse(input: &[u8]) -> (io::Result<Option<SocketAddr>>, &[u8]) {
+        let mut reader = input;
+        let result = read_header(&mut reader);
+        (result, reader)
+    }
+
+    #[test]
+    fn test_v1() {
+        let (result, rest) = parse(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nGET /");
+        assert_eq!(result.unwrap(), Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(rest, b"GET /");
+
+        let (result, _) = parse(b"PROXY TCP6 2001:db8::1 2001:db8::2 4000 443\r\n");
+        assert_eq!(result.unwrap(), Some("[2001:db8::1]:4000".parse().unwrap()));
+
+        let (result, rest) = parse(b"PROXY UNKNOWN\r\nGET /");
+        assert_eq!(result.unwrap(), None);
+        assert_eq!(rest, b"GET /");
+    }
+
+    #[test]
+    fn test_v1_malformed() {
+        for input in [
+            &b"GET / HTTP/1.1\r\n\r\n"[..],
+            b"PROXY TCP4 192.0.2.1 198.51.100.1 56324\r\n",
+            b"PROXY TCP6 192.0.2.1 198.51.100.1 56324 443\r\n",
+            b"PROXY TCP4 192.0.2.1 198.51.100.1 99999 443\r\n",
+            b"PROXY TCP4 192.0.2.1",
+        ] {
+            assert!(parse(input).0.is_err(), "{:?}", input);
+        }
+        let long = [&b"PROXY "[..], &[b'x'; 200]].concat();
+        assert!(parse(&long).0.is_err());
+    }
+
+    #[test]
+    fn test_v2() {
+        let mut input = V2_SIGNATURE.to_vec();
+        input.extend_from_slice(&[0x21, 0x11, 0, 12, 192, 0, 2, 1, 198, 51, 100, 1]);
+        input.extend_from_slice(&[0xdc, 0x04, 0x01, 0xbb]);
+        input.extend_from_slice(b"GET /");
+        let (result, rest) = parse(&input);
+        assert_eq!(result.unwrap(), Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(rest, b"GET /");
+
+        let mut input = V2_SIGNATURE.to_vec();
+        input.extend_from_slice(&[0x21, 0x21, 0, 36]);
+        input.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        input.extend_from_slice(&[0; 16]);
+        input.extend_from_slice(&[0x0f, 0xa0, 0x01, 0xbb]);
+        assert_eq!(
+            parse(&input).0.unwrap(),
+            Some("[2001:db8::1]:4000".parse().unwrap())
+        );
+
+        // LOCAL with a TLV that is skipped
+        let mut input = V2_SIGNATURE.to_vec();
+        input.extend_from_slice(&[0x20, 0x00, 0, 3, 1, 2, 3]);
+        input.extend_from_slice(b"GET /");
+        let (result, rest) = parse(&input);
+        assert_eq!(result.unwrap(), None);
+        assert_eq!(rest, b"GET /");
+    }
+
+    #[test]
+    fn test_v2_malformed() {
+        let header = |bytes: &[u8]| [&V2_SIGNATURE[..], bytes].concat();
+        // Wrong version, unknown command, too short for IPv4, truncated
+        for input in [
+            header(&[0x11, 0x11, 0, 12, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]),
+            header(&[0x22, 0x11, 0, 12, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]),
+            header(&[0x21, 0x11, 0, 4, 0, 0, 0, 0]),
+            header(&[0x21, 0x11, 0, 12, 0, 0]),
+        ] {
+            assert!(parse(&input).0.is_err(), "{:?}", input);
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// More ranges than this in one request are ignored and the whole file is
+/// sent, so overlapping ranges cannot multiply the work of a response
+pub(crate) const MAX_RANGES: usize = 16;
+
+/// A satisfiable range of bytes, both ends included
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    /// The value of the `Content-Range` header of this range
+    pub fn content_range(&self, complete_len: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, complete_len)
+    }
+}
+
+/// What to answer a `Range` header with
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum RangeRequest {
+    /// The header is invalid, not about bytes or asks for too many ranges,
+    /// it is ignored
+    Full,
+    /// The ranges that overlap the file, sorted and with overlapping and
+    /// adjacent ones merged
+    Partial(Vec<ByteRange>),
+    /// None of the ranges overlaps the file
+    Unsatisfiable,
+}
+
+/// Parses the value of a `Range` header for a file of `len` bytes, RFC 7233
+/// section 2.1
+pub(crate) fn parse(header: &str, len: u64) -> RangeRequest {
+    let (unit, specs) = match header.split_once('=') {
+        Some(split) => split,
+        None => return RangeRequest::Full,
+    };
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return RangeRequest::Full;
+    }
+    let specs: Vec<&str> = specs
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+        .collect();
+    if specs.is_empty() || specs.len() > MAX_RANGES {
+        return RangeRequest::Full;
+    }
+    let mut ranges = Vec::new();
+    for spec in specs {
+        let (first, last) = match spec.split_once('-') {
+            Some((first, last)) => (first.trim(), last.trim()),
+            None => return RangeRequest::Full,
+        };
+        let range = match (number(first), number(last)) {
+            // The last `suffix` bytes
+            (None, Some(suffix)) if first.is_empty() => {
+                if suffix == 0 || len == 0 {
+                    continue;
+                }
+                ByteRange {
+                    start: len.saturating_sub(suffix),
+                    end: len - 1,
+                }
+            }
+            (Some(start), None) if last.is_empty() => ByteRange {
+                start,
+                end: len.saturating_sub(1),
+            },
+            (Some(start), Some(end)) if end >= start => ByteRange {
+                start,
+                end: end.min(len.saturating_sub(1)),
+            },
+            _ => return RangeRequest::Full,
+        };
+        if range.start < len {
+            ranges.push(range);
+        }
+    }
+    if ranges.is_empty() {
+        return RangeRequest::Unsatisfiable;
+    }
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(previous) if range.start <= previous.end.saturating_add(1) => {
+                previous.end = previous.end.max(range.end);
+            }
+            _ => merged.push(range),
+        }
+    }
+    RangeRequest::Partial(merged)
+}
+
+/// Parses a non-negative decimal number without sign or spaces
+fn number(value: &str) -> Option<u64> {
+    if value.is_empty() || !value.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    value.parse().ok()
+}
+
+/// Returns a boundary for a `multipart/byteranges` body that is unlikely to
+/// occur in a file
+pub(crate) fn boundary() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_nanos() as u64);
+    format!(
+        "corrodedweb-{:016x}{:08x}",
+        nanos,
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// Returns the head of every part of a `multipart/byteranges` body, RFC
+/// 7233 appendix A, and the delimiter that closes the body
+///
+/// Each head starts with the line break ending the previous part, so the
+/// body is the heads each followed by the bytes of their range, then the
+/// closing delimiter.
+pub(crate) fn multipart_heads(
+    ranges: &[ByteRange],
+    complete_len: u64,
+    content_type: Option<&str>,
+    boundary: &str,
+) -> (Vec<String>, String) {
+    let content_type = content_type
+        .map(|content_type| format!("Content-Type: {}\r\n", content_type))
+        .unwrap_or_default();
+    let heads = ranges
+        .iter()
+        .map(|range| {
+            format!(
+                "\r\n--{}\r\n{}Content-Range: {}\r\n\r\n",
+                boundary,
+                content_type,
+                range.content_range(complete_len)
+            )
+        })
+        .collect();
+    (heads, format!("\r\n--{}--\r\n", boundary))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn partial(ranges: &[(u64, u64)]) -> RangeRequest {
+        RangeRequest::Partial(
+            ranges
+                .iter()
+                .map(|&(start, end)| ByteRange { start, end })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("bytes=0-99", 1000), partial(&[(0, 99)]));
+        assert_eq!(parse("bytes=900-", 1000), partial(&[(900, 999)]));
+        assert_eq!(parse("bytes=-100", 1000), partial(&[(900, 999)]));
+        assert_eq!(parse("bytes=-2000", 1000), partial(&[(0, 999)]));
+        assert_eq!(parse("bytes=990-2000", 1000), partial(&[(990, 999)]));
+        assert_eq!(
+            parse("Bytes = 0-99, 200-299", 1000),
+            partial(&[(0, 99), (200, 299)])
+        );
+        // Unsatisfiable ranges are dropped while others remain
+        assert_eq!(parse("bytes=0-9,1000-1099", 1000), partial(&[(0, 9)]));
+        assert_eq!(parse("bytes=1000-", 1000), RangeRequest::Unsatisfiable);
+        assert_eq!(parse("bytes=-0", 1000), RangeRequest::Unsatisfiable);
+        assert_eq!(parse("bytes=0-", 0), RangeRequest::Unsatisfiable);
+        // Invalid headers are ignored
+        for invalid in [
+            "0-99",
+            "items=0-99",
+            "bytes=",
+            "bytes=99-0",
+            "bytes=a-b",
+            "bytes=0-99,x",
+            "bytes=+1-2",
+            "bytes=--1",
+            "bytes=0-99999999999999999999",
+        ] {
+            assert_eq!(parse(invalid, 1000), RangeRequest::Full, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(
+            parse("bytes=200-299,0-99", 1000),
+            partial(&[(0, 99), (200, 299)])
+        );
+        assert_eq!(
+            parse("bytes=0-99,50-149,150-199,-10", 1000),
+            partial(&[(0, 199), (990, 999)])
+        );
+        let many = vec!["0-0"; MAX_RANGES].join(",");
+        assert_eq!(parse(&format!("bytes={}", many), 1000), partial(&[(0, 0)]));
+        let too_many = vec!["0-0"; MAX_RANGES + 1].join(",");
+        assert_eq!(
+            parse(&format!("bytes={}", too_many), 1000),
+            RangeRequest::Full
+        );
+    }
+
+    #[test]
+    fn test_multipart_heads() {
+        let ranges = [
+            ByteRange { start: 0, end: 4 },
+            ByteRange { start: 8, end: 9 },
+        ];
+        let (heads, end) = multipart_heads(&ranges, 10, Some("text/plain"), "b");
+        assert_eq!(
+            heads,
+            [
+                "\r\n--b\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-4/10\r\n\r\n",
+                "\r\n--b\r\nContent-Type: text/plain\r\nContent-Range: bytes 8-9/10\r\n\r\n",
+            ]
+        );
+        assert_eq!(end, "\r\n--b--\r\n");
+        assert_ne!(boundary(), boundary());
+    }
+}
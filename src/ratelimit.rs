@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long the allowance of a client takes to refill completely
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Limits how many requests each client address makes per minute, see
+/// `Server::set_rate_limit`
+///
+/// Every address has a bucket of tokens that refills steadily, so a client
+/// may make the allowed number of requests at once and then continues at
+/// the allowed rate. Shared by all workers.
+pub(crate) struct RateLimiter {
+    per_minute: u32,
+    buckets: Mutex<Buckets>,
+}
+
+struct Buckets {
+    /// The tokens left of each address and when they were counted
+    by_ip: HashMap<IpAddr, (f64, Instant)>,
+    /// When the buckets full again were last removed
+    pruned: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(per_minute: u32) -> Self {
+        RateLimiter {
+            per_minute: per_minute.max(1),
+            buckets: Mutex::new(Buckets {
+                by_ip: HashMap::new(),
+                pruned: Instant::now(),
+            }),
+        }
+    }
+
+    /// Takes a token of `ip` for a request, or returns how long until the
+    /// next one if none is left
+    pub fn acquire(&self, ip: IpAddr) -> Result<(), Duration> {
+        self.acquire_at(ip, Instant::now())
+    }
+
+    fn acquire_at(&self, ip: IpAddr, now: Instant) -> Result<(), Duration> {
+        let capacity = f64::from(self.per_minute);
+        let per_second = capacity / WINDOW.as_secs_f64();
+        let mut buckets = self.buckets.lock().unwrap();
+        // A bucket untouched for a window is full, forgetting it changes
+        // nothing. Once per window, not on every request.
+        if now.duration_since(buckets.pruned) >= WINDOW {
+            buckets
+                .by_ip
+                .retain(|_, (_, counted)| now.duration_since(*counted) < WINDOW);
+            buckets.pruned = now;
+        }
+        let (tokens, counted) = buckets.by_ip.entry(ip).or_insert((capacity, now));
+        let refilled = now.duration_since(*counted).as_secs_f64() * per_second;
+        *tokens = (*tokens + refilled).min(capacity);
+        *counted = now;
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - *tokens) / per_second))
+        }
+    }
+
+    /// Returns how many addresses have a bucket
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.buckets.lock().unwrap().by_ip.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(3);
+        let client = IpAddr::from([192, 0, 2, 1]);
+        let other = IpAddr::from([192, 0, 2, 2]);
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(limiter.acquire_at(client, start), Ok(()));
+        }
+        // A token every 20 seconds
+        assert_eq!(
+            limiter.acquire_at(client, start),
+            Err(Duration::from_secs(20))
+        );
+        assert_eq!(limiter.acquire_at(other, start), Ok(()));
+        let later = start + Duration::from_secs(15);
+        assert_eq!(
+            limiter.acquire_at(client, later),
+            Err(Duration::from_secs(5))
+        );
+        assert_eq!(
+            limiter.acquire_at(client, start + Duration::from_secs(20)),
+            Ok(())
+        );
+        assert_eq!(limiter.len(), 2);
+
+        // Full buckets are forgotten, the one used just now stays
+        let much_later = start + Duration::from_secs(100);
+        assert_eq!(limiter.acquire_at(client, much_later), Ok(()));
+        assert_eq!(limiter.len(), 1);
+        assert_eq!(limiter.acquire_at(client, much_later), Ok(()));
+        assert_eq!(limiter.acquire_at(client, much_later), Ok(()));
+        assert!(limiter.acquire_at(client, much_later).is_err());
+    }
+}
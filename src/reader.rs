@@ -0,0 +1,380 @@
+use crate::bufferpool::{PooledBuffer, BUFFER_SIZE};
+use crate::stream::Stream;
+use std::io;
+use std::io::prelude::*;
+use std::time::{Duration, Instant};
+
+/// How long clients may take to send a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RequestTimeouts {
+    /// From accepting the connection to the end of the head
+    pub head: Duration,
+    /// From the end of the head to the end of the body
+    pub body: Duration,
+}
+
+impl Default for RequestTimeouts {
+    fn default() -> Self {
+        RequestTimeouts {
+            head: Duration::from_secs(10),
+            body: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Limits on the head of a request, so clients cannot make the server
+/// buffer and parse arbitrary amounts of headers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct HeaderLimits {
+    /// How many header lines a request may have
+    pub count: usize,
+    /// How long a single header line may be, without the line break
+    pub line: usize,
+    /// How long the head may be, request line included
+    pub total: usize,
+}
+
+impl Default for HeaderLimits {
+    fn default() -> Self {
+        HeaderLimits {
+            count: 100,
+            line: 8 * 1024,
+            total: 32 * 1024,
+        }
+    }
+}
+
+/// How long a read past the head deadline waits, long enough to take
+/// what arrived while the connection waited for a worker
+const LATE_READ_TIMEOUT: Duration = Duration::from_millis(1);
+
+/// Why a request could not be read
+#[derive(Debug)]
+pub(crate) enum ReadError {
+    /// The client did not send the request within the `RequestTimeouts`
+    TimedOut,
+    /// The head exceeds the `HeaderLimits`, the message says how
+    HeadTooLarge(String),
+    Io(io::Error),
+}
+
+impl From<io::Error> for ReadError {
+    fn from(error: io::Error) -> Self {
+        ReadError::Io(error)
+    }
+}
+
+/// Reads a request into `buffer` and returns its length and how many bytes
+/// were read, which may be more for a body longer than `request_len` looks at
+/// or for the start of the next request
+///
+/// The first `pending` bytes of `buffer` are already there, they arrived
+/// together with the previous request on the connection. Reading stops once
+/// the request is complete or the client closed the connection. The head
+/// must be complete within `timeouts.head` of `accepted` and the body within
+/// `timeouts.body` after that, however the client spreads its bytes. Past
+/// the head deadline bytes that are there already are still taken, so a
+/// client that sent its request right away is not turned away for waiting
+/// for a worker. The head is checked against `limits` with every read, so an
+/// oversized one is never buffered completely. The buffer grows for heads
+/// that do not fit.
+pub(crate) fn read_request(
+    stream: &mut Stream,
+    buffer: &mut PooledBuffer,
+    pending: usize,
+    timeouts: RequestTimeouts,
+    limits: HeaderLimits,
+    accepted: Instant,
+) -> Result<(usize, usize), ReadError> {
+    let mut filled = pending;
+    let mut deadline = accepted + timeouts.head;
+    let mut head_complete = false;
+    let mut late_read_failed = false;
+    let len = loop {
+        let data = &buffer[..filled];
+        if !head_complete {
+            check_head(data, limits).map_err(ReadError::HeadTooLarge)?;
+            if head_len(data).is_some() {
+                head_complete = true;
+                deadline = Instant::now() + timeouts.body;
+            }
+        }
+        if let Some(len) = request_len(data) {
+            break len;
+        }
+        let mut remaining = deadline.saturating_duration_since(Instant::now());
+        let late = remaining.is_zero();
+        if late {
+            if head_complete || late_read_failed {
+                return Err(ReadError::TimedOut);
+            }
+            remaining = LATE_READ_TIMEOUT;
+        }
+        if filled == buffer.len() {
+            buffer.grow(BUFFER_SIZE);
+        }
+        if let Some(tcp) = stream.tcp() {
+            tcp.set_read_timeout(Some(remaining))?;
+        }
+        match stream.read(&mut buffer[filled..]) {
+            Ok(0) => break filled,
+            Ok(read) => filled += read,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock
+                        | io::ErrorKind::TimedOut
+                        | io::ErrorKind::Interrupted
+                ) =>
+            {
+                late_read_failed = late;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    };
+    // Whatever reads the rest of a large body waits as long as it takes
+    if let Some(tcp) = stream.tcp() {
+        tcp.set_read_timeout(None)?;
+    }
+    Ok((len, filled))
+}
+
+/// Checks the head received so far against `limits`, it may still be
+/// incomplete
+pub(crate) fn check_head(data: &[u8], limits: HeaderLimits) -> Result<(), String> {
+    let head = match head_len(data) {
+        Some(len) => &data[..len - 4],
+        None => data,
+    };
+    if head.len() > limits.total {
+        return Err(format!("head longer than {} bytes", limits.total));
+    }
+    let mut count = 0;
+    // The request line is only limited by the total
+    for line in head.split(|&byte| byte == b'\n').skip(1) {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.len() > limits.line {
+            return Err(format!("header line longer than {} bytes", limits.line));
+        }
+        // The start of the empty line ending an incomplete head is no header
+        if !line.is_empty() {
+            count += 1;
+        }
+    }
+    if count > limits.count {
+        return Err(format!("more than {} headers", limits.count));
+    }
+    Ok(())
+}
+
+/// Returns the length of the head including the empty line ending it, once
+/// it is complete
+pub(crate) fn head_len(data: &[u8]) -> Option<usize> {
+    data.windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .map(|end| end + 4)
+}
+
+/// Returns the length of the first request in `data` once it is complete
+///
+/// No more than `BUFFER_SIZE` bytes of a body are looked at. A chunked body
+/// is whatever arrived together with the head, even if there is a
+/// `Content-Length` as well, like `Framing::of` reads it. Without either
+/// there is none and what follows is the next request.
+pub(crate) fn request_len(data: &[u8]) -> Option<usize> {
+    let head_len = head_len(data)?;
+    let body_len = if header(data, "transfer-encoding").is_some_and(is_chunked) {
+        data.len() - head_len
+    } else {
+        header(data, "content-length").map_or(0, |len| len.parse::<usize>().unwrap_or(0))
+    };
+    let len = head_len + body_len.min(BUFFER_SIZE);
+    if data.len() >= len {
+        Some(len)
+    } else {
+        None
+    }
+}
+
+/// Returns whether the body of the complete request `data` goes on beyond
+/// it, because it is longer than `request_len` looks at or chunked
+#[cfg(feature = "tokio")]
+pub(crate) fn body_continues(data: &[u8]) -> bool {
+    let head_len = match head_len(data) {
+        Some(len) => len,
+        None => return false,
+    };
+    if header(data, "transfer-encoding").is_some_and(is_chunked) {
+        return true;
+    }
+    header(data, "content-length")
+        .and_then(|len| len.parse::<u64>().ok())
+        .is_some_and(|len| len > (data.len() - head_len) as u64)
+}
+
+/// Returns whether a `Transfer-Encoding` ends with `chunked`, which frames
+/// the body
+pub(crate) fn is_chunked(transfer_encoding: &str) -> bool {
+    transfer_encoding
+        .rsplit(',')
+        .next()
+        .is_some_and(|coding| coding.trim().eq_ignore_ascii_case("chunked"))
+}
+
+/// Returns the value of the header `name` in the head of an HTTP message
+pub(crate) fn header<'a>(message: &'a [u8], name: &str) -> Option<&'a str> {
+    let end = head_len(message).map_or(message.len(), |len| len - 4);
+    let head = std::str::from_utf8(&message[..end]).ok()?;
+    head.split("\r\n").skip(1).find_map(|line| {
+        let (header, value) = line.split_once(':')?;
+        if header.trim().eq_ignore_ascii_case(name) {
+            Some(value.trim())
+        } else {
+            None
+        }
+    })
+}
+
+/// Returns whether the client can tell where the response ends without the
+/// connection being closed, and the connection is meant to stay open
+pub(crate) fn is_delimited(response: &[u8]) -> bool {
+    // 1xx, 204 and 304 responses end with their head
+    let bodyless = response
+        .get(9..12)
+        .is_some_and(|code| code[0] == b'1' || code == b"204" || code == b"304");
+    let framed = bodyless
+        || header(response, "content-length").is_some()
+        || header(response, "transfer-encoding")
+            .is_some_and(|value| value.to_ascii_lowercase().ends_with("chunked"));
+    framed && !closes(response)
+}
+
+fn closes(message: &[u8]) -> bool {
+    header(message, "connection").is_some_and(|value| {
+        value
+            .split(',')
+            .any(|option| option.trim().eq_ignore_ascii_case("close"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::MemoryStream;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_request_len() {
+        assert_eq!(request_len(b"GET / HTTP/1.1\r\nHost: a"), None);
+        assert_eq!(request_len(b"GET / HTTP/1.1\r\n\r\n"), Some(18));
+        assert_eq!(request_len(b"POST / HTTP/1.1\r\n\r\na=1"), Some(19));
+        assert_eq!(
+            request_len(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n1\r\na"),
+            Some(51)
+        );
+        let post = b"POST / HTTP/1.1\r\nContent-Length: 3\r\n\r\na=";
+        assert_eq!(request_len(post), None);
+        assert_eq!(
+            request_len(&[&post[..], b"1GET"].concat()),
+            Some(post.len() + 1)
+        );
+        // Chunked wins over a length, as in `Framing::of`
+        let both =
+            b"POST / HTTP/1.1\r\nContent-Length: 40\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n";
+        assert_eq!(request_len(both), Some(both.len()));
+        assert_eq!(request_len(&[b'x'; BUFFER_SIZE + 10]), None);
+        let large = format!(
+            "POST / HTTP/1.1\r\nContent-Length: 99999\r\n\r\n{}",
+            "x".repeat(BUFFER_SIZE + 10)
+        );
+        assert_eq!(request_len(large.as_bytes()), Some(large.len() - 10));
+    }
+
+    #[test]
+    fn test_check_head() {
+        let limits = HeaderLimits {
+            count: 3,
+            line: 20,
+            total: 100,
+        };
+        let check = |head: &str| check_head(head.as_bytes(), limits);
+        assert!(check("GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n").is_ok());
+        // Incomplete heads are checked as far as they go
+        assert!(check("GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r").is_ok());
+        assert!(check("GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\nD").is_err());
+        assert!(check("GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\nD: 4\r\n\r\n").is_err());
+        // The request line only counts towards the total
+        assert!(check(&format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(50))).is_ok());
+        assert!(check(&format!("GET / HTTP/1.1\r\nA: {}\r\n\r\n", "a".repeat(17))).is_ok());
+        assert!(check(&format!("GET / HTTP/1.1\r\nA: {}\r\n\r\n", "a".repeat(18))).is_err());
+        assert!(check(&format!("GET / HTTP/1.1\r\nA: {}", "a".repeat(18))).is_err());
+        assert!(check(&format!("GET /{}", "a".repeat(100))).is_err());
+        // Bodies are no part of the head
+        assert!(check(&format!("GET / HTTP/1.1\r\n\r\n{}", "a".repeat(200))).is_ok());
+    }
+
+    #[test]
+    fn test_read_request_from_memory() {
+        let request = b"POST / HTTP/1.1\r\nContent-Length: 3\r\n\r\na=1GET / HTTP/1.1\r\n\r\n";
+        let mut stream = Stream::Memory(MemoryStream {
+            input: io::Cursor::new(request.to_vec()),
+            output: Arc::default(),
+            encrypted: false,
+            remaining_requests: 0,
+            sni_hostname: None,
+            #[cfg(feature = "tls")]
+            client_certificate: None,
+            #[cfg(feature = "tokio")]
+            body_feed: None,
+        });
+        let mut buffer = PooledBuffer::take();
+        let (len, filled) = read_request(
+            &mut stream,
+            &mut buffer,
+            0,
+            RequestTimeouts::default(),
+            HeaderLimits::default(),
+            Instant::now(),
+        )
+        .unwrap();
+        assert_eq!(&buffer[..len], &request[..request.len() - 18]);
+        assert_eq!(filled, request.len());
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn test_body_continues() {
+        assert!(!body_continues(b"GET / HTTP/1.1\r\n\r\n"));
+        assert!(!body_continues(
+            b"POST / HTTP/1.1\r\nContent-Length: 3\r\n\r\na=1"
+        ));
+        assert!(body_continues(
+            b"POST / HTTP/1.1\r\nContent-Length: 9000\r\n\r\na=1"
+        ));
+        assert!(body_continues(
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n"
+        ));
+        assert!(!body_continues(b"POST / HTTP/1.1\r\nContent-Length: 9000"));
+    }
+
+    #[test]
+    fn test_is_delimited() {
+        assert!(is_delimited(
+            b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok"
+        ));
+        assert!(!is_delimited(b"HTTP/1.1 404 NOT FOUND\r\n\r\n<html>"));
+        assert!(is_delimited(
+            b"HTTP/1.1 204 No Content\r\nAllow: GET\r\n\r\n"
+        ));
+        assert!(is_delimited(b"HTTP/1.1 304 Not Modified\r\n\r\n"));
+        assert!(!is_delimited(
+            b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+        ));
+        assert!(is_delimited(
+            b"HTTP/1.1 200 OK\r\nConnection: keep-alive\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n"
+        ));
+        assert!(!is_delimited(
+            b"HTTP/1.1 200 OK\r\nConnection: Upgrade, Close\r\nContent-Length: 0\r\n\r\n"
+        ));
+    }
+}
@@ -0,0 +1,491 @@
+use crate::percent;
+use std::collections::HashMap;
+
+/// A registered route pointing to a value, usually the endpoint callback
+#[derive(Clone)]
+pub struct Route<T> {
+    pattern: String,
+    method: String,
+    /// Names of the `:param` segments in the order they appear
+    params: Vec<String>,
+    wildcard: bool,
+    literals: usize,
+    value: T,
+}
+
+impl<T> Route<T> {
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    pub fn value(&self) -> &T {
+        &self.value
+    }
+}
+
+/// The result of a successful lookup
+pub struct Match<'a, T> {
+    pub route: &'a Route<T>,
+    /// Captured path parameters, `*` holds the rest matched by a wildcard
+    pub params: HashMap<String, String>,
+}
+
+#[derive(Clone, Default)]
+struct Node {
+    literals: HashMap<String, Node>,
+    param: Option<Box<Node>>,
+    /// Routes ending in this node
+    routes: Vec<usize>,
+    /// Routes ending in a wildcard after this node
+    wildcards: Vec<usize>,
+}
+
+/// Matches request paths against the registered route patterns.
+///
+/// A pattern is split into segments at `/`. A segment starting with `:`
+/// captures exactly one non-empty path segment, a final `*` captures the
+/// rest of the path. All other segments have to match literally, which
+/// includes the empty segment produced by a trailing slash.
+///
+/// If several routes match a path, the one with the highest precedence wins:
+/// 1. routes without a wildcard beat routes with a wildcard
+/// 2. more literal segments beat fewer literal segments, so an exact match
+///    beats every parameterized route
+/// 3. earlier registration beats later registration
+#[derive(Clone)]
+pub struct Router<T> {
+    root: Node,
+    routes: Vec<Route<T>>,
+    /// Maps the normalized pattern and method to the index in `routes`
+    shapes: HashMap<(String, String), usize>,
+    case_insensitive: bool,
+}
+
+impl<T> Router<T> {
+    pub fn new() -> Self {
+        Router {
+            root: Node::default(),
+            routes: Vec::new(),
+            shapes: HashMap::new(),
+            case_insensitive: false,
+        }
+    }
+
+    /// Compares literal segments ignoring their case. Captured parameters
+    /// keep the casing of the request path.
+    pub fn set_case_insensitive(&mut self, case_insensitive: bool) {
+        if self.case_insensitive != case_insensitive {
+            self.case_insensitive = case_insensitive;
+            self.root = Node::default();
+            for index in 0..self.routes.len() {
+                self.add_to_tree(index);
+            }
+        }
+    }
+
+    /// Returns the route which conflicts with `pattern` for this method.
+    ///
+    /// Patterns conflict if they only differ in their parameter names.
+    pub fn find(&self, method: &str, pattern: &str) -> Option<&Route<T>> {
+        self.shapes
+            .get(&(Router::<T>::shape(pattern), String::from(method)))
+            .map(|&index| &self.routes[index])
+    }
+
+    pub fn find_mut(&mut self, method: &str, pattern: &str) -> Option<&mut T> {
+        match self
+            .shapes
+            .get(&(Router::<T>::shape(pattern), String::from(method)))
+        {
+            Some(&index) => Some(&mut self.routes[index].value),
+            None => None,
+        }
+    }
+
+    /// Registers a route. A conflicting route is replaced but keeps its
+    /// position in the registration order.
+    pub fn insert(&mut self, method: &str, pattern: &str, value: T) {
+        let mut params = Vec::new();
+        let mut wildcard = false;
+        let mut literals = 0;
+        let segments = Router::<T>::segments(pattern);
+        for (i, segment) in segments.iter().enumerate() {
+            if let Some(name) = segment.strip_prefix(':') {
+                params.push(String::from(name));
+            } else if *segment == "*" && i == segments.len() - 1 {
+                wildcard = true;
+            } else {
+                literals += 1;
+            }
+        }
+        let route = Route {
+            pattern: String::from(pattern),
+            method: String::from(method),
+            params,
+            wildcard,
+            literals,
+            value,
+        };
+
+        let key = (Router::<T>::shape(pattern), String::from(method));
+        if let Some(&index) = self.shapes.get(&key) {
+            self.routes[index] = route;
+            return;
+        }
+
+        let index = self.routes.len();
+        self.routes.push(route);
+        self.shapes.insert(key, index);
+        self.add_to_tree(index);
+    }
+
+    fn add_to_tree(&mut self, index: usize) {
+        let case_insensitive = self.case_insensitive;
+        let mut node = &mut self.root;
+        let mut segments = Router::<T>::segments(&self.routes[index].pattern)
+            .into_iter()
+            .peekable();
+        while let Some(segment) = segments.next() {
+            if segment == "*" && segments.peek().is_none() {
+                node.wildcards.push(index);
+                return;
+            }
+            node = if segment.starts_with(':') {
+                node.param.get_or_insert_with(Default::default)
+            } else if case_insensitive {
+                node.literals.entry(segment.to_lowercase()).or_default()
+            } else {
+                node.literals.entry(String::from(segment)).or_default()
+            };
+        }
+        node.routes.push(index);
+    }
+
+    /// Finds the route with the highest precedence for this method and path
+    pub fn lookup(&self, method: &str, path: &str) -> Option<Match<'_, T>> {
+        let segments = Router::<T>::segments(path);
+        let candidates = self.candidates(path, &segments);
+
+        candidates
+            .into_iter()
+            .filter(|(index, _)| self.routes[*index].method == method)
+            .min_by_key(|(index, _)| {
+                let route = &self.routes[*index];
+                (route.wildcard, std::cmp::Reverse(route.literals), *index)
+            })
+            .map(|(index, captures)| {
+                let route = &self.routes[index];
+                let mut params: HashMap<String, String> = route
+                    .params
+                    .iter()
+                    .cloned()
+                    .zip(captures.iter().map(|c| String::from(*c)))
+                    .collect();
+                if route.wildcard {
+                    params.insert(
+                        String::from("*"),
+                        segments[route.literals + route.params.len()..].join("/"),
+                    );
+                }
+                Match { route, params }
+            })
+    }
+
+    /// Returns the methods of all routes matching the path, in registration order
+    pub fn allowed_methods(&self, path: &str) -> Vec<String> {
+        let segments = Router::<T>::segments(path);
+        let mut candidates = self.candidates(path, &segments);
+        candidates.sort_by_key(|(index, _)| *index);
+        let mut methods: Vec<String> = Vec::new();
+        for (index, _) in candidates {
+            let method = &self.routes[index].method;
+            if !methods.contains(method) {
+                methods.push(method.clone());
+            }
+        }
+        methods
+    }
+
+    /// Iterates over all routes in registration order
+    pub fn iter(&self) -> impl Iterator<Item = &Route<T>> {
+        self.routes.iter()
+    }
+
+    /// Returns the indices of all routes matching the path segments together
+    /// with the captured parameter values
+    fn candidates<'p>(&self, path: &str, segments: &[&'p str]) -> Vec<(usize, Vec<&'p str>)> {
+        let lowercase;
+        let keys = if self.case_insensitive {
+            lowercase = path.to_lowercase();
+            Router::<T>::segments(&lowercase)
+        } else {
+            segments.to_vec()
+        };
+        let mut candidates = Vec::new();
+        Router::<T>::collect(
+            &self.root,
+            segments,
+            &keys,
+            0,
+            &mut Vec::new(),
+            &mut candidates,
+        );
+        candidates
+    }
+
+    /// Walks the tree and collects every route matching the segments
+    /// together with the captured parameter values. Literal segments are
+    /// looked up with `keys`, which are the segments as stored in the tree.
+    fn collect<'p>(
+        node: &Node,
+        segments: &[&'p str],
+        keys: &[&str],
+        depth: usize,
+        captures: &mut Vec<&'p str>,
+        candidates: &mut Vec<(usize, Vec<&'p str>)>,
+    ) {
+        for &index in &node.wildcards {
+            candidates.push((index, captures.clone()));
+        }
+        if depth == segments.len() {
+            for &index in &node.routes {
+                candidates.push((index, captures.clone()));
+            }
+            return;
+        }
+        let segment = segments[depth];
+        if let Some(child) = node.literals.get(keys[depth]) {
+            Router::<T>::collect(child, segments, keys, depth + 1, captures, candidates);
+        }
+        if let Some(child) = &node.param {
+            if !segment.is_empty() {
+                captures.push(segment);
+                Router::<T>::collect(child, segments, keys, depth + 1, captures, candidates);
+                captures.pop();
+            }
+        }
+    }
+
+    fn segments(path: &str) -> Vec<&str> {
+        path.strip_prefix('/').unwrap_or(path).split('/').collect()
+    }
+
+    /// Replaces the parameter names so patterns only differing in them are equal
+    fn shape(pattern: &str) -> String {
+        Router::<T>::segments(pattern)
+            .iter()
+            .map(|segment| {
+                if segment.starts_with(':') {
+                    ":"
+                } else {
+                    segment
+                }
+            })
+            .collect::<Vec<&str>>()
+            .join("/")
+    }
+}
+
+/// Returns the path of `pattern` with its `:param` segments replaced by
+/// the percent-encoded values in `params` and a final `*` by the value of
+/// `*`, whose slashes are kept
+///
+/// Returns `None` if a parameter is missing or empty, a missing wildcard
+/// matches the empty rest.
+pub fn fill(pattern: &str, params: &[(&str, &str)]) -> Option<String> {
+    let value = |name: &str| {
+        params
+            .iter()
+            .find(|(param, _)| *param == name)
+            .map(|(_, value)| *value)
+    };
+    let segments = Router::<()>::segments(pattern);
+    let mut filled = Vec::with_capacity(segments.len());
+    for (i, segment) in segments.iter().enumerate() {
+        if let Some(name) = segment.strip_prefix(':') {
+            filled.push(percent::encode(
+                value(name).filter(|value| !value.is_empty())?,
+            ));
+        } else if *segment == "*" && i == segments.len() - 1 {
+            let rest = value("*").unwrap_or("");
+            let rest: Vec<String> = rest.split('/').map(percent::encode).collect();
+            filled.push(rest.join("/"));
+        } else {
+            filled.push(String::from(*segment));
+        }
+    }
+    Some(format!("/{}", filled.join("/")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn winner(patterns: &[&str], path: &str) -> Option<String> {
+        let mut router = Router::new();
+        for pattern in patterns {
+            router.insert("GET", pattern, ());
+        }
+        router
+            .lookup("GET", path)
+            .map(|m| String::from(m.route.pattern()))
+    }
+
+    /// Returns every ordering of the given patterns
+    fn permutations<'a>(patterns: &[&'a str]) -> Vec<Vec<&'a str>> {
+        if patterns.len() <= 1 {
+            return vec![patterns.to_vec()];
+        }
+        let mut result = Vec::new();
+        for i in 0..patterns.len() {
+            let mut rest = patterns.to_vec();
+            let first = rest.remove(i);
+            for mut permutation in permutations(&rest) {
+                permutation.insert(0, first);
+                result.push(permutation);
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn test_precedence_independent_of_registration_order() {
+        let patterns = [
+            "/users/me",
+            "/users/:id",
+            "/users/*",
+            "/:section/:id",
+            "/users/:id/posts",
+        ];
+        let expectations = [
+            ("/users/me", Some("/users/me")),
+            ("/users/42", Some("/users/:id")),
+            ("/users/42/posts", Some("/users/:id/posts")),
+            ("/users/42/comments", Some("/users/*")),
+            ("/groups/42", Some("/:section/:id")),
+            ("/groups/42/posts", None),
+        ];
+        for permutation in permutations(&patterns) {
+            for (path, expected) in expectations.iter() {
+                assert_eq!(
+                    winner(&permutation, path).as_deref(),
+                    *expected,
+                    "{} with registration order {:?}",
+                    path,
+                    permutation
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_more_literal_segments_win() {
+        for permutation in permutations(&["/:a/b/c", "/a/:b/:c", "/:a/:b/:c"]) {
+            assert_eq!(winner(&permutation, "/a/b/c").as_deref(), Some("/:a/b/c"));
+            assert_eq!(winner(&permutation, "/a/x/y").as_deref(), Some("/a/:b/:c"));
+            assert_eq!(winner(&permutation, "/x/y/z").as_deref(), Some("/:a/:b/:c"));
+        }
+    }
+
+    #[test]
+    fn test_registration_order_breaks_ties() {
+        assert_eq!(
+            winner(&["/a/:b", "/:a/b"], "/a/b").as_deref(),
+            Some("/a/:b")
+        );
+        assert_eq!(
+            winner(&["/:a/b", "/a/:b"], "/a/b").as_deref(),
+            Some("/:a/b")
+        );
+    }
+
+    #[test]
+    fn test_captures() {
+        let mut router = Router::new();
+        router.insert("GET", "/users/:uid/posts/:pid/", 1);
+        router.insert("GET", "/files/*", 2);
+
+        let m = router.lookup("GET", "/users/7/posts/9/").unwrap();
+        assert_eq!(*m.route.value(), 1);
+        assert_eq!(m.params["uid"], "7");
+        assert_eq!(m.params["pid"], "9");
+
+        let m = router.lookup("GET", "/files/a/b.txt").unwrap();
+        assert_eq!(*m.route.value(), 2);
+        assert_eq!(m.params["*"], "a/b.txt");
+
+        assert!(router.lookup("GET", "/users/7/posts/").is_none());
+        assert!(router.lookup("GET", "/users//posts/9/").is_none());
+        assert!(router.lookup("POST", "/files/a").is_none());
+    }
+
+    #[test]
+    fn test_conflicting_patterns_replace() {
+        let mut router = Router::new();
+        router.insert("GET", "/users/:id", 1);
+        router.insert("GET", "/other", 2);
+        assert_eq!(
+            router.find("GET", "/users/:uid").unwrap().pattern(),
+            "/users/:id"
+        );
+        router.insert("GET", "/users/:uid", 3);
+        let m = router.lookup("GET", "/users/5").unwrap();
+        assert_eq!(*m.route.value(), 3);
+        assert_eq!(m.params["uid"], "5");
+        assert_eq!(router.iter().count(), 2);
+    }
+
+    #[test]
+    fn test_case_insensitive() {
+        let mut router = Router::new();
+        router.insert("GET", "/contact/", 1);
+        router.insert("GET", "/Users/:name/*", 2);
+        assert!(router.lookup("GET", "/Contact/").is_none());
+
+        router.set_case_insensitive(true);
+        assert_eq!(*router.lookup("GET", "/Contact/").unwrap().route.value(), 1);
+        let m = router.lookup("GET", "/USERS/JoHn/Some/File").unwrap();
+        assert_eq!(*m.route.value(), 2);
+        assert_eq!(m.params["name"], "JoHn");
+        assert_eq!(m.params["*"], "Some/File");
+
+        router.insert("GET", "/About/", 3);
+        assert_eq!(*router.lookup("GET", "/aBOUT/").unwrap().route.value(), 3);
+
+        router.set_case_insensitive(false);
+        assert!(router.lookup("GET", "/Contact/").is_none());
+    }
+
+    #[test]
+    fn test_fill() {
+        assert_eq!(fill("/", &[]).unwrap(), "/");
+        assert_eq!(fill("/about/", &[("id", "1")]).unwrap(), "/about/");
+        assert_eq!(
+            fill("/users/:id/posts/:post/", &[("post", "7"), ("id", "a b")]).unwrap(),
+            "/users/a%20b/posts/7/"
+        );
+        assert_eq!(
+            fill("/users/:id/", &[("id", "../admin")]).unwrap(),
+            "/users/..%2Fadmin/"
+        );
+        assert_eq!(fill("/users/:id/", &[]), None);
+        assert_eq!(fill("/users/:id/", &[("id", "")]), None);
+        assert_eq!(
+            fill("/files/*", &[("*", "docs/read me.txt")]).unwrap(),
+            "/files/docs/read%20me.txt"
+        );
+        assert_eq!(fill("/files/*", &[]).unwrap(), "/files/");
+
+        // What fill builds is matched by the pattern again
+        let mut router = Router::new();
+        router.insert("GET", "/users/:id/files/*", ());
+        let path = fill("/users/:id/files/*", &[("id", "x"), ("*", "a/b")]).unwrap();
+        let m = router.lookup("GET", &path).unwrap();
+        assert_eq!(m.params["id"], "x");
+        assert_eq!(m.params["*"], "a/b");
+    }
+}
@@ -1,33 +1,576 @@
-use crate::logger::Logger;
+use crate::accept::AcceptList;
+use crate::acceptloop::{AcceptAction, AcceptErrors, ACCEPT_BACKOFF};
+#[cfg(feature = "access-files")]
+use crate::accessfile::{self, AccessFiles, AccessRules};
+use crate::accesslog::{AccessLog, Pending, Tally};
+use crate::assets::{self, Assets};
+#[cfg(feature = "tokio")]
+use crate::asynclistener::{AsyncListener, Connection};
+use crate::basicauth::{self, Protection};
+use crate::body::{BodyReader, Framing, IncomingBody};
+use crate::bufferpool::PooledBuffer;
+use crate::builder::ServerBuilder;
+#[cfg(feature = "gzip")]
+use crate::compression;
+use crate::config::{self, ConfigError};
+use crate::cookie::{self, CookieOptions};
+use crate::deadline::Watch;
+use crate::dump::{Dump, DumpSettings};
+use crate::eventstream::EventStream;
+use crate::filecache::{self, FileCache};
+use crate::headers::Headers;
+#[cfg(feature = "http2")]
+use crate::http2;
+use crate::httpdate;
+use crate::listenaddr::ListenAddr;
+use crate::listing;
+use crate::logger::{Level, Logger};
+use crate::mediatype::MediaType;
+use crate::metrics::Metrics;
+use crate::mime;
+use crate::multipart::{self, Multipart, SaveOptions, SavedFile};
+use crate::panics;
+use crate::percent;
+use crate::proxy::{ProxyError, Upstream};
+use crate::proxyprotocol;
+use crate::range::{self, ByteRange, RangeRequest};
+use crate::ratelimit::RateLimiter;
+use crate::reader::{self, HeaderLimits, ReadError, RequestTimeouts};
+use crate::router::{self, Router};
+use crate::session::{self, Session, SessionStore};
+use crate::shutdown::ShutdownHandle;
+use crate::statcache::{self, FileStat, StatCache};
+use crate::stream::KeptOpen;
+use crate::stream::MemoryStream;
+use crate::stream::Stream;
+use crate::template::Templates;
+use crate::testclient::{CapturedResponse, RequestBuilder, TestRequest};
 use crate::threadpool::ThreadPool;
+use crate::timing::{PhaseTimer, PhaseTimings, WriteClock};
+#[cfg(feature = "tls")]
+use crate::tls::ClientCertificate;
+#[cfg(all(test, feature = "tls"))]
+use crate::tls::TlsConfigBuilder;
+#[cfg(any(feature = "tls", feature = "native-tls"))]
+use crate::tls::{Accepted, TlsConfig};
+use crate::transfer::{self, SendError};
+use std::any::{Any, TypeId};
+use std::borrow::Cow;
+use std::cell::Cell;
 use std::collections::HashMap;
-use std::fs;
+use std::fmt;
 use std::fs::File;
+use std::io;
 use std::io::prelude::*;
-use std::net::TcpListener;
-use std::net::TcpStream;
+use std::io::ErrorKind;
+use std::net::Shutdown;
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
 use std::ops::Deref;
+use std::panic::Location;
 use std::path::Path;
 use std::path::PathBuf;
 use std::str;
-use std::sync::{Arc, Mutex};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+#[cfg(not(feature = "tokio"))]
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// The id of the next accepted connection, see `Request::connection_id`
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(1);
+/// The id of the next request, see `Request::request_id`
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Returns an id for a newly accepted connection, counting up from 1
+pub(crate) fn next_connection_id() -> u64 {
+    NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed)
+}
 
 /// Represents the data which was sent by the caller
 pub struct Request {
+    method: String,
+    original_method: String,
+    path: String,
+    original_path: String,
+    mount_prefix: String,
+    no_match: Option<NoMatch>,
+    raw_query: Option<String>,
+    sni_hostname: Option<String>,
+    #[cfg(feature = "tls")]
+    client_certificate: Option<ClientCertificate>,
+    peer_addr: Option<SocketAddr>,
+    scheme: &'static str,
+    host: Option<String>,
+    headers: Headers,
+    cookies: Vec<(String, String)>,
     post_parameters: HashMap<String, String>,
+    /// The POST parameters in the order they were sent, duplicates included
+    post_pairs: Vec<(String, String)>,
     query_parameters: HashMap<String, String>,
+    /// The query parameters in the order they were sent, duplicates included
+    query_pairs: Vec<(String, String)>,
+    path_parameters: HashMap<String, String>,
+    /// The request line and header lines as received
+    raw_head: String,
+    raw_body: Vec<u8>,
+    /// The rest of a body still arriving on the connection
+    incoming_body: Option<IncomingBody>,
+    /// Set once the buffered body was looked at, see `body_reader`
+    buffered_body_used: AtomicBool,
+    /// Set once `body_reader` handed out the body
+    body_reader_taken: bool,
+    /// The protocol from the request line, e.g. `HTTP/1.1`
+    version: String,
+    /// Whether the connection stays open after the response
+    keep_alive: bool,
+    /// How many more requests the connection takes after this one
+    remaining_requests: usize,
+    connection_id: u64,
+    request_id: u64,
+    /// Headers middlewares set before passing the request on
+    middleware_headers: Headers,
+    session: Option<Session>,
+    /// The user who passed `Server::protect`
+    auth_user: Option<String>,
+    /// Values hooks and middlewares attached, see `set_extension`
+    extensions: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+    /// When the request was read, which `Server::set_server_timing` and
+    /// `Server::after` measure from
+    received: Instant,
+    /// The route names of the server that read the request
+    route_names: Option<RouteNames>,
+    /// The state of the server that read the request
+    state: Option<StateMap>,
+    /// Set once the `Accept` header was looked at, which the response then
+    /// varies by
+    accept_used: Arc<AtomicBool>,
 }
 
 impl Request {
+    /// Returns a builder for requests to pass to handler functions in unit
+    /// tests, see `RequestBuilder`
+    pub fn builder() -> RequestBuilder {
+        RequestBuilder::new()
+    }
+    /// Creates a request like one read from a plain HTTP/1.1 connection
+    pub(crate) fn from_parts(
+        method: &str,
+        path: &str,
+        query: Option<&str>,
+        headers: Headers,
+        body: Vec<u8>,
+        path_parameters: HashMap<String, String>,
+    ) -> Self {
+        let target = match query {
+            Some(query) => format!("{}?{}", path, query),
+            None => String::from(path),
+        };
+        let mut raw_head = format!("{} {} HTTP/1.1", method, target);
+        for (name, value) in headers.iter() {
+            raw_head.push_str(&format!("\r\n{}: {}", name, value));
+        }
+        let mut request = Request::new();
+        request.method = String::from(method);
+        request.original_method = String::from(method);
+        request.path = String::from(path);
+        request.original_path = String::from(path);
+        request.version = String::from("HTTP/1.1");
+        request.host = headers.get("Host").map(String::from);
+        request.cookies = cookie::parse(headers.get_all("Cookie"));
+        request.headers = headers;
+        if request.is_urlencoded() {
+            request.post_pairs = Server::parse_parameters(str::from_utf8(&body).ok().as_ref());
+            request.post_parameters = first_values(&request.post_pairs);
+        }
+        request.query_pairs = Server::parse_parameters(query.as_ref());
+        request.query_parameters = first_values(&request.query_pairs);
+        request.raw_query = query.map(String::from);
+        request.path_parameters = path_parameters;
+        request.raw_head = raw_head;
+        request.raw_body = body;
+        request
+    }
     fn new() -> Self {
         Request {
+            method: String::new(),
+            original_method: String::new(),
+            path: String::new(),
+            original_path: String::new(),
+            mount_prefix: String::new(),
+            no_match: None,
+            raw_query: None,
+            sni_hostname: None,
+            #[cfg(feature = "tls")]
+            client_certificate: None,
+            peer_addr: None,
+            scheme: "http",
+            host: None,
+            headers: Headers::new(),
+            cookies: Vec::new(),
             post_parameters: HashMap::new(),
+            post_pairs: Vec::new(),
             query_parameters: HashMap::new(),
+            query_pairs: Vec::new(),
+            path_parameters: HashMap::new(),
+            raw_head: String::new(),
+            raw_body: Vec::new(),
+            incoming_body: None,
+            buffered_body_used: AtomicBool::new(false),
+            body_reader_taken: false,
+            version: String::new(),
+            keep_alive: false,
+            remaining_requests: 0,
+            connection_id: 0,
+            request_id: 0,
+            middleware_headers: Headers::new(),
+            session: None,
+            auth_user: None,
+            extensions: HashMap::new(),
+            received: Instant::now(),
+            route_names: None,
+            state: None,
+            accept_used: Arc::default(),
         }
     }
+    /// Returns a copy to hand to an error handler, without the body that
+    /// is still arriving
+    fn duplicate(&self) -> Request {
+        Request {
+            method: self.method.clone(),
+            original_method: self.original_method.clone(),
+            path: self.path.clone(),
+            original_path: self.original_path.clone(),
+            mount_prefix: self.mount_prefix.clone(),
+            no_match: self.no_match.clone(),
+            raw_query: self.raw_query.clone(),
+            sni_hostname: self.sni_hostname.clone(),
+            #[cfg(feature = "tls")]
+            client_certificate: self.client_certificate.clone(),
+            peer_addr: self.peer_addr,
+            scheme: self.scheme,
+            host: self.host.clone(),
+            headers: self.headers.clone(),
+            cookies: self.cookies.clone(),
+            post_parameters: self.post_parameters.clone(),
+            post_pairs: self.post_pairs.clone(),
+            query_parameters: self.query_parameters.clone(),
+            query_pairs: self.query_pairs.clone(),
+            path_parameters: self.path_parameters.clone(),
+            raw_head: self.raw_head.clone(),
+            raw_body: self.raw_body.clone(),
+            incoming_body: None,
+            buffered_body_used: AtomicBool::new(self.buffered_body_used.load(Ordering::Relaxed)),
+            body_reader_taken: self.body_reader_taken,
+            version: self.version.clone(),
+            keep_alive: self.keep_alive,
+            remaining_requests: self.remaining_requests,
+            connection_id: self.connection_id,
+            request_id: self.request_id,
+            middleware_headers: self.middleware_headers.clone(),
+            session: self.session.clone(),
+            auth_user: self.auth_user.clone(),
+            extensions: self.extensions.clone(),
+            received: self.received,
+            route_names: self.route_names.clone(),
+            state: self.state.clone(),
+            accept_used: self.accept_used.clone(),
+        }
+    }
+    /// Returns the HTTP method the request was dispatched with, e.g. `GET`
+    ///
+    /// This differs from `original_method()` if the method was overridden,
+    /// see `Server::allow_method_override`.
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+    /// Returns the HTTP method from the request line
+    pub fn original_method(&self) -> &str {
+        &self.original_method
+    }
+    /// Returns the value of a request header, the name is case-insensitive
+    ///
+    /// If the header was sent several times, the first value is returned.
+    pub fn get_header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+    /// Returns all request headers as they were sent
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+    /// Returns the media types the client accepts, from the `Accept` header
+    ///
+    /// The response then gets `Accept` in its `Vary` header, as it does
+    /// after `accepts_json`, `accepts_html` and `prefers`.
+    pub fn accepts(&self) -> AcceptList {
+        self.accept_used.store(true, Ordering::Relaxed);
+        AcceptList::parse(self.headers.get_joined("Accept").as_deref())
+    }
+    /// Returns whether the client accepts JSON according to its `Accept`
+    /// header
+    ///
+    /// Ranges like `*/*` count too, and so does a missing header. Use
+    /// `prefers` to choose between JSON and other types.
+    pub fn accepts_json(&self) -> bool {
+        self.accepts().quality("application/json") > 0.0
+    }
+    /// Returns whether the client accepts HTML according to its `Accept`
+    /// header, like `accepts_json`
+    pub fn accepts_html(&self) -> bool {
+        self.accepts().quality("text/html") > 0.0
+    }
+    /// Returns the parsed `Content-Type` header, `None` if there is none or
+    /// it is not of the form `type/subtype`
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.post("/import/", |request, mut response| {
+    ///     let charset = request
+    ///         .content_type()
+    ///         .filter(|content_type| content_type.is("text/csv"))
+    ///         .map(|content_type| content_type.charset().unwrap_or("utf-8").to_string());
+    ///     match charset {
+    ///         Some(charset) => {
+    ///             let _ = response.write(&format!("importing {} CSV", charset));
+    ///         }
+    ///         None => {
+    ///             let _ = response.set_status_code(415);
+    ///         }
+    ///     }
+    /// });
+    /// ```
+    pub fn content_type(&self) -> Option<MediaType> {
+        MediaType::parse(self.get_header("Content-Type")?)
+    }
+    /// Returns whether the body is `application/x-www-form-urlencoded`, the
+    /// only kind the POST parameters are parsed from
+    fn is_urlencoded(&self) -> bool {
+        self.content_type()
+            .is_some_and(|content_type| content_type.is("application/x-www-form-urlencoded"))
+    }
+    /// Returns whether the body is `application/json`
+    fn is_json(&self) -> bool {
+        self.content_type()
+            .is_some_and(|content_type| content_type.is("application/json"))
+    }
+    /// Returns whether the body is a submitted HTML form, i.e.
+    /// `application/x-www-form-urlencoded` or `multipart/form-data`
+    pub fn is_form(&self) -> bool {
+        self.content_type().is_some_and(|content_type| {
+            content_type.is("application/x-www-form-urlencoded")
+                || content_type.is("multipart/form-data")
+        })
+    }
+    /// Returns whether the request has `X-Requested-With: XMLHttpRequest`,
+    /// which jQuery and similar libraries send with their requests
+    pub fn is_ajax(&self) -> bool {
+        self.get_header("X-Requested-With")
+            .is_some_and(|value| value.trim().eq_ignore_ascii_case("XMLHttpRequest"))
+    }
+    /// Returns the entry of `offered` the client wants most according to
+    /// its `Accept` header, or `None` if it accepts none of them
+    ///
+    /// More specific media ranges override less specific ones, then the
+    /// q-value decides, then the order of `offered`. Without an `Accept`
+    /// header the first entry is returned. The response gets `Accept` in
+    /// its `Vary` header.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.get("/users/", |request, mut response| {
+    ///     match request.prefers(&["text/html", "application/json"]) {
+    ///         Some("application/json") => {
+    ///             let _ = response.write("[]");
+    ///         }
+    ///         _ => {
+    ///             let _ = response.write("<ul></ul>");
+    ///         }
+    ///     }
+    /// });
+    /// ```
+    pub fn prefers<'a>(&self, offered: &[&'a str]) -> Option<&'a str> {
+        self.accepts().preferred(offered)
+    }
+    /// Returns the value of the cookie `name`, the first one if the client
+    /// sent it several times
+    ///
+    /// Values are returned as they were sent apart from surrounding quotes,
+    /// so URL-encoded values stay encoded.
+    pub fn get_cookie(&self, name: &str) -> Option<&str> {
+        self.cookies
+            .iter()
+            .find(|(cookie, _)| cookie == name)
+            .map(|(_, value)| value.as_str())
+    }
+    /// Returns all cookies in the order they were sent, from all `Cookie`
+    /// headers
+    pub fn get_cookies(&self) -> &[(String, String)] {
+        &self.cookies
+    }
+    /// Returns the path the request was routed by, without the query string
+    ///
+    /// This is the path the client sent, percent-decoded, unless
+    /// `Server::rewrite` changed it.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+    /// Returns the requested path without the query string, exactly as the
+    /// client sent it, before any rewrite
+    pub fn original_path(&self) -> &str {
+        &self.original_path
+    }
+    /// Returns the HTTP version of the request, like `HTTP/1.1`
+    pub fn http_version(&self) -> &str {
+        &self.version
+    }
+    /// Returns the prefix the handling route was mounted under with
+    /// `Server::mount`, or an empty string
+    ///
+    /// `path()` always contains the full path including this prefix.
+    pub fn mount_prefix(&self) -> &str {
+        &self.mount_prefix
+    }
+    /// Returns the session of the client, `None` unless
+    /// `Server::enable_sessions` is on
+    ///
+    /// Only requests a route handles have a session.
+    pub fn session(&self) -> Option<&Session> {
+        self.session.as_ref()
+    }
+    /// Returns the user name the client authenticated with, `None` unless
+    /// the path is protected with `Server::protect`
+    pub fn auth_user(&self) -> Option<&str> {
+        self.auth_user.as_deref()
+    }
+    /// Attaches `value` to the request, replacing a value of the same type,
+    /// e.g. for a `Server::before` hook to pass something on to the handler
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// struct Tenant(String);
+    /// let mut s = Server::new();
+    /// s.before(|request| {
+    ///     let tenant = request.get_header("X-Tenant").unwrap_or("default").to_string();
+    ///     request.set_extension(Tenant(tenant));
+    /// });
+    /// s.get("/", |request, mut response| {
+    ///     if let Some(Tenant(tenant)) = request.get_extension::<Tenant>() {
+    ///         let _ = response.write(tenant);
+    ///     }
+    /// });
+    /// ```
+    pub fn set_extension<T: Any + Send + Sync>(&mut self, value: T) {
+        self.extensions.insert(TypeId::of::<T>(), Arc::new(value));
+    }
+    /// Returns the value of type `T` attached with `set_extension`
+    pub fn get_extension<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self.extensions.get(&TypeId::of::<T>())?.downcast_ref()
+    }
+    /// Returns the value of type `T` added with `Server::manage`, `None` if
+    /// there is none
+    ///
+    /// Returns `None` for requests that were not read by a server, e.g.
+    /// those of `Request::builder`.
+    pub fn state<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+        let value = self.state.as_ref()?.get(&TypeId::of::<T>())?;
+        value.clone().downcast().ok()
+    }
+    /// Returns the path of a named route of the server, see
+    /// `Server::url_for`
+    ///
+    /// Returns `None` for requests that were not read by a server, e.g.
+    /// those of `Request::builder`.
+    pub fn url_for(&self, name: &str, params: &[(&str, &str)]) -> Option<String> {
+        url_for(self.route_names.as_ref()?, name, params)
+    }
+    /// Returns why no route handled this request
+    ///
+    /// Only set for requests passed to the handler registered with
+    /// `Server::not_found`.
+    pub fn no_match(&self) -> Option<&NoMatch> {
+        self.no_match.as_ref()
+    }
     /// Returns POST parameters of this request
-    pub fn get_post_parameters(&self) -> HashMap<String, String> {
-        self.post_parameters.clone()
+    ///
+    /// They are parsed from `application/x-www-form-urlencoded` bodies only,
+    /// for other bodies the map is empty. Of a parameter sent more than once
+    /// the map holds the first value, `get_post_parameters_all()` has all of
+    /// them and `form_pairs()` has them in order.
+    pub fn get_post_parameters(&self) -> &HashMap<String, String> {
+        self.use_buffered_body();
+        &self.post_parameters
+    }
+    /// Returns all values of every POST parameter, in the order they were
+    /// sent
+    ///
+    /// Names are kept as sent, so the values of checkboxes named `tags[]`
+    /// are found under `tags[]`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.post("/filter/", |request, mut response| {
+    ///     let parameters = request.get_post_parameters_all();
+    ///     let tags = parameters.get("tags[]").map_or(&[][..], |tags| &tags[..]);
+    ///     let _ = response.write(&tags.join(", "));
+    /// });
+    /// ```
+    pub fn get_post_parameters_all(&self) -> HashMap<String, Vec<String>> {
+        self.use_buffered_body();
+        all_values(&self.post_pairs)
+    }
+    /// Returns the POST parameters in the order they were sent, with every
+    /// occurrence of a repeated parameter at its position
+    pub fn form_pairs(&self) -> &[(String, String)] {
+        self.use_buffered_body();
+        &self.post_pairs
+    }
+    /// Returns a single POST parameter, the first value if it was sent more
+    /// than once
+    pub fn form(&self, name: &str) -> Option<&str> {
+        self.use_buffered_body();
+        self.post_parameters.get(name).map(|v| v.as_str())
+    }
+    /// Returns a single POST parameter, the same as `form()`
+    pub fn form_param(&self, name: &str) -> Option<&str> {
+        self.form(name)
+    }
+    /// Returns a single POST parameter, or `default` if it was not sent
+    pub fn form_param_or<'a>(&'a self, name: &str, default: &'a str) -> &'a str {
+        self.form(name).unwrap_or(default)
+    }
+    /// Returns a single POST parameter parsed into `T`, `None` if it was not
+    /// sent
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.post("/cart/", |request, mut response| {
+    ///     match request.form_param_parse::<u32>("quantity") {
+    ///         Ok(quantity) => {
+    ///             let _ = response.write(&format!("{} items", quantity.unwrap_or(1)));
+    ///         }
+    ///         Err(e) => {
+    ///             let _ = response.set_status_code(400);
+    ///             let _ = response.write(&e.to_string());
+    ///         }
+    ///     }
+    /// });
+    /// ```
+    pub fn form_param_parse<T: FromStr>(&self, name: &str) -> Result<Option<T>, ParamError> {
+        parse_param(name, self.form(name))
     }
     /// Returns GET (query) parameters of this request
     ///
@@ -38,465 +581,16158 @@ impl Request {
     ///  "hallo" : "3"
     /// }
     /// ```
-    pub fn get_query_parameters(&self) -> HashMap<String, String> {
-        self.query_parameters.clone()
+    ///
+    /// Of a parameter sent more than once the map holds the first value,
+    /// `get_query_parameters_all()` has all of them and `query_pairs()` has
+    /// them in order.
+    pub fn get_query_parameters(&self) -> &HashMap<String, String> {
+        &self.query_parameters
     }
-}
-
-/// Allows you to send data back to the client
-pub struct Response {
-    stream: TcpStream,
-}
-
-impl Response {
-    fn new(stream: TcpStream) -> Self {
-        Response { stream }
+    /// Returns all values of every GET (query) parameter, in the order they
+    /// were sent
+    ///
+    /// http://localhost:7878/`?tag=rust&tag=web&page=2` will return
+    /// `{"tag": ["rust", "web"], "page": ["2"]}`.
+    pub fn get_query_parameters_all(&self) -> HashMap<String, Vec<String>> {
+        all_values(&self.query_pairs)
     }
-    /// Write data into the response. Will be flushed no later than on drop.
-    pub fn write(&mut self, data: &str) -> std::io::Result<()> {
-        self.stream.write_all(data.as_bytes())
+    /// Returns the GET (query) parameters in the order they were sent, with
+    /// every occurrence of a repeated parameter at its position
+    ///
+    /// http://localhost:7878/`?b=1&a=2&b=3` will return
+    /// `[("b", "1"), ("a", "2"), ("b", "3")]`.
+    pub fn query_pairs(&self) -> &[(String, String)] {
+        &self.query_pairs
     }
-    /// Set the status code of the response
-    pub fn set_status_code(&mut self, code: u32) -> std::io::Result<()> {
-        let response = format!("HTTP/1.1 {} OK\r\n\r\n", code);
-        self.stream.write_all(response.as_bytes())
+    /// Returns a single GET (query) parameter, the first value if it was sent
+    /// more than once
+    ///
+    /// http://localhost:7878/`?test=123` will return `Some("123")` for `test`.
+    pub fn query(&self, name: &str) -> Option<&str> {
+        self.query_parameters.get(name).map(|v| v.as_str())
     }
-}
-
-impl Drop for Response {
-    fn drop(&mut self) {
-        let _ = self.stream.flush();
+    /// Returns a single GET (query) parameter, the same as `query()`
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        self.query(name)
     }
-}
-
-type Callback = Box<dyn Fn(Request, Response) + Send + Sync>;
-
-/// Represents the web-framemorks server. The most important struct.
-pub struct Server {
-    document_root: Option<PathBuf>,
-    logger: Option<Logger>,
-    index_of: bool,
-    registered_endpoints: Arc<Mutex<HashMap<(String, String), Callback>>>,
-}
-
-impl Server {
-    /// Returns a Server instance
+    /// Returns a single GET (query) parameter, or `default` if it was not
+    /// sent
     ///
     /// # Example
     ///
     /// ```
     /// use corrodedweb::Server;
-    /// let c = Server::new();
+    /// let mut s = Server::new();
+    /// s.get("/posts/", |request, mut response| {
+    ///     let sort = request.query_param_or("sort", "newest");
+    ///     let _ = response.write(&format!("Sorted by {}", sort));
+    /// });
     /// ```
-    pub fn new() -> Self {
-        Default::default()
+    pub fn query_param_or<'a>(&'a self, name: &str, default: &'a str) -> &'a str {
+        self.query(name).unwrap_or(default)
     }
-
-    /// Sets the document root after it is tested by test_document_root()
-    /// function and returns true if successfull
-    ///
-    /// # Arguments
+    /// Returns a single GET (query) parameter parsed into `T`, `None` if it
+    /// was not sent
     ///
-    /// * `document_root` - A string slice that holds the absolute or relative
-    /// path to the document root
+    /// The error names the parameter and the value, so it can be shown to
+    /// the client as is.
     ///
     /// # Example
     ///
     /// ```
     /// use corrodedweb::Server;
     /// let mut s = Server::new();
-    /// s.set_document_root("/path/to/document/root");
-    /// s.set_document_root("../path/to/document/root");
+    /// s.get("/posts/", |request, mut response| {
+    ///     // `/posts/?page=abc` is answered with 400
+    ///     let page = match request.query_param_parse::<u32>("page") {
+    ///         Ok(page) => page.unwrap_or(1),
+    ///         Err(e) => {
+    ///             let _ = response.set_status_code(400);
+    ///             let _ = response.write(&e.to_string());
+    ///             return;
+    ///         }
+    ///     };
+    ///     let _ = response.write(&format!("Page {}", page));
+    /// });
     /// ```
-    pub fn set_document_root(&mut self, document_root: &str) -> bool {
-        match self.test_document_root(document_root) {
-            Some(root) => {
-                if let Some(path) = root.to_str() {
-                    Logger::info(
-                        &self.logger,
-                        &format!("New document_root was set to {}", path),
-                    );
-                } else {
-                    Logger::warning(
-                        &self.logger,
-                        "New document_root is not UTF-8 valid",
-                    );
-                }
-                self.document_root = Some(root);
-                true
-            }
-            None => false,
-        }
+    pub fn query_param_parse<T: FromStr>(&self, name: &str) -> Result<Option<T>, ParamError> {
+        parse_param(name, self.query(name))
     }
-
-    /// Returns the Option containing a valid path as PathBuf or None if invalid
+    /// Returns the query string exactly as it was sent, without the leading
+    /// `?` and without a fragment
+    ///
+    /// Unlike `get_query_parameters()` this keeps the order, duplicates and
+    /// encoding of the parameters, e.g. for verifying signed URLs. Returns
+    /// `None` if the URL contains no `?`.
+    pub fn raw_query(&self) -> Option<&str> {
+        self.raw_query.as_deref()
+    }
+    /// Returns the request line and the header lines exactly as the client
+    /// sent them, separated by `\r\n` and without the empty line ending the
+    /// head
+    ///
+    /// Unlike `headers()` nothing is normalized, e.g. for logging odd
+    /// clients or checking signatures over the original bytes.
+    pub fn raw_head(&self) -> &str {
+        &self.raw_head
+    }
+    /// Returns the body exactly as the client sent it
+    ///
+    /// This is the part of the body read together with the head, which the
+    /// POST parameters are parsed from as well. URL-encoded forms and JSON
+    /// are read completely before the handler runs, so for them it is the
+    /// whole body.
     ///
     /// # Example
     ///
     /// ```
     /// use corrodedweb::Server;
     /// let mut s = Server::new();
-    /// match s.get_document_root() {
-    ///     Some(path) => println!("Path is set to {:?}", path),
-    ///     None => println!("No path is set!"),
-    /// };
+    /// s.post("/webhook/", |request, mut response| {
+    ///     let signature = request.get_header("X-Signature").unwrap_or("");
+    ///     // Check `signature` against `request.raw_body()` here
+    ///     let _ = response.write(&format!("{} bytes", request.raw_body().len()));
+    /// });
     /// ```
-    pub fn get_document_root(&self) -> Option<PathBuf> {
-        self.document_root.clone()
-    }
-
-    /// Sets whether to show a list of files, when navigating to a folder
-    pub fn use_index_of(&mut self, index_of: bool) {
-        self.index_of = index_of;
-    }
-
-    /// Tests whether document root is valid an return an Option
-    fn test_document_root(&mut self, document_root: &str) -> Option<PathBuf> {
-        let mut path_to_root = PathBuf::new();
-        path_to_root.push(document_root);
-        if path_to_root.exists() {
-            Some(path_to_root)
-        } else {
-            Logger::warning(
-                &self.logger,
-                &format!("document_root {} is not valid", document_root),
-            );
-            None
-        }
+    pub fn raw_body(&self) -> &[u8] {
+        self.use_buffered_body();
+        &self.raw_body
     }
-
-    /// Sets a logger for the server instance with specific log path
+    /// Returns the body as text, see `raw_body`, failing if it is not UTF-8
     ///
-    /// # Arguments
+    /// # Example
     ///
-    /// * `log_path` - Path to log file
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.post("/echo/", |request, mut response| match request.body_string() {
+    ///     Ok(text) => {
+    ///         let _ = response.write(text);
+    ///     }
+    ///     Err(_) => {
+    ///         let _ = response.set_status_code(400);
+    ///     }
+    /// });
+    /// ```
+    pub fn body_string(&self) -> Result<&str, str::Utf8Error> {
+        str::from_utf8(self.raw_body())
+    }
+    /// Parses the body as JSON into `T`, see `raw_body`
+    ///
+    /// The `Content-Type` is not checked, any body that parses will do.
     ///
     /// # Example
     ///
     /// ```
+    /// use serde::Deserialize;
+    ///
+    /// #[derive(Deserialize)]
+    /// struct NewUser {
+    ///     name: String,
+    /// }
+    ///
     /// let mut s = corrodedweb::Server::new();
-    /// s.set_logger("./file.log")
+    /// s.post("/users/", |request, mut response| match request.json::<NewUser>() {
+    ///     Ok(user) => {
+    ///         let _ = response.write(&format!("Hello {}", user.name));
+    ///     }
+    ///     Err(e) => {
+    ///         let _ = response.set_status_code(400);
+    ///         let _ = response.write(&e.to_string());
+    ///     }
+    /// });
     /// ```
-    pub fn set_logger(&mut self, log_path: &str) {
-        self.logger = Some(Logger::new(log_path));
+    #[cfg(feature = "json")]
+    pub fn json<T: serde::de::DeserializeOwned>(&self) -> serde_json::Result<T> {
+        serde_json::from_slice(self.raw_body())
     }
-
-    /// Registers for a GET-request
+    /// Returns a reader for the body as it arrives, for uploads too large to
+    /// hold in memory
     ///
+    /// `raw_body()` and the POST parameters only cover the part of the body
+    /// that arrived together with the head, at most 8 KiB, apart from
+    /// URL-encoded forms, which are read completely. The reader hands
+    /// out all of it, decoded from its `Content-Length` or chunked framing,
+    /// and reads from the connection only as fast as it is read.
     ///
-    /// # Arguments
+    /// The reader and the buffered body exclude each other: this fails if
+    /// `raw_body()`, `get_post_parameters()` or one of the `form` methods was
+    /// called before, or if the reader was taken already. Once it was taken,
+    /// those return nothing.
     ///
-    /// * `route` - The endpoint you will register to.
-    /// * `f` - The callback closure which will be executed on request.
+    /// A handler does not need to read the body to its end. On connections
+    /// kept open by the `tokio` feature, up to 1 MiB of what it left is read
+    /// and dropped, so the next request can follow, longer rests close the
+    /// connection after the response.
     ///
     /// # Example
     ///
     /// ```
     /// use corrodedweb::Server;
+    /// use std::fs::File;
+    /// use std::io;
     /// let mut s = Server::new();
-    /// s.get("/", |request, mut response| {
-    ///
+    /// s.post("/upload/", |mut request, mut response| {
+    ///     let stored = request.body_reader().and_then(|mut body| {
+    ///         io::copy(&mut body, &mut File::create("/tmp/upload")?)
+    ///     });
+    ///     match stored {
+    ///         Ok(len) => {
+    ///             let _ = response.write(&format!("stored {} bytes", len));
+    ///         }
+    ///         Err(e) => {
+    ///             let _ = response.set_status_code(400);
+    ///             let _ = response.write(&e.to_string());
+    ///         }
+    ///     }
     /// });
     /// ```
-    pub fn get<F: Send + Sync + 'static>(&mut self, route: &str, f: F)
-    where
-        F: Fn(Request, Response),
-    {
-        self.registered_endpoints
-            .lock()
-            .unwrap()
-            .insert((String::from(route), String::from("GET")), Box::new(f));
-        Logger::info(
-            &self.logger,
-            &format!("Registered route: {}, method: {}", route, "GET"),
-        );
+    pub fn body_reader(&mut self) -> io::Result<BodyReader> {
+        if self.body_reader_taken {
+            return Err(io::Error::other("the body reader was taken already"));
+        }
+        if self.buffered_body_used.load(Ordering::Relaxed) {
+            return Err(io::Error::other(
+                "the buffered body was used, it cannot be read as a stream as well",
+            ));
+        }
+        self.body_reader_taken = true;
+        self.post_pairs.clear();
+        self.post_parameters.clear();
+        let buffered = std::mem::take(&mut self.raw_body);
+        Ok(match &self.incoming_body {
+            Some(incoming) => incoming.reader(),
+            None => BodyReader::buffered(buffered),
+        })
     }
-
-    /// Registers for a POST-request
+    /// Returns the parts of a `multipart/form-data` body, read as they arrive
     ///
+    /// Fails like `body_reader`, or if the request has another content type.
     ///
-    /// # Arguments
+    /// # Example
     ///
-    /// * `route` - The endpoint you will register to.
-    /// * `f` - The callback closure which will be executed on request.
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.post("/form/", |mut request, mut response| {
+    ///     let mut fields = Vec::new();
+    ///     if let Ok(mut multipart) = request.multipart() {
+    ///         while let Ok(Some(part)) = multipart.next_part() {
+    ///             fields.push(String::from(part.name()));
+    ///         }
+    ///     }
+    ///     let _ = response.write(&fields.join(", "));
+    /// });
+    /// ```
+    pub fn multipart(&mut self) -> io::Result<Multipart> {
+        let content_type = self.content_type();
+        let boundary = content_type
+            .as_ref()
+            .and_then(multipart::boundary)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "the body is not multipart/form-data with a boundary",
+                )
+            })?;
+        Ok(Multipart::new(self.body_reader()?, boundary))
+    }
+    /// Saves every uploaded file of a `multipart/form-data` body to `dir`
+    /// with the default `SaveOptions`
+    ///
+    /// See `save_files_with`.
+    pub fn save_files<P: AsRef<Path>>(&mut self, dir: P) -> io::Result<Vec<SavedFile>> {
+        self.save_files_with(dir, &SaveOptions::default())
+    }
+    /// Saves every uploaded file of a `multipart/form-data` body to `dir`
+    ///
+    /// Each file gets a name of its own as described for
+    /// `UploadedFile::save_with`, the name the client sent is only kept in
+    /// `SavedFile::original_filename`. Parts without a filename are plain
+    /// form fields and skipped. If one file fails to save, the ones saved
+    /// before are removed again.
     ///
     /// # Example
     ///
     /// ```
     /// use corrodedweb::Server;
     /// let mut s = Server::new();
-    /// s.post("/", |request, mut response| {
-    ///
+    /// s.post("/upload/", |mut request, mut response| {
+    ///     match request.save_files("/tmp") {
+    ///         Ok(files) => {
+    ///             let _ = response.write(&format!("{} files saved", files.len()));
+    ///         }
+    ///         Err(_) => {
+    ///             let _ = response.set_status_code(400);
+    ///         }
+    ///     }
     /// });
     /// ```
-    pub fn post<F: Send + Sync + 'static>(&mut self, route: &str, f: F)
-    where
-        F: Fn(Request, Response),
-    {
-        self.registered_endpoints
-            .lock()
-            .unwrap()
-            .insert((String::from(route), String::from("POST")), Box::new(f));
-        Logger::info(
-            &self.logger,
-            &format!("Registered route: {}, method: {}", route, "POST"),
-        );
+    pub fn save_files_with<P: AsRef<Path>>(
+        &mut self,
+        dir: P,
+        options: &SaveOptions,
+    ) -> io::Result<Vec<SavedFile>> {
+        multipart::save_files(&mut self.multipart()?, dir.as_ref(), options)
     }
-
-    /// Starts serving your files or listening for your registered enpoints.
+    /// Remembers that the buffered body was looked at, see `body_reader`
+    fn use_buffered_body(&self) {
+        self.buffered_body_used.store(true, Ordering::Relaxed);
+    }
+    /// Returns the hostname the client asked for during the TLS handshake
     ///
-    /// # Arguments
+    /// `None` for plain HTTP and for clients that did not send one.
+    pub fn sni_hostname(&self) -> Option<&str> {
+        self.sni_hostname.as_deref()
+    }
+    /// Returns the certificate the client authenticated with during the TLS
+    /// handshake
+    ///
+    /// `None` unless client certificates are enabled with
+    /// `TlsConfigBuilder::require_client_cert` or `request_client_cert` and
+    /// the client presented one.
+    #[cfg(feature = "tls")]
+    pub fn client_certificate(&self) -> Option<&ClientCertificate> {
+        self.client_certificate.as_ref()
+    }
+    /// Returns the address of the client
+    ///
+    /// With `Server::enable_proxy_protocol` this is the address the proxy
+    /// reports instead of the proxy's own. `None` if it is unknown, e.g. for
+    /// a proxy's health checks.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+    /// Returns the address of the client like `peer_addr`, or the
+    /// unspecified address `0.0.0.0:0` if it is unknown
     ///
-    /// * `port` - The port the server will listen on
+    /// That is the case for requests built with `Request::builder` or sent
+    /// with `Server::test_request`, which come from no socket, and for those
+    /// `peer_addr` returns `None` for.
+    pub fn remote_addr(&self) -> SocketAddr {
+        self.peer_addr
+            .unwrap_or_else(|| SocketAddr::from(([0, 0, 0, 0], 0)))
+    }
+    /// Returns the id of the connection the request came in on
+    ///
+    /// Connections are numbered from 1 in the order they are accepted, so
+    /// requests on a kept-alive connection or an HTTP/2 connection share
+    /// the id. It is 0 for requests built with `Request::builder`.
+    ///
+    /// # Example
     ///
     /// ```
     /// use corrodedweb::Server;
     /// let mut s = Server::new();
-    /// // Blocks, so it is commented out
-    /// // s.start_server(7878);
+    /// s.get("/", |request, mut response| {
+    ///     let _ = response.write(&format!(
+    ///         "request {} on connection {}",
+    ///         request.request_id(),
+    ///         request.connection_id()
+    ///     ));
+    /// });
     /// ```
-    pub fn start_server(&self, port: u32) {
-        if let Ok(listener) = TcpListener::bind(&format!("127.0.0.1:{}", port)) {
-            Logger::info(
+    pub fn connection_id(&self) -> u64 {
+        self.connection_id
+    }
+    /// Returns how many more requests the connection takes after this one,
+    /// 0 if it closes after the response
+    ///
+    /// The budget comes from `Server::set_keep_alive`.
+    pub fn remaining_requests(&self) -> usize {
+        self.remaining_requests
+    }
+    /// Returns the id of the request, numbered from 1 across all
+    /// connections, see `connection_id()`
+    pub fn request_id(&self) -> u64 {
+        self.request_id
+    }
+    /// Returns the IP address of the client, see `peer_addr()`
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        self.peer_addr.map(|addr| addr.ip())
+    }
+    /// Returns the scheme the client used, `http` or `https`
+    ///
+    /// Behind a proxy trusted with `Server::trust_proxy` this is taken from
+    /// the `X-Forwarded-Proto` header.
+    pub fn scheme(&self) -> &str {
+        self.scheme
+    }
+    /// Returns the host the client asked for, including the port if it is
+    /// not the default one of the scheme
+    ///
+    /// Behind a proxy trusted with `Server::trust_proxy` this is taken from
+    /// the `X-Forwarded-Host` and `X-Forwarded-Port` headers. `None` if the
+    /// client did not send a host.
+    pub fn host(&self) -> Option<&str> {
+        self.host.as_deref()
+    }
+    /// Turns a path on this server into an absolute URL the client can use,
+    /// e.g. for redirects or links in emails
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// let mut s = corrodedweb::Server::new();
+    /// s.get("/logout/", |request, mut response| {
+    ///     if let Some(url) = request.absolute_url("/login/") {
+    ///         let _ = response.see_other(&url);
+    ///     }
+    /// });
+    /// ```
+    pub fn absolute_url(&self, path: &str) -> Option<String> {
+        let host = self.host.as_ref()?;
+        Some(format!("{}://{}{}", self.scheme, host, path))
+    }
+    /// Returns the absolute URL of the request, the path as the client sent
+    /// it followed by the query string, `None` if the client did not send a
+    /// host
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.get("/", |request, mut response| {
+    ///     let _ = response.write(&format!(
+    ///         "{} {:?} {}",
+    ///         request.method(),
+    ///         request.full_url(),
+    ///         request.http_version()
+    ///     ));
+    /// });
+    /// ```
+    pub fn full_url(&self) -> Option<String> {
+        match &self.raw_query {
+            Some(query) => self.absolute_url(&format!("{}?{}", self.original_path, query)),
+            None => self.absolute_url(&self.original_path),
+        }
+    }
+    /// Returns the parameters captured by the matched route
+    ///
+    /// A request to `/users/42/` matched by the route `/users/:id/` will
+    /// return `{"id": "42"}`. The rest of the path matched by a trailing `*`
+    /// is stored under the key `*`.
+    pub fn get_path_parameters(&self) -> &HashMap<String, String> {
+        &self.path_parameters
+    }
+    /// Returns a path parameter captured by the matched route
+    pub fn path_param_str(&self, name: &str) -> Option<&str> {
+        self.path_parameters.get(name).map(|v| v.as_str())
+    }
+    /// Returns a path parameter parsed into `T`
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::{ParamError, Server};
+    /// let mut s = Server::new();
+    /// s.get("/users/:id/", |request, mut response| {
+    ///     match request.path_param::<u32>("id") {
+    ///         Ok(id) => {
+    ///             let _ = response.write(&format!("User {}", id));
+    ///         }
+    ///         Err(ParamError::Invalid { .. }) => {
+    ///             let _ = response.set_status_code(400);
+    ///         }
+    ///         Err(ParamError::Missing { .. }) => {
+    ///             let _ = response.set_status_code(404);
+    ///         }
+    ///     }
+    /// });
+    /// ```
+    pub fn path_param<T: FromStr>(&self, name: &str) -> Result<T, ParamError> {
+        let value = self
+            .path_param_str(name)
+            .ok_or_else(|| ParamError::Missing {
+                name: String::from(name),
+            })?;
+        value.parse().map_err(|_| ParamError::Invalid {
+            name: String::from(name),
+            value: String::from(value),
+        })
+    }
+}
+
+/// The request headers whose values are hidden by the `Debug` output of a
+/// request
+const REDACTED_HEADERS: [&str; 3] = ["Authorization", "Proxy-Authorization", "Cookie"];
+
+/// Shows the request with the values of credential headers and cookies
+/// hidden, see `Request::debug_full`
+impl fmt::Debug for Request {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.debug(f, true)
+    }
+}
+
+/// Shows everything of a request, obtained with `Request::debug_full`
+pub struct FullDebug<'a>(&'a Request);
+
+impl fmt::Debug for FullDebug<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.debug(f, false)
+    }
+}
+
+impl Request {
+    /// Returns something to `dbg!` or format with `{:?}` that shows the
+    /// request including credentials, unlike the request itself
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.get("/debug/", |request, _response| {
+    ///     // Authorization and Cookie values are hidden
+    ///     println!("{:?}", request);
+    ///     println!("{:?}", request.debug_full());
+    /// });
+    /// ```
+    pub fn debug_full(&self) -> FullDebug<'_> {
+        FullDebug(self)
+    }
+
+    fn debug(&self, f: &mut fmt::Formatter, redact: bool) -> fmt::Result {
+        // No `..`, so a new field does not compile until it is shown here
+        let Request {
+            method,
+            original_method,
+            path,
+            original_path,
+            mount_prefix,
+            no_match,
+            raw_query,
+            sni_hostname,
+            #[cfg(feature = "tls")]
+            client_certificate,
+            peer_addr,
+            scheme,
+            host,
+            headers,
+            cookies,
+            post_parameters,
+            post_pairs: _,
+            query_parameters,
+            query_pairs: _,
+            path_parameters,
+            raw_head,
+            raw_body,
+            incoming_body: _,
+            buffered_body_used: _,
+            body_reader_taken,
+            version,
+            keep_alive,
+            remaining_requests,
+            connection_id,
+            request_id,
+            middleware_headers: _,
+            session,
+            auth_user,
+            extensions,
+            received: _,
+            route_names: _,
+            state: _,
+            accept_used: _,
+        } = self;
+        let hidden = |name: &str| {
+            redact
+                && REDACTED_HEADERS
+                    .iter()
+                    .any(|redacted| redacted.eq_ignore_ascii_case(name))
+        };
+        let headers: Vec<(&str, &str)> = headers
+            .iter()
+            .map(|(name, value)| (name, if hidden(name) { "<redacted>" } else { value }))
+            .collect();
+        let cookies: Vec<(&str, &str)> = cookies
+            .iter()
+            .map(|(name, value)| (name.as_str(), if redact { "<redacted>" } else { value }))
+            .collect();
+        let mut debug = f.debug_struct("Request");
+        debug
+            .field("method", method)
+            .field("original_method", original_method)
+            .field("path", path)
+            .field("original_path", original_path)
+            .field("mount_prefix", mount_prefix)
+            .field("version", version)
+            .field("scheme", scheme)
+            .field("host", host)
+            .field("peer_addr", peer_addr)
+            .field("keep_alive", keep_alive)
+            .field("remaining_requests", remaining_requests)
+            .field("connection_id", connection_id)
+            .field("request_id", request_id)
+            .field("header_count", &headers.len())
+            .field("headers", &headers)
+            .field("cookies", &cookies)
+            .field("raw_query", raw_query)
+            .field("query_parameters", query_parameters)
+            .field("post_parameters", post_parameters)
+            .field("path_parameters", path_parameters)
+            .field("no_match", no_match)
+            .field("sni_hostname", sni_hostname)
+            .field("session", session)
+            .field("auth_user", auth_user)
+            .field("extension_count", &extensions.len());
+        #[cfg(feature = "tls")]
+        debug.field("client_certificate", client_certificate);
+        if !redact {
+            debug.field("raw_head", raw_head);
+        }
+        debug
+            .field("body_len", &raw_body.len())
+            .field("body_reader_taken", body_reader_taken)
+            .finish()
+    }
+}
+
+/// Parses a parameter that may be missing
+fn parse_param<T: FromStr>(name: &str, value: Option<&str>) -> Result<Option<T>, ParamError> {
+    match value {
+        Some(value) => value.parse().map(Some).map_err(|_| ParamError::Invalid {
+            name: String::from(name),
+            value: String::from(value),
+        }),
+        None => Ok(None),
+    }
+}
+
+/// The error returned when a parameter is missing or can't be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParamError {
+    /// The parameter is not present in the request
+    Missing {
+        /// The name of the parameter
+        name: String,
+    },
+    /// The parameter is present, but could not be parsed
+    Invalid {
+        /// The name of the parameter
+        name: String,
+        /// The value which could not be parsed
+        value: String,
+    },
+}
+
+impl fmt::Display for ParamError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParamError::Missing { name } => write!(f, "parameter {} is missing", name),
+            ParamError::Invalid { name, value } => {
+                write!(f, "parameter {} has the invalid value {:?}", name, value)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParamError {}
+
+/// The error inside the `std::io::Error` returned when a response that
+/// already has its answer is given another one, e.g. a body after a redirect
+///
+/// # Example
+///
+/// ```
+/// use corrodedweb::ResponseAlreadyCompleted;
+/// let mut s = corrodedweb::Server::new();
+/// s.get("/", |_request, mut response| {
+///     let _ = response.see_other("/login/");
+///     let error = response.write("Welcome").unwrap_err();
+///     assert!(error.get_ref().unwrap().is::<ResponseAlreadyCompleted>());
+/// });
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResponseAlreadyCompleted;
+
+impl fmt::Display for ResponseAlreadyCompleted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the response is completed already")
+    }
+}
+
+impl std::error::Error for ResponseAlreadyCompleted {}
+
+/// Describes why no route handled a request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NoMatch {
+    /// No route matches the requested path
+    UnknownPath,
+    /// Routes match the requested path, but not with the request method
+    MethodNotAllowed {
+        /// The methods the path can be requested with
+        allowed: Vec<String>,
+    },
+}
+
+/// The parts of a request, borrowed from the bytes which were read
+struct RequestHead<'a> {
+    /// The request line and the header lines, without the empty line
+    raw: &'a str,
+    method: &'a str,
+    /// The path and query, also for absolute-form targets
+    target: Cow<'a, str>,
+    /// The host of an absolute-form target like `http://example.com/path`
+    authority: Option<&'a str>,
+    /// The protocol, empty for HTTP/0.9 style requests without one
+    version: &'a str,
+    /// The header lines without the request line and the empty line
+    headers: &'a str,
+    body: &'a [u8],
+}
+
+impl<'a> RequestHead<'a> {
+    /// Splits the request at the request line and the empty line ending the
+    /// head without copying. Returns None if the request line is incomplete
+    /// or the head is not valid UTF-8.
+    fn parse(data: &'a [u8]) -> Option<Self> {
+        let (head, body) = match data.windows(4).position(|w| w == b"\r\n\r\n") {
+            Some(end) => (&data[..end], &data[end + 4..]),
+            None => (data, &data[data.len()..]),
+        };
+        let head = str::from_utf8(head).ok()?;
+        let (request_line, headers) = head.split_once("\r\n").unwrap_or((head, ""));
+        let mut parts = request_line.split(' ');
+        let method = parts.next()?;
+        let target = parts.next()?;
+        let version = parts.next().unwrap_or("");
+        let (authority, target) = match split_absolute_form(target) {
+            Some((authority, "")) => (Some(authority), Cow::Borrowed("/")),
+            Some((authority, path)) if !path.starts_with('/') => {
+                (Some(authority), Cow::Owned(format!("/{}", path)))
+            }
+            Some((authority, path)) => (Some(authority), Cow::Borrowed(path)),
+            None => (None, Cow::Borrowed(target)),
+        };
+        Some(RequestHead {
+            raw: head,
+            method,
+            target,
+            authority,
+            version,
+            headers,
+            body,
+        })
+    }
+
+    /// Returns how many lines of the head have the header `name`
+    fn header_count(&self, name: &str) -> usize {
+        self.headers
+            .split("\r\n")
+            .filter_map(|line| line.split_once(':'))
+            .filter(|(header, _)| header.trim().eq_ignore_ascii_case(name))
+            .count()
+    }
+
+    /// Returns why the length of the body cannot be told, which even the
+    /// lenient parser refuses, RFC 9112 section 6.3
+    ///
+    /// Otherwise whatever reads the request after this server, e.g. behind
+    /// the reverse proxy, may end the body elsewhere and take the rest for
+    /// another request.
+    fn framing_violation(&self) -> Option<&'static str> {
+        let mut lengths = self
+            .headers
+            .split("\r\n")
+            .filter_map(|line| line.split_once(':'))
+            .filter(|(name, _)| name.trim().eq_ignore_ascii_case("Content-Length"))
+            .map(|(_, value)| value.trim());
+        let first = lengths.next()?;
+        if lengths.any(|value| value != first) {
+            return Some("Content-Length headers with differing values");
+        }
+        None
+    }
+
+    /// Returns why the head is out of spec in a way the lenient parser
+    /// tolerates, see `Server::set_strict_parsing`
+    fn strict_violation(&self) -> Option<&'static str> {
+        if self.raw.contains('\0') {
+            return Some("NUL in the request head");
+        }
+        let mut content_length = None;
+        for line in self.headers.split("\r\n").filter(|line| !line.is_empty()) {
+            if line.starts_with([' ', '\t']) {
+                return Some("obs-folded header line");
+            }
+            let (name, value) = match line.split_once(':') {
+                Some(split) => split,
+                None => continue,
+            };
+            if name.ends_with([' ', '\t']) {
+                return Some("whitespace between header name and colon");
+            }
+            if !name.is_ascii() {
+                return Some("non-ASCII header name");
+            }
+            if name.eq_ignore_ascii_case("Content-Length") {
+                content_length = Some(value);
+            }
+        }
+        if let Some(violation) = self.framing_violation() {
+            return Some(violation);
+        }
+        if content_length.is_some() && self.header_count("Transfer-Encoding") > 0 {
+            return Some("both Content-Length and Transfer-Encoding");
+        }
+        None
+    }
+}
+
+/// Returns the file `path` names in the document root `root`, if there is
+/// one and the path stays inside the root, see `Server::follow_symlinks`
+fn static_file(root: &Path, path: &str, follow_symlinks: bool) -> Option<PathBuf> {
+    let relative = path.trim_start_matches('/');
+    if !stays_inside(relative) {
+        return None;
+    }
+    let file = root.join(relative);
+    if file.is_file() && is_inside(root, &file, follow_symlinks).unwrap_or(false) {
+        Some(file)
+    } else {
+        None
+    }
+}
+
+/// Returns whether the relative path `relative` names something below the
+/// directory it is joined to, judging by its segments alone
+///
+/// Backslashes count as separators on every platform, so a path rejected
+/// on Windows is rejected everywhere.
+pub(crate) fn stays_inside(relative: &str) -> bool {
+    relative.split(['/', '\\']).all(|segment| segment != "..")
+        && Path::new(relative).components().all(|component| {
+            matches!(
+                component,
+                std::path::Component::Normal(_) | std::path::Component::CurDir
+            )
+        })
+}
+
+/// Returns whether `file` is inside `root` once both are resolved, which
+/// follows symbolic links, or just whether it resolves if
+/// `follow_symlinks`
+fn is_inside(root: &Path, file: &Path, follow_symlinks: bool) -> io::Result<bool> {
+    Ok(resolve_inside(root, file, follow_symlinks)?.is_some())
+}
+
+/// Returns the resolved path of `file` if it is inside `root` or
+/// `follow_symlinks`, see `is_inside`
+///
+/// The system gives up after following a fixed number of links, so a loop
+/// of links fails instead of resolving forever.
+fn resolve_inside(root: &Path, file: &Path, follow_symlinks: bool) -> io::Result<Option<PathBuf>> {
+    let resolved = file.canonicalize()?;
+    let inside = follow_symlinks || resolved.starts_with(root.canonicalize()?);
+    Ok(Some(resolved).filter(|_| inside))
+}
+
+/// A static file being sent, with its contents if the static cache has
+/// them, see `Server::enable_static_cache`
+struct OpenFile {
+    file: File,
+    contents: Option<Arc<[u8]>>,
+}
+
+/// Returns the path of the route named `name`, see `Server::url_for`
+fn url_for(names: &RouteNames, name: &str, params: &[(&str, &str)]) -> Option<String> {
+    let names = names.read().unwrap();
+    router::fill(names.get(name)?, params)
+}
+
+/// Splits an absolute-form request target like `http://example.com/path?a=1`
+/// into the authority and the rest, `None` for targets of other forms
+fn split_absolute_form(target: &str) -> Option<(&str, &str)> {
+    let (scheme, rest) = target.split_once("://")?;
+    if !scheme.eq_ignore_ascii_case("http") && !scheme.eq_ignore_ascii_case("https") {
+        return None;
+    }
+    let end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    Some(rest.split_at(end))
+}
+
+/// Allows you to send data back to the client
+///
+/// Status, headers and body are buffered and sent together, including a
+/// Content-Length header, when the response is dropped.
+///
+/// A redirect completes the response. Anything that would give it another
+/// body or status afterwards fails with `ResponseAlreadyCompleted` instead of
+/// mixing two answers, headers can still be added.
+pub struct Response {
+    stream: Stream,
+    status: Option<u32>,
+    headers: Headers,
+    /// Headers the server adds unless the handler set them
+    defaults: Headers,
+    /// Request headers the response depends on, sent as `Vary`
+    vary: Vec<String>,
+    /// Set once the request's `Accept` header was looked at, which adds it
+    /// to `vary`
+    accept_used: Option<Arc<AtomicBool>>,
+    /// Added to a text `Content-Type` that declares no charset
+    default_charset: Option<String>,
+    /// The media types added with `Server::add_mime_type`, for `send_file`
+    mime_types: Arc<HashMap<String, String>>,
+    /// Set while `Server::set_template_root` is, for `render`
+    templates: Option<Arc<Templates>>,
+    /// Where `render` logs why a template failed
+    logger: Option<Logger>,
+    /// The session the client did not have yet, and whether its cookie is
+    /// only for https, set if the handler keeps it
+    new_session: Option<(Session, bool)>,
+    body: Vec<u8>,
+    head_only: bool,
+    /// Whether the client understands a chunked body, which HTTP/1.0
+    /// clients do not
+    chunked_allowed: bool,
+    /// Set by the first write through `io::Write`, the head cannot change
+    /// afterwards
+    head_committed: bool,
+    /// Set once the head was sent ahead of the body
+    streaming: Option<Streaming>,
+    /// Sends every write of a streamed body right away, see `set_autoflush`
+    autoflush: bool,
+    /// Holds back the streamed body until `uncork` or `flush`
+    corked: bool,
+    /// Set once the head was written to the stream
+    head_sent: bool,
+    /// Set once the response has its answer, see `is_completed`
+    completed: bool,
+    /// Set while `Server::set_debug_dump` is on
+    dump: Option<Dump>,
+    /// Set while `Server::set_phase_timings` is on
+    write_clock: Option<WriteClock>,
+    /// When the request was read, set while `Server::set_server_timing` is
+    /// on
+    server_timing: Option<Instant>,
+    /// Whether the client accepts gzip, set while
+    /// `Server::enable_compression` is on
+    #[cfg(feature = "gzip")]
+    compression: Option<bool>,
+    /// Set for handlers running with a timeout
+    watch: Option<Watch>,
+    /// Receives the stream instead of a 500 being sent if the handler
+    /// panics, for the `on_error` handler to answer on
+    panic_stream: Option<Arc<Mutex<Option<Stream>>>>,
+}
+
+/// How the body of a response whose head was sent already goes out
+#[derive(Debug, Clone, Copy)]
+struct Streaming {
+    chunked: bool,
+    /// What is left of the Content-Length the handler set
+    remaining: Option<u64>,
+    /// How many body bytes went out
+    sent: u64,
+}
+
+/// How many body bytes written through `io::Write` are collected before
+/// they go out as one chunk
+const STREAM_BUFFER_SIZE: usize = 16 * 1024;
+
+impl Response {
+    /// Returns a response that is captured in memory, for calling a handler
+    /// function directly, and what reads it once the handler is done
+    ///
+    /// The response gets no default headers, see `RequestBuilder` for an
+    /// example.
+    pub fn capture() -> (Response, CapturedResponse) {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let mut response = Response::new(Stream::Memory(MemoryStream {
+            input: io::Cursor::new(Vec::new()),
+            output: output.clone(),
+            encrypted: false,
+            remaining_requests: 0,
+            sni_hostname: None,
+            #[cfg(feature = "tls")]
+            client_certificate: None,
+            #[cfg(feature = "tokio")]
+            body_feed: None,
+        }));
+        response.chunked_allowed = true;
+        (response, CapturedResponse { output })
+    }
+    fn new(stream: Stream) -> Self {
+        Response {
+            stream,
+            status: None,
+            headers: Headers::new(),
+            defaults: Headers::new(),
+            vary: Vec::new(),
+            accept_used: None,
+            default_charset: None,
+            mime_types: Arc::default(),
+            templates: None,
+            logger: None,
+            new_session: None,
+            body: Vec::new(),
+            head_only: false,
+            chunked_allowed: false,
+            head_committed: false,
+            streaming: None,
+            autoflush: false,
+            corked: false,
+            head_sent: false,
+            completed: false,
+            dump: None,
+            write_clock: None,
+            server_timing: None,
+            #[cfg(feature = "gzip")]
+            compression: None,
+            watch: None,
+            panic_stream: None,
+        }
+    }
+    /// Write data into the response. Will be sent no later than on drop.
+    ///
+    /// Fails without writing anything if the status is one that has no
+    /// body, i.e. 1xx, 204 or 304.
+    pub fn write(&mut self, data: &str) -> std::io::Result<()> {
+        self.write_bytes(data.as_bytes())
+    }
+    /// Writes binary data into the response, like `write` does text
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.get("/pixel.gif", |_request, mut response| {
+    ///     let _ = response.set_header("Content-Type", "image/gif");
+    ///     let _ = response.write_bytes(b"GIF89a\x01\x00\x01\x00\x00\x00\x00;");
+    /// });
+    /// ```
+    pub fn write_bytes(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.check_not_completed()?;
+        if !data.is_empty() && !has_body(self.status.unwrap_or(200)) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("a {} response has no body", self.status.unwrap_or(200)),
+            ));
+        }
+        self.body.extend_from_slice(data);
+        self.send_if_due()
+    }
+    /// Writes formatted data into the response, which makes `write!` and
+    /// `writeln!` work without an intermediate `String`
+    ///
+    /// Fails like `write`, without writing anything.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.get("/list/", |request, mut response| {
+    ///     for (name, value) in request.query_pairs() {
+    ///         if write!(response, "<li>{}: {}</li>", name, value).is_err() {
+    ///             break;
+    ///         }
+    ///     }
+    /// });
+    /// ```
+    pub fn write_fmt(&mut self, args: fmt::Arguments) -> std::io::Result<()> {
+        self.check_not_completed()?;
+        let len = self.body.len();
+        std::io::Write::write_fmt(&mut self.body, args)?;
+        if self.body.len() > len && !has_body(self.status.unwrap_or(200)) {
+            self.body.truncate(len);
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("a {} response has no body", self.status.unwrap_or(200)),
+            ));
+        }
+        self.send_if_due()
+    }
+    /// Writes `value` into the response as `{}` formats it
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.get("/answer/", |_request, mut response| {
+    ///     let _ = response.write_display(&42);
+    /// });
+    /// ```
+    pub fn write_display(&mut self, value: &impl fmt::Display) -> std::io::Result<()> {
+        self.write_fmt(format_args!("{}", value))
+    }
+    /// Set the status code of the response
+    ///
+    /// Fails without changing anything if the code is not between 100 and
+    /// 599, or if it is one that has no body and something was written
+    /// already.
+    pub fn set_status_code(&mut self, code: u32) -> std::io::Result<()> {
+        self.check_not_completed()?;
+        self.check_head_not_sent()?;
+        check_status(code, self.body.len())?;
+        self.status = Some(code);
+        Ok(())
+    }
+    /// Sets the header `name`, replacing any value it had
+    ///
+    /// `Content-Length` is computed from the body, one set here has to match
+    /// the body once the response is sent, otherwise a `500` goes out
+    /// instead. Fails without changing anything if the name is not a token,
+    /// the value contains CR, LF or NUL, the head would grow beyond 64 KiB,
+    /// or the head was sent already by writing through `io::Write`.
+    pub fn set_header(&mut self, name: &str, value: &str) -> std::io::Result<()> {
+        self.check_head_not_sent()?;
+        check_header(name, value)?;
+        self.check_head_size(name, value)?;
+        self.headers.set(name, value);
+        Ok(())
+    }
+    /// Sets the `Content-Type` to `media_type` with an explicit `charset`,
+    /// e.g. for text that is not UTF-8
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.get("/legacy.txt", |_request, mut response| {
+    ///     let _ = response.content_type_with_charset("text/plain", "iso-8859-1");
+    /// });
+    /// ```
+    pub fn content_type_with_charset(
+        &mut self,
+        media_type: &str,
+        charset: &str,
+    ) -> std::io::Result<()> {
+        self.set_header(
+            "Content-Type",
+            &format!("{}; charset={}", media_type, charset),
+        )
+    }
+    /// Adds a header, keeping earlier ones with the same name, e.g. for
+    /// several `Set-Cookie` or `Link` headers
+    ///
+    /// Every value is sent on a line of its own. Headers that may appear
+    /// only once, like `Content-Type`, are replaced instead. Fails like
+    /// `set_header` does.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.get("/login/", |_request, mut response| {
+    ///     let _ = response.add_header("Set-Cookie", "session=abc; HttpOnly");
+    ///     let _ = response.add_header("Set-Cookie", "theme=dark; Expires=Wed, 21 Oct 2026 07:28:00 GMT");
+    /// });
+    /// ```
+    pub fn add_header(&mut self, name: &str, value: &str) -> std::io::Result<()> {
+        self.check_head_not_sent()?;
+        check_header(name, value)?;
+        self.check_head_size(name, value)?;
+        self.headers.append(name, value);
+        Ok(())
+    }
+    /// Sets the cookie `name` on the client, adding a `Set-Cookie` header
+    /// for every cookie
+    ///
+    /// The value is sent as it is, URL-encode values that may contain
+    /// spaces, `;`, `,` or quotes. Fails if name, value or an attribute
+    /// contain characters a cookie cannot carry, or like `add_header`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::{CookieOptions, SameSite, Server};
+    /// let mut s = Server::new();
+    /// s.post("/login/", |_request, mut response| {
+    ///     let options = CookieOptions {
+    ///         path: Some(String::from("/")),
+    ///         http_only: true,
+    ///         same_site: Some(SameSite::Lax),
+    ///         ..CookieOptions::default()
+    ///     };
+    ///     let _ = response.set_cookie("session", "abc", &options);
+    ///     let _ = response.set_cookie("theme", "dark", &CookieOptions::default());
+    /// });
+    /// ```
+    pub fn set_cookie(
+        &mut self,
+        name: &str,
+        value: &str,
+        options: &CookieOptions,
+    ) -> std::io::Result<()> {
+        let cookie = cookie::format(name, value, options)?;
+        self.add_header("Set-Cookie", &cookie)
+    }
+    /// Sends the file at `path` as the body, with the `Content-Type` static
+    /// files with its extension get and its `Content-Length`
+    ///
+    /// The file is sent while it is read, not held in memory. The status
+    /// stays what it was set to, `200` by default. Fails without changing
+    /// anything if the file cannot be opened or is a directory, so the
+    /// handler can answer `404` instead, or if something was written
+    /// already.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.get("/reports/:name/", |request, mut response| {
+    ///     let path = format!("./reports/{}.pdf", request.path_param::<u32>("name").unwrap_or(0));
+    ///     if response.send_file(&path).is_err() {
+    ///         let _ = response.set_status_code(404);
+    ///     }
+    /// });
+    /// ```
+    pub fn send_file(&mut self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let (file, len) = self.open_file(path.as_ref())?;
+        self.send_opened_file(file, len, path.as_ref())
+    }
+    /// Sends the file at `path` like `send_file`, telling browsers to save
+    /// it as `download_name` instead of displaying it
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.get("/export/", |_request, mut response| {
+    ///     let _ = response.send_file_as_attachment("/tmp/export-1234.csv", "export.csv");
+    /// });
+    /// ```
+    pub fn send_file_as_attachment(
+        &mut self,
+        path: impl AsRef<Path>,
+        download_name: &str,
+    ) -> std::io::Result<()> {
+        let (file, len) = self.open_file(path.as_ref())?;
+        self.set_header(
+            "Content-Disposition",
+            &content_disposition(Disposition::Attachment, download_name),
+        )?;
+        self.send_opened_file(file, len, path.as_ref())
+    }
+    /// Opens the file for `send_file` and returns it with its length,
+    /// failing if nothing can be sent anymore
+    fn open_file(&self, path: &Path) -> std::io::Result<(File, u64)> {
+        self.check_not_completed()?;
+        self.check_head_not_sent()?;
+        if self.head_committed || !self.body.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "something was written already",
+            ));
+        }
+        let file = File::open(path)?;
+        let metadata = file.metadata()?;
+        if metadata.is_dir() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::IsADirectory,
+                format!("{} is a directory", path.display()),
+            ));
+        }
+        Ok((file, metadata.len()))
+    }
+    /// Sends a file `open_file` opened as the body
+    fn send_opened_file(&mut self, mut file: File, len: u64, path: &Path) -> std::io::Result<()> {
+        let media_type = String::from(media_type_of(path, &self.mime_types));
+        self.set_header("Content-Type", &media_type)?;
+        self.set_header("Content-Length", &len.to_string())?;
+        io::copy(&mut file, self)?;
+        Ok(())
+    }
+    /// Sends `value` serialized as JSON, with the `Content-Type`
+    /// `application/json`
+    ///
+    /// Fails like `write` does, or if `value` cannot be serialized.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use serde::Serialize;
+    ///
+    /// #[derive(Serialize)]
+    /// struct User {
+    ///     id: u32,
+    ///     name: String,
+    /// }
+    ///
+    /// let mut s = corrodedweb::Server::new();
+    /// s.get("/users/:id/", |request, mut response| {
+    ///     let user = User {
+    ///         id: request.path_param("id").unwrap_or(0),
+    ///         name: String::from("Max"),
+    ///     };
+    ///     let _ = response.send_json(&user);
+    /// });
+    /// ```
+    #[cfg(feature = "json")]
+    pub fn send_json<T: serde::Serialize + ?Sized>(&mut self, value: &T) -> std::io::Result<()> {
+        let body = serde_json::to_string(value)?;
+        self.check_not_completed()?;
+        self.set_header("Content-Type", "application/json")?;
+        self.write(&body)
+    }
+    /// Redirects to `url` with `302 Found`, the usual temporary redirect.
+    ///
+    /// `url` is sent as it is, relative or absolute. `redirect_permanent`,
+    /// `redirect_temporary` and `see_other` send the other redirect codes.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.get("/latest/", |_request, mut response| {
+    ///     let _ = response.redirect("/releases/1.0/");
+    /// });
+    /// ```
+    pub fn redirect(&mut self, url: &str) -> std::io::Result<()> {
+        self.send_redirect(url, 302)
+    }
+    /// Redirects permanently to `url`.
+    ///
+    /// Sends `301 Moved Permanently`, or `308 Permanent Redirect` if
+    /// `preserve_method` is set and the client has to repeat the request
+    /// with the same method and body.
+    pub fn redirect_permanent(&mut self, url: &str, preserve_method: bool) -> std::io::Result<()> {
+        let code = if preserve_method { 308 } else { 301 };
+        self.send_redirect(url, code)
+    }
+    /// Redirects temporarily to `url`.
+    ///
+    /// Sends `302 Found`, or `307 Temporary Redirect` if `preserve_method`
+    /// is set.
+    pub fn redirect_temporary(&mut self, url: &str, preserve_method: bool) -> std::io::Result<()> {
+        let code = if preserve_method { 307 } else { 302 };
+        self.send_redirect(url, code)
+    }
+    /// Sends `303 See Other`, telling the client to fetch `url` with GET.
+    ///
+    /// This is the answer to a successful POST (POST-then-redirect pattern).
+    pub fn see_other(&mut self, url: &str) -> std::io::Result<()> {
+        self.send_redirect(url, 303)
+    }
+    /// Records that the response depends on the request header `name`, e.g.
+    /// `Accept` if the handler picked between HTML and JSON
+    ///
+    /// All such headers are sent in a single `Vary` header, so caches keep
+    /// the variants apart. A `Vary` header set by the handler is kept and
+    /// extended.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.get("/greeting/", |request, mut response| {
+    ///     response.vary("Accept-Language");
+    ///     let greeting = match request.get_header("Accept-Language") {
+    ///         Some(language) if language.starts_with("de") => "Hallo",
+    ///         _ => "Hello",
+    ///     };
+    ///     let _ = response.write(greeting);
+    /// });
+    /// ```
+    pub fn vary(&mut self, name: &str) {
+        self.vary.push(String::from(name));
+    }
+    /// Sets the `ETag` header, quoting `etag` unless it is quoted already or
+    /// a weak tag like `W/"v1"`
+    ///
+    /// Fails like `set_header`.
+    pub fn set_etag(&mut self, etag: &str) -> std::io::Result<()> {
+        self.set_header("ETag", &quoted_etag(etag))
+    }
+    /// Answers a conditional GET for a body that is costly to produce
+    ///
+    /// Sets the `ETag` like `set_etag`. If the request's `If-None-Match`
+    /// names `etag` or is `*`, the answer is `304 Not Modified` and `body`
+    /// is not called. Otherwise what `body` returns is written. Tags are
+    /// compared weakly, so `W/"v1"` matches `"v1"`. Requests other than GET
+    /// and HEAD always get the body.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.get("/report/", |request, mut response| {
+    ///     let version = 7;
+    ///     let _ = response.send_cached(&request, &format!("v{}", version), || {
+    ///         format!("report version {}", version)
+    ///     });
+    /// });
+    /// ```
+    pub fn send_cached<F: FnOnce() -> String>(
+        &mut self,
+        request: &Request,
+        etag: &str,
+        body: F,
+    ) -> std::io::Result<()> {
+        let etag = quoted_etag(etag);
+        self.set_header("ETag", &etag)?;
+        let conditional = request.method == "GET" || request.method == "HEAD";
+        let matched = request
+            .get_header("If-None-Match")
+            .is_some_and(|tags| etag_matches(tags, &etag));
+        if conditional && matched {
+            return self.set_status_code(304);
+        }
+        self.write(&body())
+    }
+    /// Writes the template `name` from the directory set with
+    /// `Server::set_template_root`, with the values of `context` filled in
+    ///
+    /// `{{ name }}` in the template is replaced with the value of `name`
+    /// HTML-escaped, `{{{ name }}}` with the value as it is. Names without a
+    /// value are left empty and logged as a warning. The Content-Type is
+    /// taken from the extension of the template unless set before. A
+    /// template that cannot be read or parsed is logged as an error and
+    /// answered with 500, and the error is returned.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// use std::collections::HashMap;
+    /// let mut s = Server::new();
+    /// s.set_template_root("./templates/");
+    /// s.get("/hello/", |request, mut response| {
+    ///     let mut context = HashMap::new();
+    ///     context.insert(String::from("name"), String::from(request.query_param_or("name", "you")));
+    ///     let _ = response.render("hello.html", &context);
+    /// });
+    /// ```
+    pub fn render(&mut self, name: &str, context: &HashMap<String, String>) -> std::io::Result<()> {
+        self.check_not_completed()?;
+        let template = match &self.templates {
+            Some(templates) => templates.get(name),
+            None => {
+                return Err(std::io::Error::other(
+                    "no template root is set, see Server::set_template_root",
+                ))
+            }
+        };
+        let template = match template {
+            Ok(template) => template,
+            Err(e) => {
+                Logger::error(
+                    &self.logger,
+                    &format!("Template {} cannot be rendered: {}", name, e),
+                );
+                if !self.head_committed && !self.head_sent {
+                    self.status = Some(500);
+                    self.headers.set("Content-Type", "text/html");
+                    self.body = INTERNAL_ERROR_PAGE.as_bytes().to_vec();
+                }
+                return Err(std::io::Error::other(e.to_string()));
+            }
+        };
+        let (page, missing) = template.render(context);
+        for variable in missing {
+            Logger::warning(
+                &self.logger,
+                &format!("Template {} uses {}, which has no value", name, variable),
+            );
+        }
+        if self.header("Content-Type").is_none() {
+            let content_type = String::from(media_type_of(Path::new(name), &self.mime_types));
+            self.set_header("Content-Type", &content_type)?;
+        }
+        self.write(&page)
+    }
+    /// Returns the status code, `None` until the handler set one or the head
+    /// went out with the default `200`
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.get("/", |_request, mut response| {
+    ///     assert_eq!(response.status(), None);
+    ///     let _ = response.set_status_code(201);
+    ///     assert_eq!(response.status(), Some(201));
+    /// });
+    /// ```
+    pub fn status(&self) -> Option<u32> {
+        self.status
+    }
+    /// Sends the head and everything written so far to the client now,
+    /// e.g. for a progress update in a long running handler
+    ///
+    /// From then on the body is streamed like after a write through
+    /// `io::Write`, so the headers and status cannot change anymore. Further
+    /// writes are collected and sent in pieces of 16 KiB, right away with
+    /// `set_autoflush`, or once `uncork` or `flush` is called after `cork`.
+    /// A chunked body gets a chunk per piece sent. Responses that are
+    /// captured in memory, for HTTP/2, the `tokio` feature and
+    /// `test_request`, are sent at the end as usual.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.get("/import/", |_request, mut response| {
+    ///     for step in 1..=3 {
+    ///         // Do the step here
+    ///         let _ = response.write(&format!("step {} of 3 done\n", step));
+    ///         if response.flush().is_err() {
+    ///             // The client is gone
+    ///             return;
+    ///         }
+    ///     }
+    /// });
+    /// ```
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        if self.completed {
+            return Ok(());
+        }
+        if !self.head_committed {
+            if !self.stream.is_memory() {
+                self.timed(Response::start_streaming)?;
+            }
+            self.head_committed = true;
+        }
+        std::io::Write::flush(self)
+    }
+    /// Sends each write of a streamed body to the client right away instead
+    /// of collecting 16 KiB first, off by default
+    ///
+    /// The body is streamed once written through `io::Write` or after
+    /// `flush`. Until then writes are collected whatever this is set to.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// use std::io::Write;
+    /// let mut s = Server::new();
+    /// s.get("/ticks/", |_request, mut response| {
+    ///     response.set_autoflush(true);
+    ///     for tick in 0..3 {
+    ///         // Goes out as a chunk of its own
+    ///         let _ = writeln!(response, "tick {}", tick);
+    ///     }
+    /// });
+    /// ```
+    pub fn set_autoflush(&mut self, autoflush: bool) {
+        self.autoflush = autoflush;
+    }
+    /// Holds back the streamed body until `uncork` or `flush`, however much
+    /// is written, e.g. to send many tiny writes as one piece
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// use std::io::Write;
+    /// let mut s = Server::new();
+    /// s.get("/rows/", |_request, mut response| {
+    ///     response.cork();
+    ///     for row in 0..100 {
+    ///         let _ = writeln!(response, "{}", row);
+    ///     }
+    ///     // All rows go out together
+    ///     let _ = response.uncork();
+    /// });
+    /// ```
+    pub fn cork(&mut self) {
+        self.corked = true;
+    }
+    /// Sends what `cork` held back and lets further writes go out as usual
+    pub fn uncork(&mut self) -> std::io::Result<()> {
+        self.corked = false;
+        if self.streaming.is_none() || self.completed {
+            return Ok(());
+        }
+        std::io::Write::flush(self)
+    }
+    /// Sends the head with `Content-Type: text/event-stream` and
+    /// `Cache-Control: no-cache` and returns the stream to push Server-Sent
+    /// Events over
+    ///
+    /// Events are sent as they come like a body after `flush`. Set the status
+    /// and further headers before. Fails if the head was sent already or the
+    /// response is completed.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// use std::time::Duration;
+    /// let mut s = Server::new();
+    /// s.get("/ticks/", |_request, mut response| {
+    ///     let mut events = match response.start_event_stream() {
+    ///         Ok(events) => events,
+    ///         Err(_) => return,
+    ///     };
+    ///     events.set_keep_alive(Duration::from_secs(15));
+    ///     for tick in 0.. {
+    ///         if events.send_event("tick", &tick.to_string()).is_err() {
+    ///             // The client is gone
+    ///             return;
+    ///         }
+    ///         if events.wait(Duration::from_secs(60)).is_err() {
+    ///             return;
+    ///         }
+    ///     }
+    /// });
+    /// ```
+    pub fn start_event_stream(&mut self) -> std::io::Result<EventStream<'_>> {
+        self.check_not_completed()?;
+        self.check_head_not_sent()?;
+        if !has_body(self.status.unwrap_or(200)) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("a {} response has no body", self.status.unwrap_or(200)),
+            ));
+        }
+        self.set_header("Content-Type", "text/event-stream")?;
+        self.set_header("Cache-Control", "no-cache")?;
+        self.flush()?;
+        Ok(EventStream::new(self))
+    }
+    /// Sends the head with `Transfer-Encoding: chunked`, after which every
+    /// write goes out as a chunk of its own, for bodies whose length is not
+    /// known up front
+    ///
+    /// The last chunk is sent by `finish` or once the handler returns. Set
+    /// the status and headers before. HTTP/1.0 clients, which know no
+    /// chunks, get the body until the connection closes instead. Fails if
+    /// the head was sent already, the response is completed or has a
+    /// `Content-Length`. Responses captured in memory are sent at the end
+    /// as usual, see `flush`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.get("/report.csv", |_request, mut response| {
+    ///     let _ = response.set_header("Content-Type", "text/csv");
+    ///     if response.start_chunked().is_err() {
+    ///         return;
+    ///     }
+    ///     for row in 0..1000 {
+    ///         if response.write(&format!("{},{}\n", row, row * row)).is_err() {
+    ///             // The client is gone
+    ///             return;
+    ///         }
+    ///     }
+    ///     let _ = response.finish();
+    /// });
+    /// ```
+    pub fn start_chunked(&mut self) -> std::io::Result<()> {
+        self.check_not_completed()?;
+        self.check_head_not_sent()?;
+        let code = self.status.unwrap_or(200);
+        if !has_body(code) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("a {} response has no body", code),
+            ));
+        }
+        if self.header("Content-Length").is_some() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "a body with a Content-Length is not chunked",
+            ));
+        }
+        self.autoflush = true;
+        self.flush()
+    }
+    /// Completes the response, sending what was written and ending a
+    /// streamed body, as returning from the handler does
+    ///
+    /// Writes fail afterwards. Fails if the body could not be sent or is
+    /// shorter than the `Content-Length` the handler set.
+    pub fn finish(&mut self) -> std::io::Result<()> {
+        if self.completed {
+            return Ok(());
+        }
+        self.timed(Response::send)
+    }
+    /// Returns whether the status line and headers were written to the
+    /// client, after which they cannot change
+    pub fn headers_sent(&self) -> bool {
+        self.head_sent
+    }
+    /// Returns how many body bytes were written, those sent already and
+    /// those still collected
+    ///
+    /// For HEAD requests this is what a GET would have sent.
+    pub fn bytes_written(&self) -> u64 {
+        self.streaming.map_or(0, |streaming| streaming.sent) + self.body.len() as u64
+    }
+    /// Returns the value the header `name` has or will have on the wire,
+    /// set by the handler or added by the server like `Date`
+    ///
+    /// Of a header added several times the first value is returned.
+    /// `Content-Length` is only returned if the handler set it.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        if self.head_sent {
+            return self.headers.get(name);
+        }
+        self.headers.get(name).or_else(|| self.defaults.get(name))
+    }
+    /// Returns whether the response has its answer, e.g. because of a
+    /// redirect
+    ///
+    /// Middleware can use this to tell whether a handler answered.
+    pub fn is_completed(&self) -> bool {
+        self.completed
+    }
+    fn check_not_completed(&self) -> std::io::Result<()> {
+        if self.completed {
+            return Err(std::io::Error::other(ResponseAlreadyCompleted));
+        }
+        if self.watch.as_ref().is_some_and(Watch::is_abandoned) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "the handler ran out of time, the client got a 504",
+            ));
+        }
+        Ok(())
+    }
+    /// Fails once the head went out ahead of the body, see `io::Write`
+    fn check_head_not_sent(&self) -> std::io::Result<()> {
+        if self.head_committed || self.head_sent {
+            return Err(std::io::Error::other("the response head was sent already"));
+        }
+        Ok(())
+    }
+    /// Fails if adding the header would make the head larger than
+    /// `MAX_RESPONSE_HEAD`
+    fn check_head_size(&self, name: &str, value: &str) -> std::io::Result<()> {
+        let size: usize = self
+            .headers
+            .iter()
+            .map(|(name, value)| header_line_len(name, value))
+            .sum();
+        if size + header_line_len(name, value) > MAX_RESPONSE_HEAD {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "response head too large",
+            ));
+        }
+        Ok(())
+    }
+    /// Turns the response into a redirect with a Location header and a small
+    /// HTML body. Fails without changing anything if `url` would break out of
+    /// the header line.
+    fn send_redirect(&mut self, url: &str, code: u32) -> std::io::Result<()> {
+        self.check_not_completed()?;
+        self.check_head_not_sent()?;
+        check_header_value(url)?;
+        self.check_head_size("Location", url)?;
+        self.status = Some(code);
+        self.headers.set("Location", url);
+        self.body = format!(
+            "<html>Redirecting to <a href='{0}'>{0}</a></html>",
+            escape_html(url)
+        )
+        .into_bytes();
+        self.completed = true;
+        Ok(())
+    }
+    /// Sets the headers the server adds unless the handler set them
+    ///
+    /// Their `Vary`, like `Origin` for CORS, goes with the names given to
+    /// `vary`, so a `Vary` header set by the handler extends it instead of
+    /// replacing it.
+    fn set_defaults(&mut self, defaults: Vec<(String, String)>) {
+        for (name, value) in defaults {
+            if name.eq_ignore_ascii_case("Vary") {
+                self.vary
+                    .extend(value.split(',').map(|name| String::from(name.trim())));
+            } else {
+                self.defaults.append(&name, &value);
+            }
+        }
+    }
+    /// Adds the default headers and the `Vary` header to those set by the
+    /// handler and returns the status line and header lines, without
+    /// `Content-Length`, and the `Content-Length` the handler set
+    fn head_lines(&mut self, code: u32) -> (String, Option<String>) {
+        if let Some(received) = self.server_timing {
+            let took = received.elapsed().as_secs_f64() * 1000.0;
+            self.defaults
+                .set("Server-Timing", &format!("app;dur={:.1}", took));
+        }
+        let handler_headers = std::mem::take(&mut self.headers);
+        self.headers = self
+            .defaults
+            .iter()
+            .filter(|(name, _)| !handler_headers.contains(name))
+            .chain(handler_headers.iter())
+            .map(|(name, value)| (String::from(name), String::from(value)))
+            .collect();
+        let accept_used = self
+            .accept_used
+            .as_ref()
+            .is_some_and(|used| used.load(Ordering::Relaxed));
+        self.headers.merge_vary(
+            self.vary
+                .iter()
+                .map(String::as_str)
+                .chain(accept_used.then_some("Accept")),
+        );
+        if let Some((session, secure)) = &self.new_session {
+            if session.started() {
+                self.headers
+                    .append("Set-Cookie", &SessionStore::cookie(session, *secure));
+            }
+        }
+        if let Some(content_type) = self.headers.get("Content-Type") {
+            let content_type = mime::with_charset(content_type, self.default_charset.as_deref());
+            self.headers.set("Content-Type", &content_type);
+        }
+        let content_length = self.headers.get("Content-Length").map(String::from);
+        let mut head = format!("HTTP/1.1 {} {}\r\n", code, reason_phrase(code));
+        // Content-Length follows from the body, and each header goes on a line
+        // of its own since values like those of Set-Cookie contain commas
+        for (name, value) in self
+            .headers
+            .iter()
+            .filter(|(name, _)| !name.eq_ignore_ascii_case("Content-Length"))
+        {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        (head, content_length)
+    }
+    /// Writes status line, headers and body to the stream. The body is left
+    /// out for HEAD requests, but still counted in the Content-Length.
+    fn send(&mut self) -> std::io::Result<()> {
+        if self.streaming.is_some() {
+            return self.finish_streaming();
+        }
+        if self.head_sent {
+            return Ok(());
+        }
+        self.completed = true;
+        let code = self.status.unwrap_or(200);
+        #[cfg(feature = "gzip")]
+        self.compress(code);
+        let (mut head, content_length) = self.head_lines(code);
+        match code {
+            // The length of what a GET would have returned
+            304 => {
+                if let Some(len) = &content_length {
+                    head.push_str(&format!("Content-Length: {}\r\n", len));
+                }
+            }
+            _ if has_body(code) => {
+                head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+            }
+            _ => {}
+        }
+        head.push_str("\r\n");
+        // Default headers can still push the head over the limit
+        let valid = check_message(code, content_length.as_deref(), self.body.len()).is_ok();
+        self.status = Some(code);
+        if !valid || head.len() > MAX_RESPONSE_HEAD {
+            head = format!(
+                "HTTP/1.1 500 {}\r\nContent-Length: 0\r\n\r\n",
+                reason_phrase(500)
+            );
+            self.status = Some(500);
+            self.headers = Headers::new();
+            self.body.clear();
+        }
+        self.head_sent = true;
+        let body: &[u8] = if self.head_only { &[] } else { &self.body };
+        if let Some(dump) = &self.dump {
+            dump.response(&head, body);
+        }
+        transfer::write_head_and_body(&mut self.stream, head.as_bytes(), body)
+    }
+    /// Compresses the body with gzip if compression is on, the client
+    /// accepts gzip and the body is text of at least
+    /// `compression::MIN_SIZE` bytes
+    ///
+    /// Bodies a handler encoded itself and partial ones stay as they are.
+    /// A strong `ETag` is made weak, since the bytes sent differ from those
+    /// it was given for.
+    #[cfg(feature = "gzip")]
+    fn compress(&mut self, code: u32) {
+        let accepted = match self.compression {
+            Some(accepted) => accepted,
+            None => return,
+        };
+        let compressible = self
+            .headers
+            .get("Content-Type")
+            .or_else(|| self.defaults.get("Content-Type"))
+            .is_some_and(compression::compressible);
+        if !compressible || !has_body(code) || code == 206 {
+            return;
+        }
+        self.vary("Accept-Encoding");
+        if !accepted
+            || self.body.len() < compression::MIN_SIZE
+            || self.headers.contains("Content-Encoding")
+        {
+            return;
+        }
+        match compression::gzip(&self.body) {
+            Ok(compressed) if compressed.len() < self.body.len() => {
+                self.body = compressed;
+                self.headers.set("Content-Encoding", "gzip");
+                self.headers.remove("Content-Length");
+                if let Some(etag) = self.headers.get("ETag") {
+                    if !etag.starts_with("W/") {
+                        let weak = format!("W/{}", etag);
+                        self.headers.set("ETag", &weak);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    /// Sends the head before the body is complete
+    ///
+    /// The body is framed by the `Content-Length` the handler set, chunked
+    /// for HTTP/1.1 clients otherwise, and ended by closing the connection
+    /// for older ones.
+    fn start_streaming(&mut self) -> std::io::Result<()> {
+        let code = self.status.unwrap_or(200);
+        let (mut head, content_length) = self.head_lines(code);
+        let remaining = match content_length {
+            Some(len) => match len.trim().parse::<u64>() {
+                Ok(len) => Some(len),
+                Err(_) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("Content-Length {} is no number", len),
+                    ))
+                }
+            },
+            None => None,
+        };
+        let chunked = remaining.is_none() && self.chunked_allowed;
+        match remaining {
+            Some(len) => head.push_str(&format!("Content-Length: {}\r\n", len)),
+            None if chunked => head.push_str("Transfer-Encoding: chunked\r\n"),
+            None => {}
+        }
+        head.push_str("\r\n");
+        if head.len() > MAX_RESPONSE_HEAD {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "response head too large",
+            ));
+        }
+        self.stream.write_all(head.as_bytes())?;
+        if let Some(dump) = &mut self.dump {
+            dump.start_stream(&head);
+        }
+        self.status = Some(code);
+        self.head_sent = true;
+        self.streaming = Some(Streaming {
+            chunked,
+            remaining,
+            sent: 0,
+        });
+        Ok(())
+    }
+    /// Sends the body written so far behind a head sent already
+    fn send_buffered(&mut self) -> std::io::Result<()> {
+        let streaming = match &mut self.streaming {
+            Some(streaming) => streaming,
+            None => return Ok(()),
+        };
+        let body = std::mem::take(&mut self.body);
+        if body.is_empty() || self.head_only {
+            return Ok(());
+        }
+        if let Some(remaining) = &mut streaming.remaining {
+            *remaining = remaining.saturating_sub(body.len() as u64);
+        }
+        streaming.sent += body.len() as u64;
+        if let Some(dump) = &mut self.dump {
+            dump.stream(&body);
+        }
+        if streaming.chunked {
+            let size = format!("{:x}\r\n", body.len());
+            transfer::write_head_and_body(&mut self.stream, size.as_bytes(), &body)?;
+            self.stream.write_all(b"\r\n")
+        } else {
+            self.stream.write_all(&body)
+        }
+    }
+    /// Sends the collected bytes of a streamed body if autoflush is on or
+    /// they reached `STREAM_BUFFER_SIZE`, unless the response is corked
+    fn send_if_due(&mut self) -> std::io::Result<()> {
+        if self.streaming.is_none() || self.corked {
+            return Ok(());
+        }
+        if self.autoflush {
+            std::io::Write::flush(self)
+        } else if self.body.len() >= STREAM_BUFFER_SIZE {
+            self.timed(Response::send_buffered)
+        } else {
+            Ok(())
+        }
+    }
+    /// Sends a response prepared in memory as it is, instead of this one
+    fn relay(&mut self, prepared: &[u8]) {
+        self.completed = true;
+        self.head_sent = true;
+        let _ = self.timed(|response| {
+            response.stream.write_all(prepared)?;
+            response.stream.flush()
+        });
+    }
+    /// Runs `send`, counting its time on the write clock if there is one
+    fn timed<T, F: FnOnce(&mut Self) -> T>(&mut self, send: F) -> T {
+        let started = self.write_clock.as_ref().map(|_| Instant::now());
+        let result = send(self);
+        if let (Some(clock), Some(started)) = (&self.write_clock, started) {
+            clock.add(started);
+        }
+        result
+    }
+    /// Sends the rest of a streamed body and ends it
+    fn finish_streaming(&mut self) -> std::io::Result<()> {
+        if self.completed {
+            return Ok(());
+        }
+        self.completed = true;
+        self.send_buffered()?;
+        if let Some(dump) = &mut self.dump {
+            dump.finish_stream();
+        }
+        let streaming = match self.streaming {
+            Some(streaming) => streaming,
+            None => return Ok(()),
+        };
+        if streaming.chunked && !self.head_only {
+            self.stream.write_all(b"0\r\n\r\n")?;
+        }
+        self.stream.flush()?;
+        match streaming.remaining {
+            Some(remaining) if remaining > 0 && !self.head_only => Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                format!(
+                    "the body is {} bytes shorter than its Content-Length",
+                    remaining
+                ),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Shows the status, the headers set so far and how far the response got
+impl fmt::Debug for Response {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // No `..`, so a new field does not compile until it is shown here
+        let Response {
+            stream: _,
+            status,
+            headers,
+            defaults: _,
+            vary,
+            accept_used: _,
+            default_charset: _,
+            mime_types: _,
+            templates: _,
+            logger: _,
+            new_session: _,
+            body,
+            head_only,
+            chunked_allowed: _,
+            head_committed: _,
+            streaming,
+            autoflush: _,
+            corked: _,
+            head_sent,
+            completed,
+            dump: _,
+            write_clock: _,
+            server_timing: _,
+            #[cfg(feature = "gzip")]
+                compression: _,
+            watch: _,
+            panic_stream: _,
+        } = self;
+        f.debug_struct("Response")
+            .field("status", status)
+            .field("headers", headers)
+            .field("vary", vary)
+            .field(
+                "bytes_written",
+                &(body.len() as u64 + streaming.map_or(0, |s| s.sent)),
+            )
+            .field("head_only", head_only)
+            .field("head_sent", head_sent)
+            .field(
+                "chunked",
+                &streaming.is_some_and(|streaming| streaming.chunked),
+            )
+            .field("completed", completed)
+            .finish()
+    }
+}
+
+/// Streams the body to the client, e.g. from a CSV writer or an encoder,
+/// instead of collecting all of it first
+///
+/// The first write sends the head, so headers and the status have to be
+/// set before, changing them afterwards fails. Without a `Content-Length`
+/// set by the handler the body goes out chunked, or until the connection
+/// closes for HTTP/1.0 clients. Body
+/// bytes are collected and sent in pieces of 16 KiB, `flush` sends them
+/// right away, see `Response::set_autoflush` and `Response::cork` for
+/// sending them sooner or later. Responses that are captured in memory, for HTTP/2, the
+/// `tokio` feature and `test_request`, collect the body and send it at the
+/// end as usual.
+impl std::io::Write for Response {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.check_not_completed()?;
+        let code = self.status.unwrap_or(200);
+        if !buf.is_empty() && !has_body(code) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("a {} response has no body", code),
+            ));
+        }
+        if !self.head_committed {
+            if !self.stream.is_memory() {
+                self.timed(Response::start_streaming)?;
+            }
+            self.head_committed = true;
+        }
+        if let Some(Streaming {
+            remaining: Some(remaining),
+            ..
+        }) = self.streaming
+        {
+            if (self.body.len() + buf.len()) as u64 > remaining {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "the body would be longer than its Content-Length",
+                ));
+            }
+        }
+        self.body.extend_from_slice(buf);
+        self.send_if_due()?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if self.streaming.is_none() {
+            return Ok(());
+        }
+        self.timed(|response| {
+            response.send_buffered()?;
+            response.stream.flush()
+        })
+    }
+}
+
+/// Returns whether responses with the status `code` have a body, which 1xx,
+/// 204 and 304 responses never do
+fn has_body(code: u32) -> bool {
+    !(100..200).contains(&code) && code != 204 && code != 304
+}
+
+/// Rejects status codes outside of 100 to 599, and those without a body if
+/// `body_len` bytes were written already
+fn check_status(code: u32, body_len: usize) -> std::io::Result<()> {
+    if !(100..=599).contains(&code) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{} is no status code, they are between 100 and 599", code),
+        ));
+    }
+    if body_len > 0 && !has_body(code) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "a {} response has no body, but {} bytes were written",
+                code, body_len
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that a response is consistent before it is sent, so no client
+/// waits for bytes that never come or reads the rest of a body as the next
+/// response
+///
+/// `content_length` is the header set by the handler, if any. It has to
+/// match the body, except for 304 where it describes what a GET would have
+/// returned. 1xx and 204 responses must not have one.
+fn check_message(code: u32, content_length: Option<&str>, body_len: usize) -> std::io::Result<()> {
+    check_status(code, body_len)?;
+    let content_length = match content_length {
+        Some(len) => len,
+        None => return Ok(()),
+    };
+    let error = |message: String| {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            message,
+        ))
+    };
+    match code {
+        304 => Ok(()),
+        _ if !has_body(code) => error(format!("a {} response has no Content-Length", code)),
+        _ if content_length.trim().parse::<usize>().ok() != Some(body_len) => error(format!(
+            "Content-Length is {} but the body has {} bytes",
+            content_length, body_len
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Rejects header names and values that would break out of their line or
+/// the head
+pub(crate) fn check_header(name: &str, value: &str) -> std::io::Result<()> {
+    // Names are tokens, RFC 7230 section 3.2.6
+    let valid_name = !name.is_empty()
+        && name
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte));
+    if !valid_name {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "header name contains invalid characters",
+        ));
+    }
+    check_header_value(value)
+}
+
+/// Rejects header values containing line breaks or NUL, which would let the
+/// value start a header or body of its own
+fn check_header_value(value: &str) -> std::io::Result<()> {
+    if value.contains(['\r', '\n', '\0']) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "header value contains invalid characters",
+        ));
+    }
+    Ok(())
+}
+
+/// Returns how many bytes the header takes up in the head
+fn header_line_len(name: &str, value: &str) -> usize {
+    name.len() + value.len() + 4
+}
+
+/// Returns whether the client wants to send further requests over the
+/// connection, which HTTP/1.1 clients do unless they say otherwise and
+/// HTTP/1.0 clients only if they ask for it
+fn client_keeps_alive(version: &str, headers: &Headers) -> bool {
+    let options = headers.connection_options();
+    let has = |option: &str| options.iter().any(|listed| listed == option);
+    match version {
+        "HTTP/1.1" => !has("close"),
+        "HTTP/1.0" => has("keep-alive") && !has("close"),
+        _ => false,
+    }
+}
+
+/// Runs the handler of the route `pattern`, logging instead of unwinding if
+/// it panics, and returns whether it did
+fn call_handler(
+    logger: &Option<Logger>,
+    callback: &Callback,
+    request: Request,
+    response: Response,
+    pattern: &str,
+) -> bool {
+    let method = request.method.clone();
+    let path = request.path.clone();
+    if let Err(panic) = panics::catch(|| callback(request, response)) {
+        Logger::error(
+            logger,
+            &format!(
+                "Handler of {} {} panicked serving {}: {}",
+                method, pattern, path, panic
+            ),
+        );
+        return true;
+    }
+    false
+}
+
+/// Returns the reason phrase for a status code, a generic one for its class
+/// if the code is not known
+fn reason_phrase(code: u32) -> &'static str {
+    match code {
+        100 => "Continue",
+        101 => "Switching Protocols",
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        203 => "Non-Authoritative Information",
+        204 => "No Content",
+        205 => "Reset Content",
+        206 => "Partial Content",
+        300 => "Multiple Choices",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        402 => "Payment Required",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        406 => "Not Acceptable",
+        407 => "Proxy Authentication Required",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        410 => "Gone",
+        411 => "Length Required",
+        412 => "Precondition Failed",
+        413 => "Content Too Large",
+        414 => "URI Too Long",
+        415 => "Unsupported Media Type",
+        416 => "Range Not Satisfiable",
+        417 => "Expectation Failed",
+        418 => "I'm a teapot",
+        421 => "Misdirected Request",
+        422 => "Unprocessable Content",
+        425 => "Too Early",
+        426 => "Upgrade Required",
+        428 => "Precondition Required",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        451 => "Unavailable For Legal Reasons",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        505 => "HTTP Version Not Supported",
+        _ => match code / 100 {
+            1 => "Informational",
+            2 => "Success",
+            3 => "Redirection",
+            4 => "Client Error",
+            _ => "Server Error",
+        },
+    }
+}
+
+/// Returns `etag` in quotes unless it is quoted already or weak
+fn quoted_etag(etag: &str) -> String {
+    if etag.starts_with('"') || etag.starts_with("W/\"") {
+        String::from(etag)
+    } else {
+        format!("\"{}\"", etag)
+    }
+}
+
+/// Returns whether an `If-None-Match` header names `etag` or is `*`,
+/// comparing weakly
+fn etag_matches(tags: &str, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+    tags.split(',')
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == etag || tag == "*")
+}
+
+/// Returns the entity tag of a file, made of its length and modification
+/// time
+fn file_etag(len: u64, modified: Option<SystemTime>) -> String {
+    let modified = modified
+        .and_then(|modified| modified.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .map_or(0, |modified| modified.as_secs());
+    format!("\"{:x}-{:x}\"", len, modified)
+}
+
+/// Cuts a modification time to the whole seconds HTTP dates have
+fn whole_seconds(time: Option<SystemTime>) -> Option<SystemTime> {
+    time.and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .map(|time| SystemTime::UNIX_EPOCH + Duration::from_secs(time.as_secs()))
+}
+
+/// Returns the host without the port, or `None` if it contains characters
+/// no hostname or IP address has
+fn hostname_of(host: &str) -> Option<&str> {
+    let hostname = match host.strip_prefix('[') {
+        Some(rest) => &host[..rest.find(']')? + 2],
+        None => host.split(':').next().unwrap_or(""),
+    };
+    let valid = |c: char| c.is_ascii_alphanumeric() || ".-[]:".contains(c);
+    if hostname.is_empty() || !hostname.chars().all(valid) {
+        return None;
+    }
+    Some(hostname)
+}
+
+/// Returns the first value of each parameter of `pairs`
+fn first_values(pairs: &[(String, String)]) -> HashMap<String, String> {
+    let mut values = HashMap::with_capacity(pairs.len());
+    for (name, value) in pairs {
+        values.entry(name.clone()).or_insert_with(|| value.clone());
+    }
+    values
+}
+
+/// Returns the values of each parameter of `pairs` in order
+fn all_values(pairs: &[(String, String)]) -> HashMap<String, Vec<String>> {
+    let mut values: HashMap<String, Vec<String>> = HashMap::with_capacity(pairs.len());
+    for (name, value) in pairs {
+        values.entry(name.clone()).or_default().push(value.clone());
+    }
+    values
+}
+
+/// Escapes the characters which have a special meaning in HTML
+pub(crate) fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Lets the response be passed to anything writing into a `fmt::Write`,
+/// errors of `Response::write` become `fmt::Error`
+impl fmt::Write for Response {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write(s).map_err(|_| fmt::Error)
+    }
+}
+
+impl Response {
+    /// Takes back the stream of a response a middleware passed on, together
+    /// with the headers it set, or sends the response if the middleware
+    /// answered with it already
+    fn pass_on(mut self) -> Option<(Stream, Headers)> {
+        if self.completed || self.head_committed || self.head_sent || self.streaming.is_some() {
+            return None;
+        }
+        let stream = self.take_stream();
+        Some((stream, std::mem::replace(&mut self.headers, Headers::new())))
+    }
+
+    /// Takes the stream out of the response, which then sends nothing
+    fn take_stream(&mut self) -> Stream {
+        self.head_sent = true;
+        std::mem::replace(
+            &mut self.stream,
+            Stream::Memory(MemoryStream {
+                input: io::Cursor::new(Vec::new()),
+                output: Arc::new(Mutex::new(Vec::new())),
+                encrypted: false,
+                remaining_requests: 0,
+                sni_hostname: None,
+                #[cfg(feature = "tls")]
+                client_certificate: None,
+                #[cfg(feature = "tokio")]
+                body_feed: None,
+            }),
+        )
+    }
+}
+
+impl Drop for Response {
+    fn drop(&mut self) {
+        // The handler panicked, what it prepared is no answer
+        if thread::panicking() {
+            if self.streaming.is_some() {
+                // Closing without ending the body tells the client it broke
+                return;
+            }
+            if let Some(slot) = self.panic_stream.take().filter(|_| !self.head_sent) {
+                // The `on_error` handler answers instead
+                if let Ok(mut slot) = slot.lock() {
+                    *slot = Some(self.take_stream());
+                    return;
+                }
+            }
+            self.status = Some(500);
+            self.headers = Headers::new();
+            self.body.clear();
+        }
+        let _ = self.timed(Response::send);
+    }
+}
+
+/// The most bytes the status line and headers of a response may take up
+const MAX_RESPONSE_HEAD: usize = 64 * 1024;
+
+/// Page for static files that do not exist
+const NOT_FOUND_PAGE: &str = "<html><h1>404 not found</h1><hr> powered by corrodedweb</html>";
+
+/// Page for static files the server is not allowed to read
+const FORBIDDEN_PAGE: &str = "<html><h1>403 forbidden</h1><hr> powered by corrodedweb</html>";
+
+/// Page for handlers that ran out of time and unreachable upstreams
+const GATEWAY_TIMEOUT_PAGE: &str =
+    "<html><h1>504 gateway timeout</h1><hr> powered by corrodedweb</html>";
+
+/// Page for static files that exist but could not be read
+const INTERNAL_ERROR_PAGE: &str =
+    "<html><h1>500 internal server error</h1><hr> powered by corrodedweb</html>";
+
+type Callback = Arc<dyn Fn(Request, Response) + Send + Sync>;
+
+/// Passes the request on to the next middleware, or to whatever answers it
+/// once all middlewares ran, see `Server::use_middleware`
+pub type Next<'a> = Box<dyn FnOnce(Request, Response) + 'a>;
+
+type Middleware = Arc<dyn Fn(Request, Response, Next<'_>) + Send + Sync>;
+
+type StartCallback = Arc<dyn Fn(&StartupInfo) + Send + Sync>;
+
+type CompleteCallback = Arc<dyn Fn(&RequestSummary) + Send + Sync>;
+
+type BeforeHook = Arc<dyn Fn(&mut Request) + Send + Sync>;
+
+type AfterHook = Arc<dyn Fn(&Request, &ResponseMeta) + Send + Sync>;
+
+type Rewrite = Arc<dyn Fn(&str) -> Option<String> + Send + Sync>;
+
+#[cfg(feature = "access-files")]
+type AuthProvider = Arc<dyn Fn(&Request) -> bool + Send + Sync>;
+
+/// A registered callback together with its optional description
+#[derive(Clone)]
+struct Endpoint {
+    callback: Callback,
+    description: Option<String>,
+    /// Overrides `Server::set_handler_timeout`
+    timeout: Option<Duration>,
+    /// Where the route was registered, for reporting duplicates
+    registered_at: &'static Location<'static>,
+}
+
+type Endpoints = Arc<Mutex<Router<Endpoint>>>;
+
+/// Maps the names given with `RouteBuilder::name` to their patterns
+type RouteNames = Arc<RwLock<HashMap<String, String>>>;
+
+/// Method, path, connection and request id, start and response tally of a
+/// request, for logging it once complete
+type RequestStart = (String, String, u64, u64, Instant, Option<Arc<Mutex<Tally>>>);
+
+/// The values added with `Server::manage`, by type
+type StateMap = Arc<HashMap<TypeId, Arc<dyn Any + Send + Sync>>>;
+
+/// Describes a registered route without exposing its callback
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteInfo {
+    /// The HTTP method, e.g. `GET`
+    pub method: String,
+    /// The route as it was registered, e.g. `/home/`
+    pub pattern: String,
+    /// The description given with `RouteBuilder::describe`
+    pub description: Option<String>,
+}
+
+/// What a server is about to serve, passed to the `Server::on_start`
+/// callback
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StartupInfo {
+    /// The addresses actually listened on, with the port the OS picked if
+    /// port 0 was asked for
+    pub addresses: Vec<SocketAddr>,
+    /// Whether connections are served over HTTPS
+    pub tls: bool,
+    /// How many requests are handled at the same time
+    pub workers: usize,
+    /// The registered routes, as returned by `Server::routes`
+    pub routes: Vec<RouteInfo>,
+}
+
+/// What a listener of a running server speaks, see
+/// `ServerHandle::listeners`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    /// Plain HTTP
+    Http,
+    /// HTTPS, see `Server::set_tls`
+    Https,
+    /// Plain HTTP answering every request with a redirect to HTTPS, see
+    /// `Server::redirect_http_to_https`
+    HttpsRedirect,
+}
+
+/// A socket a running server accepts connections on, see
+/// `ServerHandle::listeners`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ListenerInfo {
+    /// The local address, with the port the OS picked if port 0 was asked
+    /// for
+    pub address: SocketAddr,
+    pub protocol: Protocol,
+}
+
+/// What a request was and how long it took, passed to the
+/// `Server::on_request_complete` callback
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestSummary {
+    /// The method the request was dispatched with, see `Request::method`
+    pub method: String,
+    /// The path the request was routed by, see `Request::path`
+    pub path: String,
+    /// See `Request::connection_id`
+    pub connection_id: u64,
+    /// See `Request::request_id`
+    pub request_id: u64,
+    /// How long the phases of handling the request took, `None` unless
+    /// `Server::set_phase_timings` is on
+    pub timings: Option<PhaseTimings>,
+}
+
+/// How a request was answered, passed to the `Server::after` hooks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResponseMeta {
+    /// The status sent, `None` if no response was sent, e.g. because the
+    /// client went away
+    pub status: Option<u32>,
+    /// How many bytes went out after the head, chunk framing included
+    pub bytes_written: u64,
+    /// How long it took from reading the request to sending the response
+    pub duration: Duration,
+}
+
+/// Returned by the route registering methods of `Server`, allows to attach
+/// additional information to the route
+///
+/// # Example
+///
+/// ```
+/// use corrodedweb::Server;
+/// let mut s = Server::new();
+/// s.get("/", |request, mut response| {}).describe("The homepage");
+/// ```
+pub struct RouteBuilder {
+    endpoints: Endpoints,
+    names: RouteNames,
+    key: Option<(String, String)>,
+}
+
+impl RouteBuilder {
+    /// Sets a human readable description which is returned by `Server::routes()`
+    ///
+    /// Does nothing if the registration was ignored as a duplicate.
+    pub fn describe(self, description: &str) -> Self {
+        if let Some((method, route)) = &self.key {
+            if let Some(endpoint) = self.endpoints.lock().unwrap().find_mut(method, route) {
+                endpoint.description = Some(String::from(description));
+            }
+        }
+        self
+    }
+
+    /// Answers `504 Gateway Timeout` if the handler takes longer than
+    /// `timeout`, instead of the default set with
+    /// `Server::set_handler_timeout`
+    ///
+    /// Does nothing if the registration was ignored as a duplicate.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// use std::time::Duration;
+    /// let mut s = Server::new();
+    /// s.get("/report/", |request, mut response| {})
+    ///     .timeout(Duration::from_secs(30));
+    /// ```
+    pub fn timeout(self, timeout: Duration) -> Self {
+        if let Some((method, route)) = &self.key {
+            if let Some(endpoint) = self.endpoints.lock().unwrap().find_mut(method, route) {
+                endpoint.timeout = Some(timeout);
+            }
+        }
+        self
+    }
+
+    /// Names the route, so `Server::url_for` and `Request::url_for` build
+    /// URLs of it instead of handlers spelling out its path
+    ///
+    /// Naming another route the same replaces the earlier one. Does nothing
+    /// if the registration was ignored as a duplicate.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.get("/users/:id/", |request, mut response| {}).name("user_detail");
+    /// assert_eq!(s.url_for("user_detail", &[("id", "42")]).unwrap(), "/users/42/");
+    /// ```
+    pub fn name(self, name: &str) -> Self {
+        if let Some((_, route)) = &self.key {
+            self.names
+                .write()
+                .unwrap()
+                .insert(String::from(name), route.clone());
+        }
+        self
+    }
+}
+
+/// Decides what happens when a route is registered twice for the same method
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicateRoutePolicy {
+    /// The new callback replaces the old one and a warning is logged (default)
+    #[default]
+    Replace,
+    /// Registering a duplicate panics, to fail fast during startup, with
+    /// both places the route was registered at in the message
+    Panic,
+    /// The new callback is dropped and a warning is logged
+    Ignore,
+}
+
+/// Decides whether registered routes or files in the document root answer
+/// a path both of them have
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum StaticPrecedence {
+    /// Routes are looked up first, files only answer paths without a route
+    /// (default)
+    #[default]
+    RoutesFirst,
+    /// A file answers GET and HEAD requests for its path, even if a route
+    /// matches it
+    StaticFirst,
+}
+
+/// Decides what happens to a request whose path only has a route or a
+/// directory with the trailing slash toggled, see
+/// `Server::set_trailing_slash_policy`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TrailingSlashPolicy {
+    /// The request is answered as if it had the trailing slash toggled
+    /// (default)
+    #[default]
+    Match,
+    /// The client is redirected to the path with the slash toggled
+    Redirect,
+    /// Paths have to match exactly
+    Strict,
+}
+
+/// Decides what answers a request for a fingerprinted asset whose hash is
+/// not the current one, see `Server::enable_asset_fingerprinting`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum StaleAssetPolicy {
+    /// `404 Not Found`, like any other missing file (default)
+    #[default]
+    NotFound,
+    /// A temporary redirect to the URL with the current hash
+    Redirect,
+}
+
+/// What answers a request for a fingerprinted asset
+enum AssetAnswer {
+    /// The file at this path, which the hash in the URL matches
+    Serve(String),
+    /// A redirect to the URL with the current hash
+    Redirect(String),
+}
+
+/// Decides what happens to new connections while the connection limit is
+/// reached
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionLimitPolicy {
+    /// New connections are not accepted until a slot frees up, so they wait
+    /// in the kernel's backlog (default)
+    #[default]
+    Defer,
+    /// New connections are accepted and answered with
+    /// `503 Service Unavailable` right away
+    Reject,
+}
+
+/// How long connections stay open for further requests and for how many,
+/// see `Server::set_keep_alive`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeepAlive {
+    /// How long a connection may sit idle between requests before it is
+    /// closed, 60 seconds by default
+    pub idle_timeout: Duration,
+    /// How many requests a connection takes, the response to the last one
+    /// says `Connection: close`, 1000 by default
+    pub max_requests: usize,
+}
+
+impl KeepAlive {
+    /// Closes every connection after its first response
+    pub fn disabled() -> Self {
+        KeepAlive {
+            max_requests: 1,
+            ..KeepAlive::default()
+        }
+    }
+}
+
+impl Default for KeepAlive {
+    fn default() -> Self {
+        KeepAlive {
+            idle_timeout: Duration::from_secs(60),
+            max_requests: 1000,
+        }
+    }
+}
+
+/// An address or a network, e.g. a proxy whose forwarding headers are
+/// believed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct IpNetwork {
+    network: IpAddr,
+    prefix_len: u32,
+}
+
+impl IpNetwork {
+    /// Parses an address like `10.0.0.1` or a network like `10.0.0.0/8`
+    fn parse(proxy: &str) -> Option<Self> {
+        let (network, prefix_len) = match proxy.split_once('/') {
+            Some((network, prefix_len)) => (network, Some(prefix_len.parse().ok()?)),
+            None => (proxy, None),
+        };
+        let network: IpAddr = network.parse().ok()?;
+        let max_len = if network.is_ipv4() { 32 } else { 128 };
+        let prefix_len = prefix_len.unwrap_or(max_len);
+        if prefix_len > max_len {
+            return None;
+        }
+        Some(IpNetwork {
+            network,
+            prefix_len,
+        })
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        // Shifting by the full width means no bits are compared
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix_len).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix_len).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// How a directory of static files answers requests for its directories,
+/// see `Server::serve_static_with`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StaticOptions {
+    /// Whether directories without an index file are listed, otherwise
+    /// they are answered with 403
+    pub index_of: bool,
+    /// The files served for requests of a directory, the first one the
+    /// directory has wins
+    pub index_files: Vec<String>,
+}
+
+/// Lists nothing and serves `index.html` or `index.htm`
+impl Default for StaticOptions {
+    fn default() -> Self {
+        StaticOptions {
+            index_of: false,
+            index_files: DEFAULT_INDEX_FILES
+                .iter()
+                .map(|&name| String::from(name))
+                .collect(),
+        }
+    }
+}
+
+/// A directory of static files served below a path prefix, see
+/// `Server::serve_static`
+#[derive(Debug, Clone)]
+struct StaticMount {
+    /// Without a trailing slash
+    prefix: String,
+    /// With a trailing slash, as paths inside it are appended
+    directory: PathBuf,
+    options: StaticOptions,
+}
+
+impl StaticMount {
+    /// Returns the path inside the directory if `path` is below the prefix
+    fn path_inside<'a>(&self, path: &'a str) -> Option<&'a str> {
+        path.strip_prefix(&self.prefix)
+            .filter(|rest| rest.is_empty() || rest.starts_with('/'))
+    }
+}
+
+/// Returns the first of `names` that is no plain file name, so cannot name
+/// an index file
+fn invalid_index_file<'a>(names: &[&'a str]) -> Option<&'a str> {
+    names
+        .iter()
+        .find(|name| {
+            name.is_empty() || **name == "." || **name == ".." || name.contains(['/', '\\'])
+        })
+        .copied()
+}
+
+/// Shortest `max_age` the HSTS preload list accepts
+const HSTS_PRELOAD_MIN_AGE: Duration = Duration::from_secs(31_536_000);
+
+/// Settings of the `Strict-Transport-Security` header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HstsConfig {
+    /// How long browsers remember to only use HTTPS
+    pub max_age: Duration,
+    /// Whether the rule covers all subdomains as well
+    pub include_subdomains: bool,
+    /// Whether the host may be put on the browsers' built-in HTTPS list
+    pub preload: bool,
+}
+
+impl HstsConfig {
+    fn header_value(&self) -> String {
+        let mut value = format!("max-age={}", self.max_age.as_secs());
+        if self.include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+        if self.preload {
+            value.push_str("; preload");
+        }
+        value
+    }
+}
+
+/// Which other origins browsers let call the routes of the server, see
+/// `Server::enable_cors`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CorsConfig {
+    /// Origins like `https://app.example.com`, `*` allows every origin
+    pub allowed_origins: Vec<String>,
+    /// Methods a preflight allows, empty for those of the route
+    pub allowed_methods: Vec<String>,
+    /// Request headers a preflight allows, empty for those the browser asks
+    /// for
+    pub allowed_headers: Vec<String>,
+    /// Whether browsers send cookies and credentials along
+    pub allow_credentials: bool,
+    /// How long browsers may keep the answer to a preflight, `None` for
+    /// their default
+    pub max_age: Option<Duration>,
+}
+
+impl CorsConfig {
+    fn allows_any_origin(&self) -> bool {
+        self.allowed_origins.iter().any(|origin| origin == "*")
+    }
+
+    /// Returns the `Access-Control-Allow-Origin` header value for a request
+    /// from `origin`, `None` if the origin is not allowed
+    fn allowed_origin<'a>(&self, origin: &'a str) -> Option<&'a str> {
+        if self.allows_any_origin() {
+            Some("*")
+        } else {
+            self.allowed_origins
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(origin))
+                .then_some(origin)
+        }
+    }
+
+    /// Returns the CORS headers of every response to `request`
+    fn headers(&self, request: &Request) -> Vec<(String, String)> {
+        let mut headers = Vec::new();
+        // The answer depends on the origin unless every origin gets the same
+        if !self.allows_any_origin() {
+            headers.push((String::from("Vary"), String::from("Origin")));
+        }
+        let allowed = request
+            .get_header("Origin")
+            .and_then(|origin| self.allowed_origin(origin));
+        if let Some(allowed) = allowed {
+            headers.push((
+                String::from("Access-Control-Allow-Origin"),
+                String::from(allowed),
+            ));
+            if self.allow_credentials {
+                headers.push((
+                    String::from("Access-Control-Allow-Credentials"),
+                    String::from("true"),
+                ));
+            }
+        }
+        headers
+    }
+
+    /// Returns the headers answering a preflight for a route allowing
+    /// `methods`, none if `request` is no preflight from an allowed origin
+    fn preflight_headers(&self, request: &Request, methods: &[String]) -> Vec<(String, String)> {
+        let allowed = request
+            .get_header("Origin")
+            .and_then(|origin| self.allowed_origin(origin));
+        if allowed.is_none()
+            || request
+                .get_header("Access-Control-Request-Method")
+                .is_none()
+        {
+            return Vec::new();
+        }
+        let methods = if self.allowed_methods.is_empty() {
+            methods.join(", ")
+        } else {
+            self.allowed_methods.join(", ")
+        };
+        let mut headers = vec![(String::from("Access-Control-Allow-Methods"), methods)];
+        let allowed_headers = if self.allowed_headers.is_empty() {
+            request
+                .get_header("Access-Control-Request-Headers")
+                .map(String::from)
+        } else {
+            Some(self.allowed_headers.join(", "))
+        };
+        if let Some(allowed_headers) = allowed_headers {
+            headers.push((
+                String::from("Access-Control-Allow-Headers"),
+                allowed_headers,
+            ));
+        }
+        if let Some(max_age) = self.max_age {
+            headers.push((
+                String::from("Access-Control-Max-Age"),
+                max_age.as_secs().to_string(),
+            ));
+        }
+        headers
+    }
+}
+
+/// Settings of a route forwarded with `Server::proxy_with`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProxyConfig {
+    /// Whether the prefix is removed from the path before forwarding, so
+    /// `/api/users` reaches the upstream as `/users`
+    pub strip_prefix: bool,
+    /// How long connecting to and each read from the upstream may take
+    /// before the client gets a `504`
+    pub timeout: Duration,
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        ProxyConfig {
+            strip_prefix: false,
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// A path prefix whose requests are forwarded to an upstream
+#[derive(Clone)]
+struct ProxyRoute {
+    /// Without a trailing slash
+    prefix: String,
+    strip_prefix: bool,
+    upstream: Arc<Upstream>,
+}
+
+impl ProxyRoute {
+    /// Returns the path to forward if `path` lies under the prefix
+    fn forwarded_path<'a>(&self, path: &'a str) -> Option<&'a str> {
+        let rest = path.strip_prefix(&self.prefix)?;
+        if !rest.is_empty() && !rest.starts_with('/') {
+            return None;
+        }
+        if !self.strip_prefix {
+            Some(path)
+        } else if rest.is_empty() {
+            Some("/")
+        } else {
+            Some(rest)
+        }
+    }
+}
+
+/// A middleware registered with `Server::use_middleware` or
+/// `Server::use_middleware_at`
+#[derive(Clone)]
+struct Layer {
+    /// Without a trailing slash, empty for all paths
+    prefix: String,
+    middleware: Middleware,
+}
+
+impl Layer {
+    /// Returns whether the middleware runs for `path`
+    fn applies_to(&self, path: &str) -> bool {
+        path.strip_prefix(&self.prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    }
+}
+
+/// Runs the first of `layers`, whose `next` runs the rest, and leaves the
+/// request in `passed` if the last one passes it on
+fn run_layers<'a>(
+    layers: &'a [&Layer],
+    request: Request,
+    response: Response,
+    passed: &'a Cell<Option<(Request, Response)>>,
+) {
+    match layers.split_first() {
+        Some((layer, rest)) => (layer.middleware)(
+            request,
+            response,
+            Box::new(move |request, response| run_layers(rest, request, response, passed)),
+        ),
+        None => passed.set(Some((request, response))),
+    }
+}
+
+/// A redirect declared with `Server::redirect_rule` or
+/// `Server::redirect_prefix`
+#[derive(Clone)]
+struct RedirectRule {
+    from: String,
+    to: String,
+    status: u32,
+    /// Whether paths below `from` are redirected too, keeping their remainder
+    prefix: bool,
+}
+
+impl RedirectRule {
+    /// Returns where `path` is redirected to, if the rule applies to it
+    fn location(&self, path: &str) -> Option<String> {
+        if !self.prefix {
+            return if path == self.from {
+                Some(self.to.clone())
+            } else {
+                None
+            };
+        }
+        let rest = path.strip_prefix(&self.from)?;
+        if !rest.is_empty() && !self.from.ends_with('/') && !rest.starts_with('/') {
+            return None;
+        }
+        Some(format!("{}{}", self.to, rest))
+    }
+}
+
+/// Changes settings of a running server and controls its lifecycle,
+/// obtained with `Server::handle` or `Server::spawn`
+///
+/// The handle can be cloned and moved to other threads or into handlers.
+/// Dropping it leaves the server running, only `shutdown` stops it.
+#[derive(Clone)]
+pub struct ServerHandle {
+    document_root: Arc<RwLock<Option<PathBuf>>>,
+    follow_symlinks: bool,
+    assets: Arc<Assets>,
+    logger: Option<Logger>,
+    shutdown: ShutdownHandle,
+    ready: Arc<Readiness>,
+}
+
+impl ServerHandle {
+    /// Switches static files over to another document root, like
+    /// `Server::set_document_root` does before the server starts
+    ///
+    /// Requests arriving afterwards are served from the new root, requests
+    /// in flight finish with the one they started with. Returns false and
+    /// keeps the old root if the path does not exist.
+    pub fn set_document_root(&self, document_root: &str) -> bool {
+        replace_document_root(&self.document_root, &self.logger, document_root)
+    }
+
+    /// Returns the document root static files are served from
+    pub fn document_root(&self) -> Option<PathBuf> {
+        self.document_root.read().unwrap().clone()
+    }
+
+    /// Returns the URL of a static file with its content hash, like
+    /// `Server::asset_url`
+    ///
+    /// Meant for handlers generating HTML, which have no `Server` to ask.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.enable_asset_fingerprinting("/static/");
+    /// let handle = s.handle();
+    /// s.get("/", move |_request, mut response| {
+    ///     let _ = response.write(&format!(
+    ///         "<link rel=\"stylesheet\" href=\"{}\">",
+    ///         handle.asset_url("/static/app.css")
+    ///     ));
+    /// });
+    /// ```
+    pub fn asset_url(&self, path: &str) -> String {
+        asset_url(
+            &self.assets,
+            &self.document_root,
+            self.follow_symlinks,
+            path,
+        )
+    }
+
+    /// Stops every listener of the server at once, like
+    /// `ShutdownHandle::shutdown`
+    ///
+    /// Returns right away, `join` waits until the server stopped.
+    pub fn shutdown(&self) {
+        self.shutdown.shutdown();
+    }
+
+    /// Blocks until the server stopped, i.e. every accept loop exited and
+    /// the running requests finished or were abandoned after the shutdown
+    /// deadline
+    ///
+    /// Waits for the server to start first if it has not yet.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let handle = Server::new().spawn(0).unwrap();
+    /// handle.shutdown();
+    /// handle.join();
+    /// ```
+    pub fn join(&self) {
+        self.ready.wait_for(|state| state.stopped);
+    }
+
+    /// Returns the sockets the server accepts connections on, empty before
+    /// it started and after it stopped
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::{Protocol, Server};
+    /// let mut s = Server::new();
+    /// s.add_listen_addr("127.0.0.1:0");
+    /// let handle = s.spawn(0).unwrap();
+    /// let listeners = handle.listeners();
+    /// assert_eq!(listeners.len(), 2);
+    /// assert!(listeners.iter().all(|listener| listener.protocol == Protocol::Http));
+    /// handle.shutdown();
+    /// handle.join();
+    /// ```
+    pub fn listeners(&self) -> Vec<ListenerInfo> {
+        self.ready.state.lock().unwrap().listeners.clone()
+    }
+
+    /// Waits until the server accepts connections and returns the address
+    /// it listens on, but no longer than `timeout`
+    ///
+    /// Useful after starting on port 0, which lets the system pick a free
+    /// port. With several listeners, the address of the first is returned.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// use std::time::Duration;
+    /// let s = Server::new();
+    /// let handle = s.handle();
+    /// let shutdown = s.shutdown_handle();
+    /// let serving = std::thread::spawn(move || s.start_server(0));
+    /// let address = handle.wait_until_ready(Duration::from_secs(10)).unwrap();
+    /// assert_ne!(address.port(), 0);
+    /// shutdown.shutdown();
+    /// serving.join().unwrap().unwrap();
+    /// ```
+    pub fn wait_until_ready(&self, timeout: Duration) -> Result<SocketAddr, Timeout> {
+        let end = Instant::now() + timeout;
+        let mut state = self.ready.state.lock().unwrap();
+        loop {
+            if let Some(address) = state.address {
+                return Ok(address);
+            }
+            let remaining = end.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(Timeout);
+            }
+            state = self.ready.changed.wait_timeout(state, remaining).unwrap().0;
+        }
+    }
+}
+
+/// Where a server accepts connections once it does, and whether it stopped
+/// again
+#[derive(Default)]
+struct Readiness {
+    state: Mutex<Lifecycle>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct Lifecycle {
+    /// The address of the first listener, set once connections are accepted
+    address: Option<SocketAddr>,
+    listeners: Vec<ListenerInfo>,
+    stopped: bool,
+}
+
+impl Readiness {
+    fn update(&self, change: impl FnOnce(&mut Lifecycle)) {
+        change(&mut self.state.lock().unwrap());
+        self.changed.notify_all();
+    }
+
+    /// Blocks until `reached` holds
+    fn wait_for(&self, reached: impl Fn(&Lifecycle) -> bool) {
+        let mut state = self.state.lock().unwrap();
+        while !reached(&state) {
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+}
+
+/// The server did not start listening in time, see
+/// `ServerHandle::wait_until_ready`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeout;
+
+impl fmt::Display for Timeout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the server did not start listening in time")
+    }
+}
+
+impl std::error::Error for Timeout {}
+
+/// Checks that `document_root` exists and makes it the root static files
+/// are served from
+fn replace_document_root(
+    root: &RwLock<Option<PathBuf>>,
+    logger: &Option<Logger>,
+    document_root: &str,
+) -> bool {
+    let path = PathBuf::from(document_root);
+    if !path.exists() {
+        Logger::warning(
+            logger,
+            &format!("document_root {} is not valid", document_root),
+        );
+        return false;
+    }
+    match path.to_str() {
+        Some(path) => Logger::info(logger, &format!("New document_root was set to {}", path)),
+        None => Logger::warning(logger, "New document_root is not UTF-8 valid"),
+    }
+    *root.write().unwrap() = Some(path);
+    true
+}
+
+/// Returns the URL of the file at `path` with its content hash, or `path`
+/// itself if it is not a fingerprinted file
+fn asset_url(
+    assets: &Assets,
+    root: &RwLock<Option<PathBuf>>,
+    follow_symlinks: bool,
+    path: &str,
+) -> String {
+    let root = root.read().unwrap().clone();
+    let hash = root
+        .filter(|_| assets.covers(path))
+        .and_then(|root| static_file(&root, path, follow_symlinks))
+        .and_then(|file| assets.hash(&file).ok());
+    match hash {
+        Some(hash) => assets::fingerprinted(path, &hash),
+        None => String::from(path),
+    }
+}
+
+/// A file from the document root answering an error, see
+/// `Server::set_error_page`
+#[derive(Clone)]
+struct ErrorPage {
+    path: PathBuf,
+    /// Set once the page was found missing, so that is logged only once
+    missing_logged: Arc<AtomicBool>,
+}
+
+/// How requests for `/favicon.ico` are answered when no route handles them
+#[derive(Clone)]
+enum Favicon {
+    /// With this file, see `Server::set_favicon`
+    File(PathBuf),
+    /// With `204 No Content`, see `Server::ignore_favicon`
+    Ignored,
+}
+
+/// Whether browsers display a static file or save it, see
+/// `Server::force_download_extensions`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Disposition {
+    Inline,
+    Attachment,
+}
+
+/// Returns the media type of the file at `path` from its extension, one
+/// added with `Server::add_mime_type` or a built-in one
+fn media_type_of<'a>(path: &Path, added: &'a HashMap<String, String>) -> &'a str {
+    let added = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .and_then(|extension| added.get(&extension.to_ascii_lowercase()));
+    match added {
+        Some(media_type) => media_type.as_str(),
+        None => mime::from_path(path).unwrap_or(mime::UNKNOWN),
+    }
+}
+
+/// Returns `path` with a trailing slash removed, or added if it has none
+fn toggle_slash(path: &str) -> String {
+    match path.strip_suffix('/') {
+        Some(path) => String::from(path),
+        None => format!("{}/", path),
+    }
+}
+
+/// Returns the status redirecting to the path with the trailing slash
+/// toggled, one that keeps the method for methods other than GET and HEAD
+fn slash_redirect_status(request: &Request) -> u32 {
+    match request.method.as_str() {
+        "GET" | "HEAD" => 301,
+        _ => 308,
+    }
+}
+
+/// Returns the value of a `Content-Disposition` header naming `file_name`,
+/// RFC 6266
+///
+/// Names that are not plain ASCII get an ASCII fallback and the exact name
+/// percent-encoded in `filename*`.
+fn content_disposition(disposition: Disposition, file_name: &str) -> String {
+    let kind = match disposition {
+        Disposition::Inline => "inline",
+        Disposition::Attachment => "attachment",
+    };
+    let mut fallback = String::new();
+    for c in file_name.chars() {
+        match c {
+            '"' | '\\' => {
+                fallback.push('\\');
+                fallback.push(c);
+            }
+            c if c.is_ascii() && !c.is_ascii_control() => fallback.push(c),
+            _ => fallback.push('_'),
+        }
+    }
+    let mut value = format!("{}; filename=\"{}\"", kind, fallback);
+    if file_name
+        .chars()
+        .any(|c| !c.is_ascii() || c.is_ascii_control())
+    {
+        value.push_str("; filename*=UTF-8''");
+        for byte in file_name.bytes() {
+            if byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&byte) {
+                value.push(byte as char);
+            } else {
+                value.push_str(&format!("%{:02X}", byte));
+            }
+        }
+    }
+    value
+}
+
+/// How long clients may cache the favicon
+const FAVICON_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Returns the location of the rule applying to `path`: an exact rule, or
+/// else the one with the longest prefix
+fn find_redirect<'a>(rules: &'a [RedirectRule], path: &str) -> Option<(&'a RedirectRule, String)> {
+    let exact = rules
+        .iter()
+        .filter(|rule| !rule.prefix)
+        .find_map(|rule| Some((rule, rule.location(path)?)));
+    exact.or_else(|| {
+        rules
+            .iter()
+            .filter(|rule| rule.prefix)
+            .filter_map(|rule| Some((rule, rule.location(path)?)))
+            .max_by_key(|(rule, _)| rule.from.len())
+    })
+}
+
+/// Returns whether following the rules from `start` never ends
+///
+/// Only local targets are followed. A chain with more redirects than there
+/// are rules applies one of them twice, which a prefix rule like `/a/` to
+/// `/a/b/` does without ever revisiting a path.
+fn redirects_in_loop(rules: &[RedirectRule], start: &str) -> bool {
+    let mut visited = vec![String::from(start)];
+    for _ in 0..=rules.len() {
+        let current = &visited[visited.len() - 1];
+        let location = match find_redirect(rules, current) {
+            Some((_, location)) => location,
+            None => return false,
+        };
+        if !location.starts_with('/') || location.starts_with("//") {
+            return false;
+        }
+        let path = location.split(['?', '#']).next().unwrap_or("");
+        if visited.iter().any(|visited| visited == path) {
+            return true;
+        }
+        visited.push(String::from(path));
+    }
+    true
+}
+
+/// Appends the query of a request to a redirect location
+fn with_query(location: String, query: Option<&str>) -> String {
+    match query {
+        Some(query) if !query.is_empty() => {
+            let separator = if location.contains('?') { '&' } else { '?' };
+            format!("{}{}{}", location, separator, query)
+        }
+        _ => location,
+    }
+}
+
+/// Counts the open connections against the configured maximum
+#[cfg(not(feature = "tokio"))]
+struct ConnectionSlots {
+    max: usize,
+    open: Mutex<usize>,
+    freed: Condvar,
+}
+
+#[cfg(not(feature = "tokio"))]
+impl ConnectionSlots {
+    /// Takes a slot if one is free
+    fn try_acquire(slots: &Arc<ConnectionSlots>) -> Option<ConnectionSlot> {
+        let mut open = slots.open.lock().unwrap();
+        if *open < slots.max {
+            *open += 1;
+            Some(ConnectionSlot(slots.clone()))
+        } else {
+            None
+        }
+    }
+
+    /// Blocks until a slot is free
+    fn wait_for_free(&self) {
+        let mut open = self.open.lock().unwrap();
+        while *open >= self.max {
+            open = self.freed.wait(open).unwrap();
+        }
+    }
+}
+
+/// An occupied connection slot, freed when the connection is done
+#[cfg(not(feature = "tokio"))]
+struct ConnectionSlot(Arc<ConnectionSlots>);
+
+#[cfg(not(feature = "tokio"))]
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        *self.0.open.lock().unwrap() -= 1;
+        self.0.freed.notify_one();
+    }
+}
+
+/// How much of a static text file is checked for valid UTF-8
+const CHARSET_SNIFF_LEN: u64 = 1024;
+
+/// The port `start` listens on unless configured otherwise
+const DEFAULT_PORT: u16 = 7878;
+
+/// The files served for requests of a directory unless configured otherwise
+const DEFAULT_INDEX_FILES: &[&str] = &["index.html", "index.htm"];
+
+/// How many requests are at least handled at the same time unless
+/// configured otherwise, workers mostly wait for their clients
+const WORKERS: usize = 8;
+
+/// Returns how many requests are handled at the same time unless configured
+/// otherwise, one per CPU but at least `WORKERS`
+fn default_workers() -> usize {
+    thread::available_parallelism().map_or(WORKERS, |cpus| cpus.get().max(WORKERS))
+}
+
+/// How long clients turned away with 503 are asked to wait unless
+/// configured otherwise
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(5);
+
+/// Serializes the answer for clients the server turns away, once, so
+/// sending it costs nothing but the write while the server is overloaded
+fn service_unavailable(retry_after: Duration) -> Arc<str> {
+    Arc::from(format!(
+        "HTTP/1.1 503 Service Unavailable\r\nRetry-After: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        retry_after.as_secs().max(1)
+    ))
+}
+
+/// The answer to clients that take too long to send their request
+pub(crate) const REQUEST_TIMEOUT: &str =
+    "HTTP/1.1 408 Request Timeout\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+
+/// The answer to requests the server cannot make sense of
+const BAD_REQUEST: &str =
+    "HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+
+/// The answer to `CONNECT`, the server is no forward proxy
+const NOT_IMPLEMENTED: &str =
+    "HTTP/1.1 501 Not Implemented\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+
+/// The methods the server knows, in the order `Allow` headers list them.
+/// Static files answer others with 501.
+const KNOWN_METHODS: [&str; 7] = ["GET", "HEAD", "POST", "PUT", "PATCH", "DELETE", "OPTIONS"];
+
+/// The answer to requests whose body exceeds `Server::set_max_body_size`
+const PAYLOAD_TOO_LARGE: &str =
+    "HTTP/1.1 413 Content Too Large\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+
+/// How large URL-encoded forms, which are read completely before their
+/// handler runs, may be unless `Server::set_max_body_size` says otherwise
+const DEFAULT_MAX_FORM_SIZE: u64 = 8 * 1024 * 1024;
+
+/// The answer to requests whose head exceeds the header limits
+pub(crate) const HEADERS_TOO_LARGE: &str = "HTTP/1.1 431 Request Header Fields Too Large\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+
+/// How long fingerprinted assets may be cached, see
+/// `Server::enable_asset_fingerprinting`
+const IMMUTABLE_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+/// How long running requests may take to finish after a shutdown unless
+/// configured otherwise
+const DEFAULT_SHUTDOWN_DEADLINE: Duration = Duration::from_secs(30);
+
+/// How long a write to a client may block unless configured otherwise
+const DEFAULT_WRITE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long a client may take to send the PROXY protocol header
+const PROXY_HEADER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often a connection of the blocking server waiting for its next
+/// request looks whether the server shuts down
+const IDLE_POLL: Duration = Duration::from_millis(100);
+
+/// How many rejected connections may wait for their 503
+#[cfg(not(feature = "tokio"))]
+const REJECT_QUEUE: usize = 64;
+
+/// Starts the thread answering rejected connections with 503
+///
+/// Closing a socket with unread request bytes makes the kernel reset the
+/// connection, which can destroy the 503 before the client read it. So the
+/// request is drained after answering, which must not stall the accept loop.
+#[cfg(not(feature = "tokio"))]
+fn spawn_rejecter(response: Arc<str>, metrics: Metrics) -> SyncSender<TcpStream> {
+    let (sender, receiver) = sync_channel::<TcpStream>(REJECT_QUEUE);
+    thread::spawn(move || {
+        for mut stream in receiver {
+            metrics.count_service_unavailable();
+            let _ = stream.write_all(response.as_bytes());
+            let _ = stream.shutdown(Shutdown::Write);
+            let _ = stream.set_read_timeout(Some(Duration::from_millis(100)));
+            let mut sink = [0; 1024];
+            while let Ok(1..) = stream.read(&mut sink) {}
+        }
+    });
+    sender
+}
+
+/// Represents the web-framemorks server. The most important struct.
+#[derive(Clone)]
+pub struct Server {
+    port: Option<u16>,
+    /// Shared with every `ServerHandle`, so it can change while serving
+    document_root: Arc<RwLock<Option<PathBuf>>>,
+    logger: Option<Logger>,
+    access_log: Option<AccessLog>,
+    /// How the document root answers requests for its directories
+    static_options: StaticOptions,
+    /// Whether directory listings include names starting with a dot
+    show_hidden: bool,
+    /// Whether static files may be symbolic links leading out of their
+    /// directory
+    follow_symlinks: bool,
+    /// Served below their prefixes instead of the document root
+    static_mounts: Vec<StaticMount>,
+    default_headers: Headers,
+    default_charset: Option<String>,
+    #[cfg(feature = "mmap")]
+    mmap: bool,
+    stat_cache: Option<Arc<StatCache>>,
+    file_cache: Option<Arc<FileCache>>,
+    #[cfg(any(feature = "tls", feature = "native-tls"))]
+    tls: Option<TlsConfig>,
+    #[cfg(any(feature = "tls", feature = "native-tls"))]
+    tls_reload_interval: Option<Duration>,
+    route_listing: Option<String>,
+    duplicate_route_policy: DuplicateRoutePolicy,
+    static_precedence: StaticPrecedence,
+    trailing_slash_policy: TrailingSlashPolicy,
+    assets: Arc<Assets>,
+    stale_asset_policy: StaleAssetPolicy,
+    static_cache_control: Option<String>,
+    max_connections: Option<usize>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    /// Clients the rate limit does not apply to
+    rate_limit_exempt: Vec<IpNetwork>,
+    max_queued_connections: Option<usize>,
+    https_redirect: Option<(u16, Option<String>)>,
+    hsts: Option<HstsConfig>,
+    cors: Option<CorsConfig>,
+    proxy_protocol: bool,
+    trusted_proxies: Vec<IpNetwork>,
+    templates: Option<Arc<Templates>>,
+    /// Kept to create the templates with, see `reload_templates`
+    reload_templates: bool,
+    proxy_routes: Vec<ProxyRoute>,
+    redirect_rules: Vec<RedirectRule>,
+    rewrites: Vec<Rewrite>,
+    error_pages: HashMap<u32, ErrorPage>,
+    favicon: Option<Favicon>,
+    /// Keyed by lowercase extension
+    dispositions: HashMap<String, Disposition>,
+    /// Keyed by lowercase extension, checked before the built-in types
+    mime_types: Arc<HashMap<String, String>>,
+    connection_limit_policy: ConnectionLimitPolicy,
+    method_override: bool,
+    not_found_handler: Option<Callback>,
+    error_handler: Option<Callback>,
+    /// In the order they were registered
+    middlewares: Vec<Layer>,
+    protections: Vec<Protection>,
+    on_start: Option<StartCallback>,
+    on_request_complete: Option<CompleteCallback>,
+    /// In the order they were registered
+    before_hooks: Vec<BeforeHook>,
+    after_hooks: Vec<AfterHook>,
+    server_timing: bool,
+    shutdown: ShutdownHandle,
+    shutdown_deadline: Duration,
+    /// The serialized 503 answer, see `set_retry_after`
+    service_unavailable: Arc<str>,
+    #[cfg(feature = "signals")]
+    shutdown_on_signals: bool,
+    #[cfg(feature = "gzip")]
+    compression: bool,
+    #[cfg(feature = "access-files")]
+    access_files: Option<Arc<AccessFiles>>,
+    #[cfg(feature = "access-files")]
+    auth_provider: Option<AuthProvider>,
+    request_timeouts: RequestTimeouts,
+    /// Zero for none
+    write_timeout: Duration,
+    header_limits: HeaderLimits,
+    max_body_size: Option<u64>,
+    keep_alive: KeepAlive,
+    strict_parsing: bool,
+    handler_timeout: Option<Duration>,
+    debug_dump: DumpSettings,
+    phase_timings: bool,
+    sessions: Option<SessionStore>,
+    workers: usize,
+    canonical_host: Option<String>,
+    /// Bound next to the address passed to `start_server`
+    listen_addrs: Vec<Vec<SocketAddr>>,
+    metrics: Metrics,
+    registered_endpoints: Endpoints,
+    route_names: RouteNames,
+    state: StateMap,
+    /// Set once `start_server` took its snapshot of the routes
+    started: Arc<AtomicBool>,
+    ready: Arc<Readiness>,
+}
+
+impl Server {
+    /// Returns a Server instance
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let c = Server::new();
+    /// ```
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Returns a builder that checks every setting before creating the
+    /// server, see `ServerBuilder`
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let s = Server::builder().port(8080).workers(16).build().unwrap();
+    /// ```
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::new()
+    }
+
+    /// Creates a server from a TOML configuration file
+    ///
+    /// Every key corresponds to a setter, e.g. `document_root`, `port`,
+    /// `index_of` and `log_file`, with the tables `[tls]`, `[https_redirect]`
+    /// and `[hsts]` and one `[[proxy]]` table per proxied prefix. `bind`
+    /// takes an address or an array of them for `add_listen_addr`, and
+    /// `log_level` one of `debug`, `info`, `warning` and `error`. Durations
+    /// are given in seconds or as a string like `"1m 30s"`. Unknown keys are
+    /// logged and skipped, values of the wrong type fail with the path of
+    /// their key.
+    ///
+    /// Environment variables are applied on top of the file, see
+    /// `load_env_config`. Setters called afterwards override both.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use corrodedweb::Server;
+    /// let mut s = Server::from_config_file("corroded.toml").unwrap();
+    /// s.get("/health/", |_, mut response| {
+    ///     let _ = response.write("ok");
+    /// });
+    /// s.start().unwrap();
+    /// ```
+    pub fn from_config_file<P: AsRef<Path>>(path: P) -> Result<Server, ConfigError> {
+        let (mut server, unknown) = config::load(path.as_ref())?;
+        for key in unknown {
+            Logger::warning(
+                &server.logger,
+                &format!("Unknown configuration key {}, ignoring it", key),
+            );
+        }
+        server.load_env_config()?;
+        Ok(server)
+    }
+
+    /// Overrides settings with environment variables
+    ///
+    /// * `CORRODED_PORT`, or `PORT` as set by many hosting platforms
+    /// * `CORRODED_BIND` - addresses for `add_listen_addr`, separated by
+    ///   commas
+    /// * `CORRODED_DOC_ROOT`
+    /// * `CORRODED_INDEX_OF` - `true` or `false`
+    /// * `CORRODED_LOG_FILE`
+    /// * `CORRODED_LOG_LEVEL` - `debug`, `info`, `warning` or `error`
+    /// * `CORRODED_WORKERS`
+    /// * `CORRODED_MAX_CONNECTIONS`
+    /// * `CORRODED_SHUTDOWN_DEADLINE` - in seconds or like `1m 30s`
+    ///
+    /// Empty variables count as unset, other `CORRODED_*` variables are
+    /// logged and skipped. A value that cannot be used fails with the name of
+    /// its variable.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.load_env_config().unwrap();
+    /// ```
+    pub fn load_env_config(&mut self) -> Result<(), ConfigError> {
+        for variable in config::apply_env(self)? {
+            Logger::warning(
+                &self.logger,
+                &format!("Unknown environment variable {}, ignoring it", variable),
+            );
+        }
+        Ok(())
+    }
+
+    /// Sets the port `start` listens on, 7878 by default
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.set_port(8080);
+    /// ```
+    pub fn set_port(&mut self, port: u16) {
+        self.port = Some(port);
+    }
+
+    /// Returns the port set with `set_port` or by the configuration
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
+
+    /// Sets the document root after it is tested by test_document_root()
+    /// function and returns true if successfull
+    ///
+    /// # Arguments
+    ///
+    /// * `document_root` - A string slice that holds the absolute or relative
+    ///   path to the document root
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.set_document_root("/path/to/document/root");
+    /// s.set_document_root("../path/to/document/root");
+    /// ```
+    pub fn set_document_root(&mut self, document_root: &str) -> bool {
+        replace_document_root(&self.document_root, &self.logger, document_root)
+    }
+
+    /// Sets the directory `Response::render` reads templates from and
+    /// returns true, or logs a warning and returns false if it is no
+    /// directory
+    ///
+    /// Templates are read on first use and kept, see `reload_templates`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.set_template_root("./templates/");
+    /// ```
+    pub fn set_template_root(&mut self, path: &str) -> bool {
+        if !Path::new(path).is_dir() {
+            Logger::warning(
+                &self.logger,
+                &format!("Template root {} is no directory", path),
+            );
+            return false;
+        }
+        self.templates = Some(Arc::new(Templates::new(
+            PathBuf::from(path),
+            self.reload_templates,
+        )));
+        true
+    }
+
+    /// Sets whether templates are read again on every `Response::render`
+    /// instead of being kept after the first, e.g. while editing them, off
+    /// by default
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.reload_templates(cfg!(debug_assertions));
+    /// ```
+    pub fn reload_templates(&mut self, reload: bool) {
+        self.reload_templates = reload;
+        if let Some(templates) = &self.templates {
+            self.templates = Some(Arc::new(Templates::new(templates.root().clone(), reload)));
+        }
+    }
+
+    /// Returns the Option containing a valid path as PathBuf or None if invalid
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// match s.get_document_root() {
+    ///     Some(path) => println!("Path is set to {:?}", path),
+    ///     None => println!("No path is set!"),
+    /// };
+    /// ```
+    pub fn get_document_root(&self) -> Option<PathBuf> {
+        self.document_root.read().unwrap().clone()
+    }
+
+    /// Returns a handle that changes settings of the server while it runs
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// let handle = s.handle();
+    /// s.post("/activate/", move |_request, mut response| {
+    ///     if !handle.set_document_root("/srv/releases/124/") {
+    ///         let _ = response.set_status_code(500);
+    ///     }
+    /// });
+    /// ```
+    pub fn handle(&self) -> ServerHandle {
+        ServerHandle {
+            document_root: self.document_root.clone(),
+            follow_symlinks: self.follow_symlinks,
+            assets: self.assets.clone(),
+            logger: self.logger.clone(),
+            shutdown: self.shutdown.clone(),
+            ready: self.ready.clone(),
+        }
+    }
+
+    /// Sets whether to show a list of files, when navigating to a folder
+    ///
+    /// The list is shown only if the folder has none of the index files, see
+    /// `set_index_files`.
+    pub fn use_index_of(&mut self, index_of: bool) {
+        self.static_options.index_of = index_of;
+    }
+
+    /// Sets whether lists of files, see `use_index_of`, include names
+    /// starting with a dot, on by default
+    ///
+    /// Hidden files are still served when requested by name.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.use_index_of(true);
+    /// s.show_hidden(false);
+    /// ```
+    pub fn show_hidden(&mut self, show: bool) {
+        self.show_hidden = show;
+    }
+
+    /// Sets whether static files are served through symbolic links that
+    /// lead out of the document root or the static directory, off by
+    /// default
+    ///
+    /// Off, a file whose resolved path lies outside is refused with `403`.
+    /// On, such links are followed, e.g. into a shared volume of assets,
+    /// while `..` in the requested path is still refused before any link is
+    /// resolved. Broken links are answered with `404` either way.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.follow_symlinks(true);
+    /// ```
+    pub fn follow_symlinks(&mut self, follow: bool) {
+        self.follow_symlinks = follow;
+    }
+
+    /// Sets the files served for requests of a directory, the first one the
+    /// directory has wins, `index.html` and `index.htm` by default
+    ///
+    /// Directories without any of them are listed if `use_index_of` is on
+    /// and answered with 403 otherwise. An empty list always does the
+    /// latter. Returns false without changing anything if a name is empty
+    /// or not a plain file name.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.set_index_files(&["index.html", "default.htm"]);
+    /// ```
+    pub fn set_index_files(&mut self, names: &[&str]) -> bool {
+        if let Some(name) = invalid_index_file(names) {
+            Logger::warning(
+                &self.logger,
+                &format!("{:?} is not the name of an index file", name),
+            );
+            return false;
+        }
+        self.static_options.index_files = names.iter().map(|&name| String::from(name)).collect();
+        true
+    }
+
+    /// Serves the static files in `directory` for requests to `prefix` and
+    /// the paths below it, without listings and with the default index
+    /// files, see `serve_static_with`
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.serve_static("/assets/", "./static/");
+    /// s.serve_static("/downloads/", "/var/data/files/");
+    /// ```
+    pub fn serve_static(&mut self, prefix: &str, directory: &str) -> bool {
+        self.serve_static_with(prefix, directory, StaticOptions::default())
+    }
+
+    /// Serves the static files in `directory` for requests to `prefix` and
+    /// the paths below it, answering requests for directories as `options`
+    /// say
+    ///
+    /// `/assets/app.css` with the prefix `/assets/` is the file `app.css` in
+    /// the directory. Of nested prefixes the longest one serves a request,
+    /// the document root serves those no prefix covers and routes answer
+    /// first as they do for the document root. Paths cannot lead out of the
+    /// directory. Serving a prefix again replaces its directory.
+    ///
+    /// Returns false if `prefix` is `/` or does not start with a slash, which
+    /// the document root is for, `directory` is no directory or an index
+    /// file name is not a plain file name.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::{Server, StaticOptions};
+    /// let mut s = Server::new();
+    /// s.serve_static_with(
+    ///     "/downloads/",
+    ///     "/var/data/files/",
+    ///     StaticOptions {
+    ///         index_of: true,
+    ///         ..StaticOptions::default()
+    ///     },
+    /// );
+    /// ```
+    pub fn serve_static_with(
+        &mut self,
+        prefix: &str,
+        directory: &str,
+        options: StaticOptions,
+    ) -> bool {
+        let trimmed = prefix.trim_end_matches('/');
+        if !prefix.starts_with('/') || trimmed.is_empty() {
+            Logger::warning(
+                &self.logger,
+                &format!(
+                    "Cannot serve static files at {:?}, the prefix has to start with a slash and cannot be /",
+                    prefix
+                ),
+            );
+            return false;
+        }
+        if !Path::new(directory).is_dir() {
+            Logger::warning(
+                &self.logger,
+                &format!(
+                    "Cannot serve static files from {}, it is no directory",
+                    directory
+                ),
+            );
+            return false;
+        }
+        let names: Vec<&str> = options.index_files.iter().map(String::as_str).collect();
+        if let Some(name) = invalid_index_file(&names) {
+            Logger::warning(
+                &self.logger,
+                &format!("{:?} is not the name of an index file", name),
+            );
+            return false;
+        }
+        let mut directory = String::from(directory);
+        if !directory.ends_with('/') {
+            directory.push('/');
+        }
+        self.static_mounts.retain(|mount| mount.prefix != trimmed);
+        self.static_mounts.push(StaticMount {
+            prefix: String::from(trimmed),
+            directory: PathBuf::from(directory),
+            options,
+        });
+        true
+    }
+
+    /// Sets whether `.corroded` files in served directories are honored
+    ///
+    /// Such a file holds one directive per line. It applies to its
+    /// directory and everything below, unless a deeper file sets the same
+    /// directive differently:
+    ///
+    /// * `deny from all` answers 403 to every request, `allow from all`
+    ///   lifts that again
+    /// * `index off` answers 403 instead of a directory listing, `index on`
+    ///   lists directories even without `use_index_of`
+    /// * `require auth` only lets requests through that the auth provider
+    ///   approves, see `set_auth_provider`, `require none` lifts that again
+    /// * `header X-Foo: bar` adds a header to the responses
+    ///
+    /// Lines starting with `#` are comments. A file is parsed again once it
+    /// changes. As long as one cannot be parsed, its directory answers 500
+    /// and a warning says why. The files themselves are never served.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.use_access_files(true);
+    /// ```
+    #[cfg(feature = "access-files")]
+    pub fn use_access_files(&mut self, enabled: bool) {
+        self.access_files = if enabled {
+            Some(Arc::new(AccessFiles::default()))
+        } else {
+            None
+        };
+    }
+
+    /// Compresses response bodies with gzip for clients that accept it
+    ///
+    /// Bodies of handlers and directory listings are compressed if they
+    /// are text, JSON, JavaScript or SVG of at least 1 KB, and are sent with
+    /// `Content-Encoding: gzip` and `Vary: Accept-Encoding`. Images,
+    /// archives and other types that are compressed already go out as they
+    /// are, so do streamed bodies, static files, which keep their ranges and
+    /// zero-copy sending, and bodies a handler set `Content-Encoding` for.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.enable_compression(true);
+    /// ```
+    #[cfg(feature = "gzip")]
+    pub fn enable_compression(&mut self, enabled: bool) {
+        self.compression = enabled;
+    }
+
+    /// Sets who may see directories whose `.corroded` file says
+    /// `require auth`, without a provider nobody may
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.use_access_files(true);
+    /// s.set_auth_provider(|request| request.get_header("X-Token") == Some("secret"));
+    /// ```
+    #[cfg(feature = "access-files")]
+    pub fn set_auth_provider<F>(&mut self, f: F)
+    where
+        F: Fn(&Request) -> bool + Send + Sync + 'static,
+    {
+        self.auth_provider = Some(Arc::new(f));
+    }
+
+    /// Sends static files with `extension` as `media_type`, replacing the
+    /// built-in type of the extension if it has one
+    ///
+    /// Extensions are compared without case, a leading dot is ignored. Files
+    /// with an extension nobody knows are sent as `application/octet-stream`.
+    /// Returns false if the extension is empty or contains a dot or slash,
+    /// or `media_type` is not of the form `type/subtype`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.add_mime_type("md", "text/markdown");
+    /// ```
+    pub fn add_mime_type(&mut self, extension: &str, media_type: &str) -> bool {
+        let extension = extension.strip_prefix('.').unwrap_or(extension);
+        if extension.is_empty() || extension.contains(['.', '/', '\\']) {
+            Logger::warning(
+                &self.logger,
+                &format!("{} is not a file extension", extension),
+            );
+            return false;
+        }
+        if MediaType::parse(media_type).is_none()
+            || check_header("Content-Type", media_type).is_err()
+        {
+            Logger::warning(&self.logger, &format!("{} is not a media type", media_type));
+            return false;
+        }
+        Arc::make_mut(&mut self.mime_types).insert(
+            extension.to_ascii_lowercase(),
+            String::from(media_type.trim()),
+        );
+        true
+    }
+
+    /// Makes browsers save static files with these extensions instead of
+    /// displaying them, e.g. user uploads that could carry scripts
+    ///
+    /// Such files are sent with `Content-Disposition: attachment` naming
+    /// the file and with `X-Content-Type-Options: nosniff`. Extensions are
+    /// compared without case. An extension passed to `inline_extensions`
+    /// later is displayed again.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.force_download_extensions(&["html", "svg", "xml"]);
+    /// ```
+    pub fn force_download_extensions(&mut self, extensions: &[&str]) {
+        self.set_disposition(extensions, Disposition::Attachment);
+    }
+
+    /// Sends static files with these extensions with `Content-Disposition:
+    /// inline` naming the file, so browsers display them, overriding an
+    /// earlier `force_download_extensions`
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.inline_extensions(&["pdf"]);
+    /// ```
+    pub fn inline_extensions(&mut self, extensions: &[&str]) {
+        self.set_disposition(extensions, Disposition::Inline);
+    }
+
+    fn set_disposition(&mut self, extensions: &[&str], disposition: Disposition) {
+        for extension in extensions {
+            let extension = extension.trim_start_matches('.').to_ascii_lowercase();
+            self.dispositions.insert(extension, disposition);
+        }
+    }
+
+    /// Returns the header lines deciding whether the static file at `path`
+    /// is displayed or saved, if an extension list mentions it
+    fn disposition_headers(&self, path: &Path) -> String {
+        let disposition = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(|extension| self.dispositions.get(&extension.to_ascii_lowercase()));
+        let (disposition, file_name) = match (disposition, path.file_name()) {
+            (Some(disposition), Some(file_name)) => (*disposition, file_name.to_string_lossy()),
+            _ => return String::new(),
+        };
+        let mut headers = format!(
+            "Content-Disposition: {}\r\n",
+            content_disposition(disposition, &file_name)
+        );
+        if disposition == Disposition::Attachment
+            && !self.default_headers.contains("X-Content-Type-Options")
+        {
+            headers.push_str("X-Content-Type-Options: nosniff\r\n");
+        }
+        headers
+    }
+
+    /// Answers requests for `/favicon.ico` with the file at `path`,
+    /// wherever it is
+    ///
+    /// The file is read for every request and sent with the media type of
+    /// its extension, `image/x-icon` if unknown, a `Cache-Control` allowing
+    /// clients to keep it for a week and an `ETag`, so revalidations get a
+    /// `304 Not Modified`. Routes registered for `/favicon.ico` still win.
+    ///
+    /// Returns false and changes nothing if the file cannot be read.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.set_favicon("./assets/favicon.ico");
+    /// ```
+    pub fn set_favicon<P: AsRef<Path>>(&mut self, path: P) -> bool {
+        let path = path.as_ref();
+        if let Err(e) = File::open(path) {
+            Logger::warning(
+                &self.logger,
+                &format!("Favicon {} cannot be read: {}", path.display(), e),
+            );
+            return false;
+        }
+        self.favicon = Some(Favicon::File(path.to_path_buf()));
+        true
+    }
+
+    /// Sets whether requests for `/favicon.ico` are answered with `204 No
+    /// Content` and not logged, instead of looking for the file like any
+    /// other
+    ///
+    /// Replaces a favicon set with `set_favicon`. Routes registered for
+    /// `/favicon.ico` still win.
+    pub fn ignore_favicon(&mut self, ignore: bool) {
+        if ignore {
+            self.favicon = Some(Favicon::Ignored);
+        } else if let Some(Favicon::Ignored) = self.favicon {
+            self.favicon = None;
+        }
+    }
+
+    /// Answers errors with `status` with the file `page` from the document
+    /// root instead of the built-in page
+    ///
+    /// The page is used for static files that are missing (404), not
+    /// readable (403) or failing (500), and for requests no route handles
+    /// (404 or 405), unless a `not_found` or `on_error` handler answers
+    /// them. It is read
+    /// for every response, through the stat cache if that is enabled, and
+    /// sent with `status` and the Content-Type of its extension. If it
+    /// cannot be read, the built-in page goes out instead and a warning is
+    /// logged the first time.
+    ///
+    /// Returns false if `status` is not an error status or `page` is not a
+    /// relative path staying inside the document root.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.set_document_root("./public/");
+    /// s.set_error_page(404, "404.html");
+    /// ```
+    pub fn set_error_page(&mut self, status: u32, page: &str) -> bool {
+        if !(400..600).contains(&status) {
+            Logger::warning(
+                &self.logger,
+                &format!("{} is not an error status code", status),
+            );
+            return false;
+        }
+        let path = PathBuf::from(page);
+        let inside_root = path
+            .components()
+            .all(|component| matches!(component, std::path::Component::Normal(_)));
+        if !inside_root || page.is_empty() {
+            Logger::warning(
+                &self.logger,
+                &format!("Error page {} is not a path inside the document root", page),
+            );
+            return false;
+        }
+        self.error_pages.insert(
+            status,
+            ErrorPage {
+                path,
+                missing_logged: Arc::new(AtomicBool::new(false)),
+            },
+        );
+        true
+    }
+
+    /// Adds the header `name` to every response, from handlers, static files
+    /// and error pages alike, replacing an earlier default of the same name
+    ///
+    /// A handler setting the same header wins. Returns false, logging a
+    /// warning, if name or value would break out of the header line.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.set_default_header("X-Frame-Options", "DENY");
+    /// s.set_default_header("X-Service", "billing");
+    /// ```
+    pub fn set_default_header(&mut self, name: &str, value: &str) -> bool {
+        if let Err(e) = check_header(name, value) {
+            Logger::warning(
+                &self.logger,
+                &format!("Not adding default header {}: {}", name, e),
+            );
+            return false;
+        }
+        self.default_headers.set(name, value);
+        true
+    }
+
+    /// Sends static files from the document root with `Cache-Control:
+    /// value`, replacing a default header of that name
+    ///
+    /// Browsers revalidate files they cached with `If-None-Match` or
+    /// `If-Modified-Since`, which unchanged files answer with `304 Not
+    /// Modified`. Fingerprinted assets keep their own header. Returns false,
+    /// logging a warning, if the value would break out of the header line.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.set_cache_control("public, max-age=3600");
+    /// ```
+    pub fn set_cache_control(&mut self, value: &str) -> bool {
+        if let Err(e) = check_header("Cache-Control", value) {
+            Logger::warning(&self.logger, &format!("Not using Cache-Control: {}", e));
+            return false;
+        }
+        self.static_cache_control = Some(String::from(value));
+        true
+    }
+
+    /// Stops adding the header `name` to every response, returning whether
+    /// it was a default header
+    pub fn remove_default_header(&mut self, name: &str) -> bool {
+        self.default_headers.remove(name).is_some()
+    }
+
+    /// Returns the headers added to every response, see
+    /// `set_default_header`
+    pub fn default_headers(&self) -> &Headers {
+        &self.default_headers
+    }
+
+    /// Sets the charset added to text content types that declare none,
+    /// `utf-8` by default, or `None` to add none
+    ///
+    /// It applies to `text/*`, JSON and XML, for handler responses and
+    /// static files. Static files whose start is not valid UTF-8 get no
+    /// charset, rather than a wrong one.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.set_default_charset(None);
+    /// ```
+    pub fn set_default_charset(&mut self, charset: Option<&str>) {
+        self.default_charset = charset.map(String::from);
+    }
+
+    /// Sets whether static files between 128KB and 256MB are written straight
+    /// from a memory map instead of being streamed
+    ///
+    /// Files that are empty, outside the size window or cannot be mapped are
+    /// streamed as usual.
+    ///
+    /// Mapped files must not be truncated while they are being served. Reading
+    /// a page that no longer exists on disk kills the whole process with
+    /// `SIGBUS`, so only enable this for document roots that are replaced
+    /// atomically (e.g. by renaming) rather than rewritten in place.
+    #[cfg(feature = "mmap")]
+    pub fn use_mmap(&mut self, mmap: bool) {
+        self.mmap = mmap;
+    }
+
+    /// Remembers for one second, for up to 1024 paths, that static files do
+    /// not exist, so requests for them skip the file system
+    ///
+    /// Existing files are opened and their metadata read on every request,
+    /// so a file that was deleted, rewritten or replaced is served as it is
+    /// right away. A file that was created shows up within one second.
+    pub fn enable_stat_cache(&mut self) {
+        self.set_stat_cache(statcache::DEFAULT_TTL, statcache::DEFAULT_CAPACITY);
+    }
+
+    /// Remembers that static files do not exist for `ttl`, for up to
+    /// `capacity` paths, see `enable_stat_cache`
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// let mut s = corrodedweb::Server::new();
+    /// s.set_stat_cache(Duration::from_millis(500), 256);
+    /// ```
+    pub fn set_stat_cache(&mut self, ttl: Duration, capacity: usize) {
+        self.stat_cache = Some(Arc::new(StatCache::new(ttl, capacity)));
+    }
+
+    /// Keeps the contents of static files up to 1 MB in memory, evicting
+    /// the least recently used ones once they take more than `max_bytes`,
+    /// see `set_static_cache`
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.enable_static_cache(64 * 1024 * 1024);
+    /// ```
+    pub fn enable_static_cache(&mut self, max_bytes: u64) {
+        self.set_static_cache(max_bytes, filecache::DEFAULT_MAX_FILE_SIZE);
+    }
+
+    /// Keeps the contents of static files up to `max_file_bytes` in memory,
+    /// evicting the least recently used ones once they take more than
+    /// `max_bytes`
+    ///
+    /// Files are still opened for every request, and read again once they
+    /// were modified, so edits show up right away. Larger files are
+    /// streamed from disk as usual.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.set_static_cache(16 * 1024 * 1024, 256 * 1024);
+    /// ```
+    pub fn set_static_cache(&mut self, max_bytes: u64, max_file_bytes: u64) {
+        self.file_cache = Some(Arc::new(FileCache::new(max_bytes, max_file_bytes)));
+    }
+
+    /// Serves HTTPS instead of plain HTTP
+    ///
+    /// Clients that fail the handshake are logged and dropped.
+    #[cfg(any(feature = "tls", feature = "native-tls"))]
+    pub fn set_tls(&mut self, config: TlsConfig) {
+        self.tls = Some(config);
+    }
+
+    /// Checks the certificate and key files every `interval` and reloads them
+    /// once they changed, see `TlsConfig::reload`
+    ///
+    /// A reload that fails is logged and the previous certificates stay in
+    /// use, so a half written renewal does no harm.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// let mut s = corrodedweb::Server::new();
+    /// s.set_tls_reload_interval(Duration::from_secs(3600));
+    /// ```
+    #[cfg(any(feature = "tls", feature = "native-tls"))]
+    pub fn set_tls_reload_interval(&mut self, interval: Duration) {
+        self.tls_reload_interval = Some(interval);
+    }
+
+    /// Tests whether document root is valid an return an Option
+    /// Sets a logger for the server instance with specific log path
+    ///
+    /// # Arguments
+    ///
+    /// * `log_path` - Path to log file
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.set_logger("./file.log")
+    /// ```
+    pub fn set_logger(&mut self, log_path: &str) {
+        self.logger = Some(Logger::new(log_path));
+    }
+
+    /// Logs with `logger`, configured beforehand e.g. to write to standard
+    /// output or to leave out debug messages
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::{Level, Logger, Server};
+    /// let mut logger = Logger::to_stderr();
+    /// logger.set_level(Level::Warning);
+    /// let mut s = Server::new();
+    /// s.use_logger(logger);
+    /// ```
+    pub fn use_logger(&mut self, logger: Logger) {
+        self.logger = Some(logger);
+    }
+
+    /// Leaves out log messages less severe than `level`, see
+    /// `Logger::set_level`
+    ///
+    /// Without a logger set, one writing to standard output is set up.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::{Level, Server};
+    /// let mut s = Server::new();
+    /// s.set_logger("./file.log");
+    /// s.set_log_level(Level::Info);
+    /// ```
+    pub fn set_log_level(&mut self, level: Level) {
+        self.logger
+            .get_or_insert_with(Logger::to_stdout)
+            .set_level(level);
+    }
+
+    /// Returns the level set with `set_log_level`, `None` without a logger
+    pub fn log_level(&self) -> Option<Level> {
+        self.logger.as_ref().map(Logger::level)
+    }
+
+    /// Writes a line per answered request to the file at `path`, in the
+    /// Combined Log Format with the microseconds it took appended
+    ///
+    /// Lines look like `192.0.2.7 - - [10/Oct/2000:13:55:36 +0000] "GET /
+    /// HTTP/1.1" 200 2326 "-" "curl/8.0" 1500`, which goaccess reads with
+    /// `--log-format='%h %^[%d:%t %^] "%r" %s %b "%R" "%u" %D'`. The client is
+    /// the one `Request::client_ip` tells, the size is that of the body sent.
+    /// Requests rejected before they are routed are left out, and so are
+    /// those for an ignored favicon, see `ignore_favicon`. A response the
+    /// client went away from gets ` client-abort` appended, except with the
+    /// `tokio` feature, where the line is written before the response goes
+    /// out. This log is apart from the one of `set_logger`. Returns false and
+    /// logs a warning if the file cannot be opened.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// let mut s = corrodedweb::Server::new();
+    /// s.set_access_log("./access.log");
+    /// ```
+    pub fn set_access_log(&mut self, path: &str) -> bool {
+        match AccessLog::open(Path::new(path)) {
+            Ok(access_log) => {
+                self.access_log = Some(access_log);
+                true
+            }
+            Err(e) => {
+                Logger::warning(
+                    &self.logger,
+                    &format!("Not writing the access log to {}: {}", path, e),
+                );
+                false
+            }
+        }
+    }
+
+    /// Registers for a GET-request
+    ///
+    /// Routes may contain parameters like `/users/:id/` and end with a `*`
+    /// wildcard matching the rest of the path. If several routes match, an
+    /// exact match wins over parameterized routes, those with more literal
+    /// segments win over those with less, and all of them win over
+    /// wildcards. Among equal routes the first registered one wins.
+    ///
+    /// # Arguments
+    ///
+    /// * `route` - The endpoint you will register to.
+    /// * `f` - The callback closure which will be executed on request.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.get("/", |request, mut response| {
+    ///
+    /// });
+    /// ```
+    #[track_caller]
+    pub fn get<F>(&mut self, route: &str, f: F) -> RouteBuilder
+    where
+        F: Fn(Request, Response) + Send + Sync + 'static,
+    {
+        self.register("GET", route, Arc::new(f))
+    }
+
+    /// Registers for a POST-request
+    ///
+    ///
+    /// # Arguments
+    ///
+    /// * `route` - The endpoint you will register to.
+    /// * `f` - The callback closure which will be executed on request.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.post("/", |request, mut response| {
+    ///
+    /// });
+    /// ```
+    #[track_caller]
+    pub fn post<F>(&mut self, route: &str, f: F) -> RouteBuilder
+    where
+        F: Fn(Request, Response) + Send + Sync + 'static,
+    {
+        self.register("POST", route, Arc::new(f))
+    }
+
+    /// Registers for a PUT-request
+    ///
+    ///
+    /// # Arguments
+    ///
+    /// * `route` - The endpoint you will register to.
+    /// * `f` - The callback closure which will be executed on request.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.put("/users/:id/", |request, mut response| {
+    ///
+    /// });
+    /// ```
+    #[track_caller]
+    pub fn put<F>(&mut self, route: &str, f: F) -> RouteBuilder
+    where
+        F: Fn(Request, Response) + Send + Sync + 'static,
+    {
+        self.register("PUT", route, Arc::new(f))
+    }
+
+    /// Registers for a DELETE-request
+    ///
+    ///
+    /// # Arguments
+    ///
+    /// * `route` - The endpoint you will register to.
+    /// * `f` - The callback closure which will be executed on request.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.delete("/users/:id/", |request, mut response| {
+    ///
+    /// });
+    /// ```
+    #[track_caller]
+    pub fn delete<F>(&mut self, route: &str, f: F) -> RouteBuilder
+    where
+        F: Fn(Request, Response) + Send + Sync + 'static,
+    {
+        self.register("DELETE", route, Arc::new(f))
+    }
+
+    /// Registers for a PATCH-request
+    ///
+    ///
+    /// # Arguments
+    ///
+    /// * `route` - The endpoint you will register to.
+    /// * `f` - The callback closure which will be executed on request.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.patch("/users/:id/", |request, mut response| {
+    ///
+    /// });
+    /// ```
+    #[track_caller]
+    pub fn patch<F>(&mut self, route: &str, f: F) -> RouteBuilder
+    where
+        F: Fn(Request, Response) + Send + Sync + 'static,
+    {
+        self.register("PATCH", route, Arc::new(f))
+    }
+
+    /// Registers for a request with any HTTP method
+    ///
+    /// # Arguments
+    ///
+    /// * `method` - The HTTP method, e.g. `DELETE`.
+    /// * `route` - The endpoint you will register to.
+    /// * `f` - The callback closure which will be executed on request.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.route("DELETE", "/users/:id/", |request, mut response| {
+    ///
+    /// });
+    /// ```
+    #[track_caller]
+    pub fn route<F>(&mut self, method: &str, route: &str, f: F) -> RouteBuilder
+    where
+        F: Fn(Request, Response) + Send + Sync + 'static,
+    {
+        self.register(&method.to_uppercase(), route, Arc::new(f))
+    }
+
+    /// Registers for a GET-request like `get`, answering `504 Gateway
+    /// Timeout` if the handler takes longer than `timeout`
+    ///
+    /// See `set_handler_timeout` for what happens to a handler running out
+    /// of time, and `RouteBuilder::timeout` for the other methods.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// use std::time::Duration;
+    /// let mut s = Server::new();
+    /// s.get_with_timeout("/slow/", Duration::from_secs(10), |request, mut response| {
+    ///     // Ask the upstream here
+    ///     let _ = response.write("answered in time");
+    /// });
+    /// ```
+    #[track_caller]
+    pub fn get_with_timeout<F>(&mut self, route: &str, timeout: Duration, f: F) -> RouteBuilder
+    where
+        F: Fn(Request, Response) + Send + Sync + 'static,
+    {
+        self.get(route, f).timeout(timeout)
+    }
+
+    /// Registers for a GET-request like `get` and names the route, see
+    /// `RouteBuilder::name` and `url_for`
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.get_named("user_detail", "/users/:id/", |request, mut response| {});
+    /// s.get("/", |request, mut response| {
+    ///     if let Some(url) = request.url_for("user_detail", &[("id", "42")]) {
+    ///         let _ = response.redirect(&url);
+    ///     }
+    /// });
+    /// ```
+    #[track_caller]
+    pub fn get_named<F>(&mut self, name: &str, route: &str, f: F) -> RouteBuilder
+    where
+        F: Fn(Request, Response) + Send + Sync + 'static,
+    {
+        self.get(route, f).name(name)
+    }
+
+    /// Returns the path of the route named `name` with its parameters
+    /// replaced by `params`, pairs of parameter name and value
+    ///
+    /// Values are percent-encoded, the rest a wildcard matched is passed as
+    /// `*` and keeps its slashes. Returns `None` if no route has the name or
+    /// a parameter of it is missing or empty. Parameters the route does not
+    /// have are ignored.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.get("/files/:owner/*", |request, mut response| {}).name("file");
+    /// assert_eq!(
+    ///     s.url_for("file", &[("owner", "Jürgen"), ("*", "docs/a b.txt")]).unwrap(),
+    ///     "/files/J%C3%BCrgen/docs/a%20b.txt"
+    /// );
+    /// assert_eq!(s.url_for("file", &[("owner", "anna")]).unwrap(), "/files/anna/");
+    /// assert_eq!(s.url_for("file", &[]), None);
+    /// ```
+    pub fn url_for(&self, name: &str, params: &[(&str, &str)]) -> Option<String> {
+        url_for(&self.route_names, name, params)
+    }
+
+    /// Registers a callback for requests which are not handled by any route
+    /// and for static files that do not exist
+    ///
+    /// `Request::no_match()` tells whether the path is unknown or just not
+    /// registered for the request method. The response already carries the
+    /// status 404, or 405 together with an Allow header, which the callback
+    /// may change.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::{NoMatch, Server};
+    /// let mut s = Server::new();
+    /// s.not_found(|request, mut response| {
+    ///     if let Some(NoMatch::MethodNotAllowed { allowed }) = request.no_match() {
+    ///         let _ = response.write(&format!("Try {}", allowed.join(" or ")));
+    ///     }
+    /// });
+    /// ```
+    pub fn not_found<F>(&mut self, f: F)
+    where
+        F: Fn(Request, Response) + Send + Sync + 'static,
+    {
+        self.not_found_handler = Some(Arc::new(f));
+    }
+
+    /// Registers a callback that answers the errors the server itself
+    /// answers with `500`, handlers that panicked and static files that
+    /// could not be read
+    ///
+    /// The response already carries the status 500, which the callback may
+    /// change. Without a callback the built-in answer is sent. Handlers
+    /// that panic after starting to stream their body cannot be answered
+    /// anymore, their connection is closed as before.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.on_error(|request, mut response| {
+    ///     let _ = response.write(&format!("Sorry, {} broke", request.path()));
+    /// });
+    /// ```
+    pub fn on_error<F>(&mut self, f: F)
+    where
+        F: Fn(Request, Response) + Send + Sync + 'static,
+    {
+        self.error_handler = Some(Arc::new(f));
+    }
+
+    /// Registers a middleware that runs for every request before it is
+    /// answered by a route, a static file or anything else
+    ///
+    /// Middlewares run in the order they were registered. Each gets the
+    /// request, its response and `next`, which passes both on to the next
+    /// middleware or, after the last one, to whatever answers the request.
+    /// A middleware that does not call `next` answers the request itself with
+    /// the response. Headers it sets before calling `next` are sent with the
+    /// answer, a status or body is not.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.use_middleware(|request, mut response, next| {
+    ///     if request.get_header("Authorization").is_none() {
+    ///         let _ = response.set_status_code(401);
+    ///         return;
+    ///     }
+    ///     let _ = response.add_header("X-Checked", "yes");
+    ///     next(request, response)
+    /// });
+    /// ```
+    pub fn use_middleware<F>(&mut self, f: F)
+    where
+        F: Fn(Request, Response, Next) + Send + Sync + 'static,
+    {
+        self.use_middleware_at("", f);
+    }
+
+    /// Registers a middleware that only runs for requests to `prefix` and
+    /// the paths below it, see `use_middleware`
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.use_middleware_at("/admin/", |request, mut response, next| {
+    ///     if request.client_ip().is_some_and(|ip| ip.is_loopback()) {
+    ///         next(request, response)
+    ///     } else {
+    ///         let _ = response.set_status_code(403);
+    ///     }
+    /// });
+    /// ```
+    pub fn use_middleware_at<F>(&mut self, prefix: &str, f: F)
+    where
+        F: Fn(Request, Response, Next) + Send + Sync + 'static,
+    {
+        self.middlewares.push(Layer {
+            prefix: String::from(prefix.trim_end_matches('/')),
+            middleware: Arc::new(f),
+        });
+    }
+
+    /// Lets only clients that send one of `users`, pairs of user name and
+    /// password, with HTTP Basic authentication request `prefix` and the
+    /// paths below it
+    ///
+    /// Other clients get `401 Unauthorized` with a `WWW-Authenticate` header
+    /// before any middleware, route or static file sees the request, the
+    /// user name of those who pass is `Request::auth_user`. Where prefixes
+    /// are nested the longest one decides, protecting a prefix again
+    /// replaces its users. Passwords travel in plain text, so this belongs
+    /// behind HTTPS. Returns false if `prefix` does not start with a slash
+    /// or contains quotes, backslashes or control characters.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.protect("/admin/", &[("alice", "s3cret")]);
+    /// s.get("/admin/", |request, mut response| {
+    ///     let _ = response.write(&format!("Hello {}", request.auth_user().unwrap_or("")));
+    /// });
+    /// ```
+    pub fn protect(&mut self, prefix: &str, users: &[(&str, &str)]) -> bool {
+        self.protect_with_validator(prefix, basicauth::users(users))
+    }
+
+    /// Protects `prefix` like `protect`, with `validator` deciding whether a
+    /// user name and password are valid
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.protect_with("/", |user, password| user == "admin" && password == "changeme");
+    /// ```
+    pub fn protect_with<F>(&mut self, prefix: &str, validator: F) -> bool
+    where
+        F: Fn(&str, &str) -> bool + Send + Sync + 'static,
+    {
+        self.protect_with_validator(prefix, Arc::new(validator))
+    }
+
+    fn protect_with_validator(&mut self, prefix: &str, validator: basicauth::Validator) -> bool {
+        if !prefix.starts_with('/')
+            || prefix
+                .chars()
+                .any(|c| c == '"' || c == '\\' || c.is_control())
+        {
+            Logger::warning(
+                &self.logger,
+                &format!("Cannot protect {:?}, it is no path prefix", prefix),
+            );
+            return false;
+        }
+        let protection = Protection::new(prefix, validator);
+        self.protections
+            .retain(|protected| !protected.same_prefix(&protection));
+        self.protections.push(protection);
+        true
+    }
+
+    /// Registers a callback that runs once the server listens, right before
+    /// it accepts the first connection
+    ///
+    /// It runs exactly once per `start_server`, on the thread that called
+    /// it, e.g. to print a banner or register the address somewhere.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.on_start(|info| {
+    ///     for address in &info.addresses {
+    ///         println!("listening on http://{} with {} routes", address, info.routes.len());
+    ///     }
+    /// });
+    /// ```
+    pub fn on_start<F>(&mut self, f: F)
+    where
+        F: Fn(&StartupInfo) + Send + Sync + 'static,
+    {
+        self.on_start = Some(Arc::new(f));
+    }
+
+    /// Registers a callback that runs after each request was answered, with
+    /// what the request was and, with `set_phase_timings`, how long it took
+    ///
+    /// It runs on the worker that handled the request, after the handler
+    /// returned, for every request that got as far as being routed. Requests
+    /// rejected while they are read, e.g. for timing out, are left out.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.set_phase_timings(true);
+    /// s.on_request_complete(|summary| {
+    ///     if let Some(timings) = summary.timings {
+    ///         println!("{} {}: {}", summary.method, summary.path, timings);
+    ///     }
+    /// });
+    /// ```
+    pub fn on_request_complete<F>(&mut self, f: F)
+    where
+        F: Fn(&RequestSummary) + Send + Sync + 'static,
+    {
+        self.on_request_complete = Some(Arc::new(f));
+    }
+
+    /// Shares `value` with all handlers, which get it with `Request::state`,
+    /// replacing a value of the same type added before
+    ///
+    /// There is one value per type, so wrapping it in a type of its own
+    /// keeps it apart from values of other parts of the application. It is
+    /// shared by all workers, so changing it needs atomics or a lock.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// struct Visits(AtomicUsize);
+    /// let mut s = Server::new();
+    /// s.manage(Visits(AtomicUsize::new(0)));
+    /// s.get("/", |request, mut response| {
+    ///     if let Some(visits) = request.state::<Visits>() {
+    ///         let count = visits.0.fetch_add(1, Ordering::Relaxed) + 1;
+    ///         let _ = response.write(&format!("visit {}", count));
+    ///     }
+    /// });
+    /// ```
+    pub fn manage<T: Any + Send + Sync>(&mut self, value: T) {
+        Arc::make_mut(&mut self.state).insert(TypeId::of::<T>(), Arc::new(value));
+    }
+
+    /// Registers a hook that runs for every request before anything else
+    /// sees it, e.g. to attach a value with `Request::set_extension`
+    ///
+    /// Hooks run in the order they were registered, before authentication
+    /// and middlewares. Unlike middlewares they cannot answer the request,
+    /// only annotate it.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// use std::time::SystemTime;
+    /// let mut s = Server::new();
+    /// s.before(|request| request.set_extension(SystemTime::now()));
+    /// ```
+    pub fn before<F>(&mut self, f: F)
+    where
+        F: Fn(&mut Request) + Send + Sync + 'static,
+    {
+        self.before_hooks.push(Arc::new(f));
+    }
+
+    /// Registers a hook that runs after each request was answered, with the
+    /// request and how it was answered
+    ///
+    /// It runs on the worker that handled the request, for the requests
+    /// `on_request_complete` sees, and gets a copy of the request as the
+    /// `before` hooks left it. The duration counts from reading the request
+    /// to the last byte of the response going out.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.after(|request, meta| {
+    ///     if meta.duration.as_millis() > 500 {
+    ///         println!("slow: {} {} took {:?}", request.method(), request.path(), meta.duration);
+    ///     }
+    /// });
+    /// ```
+    pub fn after<F>(&mut self, f: F)
+    where
+        F: Fn(&Request, &ResponseMeta) + Send + Sync + 'static,
+    {
+        self.after_hooks.push(Arc::new(f));
+    }
+
+    /// Sends a `Server-Timing` header with how long the server took from
+    /// reading the request until the response head went out, off by default
+    ///
+    /// Browsers show it in their developer tools, e.g. `app;dur=12.5` for
+    /// 12.5 milliseconds. Responses of handlers, middlewares and the
+    /// `on_error` and `not_found` handlers get it, unless they set the
+    /// header themselves, static files do not.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.set_server_timing(true);
+    /// ```
+    pub fn set_server_timing(&mut self, enabled: bool) {
+        self.server_timing = enabled;
+    }
+
+    /// Grafts all routes registered on `module` into this server under `prefix`
+    ///
+    /// A route `/posts/` of the module mounted at `/blog` is served at
+    /// `/blog/posts/`. Its handler still sees the full path in
+    /// `Request::path()` and the prefix in `Request::mount_prefix()`.
+    /// Middlewares of the module run for paths below `prefix` only, after
+    /// those registered on this server so far. Conflicting routes are
+    /// handled by the duplicate route policy. A module can be mounted under
+    /// several prefixes by mounting clones of it.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// fn blog() -> Server {
+    ///     let mut blog = Server::new();
+    ///     blog.use_middleware(|request, mut response, next| {
+    ///         let _ = response.add_header("X-Section", "blog");
+    ///         next(request, response)
+    ///     });
+    ///     blog.get("/posts/", |request, mut response| {});
+    ///     blog
+    /// }
+    /// let mut s = Server::new();
+    /// let blog = blog();
+    /// s.mount("/blog", blog.clone());
+    /// s.mount("/news", blog);
+    /// ```
+    #[track_caller]
+    pub fn mount(&mut self, prefix: &str, module: Server) {
+        let prefix = prefix.trim_end_matches('/');
+        if module.get_document_root().is_some() {
+            Logger::warning(
+                &self.logger,
+                &format!(
+                    "The document root of the server mounted at {} is ignored",
+                    prefix
+                ),
+            );
+        }
+        // Clones share their routes, which stay in place for other mounts
+        let routes: Vec<(String, String, Endpoint)> = module
+            .registered_endpoints
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|route| {
+                (
+                    String::from(route.method()),
+                    String::from(route.pattern()),
+                    route.value().clone(),
+                )
+            })
+            .collect();
+        for (method, pattern, endpoint) in routes {
+            let mount_prefix = String::from(prefix);
+            let callback = endpoint.callback;
+            let mut route = self.register(
+                &method,
+                &format!("{}{}", prefix, pattern),
+                Arc::new(move |mut request, response| {
+                    // Mounts can be nested, the outermost prefix is added first
+                    request.mount_prefix.push_str(&mount_prefix);
+                    callback(request, response)
+                }),
+            );
+            if let Some(description) = endpoint.description {
+                route = route.describe(&description);
+            }
+            if let Some(timeout) = endpoint.timeout {
+                route.timeout(timeout);
+            }
+        }
+        for (name, pattern) in module.route_names.read().unwrap().iter() {
+            self.route_names
+                .write()
+                .unwrap()
+                .insert(name.clone(), format!("{}{}", prefix, pattern));
+        }
+        for layer in &module.middlewares {
+            self.middlewares.push(Layer {
+                prefix: format!("{}{}", prefix, layer.prefix),
+                middleware: layer.middleware.clone(),
+            });
+        }
+    }
+
+    /// Registers a route which redirects every GET and POST request to `to`
+    ///
+    /// # Arguments
+    ///
+    /// * `from` - The endpoint which will be redirected.
+    /// * `to` - The value of the Location header, relative or absolute.
+    /// * `status` - One of the redirect status codes 301, 302, 303, 307 or 308.
+    ///
+    /// Returns false and registers nothing if the status is not a redirect or
+    /// `to` contains characters which are not allowed in a header.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.redirect("/old-path/", "/new-path/", 301);
+    /// ```
+    #[track_caller]
+    pub fn redirect(&mut self, from: &str, to: &str, status: u32) -> bool {
+        if ![301, 302, 303, 307, 308].contains(&status) {
+            Logger::warning(
+                &self.logger,
+                &format!("{} is not a redirect status code", status),
+            );
+            return false;
+        }
+        if let Err(e) = check_header_value(to) {
+            Logger::warning(&self.logger, &format!("Not redirecting {}: {}", from, e));
+            return false;
+        }
+        for method in &["GET", "POST"] {
+            let location = String::from(to);
+            self.register(
+                method,
+                from,
+                Arc::new(move |_request, mut response| {
+                    let _ = response.send_redirect(&location, status);
+                }),
+            );
+        }
+        true
+    }
+
+    /// Redirects requests for exactly `from` to `to`, without a handler
+    ///
+    /// Rules apply to every method, but only to paths no route handles, and
+    /// take precedence over static files. The query of the request is
+    /// appended to `to`.
+    ///
+    /// Returns false and adds no rule if the status is not a redirect, `to`
+    /// is not allowed in a header or the rule would make clients go around
+    /// in circles, e.g. `/a/` to `/a/` or `/a/` to `/b/` back to `/a/`. A
+    /// rule for a path that already has one replaces it.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.redirect_rule("/old-blog/", "/blog/", 301);
+    /// ```
+    pub fn redirect_rule(&mut self, from: &str, to: &str, status: u32) -> bool {
+        self.add_redirect_rule(RedirectRule {
+            from: String::from(from),
+            to: String::from(to),
+            status,
+            prefix: false,
+        })
+    }
+
+    /// Redirects requests for `prefix` and every path below it, replacing
+    /// the prefix with `target` and keeping the rest of the path
+    ///
+    /// With `redirect_prefix("/docs/v1/", "https://archive.example.com/docs/v1/", 302)`
+    /// a request for `/docs/v1/intro.html?lang=de` goes to
+    /// `https://archive.example.com/docs/v1/intro.html?lang=de`. A prefix
+    /// without trailing slash only covers whole segments, so `/docs` does
+    /// not cover `/docsets/`. When several prefixes match, the longest
+    /// wins, and rules for an exact path win over prefixes. Otherwise this
+    /// behaves like `redirect_rule`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.redirect_prefix("/docs/v1/", "https://archive.example.com/docs/v1/", 302);
+    /// ```
+    pub fn redirect_prefix(&mut self, prefix: &str, target: &str, status: u32) -> bool {
+        self.add_redirect_rule(RedirectRule {
+            from: String::from(prefix),
+            to: String::from(target),
+            status,
+            prefix: true,
+        })
+    }
+
+    /// Registers a rewrite, which changes the target of a request before it
+    /// is routed, without the client noticing
+    ///
+    /// `f` gets the path and query the client sent, e.g. `/page.php?id=5`,
+    /// and returns the target to handle the request as instead, e.g.
+    /// `/pages/5/`, or `None` to leave it alone. The query of the returned
+    /// target replaces the original one. Rewrites are tried in the order
+    /// they were registered and the first one returning a target wins.
+    ///
+    /// The new target selects the route, redirect rule, proxy or static file
+    /// like one the client sent would, `Request::path()` returns it and
+    /// `Request::original_path()` still the path the client sent. Targets not
+    /// starting with a slash are ignored.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.rewrite(|target| {
+    ///     let id = target.strip_prefix("/page.php?id=")?;
+    ///     Some(format!("/pages/{}/", id))
+    /// });
+    /// s.get("/pages/:id/", |request, mut response| {});
+    /// ```
+    pub fn rewrite<F>(&mut self, f: F)
+    where
+        F: Fn(&str) -> Option<String> + Send + Sync + 'static,
+    {
+        self.rewrites.push(Arc::new(f));
+    }
+
+    /// Returns the target of the first rewrite applying to `target`
+    fn rewritten_target(&self, target: &str) -> Option<String> {
+        let rewritten = self.rewrites.iter().find_map(|rewrite| rewrite(target))?;
+        if !rewritten.starts_with('/') {
+            Logger::warning(
+                &self.logger,
+                &format!("Ignoring rewrite of {} to {}", target, rewritten),
+            );
+            return None;
+        }
+        Logger::debug(
+            &self.logger,
+            &format!("Rewrote {} to {}", target, rewritten),
+        );
+        Some(rewritten)
+    }
+
+    fn add_redirect_rule(&mut self, rule: RedirectRule) -> bool {
+        if ![301, 302, 303, 307, 308].contains(&rule.status) {
+            Logger::warning(
+                &self.logger,
+                &format!("{} is not a redirect status code", rule.status),
+            );
+            return false;
+        }
+        if !rule.from.starts_with('/') {
+            Logger::warning(
+                &self.logger,
+                &format!("Not redirecting {}: paths start with a slash", rule.from),
+            );
+            return false;
+        }
+        if let Err(e) = check_header_value(&rule.to) {
+            Logger::warning(
+                &self.logger,
+                &format!("Not redirecting {}: {}", rule.from, e),
+            );
+            return false;
+        }
+        let mut rules: Vec<RedirectRule> = self
+            .redirect_rules
+            .iter()
+            .filter(|other| other.prefix != rule.prefix || other.from != rule.from)
+            .cloned()
+            .collect();
+        let message = format!(
+            "Not redirecting {} to {}: the redirects would loop",
+            rule.from, rule.to
+        );
+        let start = rule.from.clone();
+        rules.push(rule);
+        if redirects_in_loop(&rules, &start) {
+            Logger::warning(&self.logger, &message);
+            return false;
+        }
+        self.redirect_rules = rules;
+        true
+    }
+
+    /// Sets whether routes match the request path regardless of its case
+    ///
+    /// `/Contact/` will then be handled by the route `/contact/`. Captured
+    /// path parameters keep the casing the client sent. Static files are not
+    /// affected.
+    pub fn set_case_insensitive_routes(&mut self, case_insensitive: bool) {
+        self.registered_endpoints
+            .lock()
+            .unwrap()
+            .set_case_insensitive(case_insensitive);
+    }
+
+    /// Sets whether a POST request may be dispatched as PUT, PATCH or DELETE
+    ///
+    /// HTML forms can only send GET and POST. If enabled, a POST request
+    /// carrying the header `X-HTTP-Method-Override: DELETE` or the form field
+    /// `_method=DELETE` is handled by the DELETE route.
+    /// `Request::original_method()` still returns `POST` in that case.
+    /// Requests with other methods are never overridden.
+    pub fn allow_method_override(&mut self, allow: bool) {
+        self.method_override = allow;
+    }
+
+    /// Sets what happens when a route is registered twice for the same method
+    ///
+    /// # Example
+    ///
+    /// ```should_panic
+    /// use corrodedweb::{DuplicateRoutePolicy, Server};
+    /// let mut s = Server::new();
+    /// s.set_duplicate_route_policy(DuplicateRoutePolicy::Panic);
+    /// s.get("/a/", |request, mut response| {});
+    /// s.get("/a/", |request, mut response| {});
+    /// ```
+    pub fn set_duplicate_route_policy(&mut self, policy: DuplicateRoutePolicy) {
+        self.duplicate_route_policy = policy;
+    }
+
+    /// Sets whether routes or files in the document root answer a path both
+    /// of them have, routes by default
+    ///
+    /// When the server starts, a warning is logged for every GET route
+    /// without parameters that has the path of a file in the document root,
+    /// naming which of them answers.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::{Server, StaticPrecedence};
+    /// let mut s = Server::new();
+    /// s.set_static_precedence(StaticPrecedence::StaticFirst);
+    /// ```
+    pub fn set_static_precedence(&mut self, precedence: StaticPrecedence) {
+        self.static_precedence = precedence;
+    }
+
+    /// Sets what happens to a request for `/counter` if only `/counter/`
+    /// has a route, and the other way around
+    ///
+    /// By default such a request is answered by the route. With
+    /// `TrailingSlashPolicy::Redirect` the client is sent to the other path
+    /// instead, with `301 Moved Permanently` for GET and HEAD and `308
+    /// Permanent Redirect` for other methods, so they keep their body.
+    /// Static directories requested without a trailing slash follow the
+    /// policy as well, with `Strict` they are not found. `/` is never
+    /// changed.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::{Server, TrailingSlashPolicy};
+    /// let mut s = Server::new();
+    /// s.set_trailing_slash_policy(TrailingSlashPolicy::Redirect);
+    /// ```
+    pub fn set_trailing_slash_policy(&mut self, policy: TrailingSlashPolicy) {
+        self.trailing_slash_policy = policy;
+    }
+
+    /// Serves the files below `prefix` in the document root under URLs
+    /// that carry a hash of their content as well, for far-future caching
+    ///
+    /// `asset_url` turns `/static/app.css` into `/static/app.<hash>.css`,
+    /// which serves the file with `Cache-Control: public, max-age=31536000,
+    /// immutable`. Once the file changes, so does its hash. Requests for an
+    /// old hash are answered as the [`StaleAssetPolicy`] says, with `404` by
+    /// default. Hashes are computed when first needed and kept until the
+    /// file changes. The files stay available under their plain names.
+    ///
+    /// Returns false if `prefix` does not start with `/`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::{Server, StaleAssetPolicy};
+    /// let mut s = Server::new();
+    /// s.enable_asset_fingerprinting("/static/");
+    /// s.set_stale_asset_policy(StaleAssetPolicy::Redirect);
+    /// ```
+    pub fn enable_asset_fingerprinting(&mut self, prefix: &str) -> bool {
+        if !prefix.starts_with('/') {
+            Logger::warning(
+                &self.logger,
+                &format!("Asset prefix {} does not start with /", prefix),
+            );
+            return false;
+        }
+        self.assets.enable(prefix);
+        true
+    }
+
+    /// Sets what answers a request for a fingerprinted asset under an old
+    /// hash, `404` by default
+    pub fn set_stale_asset_policy(&mut self, policy: StaleAssetPolicy) {
+        self.stale_asset_policy = policy;
+    }
+
+    /// Returns the URL of the file at `path` in the document root with its
+    /// content hash, see `enable_asset_fingerprinting`
+    ///
+    /// Paths outside of the fingerprinted prefix and files that do not
+    /// exist are returned as they are. `ServerHandle::asset_url` does the
+    /// same from within handlers.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.enable_asset_fingerprinting("/static/");
+    /// // Without a document root there is nothing to hash
+    /// assert_eq!(s.asset_url("/static/app.css"), "/static/app.css");
+    /// ```
+    pub fn asset_url(&self, path: &str) -> String {
+        asset_url(
+            &self.assets,
+            &self.document_root,
+            self.follow_symlinks,
+            path,
+        )
+    }
+
+    /// Limits how many connections are handled or waiting for a worker at
+    /// the same time
+    ///
+    /// What happens beyond the limit is decided by the
+    /// [`ConnectionLimitPolicy`], which defers new connections by default.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::{ConnectionLimitPolicy, Server};
+    /// let mut s = Server::new();
+    /// s.set_max_connections(256);
+    /// s.set_connection_limit_policy(ConnectionLimitPolicy::Reject);
+    /// ```
+    pub fn set_max_connections(&mut self, max: usize) {
+        self.max_connections = Some(max);
+    }
+
+    /// Limits how many requests each client address may make per minute,
+    /// zero for no limit, which is the default
+    ///
+    /// A client may make that many requests at once, after which its
+    /// allowance refills steadily over the minute. Requests beyond it are
+    /// answered with `429 Too Many Requests` and a `Retry-After` header
+    /// before any handler runs. Behind a proxy trusted with `trust_proxy`
+    /// the address is the one of the proxy. Addresses not seen for a minute
+    /// are forgotten. See `exempt_from_rate_limit` for health checks.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.set_rate_limit(120);
+    /// s.exempt_from_rate_limit("127.0.0.1");
+    /// ```
+    pub fn set_rate_limit(&mut self, requests_per_minute: u32) {
+        self.rate_limiter =
+            (requests_per_minute > 0).then(|| Arc::new(RateLimiter::new(requests_per_minute)));
+    }
+
+    /// Lets requests from `client`, an address like `127.0.0.1` or a network
+    /// like `10.0.0.0/8`, pass whatever `set_rate_limit` says
+    ///
+    /// Invalid addresses are logged and ignored, returning false.
+    pub fn exempt_from_rate_limit(&mut self, client: &str) -> bool {
+        match IpNetwork::parse(client) {
+            Some(network) => {
+                self.rate_limit_exempt.push(network);
+                true
+            }
+            None => {
+                Logger::warning(
+                    &self.logger,
+                    &format!(
+                        "{} is not an IP address or network, not exempting it from the rate limit",
+                        client
+                    ),
+                );
+                false
+            }
+        }
+    }
+
+    /// Limits how many accepted connections may wait for a worker of the
+    /// blocking server while all of them are busy, unlimited by default
+    ///
+    /// What happens beyond the limit is decided by the
+    /// [`ConnectionLimitPolicy`], like for `set_max_connections`. With the
+    /// `tokio` feature there are no workers to wait for.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::{ConnectionLimitPolicy, Server};
+    /// let mut s = Server::new();
+    /// s.set_workers(4);
+    /// // Answer 503 rather than letting a fifth client wait
+    /// s.set_max_queued_connections(0);
+    /// s.set_connection_limit_policy(ConnectionLimitPolicy::Reject);
+    /// ```
+    pub fn set_max_queued_connections(&mut self, max: usize) {
+        self.max_queued_connections = Some(max);
+    }
+
+    /// Sets what happens to new connections while the connection limit or
+    /// the limit of queued connections is reached
+    pub fn set_connection_limit_policy(&mut self, policy: ConnectionLimitPolicy) {
+        self.connection_limit_policy = policy;
+    }
+
+    /// Listens on `http_port` as well and answers every request there with a
+    /// `301` to the same path and query over HTTPS
+    ///
+    /// Nothing else is ever served on that port. The redirect points to
+    /// `https_authority` (e.g. `example.com` or `example.com:8443`) if given,
+    /// otherwise to the host the client asked for on the default HTTPS port.
+    /// Requests without a usable `Host` header then get a `400`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.redirect_http_to_https(80, Some("example.com"));
+    /// ```
+    pub fn redirect_http_to_https(&mut self, http_port: u16, https_authority: Option<&str>) {
+        self.https_redirect = Some((http_port, https_authority.map(String::from)));
+    }
+
+    /// Redirects requests for any other host to `host` with a `301`,
+    /// keeping path and query
+    ///
+    /// `host` may include a port, e.g. `www.example.com:8443`. This sends
+    /// visitors of the bare domain or the IP address to the one host search
+    /// engines should index and cookies are set for. The host is compared
+    /// with `Request::host`, so behind a trusted proxy the forwarded host
+    /// counts. Returns false and changes nothing if `host` is no valid host.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.set_canonical_host("www.example.com");
+    /// ```
+    pub fn set_canonical_host(&mut self, host: &str) -> bool {
+        let valid = hostname_of(host).is_some_and(|hostname| match &host[hostname.len()..] {
+            "" => true,
+            port => port
+                .strip_prefix(':')
+                .is_some_and(|port| port.parse::<u16>().is_ok()),
+        });
+        if !valid {
+            Logger::warning(&self.logger, &format!("{} is no valid host", host));
+            return false;
+        }
+        self.canonical_host = Some(host.to_ascii_lowercase());
+        true
+    }
+
+    /// Adds a `Strict-Transport-Security` header to every response sent
+    /// over HTTPS, telling browsers to only use HTTPS for this host
+    ///
+    /// Plain HTTP responses never get the header, so browsers are not locked
+    /// out of a host that does not speak HTTPS yet.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::{HstsConfig, Server};
+    /// use std::time::Duration;
+    /// let mut s = Server::new();
+    /// s.enable_hsts(HstsConfig {
+    ///     max_age: Duration::from_secs(31_536_000),
+    ///     include_subdomains: true,
+    ///     preload: false,
+    /// });
+    /// ```
+    pub fn enable_hsts(&mut self, config: HstsConfig) {
+        if config.preload && config.max_age < HSTS_PRELOAD_MIN_AGE {
+            Logger::warning(
+                &self.logger,
+                "HSTS preload requires a max_age of at least one year, browsers will not preload this host",
+            );
+        }
+        if config.preload && !config.include_subdomains {
+            Logger::warning(
+                &self.logger,
+                "HSTS preload requires include_subdomains, browsers will not preload this host",
+            );
+        }
+        self.hsts = Some(config);
+    }
+
+    /// Lets scripts of the `allowed_origins` call this server from their
+    /// pages in browsers
+    ///
+    /// Responses to requests from an allowed origin get an
+    /// `Access-Control-Allow-Origin` header, `*` if every origin is allowed,
+    /// otherwise the origin of the request. Preflight `OPTIONS` requests for
+    /// registered routes are answered unless a route for `OPTIONS` is
+    /// registered. Returns false and logs a warning if credentials are
+    /// allowed for every origin, which browsers refuse, or a value cannot be
+    /// sent in a header.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::{CorsConfig, Server};
+    /// use std::time::Duration;
+    /// let mut s = Server::new();
+    /// s.enable_cors(CorsConfig {
+    ///     allowed_origins: vec![String::from("https://app.example.com")],
+    ///     allow_credentials: true,
+    ///     max_age: Some(Duration::from_secs(600)),
+    ///     ..CorsConfig::default()
+    /// });
+    /// ```
+    pub fn enable_cors(&mut self, config: CorsConfig) -> bool {
+        if config.allow_credentials && config.allows_any_origin() {
+            Logger::warning(
+                &self.logger,
+                "Not enabling CORS, credentials cannot be allowed for every origin",
+            );
+            return false;
+        }
+        let values = config
+            .allowed_origins
+            .iter()
+            .chain(&config.allowed_methods)
+            .chain(&config.allowed_headers);
+        for value in values {
+            if let Err(e) = check_header_value(value) {
+                Logger::warning(&self.logger, &format!("Not enabling CORS: {}", e));
+                return false;
+            }
+        }
+        self.cors = Some(config);
+        true
+    }
+
+    /// Expects every connection to start with a PROXY protocol header, as
+    /// sent by HAProxy or AWS load balancers in TCP mode
+    ///
+    /// Both the text (v1) and binary (v2) format are understood. The client
+    /// address from the header becomes `Request::peer_addr`. Connections
+    /// without a valid header are logged and dropped, so clients cannot
+    /// spoof their address by skipping the proxy.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.enable_proxy_protocol(true);
+    /// ```
+    pub fn enable_proxy_protocol(&mut self, enabled: bool) {
+        self.proxy_protocol = enabled;
+    }
+
+    /// Believes the `X-Forwarded-Proto`, `X-Forwarded-Host` and
+    /// `X-Forwarded-Port` headers of requests from `proxy`, an address like
+    /// `10.0.0.1` or a network like `10.0.0.0/8`
+    ///
+    /// The headers then decide `Request::scheme` and `Request::host`, and
+    /// whether the HSTS header is sent. They are ignored for everyone else,
+    /// since any client can send them. Invalid addresses are logged and
+    /// ignored, returning false.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.trust_proxy("127.0.0.1");
+    /// s.trust_proxy("10.0.0.0/8");
+    /// ```
+    pub fn trust_proxy(&mut self, proxy: &str) -> bool {
+        match IpNetwork::parse(proxy) {
+            Some(proxy) => {
+                self.trusted_proxies.push(proxy);
+                true
+            }
+            None => {
+                Logger::warning(
+                    &self.logger,
+                    &format!("{} is not an IP address or network, not trusting it", proxy),
+                );
+                false
+            }
+        }
+    }
+
+    /// Forwards every request below `prefix` to the plain HTTP server at
+    /// `upstream`, keeping the path as it is
+    ///
+    /// See `proxy_with` for the details and the settings.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.set_document_root("./frontend/");
+    /// s.proxy("/api/", "http://127.0.0.1:9000");
+    /// ```
+    pub fn proxy(&mut self, prefix: &str, upstream: &str) -> bool {
+        self.proxy_with(prefix, upstream, ProxyConfig::default())
+    }
+
+    /// Forwards every request below `prefix` to the plain HTTP server at
+    /// `upstream`, an URL like `http://127.0.0.1:9000` or
+    /// `http://backend/v1`, whose path is put in front of the forwarded one
+    ///
+    /// Method, path, query, headers and body go upstream, apart from headers
+    /// that only concern the connection to the client. `X-Forwarded-For`,
+    /// `X-Forwarded-Proto` and `X-Forwarded-Host` tell the upstream about the
+    /// client. Its response is passed on while it arrives. Idle connections
+    /// to the upstream are kept for the next requests.
+    ///
+    /// Proxied paths take precedence over routes and static files. If the
+    /// upstream cannot be reached the client gets a `502`, if it does not
+    /// answer in time a `504`, both are logged. Returns false and forwards
+    /// nothing if `upstream` is not a plain HTTP URL.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::{ProxyConfig, Server};
+    /// use std::time::Duration;
+    /// let mut s = Server::new();
+    /// s.proxy_with(
+    ///     "/api/",
+    ///     "http://127.0.0.1:9000",
+    ///     ProxyConfig {
+    ///         strip_prefix: true,
+    ///         timeout: Duration::from_secs(5),
+    ///     },
+    /// );
+    /// ```
+    pub fn proxy_with(&mut self, prefix: &str, upstream: &str, config: ProxyConfig) -> bool {
+        match Upstream::parse(upstream, config.timeout) {
+            Some(parsed) => {
+                Logger::info(
+                    &self.logger,
+                    &format!("Forwarding {} to {}", prefix, upstream),
+                );
+                self.proxy_routes.push(ProxyRoute {
+                    prefix: String::from(prefix.trim_end_matches('/')),
+                    strip_prefix: config.strip_prefix,
+                    upstream: Arc::new(parsed),
+                });
+                true
+            }
+            None => {
+                Logger::warning(
+                    &self.logger,
+                    &format!("{} is not a plain HTTP URL, not forwarding to it", upstream),
+                );
+                false
+            }
+        }
+    }
+
+    /// Returns a handle that stops the server, see `ShutdownHandle`
+    ///
+    /// After `shutdown` no more connections are accepted. Requests that are
+    /// running get up to the shutdown deadline to finish, then the log is
+    /// flushed and `start_server` returns. Clones of the server share the
+    /// handle.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Returns the counters of this server, shared by its clones
+    pub fn metrics(&self) -> Metrics {
+        self.metrics.clone()
+    }
+
+    /// Sets how long running requests may take to finish after a shutdown,
+    /// 30 seconds by default
+    ///
+    /// Requests still running afterwards are abandoned and logged.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// let mut s = corrodedweb::Server::new();
+    /// // Docker kills the container 10 seconds after SIGTERM
+    /// s.set_shutdown_deadline(Duration::from_secs(8));
+    /// ```
+    pub fn set_shutdown_deadline(&mut self, deadline: Duration) {
+        self.shutdown_deadline = deadline;
+    }
+
+    /// Sets how many requests are handled at the same time, by default one
+    /// per CPU but at least 8
+    ///
+    /// Returns false and keeps the previous value for 0.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.set_workers(32);
+    /// ```
+    pub fn set_workers(&mut self, workers: usize) -> bool {
+        if workers == 0 {
+            Logger::warning(&self.logger, "A server needs at least one worker");
+            return false;
+        }
+        self.workers = workers;
+        true
+    }
+
+    /// Sets how many requests are handled at the same time, the same as
+    /// `set_workers`
+    pub fn set_worker_count(&mut self, workers: usize) -> bool {
+        self.set_workers(workers)
+    }
+
+    /// Returns how many requests are handled at the same time, see
+    /// `set_workers`
+    pub fn workers(&self) -> usize {
+        self.workers
+    }
+
+    /// Sets the `Retry-After` of the `503 Service Unavailable` sent to
+    /// clients the server turns away, 5 seconds by default
+    ///
+    /// Clients are turned away beyond the connection limit with
+    /// `ConnectionLimitPolicy::Reject`, and while running requests finish
+    /// after a shutdown. Each of them is counted in
+    /// `Metrics::service_unavailable`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// let mut s = corrodedweb::Server::new();
+    /// s.set_retry_after(Duration::from_secs(30));
+    /// ```
+    pub fn set_retry_after(&mut self, retry_after: Duration) {
+        self.service_unavailable = service_unavailable(retry_after);
+    }
+
+    /// Sets how long a client may take from connecting until it sent the
+    /// complete request head, 10 seconds by default
+    ///
+    /// The limit is for the head as a whole, so a client trickling in one
+    /// byte at a time cannot hold on to a worker. Clients exceeding it are
+    /// answered with `408 Request Timeout` before any handler runs, which is
+    /// counted in `Metrics::request_timeouts`. On a kept-alive connection the
+    /// time counts from the first byte of each further request.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// let mut s = corrodedweb::Server::new();
+    /// s.set_request_head_timeout(Duration::from_secs(5));
+    /// ```
+    pub fn set_request_head_timeout(&mut self, timeout: Duration) {
+        self.request_timeouts.head = timeout;
+    }
+
+    /// Sets how long a client may take to send the request body once the
+    /// head is complete, 60 seconds by default
+    ///
+    /// Slower clients are answered with `408 Request Timeout` like those
+    /// exceeding `set_request_head_timeout`.
+    pub fn set_request_body_timeout(&mut self, timeout: Duration) {
+        self.request_timeouts.body = timeout;
+    }
+
+    /// Sets how long a client may take to send the request head and then
+    /// its body, the same as `set_request_head_timeout` and
+    /// `set_request_body_timeout` with `timeout`
+    ///
+    /// Writing the response is limited by `set_write_timeout`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// let mut s = corrodedweb::Server::new();
+    /// s.set_read_timeout(Duration::from_secs(20));
+    /// s.set_write_timeout(Duration::from_secs(20));
+    /// ```
+    pub fn set_read_timeout(&mut self, timeout: Duration) {
+        self.set_request_head_timeout(timeout);
+        self.set_request_body_timeout(timeout);
+    }
+
+    /// Sets how long writing to a client may block before the connection is
+    /// given up, 60 seconds by default, zero for no limit
+    ///
+    /// A client that stops reading fills the socket buffers, after which
+    /// every write waits for it. Once a single write waited this long it
+    /// fails, the handler gets the error from its write or `flush` and the
+    /// worker is free again. Slow clients that keep reading are not
+    /// affected. Connections of the `tokio` feature are not limited.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// let mut s = corrodedweb::Server::new();
+    /// s.set_write_timeout(Duration::from_secs(30));
+    /// ```
+    pub fn set_write_timeout(&mut self, timeout: Duration) {
+        self.write_timeout = timeout;
+    }
+
+    /// Sets how long connections stay open for further requests and for how
+    /// many
+    ///
+    /// Idle connections are closed without a response once `idle_timeout`
+    /// passed since the last one. The response to request number
+    /// `max_requests` says `Connection: close`, `KeepAlive::disabled()` does
+    /// that for every response. Without the `tokio` feature an open
+    /// connection holds on to its worker thread while it is idle. Fails if
+    /// `max_requests` or `idle_timeout` is zero.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::{KeepAlive, Server};
+    /// use std::time::Duration;
+    /// let mut s = Server::new();
+    /// s.set_keep_alive(KeepAlive {
+    ///     idle_timeout: Duration::from_secs(5),
+    ///     max_requests: 100,
+    /// });
+    /// ```
+    pub fn set_keep_alive(&mut self, keep_alive: KeepAlive) -> bool {
+        if keep_alive.max_requests == 0 || keep_alive.idle_timeout.is_zero() {
+            Logger::warning(
+                &self.logger,
+                "Keep-alive needs at least one request and an idle timeout",
+            );
+            return false;
+        }
+        self.keep_alive = keep_alive;
+        true
+    }
+
+    /// Sets how many header lines a request may have, 100 by default
+    ///
+    /// Like the other header limits it is checked while the request is read,
+    /// requests exceeding it are answered with `431 Request Header Fields Too
+    /// Large` before any handler runs.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.set_max_header_count(50);
+    /// ```
+    pub fn set_max_header_count(&mut self, count: usize) {
+        self.header_limits.count = count;
+    }
+
+    /// Sets how many bytes a single header line may have without its line
+    /// break, 8 KiB by default
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.set_max_header_line_length(4096);
+    /// ```
+    pub fn set_max_header_line_length(&mut self, length: usize) {
+        self.header_limits.line = length;
+    }
+
+    /// Sets how many bytes the head of a request may have in total, request
+    /// line included, 32 KiB by default
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.set_max_header_bytes(16 * 1024);
+    /// ```
+    pub fn set_max_header_bytes(&mut self, bytes: usize) {
+        self.header_limits.total = bytes;
+    }
+
+    /// Sets how many bytes the body of a request may have, unlimited by
+    /// default
+    ///
+    /// Requests whose `Content-Length` exceeds it are answered with `413
+    /// Content Too Large` before any handler runs. Reading a chunked body
+    /// fails once it grows beyond the limit. URL-encoded forms are read
+    /// completely before their handler runs, so without a limit they may
+    /// have up to 8 MiB.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.set_max_body_size(1024 * 1024);
+    /// ```
+    pub fn set_max_body_size(&mut self, bytes: u64) {
+        self.max_body_size = Some(bytes);
+    }
+
+    /// Answers requests that are out of spec with 400 instead of making
+    /// sense of them, off by default
+    ///
+    /// The lenient parser tolerates obs-folded header lines, whitespace
+    /// between a header name and its colon, `Content-Length` together with
+    /// `Transfer-Encoding`, NUL bytes in the head and header names that are
+    /// not ASCII. Such requests are what request smuggling is built from,
+    /// so strict parsing rejects them and logs which rule was broken.
+    /// Leniently, a chunked body wins over a length and the connection is
+    /// closed after the response. Several `Content-Length` headers with
+    /// differing values are rejected either way.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.set_strict_parsing(true);
+    /// ```
+    pub fn set_strict_parsing(&mut self, strict: bool) {
+        self.strict_parsing = strict;
+    }
+
+    /// Answers `504 Gateway Timeout` for requests whose handler takes longer
+    /// than `timeout`, unless their route sets a timeout of its own with
+    /// `RouteBuilder::timeout`
+    ///
+    /// Handlers with a timeout run on a thread of their own while the worker
+    /// waits. Their response is collected in memory and sent once the
+    /// handler returns, so it is not streamed. Once the timeout passed, the
+    /// client gets the `504`, the timeout is logged with the route, and
+    /// whatever the handler writes afterwards fails and goes nowhere.
+    ///
+    /// A thread cannot be stopped from the outside, so the handler's thread
+    /// lives on until the handler returns. Such handlers are counted in
+    /// `Metrics::zombie_handlers`, a growing number of them tells that a
+    /// handler hangs for good.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// let mut s = corrodedweb::Server::new();
+    /// s.set_handler_timeout(Duration::from_secs(30));
+    /// ```
+    pub fn set_handler_timeout(&mut self, timeout: Duration) {
+        self.handler_timeout = Some(timeout);
+    }
+
+    /// Logs every request and response in full at debug level, for finding
+    /// out what exactly a client sends and gets while developing
+    ///
+    /// **Development only.** The dump holds request and response bodies,
+    /// which may contain passwords and personal data, and slows every
+    /// request down. Never enable it in production.
+    ///
+    /// The dump shows the request head, the response status line and
+    /// headers, and the start of both bodies as a hexdump, see
+    /// `set_debug_dump_body_limit`. The values of `Authorization`,
+    /// `Proxy-Authorization`, `Cookie` and `Set-Cookie` are hidden unless
+    /// `set_debug_dump_unredacted` is on. Nothing is dumped without a
+    /// logger. Static files are dumped without their content.
+    ///
+    /// Responses carry the headers `X-Connection-Id` and `X-Request-Id` with
+    /// the ids of `Request::connection_id` and `Request::request_id` while
+    /// the dump is on, to find a response's entries in the log.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.set_logger("./file.log");
+    /// s.set_debug_dump(true);
+    /// ```
+    pub fn set_debug_dump(&mut self, enabled: bool) {
+        self.debug_dump.enabled = enabled;
+    }
+
+    /// Sets how many bytes of each body `set_debug_dump` shows, 256 by
+    /// default
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.set_debug_dump_body_limit(4096);
+    /// ```
+    pub fn set_debug_dump_body_limit(&mut self, bytes: usize) {
+        self.debug_dump.body_limit = bytes;
+    }
+
+    /// Shows credentials and cookies in the dump of `set_debug_dump`
+    /// instead of hiding them
+    ///
+    /// **Development only**, the log then holds everything needed to act
+    /// as the users who sent requests.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.set_debug_dump_unredacted(true);
+    /// ```
+    pub fn set_debug_dump_unredacted(&mut self, unredacted: bool) {
+        self.debug_dump.unredacted = unredacted;
+    }
+
+    /// Measures how long parsing, routing, the handler and sending the
+    /// response take for every request, off by default
+    ///
+    /// The timings are logged at debug level, like `phase timings:
+    /// parse=0.1ms route=0.02ms handler=45ms write=3ms`, and passed to the
+    /// `on_request_complete` callback. The time the handler spends sending
+    /// its response counts as writing, not as the handler's. Static files
+    /// count as writing as a whole. Reading the request off the connection
+    /// is not part of any phase.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.set_logger("./file.log");
+    /// s.set_phase_timings(true);
+    /// ```
+    pub fn set_phase_timings(&mut self, enabled: bool) {
+        self.phase_timings = enabled;
+    }
+
+    /// Keeps values for each client across its requests, see
+    /// `Request::session`
+    ///
+    /// A client without a session gets an empty one on each request to a
+    /// route, which is only kept once the handler inserts a value. Its
+    /// random id then goes out in the HttpOnly cookie `corroded_session`
+    /// with the head of the response, so a handler that streams its body
+    /// inserts before writing. Sessions are kept in memory, shared by all workers, and forgotten
+    /// after 30 minutes without requests unless `set_session_expiry` says
+    /// otherwise.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.enable_sessions();
+    /// s.post("/login/", |request, _response| {
+    ///     let user = request.get_post_parameters().get("user");
+    ///     if let (Some(session), Some(user)) = (request.session(), user) {
+    ///         session.insert("user", user);
+    ///     }
+    /// });
+    /// ```
+    pub fn enable_sessions(&mut self) {
+        if self.sessions.is_none() {
+            self.sessions = Some(SessionStore::new(session::DEFAULT_EXPIRY));
+        }
+    }
+
+    /// Enables sessions like `enable_sessions`, forgetting them after
+    /// `expiry` without requests
+    ///
+    /// Sessions started before are forgotten.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.set_session_expiry(std::time::Duration::from_secs(8 * 60 * 60));
+    /// ```
+    pub fn set_session_expiry(&mut self, expiry: Duration) {
+        self.sessions = Some(SessionStore::new(expiry));
+    }
+
+    /// Shuts down gracefully once the process receives SIGINT or SIGTERM,
+    /// or Ctrl-C on Windows, see `shutdown_handle`
+    ///
+    /// Only one server per process can listen for signals. If another
+    /// handler is installed already, a warning is logged and signals are
+    /// left alone.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.shutdown_on_signals(true);
+    /// ```
+    #[cfg(feature = "signals")]
+    pub fn shutdown_on_signals(&mut self, enabled: bool) {
+        self.shutdown_on_signals = enabled;
+    }
+
+    #[track_caller]
+    fn register(&mut self, method: &str, route: &str, callback: Callback) -> RouteBuilder {
+        if self.started.load(Ordering::SeqCst) {
+            Logger::warning(
+                &self.logger,
+                &format!(
+                    "Route {} {} was registered after the server started, ignoring it",
+                    method, route
+                ),
+            );
+            return RouteBuilder {
+                endpoints: self.registered_endpoints.clone(),
+                names: self.route_names.clone(),
+                key: None,
+            };
+        }
+        let registered_at = Location::caller();
+        let mut endpoints = self.registered_endpoints.lock().unwrap();
+        if let Some(existing) = endpoints.find(method, route) {
+            let message = format!(
+                "Route {} {} was registered twice (first as {} at {}, again at {})",
+                method,
+                route,
+                existing.pattern(),
+                existing.value().registered_at,
+                registered_at
+            );
+            match self.duplicate_route_policy {
+                DuplicateRoutePolicy::Panic => {
+                    // Other clones of the server share the routes, which
+                    // must not be left poisoned
+                    drop(endpoints);
+                    panic!("{}", message)
+                }
+                DuplicateRoutePolicy::Ignore => {
+                    Logger::warning(&self.logger, &format!("{}, ignoring the new one", message));
+                    return RouteBuilder {
+                        endpoints: self.registered_endpoints.clone(),
+                        names: self.route_names.clone(),
+                        key: None,
+                    };
+                }
+                DuplicateRoutePolicy::Replace => {
+                    Logger::warning(&self.logger, &format!("{}, replacing the old one", message));
+                }
+            }
+        }
+        endpoints.insert(
+            method,
+            route,
+            Endpoint {
+                callback,
+                description: None,
+                timeout: None,
+                registered_at,
+            },
+        );
+        Logger::info(
+            &self.logger,
+            &format!("Registered route: {}, method: {}", route, method),
+        );
+        RouteBuilder {
+            endpoints: self.registered_endpoints.clone(),
+            names: self.route_names.clone(),
+            key: Some((String::from(method), String::from(route))),
+        }
+    }
+
+    /// Returns all registered routes sorted by pattern and method
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.get("/", |request, mut response| {});
+    /// for route in s.routes() {
+    ///     println!("{} {}", route.method, route.pattern);
+    /// }
+    /// ```
+    pub fn routes(&self) -> Vec<RouteInfo> {
+        let mut routes: Vec<RouteInfo> = self
+            .registered_endpoints
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|route| RouteInfo {
+                method: String::from(route.method()),
+                pattern: String::from(route.pattern()),
+                description: route.value().description.clone(),
+            })
+            .collect();
+        routes.sort_by(|a, b| (&a.pattern, &a.method).cmp(&(&b.pattern, &b.method)));
+        routes
+    }
+
+    /// Starts a request that is handled in memory, without a port or threads
+    ///
+    /// Meant for testing handlers, see `TestRequest` for an example. The
+    /// request sees the routes registered so far.
+    pub fn test_request(&self, method: &str, target: &str) -> TestRequest<'_> {
+        TestRequest::new(self, method, target)
+    }
+
+    /// Serves an HTML list of all registered routes on `path`. Meant for
+    /// debugging, so it is disabled unless this is called.
+    pub fn enable_route_listing(&mut self, path: &str) {
+        self.route_listing = Some(String::from(path));
+    }
+
+    fn generate_route_listing(&self) -> String {
+        let mut listing =
+            String::from("<html><table><tr><th>Method</th><th>Route</th><th>Description</th></tr>");
+        for route in self.routes() {
+            listing.push_str(&format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape_html(&route.method),
+                escape_html(&route.pattern),
+                escape_html(route.description.as_deref().unwrap_or(""))
+            ));
+        }
+        listing.push_str("</table></html>");
+        listing
+    }
+
+    /// Starts serving on the port set with `set_port`, or on 7878, see
+    /// `start_server`
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.set_port(8080);
+    /// // Blocks, so it is commented out
+    /// // s.start().unwrap();
+    /// ```
+    pub fn start(&self) -> std::io::Result<()> {
+        self.start_server(self.port.unwrap_or(DEFAULT_PORT))
+    }
+
+    /// Starts serving your files or listening for your registered enpoints.
+    ///
+    /// Routes are frozen at this point. Registering routes on a clone of the
+    /// server afterwards only logs a warning.
+    ///
+    /// With the `systemd` feature, a process started by systemd socket
+    /// activation serves the sockets it was passed instead and ignores
+    /// `addr`. systemd is told once the server is ready.
+    ///
+    /// Returns after a shutdown, or once a listening socket fails in a way
+    /// accepting cannot recover from. `ShutdownHandle::error` tells these
+    /// apart. Running out of file descriptors is waited out.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - A port to listen on the loopback interface, or any address
+    ///   `std::net::ToSocketAddrs` accepts, see `ListenAddr`
+    ///
+    /// Fails if the address cannot be resolved or bound. The addresses
+    /// added with `add_listen_addr` are bound as well.
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// // Block, so they are commented out
+    /// // s.start_server(7878).unwrap();
+    /// // s.start_server("0.0.0.0:8080").unwrap();
+    /// // s.start_server(("::", 8080)).unwrap();
+    /// ```
+    pub fn start_server<A: ListenAddr>(&self, addr: A) -> std::io::Result<()> {
+        let listeners = self.bind_listeners(addr)?;
+        self.serve_listeners(listeners);
+        Ok(())
+    }
+
+    /// Starts serving like `start_server`, but in the background, and
+    /// returns a handle to stop the server and wait for it
+    ///
+    /// Fails like `start_server` if an address cannot be bound, so the
+    /// server accepts connections once this returned. Dropping the handle
+    /// leaves the server running.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.get("/", |_request, mut response| {
+    ///     let _ = response.write("hello");
+    /// });
+    /// let handle = s.spawn("127.0.0.1:0").unwrap();
+    /// println!("listening on {}", handle.listeners()[0].address);
+    /// handle.shutdown();
+    /// handle.join();
+    /// ```
+    pub fn spawn<A: ListenAddr>(&self, addr: A) -> std::io::Result<ServerHandle> {
+        let listeners = self.bind_listeners(addr)?;
+        let server = self.clone();
+        thread::spawn(move || server.serve_listeners(listeners));
+        // So the handle lists the listeners right away
+        self.ready
+            .wait_for(|state| !state.listeners.is_empty() || state.stopped);
+        Ok(self.handle())
+    }
+
+    /// Adds an address to listen on besides the one passed to
+    /// `start_server` or `spawn`, e.g. for IPv4 and IPv6 or several ports
+    ///
+    /// Connections on every address are served the same way and one
+    /// shutdown stops all of them. Returns false and logs a warning if the
+    /// address cannot be resolved.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use corrodedweb::Server;
+    /// let mut s = Server::new();
+    /// s.add_listen_addr(("::1", 8080));
+    /// // Listens on 127.0.0.1:8080 and [::1]:8080, blocks
+    /// // s.start_server(8080).unwrap();
+    /// ```
+    pub fn add_listen_addr<A: ListenAddr>(&mut self, addr: A) -> bool {
+        match addr.listen_addrs() {
+            Ok(addrs) => {
+                self.listen_addrs.push(addrs);
+                true
+            }
+            Err(e) => {
+                Logger::warning(&self.logger, &format!("Cannot listen on an address: {}", e));
+                false
+            }
+        }
+    }
+
+    /// Returns the addresses added with `add_listen_addr` or by the
+    /// configuration, as they were resolved
+    pub fn listen_addrs(&self) -> Vec<SocketAddr> {
+        self.listen_addrs.iter().flatten().copied().collect()
+    }
+
+    /// Binds `addr` and the addresses added with `add_listen_addr`, or takes
+    /// the sockets systemd passed
+    fn bind_listeners<A: ListenAddr>(&self, addr: A) -> std::io::Result<Vec<TcpListener>> {
+        #[cfg(feature = "signals")]
+        {
+            // Before binding, so no signal arrives while nobody listens
+            if self.shutdown_on_signals {
+                if let Err(e) = crate::shutdown::shutdown_on_signals(self.shutdown.clone()) {
+                    Logger::warning(
+                        &self.logger,
+                        &format!("Could not listen for signals: {}", e),
+                    );
+                }
+            }
+        }
+
+        #[cfg(all(unix, feature = "systemd"))]
+        {
+            if let Some(listeners) = crate::systemd::listeners(&self.logger) {
+                return Ok(listeners);
+            }
+        }
+
+        let mut listeners = vec![self.bind(addr.listen_addrs())?];
+        for addrs in &self.listen_addrs {
+            listeners.push(self.bind(Ok(addrs.clone()))?);
+        }
+        Ok(listeners)
+    }
+
+    /// Binds the first of `addrs` that can be bound
+    fn bind(&self, addrs: std::io::Result<Vec<SocketAddr>>) -> std::io::Result<TcpListener> {
+        let listener = match addrs.and_then(|addrs| TcpListener::bind(&addrs[..])) {
+            Ok(listener) => listener,
+            Err(e) => {
+                Logger::warning(&self.logger, &format!("Could not listen: {}", e));
+                return Err(e);
+            }
+        };
+        Logger::info(
+            &self.logger,
+            &format!(
+                "Listening on {} for incomming connections",
+                listener.local_addr()?
+            ),
+        );
+        Ok(listener)
+    }
+
+    /// Describes the listeners serving requests
+    fn listener_infos(&self, listeners: &[TcpListener]) -> Vec<ListenerInfo> {
+        #[allow(unused_mut)]
+        let mut protocol = Protocol::Http;
+        #[cfg(any(feature = "tls", feature = "native-tls"))]
+        {
+            if self.tls.is_some() {
+                protocol = Protocol::Https;
+            }
+        }
+        listeners
+            .iter()
+            .filter_map(|listener| listener.local_addr().ok())
+            .map(|address| ListenerInfo { address, protocol })
+            .collect()
+    }
+
+    /// Serves connections from all `listeners` until a shutdown, every
+    /// listener but the first on a thread of its own
+    pub(crate) fn serve_listeners(&self, listeners: Vec<TcpListener>) {
+        // Routes never change while serving, so requests share a frozen copy
+        // instead of locking the registry every time
+        self.started.store(true, Ordering::SeqCst);
+        let routes = Arc::new(self.registered_endpoints.lock().unwrap().clone());
+        self.warn_about_shadowed_files();
+
+        let addresses: Vec<SocketAddr> = listeners
+            .iter()
+            .filter_map(|listener| listener.local_addr().ok())
+            .collect();
+        for address in &addresses {
+            self.shutdown.register_listener(*address);
+        }
+
+        let mut listed = self.listener_infos(&listeners);
+        let redirecting = self
+            .https_redirect
+            .as_ref()
+            .and_then(|(http_port, authority)| {
+                self.spawn_https_redirect(*http_port, authority.clone())
+            })
+            .map(|(address, redirecting)| {
+                listed.push(ListenerInfo {
+                    address,
+                    protocol: Protocol::HttpsRedirect,
+                });
+                redirecting
+            });
+        self.ready.update(|state| state.listeners = listed);
+        #[cfg(any(feature = "tls", feature = "native-tls"))]
+        {
+            if let (Some(tls), Some(interval)) = (&self.tls, self.tls_reload_interval) {
+                self.spawn_tls_reloader(tls.clone(), interval);
+            }
+        }
+
+        if let Some(on_start) = &self.on_start {
+            #[allow(unused_mut)]
+            let mut tls = false;
+            #[cfg(any(feature = "tls", feature = "native-tls"))]
+            {
+                tls |= self.tls.is_some();
+            }
+            on_start(&StartupInfo {
+                addresses: addresses.clone(),
+                tls,
+                workers: self.workers,
+                routes: self.routes(),
+            });
+        }
+
+        if let Some(address) = addresses.first() {
+            self.ready.update(|state| state.address = Some(*address));
+        }
+
+        let mut listeners = listeners.into_iter();
+        let first = match listeners.next() {
+            Some(listener) => listener,
+            None => {
+                self.ready.update(|state| state.stopped = true);
+                return;
+            }
+        };
+        let others: Vec<_> = listeners
+            .map(|listener| {
+                let server = self.clone();
+                let routes = routes.clone();
+                thread::spawn(move || server.accept_connections(listener, routes))
+            })
+            .collect();
+        #[cfg(all(unix, feature = "systemd"))]
+        {
+            if let Err(e) = crate::systemd::notify("READY=1") {
+                Logger::warning(&self.logger, &format!("Could not notify systemd: {}", e));
+            }
+        }
+
+        self.accept_connections(first, routes);
+        for other in others.into_iter().chain(redirecting) {
+            let _ = other.join();
+        }
+
+        let addresses: Vec<String> = addresses.iter().map(|a| a.to_string()).collect();
+        Logger::info(
+            &self.logger,
+            &format!("Stopped serving {}", addresses.join(", ")),
+        );
+        Logger::flush(&self.logger);
+        self.ready.update(|state| {
+            state.listeners.clear();
+            state.stopped = true;
+        });
+    }
+
+    /// Serves connections from `listener` until a shutdown
+    fn accept_connections(&self, listener: TcpListener, routes: Arc<Router<Endpoint>>) {
+        #[cfg(feature = "tokio")]
+        self.run_on_tokio(listener, routes);
+        #[cfg(not(feature = "tokio"))]
+        self.run_on_threads(listener, routes);
+    }
+
+    /// Logs how the requests that were running at shutdown ended
+    fn log_drained(&self, still_running: usize) {
+        if still_running == 0 {
+            Logger::info(&self.logger, "Shutting down, all requests finished");
+        } else {
+            Logger::warning(
+                &self.logger,
+                &format!(
+                    "Shutting down, abandoning {} requests that did not finish in time",
+                    still_running
+                ),
+            );
+        }
+    }
+
+    /// Accepts connections and serves each of them on a worker thread
+    #[cfg(not(feature = "tokio"))]
+    fn run_on_threads(&self, listener: TcpListener, routes: Arc<Router<Endpoint>>) {
+        let threadpool = ThreadPool::with_counts(self.workers, self.metrics.jobs());
+        let slots = self.max_connections.map(|max| {
+            Arc::new(ConnectionSlots {
+                max,
+                open: Mutex::new(0),
+                freed: Condvar::new(),
+            })
+        });
+        let mut accept_errors = AcceptErrors::default();
+        let mut rejecter = None;
+        // Shared by all connections instead of cloning the server for
+        // each of them
+        let server = Arc::new(self.clone());
+
+        while !self.shutdown.is_shutting_down() {
+            if let (Some(slots), ConnectionLimitPolicy::Defer) =
+                (&slots, self.connection_limit_policy)
+            {
+                slots.wait_for_free();
+            }
+            if let (Some(max), ConnectionLimitPolicy::Defer) =
+                (self.max_queued_connections, self.connection_limit_policy)
+            {
+                threadpool.wait_for_room(max);
+            }
+
+            let stream = match listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(e) => match accept_errors.handle(&e, &self.logger) {
+                    AcceptAction::Retry => continue,
+                    AcceptAction::Backoff => {
+                        thread::sleep(ACCEPT_BACKOFF);
+                        continue;
+                    }
+                    AcceptAction::Fatal => {
+                        self.shutdown.fail(&e);
+                        break;
+                    }
+                },
+            };
+            if self.shutdown.is_shutting_down() {
+                // Most likely the connection waking up this loop
+                break;
+            }
+
+            if let (Some(max), ConnectionLimitPolicy::Reject) =
+                (self.max_queued_connections, self.connection_limit_policy)
+            {
+                if !threadpool.has_room(max) {
+                    Logger::info(
+                        &self.logger,
+                        "All workers busy and the queue full, answering 503",
+                    );
+                    let _ = self.rejecter(&mut rejecter).try_send(stream);
+                    continue;
+                }
+            }
+
+            let slot = match &slots {
+                Some(slots) => match ConnectionSlots::try_acquire(slots) {
+                    Some(slot) => Some(slot),
+                    None => {
+                        Logger::info(&self.logger, "Connection limit reached, answering 503");
+                        // If even the rejecter is backed up the
+                        // connection is just closed
+                        let _ = self.rejecter(&mut rejecter).try_send(stream);
+                        continue;
+                    }
+                },
+                None => None,
+            };
+
+            let server = server.clone();
+            let routes = routes.clone();
+            let in_flight = self.shutdown.begin_request();
+            let accepted = Instant::now();
+            threadpool.execute(move || {
+                server.serve_connection(stream, accepted, &routes);
+                drop(slot);
+                drop(in_flight);
+            });
+        }
+
+        let still_running = self.drain(listener, &mut rejecter);
+        self.log_drained(still_running);
+        if still_running > 0 {
+            // Joining the workers would wait for the abandoned requests
+            std::mem::forget(threadpool);
+        }
+    }
+
+    /// Returns the thread answering turned away connections, starting it
+    /// when it is first needed
+    #[cfg(not(feature = "tokio"))]
+    fn rejecter<'a>(
+        &self,
+        rejecter: &'a mut Option<SyncSender<TcpStream>>,
+    ) -> &'a SyncSender<TcpStream> {
+        rejecter.get_or_insert_with(|| {
+            spawn_rejecter(self.service_unavailable.clone(), self.metrics.clone())
+        })
+    }
+
+    /// Waits for the running requests after a shutdown, answering
+    /// connections that come in meanwhile with 503 instead of refusing them
+    ///
+    /// Returns how many requests are still running at the deadline.
+    #[cfg(not(feature = "tokio"))]
+    fn drain(&self, listener: TcpListener, rejecter: &mut Option<SyncSender<TcpStream>>) -> usize {
+        let deadline = Instant::now() + self.shutdown_deadline;
+        if listener.set_nonblocking(true).is_err() {
+            return self.shutdown.wait_for_requests(self.shutdown_deadline);
+        }
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let still_running = self
+                .shutdown
+                .wait_for_requests(remaining.min(Duration::from_millis(10)));
+            if still_running == 0 || remaining.is_zero() {
+                return still_running;
+            }
+            while let Ok((stream, _)) = listener.accept() {
+                // Accepted sockets inherit non-blocking mode on some systems
+                if stream.set_nonblocking(false).is_ok() {
+                    let _ = self.rejecter(rejecter).try_send(stream);
+                }
+            }
+        }
+    }
+
+    /// Accepts connections and does their I/O on tokio, running only the
+    /// handlers on worker threads
+    ///
+    /// Connections needing the blocking server, for TLS or the PROXY
+    /// protocol, are handed over to it whole.
+    #[cfg(feature = "tokio")]
+    fn run_on_tokio(&self, listener: TcpListener, routes: Arc<Router<Endpoint>>) {
+        let server = Arc::new(self.clone());
+        #[allow(unused_mut)]
+        let mut needs_blocking = self.proxy_protocol;
+        #[cfg(any(feature = "tls", feature = "native-tls"))]
+        {
+            needs_blocking |= self.tls.is_some();
+        }
+
+        let exchange = {
+            let server = server.clone();
+            let routes = routes.clone();
+            move |request, connection: Connection| {
+                let stream = MemoryStream {
+                    input: io::Cursor::new(request),
+                    output: Arc::default(),
+                    encrypted: false,
+                    remaining_requests: connection.remaining_requests,
+                    sni_hostname: None,
+                    #[cfg(feature = "tls")]
+                    client_certificate: None,
+                    body_feed: connection.body_feed,
+                };
+                server.handle_in_memory(stream, connection.peer_addr, connection.id, &routes)
+            }
+        };
+        let fallback = move |tcp| server.serve_connection(tcp, Instant::now(), &routes);
+        let listener_config = AsyncListener {
+            exchange: Arc::new(exchange),
+            fallback: if needs_blocking {
+                Some(Arc::new(fallback))
+            } else {
+                None
+            },
+            max_connections: self.max_connections,
+            reject_when_full: self.connection_limit_policy == ConnectionLimitPolicy::Reject,
+            workers: self.workers,
+            shutdown: self.shutdown.clone(),
+            timeouts: self.request_timeouts,
+            header_limits: self.header_limits,
+            keep_alive: self.keep_alive,
+            metrics: self.metrics.clone(),
+            shutdown_deadline: self.shutdown_deadline,
+            service_unavailable: self.service_unavailable.clone(),
+            logger: self.logger.clone(),
+        };
+        match listener_config.run(listener) {
+            Ok(still_running) => self.log_drained(still_running),
+            Err(e) => Logger::warning(&self.logger, &format!("Could not serve connections: {}", e)),
+        }
+    }
+
+    /// Reloads the TLS certificates in the background whenever their files
+    /// change
+    #[cfg(any(feature = "tls", feature = "native-tls"))]
+    fn spawn_tls_reloader(&self, tls: TlsConfig, interval: Duration) {
+        let logger = self.logger.clone();
+        thread::spawn(move || {
+            let mut loaded = tls.files_modified();
+            loop {
+                thread::sleep(interval);
+                let modified = tls.files_modified();
+                if modified == loaded {
+                    continue;
+                }
+                // Remembered even if the reload fails, the next write to the
+                // files triggers another attempt
+                loaded = modified;
+                match tls.reload() {
+                    Ok(()) => Logger::info(&logger, "Reloaded TLS certificates"),
+                    Err(e) => Logger::warning(
+                        &logger,
+                        &format!(
+                            "Could not reload TLS certificates, keeping the old ones: {}",
+                            e
+                        ),
+                    ),
+                }
+            }
+        });
+    }
+
+    /// Starts the listener redirecting plain HTTP to HTTPS in the
+    /// background, returning its address and the thread, which ends with a
+    /// shutdown
+    fn spawn_https_redirect(
+        &self,
+        port: u16,
+        authority: Option<String>,
+    ) -> Option<(SocketAddr, thread::JoinHandle<()>)> {
+        let bound = TcpListener::bind(format!("127.0.0.1:{}", port))
+            .and_then(|listener| Ok((listener.local_addr()?, listener)));
+        let (address, listener) = match bound {
+            Ok(bound) => bound,
+            Err(e) => {
+                Logger::warning(
+                    &self.logger,
+                    &format!("Could not open port {} for HTTPS redirects: {}", port, e),
+                );
+                return None;
+            }
+        };
+        Logger::info(
+            &self.logger,
+            &format!("Open TCP Port {} for redirects to HTTPS", port),
+        );
+        self.shutdown.register_listener(address);
+        let server = Arc::new(self.clone());
+        let redirecting = thread::spawn(move || {
+            let threadpool = ThreadPool::new(2);
+            let mut accept_errors = AcceptErrors::default();
+            loop {
+                let accepted = listener.accept();
+                if server.shutdown.is_shutting_down() {
+                    break;
+                }
+                let mut stream = match accepted {
+                    Ok((stream, _)) => stream,
+                    Err(e) => match accept_errors.handle(&e, &server.logger) {
+                        AcceptAction::Retry => continue,
+                        AcceptAction::Backoff => {
+                            thread::sleep(ACCEPT_BACKOFF);
+                            continue;
+                        }
+                        AcceptAction::Fatal => {
+                            server.shutdown.fail(&e);
+                            break;
+                        }
+                    },
+                };
+                let authority = authority.clone();
+                let server = server.clone();
+                threadpool.execute(move || {
+                    if server.read_peer_addr(&stream).is_none() {
+                        return;
+                    }
+                    let mut buffer = PooledBuffer::take();
+                    let bytes_read = stream.read(&mut buffer).unwrap_or(0);
+                    let head = match RequestHead::parse(&buffer[..bytes_read]) {
+                        Some(head) => head,
+                        None => return,
+                    };
+                    let location = Server::https_location(&head, authority.as_deref());
+                    let mut response = Response::new(Stream::Plain(stream));
+                    response.defaults.set("Connection", "close");
+                    match location {
+                        Some(location) => {
+                            let _ = response.send_redirect(&location, 301);
+                        }
+                        None => response.status = Some(400),
+                    }
+                });
+            }
+        });
+        Some((address, redirecting))
+    }
+
+    /// Returns the HTTPS URL for a request that came in over plain HTTP, or
+    /// `None` if there is no authority to send the client to
+    fn https_location(head: &RequestHead, authority: Option<&str>) -> Option<String> {
+        let host;
+        let authority = match authority {
+            Some(authority) => authority,
+            None => {
+                host = match head.authority {
+                    Some(authority) => String::from(authority),
+                    None => Server::parse_headers(head.headers).remove("host")?,
+                };
+                // The port belongs to plain HTTP, the redirect goes to 443
+                hostname_of(&host)?
+            }
+        };
+        let target = head.target.split('#').next().unwrap_or("");
+        let target = if target.starts_with('/') { target } else { "/" };
+        Some(format!("https://{}{}", authority, target))
+    }
+
+    /// Splits `a=1&b=2` into its pairs, in order, with keys and values
+    /// percent-decoded and `+` read as a space
+    fn parse_parameters(parameter_string: Option<&&str>) -> Vec<(String, String)> {
+        let mut pairs = Vec::new();
+        let parameters: Vec<&str> = if let Some(string) = parameter_string {
+            if string.is_empty() {
+                Vec::new()
+            } else {
+                string.split('&').collect()
+            }
+        } else {
+            Vec::new()
+        };
+        for param in parameters {
+            let (key, value) = param.split_once('=').unwrap_or((param, ""));
+            pairs.push((percent::decode_form(key), percent::decode_form(value)));
+        }
+        pairs
+    }
+
+    /// Reads the rest of a URL-encoded form or JSON body before its handler
+    /// runs, so they are complete, returning the status to answer with
+    /// instead if it cannot be read
+    fn read_form(&self, request: &mut Request) -> Result<(), u32> {
+        let is_form = request.is_urlencoded();
+        let incoming = match request.incoming_body.take() {
+            Some(incoming) if is_form || request.is_json() => incoming,
+            incoming => {
+                request.incoming_body = incoming;
+                return Ok(());
+            }
+        };
+        let limit = self.max_body_size.unwrap_or(DEFAULT_MAX_FORM_SIZE);
+        let too_large = || {
+            Logger::info(
+                &self.logger,
+                &format!("Status 413: form larger than {} bytes", limit),
+            );
+            Err(413)
+        };
+        if let Some(Framing::Length(len)) = Framing::of(&request.headers) {
+            if len > limit {
+                return too_large();
+            }
+        }
+        let mut body = Vec::new();
+        match incoming.reader().take(limit + 1).read_to_end(&mut body) {
+            Ok(len) if len as u64 > limit => too_large(),
+            Ok(_) => {
+                if is_form {
+                    request.post_pairs =
+                        Server::parse_parameters(str::from_utf8(&body).ok().as_ref());
+                    request.post_parameters = first_values(&request.post_pairs);
+                }
+                request.raw_body = body;
+                Ok(())
+            }
+            Err(_) if incoming.exceeded() => too_large(),
+            Err(e) => {
+                Logger::info(
+                    &self.logger,
+                    &format!("Status 400: form could not be read: {}", e),
+                );
+                Err(400)
+            }
+        }
+    }
+
+    /// Starts a response carrying the headers every response on this stream
+    /// gets
+    fn response(&self, stream: Stream, request: &Request) -> Response {
+        let mut response = Response::new(stream);
+        response.chunked_allowed = request.version == "HTTP/1.1";
+        response.set_defaults(self.response_headers(request));
+        response.accept_used = Some(request.accept_used.clone());
+        response.default_charset = self.default_charset.clone();
+        response.mime_types = self.mime_types.clone();
+        response.templates = self.templates.clone();
+        response.logger = self.logger.clone();
+        response.dump = Dump::new(&self.logger, self.debug_dump);
+        if self.server_timing {
+            response.server_timing = Some(request.received);
+        }
+        #[cfg(feature = "gzip")]
+        {
+            response.compression = self.accepts_gzip(request);
+        }
+        response
+    }
+
+    /// Returns whether `request` accepts gzip, `None` while compression is
+    /// off
+    #[cfg(feature = "gzip")]
+    fn accepts_gzip(&self, request: &Request) -> Option<bool> {
+        self.compression.then(|| {
+            request
+                .get_header("Accept-Encoding")
+                .is_some_and(compression::accepts_gzip)
+        })
+    }
+
+    /// Compresses a page the server generated the way bodies of handlers
+    /// are, and returns the header lines to send along
+    #[cfg(feature = "gzip")]
+    fn compress_page(&self, request: &Request, content_type: &str, body: &mut Vec<u8>) -> String {
+        let accepted = match self.accepts_gzip(request) {
+            Some(accepted) if compression::compressible(content_type) => accepted,
+            _ => return String::new(),
+        };
+        if !accepted || body.len() < compression::MIN_SIZE {
+            return String::from("Vary: Accept-Encoding\r\n");
+        }
+        match compression::gzip(body) {
+            Ok(compressed) if compressed.len() < body.len() => {
+                *body = compressed;
+                String::from("Content-Encoding: gzip\r\nVary: Accept-Encoding\r\n")
+            }
+            _ => String::from("Vary: Accept-Encoding\r\n"),
+        }
+    }
+
+    /// Returns the headers every response to this request gets
+    fn response_headers(&self, request: &Request) -> Vec<(String, String)> {
+        let mut headers = Vec::new();
+        if !request.keep_alive {
+            headers.push((String::from("Connection"), String::from("close")));
+        } else if request.version == "HTTP/1.0" {
+            headers.push((String::from("Connection"), String::from("keep-alive")));
+        }
+        if !self.default_headers.contains("Date") {
+            headers.push((
+                String::from("Date"),
+                httpdate::format_http_date(SystemTime::now()),
+            ));
+        }
+        if self.debug_dump.enabled {
+            headers.push((
+                String::from("X-Connection-Id"),
+                request.connection_id.to_string(),
+            ));
+            headers.push((String::from("X-Request-Id"), request.request_id.to_string()));
+        }
+        if let (Some(hsts), "https") = (&self.hsts, request.scheme()) {
+            headers.push((
+                String::from("Strict-Transport-Security"),
+                hsts.header_value(),
+            ));
+        }
+        if let Some(cors) = &self.cors {
+            headers.extend(cors.headers(request));
+        }
+        headers.extend(
+            self.default_headers
+                .iter()
+                .map(|(name, value)| (String::from(name), String::from(value))),
+        );
+        for (name, _) in request.middleware_headers.iter() {
+            headers.retain(|(existing, _)| !existing.eq_ignore_ascii_case(name));
+        }
+        headers.extend(
+            request
+                .middleware_headers
+                .iter()
+                .map(|(name, value)| (String::from(name), String::from(value))),
+        );
+        headers
+    }
+
+    /// Gives the request the session of its client, returns the session
+    /// and whether its cookie is only for https if the session is new
+    fn attach_session(&self, request: &mut Request) -> Option<(Session, bool)> {
+        let store = self.sessions.as_ref()?;
+        match store.session(request.get_cookie(session::COOKIE_NAME)) {
+            Ok(session) => {
+                let new = session
+                    .is_new()
+                    .then(|| (session.clone(), request.scheme() == "https"));
+                request.session = Some(session);
+                new
+            }
+            Err(e) => {
+                Logger::warning(&self.logger, &format!("No session, no random id: {}", e));
+                None
+            }
+        }
+    }
+
+    /// Runs the middlewares registered for the request, returns it and the
+    /// stream to answer it on unless one of them answered it already
+    fn run_middlewares(&self, request: Request, stream: Stream) -> Option<(Request, Stream)> {
+        let layers: Vec<&Layer> = self
+            .middlewares
+            .iter()
+            .filter(|layer| layer.applies_to(&request.path))
+            .collect();
+        if layers.is_empty() {
+            return Some((request, stream));
+        }
+        let response = self.response(stream, &request);
+        let passed = Cell::new(None);
+        let path = request.path.clone();
+        if let Err(panic) = panics::catch(|| run_layers(&layers, request, response, &passed)) {
+            Logger::error(
+                &self.logger,
+                &format!("Middleware panicked serving {}: {}", path, panic),
+            );
+            // Whatever a later middleware passed on was not meant to be sent
+            if let Some((_, mut response)) = passed.take() {
+                response.status = Some(500);
+            }
+            return None;
+        }
+        let (mut request, response) = passed.into_inner()?;
+        let (stream, headers) = response.pass_on()?;
+        request.middleware_headers = headers;
+        Some((request, stream))
+    }
+
+    /// Returns the request with the user it authenticated as if its path is
+    /// protected, or answers `401` and returns `None` if it did not
+    fn authenticate(&self, mut request: Request, stream: Stream) -> Option<(Request, Stream)> {
+        let protection = match self
+            .protections
+            .iter()
+            .filter(|protection| protection.covers(&request.path))
+            .max_by_key(|protection| protection.specificity())
+        {
+            Some(protection) => protection,
+            None => return Some((request, stream)),
+        };
+        match protection.authenticate(request.get_header("Authorization")) {
+            Some(user) => {
+                request.auth_user = Some(user);
+                Some((request, stream))
+            }
+            None => {
+                Logger::info(&self.logger, "Status 401: Unauthorized");
+                let mut response = self.response(stream, &request);
+                response.status = Some(401);
+                response
+                    .headers
+                    .set("WWW-Authenticate", &protection.challenge());
+                let _ = response
+                    .write("<html><h1>401 unauthorized</h1><hr> powered by corrodedweb</html>");
+                None
+            }
+        }
+    }
+
+    /// Serves an accepted connection, performing the TLS handshake first if
+    /// TLS is configured
+    fn serve_connection(
+        self: &Arc<Self>,
+        tcp: TcpStream,
+        accepted: Instant,
+        routes: &Arc<Router<Endpoint>>,
+    ) {
+        let write_timeout = (!self.write_timeout.is_zero()).then_some(self.write_timeout);
+        if let Err(e) = tcp.set_write_timeout(write_timeout) {
+            Logger::warning(&self.logger, &format!("Error: {}", e));
+            return;
+        }
+        let peer_addr = match self.read_peer_addr(&tcp) {
+            Some(peer_addr) => peer_addr,
+            None => return,
+        };
+        let connection_id = next_connection_id();
+        #[cfg(any(feature = "tls", feature = "native-tls"))]
+        {
+            if let Some(tls) = &self.tls {
+                // Requests after the first are awaited on the socket itself
+                let handle = match tcp.try_clone() {
+                    Ok(handle) => handle,
+                    Err(e) => {
+                        Logger::warning(&self.logger, &format!("Error: {}", e));
+                        return;
+                    }
+                };
+                match tls.accept(tcp) {
+                    Ok(Accepted::Http1(stream)) => {
+                        self.serve_kept_open(
+                            handle,
+                            Some(stream),
+                            peer_addr,
+                            connection_id,
+                            accepted,
+                            routes,
+                        );
+                    }
+                    #[cfg(feature = "http2")]
+                    Ok(Accepted::Http2(connection, tcp)) => {
+                        self.serve_http2(*connection, tcp, peer_addr, connection_id, routes)
+                    }
+                    Err(e) => {
+                        Logger::info(&self.logger, &format!("TLS handshake failed: {}", e));
+                    }
+                }
+                return;
+            }
+        }
+        self.serve_kept_open(tcp, None, peer_addr, connection_id, accepted, routes);
+    }
+
+    /// Answers requests on a connection until either side wants to stop,
+    /// see `set_keep_alive`
+    ///
+    /// Requests go through the TLS session `tls` on `tcp` if there is one.
+    fn serve_kept_open(
+        &self,
+        tcp: TcpStream,
+        tls: Option<Stream>,
+        peer_addr: Option<SocketAddr>,
+        connection_id: u64,
+        accepted: Instant,
+        routes: &Router<Endpoint>,
+    ) {
+        let mut served = 0;
+        // Bytes of the next request that arrived together with the last one
+        let mut pending = Vec::new();
+        // The first request is timed from the accept, later ones from their
+        // first byte, however long the connection was idle before
+        let mut started = accepted;
+        let sni_hostname = tls
+            .as_ref()
+            .and_then(|tls| tls.sni_hostname().map(String::from));
+        let tls = tls.map(|tls| Arc::new(Mutex::new(tls)));
+        loop {
+            // The response may go out through anything, the connection stays
+            // with this loop
+            let handle = match tcp.try_clone() {
+                Ok(handle) => handle,
+                Err(e) => {
+                    Logger::warning(&self.logger, &format!("Error: {}", e));
+                    return;
+                }
+            };
+            served += 1;
+            let response_head = Arc::new(Mutex::new(Vec::new()));
+            let stream = Stream::KeptOpen(KeptOpen {
+                tcp: handle,
+                tls: tls.clone(),
+                sni_hostname: sni_hostname.clone(),
+                remaining_requests: self.keep_alive.max_requests.saturating_sub(served),
+                response_head: response_head.clone(),
+            });
+            let next =
+                self.handle_connection(stream, pending, peer_addr, connection_id, started, routes);
+            pending = match next {
+                Some(pending) => pending,
+                None => return,
+            };
+            // A handler that took its response elsewhere may still be
+            // writing it
+            let response_head = match Arc::try_unwrap(response_head) {
+                Ok(head) => head.into_inner().unwrap(),
+                Err(_) => return,
+            };
+            if !reader::is_delimited(&response_head) || self.shutdown.is_shutting_down() {
+                return;
+            }
+            let buffered = tls
+                .as_ref()
+                .is_some_and(|tls| tls.lock().unwrap().has_buffered_input());
+            if pending.is_empty() && !buffered && !self.await_request(&tcp) {
+                return;
+            }
+            started = Instant::now();
+        }
+    }
+
+    /// Waits for the next request on a connection kept open, returning
+    /// `false` once the client closed it, it was idle for longer than the
+    /// keep-alive allows or the server shuts down
+    fn await_request(&self, tcp: &TcpStream) -> bool {
+        let idle_until = Instant::now() + self.keep_alive.idle_timeout;
+        loop {
+            let remaining = idle_until.saturating_duration_since(Instant::now());
+            if remaining.is_zero() || self.shutdown.is_shutting_down() {
+                return false;
+            }
+            if tcp
+                .set_read_timeout(Some(remaining.min(IDLE_POLL)))
+                .is_err()
+            {
+                return false;
+            }
+            match tcp.peek(&mut [0]) {
+                Ok(read) => return read > 0,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) => {}
+                Err(_) => return false,
+            }
+        }
+    }
+
+    /// Returns the client's address, taken from the PROXY protocol header if
+    /// that is enabled
+    ///
+    /// Returns `None` if the connection must be dropped because the header is
+    /// missing or malformed.
+    fn read_peer_addr(&self, mut tcp: &TcpStream) -> Option<Option<SocketAddr>> {
+        if !self.proxy_protocol {
+            return Some(tcp.peer_addr().ok());
+        }
+        let _ = tcp.set_read_timeout(Some(PROXY_HEADER_TIMEOUT));
+        let header = proxyprotocol::read_header(&mut tcp);
+        let _ = tcp.set_read_timeout(None);
+        match header {
+            Ok(Some(peer_addr)) => Some(Some(peer_addr)),
+            Ok(None) => Some(tcp.peer_addr().ok()),
+            Err(e) => {
+                Logger::info(
+                    &self.logger,
+                    &format!("Dropping connection without valid PROXY header: {}", e),
+                );
+                None
+            }
+        }
+    }
+
+    /// Serves a connection that negotiated HTTP/2, running every request
+    /// through the same handling as HTTP/1.1
+    #[cfg(feature = "http2")]
+    fn serve_http2(
+        self: &Arc<Self>,
+        connection: rustls::ServerConnection,
+        tcp: TcpStream,
+        peer_addr: Option<SocketAddr>,
+        connection_id: u64,
+        routes: &Arc<Router<Endpoint>>,
+    ) {
+        let sni_hostname = connection.server_name().map(String::from);
+        let client_certificate = ClientCertificate::from_connection(&connection);
+        let server = self.clone();
+        let routes = routes.clone();
+        let exchange = move |request: Vec<u8>| {
+            let stream = MemoryStream {
+                input: io::Cursor::new(request),
+                output: Arc::default(),
+                encrypted: true,
+                remaining_requests: 0,
+                sni_hostname: sni_hostname.clone(),
+                client_certificate: client_certificate.clone(),
+                #[cfg(feature = "tokio")]
+                body_feed: None,
+            };
+            server.handle_in_memory(stream, peer_addr, connection_id, &routes)
+        };
+        if let Err(e) = http2::serve(connection, tcp, Arc::new(exchange)) {
+            Logger::info(&self.logger, &format!("HTTP/2 connection failed: {}", e));
+        }
+    }
+
+    /// Handles a request held in memory and returns the response that was
+    /// written
+    fn handle_in_memory(
+        &self,
+        stream: MemoryStream,
+        peer_addr: Option<SocketAddr>,
+        connection_id: u64,
+        routes: &Router<Endpoint>,
+    ) -> Vec<u8> {
+        let output = stream.output.clone();
+        self.handle_connection(
+            Stream::Memory(stream),
+            Vec::new(),
+            peer_addr,
+            connection_id,
+            Instant::now(),
+            routes,
+        );
+        let response = std::mem::take(&mut *output.lock().unwrap());
+        response
+    }
+
+    /// Handles a request of `TestRequest` with the routes registered so far
+    pub(crate) fn exchange_in_memory(&self, request: Vec<u8>) -> Vec<u8> {
+        let routes = self.registered_endpoints.lock().unwrap().clone();
+        let stream = MemoryStream {
+            input: io::Cursor::new(request),
+            output: Arc::default(),
+            encrypted: false,
+            remaining_requests: 0,
+            sni_hostname: None,
+            #[cfg(feature = "tls")]
+            client_certificate: None,
+            #[cfg(feature = "tokio")]
+            body_feed: None,
+        };
+        self.handle_in_memory(stream, None, next_connection_id(), &routes)
+    }
+
+    /// Handles a request on the connection `connection_id` and writes to its
+    /// stream
+    ///
+    /// The request starts with `pending`, which arrived together with the
+    /// previous one. Returns the bytes read beyond the request if the
+    /// connection can take the next one, which still depends on the response.
+    fn handle_connection(
+        &self,
+        mut stream: Stream,
+        pending: Vec<u8>,
+        peer_addr: Option<SocketAddr>,
+        connection_id: u64,
+        accepted: Instant,
+        routes: &Router<Endpoint>,
+    ) -> Option<Vec<u8>> {
+        // Only the bytes read for this request are ever looked at, whatever
+        // an earlier request left in the pooled buffer stays out of reach
+        let mut buffer = PooledBuffer::take();
+        if pending.len() > buffer.len() {
+            buffer.grow(pending.len() - buffer.len());
+        }
+        buffer[..pending.len()].copy_from_slice(&pending);
+        let read = reader::read_request(
+            &mut stream,
+            &mut buffer,
+            pending.len(),
+            self.request_timeouts,
+            self.header_limits,
+            accepted,
+        );
+        let (bytes_read, filled) = match read {
+            Ok(read) => read,
+            Err(ReadError::TimedOut) => {
+                self.metrics.count_request_timeout();
+                Logger::info(
+                    &self.logger,
+                    &format!("Request from {:?} timed out, answering 408", peer_addr),
+                );
+                Server::reject_request(stream, REQUEST_TIMEOUT);
+                return None;
+            }
+            Err(ReadError::HeadTooLarge(reason)) => {
+                Logger::info(
+                    &self.logger,
+                    &format!(
+                        "Request from {:?} has a {}, answering 431",
+                        peer_addr, reason
+                    ),
+                );
+                Server::reject_request(stream, HEADERS_TOO_LARGE);
+                return None;
+            }
+            Err(ReadError::Io(e)) => {
+                Logger::warning(&self.logger, format!("Error: {}", e).as_str());
+                return None;
+            }
+        };
+        let started = Instant::now();
+        let mut timer = PhaseTimer::start(self.phase_timings);
+        let head = RequestHead::parse(&buffer[..bytes_read])?;
+        if let Some(dump) = Dump::new(&self.logger, self.debug_dump) {
+            dump.request(peer_addr, head.raw, head.body);
+        }
+
+        if let Some(violation) = head.framing_violation() {
+            Logger::info(
+                &self.logger,
+                &format!("Status 400: request from {:?} has {}", peer_addr, violation),
+            );
+            Server::reject_request(stream, BAD_REQUEST);
+            return None;
+        }
+        if self.strict_parsing {
+            if let Some(violation) = head.strict_violation() {
+                Logger::info(
+                    &self.logger,
+                    &format!("Status 400: request from {:?} has {}", peer_addr, violation),
+                );
+                Server::reject_request(stream, BAD_REQUEST);
+                return None;
+            }
+        }
+        if head.method == "CONNECT" {
+            Logger::info(&self.logger, "Status 501: CONNECT is not supported");
+            Server::reject_request(stream, NOT_IMPLEMENTED);
+            return None;
+        }
+        let mut headers = Server::parse_headers(head.headers);
+        // With both, the body is chunked as `Framing::of` and `request_len`
+        // read it, and the connection is closed after the response in case
+        // something before this server went by the length, RFC 9112 section
+        // 6.3
+        let conflicting_framing = headers.contains("Content-Length")
+            && headers
+                .get("Transfer-Encoding")
+                .is_some_and(reader::is_chunked);
+        if conflicting_framing {
+            headers.remove("Content-Length");
+        }
+        if let Some(authority) = head.authority {
+            // The target names the host, a Host header naming another one
+            // is either a confused client or an attempt to slip past
+            // host-based checks
+            if headers
+                .get("Host")
+                .is_some_and(|host| !host.eq_ignore_ascii_case(authority))
+            {
+                Logger::info(
+                    &self.logger,
+                    "Status 400: Host header does not match the request target",
+                );
+                Server::reject_request(stream, BAD_REQUEST);
+                return None;
+            }
+            headers.set("Host", authority);
+        }
+        // RFC 7230 section 5.4, HTTP/1.1 clients must send exactly one
+        let hosts = head.header_count("Host");
+        if hosts > 1 || (hosts == 0 && head.authority.is_none() && head.version == "HTTP/1.1") {
+            Logger::info(
+                &self.logger,
+                &format!(
+                    "Status 400: request from {:?} has {} Host headers",
+                    peer_addr, hosts
+                ),
+            );
+            Server::reject_request(stream, BAD_REQUEST);
+            return None;
+        }
+        if let (Some(Framing::Length(len)), Some(max)) = (Framing::of(&headers), self.max_body_size)
+        {
+            if len > max {
+                Logger::info(
+                    &self.logger,
+                    &format!(
+                        "Status 413: request from {:?} has a body of {} bytes",
+                        peer_addr, len
+                    ),
+                );
+                Server::reject_request(stream, PAYLOAD_TOO_LARGE);
+                return None;
+            }
+        }
+
+        let target = head.target.split('#').next().unwrap_or("");
+        let rewritten = self.rewritten_target(target);
+        let routed = rewritten
+            .as_deref()
+            .map_or(target, |target| target.split('#').next().unwrap_or(""));
+        let (request_path, raw_query) = match routed.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (routed, None),
+        };
+        let decoded_path = percent::decode(request_path);
+        if decoded_path.contains(|c: char| c.is_control())
+            || decoded_path
+                .split(['/', '\\'])
+                .any(|segment| segment == "..")
+        {
+            Logger::info(
+                &self.logger,
+                &format!(
+                    "Status 400: request from {:?} has path {}",
+                    peer_addr, request_path
+                ),
+            );
+            Server::reject_request(stream, BAD_REQUEST);
+            return None;
+        }
+
+        let request_id = NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed);
+        Logger::debug(
+            &self.logger,
+            &format!(
+                "Connection {}, request {}: header: {}, request: {}",
+                connection_id, request_id, head.method, request_path
+            ),
+        );
+
+        let mut request = Request::new();
+        request.connection_id = connection_id;
+        request.request_id = request_id;
+        request.original_method = String::from(head.method);
+        request.path = decoded_path;
+        request.original_path = String::from(target.split('?').next().unwrap_or(""));
+        request.headers = headers;
+        request.version = String::from(head.version);
+        request.keep_alive = stream.remaining_requests() > 0
+            && !self.shutdown.is_shutting_down()
+            && !conflicting_framing
+            && client_keeps_alive(head.version, &request.headers);
+        if request.keep_alive {
+            request.remaining_requests = stream.remaining_requests();
+        }
+        // Hop-by-hop headers are between the client and this server only
+        request.headers.remove_connection_options();
+        request.cookies = cookie::parse(request.headers.get_all("Cookie"));
+        if request.is_urlencoded() {
+            request.post_pairs = Server::parse_parameters(str::from_utf8(head.body).ok().as_ref());
+            request.post_parameters = first_values(&request.post_pairs);
+        }
+        request.query_pairs = Server::parse_parameters(raw_query.as_ref());
+        request.query_parameters = first_values(&request.query_pairs);
+        request.raw_query = raw_query.map(String::from);
+        // The buffer goes back to the pool once the request is read, so the
+        // handler gets copies
+        request.raw_head = String::from(head.raw);
+        request.raw_body = head.body.to_vec();
+        request.sni_hostname = stream.sni_hostname().map(String::from);
+        request.peer_addr = peer_addr;
+        self.apply_forwarding(&mut request, stream.is_encrypted());
+        #[cfg(feature = "tls")]
+        {
+            request.client_certificate = stream.client_certificate();
+        }
+        request.method = self.effective_method(&request);
+        timer.parsed();
+
+        request.received = started;
+        request.route_names = Some(self.route_names.clone());
+        request.state = Some(self.state.clone());
+
+        let tallied =
+            self.access_log.is_some() || !self.after_hooks.is_empty() || self.logger.is_some();
+        let (tally, stream) = if tallied {
+            let (tally, stream) = Tally::wrap(stream);
+            (Some(tally), stream)
+        } else {
+            (None, stream)
+        };
+        let mut access = match (&self.access_log, &tally) {
+            (Some(_), Some(tally)) => {
+                let request_line = format!("{} {} {}", head.method, head.target, head.version);
+                Some(AccessLog::start(
+                    &request,
+                    request_line,
+                    started,
+                    tally.clone(),
+                ))
+            }
+            _ => None,
+        };
+        if let Some(retry_after) = self.rate_limited(&request) {
+            Logger::info(
+                &self.logger,
+                &format!(
+                    "Status 429: {:?} exceeded the rate limit",
+                    request.client_ip()
+                ),
+            );
+            request.keep_alive = false;
+            let mut response = self.response(stream, &request);
+            response.status = Some(429);
+            // Whole seconds, rounded up so the client does not come back early
+            let seconds = (retry_after.as_secs_f64().ceil() as u64).max(1);
+            response.headers.set("Retry-After", &seconds.to_string());
+            drop(response);
+            self.log_access(access);
+            return None;
+        }
+        if let Some(canonical) = &self.canonical_host {
+            if !request
+                .host
+                .as_ref()
+                .is_some_and(|host| host.eq_ignore_ascii_case(canonical))
+            {
+                let location = format!("{}://{}{}", request.scheme, canonical, target);
+                let mut response = self.response(stream, &request);
+                let _ = response.redirect_permanent(&location, false);
+                drop(response);
+                self.log_access(access);
+                return None;
+            }
+        }
+
+        // Bytes beyond a body that arrived completely start the next
+        // request, the rest of a longer one is only read by handlers
+        let body_complete = match Framing::of(&request.headers) {
+            None => true,
+            Some(Framing::Length(len)) => len == head.body.len() as u64,
+            Some(Framing::Chunked(_)) => false,
+        };
+        let mut next_request = body_complete.then(|| buffer[bytes_read..filled].to_vec());
+
+        for hook in &self.before_hooks {
+            hook(&mut request);
+        }
+        let observed = match &tally {
+            Some(tally) if !self.after_hooks.is_empty() => {
+                Some((request.duplicate(), tally.clone()))
+            }
+            _ => None,
+        };
+        let summary =
+            (timer.is_running() || self.on_request_complete.is_some() || self.logger.is_some())
+                .then(|| {
+                    (
+                        request.method.clone(),
+                        request.path.clone(),
+                        request.connection_id,
+                        request.request_id,
+                        started,
+                        tally,
+                    )
+                });
+        let (mut request, mut stream) = match self
+            .authenticate(request, stream)
+            .and_then(|(request, stream)| self.run_middlewares(request, stream))
+        {
+            Some(passed) => passed,
+            None => {
+                self.complete_request(summary, timer, access, observed);
+                return next_request;
+            }
+        };
+        // Upstreams get the path as it was sent, not decoded
+        let proxied = self.proxy_routes.iter().find_map(|route| {
+            let path = route.forwarded_path(request_path)?;
+            Some((route, String::from(path)))
+        });
+        timer.routed();
+        if let Some((route, path)) = proxied {
+            self.forward(stream, &request, &route.upstream, &path, &head);
+        } else if request.method == "GET" && self.route_listing.as_ref() == Some(&request.path) {
+            let listing = self.generate_route_listing();
+            let _ = self.response(stream, &request).write(&listing);
+        } else if let Some((root, answer)) = self.find_asset(&request) {
+            timer.routed();
+            match answer {
+                AssetAnswer::Serve(path) => {
+                    self.serve_static_files(
+                        &mut stream,
+                        &request,
+                        &root,
+                        &path,
+                        &self.static_options,
+                        Some(IMMUTABLE_CACHE_CONTROL),
+                    );
+                    timer.wrote();
+                }
+                AssetAnswer::Redirect(location) => {
+                    let location = with_query(location, request.raw_query());
+                    let mut response = self.response(stream, &request);
+                    let _ = response.redirect_temporary(&location, false);
+                }
+            }
+        } else if let Some((directory, path, options)) = self.static_first_directory(&request) {
+            timer.routed();
+            let cache_control = self.static_cache_control.as_deref();
+            self.serve_static_files(
+                &mut stream,
+                &request,
+                &directory,
+                path,
+                options,
+                cache_control,
+            );
+            timer.wrote();
+        } else {
+            match (
+                self.toggled_route(routes, &request.path),
+                self.trailing_slash_policy,
+            ) {
+                (Some(_), TrailingSlashPolicy::Redirect) => {
+                    let location =
+                        with_query(toggle_slash(&request.original_path), request.raw_query());
+                    let mut response = self.response(stream, &request);
+                    let _ = response.send_redirect(&location, slash_redirect_status(&request));
+                    drop(response);
+                    self.complete_request(summary, timer, access, observed);
+                    return next_request;
+                }
+                (Some(toggled), _) => request.path = toggled,
+                (None, _) => {}
+            }
+            let mut head_only = false;
+            let mut matched = routes.lookup(&request.method, &request.path);
+            if matched.is_none() && request.method == "HEAD" {
+                // Answer HEAD with the GET handler but without a body
+                matched = routes.lookup("GET", &request.path);
+                head_only = true;
+            }
+            let matched = matched.map(|matched| {
+                let endpoint = matched.route.value();
+                (
+                    endpoint.callback.clone(),
+                    String::from(matched.route.pattern()),
+                    matched.params,
+                    endpoint.timeout.or(self.handler_timeout),
+                )
+            });
+            let registered = match matched {
+                Some(_) => Vec::new(),
+                None => routes.allowed_methods(&request.path),
+            };
+            timer.routed();
+
+            if let Some((callback, pattern, params, timeout)) = matched {
+                // User registered for this route, call their callback
+                Logger::info(&self.logger, "Users custom route hit");
+
+                // The handler reads the rest of the body from the connection
+                // its response goes to
+                let stream = match Framing::of(&request.headers) {
+                    Some(framing) => {
+                        let shared = Arc::new(Mutex::new(stream));
+                        let buffered = buffer[bytes_read - head.body.len()..filled].to_vec();
+                        let incoming = IncomingBody::new(
+                            buffered,
+                            shared.clone(),
+                            framing,
+                            self.max_body_size,
+                        );
+                        request.incoming_body = Some(incoming);
+                        Stream::Shared(shared)
+                    }
+                    None => stream,
+                };
+                let incoming = request.incoming_body.clone();
+                let form_read = self.read_form(&mut request);
+                if form_read.is_err() {
+                    // The rest of the body is not read, no request can follow
+                    request.keep_alive = false;
+                }
+                let keep_alive = request.keep_alive;
+                let new_session = self.attach_session(&mut request);
+                let mut response = self.response(stream, &request);
+                response.new_session = new_session;
+                response.head_only = head_only;
+                response.write_clock = timer.write_clock();
+                request.path_parameters = params;
+
+                match (form_read, timeout) {
+                    (Err(status), _) => response.status = Some(status),
+                    (Ok(()), Some(timeout)) => {
+                        self.call_with_timeout(&callback, request, response, pattern, timeout)
+                    }
+                    (Ok(()), None) => self.call_route(&callback, request, response, &pattern),
+                }
+                if let Some(incoming) = incoming {
+                    next_request = incoming.finish(keep_alive);
+                }
+            } else if registered.is_empty() {
+                let favicon = self.favicon.as_ref().filter(|_| {
+                    request.path == "/favicon.ico"
+                        && (request.method == "GET" || request.method == "HEAD")
+                });
+                if let Some(favicon) = favicon {
+                    // An ignored favicon is not worth a line in the access log
+                    if let Favicon::Ignored = favicon {
+                        access = None;
+                    }
+                    self.serve_favicon(stream, &request, favicon);
+                } else if let Some((rule, location)) =
+                    find_redirect(&self.redirect_rules, &request.path)
+                {
+                    Logger::info(
+                        &self.logger,
+                        &format!(
+                            "Status {}: redirecting {} to {}",
+                            rule.status, request.path, location
+                        ),
+                    );
+                    let location = with_query(location, request.raw_query());
+                    let mut response = self.response(stream, &request);
+                    let _ = response.send_redirect(&location, rule.status);
+                } else if let Some((directory, path, options)) =
+                    self.static_directory(&request.path)
+                {
+                    // Read once, a root swapped meanwhile applies to the next request
+                    let cache_control = self.static_cache_control.as_deref();
+                    self.serve_static_files(
+                        &mut stream,
+                        &request,
+                        &directory,
+                        path,
+                        options,
+                        cache_control,
+                    );
+                    timer.wrote();
+                } else {
+                    request.no_match = Some(NoMatch::UnknownPath);
+                    let response = self.response(stream, &request);
+                    self.handle_no_match(request, response);
+                }
+            } else if request.method == "OPTIONS" {
+                let allowed = Server::allowed_methods(registered);
+                let mut response = self.response(stream, &request);
+                response.status = Some(204);
+                response.headers.set("Allow", &allowed.join(", "));
+                if let Some(cors) = &self.cors {
+                    for (name, value) in cors.preflight_headers(&request, &allowed) {
+                        response.headers.set(&name, &value);
+                    }
+                }
+            } else {
+                request.no_match = Some(NoMatch::MethodNotAllowed {
+                    allowed: Server::allowed_methods(registered),
+                });
+                let response = self.response(stream, &request);
+                self.handle_no_match(request, response);
+            }
+        }
+
+        self.complete_request(summary, timer, access, observed);
+        next_request
+    }
+
+    /// Logs a request with its status and phase timings and reports it to
+    /// the `on_request_complete` callback, the `after` hooks and the access
+    /// log
+    fn complete_request(
+        &self,
+        summary: Option<RequestStart>,
+        timer: PhaseTimer,
+        access: Option<Pending>,
+        observed: Option<(Request, Arc<Mutex<Tally>>)>,
+    ) {
+        self.log_access(access);
+        if let Some((request, tally)) = observed {
+            let meta = {
+                let tally = tally.lock().unwrap();
+                ResponseMeta {
+                    status: tally.status(),
+                    bytes_written: tally.body_len(),
+                    duration: request.received.elapsed(),
+                }
+            };
+            for hook in &self.after_hooks {
+                hook(&request, &meta);
+            }
+        }
+        if let Some((method, path, connection_id, request_id, started, tally)) = summary {
+            let timings = timer.finish();
+            let status = tally
+                .and_then(|tally| tally.lock().unwrap().status())
+                .map(|status| status.to_string());
+            let duration = format!("{:.3}", started.elapsed().as_secs_f64() * 1000.0);
+            let mut fields = vec![("method", method.as_str()), ("path", path.as_str())];
+            if let Some(status) = &status {
+                fields.push(("status", status));
+            }
+            fields.push(("duration_ms", &duration));
+            Logger::debug_kv(&self.logger, "Request complete", &fields);
+            if let Some(timings) = &timings {
+                Logger::debug(
+                    &self.logger,
+                    &format!(
+                        "Connection {}, request {}: phase timings: {}",
+                        connection_id, request_id, timings
+                    ),
+                );
+            }
+            if let Some(on_request_complete) = &self.on_request_complete {
+                on_request_complete(&RequestSummary {
+                    method,
+                    path,
+                    connection_id,
+                    request_id,
+                    timings,
+                });
+            }
+        }
+    }
+
+    /// Returns `path` with the trailing slash toggled if only that has
+    /// routes, unless paths have to match exactly
+    fn toggled_route(&self, routes: &Router<Endpoint>, path: &str) -> Option<String> {
+        if self.trailing_slash_policy == TrailingSlashPolicy::Strict
+            || path == "/"
+            || !routes.allowed_methods(path).is_empty()
+        {
+            return None;
+        }
+        let toggled = toggle_slash(path);
+        (!routes.allowed_methods(&toggled).is_empty()).then_some(toggled)
+    }
+
+    /// Writes the access log line of an answered request
+    fn log_access(&self, access: Option<Pending>) {
+        if let (Some(access_log), Some(pending)) = (&self.access_log, access) {
+            access_log.finish(pending);
+        }
+    }
+
+    /// Runs the handler on a thread of its own and answers `504` in its
+    /// place if it takes longer than `timeout`, see `set_handler_timeout`
+    fn call_with_timeout(
+        &self,
+        callback: &Callback,
+        request: Request,
+        mut answer: Response,
+        pattern: String,
+        timeout: Duration,
+    ) {
+        let prepared = Arc::new(Mutex::new(Vec::new()));
+        let mut response = Response::new(Stream::Memory(MemoryStream {
+            input: io::Cursor::new(Vec::new()),
+            output: prepared.clone(),
+            encrypted: false,
+            remaining_requests: 0,
+            sni_hostname: None,
+            #[cfg(feature = "tls")]
+            client_certificate: None,
+            #[cfg(feature = "tokio")]
+            body_feed: None,
+        }));
+        response.chunked_allowed = answer.chunked_allowed;
+        response.defaults = answer.defaults.clone();
+        response.vary = answer.vary.clone();
+        response.accept_used = answer.accept_used.clone();
+        response.default_charset = answer.default_charset.clone();
+        response.mime_types = answer.mime_types.clone();
+        response.templates = answer.templates.clone();
+        response.logger = answer.logger.clone();
+        response.new_session = answer.new_session.clone();
+        response.head_only = answer.head_only;
+        response.dump = answer.dump.clone();
+        response.server_timing = answer.server_timing;
+        #[cfg(feature = "gzip")]
+        {
+            response.compression = answer.compression;
+        }
+        let watch = Watch::default();
+        response.watch = Some(watch.clone());
+
+        let route = format!("{} {}", request.method, pattern);
+        let path = request.path.clone();
+        let (finished, done) = mpsc::channel();
+        let callback = callback.clone();
+        let logger = self.logger.clone();
+        let metrics = self.metrics.clone();
+        let handler_watch = watch.clone();
+        let handler_route = route.clone();
+        let failed = self.error_handler.as_ref().map(|_| request.duplicate());
+        thread::spawn(move || {
+            let panicked = call_handler(&logger, &callback, request, response, &pattern);
+            if !handler_watch.finish() {
+                metrics.count_zombie_returned();
+                Logger::info(
+                    &logger,
+                    &format!("Handler of {} returned after its timeout", handler_route),
+                );
+            }
+            let _ = finished.send(panicked);
+        });
+
+        match done.recv_timeout(timeout) {
+            Err(RecvTimeoutError::Timeout) if watch.abandon() => {
+                self.metrics.count_handler_timeout();
+                Logger::warning(
+                    &self.logger,
+                    &format!(
+                        "Status 504: handler of {} took longer than {:?} serving {}",
+                        route, timeout, path
+                    ),
+                );
+                answer.status = Some(504);
+                let _ = answer.write(GATEWAY_TIMEOUT_PAGE);
+            }
+            Ok(true) if failed.is_some() => {
+                if let Some(request) = failed {
+                    self.answer_error(request, answer);
+                }
+            }
+            _ => answer.relay(&std::mem::take(&mut *prepared.lock().unwrap())),
+        }
+    }
+
+    /// Calls the handler of a route, leaving the answer to the `on_error`
+    /// handler if it panics
+    fn call_route(
+        &self,
+        callback: &Callback,
+        request: Request,
+        mut response: Response,
+        pattern: &str,
+    ) {
+        if self.error_handler.is_none() {
+            call_handler(&self.logger, callback, request, response, pattern);
+            return;
+        }
+        let failed = request.duplicate();
+        let slot = Arc::new(Mutex::new(None));
+        response.panic_stream = Some(slot.clone());
+        call_handler(&self.logger, callback, request, response, pattern);
+        let stream = slot.lock().unwrap().take();
+        if let Some(stream) = stream {
+            let response = self.response(stream, &failed);
+            self.answer_error(failed, response);
+        }
+    }
+
+    /// Answers a request the server failed with `500` through the `on_error`
+    /// handler
+    fn answer_error(&self, request: Request, mut response: Response) {
+        Logger::info(&self.logger, "Status 500: Internal Server Error");
+        response.status = Some(500);
+        if let Some(handler) = &self.error_handler {
+            let path = request.path.clone();
+            if let Err(panic) = panics::catch(|| handler(request, response)) {
+                Logger::error(
+                    &self.logger,
+                    &format!("Error handler panicked serving {}: {}", path, panic),
+                );
+            }
+        }
+    }
+
+    /// Returns the document root and what answers a request for a
+    /// fingerprinted asset, see `enable_asset_fingerprinting`
+    ///
+    /// Stale hashes are left to the routes and files unless they redirect,
+    /// which answer them with `404` as any other missing file.
+    fn find_asset(&self, request: &Request) -> Option<(PathBuf, AssetAnswer)> {
+        if (request.method != "GET" && request.method != "HEAD")
+            || !self.assets.covers(&request.path)
+        {
+            return None;
+        }
+        let root = self.get_document_root()?;
+        if static_file(&root, &request.path, self.follow_symlinks).is_some() {
+            // A file really has this name
+            return None;
+        }
+        let (path, hash) = assets::split_fingerprint(&request.path)?;
+        let file = static_file(&root, &path, self.follow_symlinks)?;
+        let current = self.assets.hash(&file).ok()?;
+        if current == hash {
+            Some((root, AssetAnswer::Serve(path)))
+        } else if self.stale_asset_policy == StaleAssetPolicy::Redirect {
+            let location = assets::fingerprinted(&path, &current);
+            Some((root, AssetAnswer::Redirect(location)))
+        } else {
+            None
+        }
+    }
+
+    /// Returns where the static files answering `path` are, see
+    /// `static_directory`, if a file there answers the request before the
+    /// routes are looked up, see `StaticPrecedence::StaticFirst`
+    fn static_first_directory<'a>(
+        &'a self,
+        request: &'a Request,
+    ) -> Option<(PathBuf, &'a str, &'a StaticOptions)> {
+        if self.static_precedence != StaticPrecedence::StaticFirst
+            || (request.method != "GET" && request.method != "HEAD")
+        {
+            return None;
+        }
+        let (directory, path, options) = self.static_directory(&request.path)?;
+        static_file(&directory, path, self.follow_symlinks).map(|_| (directory, path, options))
+    }
+
+    /// Returns the directory of the static files answering `path`, the path
+    /// inside it and how it answers requests for directories
+    ///
+    /// The mount with the longest prefix covering `path` serves it, the
+    /// document root serves paths no mount covers.
+    fn static_directory<'a>(
+        &'a self,
+        path: &'a str,
+    ) -> Option<(PathBuf, &'a str, &'a StaticOptions)> {
+        let mount = self
+            .static_mounts
+            .iter()
+            .filter_map(|mount| Some((mount, mount.path_inside(path)?)))
+            .max_by_key(|(mount, _)| mount.prefix.len());
+        match mount {
+            Some((mount, inside)) => Some((mount.directory.clone(), inside, &mount.options)),
+            None => Some((self.get_document_root()?, path, &self.static_options)),
+        }
+    }
+
+    /// Warns about GET routes that have the path of a file in the document
+    /// root, since only one of them can answer
+    fn warn_about_shadowed_files(&self) {
+        let root = match self.get_document_root() {
+            Some(root) => root,
+            None => return,
+        };
+        let routes = self.routes();
+        let literal = routes
+            .iter()
+            .filter(|route| route.method == "GET" && !route.pattern.contains([':', '*']));
+        for route in literal {
+            if let Some(file) = static_file(&root, &route.pattern, self.follow_symlinks) {
+                let answering = match self.static_precedence {
+                    StaticPrecedence::RoutesFirst => "the route answers",
+                    StaticPrecedence::StaticFirst => "the file answers",
+                };
+                Logger::warning(
+                    &self.logger,
+                    &format!(
+                        "Route GET {} has the path of the file {}, {}",
+                        route.pattern,
+                        file.display(),
+                        answering
+                    ),
+                );
+            }
+        }
+    }
+
+    /// Answers a GET or HEAD request for `/favicon.ico`
+    fn serve_favicon(&self, stream: Stream, request: &Request, favicon: &Favicon) {
+        let mut response = self.response(stream, request);
+        response.head_only = request.method == "HEAD";
+        let path = match favicon {
+            Favicon::File(path) => path,
+            Favicon::Ignored => {
+                response.status = Some(204);
+                return;
+            }
+        };
+        let read = File::open(path).and_then(|mut file| {
+            let metadata = file.metadata()?;
+            let mut content = Vec::new();
+            file.read_to_end(&mut content)?;
+            Ok((metadata, content))
+        });
+        let (metadata, content) = match read {
+            Ok(read) => read,
+            Err(e) => {
+                Logger::warning(
+                    &self.logger,
+                    &format!("Favicon {} cannot be read: {}", path.display(), e),
+                );
+                response.status = Some(404);
+                response.body = NOT_FOUND_PAGE.as_bytes().to_vec();
+                return;
+            }
+        };
+        let etag = file_etag(content.len() as u64, metadata.modified().ok());
+        response.headers.set(
+            "Cache-Control",
+            &format!("public, max-age={}", FAVICON_MAX_AGE.as_secs()),
+        );
+        response.headers.set("ETag", &etag);
+        let revalidated = request
+            .get_header("If-None-Match")
+            .is_some_and(|tags| etag_matches(tags, &etag));
+        if revalidated {
+            response.status = Some(304);
+            return;
+        }
+        response.headers.set(
+            "Content-Type",
+            mime::from_path(path).unwrap_or("image/x-icon"),
+        );
+        response.body = content;
+    }
+
+    /// Answers a request that will not be read completely with `response`
+    /// and closes the connection
+    fn reject_request(mut stream: Stream, response: &str) {
+        if stream.write_all(response.as_bytes()).is_err() {
+            return;
+        }
+        let _ = stream.flush();
+        // Closing with unread bytes would reset the connection and could
+        // destroy the answer, so drain what the client still sends for a bit
+        if let Some(tcp) = stream.tcp() {
+            let _ = tcp.shutdown(Shutdown::Write);
+            let _ = tcp.set_read_timeout(Some(Duration::from_millis(100)));
+        }
+        let mut sink = [0; 1024];
+        while let Ok(1..) = stream.read(&mut sink) {}
+    }
+
+    /// Forwards the request to `upstream` as `path` and relays the response
+    fn forward(
+        &self,
+        mut stream: Stream,
+        request: &Request,
+        upstream: &Upstream,
+        path: &str,
+        head: &RequestHead,
+    ) {
+        // Whatever did not fit into the first read is still on the stream
+        let mut body = head.body.to_vec();
+        let content_length = request
+            .get_header("Content-Length")
+            .and_then(|len| len.parse::<u64>().ok())
+            .unwrap_or(0);
+        if content_length > body.len() as u64 {
+            let missing = content_length - body.len() as u64;
+            if let Err(e) = (&mut stream).take(missing).read_to_end(&mut body) {
+                Logger::warning(&self.logger, &format!("Error: {}", e));
+                return;
+            }
+        }
+
+        let client_ip = request.client_ip().map(|ip| ip.to_string());
+        let forwarded_for = match (request.get_header("X-Forwarded-For"), client_ip) {
+            (Some(earlier), Some(ip)) => Some(format!("{}, {}", earlier, ip)),
+            (None, Some(ip)) => Some(ip),
+            (earlier, None) => earlier.map(String::from),
+        };
+        let mut forwarded = vec![("X-Forwarded-Proto", String::from(request.scheme()))];
+        if let Some(forwarded_for) = forwarded_for {
+            forwarded.push(("X-Forwarded-For", forwarded_for));
+        }
+        if let Some(host) = request.host() {
+            forwarded.push(("X-Forwarded-Host", String::from(host)));
+        }
+        let upstream_request = upstream.request(
+            &request.original_method,
+            path,
+            request.raw_query(),
+            head.headers,
+            &forwarded,
+            &body,
+        );
+
+        let head_only = request.original_method == "HEAD";
+        let extra_headers = self.response_headers(request);
+        let (status, page) =
+            match upstream.forward(&upstream_request, head_only, &extra_headers, &mut stream) {
+                Ok(()) => return,
+                Err(ProxyError::Interrupted(e)) => {
+                    Logger::info(&self.logger, &format!("Proxied response broke off: {}", e));
+                    return;
+                }
+                Err(ProxyError::BadGateway(e)) => {
+                    Logger::warning(
+                        &self.logger,
+                        &format!("Status 502: Upstream for {} failed: {}", request.path, e),
+                    );
+                    (
+                        502,
+                        "<html><h1>502 bad gateway</h1><hr> powered by corrodedweb</html>",
+                    )
+                }
+                Err(ProxyError::Timeout) => {
+                    Logger::warning(
+                        &self.logger,
+                        &format!("Status 504: Upstream for {} timed out", request.path),
+                    );
+                    (504, GATEWAY_TIMEOUT_PAGE)
+                }
+            };
+        let mut response = self.response(stream, request);
+        response.status = Some(status);
+        let _ = response.write(page);
+    }
+
+    /// Prepares the 404 or 405 response for a request no route handled and
+    /// passes it to the not found handler, or sends a default page
+    fn handle_no_match(&self, request: Request, mut response: Response) {
+        let default_body = match &request.no_match {
+            Some(NoMatch::MethodNotAllowed { allowed }) => {
+                Logger::info(&self.logger, "Status 405: Method not allowed");
+                response.status = Some(405);
+                response.headers.set("Allow", &allowed.join(", "));
+                "<html><h1>405 method not allowed</h1><hr> powered by corrodedweb</html>"
+            }
+            _ => {
+                Logger::info(&self.logger, "Status 404: Not found");
+                response.status = Some(404);
+                "<html><h1>404 not found</h1><hr> powered by corrodedweb</html>"
+            }
+        };
+        match &self.not_found_handler {
+            Some(handler) => handler.deref()(request, response),
+            None => match self.read_error_page(response.status.unwrap_or(404)) {
+                Some((content_type, page)) => {
+                    if let Some(content_type) = content_type {
+                        response.headers.set("Content-Type", &content_type);
+                    }
+                    response.body = page;
+                }
+                None => {
+                    let _ = response.write(default_body);
+                }
+            },
+        }
+    }
+
+    /// Completes the registered methods of a path with the implicitly
+    /// answered ones and sorts them. HEAD is implied by GET and OPTIONS is
+    /// always answered.
+    fn allowed_methods(mut methods: Vec<String>) -> Vec<String> {
+        if methods.iter().any(|m| m == "GET") {
+            methods.push(String::from("HEAD"));
+        }
+        methods.push(String::from("OPTIONS"));
+        methods.sort_by_key(|m| {
+            (
+                KNOWN_METHODS
+                    .iter()
+                    .position(|o| o == m)
+                    .unwrap_or(KNOWN_METHODS.len()),
+                m.clone(),
+            )
+        });
+        methods.dedup();
+        methods
+    }
+
+    /// Returns how long the client of `request` has to wait if it exceeded
+    /// the rate limit
+    fn rate_limited(&self, request: &Request) -> Option<Duration> {
+        let limiter = self.rate_limiter.as_ref()?;
+        // IPv4 clients of a dual-stack listener count as IPv4
+        let ip = request.client_ip()?.to_canonical();
+        if self
+            .rate_limit_exempt
+            .iter()
+            .any(|network| network.contains(ip))
+        {
+            return None;
+        }
+        limiter.acquire(ip).err()
+    }
+
+    /// Decides the scheme and host the client used, taking the forwarding
+    /// headers of trusted proxies into account
+    fn apply_forwarding(&self, request: &mut Request, encrypted: bool) {
+        let trusted = request.peer_addr.is_some_and(|peer| {
+            self.trusted_proxies
+                .iter()
+                .any(|proxy| proxy.contains(peer.ip()))
+        });
+        // Proxies in a chain append, the first value is the client's
+        let forwarded = |name: &str| {
+            if !trusted {
+                return None;
+            }
+            request
+                .get_header(name)
+                .and_then(|value| value.split(',').next())
+                .map(str::trim)
+        };
+
+        let scheme = match forwarded("X-Forwarded-Proto").map(str::to_lowercase) {
+            Some(proto) if proto == "https" => "https",
+            Some(proto) if proto == "http" => "http",
+            _ if encrypted => "https",
+            _ => "http",
+        };
+        let host = forwarded("X-Forwarded-Host")
+            .or_else(|| request.get_header("Host"))
+            .and_then(|host| {
+                let hostname = hostname_of(host)?;
+                let port = match host[hostname.len()..].strip_prefix(':') {
+                    Some(port) => Some(port.parse::<u16>().ok()?),
+                    None => None,
+                };
+                let port = match forwarded("X-Forwarded-Port") {
+                    Some(port) => Some(port.parse::<u16>().ok()?),
+                    None => port,
+                };
+                Some(match port {
+                    Some(443) if scheme == "https" => String::from(hostname),
+                    Some(80) if scheme == "http" => String::from(hostname),
+                    Some(port) => format!("{}:{}", hostname, port),
+                    None => String::from(hostname),
+                })
+            });
+        request.scheme = scheme;
+        request.host = host;
+    }
+
+    /// Returns the method a request is dispatched with, taking a permitted
+    /// method override into account
+    fn effective_method(&self, request: &Request) -> String {
+        if self.method_override && request.original_method == "POST" {
+            let requested = request
+                .get_header("X-HTTP-Method-Override")
+                .or_else(|| request.post_parameters.get("_method").map(|m| m.as_str()));
+            if let Some(method) = requested {
+                let method = method.to_uppercase();
+                if ["PUT", "PATCH", "DELETE"].contains(&method.as_str()) {
+                    return method;
+                }
+                Logger::warning(
+                    &self.logger,
+                    &format!("Refused to override POST with {}", method),
+                );
+            }
+        }
+        request.original_method.clone()
+    }
+
+    /// Parses the header lines following the request line
+    ///
+    /// Names and values are copied once each, since the read buffer is
+    /// reused for the next request.
+    fn parse_headers(lines: &str) -> Headers {
+        let mut headers = Headers::with_capacity(lines.matches("\r\n").count() + 1);
+        for line in lines.split("\r\n") {
+            if let Some((name, value)) = line.split_once(':') {
+                headers.append(name.trim(), value.trim());
+            }
+        }
+        headers
+    }
+
+    /// Serves static files
+    fn serve_static_files(
+        &self,
+        stream: &mut Stream,
+        request: &Request,
+        path: &Path,
+        virtual_path: &str,
+        options: &StaticOptions,
+        cache_control: Option<&str>,
+    ) {
+        let v_path = virtual_path.trim_start_matches('/');
+        let mut headers = self.response_headers(request);
+        if let Some(cache_control) = cache_control {
+            headers.retain(|(name, _)| !name.eq_ignore_ascii_case("Cache-Control"));
+            headers.push((String::from("Cache-Control"), String::from(cache_control)));
+        }
+        let extra: String = headers
+            .iter()
+            .map(|(name, value)| format!("{}: {}\r\n", name, value))
+            .collect();
+        if !KNOWN_METHODS.contains(&request.method.as_str()) {
+            Logger::info(
+                &self.logger,
+                &format!("Status 501: {} is not supported", request.method),
+            );
+            let head = format!(
+                "HTTP/1.1 501 Not Implemented\r\n{}Content-Length: 0\r\n\r\n",
+                extra
+            );
+            self.write_to_stream(stream, head.as_bytes());
+            return;
+        }
+
+        let requested_path = format!(
+            "{}{}",
+            path.to_path_buf().into_os_string().into_string().unwrap(),
+            v_path,
+        );
+
+        let requested_path = PathBuf::from(requested_path);
+        // Requests reach this with `..` rejected already, checking again
+        // keeps the document root closed to callers that forget, and it is
+        // all that holds when links are followed
+        if !stays_inside(v_path) {
+            Logger::info(
+                &self.logger,
+                &format!("Status 404: {} leads out of the document root", v_path),
+            );
+            self.send_file_error(stream, request, 404, &extra);
+            return;
+        }
+        // A path that cannot be resolved, e.g. a missing file or a broken
+        // link, is left to opening it below to report. Loops of links end up
+        // with an error of their own.
+        let resolved = match resolve_inside(path, &requested_path, self.follow_symlinks) {
+            Ok(Some(resolved)) => Some(resolved),
+            Ok(None) => {
+                Logger::info(
+                    &self.logger,
+                    &format!("Status 403: {} links out of the document root", v_path),
+                );
+                self.send_file_error(stream, request, 403, &extra);
+                return;
+            }
+            Err(e) if Server::status_for_file_error(&e) == 500 => {
+                Logger::info(
+                    &self.logger,
+                    &format!("Status 404: {} cannot be resolved: {}", v_path, e),
+                );
+                self.send_file_error(stream, request, 404, &extra);
+                return;
+            }
+            Err(_) => None,
+        };
+        #[cfg(feature = "access-files")]
+        let access = {
+            let name = v_path.rsplit('/').next().unwrap_or("");
+            if self.access_files.is_some() && name == accessfile::ACCESS_FILE {
+                self.send_file_error(stream, request, 404, &extra);
+                return;
+            }
+            // The name may still turn out to be a directory as well
+            let directory = path.join(&v_path[..v_path.len() - name.len()]);
+            match self.access_rules(request, path, &directory) {
+                Ok(rules) => rules,
+                Err(status) => {
+                    self.send_file_error(stream, request, status, &extra);
+                    return;
+                }
+            }
+        };
+        if self
+            .stat_cache
+            .as_ref()
+            .is_some_and(|c| c.is_missing(&requested_path))
+        {
+            self.send_file_error(stream, request, 404, &extra);
+            return;
+        }
+
+        // Opening first and asking the open file for its metadata avoids
+        // racing against the file being replaced between separate checks
+        let mut file = match File::open(&requested_path) {
+            Ok(file) => file,
+            Err(e) => {
+                if e.kind() == ErrorKind::NotFound {
+                    if let Some(cache) = &self.stat_cache {
+                        cache.insert_missing(&requested_path);
+                    }
+                }
+                let status = Server::status_for_file_error(&e);
+                if status == 500 {
+                    Logger::warning(&self.logger, format!("Error: {}", e).as_str());
+                }
+                self.send_file_error(stream, request, status, &extra);
+                return;
+            }
+        };
+
+        // Remembered metadata could be of a file rewritten since, whose
+        // length would then frame the response wrongly
+        let stat = match file.metadata() {
+            Ok(metadata) => FileStat::from(&metadata),
+            Err(e) => {
+                Logger::warning(&self.logger, format!("Error: {}", e).as_str());
+                self.send_file_error(stream, request, Server::status_for_file_error(&e), &extra);
+                return;
+            }
+        };
+
+        if stat.is_dir && !v_path.is_empty() && !v_path.ends_with('/') {
+            match self.trailing_slash_policy {
+                TrailingSlashPolicy::Match => {}
+                TrailingSlashPolicy::Redirect => {
+                    let location =
+                        with_query(toggle_slash(&request.original_path), request.raw_query());
+                    let status = slash_redirect_status(request);
+                    self.write_to_stream(
+                        stream,
+                        format!(
+                            "HTTP/1.1 {} {}\r\n{}Location: {}\r\nContent-Length: 0\r\n\r\n",
+                            status,
+                            reason_phrase(status),
+                            extra,
+                            location
+                        )
+                        .as_bytes(),
+                    );
+                    return;
+                }
+                TrailingSlashPolicy::Strict => {
+                    self.send_file_error(stream, request, 404, &extra);
+                    return;
+                }
+            }
+        }
+
+        #[cfg(feature = "access-files")]
+        let (extra, index_of) = {
+            let access = if stat.is_dir && !v_path.is_empty() && !v_path.ends_with('/') {
+                match self.access_rules(request, path, &requested_path) {
+                    Ok(rules) => rules,
+                    Err(status) => {
+                        self.send_file_error(stream, request, status, &extra);
+                        return;
+                    }
+                }
+            } else {
+                access
+            };
+            if stat.is_dir && access.index == Some(false) {
+                self.send_file_error(stream, request, 403, &extra);
+                return;
+            }
+            let added: String = access
+                .headers
+                .iter()
+                .map(|(name, value)| format!("{}: {}\r\n", name, value))
+                .collect();
+            (extra + &added, access.index.unwrap_or(options.index_of))
+        };
+        #[cfg(not(feature = "access-files"))]
+        let index_of = options.index_of;
+
+        if !stat.is_dir {
+            Logger::info(
+                &self.logger,
+                &format!("Requested file {} exists", requested_path.display()),
+            );
+            let content_type = self.static_content_type(&requested_path, &mut file);
+            let extra = extra + &self.disposition_headers(&requested_path);
+            let contents =
+                resolved.and_then(|resolved| self.cached_contents(&resolved, &mut file, &stat));
+            self.send_static_file(
+                stream,
+                request,
+                &mut OpenFile { file, contents },
+                &stat,
+                Some(&content_type),
+                &extra,
+            );
+        } else if let Some((index_path, mut index, index_stat)) =
+            self.find_index_file(&options.index_files, &requested_path)
+        {
+            Logger::info(
+                &self.logger,
+                &format!("Requested directory has {}", index_path.display()),
+            );
+            let content_type = self.static_content_type(&index_path, &mut index);
+            let extra = extra + &self.disposition_headers(&index_path);
+            let contents = match &self.file_cache {
+                Some(_) => index_path
+                    .canonicalize()
+                    .ok()
+                    .and_then(|resolved| self.cached_contents(&resolved, &mut index, &index_stat)),
+                None => None,
+            };
+            self.send_static_file(
+                stream,
+                request,
+                &mut OpenFile {
+                    file: index,
+                    contents,
+                },
+                &index_stat,
+                Some(&content_type),
+                &extra,
+            );
+        } else if index_of {
+            Logger::info(
+                &self.logger,
+                &format!("Requested path {} is directory", requested_path.display()),
+            );
+            let mut index_of =
+                match listing::render(&requested_path, &request.path, self.show_hidden) {
+                    Ok(page) => page.into_bytes(),
+                    Err(e) => {
+                        Logger::warning(&self.logger, &format!("Error: {}", e));
+                        self.send_file_error(
+                            stream,
+                            request,
+                            Server::status_for_file_error(&e),
+                            &extra,
+                        );
+                        return;
+                    }
+                };
+            let content_type = mime::with_charset("text/html", self.default_charset.as_deref());
+            #[cfg(feature = "gzip")]
+            let extra = extra + &self.compress_page(request, &content_type, &mut index_of);
+            let mut answer = format!(
+                "HTTP/1.1 200 OK\r\n{}Content-Type: {}\r\nContent-Length: {}\r\n\r\n",
+                extra,
+                content_type,
+                index_of.len()
+            )
+            .into_bytes();
+            if request.method != "HEAD" {
+                answer.append(&mut index_of);
+            }
+            self.write_to_stream(stream, &answer);
+        } else {
+            Logger::info(
+                &self.logger,
+                &format!(
+                    "Status 403: directory {} has no index file",
+                    requested_path.display()
+                ),
+            );
+            self.send_file_error(stream, request, 403, &extra);
+        }
+    }
+
+    /// Opens the first of the index files that `directory` has
+    fn find_index_file(
+        &self,
+        index_files: &[String],
+        directory: &Path,
+    ) -> Option<(PathBuf, File, FileStat)> {
+        index_files.iter().find_map(|name| {
+            let path = directory.join(name);
+            let file = File::open(&path).ok()?;
+            let metadata = file.metadata().ok()?;
+            if metadata.is_dir()
+                || !is_inside(directory, &path, self.follow_symlinks).unwrap_or(false)
+            {
+                return None;
+            }
+            Some((path, file, FileStat::from(&metadata)))
+        })
+    }
+
+    /// Returns the contents of the static file at the canonical `path` from
+    /// the static cache, `None` unless it is on and the file small enough
+    fn cached_contents(&self, path: &Path, file: &mut File, stat: &FileStat) -> Option<Arc<[u8]>> {
+        self.file_cache.as_ref()?.contents(path, file, stat)
+    }
+
+    /// Sends a static file that exists, or the ranges of it the request
+    /// asks for, only the head for HEAD requests
+    fn send_static_file(
+        &self,
+        stream: &mut Stream,
+        request: &Request,
+        file: &mut OpenFile,
+        stat: &FileStat,
+        content_type: Option<&str>,
+        extra: &str,
+    ) {
+        let etag = file_etag(stat.len, stat.modified);
+        let mut validators = format!("Accept-Ranges: bytes\r\nETag: {}\r\n", etag);
+        if let Some(modified) = stat.modified {
+            validators.push_str(&format!(
+                "Last-Modified: {}\r\n",
+                httpdate::format_http_date(modified)
+            ));
+        }
+        if (request.method == "GET" || request.method == "HEAD")
+            && Server::is_not_modified(request, &etag, stat.modified)
+        {
+            Logger::info(&self.logger, "Status 304: Not Modified");
+            let head = format!("HTTP/1.1 304 Not Modified\r\n{}{}\r\n", extra, validators);
+            self.write_to_stream(stream, head.as_bytes());
+            return;
+        }
+        let content_type_line = content_type
+            .map(|content_type| format!("Content-Type: {}\r\n", content_type))
+            .unwrap_or_default();
+        let ranges = match request.get_header("Range") {
+            Some(range)
+                if request.method == "GET"
+                    && Server::if_range_matches(request, &etag, stat.modified) =>
+            {
+                range::parse(range, stat.len)
+            }
+            _ => RangeRequest::Full,
+        };
+        let sent = match ranges {
+            RangeRequest::Full => {
+                let head = format!(
+                    "HTTP/1.1 200 OK\r\n{}{}{}Content-Length: {}\r\n\r\n",
+                    extra, validators, content_type_line, stat.len
+                );
+                if request.method == "HEAD" {
+                    self.write_to_stream(stream, head.as_bytes());
+                    return;
+                }
+                self.send_file_response(file, stream, head.as_bytes(), 0, stat.len)
+            }
+            RangeRequest::Unsatisfiable => {
+                Logger::info(&self.logger, "Status 416: Range Not Satisfiable");
+                let head = format!(
+                    "HTTP/1.1 416 Range Not Satisfiable\r\n{}Content-Range: bytes */{}\r\nContent-Length: 0\r\n\r\n",
+                    extra, stat.len
+                );
+                self.write_to_stream(stream, head.as_bytes());
+                return;
+            }
+            RangeRequest::Partial(ranges) if ranges.len() == 1 => {
+                let range = ranges[0];
+                let head = format!(
+                    "HTTP/1.1 206 Partial Content\r\n{}{}{}Content-Range: {}\r\nContent-Length: {}\r\n\r\n",
+                    extra,
+                    validators,
+                    content_type_line,
+                    range.content_range(stat.len),
+                    range.len()
+                );
+                self.send_file_response(file, stream, head.as_bytes(), range.start, range.len())
+            }
+            RangeRequest::Partial(ranges) => {
+                let extra = format!("{}{}", extra, validators);
+                self.send_byteranges(file, stream, &ranges, stat.len, content_type, &extra)
+            }
+        };
+        match sent {
+            Ok(bytes_sent) => {
+                Logger::info(
+                    &self.logger,
+                    format!("\t{} bytes were sent", bytes_sent).as_str(),
+                );
+            }
+            // Nothing went out yet, so the client learns the file could
+            // not be read instead of getting an empty 200
+            Err(SendError::Unsent(e)) => {
+                Logger::warning(&self.logger, format!("Error: {}", e).as_str());
+                self.send_file_error(stream, request, Server::status_for_file_error(&e), extra);
+            }
+            Err(SendError::Partial(e)) => self.log_write_error(&e),
+        }
+    }
+
+    /// Sends several ranges of a file as a `multipart/byteranges` response
+    fn send_byteranges(
+        &self,
+        file: &mut OpenFile,
+        stream: &mut Stream,
+        ranges: &[ByteRange],
+        complete_len: u64,
+        content_type: Option<&str>,
+        extra: &str,
+    ) -> Result<u64, SendError> {
+        let boundary = range::boundary();
+        let (part_heads, end) =
+            range::multipart_heads(ranges, complete_len, content_type, &boundary);
+        let body_len = part_heads
+            .iter()
+            .map(|head| head.len() as u64)
+            .chain(ranges.iter().map(ByteRange::len))
+            .sum::<u64>()
+            + end.len() as u64;
+        let mut head = format!(
+            "HTTP/1.1 206 Partial Content\r\n{}Content-Type: multipart/byteranges; boundary={}\r\nContent-Length: {}\r\n\r\n",
+            extra, boundary, body_len
+        );
+        let mut sent = 0;
+        for (range, part_head) in ranges.iter().zip(part_heads) {
+            // The first part goes out together with the head of the response
+            head.push_str(&part_head);
+            sent += match self.send_file_response(
+                file,
+                stream,
+                head.as_bytes(),
+                range.start,
+                range.len(),
+            ) {
+                Ok(part_sent) => part_sent,
+                Err(SendError::Unsent(e)) if sent > 0 => return Err(SendError::Partial(e)),
+                Err(e) => return Err(e),
+            };
+            head.clear();
+        }
+        stream
+            .write_all(end.as_bytes())
+            .and_then(|_| stream.flush())
+            .map_err(SendError::Partial)?;
+        Ok(sent + end.len() as u64)
+    }
+
+    /// Returns whether the `If-Range` header, if there is one, names the
+    /// current version of a file, so its `Range` header applies
+    ///
+    /// Entity tags are compared strongly, so weak ones never match, and
+    /// dates have to equal the modification time.
+    fn if_range_matches(request: &Request, etag: &str, modified: Option<SystemTime>) -> bool {
+        let validator = match request.get_header("If-Range") {
+            Some(validator) => validator.trim(),
+            None => return true,
+        };
+        if validator.starts_with('"') || validator.starts_with("W/") {
+            return validator == etag;
+        }
+        match (
+            httpdate::parse_http_date(validator),
+            whole_seconds(modified),
+        ) {
+            (Some(date), Some(modified)) => date == modified,
+            _ => false,
+        }
+    }
+
+    /// Returns whether the copy the client has cached is current, RFC 9110
+    /// section 13.2.2: `If-None-Match` decides if the request has one,
+    /// `If-Modified-Since` otherwise
+    fn is_not_modified(request: &Request, etag: &str, modified: Option<SystemTime>) -> bool {
+        if let Some(tags) = request.get_header("If-None-Match") {
+            return etag_matches(tags, etag);
+        }
+        let since = request
+            .get_header("If-Modified-Since")
+            .and_then(|since| httpdate::parse_http_date(since.trim()));
+        match (since, whole_seconds(modified)) {
+            (Some(since), Some(modified)) => modified <= since,
+            _ => false,
+        }
+    }
+
+    /// Returns the rules of the access files for `directory` below the
+    /// document root `root`, or the status to answer if they forbid the
+    /// request
+    #[cfg(feature = "access-files")]
+    fn access_rules(
+        &self,
+        request: &Request,
+        root: &Path,
+        directory: &Path,
+    ) -> Result<AccessRules, u32> {
+        let access_files = match &self.access_files {
+            Some(access_files) => access_files,
+            None => return Ok(AccessRules::default()),
+        };
+        let rules = match access_files.rules(root, directory, &self.logger) {
+            Ok(rules) => rules,
+            Err(_) => return Err(500),
+        };
+        if rules.deny == Some(true) {
+            Logger::info(
+                &self.logger,
+                &format!("Access to {} is denied", request.path),
+            );
+            return Err(403);
+        }
+        if rules.require_auth == Some(true)
+            && !self
+                .auth_provider
+                .as_ref()
+                .is_some_and(|approves| approves(request))
+        {
+            Logger::info(
+                &self.logger,
+                &format!("Access to {} requires authentication", request.path),
+            );
+            return Err(403);
+        }
+        Ok(rules)
+    }
+
+    /// Answers a request for a static file with the error page of `status`,
+    /// 403, 404 or 500, or through the `not_found` or `on_error` handler
+    fn send_file_error(&self, stream: &mut Stream, request: &Request, status: u32, extra: &str) {
+        let (status_line, built_in) = match status {
+            403 => ("403 FORBIDDEN", FORBIDDEN_PAGE),
+            404 => ("404 NOT FOUND", NOT_FOUND_PAGE),
+            _ => ("500 INTERNAL SERVER ERROR", INTERNAL_ERROR_PAGE),
+        };
+        let handler = match status {
+            404 => self.not_found_handler.as_ref(),
+            500 => self.error_handler.as_ref(),
+            _ => None,
+        };
+        if let Some(handler) = handler {
+            self.send_handler_error(stream, request, status, handler);
+            return;
+        }
+        Logger::info(
+            &self.logger,
+            &format!("Status {}: {}", status, reason_phrase(status)),
+        );
+        let custom = self.read_error_page(status);
+        let (content_type, page) = match &custom {
+            Some((Some(content_type), page)) => {
+                (format!("Content-Type: {}\r\n", content_type), &page[..])
+            }
+            Some((None, page)) => (String::new(), &page[..]),
+            None => (String::new(), built_in.as_bytes()),
+        };
+        let mut response = format!(
+            "HTTP/1.1 {}\r\n{}{}Content-Length: {}\r\n\r\n",
+            status_line,
+            extra,
+            content_type,
+            page.len()
+        )
+        .into_bytes();
+        if request.method != "HEAD" {
+            response.extend_from_slice(page);
+        }
+        self.write_to_stream(stream, &response);
+    }
+
+    /// Lets `handler` answer a request for a static file with `status`,
+    /// sending what it answered once it returns
+    fn send_handler_error(
+        &self,
+        stream: &mut Stream,
+        request: &Request,
+        status: u32,
+        handler: &Callback,
+    ) {
+        Logger::info(
+            &self.logger,
+            &format!("Status {}: {}", status, reason_phrase(status)),
+        );
+        let mut failed = request.duplicate();
+        if status == 404 {
+            failed.no_match = Some(NoMatch::UnknownPath);
+        }
+        let (mut response, captured) = Response::capture();
+        response.chunked_allowed = request.version == "HTTP/1.1";
+        response.set_defaults(self.response_headers(request));
+        response.accept_used = Some(request.accept_used.clone());
+        response.default_charset = self.default_charset.clone();
+        response.mime_types = self.mime_types.clone();
+        response.templates = self.templates.clone();
+        response.logger = self.logger.clone();
+        response.head_only = request.method == "HEAD";
+        response.status = Some(status);
+        #[cfg(feature = "gzip")]
+        {
+            response.compression = self.accepts_gzip(request);
+        }
+        let path = request.path.clone();
+        if let Err(panic) = panics::catch(|| handler(failed, response)) {
+            Logger::error(
                 &self.logger,
-                &format!("Open TCP Port {} for incomming connections", port),
+                &format!("Error handler panicked serving {}: {}", path, panic),
+            );
+        }
+        let answer = std::mem::take(&mut *captured.output.lock().unwrap());
+        self.write_to_stream(stream, &answer);
+    }
+
+    /// Reads the page set with `set_error_page` for `status` and returns its
+    /// Content-Type and content, `None` to use the built-in page
+    fn read_error_page(&self, status: u32) -> Option<(Option<String>, Vec<u8>)> {
+        let page = self.error_pages.get(&status)?;
+        let path = self.get_document_root()?.join(&page.path);
+        let read = || -> io::Result<(Option<String>, Vec<u8>)> {
+            if self
+                .stat_cache
+                .as_ref()
+                .is_some_and(|c| c.is_missing(&path))
+            {
+                return Err(ErrorKind::NotFound.into());
+            }
+            let mut file = File::open(&path)?;
+            let content_type = Some(self.static_content_type(&path, &mut file));
+            file.seek(io::SeekFrom::Start(0))?;
+            let mut content = Vec::new();
+            file.read_to_end(&mut content)?;
+            Ok((content_type, content))
+        };
+        match read() {
+            Ok(page) => Some(page),
+            Err(e) => {
+                if e.kind() == ErrorKind::NotFound {
+                    if let Some(cache) = &self.stat_cache {
+                        cache.insert_missing(&path);
+                    }
+                }
+                if !page.missing_logged.swap(true, Ordering::Relaxed) {
+                    Logger::warning(
+                        &self.logger,
+                        &format!(
+                            "Error page {} cannot be read, using the built-in page: {}",
+                            path.display(),
+                            e
+                        ),
+                    );
+                }
+                None
+            }
+        }
+    }
+
+    /// Writes a complete response to the client
+    fn write_to_stream(&self, stream: &mut Stream, bytes: &[u8]) {
+        if let Some(dump) = Dump::new(&self.logger, self.debug_dump) {
+            let end = bytes
+                .windows(4)
+                .position(|window| window == b"\r\n\r\n")
+                .map_or(bytes.len(), |end| end + 4);
+            dump.response(&String::from_utf8_lossy(&bytes[..end]), &bytes[end..]);
+        }
+        if let Err(e) = stream.write_all(bytes).and_then(|_| stream.flush()) {
+            self.log_write_error(&e);
+        }
+    }
+
+    /// Logs why a response could not be written. A client that went away
+    /// in the middle, e.g. by closing the browser tab during a download, is
+    /// normal and only logged at debug level.
+    fn log_write_error(&self, e: &io::Error) {
+        if transfer::is_disconnect(e) {
+            Logger::debug(&self.logger, &format!("Client aborted the response: {}", e));
+        } else {
+            Logger::warning(&self.logger, &format!("Error: {}", e));
+        }
+    }
+
+    /// Returns the `Content-Type` of a static file, with the default charset
+    /// if the file is text and starts with valid UTF-8
+    fn static_content_type(&self, path: &Path, file: &mut File) -> String {
+        let media_type = media_type_of(path, &self.mime_types);
+        let charset = match &self.default_charset {
+            Some(charset) if mime::is_text(media_type) => charset,
+            _ => return String::from(media_type),
+        };
+        let mut prefix = Vec::new();
+        // The file is sent from an explicit offset afterwards, so reading
+        // moves nothing
+        if file
+            .take(CHARSET_SNIFF_LEN)
+            .read_to_end(&mut prefix)
+            .is_err()
+            || (charset.eq_ignore_ascii_case("utf-8") && !mime::looks_like_utf8(&prefix))
+        {
+            return String::from(media_type);
+        }
+        mime::with_charset(media_type, Some(charset))
+    }
+
+    /// Maps the error of opening or reading a static file to the status
+    /// code to answer with
+    fn status_for_file_error(error: &std::io::Error) -> u32 {
+        match error.kind() {
+            ErrorKind::NotFound | ErrorKind::NotADirectory | ErrorKind::InvalidInput => 404,
+            ErrorKind::PermissionDenied => 403,
+            _ => 500,
+        }
+    }
+
+    /// Writes the head and `len` bytes of the file starting at `offset` to
+    /// the client
+    fn send_file_response(
+        &self,
+        file: &mut OpenFile,
+        stream: &mut Stream,
+        head: &[u8],
+        offset: u64,
+        len: u64,
+    ) -> Result<u64, SendError> {
+        if let Some(dump) = Dump::new(&self.logger, self.debug_dump) {
+            dump.response_without_body(&String::from_utf8_lossy(head), len);
+        }
+        if let Some(contents) = &file.contents {
+            let part = &contents[offset as usize..(offset + len) as usize];
+            transfer::write_head_and_body(stream, head, part)
+                .and_then(|_| stream.flush())
+                .map_err(SendError::Partial)?;
+            return Ok(len);
+        }
+        #[cfg(feature = "mmap")]
+        {
+            if self.mmap {
+                if let Some(sent) = transfer::send_mapped(&file.file, stream, head, offset, len)? {
+                    return Ok(sent);
+                }
+            }
+        }
+        transfer::send_file(&mut file.file, stream, head, offset, len)
+    }
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Server {
+            port: None,
+            document_root: Arc::default(),
+            logger: None,
+            access_log: None,
+            static_options: StaticOptions::default(),
+            show_hidden: true,
+            follow_symlinks: false,
+            static_mounts: Vec::new(),
+            default_headers: Headers::new(),
+            default_charset: Some(String::from("utf-8")),
+            #[cfg(feature = "mmap")]
+            mmap: false,
+            stat_cache: None,
+            file_cache: None,
+            #[cfg(any(feature = "tls", feature = "native-tls"))]
+            tls: None,
+            #[cfg(any(feature = "tls", feature = "native-tls"))]
+            tls_reload_interval: None,
+            route_listing: None,
+            duplicate_route_policy: DuplicateRoutePolicy::default(),
+            static_precedence: StaticPrecedence::default(),
+            trailing_slash_policy: TrailingSlashPolicy::default(),
+            assets: Arc::default(),
+            stale_asset_policy: StaleAssetPolicy::default(),
+            static_cache_control: None,
+            max_connections: None,
+            rate_limiter: None,
+            rate_limit_exempt: Vec::new(),
+            max_queued_connections: None,
+            https_redirect: None,
+            hsts: None,
+            cors: None,
+            proxy_protocol: false,
+            trusted_proxies: Vec::new(),
+            templates: None,
+            reload_templates: false,
+            proxy_routes: Vec::new(),
+            redirect_rules: Vec::new(),
+            rewrites: Vec::new(),
+            error_pages: HashMap::new(),
+            favicon: None,
+            dispositions: HashMap::new(),
+            mime_types: Arc::default(),
+            connection_limit_policy: ConnectionLimitPolicy::default(),
+            method_override: false,
+            not_found_handler: None,
+            error_handler: None,
+            middlewares: Vec::new(),
+            protections: Vec::new(),
+            on_start: None,
+            on_request_complete: None,
+            before_hooks: Vec::new(),
+            after_hooks: Vec::new(),
+            server_timing: false,
+            shutdown: ShutdownHandle::default(),
+            shutdown_deadline: DEFAULT_SHUTDOWN_DEADLINE,
+            service_unavailable: service_unavailable(DEFAULT_RETRY_AFTER),
+            #[cfg(feature = "signals")]
+            shutdown_on_signals: false,
+            #[cfg(feature = "gzip")]
+            compression: false,
+            #[cfg(feature = "access-files")]
+            access_files: None,
+            #[cfg(feature = "access-files")]
+            auth_provider: None,
+            request_timeouts: RequestTimeouts::default(),
+            write_timeout: DEFAULT_WRITE_TIMEOUT,
+            header_limits: HeaderLimits::default(),
+            max_body_size: None,
+            keep_alive: KeepAlive::default(),
+            strict_parsing: false,
+            handler_timeout: None,
+            debug_dump: DumpSettings::default(),
+            phase_timings: false,
+            sessions: None,
+            workers: default_workers(),
+            canonical_host: None,
+            listen_addrs: Vec::new(),
+            metrics: Metrics::default(),
+            registered_endpoints: Arc::new(Mutex::new(Router::new())),
+            route_names: RouteNames::default(),
+            state: StateMap::default(),
+            started: Arc::new(AtomicBool::new(false)),
+            ready: Arc::new(Readiness::default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cookie::SameSite;
+    use crate::logger::LogFormat;
+    use std::fs;
+    use std::sync::atomic::AtomicUsize;
+    use std::thread;
+    use std::time::Instant;
+
+    /// Starts the server in the background on a free port and returns the
+    /// port once the server accepts connections
+    fn spawn_server(server: Server) -> u16 {
+        spawn_stoppable_server(server).1
+    }
+
+    /// Creates an empty directory to serve static files from
+    fn temp_document_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("corrodedweb_{}", name));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        root
+    }
+
+    /// Sends the raw request in a single write and returns everything the
+    /// server answered until it closed the connection
+    ///
+    /// The sending side is shut down afterwards, so a server that keeps
+    /// connections alive closes it as well.
+    fn raw_request(port: u16, request: &str) -> String {
+        raw_bytes_request(port, request.as_bytes())
+    }
+
+    fn raw_bytes_request(port: u16, request: &[u8]) -> String {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream.write_all(request).unwrap();
+        let _ = stream.shutdown(std::net::Shutdown::Write);
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    fn no_redirect_client() -> reqwest::Client {
+        reqwest::Client::builder()
+            .redirect(reqwest::RedirectPolicy::none())
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_get() {
+        let mut server = Server::new();
+        server.get("/", |request, mut response| {
+            let parameters = request.get_query_parameters();
+            assert_eq!(parameters.get("param1"), Some(&String::from("hello")));
+            assert_eq!(parameters.get("param2"), Some(&String::from("1234")));
+            let _ = response.set_status_code(200);
+            let _ = response.write("123456789");
+        });
+
+        let response = server
+            .test_request("GET", "/?param1=hello&param2=1234")
+            .send();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.header("Content-Length"), Some("9"));
+        assert_eq!(response.text(), "123456789");
+    }
+
+    #[test]
+    fn test_post() {
+        let mut server = Server::new();
+        server.post("/post/", |request, mut response| {
+            assert_eq!(request.get_header("X-Test"), Some("yes"));
+            assert_eq!(request.form("a"), Some("1"));
+            let _ = response.set_status_code(200);
+            let _ = response.write("123456789");
+        });
+
+        let response = server
+            .test_request("POST", "/post/")
+            .header("X-Test", "yes")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body("a=1")
+            .send();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.text(), "123456789");
+        assert_eq!(server.test_request("GET", "/post/").send().status(), 405);
+    }
+
+    #[test]
+    fn test_body_content_types() {
+        let mut server = Server::new();
+        server.post("/", |request, mut response| {
+            let _ = response.write(&format!(
+                "{:?} {:?}",
+                request.get_post_parameters(),
+                request.body_string()
+            ));
+        });
+        let post = |content_type: &str, body: &str| {
+            server
+                .test_request("POST", "/")
+                .header("Content-Type", content_type)
+                .body(body)
+                .send()
+                .text()
+        };
+        assert_eq!(
+            post("application/x-www-form-urlencoded", "a=1"),
+            r#"{"a": "1"} Ok("a=1")"#
+        );
+        assert_eq!(
+            post("application/json", r#"{"a=1": 2}"#),
+            r#"{} Ok("{\"a=1\": 2}")"#
+        );
+        assert_eq!(post("text/plain", "a=1"), r#"{} Ok("a=1")"#);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_json() {
+        #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+        struct User {
+            name: String,
+            tags: Vec<String>,
+            admin: bool,
+        }
+        let mut server = Server::new();
+        server.post("/users/", |request, mut response| {
+            match request.json::<User>() {
+                Ok(mut user) => {
+                    user.admin = true;
+                    let _ = response.send_json(&user);
+                }
+                Err(e) => {
+                    let _ = response.set_status_code(400);
+                    let _ = response.write(&e.to_string());
+                }
+            }
+        });
+        let port = spawn_server(server);
+
+        // Far more than arrives together with the head
+        let user = User {
+            name: String::from("Max"),
+            tags: vec!["x".repeat(90); 1000],
+            admin: false,
+        };
+        let body = serde_json::to_string(&user).unwrap();
+        let mut resp = reqwest::Client::new()
+            .post(&format!("http://localhost:{}/users/", port))
+            .header("Content-Type", "application/json")
+            .body(body)
+            .send()
+            .unwrap();
+        assert_eq!(resp.status().as_u16(), 200);
+        assert_eq!(
+            resp.headers()["content-type"],
+            "application/json; charset=utf-8"
+        );
+        let text = resp.text().unwrap();
+        assert_eq!(
+            resp.headers()["content-length"],
+            text.len().to_string().as_str()
+        );
+        let returned: User = serde_json::from_str(&text).unwrap();
+        assert_eq!(
+            returned,
+            User {
+                admin: true,
+                ..user
+            }
+        );
+
+        let mut resp = reqwest::Client::new()
+            .post(&format!("http://localhost:{}/users/", port))
+            .body("{\"name\": 1}")
+            .send()
+            .unwrap();
+        assert_eq!(resp.status().as_u16(), 400);
+        assert!(resp.text().unwrap().starts_with("invalid type"));
+    }
+
+    #[test]
+    fn test_redirect_helpers() {
+        let mut server = Server::new();
+        server.get("/permanent/", |_request, mut response| {
+            let _ = response.redirect_permanent("/target/", false);
+        });
+        server.get("/permanent-preserve/", |_request, mut response| {
+            let _ = response.redirect_permanent("/target/", true);
+        });
+        server.get("/plain/", |_request, mut response| {
+            let _ = response.redirect("../elsewhere/?a=1&b=2");
+        });
+        server.get("/temporary/", |_request, mut response| {
+            let _ = response.redirect_temporary("http://example.com/", false);
+        });
+        server.get("/temporary-preserve/", |_request, mut response| {
+            let _ = response.redirect_temporary("/target/", true);
+        });
+        server.post("/form/", |_request, mut response| {
+            let _ = response.see_other("/done/");
+        });
+        server.get("/injection/", |_request, mut response| {
+            if response.see_other("/a\r\nSet-Cookie: pwned=1").is_err() {
+                let _ = response.set_status_code(400);
+            }
+        });
+        assert!(server.redirect("/old-path/", "/new-path/", 301));
+        assert!(!server.redirect("/bad/", "/new-path/", 200));
+        assert!(!server.redirect("/bad/", "/new\r\n", 302));
+        let port = spawn_server(server);
+
+        let client = no_redirect_client();
+        let expectations = [
+            ("/plain/", 302, "../elsewhere/?a=1&b=2"),
+            ("/permanent/", 301, "/target/"),
+            ("/permanent-preserve/", 308, "/target/"),
+            ("/temporary/", 302, "http://example.com/"),
+            ("/temporary-preserve/", 307, "/target/"),
+            ("/old-path/", 301, "/new-path/"),
+        ];
+        for (path, status, location) in expectations.iter() {
+            let resp = client
+                .get(&format!("http://localhost:{}{}", port, path))
+                .send()
+                .unwrap();
+            assert_eq!(resp.status().as_u16(), *status);
+            assert_eq!(resp.headers()["location"], *location);
+        }
+
+        let resp = client
+            .post(&format!("http://localhost:{}/form/", port))
+            .send()
+            .unwrap();
+        assert_eq!(resp.status().as_u16(), 303);
+        assert_eq!(resp.headers()["location"], "/done/");
+
+        let resp = client
+            .post(&format!("http://localhost:{}/old-path/", port))
+            .send()
+            .unwrap();
+        assert_eq!(resp.status().as_u16(), 301);
+
+        let resp = client
+            .get(&format!("http://localhost:{}/injection/", port))
+            .send()
+            .unwrap();
+        assert_eq!(resp.status().as_u16(), 400);
+        assert!(resp.headers().get("set-cookie").is_none());
+        assert!(resp.headers().get("location").is_none());
+
+        let resp = client
+            .get(&format!("http://localhost:{}/bad/", port))
+            .send()
+            .unwrap();
+        assert_eq!(resp.status().as_u16(), 404);
+    }
+
+    #[test]
+    fn test_redirect_rules() {
+        let mut server = Server::new();
+        server.get("/blog/moved/", |_request, mut response| {
+            let _ = response.write("route");
+        });
+        assert!(server.redirect_rule("/old-blog/", "/blog/", 301));
+        assert!(server.redirect_prefix("/blog/", "/posts/", 308));
+        assert!(server.redirect_prefix("/docs/v1/", "https://archive.example.com/docs/v1/", 302));
+        assert!(server.redirect_prefix("/docs", "/manual?from=docs", 302));
+
+        let redirect = |path: &str| {
+            let response = server.test_request("GET", path).send();
+            let location = response.header("Location").map(String::from);
+            (response.status(), location)
+        };
+        let location = |status, location: &str| (status, Some(String::from(location)));
+        assert_eq!(redirect("/old-blog/"), location(301, "/blog/"));
+        assert_eq!(
+            redirect("/old-blog/?page=2"),
+            location(301, "/blog/?page=2")
+        );
+        assert_eq!(
+            redirect("/blog/2020/hello/"),
+            location(308, "/posts/2020/hello/")
+        );
+        assert_eq!(
+            redirect("/docs/v1/intro.html?lang=de&x=1"),
+            location(
+                302,
+                "https://archive.example.com/docs/v1/intro.html?lang=de&x=1"
+            )
+        );
+        assert_eq!(
+            redirect("/docs/v2/?lang=de"),
+            location(302, "/manual?from=docs/v2/&lang=de")
+        );
+        assert_eq!(redirect("/docsets/"), (404, None));
+        // Routes win over rules
+        assert_eq!(redirect("/blog/moved/"), (200, None));
+        let response = server.test_request("POST", "/old-blog/").send();
+        assert_eq!(response.status(), 301);
+    }
+
+    #[test]
+    fn test_rewrite() {
+        let mut server = Server::new();
+        server.set_document_root("./demo/www/");
+        server.get("/pages/:id/", |request, mut response| {
+            let _ = response.write(&format!(
+                "{} {} {} {:?}",
+                request.path_param_str("id").unwrap_or(""),
+                request.path(),
+                request.original_path(),
+                request.query("id"),
+            ));
+        });
+        server.rewrite(|target| {
+            let (path, query) = target.split_once('?')?;
+            let id = query.strip_prefix("id=")?;
+            if path == "/page.php" {
+                Some(format!("/pages/{}/", id))
+            } else {
+                None
+            }
+        });
+        server.rewrite(|target| match target {
+            "/welcome" => Some(String::from("/hello.html")),
+            "/relative" => Some(String::from("hello.html")),
+            _ => None,
+        });
+
+        let response = server.test_request("GET", "/page.php?id=5").send();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.text(), "5 /pages/5/ /page.php None");
+        assert!(response.header("Location").is_none());
+        let response = server.test_request("GET", "/pages/6/?id=6").send();
+        assert_eq!(response.text(), "6 /pages/6/ /pages/6/ Some(\"6\")");
+        // Static files are resolved from the rewritten path
+        let response = server.test_request("GET", "/welcome").send();
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response.text(),
+            std::fs::read_to_string("./demo/www/hello.html").unwrap()
+        );
+        assert_eq!(server.test_request("GET", "/relative").send().status(), 404);
+        assert_eq!(server.test_request("GET", "/page.php").send().status(), 404);
+    }
+
+    #[test]
+    fn test_redirect_rule_validation() {
+        let mut server = Server::new();
+        assert!(!server.redirect_rule("/a/", "/a/", 301));
+        assert!(!server.redirect_rule("/a/", "/b/", 200));
+        assert!(!server.redirect_rule("a/", "/b/", 301));
+        assert!(!server.redirect_rule("/a/", "/b/\r\nX: 1", 301));
+        assert!(!server.redirect_prefix("/a/", "/a/b/", 301));
+        assert!(server.redirect_rule("/a/", "/b/", 301));
+        assert!(!server.redirect_rule("/b/", "/a/?again=1", 301));
+        assert!(!server.redirect_prefix("/b/", "/a/", 301));
+        // External targets end the chain
+        assert!(server.redirect_rule("/b/", "http://example.com/a/", 301));
+        // Replacing a rule is checked too, and keeps the old one on failure
+        assert!(!server.redirect_rule("/b/", "/a/", 301));
+        assert!(server.redirect_prefix("/c/", "/a/", 302));
+        assert!(server.redirect_prefix("/c/", "/d/", 302));
+
+        let location = |path: &str| {
+            let response = server.test_request("GET", path).send();
+            response.header("Location").map(String::from)
+        };
+        assert_eq!(location("/b/").as_deref(), Some("http://example.com/a/"));
+        assert_eq!(location("/c/x").as_deref(), Some("/d/x"));
+    }
+
+    #[test]
+    fn test_routes() {
+        let mut server = Server::new();
+        server
+            .get("/", |_request, _response| {})
+            .describe("Homepage");
+        server.post("/login/", |_request, _response| {});
+        server.get("/login/", |_request, _response| {});
+        server.enable_route_listing("/__routes");
+
+        let routes = server.routes();
+        let expected = vec![
+            RouteInfo {
+                method: String::from("GET"),
+                pattern: String::from("/"),
+                description: Some(String::from("Homepage")),
+            },
+            RouteInfo {
+                method: String::from("GET"),
+                pattern: String::from("/login/"),
+                description: None,
+            },
+            RouteInfo {
+                method: String::from("POST"),
+                pattern: String::from("/login/"),
+                description: None,
+            },
+        ];
+        assert_eq!(routes, expected);
+
+        let port = spawn_server(server);
+        let mut resp = reqwest::get(&format!("http://localhost:{}/__routes", port)).unwrap();
+        let body = resp.text().unwrap();
+        assert!(body.contains("<td>POST</td><td>/login/</td>"));
+        assert!(body.contains("<td>Homepage</td>"));
+    }
+
+    #[test]
+    fn test_duplicate_route_replace() {
+        let mut server = Server::new();
+        server
+            .get("/a/", |_request, _response| {})
+            .describe("first");
+        server
+            .get("/a/", |_request, _response| {})
+            .describe("second");
+        let routes = server.routes();
+        assert_eq!(routes.len(), 1);
+        assert_eq!(routes[0].description, Some(String::from("second")));
+    }
+
+    #[test]
+    fn test_duplicate_route_ignore() {
+        let mut server = Server::new();
+        server.set_duplicate_route_policy(DuplicateRoutePolicy::Ignore);
+        server
+            .get("/a/", |_request, _response| {})
+            .describe("first");
+        server
+            .get("/a/", |_request, _response| {})
+            .describe("second");
+        server.post("/a/", |_request, _response| {});
+        let routes = server.routes();
+        assert_eq!(routes.len(), 2);
+        assert_eq!(routes[0].description, Some(String::from("first")));
+    }
+
+    #[test]
+    #[should_panic(expected = "Route GET /a/ was registered twice")]
+    fn test_duplicate_route_panic() {
+        let mut server = Server::new();
+        server.set_duplicate_route_policy(DuplicateRoutePolicy::Panic);
+        server.get("/a/", |_request, _response| {});
+        server.get("/a/", |_request, _response| {});
+    }
+
+    #[test]
+    fn test_duplicate_route_panic_leaves_routes_usable() {
+        let mut server = Server::new();
+        server.set_duplicate_route_policy(DuplicateRoutePolicy::Panic);
+        let first_line = line!() + 1;
+        server.get("/a/", |_request, _response| {});
+        let panic = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            server.get("/a/", |_request, _response| {});
+        }))
+        .unwrap_err();
+        let message = panic.downcast_ref::<String>().unwrap();
+        assert!(
+            message.contains(&format!("first as /a/ at {}:{}:", file!(), first_line)),
+            "{}",
+            message
+        );
+        assert!(
+            message.contains(&format!("again at {}:{}:", file!(), first_line + 2)),
+            "{}",
+            message
+        );
+
+        // The routes were not poisoned by the panic
+        server.post("/a/", |_request, _response| {});
+        assert_eq!(server.routes().len(), 2);
+    }
+
+    #[test]
+    fn test_case_insensitive_routes() {
+        let mut server = Server::new();
+        server.set_case_insensitive_routes(true);
+        server.get("/contact/:name/", |request, mut response| {
+            let _ = response.set_status_code(200);
+            let _ = response.write(&format!(
+                "{} {}",
+                request.path(),
+                request.get_path_parameters()["name"]
+            ));
+        });
+        let port = spawn_server(server);
+
+        let mut resp =
+            reqwest::get(&format!("http://localhost:{}/Contact/MaxMustermann/", port)).unwrap();
+        assert!(resp.status().is_success());
+        assert_eq!(
+            resp.text().unwrap(),
+            "/Contact/MaxMustermann/ MaxMustermann"
+        );
+    }
+
+    #[test]
+    fn test_method_override() {
+        let mut server = Server::new();
+        server.allow_method_override(true);
+        for method in &["GET", "POST", "DELETE", "PUT"] {
+            server.route(method, "/item/", |request, mut response| {
+                let _ = response.set_status_code(200);
+                let _ = response.write(&format!(
+                    "{} {}",
+                    request.method(),
+                    request.original_method()
+                ));
+            });
+        }
+        let port = spawn_server(server);
+
+        let client = reqwest::Client::new();
+        let mut resp = client
+            .post(&format!("http://localhost:{}/item/", port))
+            .header("X-HTTP-Method-Override", "DELETE")
+            .send()
+            .unwrap();
+        assert_eq!(resp.text().unwrap(), "DELETE POST");
+
+        let response = raw_request(
+            port,
+            "POST /item/ HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: 18\r\n\r\nname=x&_method=put",
+        );
+        assert!(response.ends_with("PUT POST"));
+
+        let mut resp = client
+            .get(&format!("http://localhost:{}/item/", port))
+            .header("X-HTTP-Method-Override", "DELETE")
+            .send()
+            .unwrap();
+        assert_eq!(resp.text().unwrap(), "GET GET");
+
+        let mut resp = client
+            .post(&format!("http://localhost:{}/item/", port))
+            .header("X-HTTP-Method-Override", "GET")
+            .send()
+            .unwrap();
+        assert_eq!(resp.text().unwrap(), "POST POST");
+    }
+
+    #[test]
+    fn test_method_routes() {
+        let mut server = Server::new();
+        let echo = |request: Request, mut response: Response| {
+            let _ = response.write(&format!(
+                "{} {}",
+                request.method(),
+                request.get_path_parameters()["id"]
+            ));
+        };
+        server.get("/users/:id/", echo);
+        server.put("/users/:id/", echo);
+        server.delete("/users/:id/", echo);
+        server.patch("/users/:id/", echo);
+        server.route("purge", "/users/:id/", echo);
+        let port = spawn_server(server);
+
+        let client = reqwest::Client::new();
+        let url = format!("http://localhost:{}/users/7/", port);
+        let methods = [
+            reqwest::Method::GET,
+            reqwest::Method::PUT,
+            reqwest::Method::DELETE,
+            reqwest::Method::PATCH,
+            reqwest::Method::from_bytes(b"PURGE").unwrap(),
+        ];
+        for method in &methods {
+            let mut resp = client.request(method.clone(), &url).send().unwrap();
+            assert_eq!(resp.text().unwrap(), format!("{} 7", method));
+        }
+        let resp = client.head(&url).send().unwrap();
+        assert_eq!(resp.status().as_u16(), 200);
+        assert_eq!(resp.headers()["content-length"], "6");
+        let resp = client
+            .request(reqwest::Method::OPTIONS, &url)
+            .send()
+            .unwrap();
+        assert_eq!(resp.status().as_u16(), 204);
+        assert_eq!(
+            resp.headers()["allow"],
+            "GET, HEAD, PUT, PATCH, DELETE, OPTIONS, PURGE"
+        );
+    }
+
+    #[test]
+    fn test_automatic_head_and_options() {
+        let mut server = Server::new();
+        server.get("/resource/", |_request, mut response| {
+            let _ = response.set_status_code(200);
+            let _ = response.write("123456789");
+        });
+        server.get("/custom/", |_request, _response| {});
+        server.route("OPTIONS", "/custom/", |_request, mut response| {
+            let _ = response.write("custom options");
+        });
+        let port = spawn_server(server);
+
+        let response = raw_request(port, "HEAD /resource/ HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Length: 9\r\n"));
+        assert!(response.ends_with("\r\n\r\n"));
+
+        let response = raw_request(
+            port,
+            "OPTIONS /resource/ HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 204 No Content\r\n"));
+        assert!(response.contains("Allow: GET, HEAD, OPTIONS\r\n"));
+
+        let response = raw_request(port, "OPTIONS /custom/ HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(response.ends_with("custom options"));
+    }
+
+    fn blog_module() -> Server {
+        let mut blog = Server::new();
+        blog.get("/", |request, mut response| {
+            let _ = response.write(&format!("index {}", request.mount_prefix()));
+        });
+        blog.get("/posts/:id/", |request, mut response| {
+            let _ = response.write(&format!(
+                "{} {} {}",
+                request.path(),
+                request.mount_prefix(),
+                request.get_path_parameters()["id"]
+            ));
+        });
+        blog
+    }
+
+    #[test]
+    fn test_mount() {
+        let mut server = Server::new();
+        server.set_document_root("./demo/www/");
+        let mut archive = Server::new();
+        archive.mount("/blog", blog_module());
+        server.mount("/archive/", archive);
+        server.mount("/blog", blog_module());
+        let port = spawn_server(server);
+
+        let mut resp = reqwest::get(&format!("http://localhost:{}/blog/", port)).unwrap();
+        assert_eq!(resp.text().unwrap(), "index /blog");
+        let mut resp = reqwest::get(&format!("http://localhost:{}/blog/posts/7/", port)).unwrap();
+        assert_eq!(resp.text().unwrap(), "/blog/posts/7/ /blog 7");
+        let mut resp =
+            reqwest::get(&format!("http://localhost:{}/archive/blog/posts/7/", port)).unwrap();
+        assert_eq!(
+            resp.text().unwrap(),
+            "/archive/blog/posts/7/ /archive/blog 7"
+        );
+
+        let resp = reqwest::get(&format!("http://localhost:{}/posts/7/", port)).unwrap();
+        assert_eq!(resp.status().as_u16(), 404);
+    }
+
+    fn admin_module() -> Server {
+        let mut admin = Server::new();
+        admin.use_middleware(|request, mut response, next| {
+            if request.get_header("X-Token") == Some("secret") {
+                next(request, response)
+            } else {
+                let _ = response.set_status_code(403);
+            }
+        });
+        admin.get("/users/", |_request, mut response| {
+            let _ = response.write("users");
+        });
+        admin
+    }
+
+    fn api_module() -> Server {
+        let mut api = Server::new();
+        api.use_middleware(|request, mut response, next| {
+            let _ = response.add_header("X-Api", "1");
+            next(request, response)
+        });
+        api.get("/status/", |request, mut response| {
+            let _ = response.write(&format!("ok {}", request.mount_prefix()));
+        });
+        api
+    }
+
+    #[test]
+    fn test_mount_middleware() {
+        let mut server = Server::new();
+        server.get("/", |_request, mut response| {
+            let _ = response.write("home");
+        });
+        server.mount("/admin", admin_module());
+        let api = api_module();
+        server.mount("/api", api.clone());
+        server.mount("/api/v2", api);
+
+        let response = server.test_request("GET", "/admin/users/").send();
+        assert_eq!(response.status(), 403);
+        let response = server
+            .test_request("GET", "/admin/users/")
+            .header("X-Token", "secret")
+            .send();
+        assert_eq!(response.text(), "users");
+
+        let response = server.test_request("GET", "/api/status/").send();
+        assert_eq!(response.text(), "ok /api");
+        assert_eq!(response.header("X-Api"), Some("1"));
+        let response = server.test_request("GET", "/api/v2/status/").send();
+        assert_eq!(response.text(), "ok /api/v2");
+        assert_eq!(response.header("X-Api"), Some("1"));
+
+        // Middlewares of modules stay below their prefix
+        let response = server.test_request("GET", "/").send();
+        assert_eq!(response.text(), "home");
+        assert_eq!(response.header("X-Api"), None);
+    }
+
+    #[test]
+    fn test_url_for() {
+        let mut blog = Server::new();
+        blog.get("/posts/:slug/", |_request, _response| {})
+            .name("post");
+        let mut server = Server::new();
+        server.get_named("user_detail", "/users/:id/", |_request, _response| {});
+        server
+            .route("PUT", "/users/:id/avatar", |_request, _response| {})
+            .name("avatar");
+        server.get("/", |request, mut response| {
+            let url = request.url_for("user_detail", &[("id", "42")]).unwrap();
+            let _ = response.redirect(&url);
+        });
+        server.mount("/blog", blog);
+
+        assert_eq!(
+            server.url_for("user_detail", &[("id", "42")]).unwrap(),
+            "/users/42/"
+        );
+        assert_eq!(
+            server
+                .url_for("avatar", &[("id", "a&b"), ("size", "big")])
+                .unwrap(),
+            "/users/a%26b/avatar"
+        );
+        assert_eq!(
+            server.url_for("post", &[("slug", "hello")]).unwrap(),
+            "/blog/posts/hello/"
+        );
+        assert_eq!(server.url_for("user_detail", &[]), None);
+        assert_eq!(server.url_for("unknown", &[("id", "42")]), None);
+
+        let response = server.test_request("GET", "/").send();
+        assert_eq!(response.header("Location"), Some("/users/42/"));
+        assert_eq!(Request::builder().build().url_for("user_detail", &[]), None);
+    }
+
+    #[test]
+    fn test_no_match() {
+        let mut server = Server::new();
+        server.get("/thing/", |_request, _response| {});
+        server.route("PUT", "/thing/", |_request, _response| {});
+        let port = spawn_server(server);
+
+        let client = reqwest::Client::new();
+        let mut resp = client
+            .post(&format!("http://localhost:{}/thing/", port))
+            .send()
+            .unwrap();
+        assert_eq!(resp.status().as_u16(), 405);
+        assert_eq!(resp.headers()["allow"], "GET, HEAD, PUT, OPTIONS");
+        assert!(resp.text().unwrap().contains("405 method not allowed"));
+
+        let mut resp = client
+            .get(&format!("http://localhost:{}/other/", port))
+            .send()
+            .unwrap();
+        assert_eq!(resp.status().as_u16(), 404);
+        assert!(resp.text().unwrap().contains("404 not found"));
+    }
+
+    #[test]
+    fn test_method_not_allowed_precedence() {
+        let root = temp_document_root("method_not_allowed");
+        fs::write(root.join("report.txt"), "static report").unwrap();
+        let mut server = Server::new();
+        server.set_document_root(&format!("{}/", root.display()));
+        server.get("/report.txt", |_request, mut response| {
+            let _ = response.write("generated report");
+        });
+        server.get("/users/:id/", |_request, _response| {});
+
+        // A registered path answers 405 rather than falling through to a
+        // file of the same name
+        let response = server.test_request("POST", "/report.txt").send();
+        assert_eq!(response.status(), 405);
+        assert_eq!(response.header("Allow"), Some("GET, HEAD, OPTIONS"));
+        assert!(!response.text().contains("static report"));
+
+        // Parameterized routes count, with or without the trailing slash
+        for path in ["/users/7/", "/users/7"] {
+            let response = server.test_request("DELETE", path).send();
+            assert_eq!(response.status(), 405, "{}", path);
+            assert_eq!(response.header("Allow"), Some("GET, HEAD, OPTIONS"));
+        }
+        let response = server.test_request("DELETE", "/groups/7/").send();
+        assert_eq!(response.status(), 404);
+        assert_eq!(response.header("Allow"), None);
+    }
+
+    #[test]
+    fn test_not_found_handler() {
+        let mut server = Server::new();
+        server.post("/thing/", |_request, _response| {});
+        server.not_found(|request, mut response| {
+            let _ = match request.no_match() {
+                Some(NoMatch::MethodNotAllowed { allowed }) => {
+                    response.write(&format!("allowed: {}", allowed.join(" ")))
+                }
+                Some(NoMatch::UnknownPath) => response.write("unknown path"),
+                None => response.write("unreachable"),
+            };
+        });
+        let port = spawn_server(server);
+
+        let mut resp = reqwest::get(&format!("http://localhost:{}/thing/", port)).unwrap();
+        assert_eq!(resp.status().as_u16(), 405);
+        assert_eq!(resp.headers()["allow"], "POST, OPTIONS");
+        assert_eq!(resp.text().unwrap(), "allowed: POST OPTIONS");
+
+        let mut resp = reqwest::get(&format!("http://localhost:{}/other/", port)).unwrap();
+        assert_eq!(resp.status().as_u16(), 404);
+        assert_eq!(resp.text().unwrap(), "unknown path");
+    }
+
+    #[test]
+    fn test_middleware() {
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let mut server = Server::new();
+        let seen = order.clone();
+        server.use_middleware(move |request, mut response, next| {
+            seen.lock()
+                .unwrap()
+                .push(format!("first {}", request.path()));
+            let _ = response.add_header("X-First", "1");
+            next(request, response)
+        });
+        let seen = order.clone();
+        server.use_middleware(move |request, mut response, next| {
+            seen.lock()
+                .unwrap()
+                .push(format!("second {}", request.path()));
+            if request.get_header("Authorization").is_none() {
+                let _ = response.set_status_code(401);
+                let _ = response.write("who are you?");
+                return;
+            }
+            next(request, response)
+        });
+        let seen = order.clone();
+        server.use_middleware_at("/admin/", move |request, response, next| {
+            seen.lock().unwrap().push(String::from("admin"));
+            next(request, response)
+        });
+        let seen = order.clone();
+        server.get("/admin/", move |_request, mut response| {
+            seen.lock().unwrap().push(String::from("handler"));
+            let _ = response.write("welcome");
+        });
+        server.get("/administrator/", |_request, mut response| {
+            let _ = response.write("not scoped");
+        });
+
+        let response = server.test_request("GET", "/admin/").send();
+        assert_eq!(response.status(), 401);
+        assert_eq!(response.text(), "who are you?");
+        assert_eq!(response.header("X-First"), Some("1"));
+        assert_eq!(
+            *order.lock().unwrap(),
+            vec!["first /admin/", "second /admin/"]
+        );
+
+        order.lock().unwrap().clear();
+        let response = server
+            .test_request("GET", "/admin/")
+            .header("Authorization", "Basic YTpi")
+            .send();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.text(), "welcome");
+        assert_eq!(response.header("X-First"), Some("1"));
+        assert_eq!(
+            *order.lock().unwrap(),
+            vec!["first /admin/", "second /admin/", "admin", "handler"]
+        );
+
+        order.lock().unwrap().clear();
+        let response = server
+            .test_request("GET", "/administrator/")
+            .header("Authorization", "Basic YTpi")
+            .send();
+        assert_eq!(response.text(), "not scoped");
+        assert_eq!(
+            *order.lock().unwrap(),
+            vec!["first /administrator/", "second /administrator/"]
+        );
+
+        // Requests no route answers go through the middlewares too
+        let response = server
+            .test_request("GET", "/missing/")
+            .header("Authorization", "Basic YTpi")
+            .send();
+        assert_eq!(response.status(), 404);
+        assert_eq!(response.header("X-First"), Some("1"));
+    }
+
+    #[test]
+    fn test_protect() {
+        let root = temp_document_root("protect");
+        fs::write(root.join("notes.txt"), "static secret").unwrap();
+        let mut server = Server::new();
+        server.set_document_root(&format!("{}/", root.display()));
+        assert!(server.protect("/admin/", &[("alice", "s3cret"), ("bob", "hunter2")]));
+        assert!(server.protect("/admin/root/", &[("root", "toor")]));
+        assert!(server.protect_with("/notes.txt", |user, password| {
+            user == "reader" && password == "books"
+        }));
+        assert!(!server.protect("admin/", &[("alice", "s3cret")]));
+        assert!(!server.protect("/a\"b/", &[("alice", "s3cret")]));
+        server.get("/admin/", |request, mut response| {
+            let _ = response.write(&format!("hello {}", request.auth_user().unwrap()));
+        });
+        server.get("/admin/root/", |request, mut response| {
+            let _ = response.write(request.auth_user().unwrap());
+        });
+        server.get("/public/", |request, mut response| {
+            let _ = response.write(&format!("{:?}", request.auth_user()));
+        });
+        let get = |path: &str, authorization: Option<&str>| {
+            let mut request = server.test_request("GET", path);
+            if let Some(authorization) = authorization {
+                request = request.header("Authorization", authorization);
+            }
+            request.send()
+        };
+        // alice:s3cret, alice:wrong, root:toor and reader:books
+        let alice = Some("Basic YWxpY2U6czNjcmV0");
+        let wrong = Some("Basic YWxpY2U6d3Jvbmc=");
+        let root_user = Some("Basic cm9vdDp0b29y");
+        let reader = Some("Basic cmVhZGVyOmJvb2tz");
+
+        let response = get("/admin/", None);
+        assert_eq!(response.status(), 401);
+        assert_eq!(
+            response.header("WWW-Authenticate"),
+            Some("Basic realm=\"/admin\", charset=\"UTF-8\"")
+        );
+        assert_eq!(get("/admin/", wrong).status(), 401);
+        assert_eq!(
+            get("/admin/", Some("Bearer YWxpY2U6czNjcmV0")).status(),
+            401
+        );
+        let response = get("/admin/", alice);
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.text(), "hello alice");
+
+        // The longest prefix decides
+        assert_eq!(get("/admin/root/", alice).status(), 401);
+        assert_eq!(get("/admin/root/", root_user).text(), "root");
+        assert_eq!(get("/admin/", root_user).status(), 401);
+
+        let response = get("/notes.txt", alice);
+        assert_eq!(response.status(), 401);
+        assert_eq!(
+            response.header("WWW-Authenticate"),
+            Some("Basic realm=\"/notes.txt\", charset=\"UTF-8\"")
+        );
+        let response = get("/notes.txt", reader);
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.text(), "static secret");
+
+        assert_eq!(get("/public/", None).text(), "None");
+    }
+
+    #[test]
+    fn test_serve_static() {
+        let root = temp_document_root("serve_static_root");
+        let assets = temp_document_root("serve_static_assets");
+        let downloads = temp_document_root("serve_static_downloads");
+        fs::write(root.join("index.html"), "home").unwrap();
+        fs::write(root.join("secret.txt"), "root only").unwrap();
+        fs::write(assets.join("app.css"), "body {}").unwrap();
+        fs::create_dir(assets.join("img")).unwrap();
+        fs::write(assets.join("img/logo.svg"), "<svg/>").unwrap();
+        fs::write(downloads.join("report.pdf"), "%PDF").unwrap();
+        fs::create_dir(downloads.join("old")).unwrap();
+        fs::write(downloads.join("old/index.htm"), "old index").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(root.join("secret.txt"), assets.join("link.txt")).unwrap();
+
+        let mut server = Server::new();
+        server.set_document_root(&format!("{}/", root.display()));
+        assert!(server.serve_static("/assets/", assets.to_str().unwrap()));
+        assert!(server.serve_static_with(
+            "/downloads",
+            downloads.to_str().unwrap(),
+            StaticOptions {
+                index_of: true,
+                index_files: Vec::new(),
+            },
+        ));
+        assert!(!server.serve_static("/", assets.to_str().unwrap()));
+        assert!(!server.serve_static("assets/", assets.to_str().unwrap()));
+        assert!(!server.serve_static("/missing/", root.join("missing").to_str().unwrap()));
+        assert!(!server.serve_static_with(
+            "/bad/",
+            assets.to_str().unwrap(),
+            StaticOptions {
+                index_of: false,
+                index_files: vec![String::from("../index.html")],
+            },
+        ));
+        server.get("/assets/generated.css", |_request, mut response| {
+            let _ = response.write("generated");
+        });
+        let get = |server: &Server, path: &str| {
+            let response = server.test_request("GET", path).send();
+            (response.status(), response.text())
+        };
+
+        assert_eq!(
+            get(&server, "/assets/app.css"),
+            (200, String::from("body {}"))
+        );
+        assert_eq!(
+            get(&server, "/assets/img/logo.svg"),
+            (200, String::from("<svg/>"))
+        );
+        assert_eq!(
+            get(&server, "/downloads/report.pdf"),
+            (200, String::from("%PDF"))
+        );
+        assert_eq!(get(&server, "/"), (200, String::from("home")));
+        assert_eq!(
+            get(&server, "/secret.txt"),
+            (200, String::from("root only"))
+        );
+        // Routes still answer first
+        assert_eq!(
+            get(&server, "/assets/generated.css"),
+            (200, String::from("generated"))
+        );
+        // The mount answers its paths even where the document root has none
+        assert_eq!(get(&server, "/assets/secret.txt").0, 404);
+        assert_eq!(get(&server, "/assetsx/app.css").0, 404);
+        #[cfg(unix)]
+        assert_eq!(get(&server, "/assets/link.txt").0, 403);
+
+        // Each mount answers directories its own way
+        assert_eq!(get(&server, "/assets/img/").0, 403);
+        let (status, listing) = get(&server, "/downloads/");
+        assert_eq!(status, 200);
+        assert!(listing.contains("report.pdf"));
+        let (status, listing) = get(&server, "/downloads/old/");
+        assert_eq!(status, 200);
+        assert!(listing.contains("index.htm"));
+
+        // The longest prefix wins
+        assert!(server.serve_static("/assets/img/", downloads.to_str().unwrap()));
+        assert_eq!(
+            get(&server, "/assets/img/report.pdf"),
+            (200, String::from("%PDF"))
+        );
+        assert_eq!(get(&server, "/assets/img/logo.svg").0, 404);
+        assert_eq!(
+            get(&server, "/assets/app.css"),
+            (200, String::from("body {}"))
+        );
+    }
+
+    #[test]
+    fn test_not_found_handler_for_static_files() {
+        let root = temp_document_root("not_found_handler");
+        fs::write(root.join("index.html"), "home").unwrap();
+        let mut server = Server::new();
+        server.set_document_root(&format!("{}/", root.display()));
+        server.not_found(|request, mut response| {
+            let _ = response.write(&format!(
+                "no {} here, {:?}",
+                request.path(),
+                request.no_match()
+            ));
+        });
+
+        let response = server.test_request("GET", "/missing.html").send();
+        assert_eq!(response.status(), 404);
+        assert_eq!(response.text(), "no /missing.html here, Some(UnknownPath)");
+        assert_eq!(
+            server.test_request("GET", "/index.html").send().text(),
+            "home"
+        );
+    }
+
+    #[test]
+    fn test_path_param() {
+        let mut request = Request::new();
+        request
+            .path_parameters
+            .insert(String::from("id"), String::from("42"));
+        request.path_parameters.insert(
+            String::from("uuid"),
+            String::from("9b2e3c1e-7d4f-4a8e-9f6a-1c2d3e4f5a6b"),
+        );
+
+        assert_eq!(request.path_param::<u32>("id"), Ok(42));
+        assert_eq!(
+            request.path_param::<String>("uuid"),
+            Ok(String::from("9b2e3c1e-7d4f-4a8e-9f6a-1c2d3e4f5a6b"))
+        );
+        assert_eq!(
+            request.path_param_str("uuid"),
+            Some("9b2e3c1e-7d4f-4a8e-9f6a-1c2d3e4f5a6b")
+        );
+        assert_eq!(
+            request.path_param::<u32>("uuid"),
+            Err(ParamError::Invalid {
+                name: String::from("uuid"),
+                value: String::from("9b2e3c1e-7d4f-4a8e-9f6a-1c2d3e4f5a6b"),
+            })
+        );
+        assert_eq!(
+            request.path_param::<u8>("missing"),
+            Err(ParamError::Missing {
+                name: String::from("missing"),
+            })
+        );
+        assert_eq!(request.path_param_str("missing"), None);
+    }
+
+    #[test]
+    fn test_raw_query() {
+        let mut server = Server::new();
+        server.get("/signed/", |request, mut response| {
+            let _ = response.write(&format!("{:?}", request.raw_query()));
+        });
+        let port = spawn_server(server);
+
+        let response = raw_request(
+            port,
+            "GET /signed/?b=2&a=1&a=3&sig=%2Fx#top HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        );
+        assert!(response.ends_with("Some(\"b=2&a=1&a=3&sig=%2Fx\")"));
+        let response = raw_request(port, "GET /signed/? HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(response.ends_with("Some(\"\")"));
+        let response = raw_request(port, "GET /signed/ HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(response.ends_with("None"));
+    }
+
+    #[test]
+    fn test_concurrent_handlers() {
+        let mut server = Server::new();
+        server.get("/slow/", |_request, mut response| {
+            thread::sleep(std::time::Duration::from_millis(300));
+            let _ = response.write("done");
+        });
+        let port = spawn_server(server);
+
+        // Creating a client takes a while, so that happens before timing
+        let http_clients: Vec<_> = (0..2).map(|_| reqwest::Client::new()).collect();
+        let start = std::time::Instant::now();
+        let clients: Vec<_> = http_clients
+            .into_iter()
+            .map(|client| {
+                thread::spawn(move || {
+                    let mut resp = client
+                        .get(&format!("http://localhost:{}/slow/", port))
+                        .send()
+                        .unwrap();
+                    assert_eq!(resp.text().unwrap(), "done");
+                })
+            })
+            .collect();
+        for client in clients {
+            client.join().unwrap();
+        }
+        assert!(start.elapsed() < std::time::Duration::from_millis(550));
+    }
+
+    #[test]
+    fn test_percent_decoding() {
+        let root = temp_document_root("percent_decoding");
+        fs::create_dir_all(root.join("my folder")).unwrap();
+        fs::write(root.join("my folder/file.txt"), "found").unwrap();
+        fs::write(root.join("secret.txt"), "secret").unwrap();
+
+        let mut server = Server::new();
+        server.set_document_root(&format!("{}/", root.display()));
+        server.get("/greet/:name/", |request, mut response| {
+            let _ = response.write(&format!(
+                "{}|{}|{}",
+                request.path_param_str("name").unwrap_or(""),
+                request.query("name").unwrap_or(""),
+                request.query("msg").unwrap_or("")
+            ));
+        });
+        server.post("/form/", |request, mut response| {
+            let _ = response.write(&format!(
+                "{}|{}",
+                request.form("caf\u{e9}").unwrap_or(""),
+                request.form("discount").unwrap_or("")
+            ));
+        });
+        let port = spawn_server(server);
+        let get = |target: &str| {
+            raw_request(
+                port,
+                &format!(
+                    "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+                    target
+                ),
+            )
+        };
+
+        let response = get("/greet/J%C3%BCrgen/?name=J%C3%BCrgen&msg=hello+world");
+        assert!(response.ends_with("\r\n\r\nJürgen|Jürgen|hello world"));
+        let response = get("/my%20folder/file.txt");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("found"));
+        let response = get("/greet/%ZZ/?msg=100%25+%ZZ");
+        assert!(response.ends_with("%ZZ||100% %ZZ"));
+
+        let body = "caf%C3%A9=cr%C3%A8me+br%C3%BBl%C3%A9e&discount=50%25";
+        let response = raw_request(
+            port,
+            &format!(
+                "POST /form/ HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+                 Content-Type: application/x-www-form-urlencoded\r\n\
+                 Content-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            ),
+        );
+        assert!(response.ends_with("crème brûlée|50%"));
+
+        // Decoding must not lead out of the document root or into headers
+        for target in [
+            "/my%20folder/%2e%2e/secret.txt",
+            "/a/../secret.txt",
+            "/%0d%0aX:1",
+        ] {
+            assert!(
+                get(target).starts_with("HTTP/1.1 400 Bad Request\r\n"),
+                "{}",
+                target
+            );
+        }
+    }
+
+    #[test]
+    fn test_static_revalidation() {
+        let root = temp_document_root("static_revalidation");
+        fs::write(root.join("photo.png"), b"\x89PNG\r\n\x1a\n").unwrap();
+        let mut server = Server::new();
+        server.set_document_root(&format!("{}/", root.display()));
+        assert!(server.set_cache_control("public, max-age=3600"));
+        assert!(!server.set_cache_control("max-age=1\r\nX-Injected: 1"));
+        let port = spawn_server(server);
+
+        let url = format!("http://localhost:{}/photo.png", port);
+        let client = reqwest::Client::new();
+        let first = client.get(&url).send().unwrap();
+        assert_eq!(first.status(), 200);
+        assert_eq!(
+            first.headers()["Cache-Control"].to_str().unwrap(),
+            "public, max-age=3600"
+        );
+        let etag = first.headers()["ETag"].to_str().unwrap().to_string();
+        let modified = first.headers()["Last-Modified"]
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let mut cached = client
+            .get(&url)
+            .header("If-None-Match", etag.as_str())
+            .send()
+            .unwrap();
+        assert_eq!(cached.status(), 304);
+        assert_eq!(cached.headers()["ETag"].to_str().unwrap(), etag);
+        assert_eq!(cached.text().unwrap(), "");
+        let cached = client
+            .get(&url)
+            .header("If-Modified-Since", modified.as_str())
+            .send()
+            .unwrap();
+        assert_eq!(cached.status(), 304);
+
+        // If-None-Match decides when both are sent
+        let changed = client
+            .get(&url)
+            .header("If-None-Match", "\"outdated\"")
+            .header("If-Modified-Since", modified.as_str())
+            .send()
+            .unwrap();
+        assert_eq!(changed.status(), 200);
+        let old = client
+            .get(&url)
+            .header("If-Modified-Since", "Thu, 01 Jan 1970 00:00:00 GMT")
+            .send()
+            .unwrap();
+        assert_eq!(old.status(), 200);
+    }
+
+    #[test]
+    fn test_sparse_static_file() {
+        let root = temp_document_root("sparse_static_file");
+        // Sparse, so the file takes no disk space and reading it is cheap
+        let len: u64 = 300 * 1024 * 1024;
+        File::create(root.join("video.mp4"))
+            .and_then(|file| file.set_len(len))
+            .unwrap();
+        let mut server = Server::new();
+        server.set_document_root(&format!("{}/", root.display()));
+        let port = spawn_server(server);
+
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream
+            .write_all(b"GET /video.mp4 HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut reader = io::BufReader::new(stream);
+        let mut head = String::new();
+        while !head.ends_with("\r\n\r\n") {
+            assert!(reader.read_line(&mut head).unwrap() > 0);
+        }
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains(&format!("Content-Length: {}\r\n", len)));
+        // Counted as it arrives, neither side holds the whole file
+        assert_eq!(io::copy(&mut reader, &mut io::sink()).unwrap(), len);
+    }
+
+    #[test]
+    fn test_index_files() {
+        let root = temp_document_root("index_files");
+        fs::write(root.join("index.html"), "<h1>Home</h1>").unwrap();
+        fs::create_dir_all(root.join("legacy")).unwrap();
+        fs::write(root.join("legacy/index.htm"), "old").unwrap();
+        fs::write(root.join("legacy/default.htm"), "default").unwrap();
+        fs::create_dir_all(root.join("empty")).unwrap();
+        fs::create_dir_all(root.join("tricky/index.html")).unwrap();
+
+        let mut server = Server::new();
+        server.set_document_root(&format!("{}/", root.display()));
+        let get = |server: &Server, path: &str| {
+            let response = server.test_request("GET", path).send();
+            (response.status(), response.text())
+        };
+
+        let home = server.test_request("GET", "/").send();
+        assert_eq!(home.status(), 200);
+        assert_eq!(home.text(), "<h1>Home</h1>");
+        assert_eq!(
+            home.header("Content-Type"),
+            Some("text/html; charset=utf-8")
+        );
+        assert_eq!(get(&server, "/legacy/"), (200, String::from("old")));
+        assert_eq!(get(&server, "/empty/").0, 403);
+        // A directory named like an index file is no index file
+        assert_eq!(get(&server, "/tricky/").0, 403);
+
+        server.use_index_of(true);
+        let (status, listing) = get(&server, "/empty/");
+        assert_eq!(status, 200);
+        assert!(listing.contains("<html"));
+
+        assert!(server.set_index_files(&["default.htm", "index.htm"]));
+        assert_eq!(get(&server, "/legacy/"), (200, String::from("default")));
+        assert!(!server.set_index_files(&["index.html", "sub/index.html"]));
+        assert!(!server.set_index_files(&[""]));
+        assert_eq!(get(&server, "/legacy/"), (200, String::from("default")));
+        assert!(server.set_index_files(&[]));
+        assert_eq!(get(&server, "/legacy/").0, 200);
+        assert!(get(&server, "/legacy/").1.contains("default.htm"));
+    }
+
+    #[test]
+    fn test_render_templates() {
+        let root = temp_document_root("render_templates");
+        fs::write(
+            root.join("page.html"),
+            "<h1>{{ title }}</h1>\n{{{ footer }}}{{ missing }}",
+        )
+        .unwrap();
+        fs::write(root.join("broken.txt"), "{{ open").unwrap();
+        let mut server = Server::new();
+        server.get("/untemplated/", |_request, mut response| {
+            assert!(response.render("page.html", &HashMap::new()).is_err());
+        });
+        assert_eq!(
+            server.test_request("GET", "/untemplated/").send().status(),
+            200
+        );
+        assert!(!server.set_template_root(root.join("missing").to_str().unwrap()));
+        assert!(server.set_template_root(root.to_str().unwrap()));
+        server.get("/page/", |request, mut response| {
+            let mut context = HashMap::new();
+            let title = request.query_param_or("title", "");
+            context.insert(String::from("title"), String::from(title));
+            context.insert(String::from("footer"), String::from("<hr>"));
+            response.render("page.html", &context).unwrap();
+        });
+        server.get("/:name/", |request, mut response| {
+            let name = &request.get_path_parameters()["name"];
+            assert!(response.render(name, &HashMap::new()).is_err());
+        });
+
+        let response = server
+            .test_request("GET", "/page/?title=%3Cscript%3Ealert(1)%3C/script%3E")
+            .send();
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response.header("Content-Type"),
+            Some("text/html; charset=utf-8")
+        );
+        assert_eq!(
+            response.text(),
+            "<h1>&lt;script&gt;alert(1)&lt;/script&gt;</h1>\n<hr>"
+        );
+        for path in ["/missing.html/", "/broken.txt/"] {
+            let response = server.test_request("GET", path).send();
+            assert_eq!(response.status(), 500);
+            assert_eq!(response.text(), INTERNAL_ERROR_PAGE);
+        }
+    }
+
+    #[test]
+    fn test_directory_listing() {
+        let root = temp_document_root("directory_listing");
+        fs::create_dir_all(root.join("sub/deeper")).unwrap();
+        fs::write(root.join("sub/<b>bold.txt"), "x").unwrap();
+        fs::write(root.join("sub/.secret"), "x").unwrap();
+        let mut server = Server::new();
+        server.set_document_root(&format!("{}/", root.display()));
+        server.use_index_of(true);
+        assert!(server.serve_static_with(
+            "/files/",
+            root.join("sub").to_str().unwrap(),
+            StaticOptions {
+                index_of: true,
+                index_files: Vec::new(),
+            },
+        ));
+
+        let listing = server.test_request("GET", "/sub/").send();
+        assert_eq!(listing.status(), 200);
+        let listing = listing.text();
+        assert!(
+            listing.contains("<a href=\"deeper/\">deeper/</a>"),
+            "{}",
+            listing
+        );
+        assert!(!listing.contains("<b>bold"), "{}", listing);
+        assert!(listing.contains("&lt;b&gt;bold.txt"), "{}", listing);
+        assert!(listing.contains(".secret"), "{}", listing);
+        // Relative links work below a prefix too
+        let listing = server.test_request("GET", "/files/").send().text();
+        assert!(listing.contains("Index of /files/"), "{}", listing);
+        assert!(
+            listing.contains("<a href=\"deeper/\">deeper/</a>"),
+            "{}",
+            listing
+        );
+
+        server.show_hidden(false);
+        let listing = server.test_request("GET", "/sub/").send().text();
+        assert!(!listing.contains(".secret"), "{}", listing);
+        assert_eq!(
+            server.test_request("GET", "/sub/.secret").send().text(),
+            "x"
+        );
+    }
+
+    #[test]
+    fn test_path_traversal() {
+        let dir = temp_document_root("path_traversal");
+        let root = dir.join("public");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("sub/inside.txt"), "inside").unwrap();
+        fs::write(dir.join("secret.txt"), "TOP SECRET").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(dir.join("secret.txt"), root.join("link.txt")).unwrap();
+
+        let mut server = Server::new();
+        server.set_document_root(&format!("{}/", root.display()));
+        server.use_index_of(true);
+        let port = spawn_server(server);
+        let get = |target: &str| {
+            raw_request(
+                port,
+                &format!(
+                    "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+                    target
+                ),
+            )
+        };
+
+        assert!(get("/sub/inside.txt").ends_with("inside"));
+        let payloads = [
+            "/../secret.txt",
+            "/sub/../../secret.txt",
+            "/%2e%2e/secret.txt",
+            "/%2E%2E%2fsecret.txt",
+            "/sub/..%2f..%2fsecret.txt",
+            "/..%5csecret.txt",
+            "/sub%5c..%5c..%5csecret.txt",
+            "/..",
+            "/%2e%2e/",
+        ];
+        for payload in payloads.iter() {
+            let response = get(payload);
+            assert!(!response.contains("TOP SECRET"), "{}", payload);
+            assert!(
+                response.starts_with("HTTP/1.1 400 ") || response.starts_with("HTTP/1.1 404 "),
+                "{}: {}",
+                payload,
+                response
+            );
+        }
+        #[cfg(unix)]
+        assert!(get("/link.txt").starts_with("HTTP/1.1 403 "));
+        assert!(stays_inside("sub/inside.txt"));
+        assert!(!stays_inside("sub\\..\\..\\secret.txt"));
+        assert!(!stays_inside("/etc/passwd"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_follow_symlinks() {
+        use std::os::unix::fs::symlink;
+        let dir = temp_document_root("follow_symlinks");
+        let root = dir.join("public");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::create_dir_all(dir.join("shared")).unwrap();
+        fs::write(root.join("inside.txt"), "inside").unwrap();
+        fs::write(dir.join("shared/app.css"), "shared").unwrap();
+        symlink(root.join("inside.txt"), root.join("sub/in.txt")).unwrap();
+        symlink(dir.join("shared/app.css"), root.join("out.css")).unwrap();
+        symlink(dir.join("shared"), root.join("assets")).unwrap();
+        symlink(root.join("missing.txt"), root.join("broken.txt")).unwrap();
+        symlink(root.join("loop_b"), root.join("loop_a")).unwrap();
+        symlink(root.join("loop_a"), root.join("loop_b")).unwrap();
+
+        for follow in [false, true] {
+            let mut server = Server::new();
+            server.set_document_root(&format!("{}/", root.display()));
+            server.follow_symlinks(follow);
+            let port = spawn_server(server);
+            let get = |target: &str| {
+                raw_request(
+                    port,
+                    &format!(
+                        "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+                        target
+                    ),
+                )
+            };
+            assert!(get("/sub/in.txt").ends_with("inside"), "{}", follow);
+            for target in ["/out.css", "/assets/app.css"] {
+                let response = get(target);
+                if follow {
+                    assert!(response.ends_with("shared"), "{}", response);
+                } else {
+                    assert!(response.starts_with("HTTP/1.1 403 "), "{}", response);
+                }
+            }
+            for target in ["/broken.txt", "/loop_a"] {
+                let response = get(target);
+                assert!(response.starts_with("HTTP/1.1 404 "), "{}", response);
+            }
+            // Rejected for its `..` before the file system is looked at
+            assert_eq!(get("/sub/../../shared/app.css"), BAD_REQUEST);
+        }
+    }
+
+    #[test]
+    fn test_repeated_parameters() {
+        let request = Request::builder()
+            .method("POST")
+            .query("tag=rust&tag=web&tags[]=a&tags[]=b&empty=&bare&token=YWI=Yw==")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(b"color=red&size=L&color=blue&color=")
+            .build();
+        let strings =
+            |values: &[&str]| -> Vec<String> { values.iter().map(|v| String::from(*v)).collect() };
+
+        // The single values are the first ones sent
+        assert_eq!(request.query("tag"), Some("rust"));
+        assert_eq!(request.query("tags[]"), Some("a"));
+        assert_eq!(request.query("empty"), Some(""));
+        assert_eq!(request.query("bare"), Some(""));
+        assert_eq!(request.query("token"), Some("YWI=Yw=="));
+        let all = request.get_query_parameters_all();
+        assert_eq!(all.len(), 5);
+        assert_eq!(all["tag"], strings(&["rust", "web"]));
+        assert_eq!(all["tags[]"], strings(&["a", "b"]));
+        assert_eq!(all["empty"], strings(&[""]));
+        assert_eq!(all["token"], strings(&["YWI=Yw=="]));
+
+        assert_eq!(request.form("color"), Some("red"));
+        assert_eq!(request.get_post_parameters()["size"], "L");
+        let all = request.get_post_parameters_all();
+        assert_eq!(all["color"], strings(&["red", "blue", ""]));
+        assert_eq!(all["size"], strings(&["L"]));
+        assert!(Request::new().get_query_parameters_all().is_empty());
+
+        let request = Request::builder()
+            .method("POST")
+            .query("tag=web&tag=rust")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(b"tag=web&tag=rust")
+            .build();
+        assert_eq!(request.query("tag"), Some("web"));
+        assert_eq!(request.get_query_parameters()["tag"], "web");
+        assert_eq!(request.form("tag"), Some("web"));
+        assert_eq!(request.get_post_parameters()["tag"], "web");
+    }
+
+    #[test]
+    fn test_single_parameter_accessors() {
+        let mut request = Request::new();
+        request.query_parameters = Server::parse_parameters(Some(&"page=2&sort="))
+            .into_iter()
+            .collect();
+        request.post_parameters = Server::parse_parameters(Some(&"fname=Max"))
+            .into_iter()
+            .collect();
+
+        assert_eq!(request.query("page"), Some("2"));
+        assert_eq!(request.query("sort"), Some(""));
+        assert_eq!(request.query("fname"), None);
+        assert_eq!(request.form("fname"), Some("Max"));
+        assert_eq!(request.form("page"), None);
+        assert_eq!(request.get_query_parameters().len(), 2);
+
+        assert_eq!(request.query_param("page"), Some("2"));
+        assert_eq!(request.query_param_or("page", "1"), "2");
+        assert_eq!(request.query_param_or("limit", "10"), "10");
+        assert_eq!(request.form_param_or("fname", "-"), "Max");
+        assert_eq!(request.query_param_parse::<u32>("page"), Ok(Some(2)));
+        assert_eq!(request.query_param_parse::<u32>("limit"), Ok(None));
+        assert_eq!(request.form_param_parse::<u32>("page"), Ok(None));
+        let invalid = request.query_param_parse::<u32>("sort").unwrap_err();
+        assert_eq!(
+            invalid,
+            ParamError::Invalid {
+                name: String::from("sort"),
+                value: String::new(),
+            }
+        );
+        assert_eq!(
+            request
+                .form_param_parse::<u32>("fname")
+                .unwrap_err()
+                .to_string(),
+            "parameter fname has the invalid value \"Max\""
+        );
+    }
+
+    #[test]
+    fn test_raw_head_and_body() {
+        let mut server = Server::new();
+        server.post("/hook/", |request, mut response| {
+            let _ = response.write(request.raw_head());
+            let _ = response.write("|");
+            let _ = response.write(str::from_utf8(request.raw_body()).unwrap());
+        });
+        let response = server
+            .test_request("POST", "/hook/?b=1")
+            .header("x-ODD-case", "  spaced\tvalue ")
+            .header("X-Signature", "abc")
+            .body("{\"id\": 1}")
+            .send();
+        assert_eq!(
+            response.text(),
+            "POST /hook/?b=1 HTTP/1.1\r\nHost: localhost\r\nx-ODD-case:   spaced\tvalue \r\nX-Signature: abc\r\nContent-Length: 9|{\"id\": 1}"
+        );
+    }
+
+    #[test]
+    fn test_formatted_writes() {
+        let items = [("a", 1), ("b<", 22)];
+        let mut server = Server::new();
+        server.get("/old/", move |_request, mut response| {
+            for (name, count) in &items {
+                let _ = response.write(&format!("<li>{}: {}</li>\n", name, count));
+            }
+            let _ = response.write(&format!("{}", 3.5));
+        });
+        server.get("/new/", move |_request, mut response| {
+            for (name, count) in &items {
+                writeln!(response, "<li>{}: {}</li>", name, count).unwrap();
+            }
+            response.write_display(&3.5).unwrap();
+        });
+        server.get("/fmt/", move |_request, mut response| {
+            let writer: &mut dyn fmt::Write = &mut response;
+            for (name, count) in &items {
+                writeln!(writer, "<li>{}: {}</li>", name, count).unwrap();
+            }
+            write!(writer, "{}", 3.5).unwrap();
+        });
+        let old = server.test_request("GET", "/old/").send().text();
+        assert_eq!(old, "<li>a: 1</li>\n<li>b<: 22</li>\n3.5");
+        assert_eq!(server.test_request("GET", "/new/").send().text(), old);
+        assert_eq!(server.test_request("GET", "/fmt/").send().text(), old);
+
+        // Statuses without a body reject formatted writes like plain ones
+        server.get("/empty/", |_request, mut response| {
+            response.set_status_code(204).unwrap();
+            assert!(write!(response, "{}", 1).is_err());
+            assert!(write!(response, "").is_ok());
+        });
+        assert_eq!(server.test_request("GET", "/empty/").send().status(), 204);
+    }
+
+    /// Writes rows the way a CSV library would, through any `io::Write`
+    fn write_csv<W: Write>(mut writer: W, rows: usize) -> std::io::Result<()> {
+        writer.write_all(b"id,name,score\n")?;
+        for id in 0..rows {
+            writeln!(writer, "{},\"user {}\",{}", id, id, id * 7 % 100)?;
+        }
+        writer.flush()
+    }
+
+    /// Reads from `stream` until what arrived contains `expected`, returning
+    /// how long that took
+    #[cfg(not(feature = "tokio"))]
+    fn read_until(stream: &mut TcpStream, received: &mut Vec<u8>, expected: &str) -> Duration {
+        let started = Instant::now();
+        let mut buffer = [0; 1024];
+        while !String::from_utf8_lossy(received).contains(expected) {
+            let read = stream.read(&mut buffer).unwrap();
+            assert!(read > 0, "{}", String::from_utf8_lossy(received));
+            received.extend_from_slice(&buffer[..read]);
+        }
+        started.elapsed()
+    }
+
+    // The tokio listener captures responses in memory, they are not streamed
+    #[cfg(not(feature = "tokio"))]
+    #[test]
+    fn test_flush_control() {
+        let pause = Duration::from_millis(400);
+        let mut server = Server::new();
+        server.get("/progress/", move |_request, mut response| {
+            let _ = response.write("started\n");
+            response.flush().unwrap();
+            assert!(response.headers_sent());
+            thread::sleep(pause);
+            let _ = response.write("done\n");
+        });
+        server.get("/corked/", move |_request, mut response| {
+            response.cork();
+            for _ in 0..4 {
+                response.write_all(&[b'x'; STREAM_BUFFER_SIZE]).unwrap();
+            }
+            thread::sleep(pause);
+            response.uncork().unwrap();
+            response.write_all(b"end").unwrap();
+        });
+        server.get("/autoflush/", move |_request, mut response| {
+            response.set_autoflush(true);
+            response.write_all(b"tick").unwrap();
+            thread::sleep(pause);
+            response.write_all(b"tock").unwrap();
+        });
+        let port = spawn_server(server);
+        let request = |path: &str| {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+            stream.write_all(request.as_bytes()).unwrap();
+            stream
+        };
+
+        // The flushed part arrives while the handler still sleeps
+        let mut received = Vec::new();
+        let mut stream = request("/progress/");
+        assert!(read_until(&mut stream, &mut received, "8\r\nstarted\n\r\n") < pause / 2);
+        assert!(read_until(&mut stream, &mut received, "0\r\n\r\n") >= pause / 2);
+        assert!(String::from_utf8_lossy(&received).contains("5\r\ndone\n\r\n"));
+
+        // Past the 16 KiB a corked body is held back until uncorked, and
+        // goes out as one chunk
+        let mut received = Vec::new();
+        let mut stream = request("/corked/");
+        read_until(&mut stream, &mut received, "\r\n\r\n");
+        assert!(read_until(&mut stream, &mut received, "10000\r\nxxx") >= pause / 2);
+        read_until(&mut stream, &mut received, "0\r\n\r\n");
+        assert!(String::from_utf8_lossy(&received).ends_with("\r\n3\r\nend\r\n0\r\n\r\n"));
+
+        // Every write is a chunk of its own, sent right away
+        let mut received = Vec::new();
+        let mut stream = request("/autoflush/");
+        assert!(read_until(&mut stream, &mut received, "4\r\ntick\r\n") < pause / 2);
+        assert!(read_until(&mut stream, &mut received, "4\r\ntock\r\n0\r\n\r\n") >= pause / 2);
+    }
+
+    #[cfg(not(feature = "tokio"))]
+    #[test]
+    fn test_event_stream() {
+        let pause = Duration::from_millis(400);
+        let (disconnected, gone) = mpsc::channel();
+        let mut server = Server::new();
+        server.get("/events/", move |_request, mut response| {
+            let mut events = response.start_event_stream().unwrap();
+            events.set_keep_alive(pause / 4);
+            events.send_event("tick", "1").unwrap();
+            events.wait(pause).unwrap();
+            events.send_data("two\nlines").unwrap();
+            // Runs until the client is gone
+            while events.send_data("more").is_ok() {
+                thread::sleep(Duration::from_millis(10));
+            }
+            assert!(response.set_header("X-Late", "1").is_err());
+            let _ = disconnected.send(());
+        });
+        let port = spawn_server(server);
+
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream
+            .write_all(b"GET /events/ HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut received = Vec::new();
+        assert!(read_until(&mut stream, &mut received, "event: tick\ndata: 1\n\n") < pause / 2);
+        assert!(read_until(&mut stream, &mut received, ": keep-alive\n\n") < pause / 2);
+        assert!(read_until(&mut stream, &mut received, "data: two\ndata: lines\n\n") >= pause / 4);
+        let head = String::from_utf8_lossy(&received);
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains("Content-Type: text/event-stream"));
+        assert!(head.contains("Cache-Control: no-cache\r\n"));
+        assert!(head.contains("Transfer-Encoding: chunked\r\n"));
+        drop(stream);
+        gone.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        // In memory the events are collected like any body
+        let mut server = Server::new();
+        server.get("/events/", |_request, mut response| {
+            let mut events = response.start_event_stream().unwrap();
+            events.send_data("hello").unwrap();
+            assert!(events.send_event("bad\nname", "x").is_err());
+            events.send_event("bye", "").unwrap();
+        });
+        server.get("/late/", |_request, mut response| {
+            let _ = response.redirect("/events/");
+            assert!(response.start_event_stream().is_err());
+        });
+        let response = server.test_request("GET", "/events/").send();
+        assert_eq!(response.text(), "data: hello\n\nevent: bye\ndata: \n\n");
+        assert_eq!(response.header("Cache-Control"), Some("no-cache"));
+        assert_eq!(server.test_request("GET", "/late/").send().status(), 302);
+    }
+
+    #[test]
+    fn test_chunked_response() {
+        let mut server = Server::new();
+        server.get("/report/", |_request, mut response| {
+            response.set_header("Content-Type", "text/plain").unwrap();
+            response.start_chunked().unwrap();
+            assert!(response.start_chunked().is_err());
+            response.write("hello").unwrap();
+            response.write_bytes(b", world!\n").unwrap();
+            response.write("").unwrap();
+            response.finish().unwrap();
+            assert!(response.write("late").is_err());
+        });
+        server.get("/sized/", |_request, mut response| {
+            response.set_header("Content-Length", "2").unwrap();
+            assert!(response.start_chunked().is_err());
+            response.write("ok").unwrap();
+        });
+        server.post("/form/", |request, mut response| {
+            let tags = &request.get_post_parameters_all()["tag"];
+            response.write(&tags.join(",")).unwrap();
+        });
+        let port = spawn_server(server);
+
+        // The tokio listener captures responses in memory like `test_request`
+        #[cfg(not(feature = "tokio"))]
+        {
+            // The second request is answered after the end of the chunked body
+            let raw = raw_request(
+                port,
+                "GET /report/ HTTP/1.1\r\nHost: localhost\r\n\r\n\
+                 GET /sized/ HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            );
+            let (head, rest) = raw.split_once("\r\n\r\n").unwrap();
+            assert!(head.contains("\r\nTransfer-Encoding: chunked"), "{}", head);
+            assert!(!head.contains("Content-Length"), "{}", head);
+            let (body, second) = rest.split_at(rest.find("HTTP/1.1 ").unwrap());
+            assert_eq!(body, "5\r\nhello\r\n9\r\n, world!\n\r\n0\r\n\r\n");
+            assert!(second.starts_with("HTTP/1.1 200 OK\r\n"), "{}", second);
+            assert!(
+                second.ends_with("Content-Length: 2\r\n\r\nok"),
+                "{}",
+                second
+            );
+
+            // HTTP/1.0 clients get the body until the connection closes
+            let raw = raw_request(port, "GET /report/ HTTP/1.0\r\n\r\n");
+            assert!(!raw.contains("Transfer-Encoding"), "{}", raw);
+            assert!(raw.ends_with("\r\n\r\nhello, world!\n"), "{}", raw);
+        }
+
+        // Chunked request bodies reach the handler decoded
+        let raw = raw_request(
+            port,
+            "POST /form/ HTTP/1.1\r\nHost: localhost\r\n\
+             Content-Type: application/x-www-form-urlencoded\r\n\
+             Transfer-Encoding: chunked\r\nConnection: close\r\n\r\n\
+             6\r\ntag=a&\r\n5\r\ntag=b\r\n0\r\n\r\n",
+        );
+        assert!(raw.ends_with("\r\n\r\na,b"), "{}", raw);
+    }
+
+    #[test]
+    fn test_streamed_response() {
+        let mut expected = Vec::new();
+        write_csv(&mut expected, 2000).unwrap();
+        let expected_len = expected.len() as u64;
+
+        let mut server = Server::new();
+        server.get("/export.csv", move |_request, mut response| {
+            let _ = response.set_header("Content-Type", "text/csv");
+            write_csv(&mut response, 2000).unwrap();
+            assert_eq!(response.bytes_written(), expected_len);
+            assert_eq!(response.headers_sent(), !cfg!(feature = "tokio"));
+            // The head is gone, so are its headers
+            assert!(response.set_header("X-Late", "1").is_err());
+            assert!(response.set_status_code(500).is_err());
+        });
+        server.get("/sized.txt", |_request, mut response| {
+            let _ = response.set_header("Content-Length", "5");
+            response.write_all(b"hello").unwrap();
+            assert!(response.write_all(b"!").is_err());
+        });
+        let port = spawn_server(server);
+
+        let mut client = reqwest::get(&format!("http://localhost:{}/export.csv", port)).unwrap();
+        assert_eq!(client.status(), 200);
+        assert_eq!(client.text().unwrap().as_bytes(), &expected[..]);
+
+        // The tokio listener captures responses in memory like `test_request`
+        #[cfg(not(feature = "tokio"))]
+        {
+            let raw = raw_request(port, "GET /export.csv HTTP/1.1\r\nHost: localhost\r\n\r\n");
+            assert!(raw.contains("Transfer-Encoding: chunked\r\n"));
+            assert!(!raw.contains("Content-Length"));
+            assert!(raw.ends_with("\r\n0\r\n\r\n"));
+
+            // HTTP/1.0 clients get the body until the connection closes
+            let raw = raw_request(port, "GET /export.csv HTTP/1.0\r\n\r\n");
+            assert!(!raw.contains("Transfer-Encoding"));
+            assert!(raw.ends_with(str::from_utf8(&expected).unwrap()));
+
+            let raw = raw_request(port, "GET /sized.txt HTTP/1.1\r\nHost: localhost\r\n\r\n");
+            assert!(raw.contains("Content-Length: 5\r\n"));
+            assert!(!raw.contains("Transfer-Encoding"));
+            assert!(raw.ends_with("\r\n\r\nhello"));
+        }
+
+        // In memory the body is collected and sent with its length
+        let mut server = Server::new();
+        server.get("/export.csv", |_request, mut response| {
+            write_csv(&mut response, 10).unwrap();
+        });
+        let response = server.test_request("GET", "/export.csv").send();
+        let mut expected = Vec::new();
+        write_csv(&mut expected, 10).unwrap();
+        assert_eq!(response.body(), &expected[..]);
+        assert_eq!(
+            response.header("Content-Length"),
+            Some(expected.len().to_string().as_str())
+        );
+    }
+
+    #[test]
+    fn test_response_introspection() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let mut response = Response::new(Stream::Memory(MemoryStream {
+            input: io::Cursor::new(Vec::new()),
+            output: output.clone(),
+            encrypted: false,
+            remaining_requests: 0,
+            sni_hostname: None,
+            #[cfg(feature = "tls")]
+            client_certificate: None,
+            #[cfg(feature = "tokio")]
+            body_feed: None,
+        }));
+        response
+            .defaults
+            .set("Date", "Thu, 01 Jan 2026 00:00:00 GMT");
+        response.defaults.set("X-Default", "server");
+        assert_eq!(response.status(), None);
+        assert!(!response.headers_sent());
+        assert_eq!(response.bytes_written(), 0);
+        assert_eq!(
+            response.header("Date"),
+            Some("Thu, 01 Jan 2026 00:00:00 GMT")
+        );
+        assert_eq!(response.header("X-Handler"), None);
+
+        response.set_status_code(404).unwrap();
+        response.set_header("X-Handler", "1").unwrap();
+        response.set_header("x-default", "handler").unwrap();
+        response.write("gone").unwrap();
+        assert_eq!(response.status(), Some(404));
+        assert_eq!(response.header("x-handler"), Some("1"));
+        assert_eq!(response.header("X-Default"), Some("handler"));
+        assert_eq!(response.bytes_written(), 4);
+        assert!(!response.headers_sent());
+
+        response.send().unwrap();
+        assert!(response.headers_sent());
+        assert_eq!(response.status(), Some(404));
+        assert_eq!(
+            response.header("Date"),
+            Some("Thu, 01 Jan 2026 00:00:00 GMT")
+        );
+        assert_eq!(response.bytes_written(), 4);
+        assert!(response.set_header("X-Late", "1").is_err());
+        drop(response);
+        // Dropping does not send the response again
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert!(output.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert_eq!(output.matches("HTTP/1.1").count(), 1);
+
+        // The status goes out as 200 unless set
+        let mut response = Response::new(Stream::Memory(MemoryStream {
+            input: io::Cursor::new(Vec::new()),
+            output: Arc::default(),
+            encrypted: false,
+            remaining_requests: 0,
+            sni_hostname: None,
+            #[cfg(feature = "tls")]
+            client_certificate: None,
+            #[cfg(feature = "tokio")]
+            body_feed: None,
+        }));
+        response.send().unwrap();
+        assert_eq!(response.status(), Some(200));
+    }
+
+    #[test]
+    fn test_debug() {
+        let mut server = Server::new();
+        server.post("/users/:id/", |request, mut response| {
+            let _ = response.set_status_code(201);
+            let _ = response.set_header("X-Trace", "abc");
+            let _ = response.write("created");
+            let _ = response.write(&format!(
+                "\n{:?}\n{:?}\n{:?}",
+                request,
+                request.debug_full(),
+                response
+            ));
+        });
+        let text = server
+            .test_request("POST", "/users/7/?page=2")
+            .header("Authorization", "Bearer secret-token")
+            .header("Cookie", "session=secret-session")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body("name=Max")
+            .send()
+            .text();
+        let mut lines = text.lines().skip(1);
+        let (redacted, full, response) = (
+            lines.next().unwrap(),
+            lines.next().unwrap(),
+            lines.next().unwrap(),
+        );
+        for expected in [
+            "method: \"POST\"",
+            "path: \"/users/7/\"",
+            "version: \"HTTP/1.1\"",
+            "header_count: 5",
+            "(\"Authorization\", \"<redacted>\")",
+            "(\"session\", \"<redacted>\")",
+            "\"page\": \"2\"",
+            "\"name\": \"Max\"",
+            "\"id\": \"7\"",
+            "peer_addr: None",
+            "body_len: 8",
+        ] {
+            assert!(redacted.contains(expected), "{} in {}", expected, redacted);
+        }
+        assert!(!redacted.contains("secret"));
+        assert!(full.contains("Bearer secret-token"));
+        assert!(full.contains("(\"session\", \"secret-session\")"));
+        assert!(full.contains("raw_head: \"POST /users/7/?page=2 HTTP/1.1"));
+        for expected in [
+            "status: Some(201)",
+            "\"X-Trace\"",
+            "bytes_written: 7",
+            "head_sent: false",
+            "completed: false",
+        ] {
+            assert!(response.contains(expected), "{} in {}", expected, response);
+        }
+    }
+
+    #[test]
+    fn test_ordered_parameters() {
+        let mut server = Server::new();
+        server.post("/", |request, mut response| {
+            let pairs: Vec<String> = request
+                .query_pairs()
+                .iter()
+                .chain(request.form_pairs())
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect();
+            let _ = response.write(&pairs.join(","));
+            let _ = response.write(&format!(";{}", request.query("tag").unwrap_or("")));
+        });
+        let response = server
+            .test_request("POST", "/?zeta=1&tag=a&alpha=2&tag=b&mid=")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body("z=9&a=8")
+            .send();
+        assert_eq!(response.text(), "zeta=1,tag=a,alpha=2,tag=b,mid=,z=9,a=8;a");
+    }
+
+    #[test]
+    fn test_large_static_file() {
+        let root = temp_document_root("large_static_file");
+        let content: Vec<u8> = (0..5 * 1024 * 1024u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+            .collect();
+        fs::write(root.join("large.bin"), &content).unwrap();
+
+        let mut server = Server::new();
+        server.set_document_root(&format!("{}/", root.display()));
+        let port = spawn_server(server);
+
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream
+            .write_all(b"GET /large.bin HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        stream.shutdown(std::net::Shutdown::Write).unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+
+        let end = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        let head = str::from_utf8(&response[..end]).unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains("\r\nDate: "));
+        assert!(head.ends_with(&format!("\r\nContent-Length: {}\r\n\r\n", content.len())));
+        assert!(response[end..] == content[..]);
+    }
+
+    #[test]
+    fn test_client_abort() {
+        let root = temp_document_root("client_abort");
+        fs::write(root.join("large.bin"), vec![b'x'; 16 * 1024 * 1024]).unwrap();
+        let log = root.join("server.log");
+
+        let mut server = Server::new();
+        server.set_document_root(&format!("{}/", root.display()));
+        server.set_logger(&log.to_string_lossy());
+        let port = spawn_server(server);
+
+        for _ in 0..3 {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            stream
+                .write_all(b"GET /large.bin HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                .unwrap();
+            let mut half = vec![0; 64 * 1024];
+            stream.read_exact(&mut half).unwrap();
+            // Unread data makes the kernel reset the connection
+            drop(stream);
+        }
+        // Workers are free again
+        let response = raw_request(port, "GET /missing.txt HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404"));
+
+        let started = Instant::now();
+        let mut contents = String::new();
+        while contents.matches("Client aborted").count() < 3 {
+            assert!(started.elapsed() < Duration::from_secs(5), "{}", contents);
+            thread::sleep(Duration::from_millis(20));
+            contents = fs::read_to_string(&log).unwrap();
+        }
+        assert!(!contents.contains("WARNING"), "{}", contents);
+        assert!(contents
+            .lines()
+            .filter(|line| line.contains("Client aborted"))
+            .all(|line| line.starts_with("DEBUG")));
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_mmap_static_files() {
+        let root = temp_document_root("mmap_static_files");
+        let content: Vec<u8> = (0..300 * 1024u32).map(|i| (i % 253) as u8).collect();
+        fs::write(root.join("mapped.bin"), &content).unwrap();
+        fs::write(root.join("empty.txt"), "").unwrap();
+
+        let mut server = Server::new();
+        server.set_document_root(&format!("{}/", root.display()));
+        server.use_mmap(true);
+        let port = spawn_server(server);
+
+        let mut resp = reqwest::get(&format!("http://localhost:{}/mapped.bin", port)).unwrap();
+        let mut body = Vec::new();
+        resp.copy_to(&mut body).unwrap();
+        assert!(body == content);
+
+        let mut resp = reqwest::get(&format!("http://localhost:{}/empty.txt", port)).unwrap();
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.text().unwrap(), "");
+    }
+
+    #[test]
+    fn test_stat_cache_deleted_file() {
+        let root = temp_document_root("stat_cache_deleted_file");
+        fs::write(root.join("hot.txt"), "hot").unwrap();
+
+        let mut server = Server::new();
+        server.set_document_root(&format!("{}/", root.display()));
+        server.set_stat_cache(Duration::from_secs(60), 16);
+        let port = spawn_server(server);
+
+        let mut resp = reqwest::get(&format!("http://localhost:{}/hot.txt", port)).unwrap();
+        assert_eq!(resp.text().unwrap(), "hot");
+
+        fs::remove_file(root.join("hot.txt")).unwrap();
+        let resp = reqwest::get(&format!("http://localhost:{}/hot.txt", port)).unwrap();
+        assert_eq!(resp.status(), 404);
+
+        fs::write(root.join("hot.txt"), "back").unwrap();
+        let resp = reqwest::get(&format!("http://localhost:{}/hot.txt", port)).unwrap();
+        assert_eq!(resp.status(), 404);
+    }
+
+    #[test]
+    fn test_stat_cache_rewritten_file() {
+        let root = temp_document_root("stat_cache_rewritten_file");
+        let file = root.join("data.txt");
+        fs::write(&file, "sixteen bytes!!!").unwrap();
+
+        let mut server = Server::new();
+        server.set_document_root(&format!("{}/", root.display()));
+        server.set_stat_cache(Duration::from_secs(30), 16);
+        let get = |server: &Server| server.test_request("GET", "/data.txt").send();
+        assert_eq!(get(&server).text(), "sixteen bytes!!!");
+        let etag = get(&server).header("ETag").map(String::from);
+
+        for contents in ["short", "now a good deal longer than before"] {
+            fs::write(&file, contents).unwrap();
+            let response = get(&server);
+            assert_eq!(
+                response.header("Content-Length"),
+                Some(contents.len().to_string().as_str())
+            );
+            assert_eq!(response.text(), contents);
+            assert_ne!(response.header("ETag").map(String::from), etag);
+        }
+    }
+
+    #[test]
+    fn test_static_cache() {
+        let root = temp_document_root("static_cache");
+        fs::write(root.join("app.js"), "let version = 1;").unwrap();
+        fs::write(root.join("index.html"), "home").unwrap();
+        fs::write(root.join("large.txt"), "x".repeat(64)).unwrap();
+
+        let mut server = Server::new();
+        server.set_document_root(&format!("{}/", root.display()));
+        server.set_static_cache(1024, 32);
+        let get = |server: &Server, path: &str| server.test_request("GET", path).send().text();
+
+        assert_eq!(get(&server, "/app.js"), "let version = 1;");
+        assert_eq!(get(&server, "/app.js"), "let version = 1;");
+        assert_eq!(get(&server, "/"), "home");
+        let partial = server
+            .test_request("GET", "/app.js")
+            .header("Range", "bytes=4-10")
+            .send();
+        assert_eq!(partial.status(), 206);
+        assert_eq!(partial.text(), "version");
+
+        // An edit shows up right away, also with the same size
+        let app = root.join("app.js");
+        fs::write(&app, "let version = 2;").unwrap();
+        let later = SystemTime::now() + Duration::from_secs(5);
+        File::options()
+            .write(true)
+            .open(&app)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        assert_eq!(get(&server, "/app.js"), "let version = 2;");
+        fs::write(root.join("index.html"), "new home").unwrap();
+        assert_eq!(get(&server, "/"), "new home");
+
+        // Files above the limit are streamed
+        assert_eq!(get(&server, "/large.txt"), "x".repeat(64));
+        fs::write(root.join("large.txt"), "y".repeat(64)).unwrap();
+        assert_eq!(get(&server, "/large.txt"), "y".repeat(64));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unreadable_static_file() {
+        use std::os::unix::fs::PermissionsExt;
+        let root = temp_document_root("unreadable_static_file");
+        let secret = root.join("secret.txt");
+        fs::write(&secret, "secret").unwrap();
+        fs::set_permissions(&secret, fs::Permissions::from_mode(0o000)).unwrap();
+        if File::open(&secret).is_ok() {
+            // Permissions do not stop root
+            return;
+        }
+
+        let mut server = Server::new();
+        server.set_document_root(&format!("{}/", root.display()));
+        let response = server.test_request("GET", "/secret.txt").send();
+        assert_eq!(response.status(), 403);
+        assert_eq!(response.text(), FORBIDDEN_PAGE);
+    }
+
+    #[test]
+    fn test_error_page() {
+        let root = temp_document_root("error_page");
+        let page = "<html><style>h1 { color: teal; }</style><h1>Lost?</h1></html>";
+        fs::write(root.join("404.html"), page).unwrap();
+        fs::create_dir_all(root.join("errors")).unwrap();
+        fs::write(root.join("errors/405.txt"), "wrong method").unwrap();
+
+        let mut server = Server::new();
+        server.set_document_root(&format!("{}/", root.display()));
+        server.enable_stat_cache();
+        server.route("PUT", "/thing/", |_request, _response| {});
+        assert!(server.set_error_page(404, "404.html"));
+        assert!(server.set_error_page(405, "errors/405.txt"));
+        assert!(!server.set_error_page(200, "404.html"));
+        assert!(!server.set_error_page(404, "../404.html"));
+        assert!(!server.set_error_page(404, "/etc/404.html"));
+        assert!(!server.set_error_page(404, ""));
+
+        for _ in 0..2 {
+            let response = server.test_request("GET", "/missing.html").send();
+            assert_eq!(response.status(), 404);
+            assert_eq!(
+                response.header("Content-Type"),
+                Some("text/html; charset=utf-8")
+            );
+            assert_eq!(response.text(), page);
+        }
+        let response = server.test_request("GET", "/thing/").send();
+        assert_eq!(response.status(), 405);
+        assert_eq!(response.text(), "wrong method");
+
+        // A missing page falls back to the built-in one
+        assert!(server.set_error_page(404, "gone.html"));
+        let response = server.test_request("GET", "/missing.html").send();
+        assert_eq!(response.status(), 404);
+        assert_eq!(response.text(), NOT_FOUND_PAGE);
+    }
+
+    #[test]
+    #[cfg(feature = "access-files")]
+    fn test_access_files() {
+        let root = temp_document_root("access_files");
+        for dir in ["private/public", "listed/unlisted", "broken/deeper"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        let files = [
+            (".corroded", "header X-Site: root\n"),
+            ("index.txt", "root"),
+            ("private/.corroded", "deny from all\nheader X-Site: private"),
+            ("private/secret.txt", "secret"),
+            ("private/public/.corroded", "allow from all"),
+            ("private/public/open.txt", "open"),
+            ("listed/.corroded", "index on"),
+            ("listed/unlisted/.corroded", "index off"),
+            ("broken/.corroded", "deny from everyone"),
+            ("broken/deeper/file.txt", "file"),
+            ("members.txt", "members"),
+        ];
+        for (file, content) in files.iter() {
+            fs::write(root.join(file), content).unwrap();
+        }
+
+        let mut server = Server::new();
+        server.set_document_root(&format!("{}/", root.display()));
+        let get = |server: &Server, path: &str| {
+            let response = server.test_request("GET", path).send();
+            let site = response.header("X-Site").map(String::from);
+            (response.status(), site)
+        };
+        // Off unless enabled
+        assert_eq!(get(&server, "/private/secret.txt").0, 200);
+
+        server.use_access_files(true);
+        assert_eq!(
+            get(&server, "/index.txt"),
+            (200, Some(String::from("root")))
+        );
+        assert_eq!(get(&server, "/private/secret.txt").0, 403);
+        assert_eq!(get(&server, "/private/missing.txt").0, 403);
+        assert_eq!(get(&server, "/private/").0, 403);
+        // A deeper file overrides, inheriting everything it does not set
+        assert_eq!(
+            get(&server, "/private/public/open.txt"),
+            (200, Some(String::from("private")))
+        );
+        assert_eq!(get(&server, "/listed/").0, 200);
+        assert_eq!(get(&server, "/listed/unlisted/").0, 403);
+        assert_eq!(get(&server, "/listed/unlisted").0, 403);
+        // Fails closed for the directory and everything below
+        assert_eq!(get(&server, "/broken/deeper/file.txt").0, 500);
+        assert_eq!(get(&server, "/.corroded").0, 404);
+        assert_eq!(get(&server, "/private/public/.corroded").0, 404);
+
+        // Changes are picked up
+        fs::write(root.join("broken/.corroded"), "deny from all\n").unwrap();
+        assert_eq!(get(&server, "/broken/deeper/file.txt").0, 403);
+        fs::write(root.join(".corroded"), "require auth").unwrap();
+        assert_eq!(get(&server, "/members.txt").0, 403);
+        server.set_auth_provider(|request| request.get_header("X-Token") == Some("secret"));
+        assert_eq!(get(&server, "/members.txt").0, 403);
+        let response = server
+            .test_request("GET", "/members.txt")
+            .header("X-Token", "secret")
+            .send();
+        assert_eq!(response.text(), "members");
+    }
+
+    #[test]
+    fn test_favicon() {
+        let root = temp_document_root("favicon");
+        let icon = root.join("icon.ico");
+        fs::write(&icon, [0u8, 0, 1, 0, 1, 0]).unwrap();
+        fs::write(root.join("favicon.ico"), "static").unwrap();
+
+        let mut server = Server::new();
+        server.set_document_root(&format!("{}/", root.display()));
+        assert!(!server.set_favicon(root.join("missing.ico")));
+        assert_eq!(
+            server.test_request("GET", "/favicon.ico").send().text(),
+            "static"
+        );
+
+        assert!(server.set_favicon(&icon));
+        let response = server.test_request("GET", "/favicon.ico").send();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.header("Content-Type"), Some("image/x-icon"));
+        assert_eq!(
+            response.header("Cache-Control"),
+            Some("public, max-age=604800")
+        );
+        assert_eq!(response.body(), &[0, 0, 1, 0, 1, 0]);
+        let etag = response.header("ETag").unwrap();
+        let revalidated = server
+            .test_request("GET", "/favicon.ico")
+            .header("If-None-Match", &format!("\"other\", W/{}", etag))
+            .send();
+        assert_eq!(revalidated.status(), 304);
+        assert!(revalidated.body().is_empty());
+        let head = server.test_request("HEAD", "/favicon.ico").send();
+        assert_eq!(head.status(), 200);
+        assert!(head.body().is_empty());
+
+        server.ignore_favicon(true);
+        let response = server.test_request("GET", "/favicon.ico").send();
+        assert_eq!(response.status(), 204);
+        assert!(response.body().is_empty());
+        server.ignore_favicon(false);
+        assert_eq!(
+            server.test_request("GET", "/favicon.ico").send().text(),
+            "static"
+        );
+
+        // Routes win
+        server.ignore_favicon(true);
+        server.get("/favicon.ico", |_request, mut response| {
+            let _ = response.write("route");
+        });
+        assert_eq!(
+            server.test_request("GET", "/favicon.ico").send().text(),
+            "route"
+        );
+    }
+
+    #[test]
+    fn test_static_ranges() {
+        let root = temp_document_root("static_ranges");
+        let content: Vec<u8> = (0..1000).map(|i| b"0123456789abcdef"[i % 16]).collect();
+        fs::write(root.join("fixture.txt"), &content).unwrap();
+        let mut server = Server::new();
+        server.set_document_root(&format!("{}/", root.display()));
+        let get = |range: &str| {
+            server
+                .test_request("GET", "/fixture.txt")
+                .header("Range", range)
+                .send()
+        };
+
+        let full = server.test_request("GET", "/fixture.txt").send();
+        assert_eq!(full.status(), 200);
+        assert_eq!(full.header("Accept-Ranges"), Some("bytes"));
+        let etag = full.header("ETag").unwrap().to_string();
+        let last_modified = full.header("Last-Modified").unwrap().to_string();
+
+        let single = get("bytes=-10");
+        assert_eq!(single.status(), 206);
+        assert_eq!(single.header("Content-Range"), Some("bytes 990-999/1000"));
+        assert_eq!(single.body(), &content[990..]);
+
+        let multi = get("bytes=200-299, 0-99");
+        assert_eq!(multi.status(), 206);
+        let content_type = multi.header("Content-Type").unwrap();
+        let boundary = content_type
+            .strip_prefix("multipart/byteranges; boundary=")
+            .unwrap();
+        let mut expected = Vec::new();
+        for (start, end) in [(0, 99), (200, 299)] {
+            expected.extend_from_slice(
+                format!(
+                    "\r\n--{}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Range: bytes {}-{}/1000\r\n\r\n",
+                    boundary, start, end
+                )
+                .as_bytes(),
+            );
+            expected.extend_from_slice(&content[start..=end]);
+        }
+        expected.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+        assert_eq!(multi.body(), &expected[..]);
+        assert_eq!(
+            multi.header("Content-Length"),
+            Some(expected.len().to_string().as_str())
+        );
+
+        // Overlapping ranges are merged into one
+        let merged = get("bytes=0-49,25-99");
+        assert_eq!(merged.header("Content-Range"), Some("bytes 0-99/1000"));
+        let unsatisfiable = get("bytes=1000-");
+        assert_eq!(unsatisfiable.status(), 416);
+        assert_eq!(unsatisfiable.header("Content-Range"), Some("bytes */1000"));
+        assert_eq!(get("bytes=99-0").status(), 200);
+        let too_many = vec!["0-0"; range::MAX_RANGES + 1].join(",");
+        assert_eq!(get(&format!("bytes={}", too_many)).status(), 200);
+
+        let if_range = |validator: &str| {
+            server
+                .test_request("GET", "/fixture.txt")
+                .header("Range", "bytes=0-9")
+                .header("If-Range", validator)
+                .send()
+        };
+        assert_eq!(if_range(&etag).status(), 206);
+        assert_eq!(if_range(&last_modified).status(), 206);
+        let changed = if_range("\"other\"");
+        assert_eq!(changed.status(), 200);
+        assert_eq!(changed.body(), &content[..]);
+        assert_eq!(if_range(&format!("W/{}", etag)).status(), 200);
+        assert_eq!(if_range("Sun, 06 Nov 1994 08:49:37 GMT").status(), 200);
+    }
+
+    #[test]
+    fn test_write_bytes() {
+        // Every byte value, most sequences of them are not UTF-8
+        let payload: Vec<u8> = (0..600u32).map(|i| (i * 151 % 256) as u8).collect();
+        assert!(str::from_utf8(&payload).is_err());
+        let mut server = Server::new();
+        let sent = payload.clone();
+        server.get("/payload.bin", move |_request, mut response| {
+            let _ = response.set_header("Content-Type", "application/octet-stream");
+            let _ = response.write_bytes(&sent[..300]);
+            let _ = response.write_bytes(&sent[300..]);
+        });
+        server.get("/empty/", |_request, mut response| {
+            let _ = response.set_status_code(204);
+            assert!(response.write_bytes(b"\xff").is_err());
+            assert!(response.write_bytes(b"").is_ok());
+        });
+        assert_eq!(server.test_request("GET", "/empty/").send().status(), 204);
+        let port = spawn_server(server);
+
+        let mut resp = reqwest::get(&format!("http://localhost:{}/payload.bin", port)).unwrap();
+        assert_eq!(resp.headers()["content-length"], "600");
+        let mut body = Vec::new();
+        resp.copy_to(&mut body).unwrap();
+        assert_eq!(body, payload);
+    }
+
+    #[test]
+    fn test_send_file() {
+        let root = temp_document_root("send_file");
+        let binary: Vec<u8> = (0..=255).cycle().take(100_000).collect();
+        fs::write(root.join("data.bin"), &binary).unwrap();
+        fs::write(root.join("report.conf"), "a = 1").unwrap();
+        let mut server = Server::new();
+        server.add_mime_type("conf", "text/x-conf");
+        let dir = root.clone();
+        server.get("/files/:name/", move |request, mut response| {
+            let name: String = request.path_param("name").unwrap();
+            let sent = match request.query("as") {
+                Some(download_name) => {
+                    response.send_file_as_attachment(dir.join(&name), download_name)
+                }
+                None => response.send_file(dir.join(&name)),
+            };
+            if let Err(e) = sent {
+                let _ = response.set_status_code(404);
+                let _ = response.write(&e.kind().to_string());
+            }
+        });
+        server.get("/written/", move |_request, mut response| {
+            let _ = response.write("x");
+            assert!(response.send_file(root.join("data.bin")).is_err());
+        });
+
+        let response = server.test_request("GET", "/files/report.conf/").send();
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response.header("Content-Type"),
+            Some("text/x-conf; charset=utf-8")
+        );
+        assert_eq!(response.header("Content-Disposition"), None);
+        assert_eq!(response.text(), "a = 1");
+        let response = server
+            .test_request("GET", "/files/report.conf/?as=Bericht%20%C3%BC.conf")
+            .send();
+        assert_eq!(
+            response.header("Content-Disposition"),
+            Some("attachment; filename=\"Bericht _.conf\"; filename*=UTF-8''Bericht%20%C3%BC.conf")
+        );
+        let response = server
+            .test_request("GET", "/files/missing.txt/?as=x.txt")
+            .send();
+        assert_eq!(response.status(), 404);
+        assert_eq!(response.header("Content-Disposition"), None);
+        assert_eq!(response.text(), "entity not found");
+        assert_eq!(server.test_request("GET", "/written/").send().text(), "x");
+
+        let port = spawn_server(server);
+        let mut resp = reqwest::get(&format!("http://localhost:{}/files/data.bin/", port)).unwrap();
+        assert_eq!(resp.headers()["content-type"], "application/octet-stream");
+        assert_eq!(resp.headers()["content-length"], "100000");
+        let mut body = Vec::new();
+        resp.copy_to(&mut body).unwrap();
+        assert_eq!(body, binary);
+        let head = raw_request(
+            port,
+            "HEAD /files/data.bin/ HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        );
+        assert!(head.contains("Content-Length: 100000\r\n"), "{}", head);
+        assert!(head.ends_with("\r\n\r\n"), "{}", head);
+    }
+
+    #[test]
+    fn test_static_head_and_unknown_methods() {
+        let root = temp_document_root("static_head");
+        fs::write(root.join("page.html"), "<p>hello</p>").unwrap();
+        fs::create_dir(root.join("listed")).unwrap();
+        fs::write(root.join("listed/a.txt"), "a").unwrap();
+        let mut server = Server::new();
+        server.set_document_root(&format!("{}/", root.display()));
+        server.use_index_of(true);
+        server.register(
+            "TRACE",
+            "/traced/",
+            Arc::new(|_, mut response: Response| {
+                let _ = response.write("traced");
+            }),
+        );
+
+        let get = server.test_request("GET", "/page.html").send();
+        let head = server.test_request("HEAD", "/page.html").send();
+        assert_eq!(head.status(), 200);
+        assert_eq!(head.header("Content-Length"), Some("12"));
+        assert_eq!(head.header("Content-Type"), get.header("Content-Type"));
+        assert_eq!(head.header("ETag"), get.header("ETag"));
+        assert_eq!(head.text(), "");
+
+        let listing = server.test_request("GET", "/listed/").send();
+        let head = server.test_request("HEAD", "/listed/").send();
+        assert_eq!(head.status(), 200);
+        assert_eq!(
+            head.header("Content-Length"),
+            Some(listing.text().len().to_string().as_str())
+        );
+        assert_eq!(head.text(), "");
+
+        let head = server.test_request("HEAD", "/missing.html").send();
+        assert_eq!(head.status(), 404);
+        assert_eq!(
+            head.header("Content-Length"),
+            Some(NOT_FOUND_PAGE.len().to_string().as_str())
+        );
+        assert_eq!(head.text(), "");
+
+        for method in ["TRACE", "FOO"] {
+            let response = server.test_request(method, "/page.html").send();
+            assert_eq!(response.status(), 501);
+            assert_eq!(response.text(), "");
+        }
+        // Routes may still answer methods the server does not know
+        assert_eq!(
+            server.test_request("TRACE", "/traced/").send().text(),
+            "traced"
+        );
+
+        let port = spawn_server(server);
+        let response = raw_request(port, "HEAD /page.html HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(response.contains("Content-Length: 12\r\n"), "{}", response);
+        assert!(response.ends_with("\r\n\r\n"), "{}", response);
+    }
+
+    #[test]
+    fn test_content_disposition() {
+        let root = temp_document_root("content_disposition");
+        for file in ["upload.HTML", "image.svg", "notes.txt", "report.pdf"] {
+            fs::write(root.join(file), "content").unwrap();
+        }
+        let mut server = Server::new();
+        server.set_document_root(&format!("{}/", root.display()));
+        server.force_download_extensions(&["html", ".svg", "pdf"]);
+        server.inline_extensions(&["pdf"]);
+        let headers = |path: &str| {
+            let response = server.test_request("GET", path).send();
+            assert_eq!(response.status(), 200);
+            (
+                response.header("Content-Disposition").map(String::from),
+                response.header("X-Content-Type-Options").map(String::from),
+            )
+        };
+        let attachment = |name: &str| {
+            (
+                Some(format!("attachment; filename=\"{}\"", name)),
+                Some(String::from("nosniff")),
+            )
+        };
+        assert_eq!(headers("/upload.HTML"), attachment("upload.HTML"));
+        assert_eq!(headers("/image.svg"), attachment("image.svg"));
+        assert_eq!(headers("/notes.txt"), (None, None));
+        assert_eq!(
+            headers("/report.pdf"),
+            (Some(String::from("inline; filename=\"report.pdf\"")), None)
+        );
+
+        assert_eq!(
+            content_disposition(Disposition::Attachment, "a \"b\"\\c.html"),
+            "attachment; filename=\"a \\\"b\\\"\\\\c.html\""
+        );
+        assert_eq!(
+            content_disposition(Disposition::Inline, "größe 1.svg"),
+            "inline; filename=\"gr__e 1.svg\"; filename*=UTF-8''gr%C3%B6%C3%9Fe%201.svg"
+        );
+    }
+
+    #[test]
+    fn test_swap_document_root() {
+        let release_a = temp_document_root("swap_document_root_a");
+        let release_b = temp_document_root("swap_document_root_b");
+        fs::write(release_a.join("version.txt"), "A").unwrap();
+        fs::write(release_b.join("version.txt"), "B").unwrap();
+
+        let mut server = Server::new();
+        server.set_document_root(&format!("{}/", release_a.display()));
+        let handle = server.handle();
+        let release = format!("{}/", release_b.display());
+        server.post("/activate/", move |_request, mut response| {
+            let activated =
+                handle.set_document_root(&release) && !handle.set_document_root("/does/not/exist/");
+            let _ = response.write(&activated.to_string());
+        });
+        let port = spawn_server(server);
+
+        let version = || {
+            reqwest::get(&format!("http://localhost:{}/version.txt", port))
+                .unwrap()
+                .text()
+                .unwrap()
+        };
+        assert_eq!(version(), "A");
+        let mut activated = reqwest::Client::new()
+            .post(&format!("http://localhost:{}/activate/", port))
+            .send()
+            .unwrap();
+        assert_eq!(activated.text().unwrap(), "true");
+        assert_eq!(version(), "B");
+    }
+
+    #[test]
+    fn test_status_for_file_error() {
+        let error = |kind| std::io::Error::new(kind, "open failed");
+        assert_eq!(
+            Server::status_for_file_error(&error(ErrorKind::NotFound)),
+            404
+        );
+        assert_eq!(
+            Server::status_for_file_error(&error(ErrorKind::PermissionDenied)),
+            403
+        );
+        assert_eq!(Server::status_for_file_error(&error(ErrorKind::Other)), 500);
+        assert_eq!(
+            Server::status_for_file_error(&error(ErrorKind::UnexpectedEof)),
+            500
+        );
+    }
+
+    #[test]
+    fn test_routes_frozen_after_start() {
+        let mut server = Server::new();
+        server.get("/early/", |_request, mut response| {
+            let _ = response.write("early");
+        });
+        let mut late = server.clone();
+        let port = spawn_server(server);
+
+        late.get("/late/", |_request, mut response| {
+            let _ = response.write("late");
+        });
+
+        let mut resp = reqwest::get(&format!("http://localhost:{}/early/", port)).unwrap();
+        assert_eq!(resp.text().unwrap(), "early");
+        let resp = reqwest::get(&format!("http://localhost:{}/late/", port)).unwrap();
+        assert_eq!(resp.status(), 404);
+        assert!(late.routes().iter().all(|route| route.pattern != "/late/"));
+    }
+
+    /// Starts two slow requests 50ms apart and returns both answers
+    fn two_slow_requests(port: u16) -> (String, String) {
+        let first = thread::spawn(move || {
+            raw_request(port, "GET /slow/ HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        });
+        thread::sleep(Duration::from_millis(50));
+        let second = raw_request(port, "GET /slow/ HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        (first.join().unwrap(), second)
+    }
+
+    fn slow_server() -> Server {
+        let mut server = Server::new();
+        server.get("/slow/", |_request, mut response| {
+            thread::sleep(Duration::from_millis(300));
+            let _ = response.write("done");
+        });
+        server.set_max_connections(1);
+        server
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let limited = || {
+            let mut server = Server::new();
+            server.get("/", |_request, mut response| {
+                let _ = response.write("ok");
+            });
+            server.set_rate_limit(60);
+            server
+        };
+        let port = spawn_server(limited());
+        let mut server = limited();
+        assert!(server.exempt_from_rate_limit("127.0.0.0/8"));
+        assert!(!server.exempt_from_rate_limit("localhost"));
+        let exempt_port = spawn_server(server);
+        let get = |port| raw_request(port, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+
+        for _ in 0..60 {
+            assert!(get(port).ends_with("\r\n\r\nok"));
+        }
+        let response = get(port);
+        assert!(
+            response.starts_with("HTTP/1.1 429 Too Many Requests\r\n"),
+            "{}",
+            response
+        );
+        assert!(response.contains("\r\nRetry-After: 1\r\n"), "{}", response);
+        assert!(
+            response.contains("\r\nConnection: close\r\n"),
+            "{}",
+            response
+        );
+        for _ in 0..100 {
+            assert!(get(exempt_port).ends_with("\r\n\r\nok"));
+        }
+        // A request per second is allowed again
+        thread::sleep(Duration::from_millis(1100));
+        assert!(get(port).ends_with("\r\n\r\nok"));
+        assert!(get(port).starts_with("HTTP/1.1 429 "));
+        // Requests without an address, like these, are not limited
+        let mut server = Server::new();
+        server.set_rate_limit(1);
+        for _ in 0..3 {
+            assert_eq!(server.test_request("GET", "/").send().status(), 404);
+        }
+    }
+
+    #[test]
+    fn test_max_connections_reject() {
+        let mut server = slow_server();
+        server.set_connection_limit_policy(ConnectionLimitPolicy::Reject);
+        let metrics = server.metrics();
+        let port = spawn_server(server);
+        // The probe connection of spawn_server holds the slot for a moment
+        thread::sleep(Duration::from_millis(50));
+
+        let (first, second) = two_slow_requests(port);
+        assert!(first.starts_with("HTTP/1.1 200 OK"));
+        assert_eq!(
+            second,
+            "HTTP/1.1 503 Service Unavailable\r\nRetry-After: 5\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+        );
+        assert_eq!(metrics.service_unavailable(), 1);
+    }
+
+    #[test]
+    fn test_max_connections_defer() {
+        let port = spawn_server(slow_server());
+        thread::sleep(Duration::from_millis(50));
+
+        let start = Instant::now();
+        let (first, second) = two_slow_requests(port);
+        assert!(first.ends_with("done"));
+        assert!(second.ends_with("done"));
+        assert!(start.elapsed() >= Duration::from_millis(600));
+    }
+
+    #[test]
+    fn test_read_timeout() {
+        let mut server = Server::new();
+        server.set_read_timeout(Duration::from_secs(20));
+        assert_eq!(server.request_timeouts.head, Duration::from_secs(20));
+        assert_eq!(server.request_timeouts.body, Duration::from_secs(20));
+    }
+
+    #[test]
+    fn test_worker_count() {
+        let mut server = Server::new();
+        assert!(server.workers() >= 8);
+        assert!(server.set_worker_count(3));
+        assert_eq!(server.workers(), 3);
+        assert!(!server.set_worker_count(0));
+        assert_eq!(server.workers(), 3);
+    }
+
+    #[cfg(not(feature = "tokio"))]
+    #[test]
+    fn test_worker_queue() {
+        let serve = |policy| {
+            let mut server = Server::new();
+            let metrics = server.metrics();
+            server.get("/slow/", move |_request, mut response| {
+                thread::sleep(Duration::from_millis(300));
+                let _ = response.write(&format!("busy {}", metrics.busy_workers()));
+            });
+            server.set_workers(1);
+            server.set_max_queued_connections(0);
+            server.set_connection_limit_policy(policy);
+            let port = spawn_server(server);
+            thread::sleep(Duration::from_millis(50));
+            port
+        };
+
+        let port = serve(ConnectionLimitPolicy::Reject);
+        let (first, second) = two_slow_requests(port);
+        assert!(first.ends_with("busy 1"));
+        assert!(second.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+
+        let port = serve(ConnectionLimitPolicy::Defer);
+        let start = Instant::now();
+        let (first, second) = two_slow_requests(port);
+        assert!(first.ends_with("busy 1"));
+        assert!(second.ends_with("busy 1"));
+        assert!(start.elapsed() >= Duration::from_millis(600));
+    }
+
+    /// Reads a single response that has a `Content-Length`, leaving the
+    /// connection open
+    fn read_delimited_response(stream: &mut TcpStream) -> String {
+        let mut response = Vec::new();
+        let mut byte = [0];
+        while !response.ends_with(b"\r\n\r\n") {
+            stream.read_exact(&mut byte).unwrap();
+            response.push(byte[0]);
+        }
+        let head = String::from_utf8(response).unwrap();
+        let len: usize = head
+            .lines()
+            .find_map(|line| line.strip_prefix("Content-Length: "))
+            .unwrap()
+            .parse()
+            .unwrap();
+        let mut body = vec![0; len];
+        stream.read_exact(&mut body).unwrap();
+        head + &String::from_utf8(body).unwrap()
+    }
+
+    #[test]
+    fn test_connection_close() {
+        let mut server = Server::new();
+        server.get("/", |_request, mut response| {
+            let _ = response.write("hello");
+        });
+        let port = spawn_server(server);
+
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream
+            .write_all(
+                b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n\
+                  GET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            )
+            .unwrap();
+        // Ends because the server closes, not because of the timeout
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert_eq!(response.matches("HTTP/1.1 200 OK").count(), 1);
+        assert!(response.contains("\r\nConnection: close\r\n"));
+        assert!(response.ends_with("hello"));
+    }
+
+    #[test]
+    fn test_http10_keep_alive() {
+        let mut server = Server::new();
+        server.get("/", |_request, mut response| {
+            let _ = response.write("hello");
+        });
+        let port = spawn_server(server);
+
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        for _ in 0..2 {
+            stream
+                .write_all(b"GET / HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n")
+                .unwrap();
+            let response = read_delimited_response(&mut stream);
+            assert!(response.contains("\r\nConnection: keep-alive\r\n"));
+            assert!(response.ends_with("hello"));
+        }
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let response = read_delimited_response(&mut stream);
+        assert!(!response.contains("Connection:"));
+
+        // Without asking, an HTTP/1.0 client gets a single response
+        stream.write_all(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.contains("\r\nConnection: close\r\n"));
+        assert!(response.ends_with("hello"));
+    }
+
+    #[test]
+    fn test_connection_id() {
+        let mut server = Server::new();
+        server.get("/", |request, mut response| {
+            let _ = response.write(&format!(
+                "{} {}",
+                request.connection_id(),
+                request.request_id()
+            ));
+        });
+        server.set_debug_dump(true);
+        let port = spawn_server(server);
+
+        let id = |response: &str, name: &str| -> String {
+            let prefix = format!("{}: ", name);
+            String::from(
+                response
+                    .lines()
+                    .find_map(|line| line.strip_prefix(prefix.as_str()))
+                    .unwrap(),
+            )
+        };
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut responses = Vec::new();
+        for _ in 0..2 {
+            stream
+                .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                .unwrap();
+            responses.push(read_delimited_response(&mut stream));
+        }
+        let (first, second) = (&responses[0], &responses[1]);
+        assert_eq!(id(first, "X-Connection-Id"), id(second, "X-Connection-Id"));
+        assert_ne!(id(first, "X-Request-Id"), id(second, "X-Request-Id"));
+        assert!(first.ends_with(&format!(
+            "{} {}",
+            id(first, "X-Connection-Id"),
+            id(first, "X-Request-Id")
+        )));
+
+        let other = raw_request(port, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert_ne!(id(&other, "X-Connection-Id"), id(first, "X-Connection-Id"));
+    }
+
+    #[test]
+    fn test_keep_alive_limits() {
+        let serve = |keep_alive| {
+            let mut server = Server::new();
+            server.get("/", |request, mut response| {
+                let _ = response.write(&request.remaining_requests().to_string());
+            });
+            assert!(server.set_keep_alive(keep_alive));
+            spawn_server(server)
+        };
+        let connect = |port| {
+            let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            stream
+        };
+        let request = b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let port = serve(KeepAlive {
+            idle_timeout: Duration::from_millis(300),
+            max_requests: 2,
+        });
+
+        let mut stream = connect(port);
+        stream.write_all(request).unwrap();
+        let first = read_delimited_response(&mut stream);
+        assert!(!first.contains("Connection:"));
+        assert!(first.ends_with("\r\n\r\n1"));
+        stream.write_all(request).unwrap();
+        let second = read_delimited_response(&mut stream);
+        assert!(second.contains("\r\nConnection: close\r\n"));
+        assert!(second.ends_with("\r\n\r\n0"));
+        assert_eq!(stream.read(&mut [0]).unwrap(), 0);
+
+        // Idle past the timeout, the connection is closed without a word
+        let mut stream = connect(port);
+        stream.write_all(request).unwrap();
+        read_delimited_response(&mut stream);
+        let idle = Instant::now();
+        assert_eq!(stream.read(&mut [0]).unwrap(), 0);
+        assert!(idle.elapsed() >= Duration::from_millis(250));
+
+        let port = serve(KeepAlive::disabled());
+        let response = raw_request(port, str::from_utf8(request).unwrap());
+        assert!(response.contains("\r\nConnection: close\r\n"));
+        assert!(response.ends_with("\r\n\r\n0"));
+
+        let mut server = Server::new();
+        assert!(!server.set_keep_alive(KeepAlive {
+            idle_timeout: Duration::from_secs(1),
+            max_requests: 0,
+        }));
+    }
+
+    #[test]
+    fn test_pipelined_requests() {
+        let mut server = Server::new();
+        server.get("/:name/", |request, mut response| {
+            let _ = response.write(request.path_param_str("name").unwrap_or(""));
+        });
+        server.post("/echo/", |request, mut response| {
+            let _ = response.write(&String::from_utf8_lossy(request.raw_body()));
+        });
+        let port = spawn_server(server);
+
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        // All three in a single write, each has to be told from the others
+        stream
+            .write_all(
+                b"GET /first/ HTTP/1.1\r\nHost: localhost\r\n\r\n\
+                  POST /echo/ HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\n\r\nbody\
+                  GET /third/ HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            )
+            .unwrap();
+        let first = read_delimited_response(&mut stream);
+        assert!(first.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(first.ends_with("\r\nContent-Length: 5\r\n\r\nfirst"));
+        let second = read_delimited_response(&mut stream);
+        assert!(second.ends_with("\r\nContent-Length: 4\r\n\r\nbody"));
+        let third = read_delimited_response(&mut stream);
+        assert!(third.contains("\r\nConnection: close\r\n"));
+        assert!(third.ends_with("\r\n\r\nthird"));
+        assert_eq!(stream.read(&mut [0]).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_slow_request_head() {
+        let handled = Arc::new(AtomicBool::new(false));
+        let mut server = Server::new();
+        let handler_ran = handled.clone();
+        server.get("/", move |_request, mut response| {
+            handler_ran.store(true, Ordering::SeqCst);
+            let _ = response.write("hello");
+        });
+        server.set_request_head_timeout(Duration::from_millis(300));
+        let metrics = server.metrics();
+        let port = spawn_server(server);
+
+        // Every byte arrives well within any per-read timeout, the head as a
+        // whole does not
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_millis(50)))
+            .unwrap();
+        let start = Instant::now();
+        let mut response = Vec::new();
+        let mut buffer = [0; 256];
+        for byte in b"GET / HTTP/1.1\r\nHost: localhost\r\nX-Slow: 1234567890"
+            .iter()
+            .cycle()
+        {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            if stream.write_all(&[*byte]).is_err() {
+                break;
+            }
+            // Waiting for the answer paces the bytes
+            match stream.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => {
+                    response.extend_from_slice(&buffer[..read]);
+                    break;
+                }
+                Err(_) => {}
+            }
+        }
+        stream.set_read_timeout(None).unwrap();
+        let _ = stream.read_to_end(&mut response);
+        let response = String::from_utf8(response).unwrap();
+        assert_eq!(response, REQUEST_TIMEOUT);
+        assert!(start.elapsed() >= Duration::from_millis(300));
+        assert!(!handled.load(Ordering::SeqCst));
+        assert_eq!(metrics.request_timeouts(), 1);
+
+        // Clients sending their request in one go are not affected
+        let response = raw_request(port, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(response.ends_with("hello"));
+        assert_eq!(metrics.request_timeouts(), 1);
+    }
+
+    #[test]
+    fn test_silent_clients_do_not_pin_workers() {
+        let mut server = Server::new();
+        server.set_workers(2);
+        server.set_request_head_timeout(Duration::from_millis(300));
+        server.get("/", |_request, mut response| {
+            let _ = response.write("hello");
+        });
+        let metrics = server.metrics();
+        let port = spawn_server(server);
+
+        // More connections that never send a byte than there are workers
+        let mut silent: Vec<TcpStream> = (0..4)
+            .map(|_| TcpStream::connect(("127.0.0.1", port)).unwrap())
+            .collect();
+        let start = Instant::now();
+        let response = raw_request(port, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(response.ends_with("hello"), "{}", response);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        for stream in &mut silent {
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            assert_eq!(response, REQUEST_TIMEOUT);
+        }
+        assert_eq!(metrics.request_timeouts(), 4);
+    }
+
+    // The tokio listener captures responses in memory, they are not streamed
+    #[cfg(not(feature = "tokio"))]
+    #[test]
+    fn test_write_timeout() {
+        let (finished, outcome) = mpsc::channel();
+        let mut server = Server::new();
+        server.set_write_timeout(Duration::from_millis(200));
+        server.get("/huge/", move |_request, mut response| {
+            let chunk = vec![b'x'; 64 * 1024];
+            let start = Instant::now();
+            // Far more than the socket buffers take
+            let written = (0..4096).find(|_| response.write_all(&chunk).is_err());
+            let _ = finished.send((written, start.elapsed()));
+        });
+        let port = spawn_server(server);
+
+        // Asks and never reads
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream
+            .write_all(b"GET /huge/ HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let (failed_at, took) = outcome.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert!(failed_at.is_some());
+        assert!(took < Duration::from_secs(5));
+
+        // The worker is free again
+        let mut resp = reqwest::get(&format!("http://localhost:{}/missing/", port)).unwrap();
+        assert_eq!(resp.status(), 404);
+        assert!(resp.text().is_ok());
+        drop(stream);
+    }
+
+    #[test]
+    fn test_split_absolute_form() {
+        assert_eq!(
+            split_absolute_form("http://example.com/path?a=1"),
+            Some(("example.com", "/path?a=1"))
+        );
+        assert_eq!(
+            split_absolute_form("HTTPS://example.com:8443"),
+            Some(("example.com:8443", ""))
+        );
+        assert_eq!(
+            split_absolute_form("http://example.com?a=1"),
+            Some(("example.com", "?a=1"))
+        );
+        assert_eq!(split_absolute_form("/path?next=http://example.com/"), None);
+        assert_eq!(split_absolute_form("ftp://example.com/file"), None);
+        assert_eq!(split_absolute_form("*"), None);
+
+        let head = RequestHead::parse(b"GET http://example.com?a=1 HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(head.authority, Some("example.com"));
+        assert_eq!(head.target, "/?a=1");
+        let head = RequestHead::parse(b"GET /plain HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(head.authority, None);
+        assert_eq!(head.target, "/plain");
+    }
+
+    #[test]
+    fn test_absolute_form_targets() {
+        let mut server = Server::new();
+        server.get("/hello/", |request, mut response| {
+            let _ = response.write(&format!(
+                "{} {} {}",
+                request.host().unwrap_or("-"),
+                request.path,
+                request.raw_query().unwrap_or("-")
+            ));
+        });
+        let port = spawn_server(server);
+        let body = |response: String| String::from(response.split("\r\n\r\n").nth(1).unwrap());
+
+        let response = raw_request(
+            port,
+            &format!(
+                "GET http://localhost:{}/hello/?a=1 HTTP/1.1\r\nHost: localhost:{}\r\n\r\n",
+                port, port
+            ),
+        );
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert_eq!(body(response), format!("localhost:{} /hello/ a=1", port));
+        // The authority stands in for a missing Host header
+        let response = raw_request(port, "GET HTTP://example.com/hello/ HTTP/1.1\r\n\r\n");
+        assert_eq!(body(response), "example.com /hello/ -");
+        // Anything but the path is no part of the route lookup
+        let response = raw_request(port, "GET http://example.com/missing HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404"));
+
+        let response = raw_request(
+            port,
+            "GET http://example.com/hello/ HTTP/1.1\r\nHost: other.example\r\n\r\n",
+        );
+        assert_eq!(response, BAD_REQUEST);
+        let response = raw_request(
+            port,
+            "CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\n\r\n",
+        );
+        assert_eq!(response, NOT_IMPLEMENTED);
+    }
+
+    #[test]
+    fn test_host_header_required() {
+        let mut server = Server::new();
+        server.get("/", |_request, mut response| {
+            let _ = response.write("hello");
+        });
+        let port = spawn_server(server);
+
+        assert_eq!(raw_request(port, "GET / HTTP/1.1\r\n\r\n"), BAD_REQUEST);
+        assert_eq!(
+            raw_request(
+                port,
+                "GET / HTTP/1.1\r\nHost: a.example\r\nHost: b.example\r\n\r\n"
+            ),
+            BAD_REQUEST
+        );
+        assert!(raw_request(port, "GET / HTTP/1.1\r\nHost: a.example\r\n\r\n").ends_with("hello"));
+        // HTTP/1.0 predates the header
+        assert!(raw_request(port, "GET / HTTP/1.0\r\n\r\n").ends_with("hello"));
+    }
+
+    #[test]
+    fn test_strict_parsing() {
+        let violations = [
+            (
+                "obs-folded header line",
+                "POST / HTTP/1.1\r\nHost: localhost\r\nX-Long: a\r\n b\r\n\r\n",
+            ),
+            (
+                "whitespace between header name and colon",
+                "POST / HTTP/1.1\r\nHost: localhost\r\nX-Name : a\r\n\r\n",
+            ),
+            (
+                "Content-Length headers with differing values",
+                "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 3\r\nContent-Length: 5\r\n\r\na=1",
+            ),
+            (
+                "both Content-Length and Transfer-Encoding",
+                "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\na=1",
+            ),
+            (
+                "NUL in the request head",
+                "POST / HTTP/1.1\r\nHost: localhost\r\nX-Name: a\0b\r\n\r\n",
+            ),
+            (
+                "non-ASCII header name",
+                "POST / HTTP/1.1\r\nHost: localhost\r\nX-N\u{e4}me: a\r\n\r\n",
+            ),
+        ];
+        let root = temp_document_root("strict_parsing");
+        let log = root.join("server.log");
+        let strict_server = |strict: bool| {
+            let mut server = Server::new();
+            server.set_logger(&log.to_string_lossy());
+            server.set_strict_parsing(strict);
+            server.post("/", |_request, mut response| {
+                let _ = response.write("ok");
+            });
+            spawn_server(server)
+        };
+        let lenient = strict_server(false);
+        let strict = strict_server(true);
+
+        for (reason, request) in violations {
+            if reason == "Content-Length headers with differing values" {
+                assert_eq!(raw_request(lenient, request), BAD_REQUEST);
+            } else {
+                assert!(raw_request(lenient, request).ends_with("ok"), "{}", reason);
+            }
+            assert_eq!(raw_request(strict, request), BAD_REQUEST, "{}", reason);
+            let contents = fs::read_to_string(&log).unwrap();
+            assert!(
+                contents.contains(&format!(" has {}\n", reason)),
+                "{}",
+                reason
+            );
+        }
+        // Repeating the same length is fine
+        let repeated = "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 3\r\ncontent-length: 3\r\n\r\na=1";
+        assert!(raw_request(strict, repeated).ends_with("ok"));
+    }
+
+    #[test]
+    fn test_request_smuggling() {
+        let mut server = Server::new();
+        server.post("/a/", |mut request, mut response| {
+            let mut body = String::new();
+            let _ = request
+                .body_reader()
+                .and_then(|mut reader| reader.read_to_string(&mut body));
+            let _ = response.write(&format!("body {:?}", body));
+        });
+        server.get("/b/", |_request, mut response| {
+            let _ = response.write("smuggled");
+        });
+        let port = spawn_server(server);
+        let smuggled = "GET /b/ HTTP/1.1\r\nHost: localhost\r\n\r\n";
+
+        // Chunked wins over the length, the connection closes after it
+        let body = format!("0\r\n\r\n{}", smuggled);
+        let both = format!(
+            "POST /a/ HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\
+             Transfer-Encoding: chunked\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let response = raw_request(port, &both);
+        assert_eq!(response.matches("HTTP/1.1 ").count(), 1, "{}", response);
+        assert!(response.contains("Connection: close\r\n"), "{}", response);
+        assert!(response.ends_with("body \"\""), "{}", response);
+
+        let differing = format!(
+            "POST /a/ HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\n\
+             Content-Length: {}\r\n\r\n{}",
+            smuggled.len(),
+            smuggled
+        );
+        assert_eq!(raw_request(port, &differing), BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_canonical_host() {
+        let mut server = Server::new();
+        server.get("/", |_request, mut response| {
+            let _ = response.write("hello");
+        });
+        assert!(!server.set_canonical_host("www.example.com/path"));
+        assert!(!server.set_canonical_host("www.example.com:http"));
+        assert!(server.set_canonical_host("WWW.example.com"));
+        let port = spawn_server(server);
+
+        let location = |host: &str, target: &str| {
+            let response = raw_request(
+                port,
+                &format!("GET {} HTTP/1.1\r\nHost: {}\r\n\r\n", target, host),
+            );
+            assert!(response.starts_with("HTTP/1.1 301 Moved Permanently\r\n"));
+            let line = response
+                .lines()
+                .find(|line| line.starts_with("Location: "))
+                .unwrap();
+            String::from(&line["Location: ".len()..])
+        };
+        assert_eq!(
+            location("example.com", "/docs/?page=2"),
+            "http://www.example.com/docs/?page=2"
+        );
+        assert_eq!(
+            location(&format!("127.0.0.1:{}", port), "/"),
+            "http://www.example.com/"
+        );
+        assert_eq!(
+            location(&format!("www.example.com:{}", port), "/"),
+            "http://www.example.com/"
+        );
+
+        let response = raw_request(port, "GET / HTTP/1.1\r\nHost: www.Example.com\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with("hello"));
+        let response = raw_request(port, "GET / HTTP/1.1\r\nHost: www.example.com:80\r\n\r\n");
+        assert!(response.ends_with("hello"));
+    }
+
+    #[test]
+    fn test_body_size() {
+        let mut server = Server::new();
+        server.post("/form/", |request, mut response| {
+            let _ = response.write(&format!(
+                "{} {}",
+                request.get_post_parameters().len(),
+                request.form("last").unwrap_or("")
+            ));
+        });
+        server.post("/upload/", |mut request, mut response| {
+            let mut body = Vec::new();
+            let read = request
+                .body_reader()
+                .and_then(|mut reader| reader.read_to_end(&mut body));
+            let _ = match read {
+                Ok(len) => response.write(&format!("{} bytes", len)),
+                Err(e) => response.write(&e.to_string()),
+            };
+        });
+        server.set_max_body_size(200 * 1024);
+        let port = spawn_server(server);
+
+        // About 100 KB, far more than arrives together with the head
+        let mut fields: Vec<(String, String)> = (0..1000)
+            .map(|i| (format!("field{}", i), "x".repeat(90)))
+            .collect();
+        fields.push((String::from("last"), String::from("end of form")));
+        let mut response = reqwest::Client::new()
+            .post(&format!("http://localhost:{}/form/", port))
+            .form(&fields)
+            .send()
+            .unwrap();
+        assert_eq!(response.text().unwrap(), "1001 end of form");
+
+        let request = |path: &str, headers: &str, body: &str| {
+            raw_request(
+                port,
+                &format!(
+                    "POST {} HTTP/1.1\r\nHost: localhost\r\n{}\r\n\r\n{}",
+                    path, headers, body
+                ),
+            )
+        };
+        assert_eq!(
+            request("/form/", "Content-Length: 300000", ""),
+            PAYLOAD_TOO_LARGE
+        );
+        let chunked = "Transfer-Encoding: chunked\r\nConnection: close";
+        let response = request(
+            "/form/",
+            &format!(
+                "Content-Type: application/x-www-form-urlencoded\r\n{}",
+                chunked
+            ),
+            "40000\r\na=1",
+        );
+        assert!(response.starts_with("HTTP/1.1 413 Content Too Large\r\n"));
+        let response = request("/upload/", chunked, "3\r\nabc\r\n40000\r\n");
+        assert!(response.ends_with("body larger than 204800 bytes"));
+        let response = request("/upload/", chunked, "3\r\nabc\r\n0\r\n\r\n");
+        assert!(response.ends_with("3 bytes"));
+    }
+
+    #[test]
+    fn test_header_limits() {
+        let handled = Arc::new(AtomicBool::new(false));
+        let mut server = Server::new();
+        let handler_ran = handled.clone();
+        server.get("/", move |_request, mut response| {
+            handler_ran.store(true, Ordering::SeqCst);
+            let _ = response.write("hello");
+        });
+        server.set_max_header_count(10);
+        server.set_max_header_line_length(200);
+        server.set_max_header_bytes(1000);
+        let port = spawn_server(server);
+
+        let request = |headers: &[String]| {
+            let mut request = String::from("GET / HTTP/1.1\r\nHost: localhost\r\n");
+            for header in headers {
+                request.push_str(header);
+                request.push_str("\r\n");
+            }
+            request.push_str("\r\n");
+            raw_request(port, &request)
+        };
+        let many: Vec<_> = (0..10).map(|i| format!("X-{}: {}", i, i)).collect();
+        assert_eq!(request(&many), HEADERS_TOO_LARGE);
+        let long = [format!("X-Long: {}", "a".repeat(200))];
+        assert_eq!(request(&long), HEADERS_TOO_LARGE);
+        let large: Vec<_> = (0..9)
+            .map(|i| format!("X-{}: {}", i, "a".repeat(150)))
+            .collect();
+        assert_eq!(request(&large), HEADERS_TOO_LARGE);
+        // An oversized head is rejected without waiting for its end
+        let endless = format!("GET / HTTP/1.1\r\nX-Long: {}", "a".repeat(300));
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream.write_all(endless.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert_eq!(response, HEADERS_TOO_LARGE);
+        assert!(!handled.load(Ordering::SeqCst));
+
+        // Requests right at the limits are handled
+        assert!(request(&many[..9]).ends_with("hello"));
+        assert!(request(&[format!("X-Long: {}", "a".repeat(192))]).ends_with("hello"));
+        assert!(handled.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_slow_request_body() {
+        let mut server = Server::new();
+        server.post("/", |_request, mut response| {
+            let _ = response.write("handled");
+        });
+        server.set_request_body_timeout(Duration::from_millis(200));
+        let metrics = server.metrics();
+        let port = spawn_server(server);
+
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream
+            .write_all(b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 10\r\n\r\na=1")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert_eq!(response, REQUEST_TIMEOUT);
+        assert_eq!(metrics.request_timeouts(), 1);
+    }
+
+    #[test]
+    fn test_client_keeps_alive() {
+        let keeps_alive = |version, connection: &[&str]| {
+            let headers = connection
+                .iter()
+                .map(|value| (String::from("Connection"), String::from(*value)))
+                .collect();
+            client_keeps_alive(version, &headers)
+        };
+        assert!(keeps_alive("HTTP/1.1", &[]));
+        assert!(keeps_alive("HTTP/1.1", &["keep-alive"]));
+        assert!(!keeps_alive("HTTP/1.1", &["Close"]));
+        assert!(!keeps_alive("HTTP/1.1", &["Upgrade", "upgrade, close"]));
+        assert!(!keeps_alive("HTTP/1.0", &[]));
+        assert!(keeps_alive("HTTP/1.0", &["Keep-Alive"]));
+        assert!(!keeps_alive("HTTP/1.0", &["keep-alive, close"]));
+        assert!(!keeps_alive("", &["keep-alive"]));
+    }
+
+    #[test]
+    fn test_hop_by_hop_request_headers() {
+        let mut server = Server::new();
+        server.get("/", |request, mut response| {
+            let names: Vec<&str> = request.headers().iter().map(|(name, _)| name).collect();
+            let _ = response.write(&names.join(","));
+        });
+        let response = server
+            .test_request("GET", "/")
+            .header("Connection", "Keep-Alive, X-Trace")
+            .header("Keep-Alive", "timeout=5")
+            .header("X-Trace", "1")
+            .header("Accept", "*/*")
+            .send();
+        assert_eq!(response.text(), "Host,Connection,Accept");
+        // Test requests are not connections that could stay open
+        assert_eq!(response.header("Connection"), Some("close"));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn test_idle_keep_alive_connections() {
+        let mut server = Server::new();
+        server.get("/", |_request, mut response| {
+            let _ = response.write("hello");
+        });
+        let port = spawn_server(server);
+
+        let request = b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        // Far more idle connections than there are worker threads
+        let mut idle: Vec<TcpStream> = (0..5000)
+            .map(|_| {
+                let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+                stream.write_all(request).unwrap();
+                assert!(read_delimited_response(&mut stream).ends_with("hello"));
+                stream
+            })
+            .collect();
+
+        let start = Instant::now();
+        for _ in 0..20 {
+            let mut resp = reqwest::get(&format!("http://localhost:{}/", port)).unwrap();
+            assert_eq!(resp.text().unwrap(), "hello");
+        }
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        let stream = &mut idle[1234];
+        stream.write_all(request).unwrap();
+        assert!(read_delimited_response(stream).ends_with("hello"));
+    }
+
+    #[test]
+    fn test_pooled_buffers_do_not_leak() {
+        let mut server = Server::new();
+        server.post("/echo/", |request, mut response| {
+            let _ = response.write(request.form("a").unwrap_or("missing"));
+        });
+        let port = spawn_server(server);
+
+        let post = |body: &str| {
+            raw_request(
+                port,
+                &format!(
+                    "POST /echo/ HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: {}\r\n\r\n{}",
+                    body.len(),
+                    body
+                ),
+            )
+        };
+        // More rounds than workers so every worker reuses a buffer
+        for _ in 0..20 {
+            let long = post(&format!("a={}&b=2", "x".repeat(500)));
+            assert!(long.ends_with(&"x".repeat(500)));
+            let short = post("a=y");
+            assert!(short.ends_with("\r\n\r\ny"));
+        }
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn test_tls() {
+        let (cert_path, key_path) = crate::tls::write_self_signed("server");
+        let mut server = Server::new();
+        server.get("/secure/", |_request, mut response| {
+            let _ = response.write("encrypted");
+        });
+        server.set_tls(TlsConfig::from_pem_files(&cert_path, key_path).unwrap());
+        let port = spawn_server(server);
+
+        let client = reqwest::Client::builder()
+            .danger_accept_invalid_certs(true)
+            .build()
+            .unwrap();
+        let mut resp = client
+            .get(&format!("https://localhost:{}/secure/", port))
+            .send()
+            .unwrap();
+        assert_eq!(resp.text().unwrap(), "encrypted");
+
+        // Plain HTTP fails the handshake and never reaches the handler
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream
+            .write_all(b"GET /secure/ HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response);
+        assert!(!String::from_utf8_lossy(&response).contains("encrypted"));
+
+        // The worker survived the failed handshake, and a client trusting the
+        // certificate itself gets through
+        let trusted = reqwest::Certificate::from_pem(&fs::read(&cert_path).unwrap()).unwrap();
+        let client = reqwest::Client::builder()
+            .add_root_certificate(trusted)
+            .build()
+            .unwrap();
+        let mut resp = client
+            .get(&format!("https://localhost:{}/secure/", port))
+            .send()
+            .unwrap();
+        assert_eq!(resp.text().unwrap(), "encrypted");
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn test_tls_keep_alive() {
+        use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
+
+        let (cert_path, key_path) = crate::tls::write_self_signed("keep_alive");
+        let mut server = Server::new();
+        server.get("/", |request, mut response| {
+            let _ = response.write(&format!("connection {}", request.connection_id()));
+        });
+        server.set_tls(TlsConfig::from_pem_files(&cert_path, key_path).unwrap());
+        let port = spawn_server(server);
+
+        let mut builder = SslConnector::builder(SslMethod::tls()).unwrap();
+        builder.set_verify(SslVerifyMode::NONE);
+        let tcp = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let mut stream = builder.build().connect("localhost", tcp).unwrap();
+        let read_response = |stream: &mut openssl::ssl::SslStream<TcpStream>| {
+            let mut response = Vec::new();
+            let mut byte = [0];
+            while !response.ends_with(b"\r\n\r\n") {
+                stream.read_exact(&mut byte).unwrap();
+                response.push(byte[0]);
+            }
+            let head = String::from_utf8(response).unwrap();
+            let len: usize = head
+                .lines()
+                .find_map(|line| line.strip_prefix("Content-Length: "))
+                .unwrap()
+                .parse()
+                .unwrap();
+            let mut body = vec![0; len];
+            stream.read_exact(&mut body).unwrap();
+            (head, String::from_utf8(body).unwrap())
+        };
+
+        // Both requests are answered over the one handshake, the second of
+        // two pipelined ones as well
+        let request = "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        stream.write_all(request.as_bytes()).unwrap();
+        let (head, first) = read_response(&mut stream);
+        assert!(!head.contains("Connection: close"), "{}", head);
+        stream.write_all(request.repeat(2).as_bytes()).unwrap();
+        assert_eq!(read_response(&mut stream).1, first);
+        assert_eq!(read_response(&mut stream).1, first);
+    }
+
+    #[cfg(feature = "native-tls")]
+    #[test]
+    fn test_native_tls() {
+        let identity = crate::tls::write_self_signed_pkcs12("server");
+        let mut server = Server::new();
+        server.get("/secure/", |_request, mut response| {
+            let _ = response.write("encrypted");
+        });
+        server.set_tls(TlsConfig::from_pkcs12_file(identity, "secret").unwrap());
+        let port = spawn_server(server);
+
+        let client = reqwest::Client::builder()
+            .danger_accept_invalid_certs(true)
+            .build()
+            .unwrap();
+        let mut resp = client
+            .get(&format!("https://localhost:{}/secure/", port))
+            .send()
+            .unwrap();
+        assert_eq!(resp.text().unwrap(), "encrypted");
+
+        // Plain HTTP fails the handshake and never reaches the handler
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream
+            .write_all(b"GET /secure/ HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response);
+        assert!(!String::from_utf8_lossy(&response).contains("encrypted"));
+    }
+
+    /// Connects over TLS, optionally sending `hostname` via SNI, and returns
+    /// the DNS name of the leaf certificate and the response body
+    #[cfg(feature = "tls")]
+    fn sni_request(port: u16, hostname: Option<&str>) -> Result<(String, String), String> {
+        use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
+
+        let mut builder = SslConnector::builder(SslMethod::tls()).unwrap();
+        builder.set_verify(SslVerifyMode::NONE);
+        let mut config = builder.build().configure().unwrap();
+        config.set_use_server_name_indication(hostname.is_some());
+        config.set_verify_hostname(false);
+        let tcp = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let mut stream = config
+            .connect(hostname.unwrap_or("localhost"), tcp)
+            .map_err(|e| e.to_string())?;
+
+        let leaf = stream.ssl().peer_certificate().unwrap();
+        let names = leaf.subject_alt_names().unwrap();
+        let name = String::from(names.iter().next().unwrap().dnsname().unwrap());
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response);
+        let body = response.split("\r\n\r\n").nth(1).unwrap_or("").to_string();
+        Ok((name, body))
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn test_tls_sni() {
+        let (a_cert, a_key) = crate::tls::write_self_signed_for("sni_a", "a.example.com");
+        let (b_cert, b_key) = crate::tls::write_self_signed_for("sni_b", "b.example.com");
+        let config = || {
+            TlsConfig::new()
+                .add_cert("a.example.com", &a_cert, &a_key)
+                .add_cert("B.example.com", &b_cert, &b_key)
+                .default_cert(&a_cert, &a_key)
+        };
+        let sni_server = |config: TlsConfigBuilder| {
+            let mut server = Server::new();
+            server.get("/", |request, mut response| {
+                let _ = response.write(request.sni_hostname().unwrap_or("none"));
+            });
+            server.set_tls(config.build().unwrap());
+            server
+        };
+        let port = spawn_server(sni_server(config()));
+        let strict_port = spawn_server(sni_server(config().reject_unknown_hostnames(true)));
+
+        let expect = |name: &str, body: &str| Ok((String::from(name), String::from(body)));
+        assert_eq!(
+            sni_request(port, Some("a.example.com")),
+            expect("a.example.com", "a.example.com")
+        );
+        assert_eq!(
+            sni_request(port, Some("b.example.com")),
+            expect("b.example.com", "b.example.com")
+        );
+        assert_eq!(
+            sni_request(port, Some("c.example.com")),
+            expect("a.example.com", "c.example.com")
+        );
+        assert_eq!(sni_request(port, None), expect("a.example.com", "none"));
+
+        assert!(sni_request(strict_port, Some("c.example.com")).is_err());
+        assert_eq!(
+            sni_request(strict_port, Some("b.example.com")),
+            expect("b.example.com", "b.example.com")
+        );
+    }
+
+    /// Connects over TLS, optionally presenting a client certificate, and
+    /// returns the response body
+    #[cfg(feature = "tls")]
+    fn client_cert_request(port: u16, client: Option<&(PathBuf, PathBuf)>) -> Option<String> {
+        use openssl::ssl::{SslConnector, SslFiletype, SslMethod, SslVerifyMode};
+
+        let mut builder = SslConnector::builder(SslMethod::tls()).unwrap();
+        builder.set_verify(SslVerifyMode::NONE);
+        if let Some((cert, key)) = client {
+            builder.set_certificate_chain_file(cert).unwrap();
+            builder.set_private_key_file(key, SslFiletype::PEM).unwrap();
+        }
+        let tcp = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        // With TLS 1.3 a refused certificate may only show once reading
+        let mut stream = builder.build().connect("localhost", tcp).ok()?;
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .ok()?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).ok()?;
+        response.split("\r\n\r\n").nth(1).map(String::from)
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn test_client_certificates() {
+        let (cert, key) = crate::tls::write_self_signed("client_certificates");
+        let files = crate::tls::write_client_certs("client_certificates");
+        let client_cert_server = |config: TlsConfigBuilder| {
+            let mut server = Server::new();
+            server.get("/", |request, mut response| {
+                let body = match request.client_certificate() {
+                    Some(cert) => format!("{} {}", cert.subject, cert.subject_alt_names.join(",")),
+                    None => String::from("anonymous"),
+                };
+                let _ = response.write(&body);
+            });
+            server.set_tls(config.build().unwrap());
+            server
+        };
+        let config = || TlsConfig::new().default_cert(&cert, &key);
+        let port = spawn_server(client_cert_server(config().require_client_cert(&files.ca)));
+        let optional_port =
+            spawn_server(client_cert_server(config().request_client_cert(&files.ca)));
+
+        let admin = Some(String::from("CN=admin admin.example.com"));
+        assert_eq!(client_cert_request(port, Some(&files.valid)), admin);
+        assert_eq!(client_cert_request(port, Some(&files.untrusted)), None);
+        assert_eq!(client_cert_request(port, None), None);
+
+        assert_eq!(
+            client_cert_request(optional_port, Some(&files.valid)),
+            admin
+        );
+        assert_eq!(
+            client_cert_request(optional_port, Some(&files.untrusted)),
+            None
+        );
+        assert_eq!(
+            client_cert_request(optional_port, None),
+            Some(String::from("anonymous"))
+        );
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn test_tls_reload() {
+        let (cert, key) = crate::tls::write_self_signed_for("tls_reload", "old.example.com");
+        let tls = TlsConfig::from_pem_files(&cert, &key).unwrap();
+        let mut server = Server::new();
+        server.get("/", |_request, _response| {});
+        server.set_tls(tls.clone());
+        let port = spawn_server(server);
+        let leaf = || sni_request(port, None).unwrap().0;
+        assert_eq!(leaf(), "old.example.com");
+
+        // A broken key keeps the old certificate
+        fs::write(&key, "broken").unwrap();
+        assert!(tls.reload().is_err());
+        assert_eq!(leaf(), "old.example.com");
+
+        crate::tls::write_self_signed_for("tls_reload", "new.example.com");
+        tls.reload().unwrap();
+        assert_eq!(leaf(), "new.example.com");
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn test_tls_reload_interval() {
+        let (cert, key) =
+            crate::tls::write_self_signed_for("tls_reload_interval", "old.example.com");
+        let mut server = Server::new();
+        server.get("/", |_request, _response| {});
+        server.set_tls(TlsConfig::from_pem_files(cert, key).unwrap());
+        server.set_tls_reload_interval(Duration::from_millis(20));
+        let port = spawn_server(server);
+        assert_eq!(sni_request(port, None).unwrap().0, "old.example.com");
+
+        crate::tls::write_self_signed_for("tls_reload_interval", "new.example.com");
+        let started = Instant::now();
+        while sni_request(port, None).unwrap().0 != "new.example.com" {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    #[test]
+    fn test_https_location() {
+        let location = |request: &str, authority| {
+            let head = RequestHead::parse(request.as_bytes()).unwrap();
+            Server::https_location(&head, authority)
+        };
+        assert_eq!(
+            location(
+                "GET /a/b?c=d#e HTTP/1.1\r\nHost: example.com:80\r\n\r\n",
+                None
+            ),
+            Some(String::from("https://example.com/a/b?c=d"))
+        );
+        assert_eq!(
+            location("GET / HTTP/1.1\r\nHost: [::1]:8080\r\n\r\n", None),
+            Some(String::from("https://[::1]/"))
+        );
+        assert_eq!(
+            location(
+                "GET /x HTTP/1.1\r\nHost: evil.com\r\n\r\n",
+                Some("example.com:8443")
+            ),
+            Some(String::from("https://example.com:8443/x"))
+        );
+        assert_eq!(location("GET / HTTP/1.1\r\n\r\n", None), None);
+        assert_eq!(
+            location("GET / HTTP/1.1\r\nHost: a.com/evil\r\n\r\n", None),
+            None
+        );
+        assert_eq!(
+            location("GET http://a.com/x HTTP/1.1\r\nHost: a.com\r\n\r\n", None),
+            Some(String::from("https://a.com/x"))
+        );
+        assert_eq!(
+            location("GET http://a.com:80/x?y HTTP/1.1\r\n\r\n", None),
+            Some(String::from("https://a.com/x?y"))
+        );
+    }
+
+    #[test]
+    fn test_redirect_http_to_https() {
+        let root = temp_document_root("redirect_http_to_https");
+        fs::write(root.join("secret.txt"), "secret content").unwrap();
+
+        let mut server = Server::new();
+        server.set_document_root(&format!("{}/", root.display()));
+        server.redirect_http_to_https(7901, Some("example.com:8443"));
+        // The redirect listener is bound before the server is ready
+        spawn_server(server);
+
+        let resp = no_redirect_client()
+            .get("http://localhost:7901/secret.txt?x=1")
+            .send()
+            .unwrap();
+        assert_eq!(resp.status(), 301);
+        assert_eq!(
+            resp.headers()["location"],
+            "https://example.com:8443/secret.txt?x=1"
+        );
+        let response = raw_request(7901, "GET /secret.txt HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 301 Moved Permanently"));
+        assert!(!response.contains("secret content"));
+    }
+
+    fn peer_addr_server() -> Server {
+        let mut server = Server::new();
+        server.get("/", |request, mut response| {
+            let peer = match request.peer_addr() {
+                Some(peer) => peer.to_string(),
+                None => String::from("unknown"),
+            };
+            let _ = response.write(&peer);
+        });
+        server
+    }
+
+    #[test]
+    fn test_peer_addr() {
+        let port = spawn_server(peer_addr_server());
+        let response = raw_request(port, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(response.contains("\r\n\r\n127.0.0.1:"));
+
+        let mut request = Request::new();
+        assert_eq!(request.remote_addr().to_string(), "0.0.0.0:0");
+        request.peer_addr = Some(SocketAddr::from(([192, 0, 2, 1], 56324)));
+        assert_eq!(request.remote_addr().to_string(), "192.0.2.1:56324");
+    }
+
+    #[test]
+    fn test_proxy_protocol() {
+        let mut server = peer_addr_server();
+        server.enable_proxy_protocol(true);
+        let port = spawn_server(server);
+        let body = |response: String| String::from(response.split("\r\n\r\n").nth(1).unwrap());
+
+        let response = raw_request(
+            port,
+            "PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nGET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        );
+        assert_eq!(body(response), "192.0.2.1:56324");
+
+        let mut v2 = b"\r\n\r\n\0\r\nQUIT\n\x21\x21\x00\x24".to_vec();
+        v2.extend_from_slice(
+            &"2001:db8::1"
+                .parse::<std::net::Ipv6Addr>()
+                .unwrap()
+                .octets(),
+        );
+        v2.extend_from_slice(&[0; 16]);
+        v2.extend_from_slice(&[0x0f, 0xa0, 0x01, 0xbb]);
+        v2.extend_from_slice(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert_eq!(body(raw_bytes_request(port, &v2)), "[2001:db8::1]:4000");
+
+        // LOCAL health checks keep the proxy's address
+        let local =
+            b"\r\n\r\n\0\r\nQUIT\n\x20\x00\x00\x00GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        assert!(body(raw_bytes_request(port, local)).starts_with("127.0.0.1:"));
+
+        for request in &[
+            "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            "PROXY TCP4 192.0.2.1 nonsense\r\nGET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        ] {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            stream.write_all(request.as_bytes()).unwrap();
+            let mut response = String::new();
+            let _ = stream.read_to_string(&mut response);
+            assert_eq!(response, "");
+        }
+    }
+
+    fn forwarding_server(proxy: &str) -> Server {
+        let mut server = Server::new();
+        server.trust_proxy(proxy);
+        server.enable_hsts(HstsConfig {
+            max_age: Duration::from_secs(600),
+            include_subdomains: false,
+            preload: false,
+        });
+        server.get("/", |request, mut response| {
+            let _ = response.write(&format!(
+                "{} {}",
+                request.scheme(),
+                request.host().unwrap_or("none")
+            ));
+        });
+        server.get("/logout/", |request, mut response| {
+            let _ = response.see_other(&request.absolute_url("/login/").unwrap());
+        });
+        server
+    }
+
+    #[test]
+    fn test_trusted_proxy() {
+        let port = spawn_server(forwarding_server("127.0.0.0/8"));
+        let untrusted_port = spawn_server(forwarding_server("10.0.0.1"));
+        let forwarded = "X-Forwarded-Proto: https\r\nX-Forwarded-Host: example.com";
+
+        let response = raw_request(
+            port,
+            &format!(
+                "GET / HTTP/1.1\r\nHost: 127.0.0.1:{}\r\n{}\r\n\r\n",
+                port, forwarded
+            ),
+        );
+        assert!(response.ends_with("\r\n\r\nhttps example.com"));
+        assert!(response.contains("Strict-Transport-Security: max-age=600\r\n"));
+        let response = raw_request(
+            port,
+            &format!(
+                "GET /logout/ HTTP/1.1\r\nHost: 127.0.0.1\r\n{}\r\n\r\n",
+                forwarded
+            ),
+        );
+        assert!(response.starts_with("HTTP/1.1 303 See Other\r\n"));
+        assert!(response.contains("Location: https://example.com/login/\r\n"));
+        let response = raw_request(
+            port,
+            &format!(
+                "GET / HTTP/1.1\r\nHost: 127.0.0.1\r\n{}\r\nX-Forwarded-Port: 8443\r\n\r\n",
+                forwarded
+            ),
+        );
+        assert!(response.ends_with("\r\n\r\nhttps example.com:8443"));
+
+        // Anyone else's forwarding headers are ignored
+        let response = raw_request(
+            untrusted_port,
+            &format!(
+                "GET / HTTP/1.1\r\nHost: localhost:{}\r\n{}\r\n\r\n",
+                untrusted_port, forwarded
+            ),
+        );
+        assert!(response.ends_with(&format!("\r\n\r\nhttp localhost:{}", untrusted_port)));
+        assert!(!response.contains("Strict-Transport-Security"));
+    }
+
+    #[test]
+    fn test_trusted_proxy_networks() {
+        let proxy = |proxy: &str| IpNetwork::parse(proxy).unwrap();
+        let ip = |ip: &str| ip.parse::<IpAddr>().unwrap();
+        assert!(proxy("10.0.0.0/8").contains(ip("10.1.2.3")));
+        assert!(!proxy("10.0.0.0/8").contains(ip("11.0.0.1")));
+        assert!(proxy("192.0.2.7").contains(ip("192.0.2.7")));
+        assert!(!proxy("192.0.2.7").contains(ip("192.0.2.8")));
+        assert!(proxy("0.0.0.0/0").contains(ip("203.0.113.1")));
+        assert!(proxy("2001:db8::/32").contains(ip("2001:db8:1::1")));
+        assert!(!proxy("2001:db8::/32").contains(ip("10.0.0.1")));
+        for invalid in &["10.0.0.0/33", "10.0.0.0/", "proxy.local", "::1/129"] {
+            assert_eq!(IpNetwork::parse(invalid), None);
+        }
+    }
+
+    fn hsts_server() -> Server {
+        let root = temp_document_root("hsts");
+        fs::write(root.join("file.txt"), "static").unwrap();
+        let mut server = Server::new();
+        server.set_document_root(&format!("{}/", root.display()));
+        server.get("/", |_request, _response| {});
+        server.enable_hsts(HstsConfig {
+            max_age: Duration::from_secs(600),
+            include_subdomains: true,
+            preload: false,
+        });
+        server
+    }
+
+    #[test]
+    fn test_hsts_plain_http() {
+        let port = spawn_server(hsts_server());
+        for path in &["/", "/file.txt", "/missing.txt"] {
+            let resp = reqwest::get(&format!("http://localhost:{}{}", port, path)).unwrap();
+            assert!(resp.headers().get("strict-transport-security").is_none());
+        }
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn test_hsts_tls() {
+        let (cert, key) = crate::tls::write_self_signed("hsts");
+        let mut server = hsts_server();
+        server.set_tls(TlsConfig::from_pem_files(cert, key).unwrap());
+        let port = spawn_server(server);
+
+        let client = reqwest::Client::builder()
+            .danger_accept_invalid_certs(true)
+            .build()
+            .unwrap();
+        for path in &["/", "/file.txt", "/missing.txt"] {
+            let resp = client
+                .get(&format!("https://localhost:{}{}", port, path))
+                .send()
+                .unwrap();
+            assert_eq!(
+                resp.headers()["strict-transport-security"],
+                "max-age=600; includeSubDomains"
+            );
+        }
+    }
+
+    /// Accepts any server certificate, the tests only use self-signed ones
+    #[cfg(feature = "http2")]
+    #[derive(Debug)]
+    struct AcceptAnyCert;
+
+    #[cfg(feature = "http2")]
+    impl rustls::client::danger::ServerCertVerifier for AcceptAnyCert {
+        fn verify_server_cert(
+            &self,
+            _end_entity: &rustls::pki_types::CertificateDer<'_>,
+            _intermediates: &[rustls::pki_types::CertificateDer<'_>],
+            _server_name: &rustls::pki_types::ServerName<'_>,
+            _ocsp_response: &[u8],
+            _now: rustls::pki_types::UnixTime,
+        ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+            Ok(rustls::client::danger::ServerCertVerified::assertion())
+        }
+
+        fn verify_tls12_signature(
+            &self,
+            _message: &[u8],
+            _cert: &rustls::pki_types::CertificateDer<'_>,
+            _dss: &rustls::DigitallySignedStruct,
+        ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+            Ok(rustls::client::danger::HandshakeSignatureValid::assertion())
+        }
+
+        fn verify_tls13_signature(
+            &self,
+            _message: &[u8],
+            _cert: &rustls::pki_types::CertificateDer<'_>,
+            _dss: &rustls::DigitallySignedStruct,
+        ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+            Ok(rustls::client::danger::HandshakeSignatureValid::assertion())
+        }
+
+        fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+            rustls::crypto::ring::default_provider()
+                .signature_verification_algorithms
+                .supported_schemes()
+        }
+    }
+
+    /// Sends each request over one HTTP/2 connection and returns status and
+    /// body of the responses
+    #[cfg(feature = "http2")]
+    fn http2_requests(
+        port: u16,
+        requests: Vec<http::Request<Option<&'static str>>>,
+    ) -> Vec<(u16, String)> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_io()
+            .build()
+            .unwrap();
+        runtime.block_on(async move {
+            use std::convert::TryFrom;
+
+            let mut config = rustls::ClientConfig::builder_with_provider(Arc::new(
+                rustls::crypto::ring::default_provider(),
+            ))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(AcceptAnyCert))
+            .with_no_client_auth();
+            config.alpn_protocols = vec![b"h2".to_vec()];
+            let connector = tokio_rustls::TlsConnector::from(Arc::new(config));
+            let tcp = tokio::net::TcpStream::connect(("127.0.0.1", port))
+                .await
+                .unwrap();
+            let name = rustls::pki_types::ServerName::try_from("localhost")
+                .unwrap()
+                .to_owned();
+            let tls = connector.connect(name, tcp).await.unwrap();
+            assert_eq!(tls.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+
+            let (client, connection) = h2::client::handshake(tls).await.unwrap();
+            tokio::spawn(connection);
+            let mut client = client.ready().await.unwrap();
+            let mut responses = Vec::new();
+            for request in requests {
+                let (parts, body) = request.into_parts();
+                let request = http::Request::from_parts(parts, ());
+                let (response, mut send) = client.send_request(request, body.is_none()).unwrap();
+                if let Some(body) = body {
+                    send.send_data(bytes::Bytes::from(body), true).unwrap();
+                }
+                let (parts, mut body) = response.await.unwrap().into_parts();
+                let mut content = Vec::new();
+                while let Some(chunk) = body.data().await {
+                    let chunk = chunk.unwrap();
+                    let _ = body.flow_control().release_capacity(chunk.len());
+                    content.extend_from_slice(&chunk);
+                }
+                responses.push((parts.status.as_u16(), String::from_utf8(content).unwrap()));
+                client = client.ready().await.unwrap();
+            }
+            responses
+        })
+    }
+
+    #[cfg(feature = "http2")]
+    #[test]
+    fn test_http2() {
+        let (cert, key) = crate::tls::write_self_signed("http2");
+        let root = temp_document_root("http2");
+        fs::write(root.join("file.txt"), "static over h2").unwrap();
+
+        let mut server = Server::new();
+        server.set_document_root(&format!("{}/", root.display()));
+        server.set_tls(TlsConfig::from_pem_files(cert, key).unwrap());
+        server.get("/hello/", |request, mut response| {
+            let name = request.query("name").unwrap_or("nobody");
+            let _ = response.write(&format!("hello {}", name));
+        });
+        server.post("/echo/", |request, mut response| {
+            let _ = response.write(request.form("text").unwrap_or(""));
+        });
+        let port = spawn_server(server);
+
+        let responses = http2_requests(
+            port,
+            vec![
+                http::Request::get(format!("https://localhost:{}/hello/?name=h2", port))
+                    .body(None)
+                    .unwrap(),
+                http::Request::post(format!("https://localhost:{}/echo/", port))
+                    .header("content-type", "application/x-www-form-urlencoded")
+                    .body(Some("text=posted"))
+                    .unwrap(),
+                http::Request::get(format!("https://localhost:{}/file.txt", port))
+                    .body(None)
+                    .unwrap(),
+                http::Request::get(format!("https://localhost:{}/missing.txt", port))
+                    .body(None)
+                    .unwrap(),
+            ],
+        );
+        assert_eq!(responses[0], (200, String::from("hello h2")));
+        assert_eq!(responses[1], (200, String::from("posted")));
+        assert_eq!(responses[2], (200, String::from("static over h2")));
+        assert_eq!(responses[3].0, 404);
+
+        // Clients that do not offer h2 still get HTTP/1.1
+        let client = reqwest::Client::builder()
+            .danger_accept_invalid_certs(true)
+            .build()
+            .unwrap();
+        let mut resp = client
+            .get(&format!("https://localhost:{}/hello/?name=fallback", port))
+            .send()
+            .unwrap();
+        assert_eq!(resp.text().unwrap(), "hello fallback");
+    }
+
+    #[test]
+    fn test_hsts_header_value() {
+        let config = HstsConfig {
+            max_age: Duration::from_secs(31_536_000),
+            include_subdomains: false,
+            preload: false,
+        };
+        assert_eq!(config.header_value(), "max-age=31536000");
+        let config = HstsConfig {
+            include_subdomains: true,
+            preload: true,
+            ..config
+        };
+        assert_eq!(
+            config.header_value(),
+            "max-age=31536000; includeSubDomains; preload"
+        );
+    }
+
+    /// An upstream that echoes what it received
+    fn echo_upstream() -> Server {
+        let mut upstream = Server::new();
+        for method in &["GET", "POST"] {
+            upstream.route(method, "/*", |request, mut response| {
+                let _ = response.write(&format!(
+                    "{} {}?{} for={} proto={} host={} a={}",
+                    request.method(),
+                    request.path(),
+                    request.raw_query().unwrap_or(""),
+                    request.get_header("X-Forwarded-For").unwrap_or("-"),
+                    request.get_header("X-Forwarded-Proto").unwrap_or("-"),
+                    request.get_header("X-Forwarded-Host").unwrap_or("-"),
+                    request.form("a").unwrap_or("-")
+                ));
+            });
+        }
+        upstream
+    }
+
+    #[test]
+    fn test_proxy() {
+        let upstream_port = spawn_server(echo_upstream());
+        let mut server = Server::new();
+        server.get("/local/", |_request, mut response| {
+            let _ = response.write("local");
+        });
+        assert!(server.proxy("/api/", &format!("http://127.0.0.1:{}", upstream_port)));
+        assert!(server.proxy_with(
+            "/stripped",
+            &format!("http://127.0.0.1:{}/v1", upstream_port),
+            ProxyConfig {
+                strip_prefix: true,
+                ..ProxyConfig::default()
+            },
+        ));
+        assert!(!server.proxy("/tls/", &format!("https://127.0.0.1:{}", upstream_port)));
+        let port = spawn_server(server);
+
+        let client = reqwest::Client::new();
+        let mut resp = client
+            .post(&format!("http://localhost:{}/api/users/?page=2", port))
+            .header("X-Forwarded-For", "192.0.2.1")
+            .form(&[("a", "1")])
+            .send()
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+        assert_eq!(
+            resp.text().unwrap(),
+            format!(
+                "POST /api/users/?page=2 for=192.0.2.1, 127.0.0.1 proto=http host=localhost:{} a=1",
+                port
+            )
+        );
+
+        let mut resp = reqwest::get(&format!("http://localhost:{}/stripped/users", port)).unwrap();
+        assert!(resp.text().unwrap().starts_with("GET /v1/users? "));
+        let mut resp = reqwest::get(&format!("http://localhost:{}/stripped", port)).unwrap();
+        assert!(resp.text().unwrap().starts_with("GET /v1/? "));
+
+        // Only whole segments match
+        let resp = reqwest::get(&format!("http://localhost:{}/apis/", port)).unwrap();
+        assert_eq!(resp.status(), 404);
+        let mut resp = reqwest::get(&format!("http://localhost:{}/local/", port)).unwrap();
+        assert_eq!(resp.text().unwrap(), "local");
+
+        // The body does not need to arrive together with the head
+        let long = "x".repeat(6000);
+        let mut resp = client
+            .post(&format!("http://localhost:{}/api/", port))
+            .form(&[("a", long.as_str())])
+            .send()
+            .unwrap();
+        assert!(resp.text().unwrap().ends_with(&format!("a={}", long)));
+    }
+
+    #[test]
+    fn test_proxy_errors() {
+        // A port that was free a moment ago refuses connections
+        let refused = TcpListener::bind("127.0.0.1:0").unwrap();
+        let refused_port = refused.local_addr().unwrap().port();
+        drop(refused);
+        // A server that accepts but never answers
+        let silent = TcpListener::bind("127.0.0.1:0").unwrap();
+        let silent_port = silent.local_addr().unwrap().port();
+
+        let mut server = Server::new();
+        server.proxy("/refused/", &format!("http://127.0.0.1:{}", refused_port));
+        server.proxy_with(
+            "/silent/",
+            &format!("http://127.0.0.1:{}", silent_port),
+            ProxyConfig {
+                timeout: Duration::from_millis(200),
+                ..ProxyConfig::default()
+            },
+        );
+        let port = spawn_server(server);
+
+        let response = raw_request(port, "GET /refused/ HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 502 Bad Gateway"));
+        let response = raw_request(port, "GET /silent/ HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 504 Gateway Timeout"));
+        drop(silent);
+    }
+
+    /// Starts the server in the background on a free port and returns the
+    /// thread serving and the port once the server accepts connections
+    fn spawn_stoppable_server(server: Server) -> (thread::JoinHandle<()>, u16) {
+        let handle = server.handle();
+        let serving = thread::spawn(move || server.start_server(0).unwrap());
+        let address = handle.wait_until_ready(Duration::from_secs(10)).unwrap();
+        (serving, address.port())
+    }
+
+    #[test]
+    fn test_graceful_shutdown() {
+        let server = slow_server();
+        let handle = server.shutdown_handle();
+        let (serving, port) = spawn_stoppable_server(server);
+        thread::sleep(Duration::from_millis(50));
+
+        let request = thread::spawn(move || {
+            raw_request(port, "GET /slow/ HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        });
+        thread::sleep(Duration::from_millis(50));
+        let start = Instant::now();
+        handle.shutdown();
+        serving.join().unwrap();
+
+        // The running request was finished before start_server returned
+        assert!(start.elapsed() >= Duration::from_millis(150));
+        assert!(request.join().unwrap().ends_with("done"));
+        assert!(TcpStream::connect(("127.0.0.1", port)).is_err());
+    }
+
+    #[test]
+    fn test_restart_on_same_port() {
+        let serve = |port| {
+            let mut server = Server::new();
+            server.get("/", |_request, mut response| {
+                let _ = response.write("hello");
+            });
+            let handle = server.spawn(port).unwrap();
+            let address = handle.wait_until_ready(Duration::from_secs(10)).unwrap();
+            (handle, address.port())
+        };
+        let (handle, port) = serve(0);
+        assert!(raw_request(port, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").ends_with("hello"));
+        handle.shutdown();
+        // A second shutdown changes nothing
+        handle.shutdown();
+        handle.join();
+        assert!(TcpStream::connect(("127.0.0.1", port)).is_err());
+
+        let (handle, restarted) = serve(port);
+        assert_eq!(restarted, port);
+        assert!(raw_request(port, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").ends_with("hello"));
+        handle.shutdown();
+        handle.join();
+    }
+
+    #[test]
+    fn test_bind_addresses() {
+        let hello = |addr: &str| {
+            let mut server = Server::new();
+            server.get("/", |_request, mut response| {
+                let _ = response.write("hello");
+            });
+            let handle = server.spawn(addr).unwrap();
+            let address = handle.wait_until_ready(Duration::from_secs(10)).unwrap();
+            (handle, address)
+        };
+        let request = b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let get = |address: SocketAddr| {
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream.write_all(request).unwrap();
+            let _ = stream.shutdown(std::net::Shutdown::Write);
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+
+        let (handle, address) = hello("0.0.0.0:0");
+        assert!(address.ip().is_unspecified());
+        assert!(get(SocketAddr::from(([127, 0, 0, 1], address.port()))).ends_with("hello"));
+        handle.shutdown();
+        handle.join();
+
+        let (handle, address) = hello("[::1]:0");
+        assert_eq!(address.ip(), std::net::Ipv6Addr::LOCALHOST);
+        assert!(get(address).ends_with("hello"));
+        handle.shutdown();
+        handle.join();
+    }
+
+    #[test]
+    fn test_unavailable_while_draining() {
+        let mut server = Server::new();
+        server.get("/slow/", |_request, mut response| {
+            thread::sleep(Duration::from_millis(300));
+            let _ = response.write("done");
+        });
+        server.set_retry_after(Duration::from_secs(30));
+        let metrics = server.metrics();
+        let handle = server.shutdown_handle();
+        let (serving, port) = spawn_stoppable_server(server);
+
+        let request = thread::spawn(move || {
+            raw_request(port, "GET /slow/ HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        });
+        thread::sleep(Duration::from_millis(50));
+        handle.shutdown();
+
+        // Neither refused nor left hanging while the slow request finishes
+        let response = raw_request(port, "GET /slow/ HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert_eq!(
+            response,
+            "HTTP/1.1 503 Service Unavailable\r\nRetry-After: 30\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+        );
+        assert!(request.join().unwrap().ends_with("done"));
+        serving.join().unwrap();
+        assert_eq!(metrics.service_unavailable(), 1);
+    }
+
+    #[test]
+    fn test_shutdown_deadline() {
+        let mut server = Server::new();
+        server.get("/stuck/", |_request, mut response| {
+            thread::sleep(Duration::from_secs(2));
+            let _ = response.write("late");
+        });
+        server.set_shutdown_deadline(Duration::from_millis(100));
+        let handle = server.shutdown_handle();
+        let (serving, port) = spawn_stoppable_server(server);
+
+        thread::spawn(move || {
+            let _ = TcpStream::connect(("127.0.0.1", port)).and_then(|mut stream| {
+                stream.write_all(b"GET /stuck/ HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            });
+        });
+        thread::sleep(Duration::from_millis(100));
+        let start = Instant::now();
+        handle.shutdown();
+        serving.join().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[cfg(all(target_os = "linux", feature = "signals"))]
+    #[test]
+    fn test_shutdown_on_sigterm() {
+        let mut server = Server::new();
+        server.shutdown_on_signals(true);
+        let (serving, _) = spawn_stoppable_server(server);
+
+        // Safety: sends a signal to this process, which the server handles
+        assert_eq!(unsafe { libc::kill(libc::getpid(), libc::SIGTERM) }, 0);
+        serving.join().unwrap();
+    }
+
+    #[test]
+    fn test_on_start() {
+        let mut server = Server::new();
+        server.get("/", |_request, mut response| {
+            let _ = response.write("up");
+        });
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let sender = Mutex::new(sender);
+        server.on_start(move |info| {
+            sender.lock().unwrap().send(info.clone()).unwrap();
+        });
+        let handle = server.shutdown_handle();
+        let serving = thread::spawn(move || server.start_server(0).unwrap());
+
+        let info = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(info.addresses.len(), 1);
+        let address = info.addresses[0];
+        assert_ne!(address.port(), 0);
+        assert!(!info.tls);
+        assert_eq!(info.workers, default_workers());
+        assert_eq!(info.routes.len(), 1);
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.ends_with("up"));
+
+        handle.shutdown();
+        serving.join().unwrap();
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_start_server_addresses() {
+        for use_string in [true, false] {
+            let mut server = Server::new();
+            server.get("/", |_request, mut response| {
+                let _ = response.write("up");
+            });
+            let handle = server.shutdown_handle();
+            let ready = server.handle();
+            let serving = if use_string {
+                thread::spawn(move || server.start_server("127.0.0.1:0"))
+            } else {
+                thread::spawn(move || server.start_server(("127.0.0.1", 0)))
+            };
+            let port = ready
+                .wait_until_ready(Duration::from_secs(10))
+                .unwrap()
+                .port();
+            assert!(raw_request(port, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").ends_with("up"));
+            handle.shutdown();
+            serving.join().unwrap().unwrap();
+        }
+
+        let server = Server::new();
+        assert!(server.start_server("not an address").is_err());
+        assert!(server.start_server("127.0.0.1:70000").is_err());
+        let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = taken.local_addr().unwrap().port();
+        let error = server.start_server(port).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AddrInUse);
+        // A server that never listens is never ready
+        assert_eq!(
+            server.handle().wait_until_ready(Duration::from_millis(10)),
+            Err(Timeout)
+        );
+    }
+
+    #[test]
+    fn test_spawn_and_join() {
+        let mut server = Server::new();
+        server.get("/", |_request, mut response| {
+            let _ = response.write("up");
+        });
+        assert!(server.add_listen_addr("127.0.0.1:0"));
+        assert!(!server.add_listen_addr("not an address"));
+        server.redirect_http_to_https(0, Some("example.com"));
+        let handle = server.spawn(0).unwrap();
+        let listeners = handle.listeners();
+        let protocols: Vec<Protocol> = listeners.iter().map(|l| l.protocol).collect();
+        assert_eq!(
+            protocols,
+            [Protocol::Http, Protocol::Http, Protocol::HttpsRedirect]
+        );
+        // Dropping the handle leaves the server running
+        drop(handle);
+        let request = "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        for listener in &listeners[..2] {
+            assert!(raw_request(listener.address.port(), request).ends_with("up"));
+        }
+        let redirected = raw_request(listeners[2].address.port(), request);
+        assert!(redirected.starts_with("HTTP/1.1 301 Moved Permanently"));
+
+        // One call stops every listener
+        let handle = server.handle();
+        handle.shutdown();
+        handle.join();
+        assert!(handle.listeners().is_empty());
+        for listener in &listeners {
+            assert!(TcpStream::connect(listener.address).is_err());
+        }
+
+        let mut server = Server::new();
+        let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+        server.add_listen_addr(taken.local_addr().unwrap());
+        assert!(server.spawn(0).is_err());
+    }
+
+    #[test]
+    fn test_repeated_headers() {
+        let mut server = Server::new();
+        server.get("/cookies/", |_request, mut response| {
+            let _ = response.add_header(
+                "Set-Cookie",
+                "session=abc; Expires=Wed, 21 Oct 2026 07:28:00 GMT; HttpOnly",
+            );
+            let _ = response.add_header("Set-Cookie", "theme=dark, light");
+            let _ = response.add_header("Link", "</a.css>; rel=preload");
+            let _ = response.add_header("Link", "</b.js>; rel=preload");
+            let _ = response.set_header("X-Mode", "first");
+            let _ = response.set_header("x-mode", "second");
+            let _ = response.set_header("Content-Length", "2");
+            assert!(response
+                .add_header("X-Evil", "a\r\nSet-Cookie: b=c")
+                .is_err());
+            assert!(response.set_header("X Evil", "a").is_err());
+            let _ = response.write("ok");
+        });
+        let port = spawn_server(server);
+
+        let response = raw_request(
+            port,
+            "GET /cookies/ HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        );
+        let lines: Vec<&str> = response.split("\r\n").collect();
+        let with_prefix = |prefix: &str| {
+            lines
+                .iter()
+                .filter(|line| line.starts_with(prefix))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            with_prefix("Set-Cookie: "),
+            vec![
+                "Set-Cookie: session=abc; Expires=Wed, 21 Oct 2026 07:28:00 GMT; HttpOnly",
+                "Set-Cookie: theme=dark, light",
+            ]
+        );
+        assert_eq!(with_prefix("Link: ").len(), 2);
+        assert_eq!(with_prefix("x-mode: "), vec!["x-mode: second"]);
+        assert_eq!(with_prefix("Content-Length: "), vec!["Content-Length: 2"]);
+        assert!(!response.contains("X-Evil"));
+        assert!(response.ends_with("\r\n\r\nok"));
+    }
+
+    #[test]
+    fn test_date_header() {
+        let mut server = Server::new();
+        server.get("/", |_request, mut response| {
+            let _ = response.write("now");
+        });
+        server.get("/fixed/", |_request, mut response| {
+            let _ = response.set_header("Date", "Sun, 06 Nov 1994 08:49:37 GMT");
+        });
+        let before = SystemTime::now() - Duration::from_secs(1);
+        let response = server.test_request("GET", "/").send();
+        let date = httpdate::parse_http_date(response.header("Date").unwrap()).unwrap();
+        assert!(date >= before && date <= SystemTime::now());
+        assert_eq!(
+            server.test_request("GET", "/fixed/").send().header("Date"),
+            Some("Sun, 06 Nov 1994 08:49:37 GMT")
+        );
+
+        assert!(server.set_default_header("date", "Thu, 01 Jan 1970 00:00:00 GMT"));
+        let response = server.test_request("GET", "/").send();
+        let dates: Vec<_> = response
+            .headers()
+            .iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case("Date"))
+            .collect();
+        assert_eq!(dates.len(), 1);
+        assert_eq!(dates[0].1, "Thu, 01 Jan 1970 00:00:00 GMT");
+    }
+
+    #[test]
+    fn test_check_header() {
+        assert!(check_header("X-Request-ID", "42").is_ok());
+        assert!(check_header("x_custom.v2!", "a: b; c=\"d\"").is_ok());
+        assert!(check_header("Empty", "").is_ok());
+        let invalid = [
+            ("", "a"),
+            ("X Evil", "a"),
+            ("X-Evil:", "a"),
+            ("X-(Evil)", "a"),
+            ("X-Évil", "a"),
+            ("X-Evil\r\n", "a"),
+            ("X-Evil", "value\r\nSet-Cookie: pwned=1"),
+            ("X-Evil", "value\nSet-Cookie: pwned=1"),
+            ("X-Evil", "value\r"),
+            ("X-Evil", "value\0"),
+        ];
+        for (name, value) in invalid.iter() {
+            let error = check_header(name, value).unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput, "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn test_check_message() {
+        // Status codes
+        assert!(check_message(200, None, 0).is_ok());
+        assert!(check_message(599, None, 0).is_ok());
+        assert!(check_message(99, None, 0).is_err());
+        assert!(check_message(600, None, 0).is_err());
+        assert!(check_message(99999, None, 0).is_err());
+        // Statuses without a body
+        for code in &[100, 101, 204, 304] {
+            assert!(check_message(*code, None, 0).is_ok());
+            assert!(check_message(*code, None, 1).is_err());
+        }
+        // Content-Length set by the handler
+        assert!(check_message(200, Some("5"), 5).is_ok());
+        assert!(check_message(200, Some("1000"), 5).is_err());
+        assert!(check_message(200, Some("five"), 5).is_err());
+        assert!(check_message(204, Some("0"), 0).is_err());
+        assert!(check_message(304, Some("1000"), 0).is_ok());
+    }
+
+    #[test]
+    fn test_send_cached() {
+        let rendered = Arc::new(AtomicU64::new(0));
+        let counter = rendered.clone();
+        let mut server = Server::new();
+        server.get("/report/", move |request, mut response| {
+            let _ = response.send_cached(&request, "v7", || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                String::from("expensive")
+            });
+        });
+        server.get("/tagged/", |_request, mut response| {
+            response.set_etag("W/\"draft\"").unwrap();
+            assert!(response.set_etag("bad\ntag").is_err());
+        });
+
+        let first = server.test_request("GET", "/report/").send();
+        assert_eq!((first.status(), first.text().as_str()), (200, "expensive"));
+        let etag = String::from(first.header("ETag").unwrap());
+        assert_eq!(etag, "\"v7\"");
+        let second = server
+            .test_request("GET", "/report/")
+            .header("If-None-Match", &etag)
+            .send();
+        assert_eq!((second.status(), second.text().as_str()), (304, ""));
+        assert_eq!(second.header("ETag"), Some("\"v7\""));
+        assert_eq!(rendered.load(Ordering::SeqCst), 1);
+
+        for tags in ["\"v6\", W/\"v7\"", "*"] {
+            let revalidated = server
+                .test_request("GET", "/report/")
+                .header("If-None-Match", tags)
+                .send();
+            assert_eq!(revalidated.status(), 304);
+        }
+        let changed = server
+            .test_request("GET", "/report/")
+            .header("If-None-Match", "\"v6\"")
+            .send();
+        assert_eq!(changed.status(), 200);
+        assert_eq!(rendered.load(Ordering::SeqCst), 2);
+
+        let tagged = server.test_request("GET", "/tagged/").send();
+        assert_eq!(tagged.header("ETag"), Some("W/\"draft\""));
+    }
+
+    #[test]
+    fn test_status_lines() {
+        let mut server = Server::new();
+        server.get("/status/:code/", |request, mut response| {
+            let code = request.path_param_str("code").unwrap().parse().unwrap();
+            let _ = response.set_status_code(code);
+        });
+        server.get("/no-status/", |_request, mut response| {
+            let _ = response.write("body");
+        });
+        server.get("/nothing/", |_, _| {});
+        let port = spawn_server(server);
+
+        let status_line = |path: &str| {
+            let response = raw_request(
+                port,
+                &format!(
+                    "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+                    path
+                ),
+            );
+            String::from(response.split("\r\n").next().unwrap())
+        };
+        let expectations = [
+            ("/status/201/", "HTTP/1.1 201 Created"),
+            ("/status/401/", "HTTP/1.1 401 Unauthorized"),
+            ("/status/404/", "HTTP/1.1 404 Not Found"),
+            ("/status/405/", "HTTP/1.1 405 Method Not Allowed"),
+            ("/status/500/", "HTTP/1.1 500 Internal Server Error"),
+            ("/status/299/", "HTTP/1.1 299 Success"),
+            ("/status/499/", "HTTP/1.1 499 Client Error"),
+            ("/no-status/", "HTTP/1.1 200 OK"),
+            ("/nothing/", "HTTP/1.1 200 OK"),
+        ];
+        for (path, expected) in expectations.iter() {
+            assert_eq!(status_line(path), *expected, "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_response_consistency() {
+        let mut server = Server::new();
+        server.get("/invalid/", |_request, mut response| {
+            assert!(response.set_status_code(99999).is_err());
+            assert!(response.set_status_code(99).is_err());
+            let _ = response.write("still 200");
+        });
+        server.get("/no-content/", |_request, mut response| {
+            response.set_status_code(204).unwrap();
+            assert!(response.write("body").is_err());
+            assert!(response.write("").is_ok());
+        });
+        server.get("/late-status/", |_request, mut response| {
+            response.write("body").unwrap();
+            assert!(response.set_status_code(304).is_err());
+        });
+        server.get("/not-modified/", |_request, mut response| {
+            response.set_status_code(304).unwrap();
+            response.set_header("Content-Length", "1000").unwrap();
+        });
+        server.get("/wrong-length/", |_request, mut response| {
+            response.set_header("Content-Length", "1000").unwrap();
+            let _ = response.write("short");
+        });
+        server.get("/status-only/", |_request, mut response| {
+            let _ = response.set_status_code(200);
+        });
+
+        let response = server.test_request("GET", "/invalid/").send();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.text(), "still 200");
+
+        let response = server.test_request("GET", "/no-content/").send();
+        assert_eq!(response.status(), 204);
+        assert_eq!(response.header("Content-Length"), None);
+        assert_eq!(response.text(), "");
+
+        let response = server.test_request("GET", "/late-status/").send();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.text(), "body");
+
+        let response = server.test_request("GET", "/not-modified/").send();
+        assert_eq!(response.status(), 304);
+        assert_eq!(response.header("Content-Length"), Some("1000"));
+
+        // A client would wait for 995 more bytes
+        let response = server.test_request("GET", "/wrong-length/").send();
+        assert_eq!(response.status(), 500);
+        assert_eq!(response.header("Content-Length"), Some("0"));
+
+        let response = server.test_request("GET", "/status-only/").send();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.header("Content-Length"), Some("0"));
+        assert_eq!(response.text(), "");
+    }
+
+    #[test]
+    fn test_response_completed_once() {
+        let completed = |result: std::io::Result<()>| {
+            result
+                .unwrap_err()
+                .get_ref()
+                .is_some_and(|error| error.is::<ResponseAlreadyCompleted>())
+        };
+        let mut server = Server::new();
+        server.get("/fall-through/", move |_request, mut response| {
+            assert!(!response.is_completed());
+            response.write("partial").unwrap();
+            response.redirect_temporary("/login/", false).unwrap();
+            assert!(response.is_completed());
+            assert!(completed(response.write("Welcome")));
+            assert!(completed(response.set_status_code(200)));
+            // Headers still go with the redirect
+            response.add_header("Set-Cookie", "flash=1").unwrap();
+        });
+        server.get("/twice/", move |_request, mut response| {
+            response.see_other("/first/").unwrap();
+            assert!(completed(response.see_other("/second/")));
+            assert!(completed(response.redirect_permanent("/third/", true)));
+        });
+
+        let response = server.test_request("GET", "/fall-through/").send();
+        assert_eq!(response.status(), 302);
+        assert_eq!(response.header("Location"), Some("/login/"));
+        assert_eq!(response.header("Set-Cookie"), Some("flash=1"));
+        assert!(!response.text().contains("Welcome"));
+        assert!(!response.text().contains("partial"));
+
+        let response = server.test_request("GET", "/twice/").send();
+        assert_eq!(response.status(), 303);
+        assert_eq!(response.header("Location"), Some("/first/"));
+    }
+
+    #[test]
+    fn test_header_injection() {
+        let payload = "value\r\nSet-Cookie: pwned=1";
+        let mut server = Server::new();
+        server.get("/", move |_request, mut response| {
+            let results = [
+                response.set_header("X-Evil", payload),
+                response.add_header("X-Evil", payload),
+                response.add_header("Set-Cookie: pwned=1\r\nX-Evil", "a"),
+                response.redirect_temporary(payload, false),
+                response.see_other(payload),
+            ];
+            let rejected = results.iter().filter(|result| result.is_err()).count();
+            let _ = response.write(&rejected.to_string());
+        });
+        server.get("/huge/", |_request, mut response| {
+            let value = "x".repeat(1000);
+            let mut added = 0;
+            while response.add_header("X-Filler", &value).is_ok() {
+                added += 1;
+            }
+            let _ = response.write(&added.to_string());
+        });
+        assert!(!server.set_default_header("X-Evil", payload));
+        assert!(!server.set_default_header("X Evil", "a"));
+        assert!(!server.redirect("/old/", payload, 301));
+        assert!(server.default_headers().is_empty());
+
+        let response = server.test_request("GET", "/").send();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.text(), "5");
+        assert_eq!(response.header("Set-Cookie"), None);
+        assert_eq!(response.header("X-Evil"), None);
+        assert_eq!(response.header("Location"), None);
+        assert_eq!(server.test_request("GET", "/old/").send().status(), 404);
+
+        let response = server.test_request("GET", "/huge/").send();
+        assert_eq!(response.status(), 200);
+        let added: usize = response.text().parse().unwrap();
+        assert!(added > 0 && added < 66);
+        assert_eq!(response.headers().len(), added + 3);
+    }
+
+    #[test]
+    fn test_oversized_default_headers() {
+        let mut server = Server::new();
+        let value = "x".repeat(1000);
+        server.get("/", move |_request, mut response| {
+            for _ in 0..40 {
+                let _ = response.add_header("X-Filler", &value);
+            }
+            let _ = response.write("body");
+        });
+        assert!(server.set_default_header("X-Default", &"y".repeat(40_000)));
+        let response = server.test_request("GET", "/").send();
+        assert_eq!(response.status(), 500);
+        assert_eq!(response.header("X-Filler"), None);
+        assert_eq!(response.text(), "");
+    }
+
+    #[test]
+    fn test_cookies() {
+        let mut server = Server::new();
+        server.get("/", |request, mut response| {
+            let cookies: Vec<String> = request
+                .get_cookies()
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect();
+            let _ = response.write(&format!(
+                "{} {}",
+                request.get_cookie("a").unwrap_or("-"),
+                cookies.join(",")
+            ));
+        });
+
+        let response = server
+            .test_request("GET", "/")
+            .header("Cookie", "a=1; b=\"x y\"")
+            .header("Cookie", "a=2")
+            .send();
+        assert_eq!(response.text(), "1 a=1,b=x y,a=2");
+        assert_eq!(server.test_request("GET", "/").send().text(), "- ");
+    }
+
+    #[test]
+    fn test_set_cookie() {
+        let mut server = Server::new();
+        server.get("/login/", |_request, mut response| {
+            let options = CookieOptions {
+                path: Some(String::from("/")),
+                http_only: true,
+                same_site: Some(SameSite::Lax),
+                ..CookieOptions::default()
+            };
+            let _ = response.set_cookie("session", "YWJjZA==", &options);
+            let _ = response.set_cookie("theme", "dark", &CookieOptions::default());
+            assert!(response
+                .set_cookie("bad", "a;b", &CookieOptions::default())
+                .is_err());
+        });
+        server.get("/whoami/", |request, mut response| {
+            let mut cookies = request.get_cookies().to_vec();
+            cookies.sort();
+            let _ = response.write(&format!("{:?}", cookies));
+        });
+
+        let response = server.test_request("GET", "/login/").send();
+        let cookies: Vec<&str> = response
+            .headers()
+            .iter()
+            .filter(|(name, _)| name == "Set-Cookie")
+            .map(|(_, value)| value.as_str())
+            .collect();
+        assert_eq!(
+            cookies,
+            vec![
+                "session=YWJjZA==; Path=/; HttpOnly; SameSite=Lax",
+                "theme=dark"
+            ]
+        );
+
+        let port = spawn_server(server);
+        let client = reqwest::Client::builder()
+            .cookie_store(true)
+            .build()
+            .unwrap();
+        client
+            .get(&format!("http://localhost:{}/login/", port))
+            .send()
+            .unwrap();
+        let mut resp = client
+            .get(&format!("http://localhost:{}/whoami/", port))
+            .send()
+            .unwrap();
+        // Without a path, theme only goes back to /login/ and below
+        assert_eq!(resp.text().unwrap(), r#"[("session", "YWJjZA==")]"#);
+    }
+
+    #[test]
+    fn test_sessions() {
+        let mut server = Server::new();
+        server.enable_sessions();
+        server.get("/count/", |request, mut response| {
+            let session = request.session().unwrap();
+            let count = session.get::<u32>("count").unwrap_or(0) + 1;
+            session.insert("count", count);
+            let _ = response.write_display(&count);
+        });
+        server.get("/peek/", |request, mut response| {
+            let count = request.session().unwrap().get::<u32>("count");
+            let _ = response.write_display(&count.unwrap_or(0));
+        });
+        let port = spawn_server(server);
+        let url = format!("http://localhost:{}/count/", port);
+
+        // Reading a session that does not exist yet does not start one
+        let peek = format!("http://localhost:{}/peek/", port);
+        let mut resp = reqwest::get(&peek).unwrap();
+        assert!(resp.headers().get("set-cookie").is_none());
+        assert_eq!(resp.text().unwrap(), "0");
+
+        let client = reqwest::Client::builder()
+            .cookie_store(true)
+            .build()
+            .unwrap();
+        let mut resp = client.get(&url).send().unwrap();
+        let cookie = resp.headers()["set-cookie"].to_str().unwrap().to_owned();
+        assert!(cookie.starts_with("corroded_session="), "{}", cookie);
+        assert!(cookie.contains("; HttpOnly"), "{}", cookie);
+        assert_eq!(resp.text().unwrap(), "1");
+        let mut resp = client.get(&url).send().unwrap();
+        assert!(resp.headers().get("set-cookie").is_none());
+        assert_eq!(resp.text().unwrap(), "2");
+        assert_eq!(client.get(&url).send().unwrap().text().unwrap(), "3");
+
+        // Other clients count for themselves
+        let other = reqwest::Client::builder()
+            .cookie_store(true)
+            .build()
+            .unwrap();
+        assert_eq!(other.get(&url).send().unwrap().text().unwrap(), "1");
+        assert_eq!(reqwest::get(&url).unwrap().text().unwrap(), "1");
+        assert_eq!(client.get(&url).send().unwrap().text().unwrap(), "4");
+        let mut resp = client.get(&peek).send().unwrap();
+        assert!(resp.headers().get("set-cookie").is_none());
+        assert_eq!(resp.text().unwrap(), "4");
+    }
+
+    #[test]
+    fn test_access_log() {
+        let root = temp_document_root("access_log");
+        let path = root.join("access.log");
+        let mut server = Server::new();
+        assert!(server.set_access_log(path.to_str().unwrap()));
+        assert!(!server.set_access_log("/does/not/exist/access.log"));
+        server.get("/hello/", |_request, mut response| {
+            let _ = response.write("hello");
+        });
+        server.get("/large/", |_request, mut response| {
+            let _ = response.write_bytes(&vec![b'x'; 16 * 1024 * 1024]);
+        });
+        server.ignore_favicon(true);
+        let port = spawn_server(server);
+
+        raw_request(
+            port,
+            "GET /hello/?a=1 HTTP/1.1\r\nHost: localhost\r\nReferer: http://localhost/\r\n\
+             User-Agent: test \"agent\"\r\nConnection: close\r\n\r\n",
+        );
+        raw_request(port, "GET /missing HTTP/1.0\r\n\r\n");
+        let favicon = raw_request(port, "GET /favicon.ico HTTP/1.0\r\n\r\n");
+        assert!(favicon.starts_with("HTTP/1.1 204 "), "{}", favicon);
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream
+            .write_all(b"GET /large/ HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut start = vec![0; 64 * 1024];
+        stream.read_exact(&mut start).unwrap();
+        // Unread data makes the kernel reset the connection
+        drop(stream);
+        // The line is written after the response went out
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let log = loop {
+            let log = fs::read_to_string(&path).unwrap();
+            if log.lines().count() == 3 || Instant::now() > deadline {
+                break log;
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 3, "{}", log);
+        assert!(!log.contains("favicon"), "{}", log);
+
+        let (client, rest) = lines[0].split_once(" - - [").unwrap();
+        assert_eq!(client, "127.0.0.1");
+        let (date, rest) = rest.split_once("] ").unwrap();
+        assert_eq!(date.len(), 26, "{}", date);
+        assert!(date.ends_with(" +0000"), "{}", date);
+        let (fields, took) = rest.rsplit_once(' ').unwrap();
+        assert_eq!(
+            fields,
+            "\"GET /hello/?a=1 HTTP/1.1\" 200 5 \"http://localhost/\" \"test \\\"agent\\\"\""
+        );
+        assert!(took.parse::<u64>().is_ok(), "{}", took);
+
+        assert!(
+            lines[1].contains("] \"GET /missing HTTP/1.0\" 404 "),
+            "{}",
+            lines[1]
+        );
+        assert!(lines[1].contains(" \"-\" \"-\" "), "{}", lines[1]);
+        assert!(!lines[1].ends_with("client-abort"), "{}", lines[1]);
+
+        assert!(
+            lines[2].contains("] \"GET /large/ HTTP/1.1\" 200 "),
+            "{}",
+            lines[2]
+        );
+        // On tokio the response is written after its line
+        #[cfg(not(feature = "tokio"))]
+        assert!(lines[2].ends_with(" client-abort"), "{}", lines[2]);
+    }
+
+    #[test]
+    fn test_request_line_accessors() {
+        let mut server = Server::new();
+        server.get("/parameter_demo/", |request, mut response| {
+            let _ = write!(
+                response,
+                "{} {} {} {:?} {:?}",
+                request.method(),
+                request.path(),
+                request.http_version(),
+                request.full_url(),
+                request.client_ip()
+            );
+        });
+        let port = spawn_server(server);
+
+        let url = format!("http://localhost:{}/parameter_demo/?a=1", port);
+        assert_eq!(
+            reqwest::get(&url).unwrap().text().unwrap(),
+            format!(
+                "GET /parameter_demo/ HTTP/1.1 Some(\"{}\") Some(127.0.0.1)",
+                url
+            )
+        );
+        let response = raw_request(
+            port,
+            "GET /parameter%5Fdemo/ HTTP/1.0\r\nHost: example.com\r\n\r\n",
+        );
+        assert!(
+            response.ends_with(
+                "GET /parameter_demo/ HTTP/1.0 \
+                 Some(\"http://example.com/parameter%5Fdemo/\") Some(127.0.0.1)"
+            ),
+            "{}",
+            response
+        );
+    }
+
+    #[test]
+    fn test_cors() {
+        let cors_server = |config| {
+            let mut server = Server::new();
+            assert!(server.enable_cors(config));
+            server.get("/api/items/", |_request, mut response| {
+                let _ = response.write("items");
+            });
+            server.post("/api/items/", |_request, _response| {});
+            server.get("/api/localized/", |_request, mut response| {
+                let _ = response.set_header("Vary", "Accept-Language");
+            });
+            server
+        };
+        let mut server = cors_server(CorsConfig {
+            allowed_origins: vec![String::from("https://app.example")],
+            allow_credentials: true,
+            max_age: Some(Duration::from_secs(600)),
+            ..CorsConfig::default()
+        });
+
+        let response = server
+            .test_request("OPTIONS", "/api/items/")
+            .header("Origin", "https://app.example")
+            .header("Access-Control-Request-Method", "POST")
+            .header("Access-Control-Request-Headers", "content-type, x-token")
+            .send();
+        assert_eq!(response.status(), 204);
+        for (name, value) in [
+            ("Access-Control-Allow-Origin", "https://app.example"),
+            ("Access-Control-Allow-Credentials", "true"),
+            ("Access-Control-Allow-Methods", "GET, HEAD, POST, OPTIONS"),
+            ("Access-Control-Allow-Headers", "content-type, x-token"),
+            ("Access-Control-Max-Age", "600"),
+            ("Vary", "Origin"),
+        ] {
+            assert_eq!(response.header(name), Some(value), "{}", name);
+        }
+
+        let response = server
+            .test_request("GET", "/api/items/")
+            .header("Origin", "https://app.example")
+            .send();
+        assert_eq!(response.text(), "items");
+        assert_eq!(
+            response.header("Access-Control-Allow-Origin"),
+            Some("https://app.example")
+        );
+        assert_eq!(response.header("Access-Control-Allow-Methods"), None);
+        assert_eq!(response.header("Vary"), Some("Origin"));
+
+        // The handler's Vary is merged with the one of CORS
+        let response = server
+            .test_request("GET", "/api/localized/")
+            .header("Origin", "https://app.example")
+            .send();
+        assert_eq!(response.header("Vary"), Some("Accept-Language, Origin"));
+
+        // Other origins are left to the browser to refuse
+        let response = server
+            .test_request("OPTIONS", "/api/items/")
+            .header("Origin", "https://evil.example")
+            .header("Access-Control-Request-Method", "POST")
+            .send();
+        assert_eq!(response.status(), 204);
+        assert_eq!(response.header("Access-Control-Allow-Origin"), None);
+        assert_eq!(response.header("Access-Control-Allow-Methods"), None);
+
+        assert!(!server.enable_cors(CorsConfig {
+            allowed_origins: vec![String::from("*")],
+            allow_credentials: true,
+            ..CorsConfig::default()
+        }));
+
+        let server = cors_server(CorsConfig {
+            allowed_origins: vec![String::from("*")],
+            allowed_headers: vec![String::from("Content-Type")],
+            ..CorsConfig::default()
+        });
+        let response = server
+            .test_request("OPTIONS", "/api/items/")
+            .header("Origin", "https://any.example")
+            .header("Access-Control-Request-Method", "POST")
+            .header("Access-Control-Request-Headers", "x-token")
+            .send();
+        assert_eq!(response.header("Access-Control-Allow-Origin"), Some("*"));
+        assert_eq!(
+            response.header("Access-Control-Allow-Headers"),
+            Some("Content-Type")
+        );
+        assert_eq!(response.header("Access-Control-Allow-Credentials"), None);
+        assert_eq!(response.header("Vary"), None);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_compression() {
+        let page = "<p>Corroded, but compressed</p>\n".repeat(200);
+        let root = temp_document_root("compression");
+        fs::create_dir(root.join("files")).unwrap();
+        for i in 0..40 {
+            fs::write(root.join("files").join(format!("file_{:02}.txt", i)), "").unwrap();
+        }
+        let mut server = Server::new();
+        server.set_document_root(&format!("{}/", root.display()));
+        server.use_index_of(true);
+        server.enable_compression(true);
+        let served = page.clone();
+        server.get("/page/", move |_request, mut response| {
+            let _ = response.set_header("Content-Type", "text/html");
+            let _ = response.set_etag("page-v1");
+            let _ = response.write(&served);
+        });
+        server.get("/small/", |_request, mut response| {
+            let _ = response.set_header("Content-Type", "text/plain");
+            let _ = response.write("short");
+        });
+        server.get("/image/", |_request, mut response| {
+            let _ = response.set_header("Content-Type", "image/png");
+            let _ = response.write(&"\0".repeat(4096));
+        });
+        let port = spawn_server(server);
+
+        // reqwest asks for gzip and decompresses transparently
+        let mut resp = reqwest::get(&format!("http://localhost:{}/page/", port)).unwrap();
+        assert_eq!(resp.status().as_u16(), 200);
+        assert_eq!(resp.text().unwrap(), page);
+
+        let raw = |path: &str, accept_encoding: &str| {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            let request = format!(
+                "GET {} HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: {}\r\nConnection: close\r\n\r\n",
+                path, accept_encoding
             );
+            stream.write_all(request.as_bytes()).unwrap();
+            let mut response = Vec::new();
+            stream.read_to_end(&mut response).unwrap();
+            let end = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+            let body = response.split_off(end + 4);
+            (String::from_utf8(response).unwrap(), body)
+        };
+        let (head, body) = raw("/page/", "gzip, br");
+        assert!(head.contains("Content-Encoding: gzip\r\n"), "{}", head);
+        assert!(head.contains("Vary: Accept-Encoding\r\n"), "{}", head);
+        assert!(head.contains(&format!("Content-Length: {}\r\n", body.len())));
+        // The compressed bytes are not those the strong tag was given for
+        assert!(head.contains("ETag: W/\"page-v1\"\r\n"), "{}", head);
+        assert!(body.len() < page.len() / 10);
+        assert_eq!(compression::gzip(page.as_bytes()).unwrap(), body);
 
-            let threadpool = ThreadPool::new(8);
+        let (head, body) = raw("/page/", "gzip;q=0");
+        assert!(!head.contains("Content-Encoding"), "{}", head);
+        assert!(head.contains("ETag: \"page-v1\"\r\n"), "{}", head);
+        assert!(head.contains("Vary: Accept-Encoding\r\n"), "{}", head);
+        assert_eq!(body, page.as_bytes());
 
-            for stream in listener.incoming() {
-                let s = self.clone();
-                if let Ok(stream) = stream {
-                    threadpool.execute(move || {
-                        s.handle_connection(stream);
-                    });
-                }
+        let (head, body) = raw("/small/", "gzip");
+        assert!(!head.contains("Content-Encoding"), "{}", head);
+        assert_eq!(body, b"short");
+
+        let (head, body) = raw("/image/", "gzip");
+        assert!(!head.contains("Content-Encoding"), "{}", head);
+        assert!(!head.contains("Vary"), "{}", head);
+        assert_eq!(body.len(), 4096);
+
+        let (head, body) = raw("/files/", "gzip");
+        assert!(head.contains("Content-Encoding: gzip\r\n"), "{}", head);
+        assert!(head.contains(&format!("Content-Length: {}\r\n", body.len())));
+        let mut listing = String::new();
+        flate2::read::GzDecoder::new(&body[..])
+            .read_to_string(&mut listing)
+            .unwrap();
+        assert!(listing.contains("file_39.txt"));
+    }
+
+    #[test]
+    fn test_vary() {
+        let mut server = Server::new();
+        server.get("/", |request, mut response| {
+            let _ = response.set_header("Vary", "Cookie");
+            response.vary("cookie");
+            let _ = request.accepts_json();
+        });
+        server.get("/plain/", |_request, _response| {});
+
+        let response = server.test_request("GET", "/").send();
+        assert_eq!(response.header("Vary"), Some("Cookie, Accept"));
+        let response = server.test_request("GET", "/plain/").send();
+        assert_eq!(response.header("Vary"), None);
+    }
+
+    #[test]
+    fn test_prefers() {
+        let mut server = Server::new();
+        server.get("/", |request, mut response| {
+            let preferred = request.prefers(&["text/html", "application/json"]);
+            let _ = response.write(preferred.unwrap_or("none"));
+        });
+        let prefers = |accept: Option<&str>| {
+            let request = server.test_request("GET", "/");
+            match accept {
+                Some(accept) => request.header("Accept", accept),
+                None => request,
             }
-        }
+            .send()
+            .text()
+        };
+        assert_eq!(prefers(None), "text/html");
+        // Negotiating by Accept varies the response by it without being told
+        let response = server.test_request("GET", "/").send();
+        assert_eq!(response.header("Vary"), Some("Accept"));
+        assert_eq!(prefers(Some("application/json")), "application/json");
+        assert_eq!(prefers(Some("image/png")), "none");
+        assert_eq!(
+            prefers(Some("text/*;q=0.5, application/*")),
+            "application/json"
+        );
     }
 
-    fn parse_parameters(parameter_string: Option<&&str>) -> HashMap<String, String> {
-        let mut map = HashMap::new();
-        let parameters: Vec<&str> = if let Some(string) = parameter_string {
-            if string.is_empty() {
-                Vec::new()
-            } else {
-                string.split('&').collect()
+    #[test]
+    fn test_request_type_shortcuts() {
+        let request = |headers: &[(&str, &str)]| {
+            let mut builder = Request::builder();
+            for (name, value) in headers {
+                builder = builder.header(name, value);
             }
-        } else {
-            Vec::new()
+            builder.build()
         };
-        for param in parameters {
-            let kv_pair: Vec<&str> = param.split('=').collect();
-            map.insert(
-                kv_pair.get(0).unwrap_or(&"").to_string(),
-                kv_pair.get(1).unwrap_or(&"").to_string(),
+
+        let browser = request(&[(
+            "Accept",
+            "text/html,application/xhtml+xml,application/xml;q=0.9",
+        )]);
+        assert!(browser.accepts_html() && !browser.accepts_json());
+        let api = request(&[("Accept", "application/json, text/html;q=0")]);
+        assert!(api.accepts_json() && !api.accepts_html());
+        let anything = request(&[]);
+        assert!(anything.accepts_json() && anything.accepts_html());
+        assert_eq!(anything.content_type(), None);
+        assert!(!anything.is_form() && !anything.is_ajax());
+
+        let upload = request(&[
+            ("Content-Type", "multipart/form-data; boundary=\"--x y\""),
+            ("X-Requested-With", "XMLHttpRequest"),
+        ]);
+        let content_type = upload.content_type().unwrap();
+        assert_eq!(content_type.media_type(), "multipart/form-data");
+        assert_eq!(content_type.boundary(), Some("--x y"));
+        assert!(upload.is_form() && upload.is_ajax());
+        let form = request(&[(
+            "Content-Type",
+            "Application/X-WWW-Form-Urlencoded; charset=UTF-8",
+        )]);
+        assert!(form.is_form());
+        assert_eq!(form.content_type().unwrap().charset(), Some("UTF-8"));
+        assert!(!request(&[("Content-Type", "application/json")]).is_form());
+    }
+
+    #[test]
+    fn test_static_content_types() {
+        let root = temp_document_root("static_content_types");
+        fs::write(root.join("umlauts.txt"), "Grüße aus Konstanz").unwrap();
+        fs::write(root.join("latin1.txt"), b"Gr\xFC\xDFe").unwrap();
+        fs::write(root.join("page.html"), "<p>Hallo</p>").unwrap();
+        fs::write(root.join("data.json"), "{}").unwrap();
+        fs::write(root.join("logo.png"), b"\x89PNG\r\n\x1a\n").unwrap();
+        fs::write(root.join("README"), "no extension").unwrap();
+        fs::write(root.join("notes.md"), "# Notes").unwrap();
+        fs::write(root.join("diagram.drawio"), "<mxfile/>").unwrap();
+
+        let mut server = Server::new();
+        server.set_document_root(&format!("{}/", root.display()));
+        assert!(server.add_mime_type(".DrawIO", "application/vnd.jgraph.mxfile"));
+        assert!(!server.add_mime_type("tar.gz", "application/gzip"));
+        assert!(!server.add_mime_type("", "text/plain"));
+        assert!(!server.add_mime_type("bad", "text"));
+        assert!(!server.add_mime_type("bad", "text/plain\r\nX-Injected: 1"));
+        let content_type = |server: &Server, path: &str| {
+            server
+                .test_request("GET", path)
+                .send()
+                .header("Content-Type")
+                .map(String::from)
+        };
+        let expected = [
+            ("/umlauts.txt", Some("text/plain; charset=utf-8")),
+            ("/latin1.txt", Some("text/plain")),
+            ("/page.html", Some("text/html; charset=utf-8")),
+            ("/data.json", Some("application/json; charset=utf-8")),
+            ("/logo.png", Some("image/png")),
+            ("/README", Some("application/octet-stream")),
+            ("/notes.md", Some("text/markdown; charset=utf-8")),
+            ("/diagram.drawio", Some("application/vnd.jgraph.mxfile")),
+        ];
+        for (path, content_type_of) in expected.iter() {
+            assert_eq!(
+                content_type(&server, path).as_deref(),
+                *content_type_of,
+                "{}",
+                path
             );
         }
-        map
+
+        server.set_default_charset(None);
+        assert_eq!(
+            content_type(&server, "/umlauts.txt").as_deref(),
+            Some("text/plain")
+        );
     }
 
-    /// Handles a connection and writes to a TcpStream
-    fn handle_connection(&self, mut stream: TcpStream) {
-        let mut buffer = [0; 1024];
-        if let Err(e) = stream.read(&mut buffer) {
-            Logger::warning(&self.logger, format!("Error: {}", e).as_str())
-        }
+    #[test]
+    fn test_handler_charset() {
+        let mut server = Server::new();
+        server.get("/text/", |_request, mut response| {
+            let _ = response.set_header("Content-Type", "text/plain");
+        });
+        server.get("/latin1/", |_request, mut response| {
+            let _ = response.content_type_with_charset("text/plain", "iso-8859-1");
+        });
+        server.get("/image/", |_request, mut response| {
+            let _ = response.set_header("Content-Type", "image/png");
+        });
+        server.get("/none/", |_request, _response| {});
+
+        let content_type = |path| {
+            server
+                .test_request("GET", path)
+                .send()
+                .header("Content-Type")
+                .map(String::from)
+        };
+        assert_eq!(
+            content_type("/text/").as_deref(),
+            Some("text/plain; charset=utf-8")
+        );
+        assert_eq!(
+            content_type("/latin1/").as_deref(),
+            Some("text/plain; charset=iso-8859-1")
+        );
+        assert_eq!(content_type("/image/").as_deref(), Some("image/png"));
+        assert_eq!(content_type("/none/"), None);
+    }
 
-        if let Ok(s) = str::from_utf8(&buffer) {
-            let si = s.replace("\u{0}", "");
-            let header_lines: Vec<&str> = si.split("\r\n").collect();
-            let header: Vec<&str> = header_lines[0].split(' ').collect();
+    #[test]
+    fn test_default_headers() {
+        let root = temp_document_root("default_headers");
+        fs::write(root.join("static.txt"), "static").unwrap();
 
-            if header.len() > 1 {
-                let url_with_params: Vec<&str> = header[1].split('?').collect();
-                let request = String::from(url_with_params[0]);
+        let mut server = Server::new();
+        server.set_document_root(&format!("{}/", root.display()));
+        assert!(server.set_default_header("X-Frame-Options", "SAMEORIGIN"));
+        assert!(server.set_default_header("X-Service", "billing"));
+        assert!(server.set_default_header("x-frame-options", "DENY"));
+        assert!(!server.set_default_header("X-Evil", "a\r\nSet-Cookie: pwned=1"));
+        assert_eq!(server.default_headers().len(), 2);
+        server.get("/handler/", |_request, mut response| {
+            let _ = response.write("ok");
+        });
+        server.get("/override/", |_request, mut response| {
+            let _ = response.add_header("X-Service", "reports");
+        });
 
-                Logger::debug(
-                    &self.logger,
-                    &format!("header: {}, request: {}", header[0], request),
-                );
+        let handler = server.test_request("GET", "/handler/").send();
+        assert_eq!(handler.status(), 200);
+        assert_eq!(handler.header("X-Frame-Options"), Some("DENY"));
+        assert_eq!(handler.header("X-Service"), Some("billing"));
+        assert_eq!(handler.header("X-Evil"), None);
 
-                if let Some(callback) = self
-                    .registered_endpoints
-                    .lock()
-                    .unwrap()
-                    .get(&(request, header[0].to_string()))
-                {
-                    // User registered for this route, call their callback
-                    Logger::info(&self.logger, "Users custom route hit");
+        let overridden = server.test_request("GET", "/override/").send();
+        let services: Vec<_> = overridden
+            .headers()
+            .iter()
+            .filter(|(name, _)| name == "X-Service")
+            .collect();
+        assert_eq!(services.len(), 1);
+        assert_eq!(overridden.header("X-Service"), Some("reports"));
 
-                    let response = Response::new(stream);
-                    let mut request = Request::new();
-                    request.post_parameters = Server::parse_parameters(header_lines.last());
-                    request.query_parameters = Server::parse_parameters(url_with_params.get(1));
+        let static_file = server.test_request("GET", "/static.txt").send();
+        assert_eq!(static_file.text(), "static");
+        assert_eq!(static_file.header("X-Service"), Some("billing"));
 
-                    callback.deref()(request, response);
-                } else if let Some(path) = &self.document_root {
-                    self.serve_static_files(&mut stream, path, header[1]);
-                }
-            }
-        }
+        let not_found = server.test_request("GET", "/missing/").send();
+        assert_eq!(not_found.status(), 404);
+        assert_eq!(not_found.header("X-Frame-Options"), Some("DENY"));
+
+        assert!(server.remove_default_header("X-SERVICE"));
+        assert!(!server.remove_default_header("X-Service"));
+        let handler = server.test_request("GET", "/handler/").send();
+        assert_eq!(handler.header("X-Service"), None);
     }
 
-    /// Serves static files
-    fn serve_static_files(&self, stream: &mut TcpStream, path: &PathBuf, virtual_path: &str) {
-        let v_path = virtual_path.trim_start_matches('/');
+    #[test]
+    fn test_phase_timings() {
+        let root = temp_document_root("phase_timings");
+        let log = root.join("server.log");
+        let summaries = Arc::new(Mutex::new(Vec::new()));
+        let mut server = Server::new();
+        server.set_logger(&log.to_string_lossy());
+        server.get("/slow/", |_request, mut response| {
+            thread::sleep(Duration::from_millis(50));
+            let _ = response.write("done");
+        });
+        let recorded = summaries.clone();
+        server.on_request_complete(move |summary| recorded.lock().unwrap().push(summary.clone()));
 
-        let mut write_to_stream = |bytes| {
-            if let Err(e) = stream.write_all(bytes) {
-                Logger::warning(&self.logger, format!("Error: {}", e).as_str());
-            }
-            if let Err(e) = stream.flush() {
-                Logger::warning(&self.logger, format!("Error: {}", e).as_str());
+        server.test_request("GET", "/slow/").send();
+        server.set_phase_timings(true);
+        assert_eq!(server.test_request("GET", "/slow/").send().text(), "done");
+
+        let summaries = summaries.lock().unwrap();
+        assert_eq!(summaries.len(), 2);
+        assert_eq!(summaries[0].timings, None);
+        assert_eq!(summaries[1].method, "GET");
+        assert_eq!(summaries[1].path, "/slow/");
+        let timings = summaries[1].timings.unwrap();
+        assert!(timings.handler >= Duration::from_millis(50));
+        assert!(timings.handler > timings.parse + timings.route + timings.write);
+        let contents = fs::read_to_string(&log).unwrap();
+        assert_eq!(contents.matches("phase timings: parse=").count(), 1);
+    }
+
+    #[test]
+    fn test_json_request_log() {
+        let root = temp_document_root("json_request_log");
+        let log = root.join("server.log");
+        let mut logger = Logger::new(&log.to_string_lossy());
+        logger.set_format(LogFormat::Json);
+        let mut server = Server::new();
+        server.use_logger(logger);
+        server.get("/items/", |_request, mut response| {
+            let _ = response.set_status_code(201);
+            let _ = response.write("created");
+        });
+        server.test_request("GET", "/items/").send();
+
+        let contents = fs::read_to_string(&log).unwrap();
+        let entries: Vec<serde_json::Value> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let entry = entries
+            .iter()
+            .find(|entry| entry["msg"] == "Request complete")
+            .unwrap();
+        assert_eq!(entry["level"], "debug");
+        assert_eq!(entry["fields"]["method"], "GET");
+        assert_eq!(entry["fields"]["path"], "/items/");
+        assert_eq!(entry["fields"]["status"], "201");
+        let duration: f64 = entry["fields"]["duration_ms"]
+            .as_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(duration >= 0.0);
+    }
+
+    #[test]
+    fn test_managed_state() {
+        struct Name(&'static str);
+        let counter = Arc::new(AtomicUsize::new(0));
+        let mut server = Server::new();
+        server.manage(counter.clone());
+        server.manage(Name("old"));
+        server.manage(Name("new"));
+        for path in ["/a/", "/b/"] {
+            server.get(path, |request, mut response| {
+                let counter = request.state::<Arc<AtomicUsize>>().unwrap();
+                let count = counter.fetch_add(1, Ordering::Relaxed) + 1;
+                let name = request.state::<Name>().unwrap().0;
+                let _ = response.write(&format!("{} {}", name, count));
+            });
+        }
+        server.get("/unmanaged/", |request, mut response| {
+            let _ = response.write(&format!("{:?}", request.state::<String>()));
+        });
+
+        assert_eq!(server.test_request("GET", "/a/").send().text(), "new 1");
+        assert_eq!(server.test_request("GET", "/b/").send().text(), "new 2");
+        let port = spawn_server(server);
+        let response = raw_request(port, "GET /a/ HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(response.ends_with("new 3"), "{}", response);
+        assert_eq!(counter.load(Ordering::Relaxed), 3);
+        let response = raw_request(port, "GET /unmanaged/ HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(response.ends_with("None"), "{}", response);
+        assert!(Request::builder().build().state::<Name>().is_none());
+    }
+
+    #[test]
+    fn test_hooks() {
+        #[derive(Debug, PartialEq)]
+        struct Tag(&'static str);
+        let observed = Arc::new(Mutex::new(Vec::new()));
+        let mut server = Server::new();
+        server.before(|request| request.set_extension(Tag("first")));
+        server.before(|request| {
+            if request.path() == "/tagged/" {
+                request.set_extension(Tag("second"));
             }
-        };
+        });
+        let recorded = observed.clone();
+        server.after(move |request, meta| {
+            let tag = request.get_extension::<Tag>().map(|tag| tag.0);
+            recorded
+                .lock()
+                .unwrap()
+                .push((String::from(request.path()), tag, *meta));
+        });
+        server.get("/tagged/", |request, mut response| {
+            assert_eq!(request.get_extension::<Tag>(), Some(&Tag("second")));
+            assert_eq!(request.get_extension::<u32>(), None);
+            thread::sleep(Duration::from_millis(30));
+            let _ = response.set_status_code(201);
+            let _ = response.write("created");
+        });
+        server.get("/timed/", |_request, mut response| {
+            let _ = response.set_header("Server-Timing", "db;dur=3");
+            let _ = response.write("own");
+        });
 
-        let requested_path = format!(
-            "{}{}",
-            path.clone().into_os_string().into_string().unwrap(),
-            v_path,
+        let tagged = server.test_request("GET", "/tagged/").send();
+        assert_eq!(tagged.header("Server-Timing"), None);
+        server.test_request("GET", "/missing/").send();
+        let observed = observed.lock().unwrap().clone();
+        assert_eq!(observed.len(), 2);
+        let (path, tag, meta) = &observed[0];
+        assert_eq!((path.as_str(), *tag), ("/tagged/", Some("second")));
+        assert_eq!(meta.status, Some(201));
+        assert_eq!(meta.bytes_written, 7);
+        assert!(meta.duration >= Duration::from_millis(30));
+        let (path, tag, meta) = &observed[1];
+        assert_eq!((path.as_str(), *tag), ("/missing/", Some("first")));
+        assert_eq!(meta.status, Some(404));
+
+        server.set_server_timing(true);
+        let tagged = server.test_request("GET", "/tagged/").send();
+        let timing = tagged.header("Server-Timing").unwrap();
+        let took: f64 = timing.strip_prefix("app;dur=").unwrap().parse().unwrap();
+        assert!(took >= 30.0, "{}", timing);
+        let timed = server.test_request("GET", "/timed/").send();
+        assert_eq!(timed.header("Server-Timing"), Some("db;dur=3"));
+    }
+
+    #[test]
+    fn test_handler_timeout() {
+        let root = temp_document_root("handler_timeout");
+        let log = root.join("server.log");
+        let (late_write, late_result) = mpsc::channel();
+        let mut server = Server::new();
+        server.set_logger(&log.to_string_lossy());
+        server.set_handler_timeout(Duration::from_secs(10));
+        server.get_with_timeout(
+            "/hang/",
+            Duration::from_millis(100),
+            move |_request, mut response| {
+                thread::sleep(Duration::from_millis(400));
+                late_write.send(response.write("too late")).unwrap();
+            },
         );
+        server.get("/quick/", |_request, mut response| {
+            let _ = response.set_header("X-Quick", "yes");
+            let _ = response.write("in time");
+        });
+        let metrics = server.metrics();
 
-        if Path::new(&requested_path).exists() {
-            if Path::new(&requested_path).is_file() {
-                Logger::info(
-                    &self.logger,
-                    &format!("Requested file {} exists", requested_path),
-                );
-                let mut buf = Vec::new();
-                match File::open(&requested_path) {
-                    Ok(mut content) => {
-                        match content.read_to_end(&mut buf) {
-                            Ok(bytes_read) => {
-                                Logger::info(
-                                    &self.logger,
-                                    format!("\t{} bytes were read", bytes_read).as_str(),
-                                );
-                            }
-                            Err(e) => {
-                                Logger::warning(&self.logger, format!("Error: {}", e).as_str());
-                            }
-                        };
-                    }
-                    Err(e) => {
-                        Logger::warning(&self.logger, format!("Error: {}", e).as_str());
-                    }
-                };
-                let ok = String::from("HTTP/1.1 200 OK\r\n\r\n");
-                let response = ok.as_bytes();
-                write_to_stream(&[response, buf.as_slice()].concat());
-            } else if Path::new(&requested_path).is_dir() && self.index_of {
-                Logger::info(
-                    &self.logger,
-                    &format!("Requested path {} is directory", requested_path),
-                );
-                let index_of = Server::generate_index_of(&requested_path, v_path);
-                write_to_stream(format!("HTTP/1.1 200 OK\r\n\r\n{}", index_of).as_bytes());
-            }
-        } else {
-            Logger::info(&self.logger, "Status 404: Not found");
-            write_to_stream(
-                format!(
-                    "HTTP/1.1 404 NOT FOUND\r\n\r\n{}",
-                    "<html><h1>404 not found</h1><hr> powered by corrodedweb</html>"
-                )
-                .as_bytes(),
-            );
+        let started = Instant::now();
+        let hung = server.test_request("GET", "/hang/").send();
+        assert!(started.elapsed() < Duration::from_millis(300));
+        assert_eq!(hung.status(), 504);
+        assert_eq!(metrics.handler_timeouts(), 1);
+        assert_eq!(metrics.zombie_handlers(), 1);
+
+        let quick = server.test_request("GET", "/quick/").send();
+        assert_eq!(quick.status(), 200);
+        assert_eq!(quick.header("X-Quick"), Some("yes"));
+        assert_eq!(quick.text(), "in time");
+
+        let error = late_result.recv().unwrap().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        while metrics.zombie_handlers() > 0 {
+            thread::sleep(Duration::from_millis(10));
         }
+        assert_eq!(metrics.handler_timeouts(), 1);
+        let contents = fs::read_to_string(&log).unwrap();
+        assert!(contents.contains("Status 504: handler of GET /hang/ took longer than 100ms"));
     }
 
-    fn generate_index_of(path: &str, virtual_path: &str) -> String {
-        let paths = fs::read_dir(path).unwrap();
-        let mut index_of = String::new();
-        index_of.push_str(&format!(
-            "<html>Index of <b>/{}</b><br><br><ul>",
-            virtual_path
-        ));
-        index_of.push_str("<li><a href='..'>..</li>");
-        for path in paths {
-            let path = path.unwrap().path();
-            let file_name = &path.file_name().unwrap().to_string_lossy();
-            index_of.push_str(&format!(
-                "<li><a href='{}/{}'>{}</li>",
-                virtual_path, file_name, file_name
-            ));
+    #[test]
+    fn test_body_reader() {
+        const UPLOAD: usize = 10 * 1024 * 1024;
+        const HEAD_START: usize = 1024 * 1024;
+        // FNV-1a, which does not care how the bytes are split up
+        fn hash(mut hash: u64, bytes: &[u8]) -> u64 {
+            for &byte in bytes {
+                hash = (hash ^ u64::from(byte)).wrapping_mul(0x100_0000_01b3);
+            }
+            hash
         }
-        index_of.push_str("</ul></html>");
-        index_of
-    }
-}
+        const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
 
-impl Default for Server {
-    fn default() -> Self {
-        Server {
-            document_root: None,
-            logger: None,
-            index_of: false,
-            registered_endpoints: Arc::new(Mutex::new(HashMap::new())),
+        let (started, handler_started) = mpsc::channel();
+        let started = Mutex::new(started);
+        let mut server = Server::new();
+        server.post("/upload/", move |mut request, mut response| {
+            let mut body = request.body_reader().unwrap();
+            let mut piece = vec![0; 64 * 1024];
+            let (mut len, mut digest) = (0, FNV_OFFSET);
+            loop {
+                let read = body.read(&mut piece).unwrap();
+                if read == 0 {
+                    break;
+                }
+                if len < HEAD_START && len + read >= HEAD_START {
+                    let _ = started.lock().unwrap().send(());
+                }
+                len += read;
+                digest = hash(digest, &piece[..read]);
+            }
+            let _ = response.write(&format!("{} {:x}", len, digest));
+        });
+        server.post("/ignore/", |_request, mut response| {
+            let _ = response.write("ignored");
+        });
+        let port = spawn_server(server);
+
+        let upload: Vec<u8> = (0..UPLOAD).map(|i| (i % 251) as u8).collect();
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        write!(
+            stream,
+            "POST /upload/ HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n",
+            UPLOAD
+        )
+        .unwrap();
+        // The handler reads while the rest is held back, nothing waits for
+        // the whole body
+        stream.write_all(&upload[..HEAD_START]).unwrap();
+        handler_started
+            .recv_timeout(Duration::from_secs(10))
+            .expect("the handler did not get the start of the body");
+        stream.write_all(&upload[HEAD_START..]).unwrap();
+        let response = read_delimited_response(&mut stream);
+        let expected = format!("{} {:x}", UPLOAD, hash(FNV_OFFSET, &upload));
+        assert!(response.ends_with(&expected), "{}", response);
+
+        // With keep-alive, a short body left unread is skipped and the
+        // connection goes on, after a long one it closes
+        #[cfg(feature = "tokio")]
+        {
+            let ignored = format!(
+                "POST /ignore/ HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n",
+                100_000
+            );
+            stream.write_all(ignored.as_bytes()).unwrap();
+            stream.write_all(&upload[..100_000]).unwrap();
+            assert!(read_delimited_response(&mut stream).ends_with("\r\n\r\nignored"));
+
+            let chunked = "POST /upload/ HTTP/1.1\r\nHost: localhost\r\n\
+                           Transfer-Encoding: chunked\r\n\r\n\
+                           5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n";
+            stream.write_all(chunked.as_bytes()).unwrap();
+            let expected = format!("11 {:x}", hash(FNV_OFFSET, b"hello world"));
+            assert!(read_delimited_response(&mut stream).ends_with(&expected));
+
+            let long = 2 * 1024 * 1024;
+            let ignored = format!(
+                "POST /ignore/ HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n",
+                long
+            );
+            stream.write_all(ignored.as_bytes()).unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            let rest = upload[..long].to_vec();
+            thread::spawn(move || {
+                let _ = writer.write_all(&rest);
+            });
+            assert!(read_delimited_response(&mut stream).ends_with("\r\n\r\nignored"));
+            assert!(!matches!(stream.read(&mut [0]), Ok(1)));
         }
     }
-}
 
-impl Clone for Server {
-    fn clone(&self) -> Self {
-        Server {
-            document_root: self.document_root.clone(),
-            logger: self.logger.clone(),
-            index_of: self.index_of,
-            registered_endpoints: self.registered_endpoints.clone(),
-        }
+    #[test]
+    fn test_body_reader_excludes_buffered_body() {
+        let form = || {
+            Request::builder()
+                .method("POST")
+                .header("Content-Type", "application/x-www-form-urlencoded")
+                .body(b"a=1")
+                .build()
+        };
+        let mut request = form();
+        let mut body = String::new();
+        request
+            .body_reader()
+            .unwrap()
+            .read_to_string(&mut body)
+            .unwrap();
+        assert_eq!(body, "a=1");
+        assert!(request.body_reader().is_err());
+        assert!(request.raw_body().is_empty());
+        assert_eq!(request.form("a"), None);
+
+        let mut request = form();
+        assert_eq!(request.form("a"), Some("1"));
+        assert!(request.body_reader().is_err());
+
+        let mut server = Server::new();
+        server.post("/echo/", |mut request, mut response| {
+            let mut body = Vec::new();
+            let read = request
+                .body_reader()
+                .and_then(|mut reader| reader.read_to_end(&mut body));
+            match read {
+                Ok(_) => {
+                    let _ = response.write(str::from_utf8(&body).unwrap());
+                }
+                Err(e) => {
+                    let _ = response.set_status_code(400);
+                    let _ = response.write(&e.to_string());
+                }
+            }
+        });
+        let echoed = server
+            .test_request("POST", "/echo/")
+            .header("Transfer-Encoding", "chunked")
+            .body("3\r\nabc\r\n2;note=x\r\nde\r\n0\r\n\r\n")
+            .send();
+        assert_eq!((echoed.status(), echoed.text().as_str()), (200, "abcde"));
+        let long = "x".repeat(20_000);
+        let echoed = server.test_request("POST", "/echo/").body(&long).send();
+        assert_eq!(echoed.text(), long);
+        let truncated = server
+            .test_request("POST", "/echo/")
+            .header("Content-Length", "10")
+            .body("short")
+            .send();
+        assert_eq!(truncated.status(), 400);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::thread;
+    #[test]
+    fn test_save_files() {
+        let dir = temp_document_root("save_files");
+        let upload_dir = dir.clone();
+        let mut server = Server::new();
+        server.post("/upload/", move |mut request, mut response| {
+            let options = SaveOptions {
+                max_size: 50_000,
+                sync: true,
+            };
+            match request.save_files_with(&upload_dir, &options) {
+                Ok(files) => {
+                    for file in files {
+                        let line = format!(
+                            "{} {} {} {}\n",
+                            file.name,
+                            file.original_filename.unwrap(),
+                            file.len,
+                            file.path.display()
+                        );
+                        let _ = response.write(&line);
+                    }
+                }
+                Err(e) => {
+                    let _ = response.set_status_code(400);
+                    let _ = response.write(&e.to_string());
+                }
+            }
+        });
+        let upload = |files: &[(&str, &str)]| {
+            let mut body = String::from(
+                "--b0undary\r\nContent-Disposition: form-data; name=\"note\"\r\n\r\nhi\r\n",
+            );
+            for (filename, data) in files {
+                body.push_str(&format!(
+                    "--b0undary\r\n\
+                     Content-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\n\
+                     Content-Type: application/octet-stream\r\n\r\n{}\r\n",
+                    filename, data
+                ));
+            }
+            body.push_str("--b0undary--\r\n");
+            server
+                .test_request("POST", "/upload/")
+                .header("Content-Type", "multipart/form-data; boundary=b0undary")
+                .body(&body)
+                .send()
+        };
+
+        let large = "0123456789".repeat(3_000);
+        let response = upload(&[("report.txt", "first file"), ("../../evil.sh", &large)]);
+        assert_eq!(response.status(), 200);
+        let text = response.text();
+        let lines: Vec<Vec<&str>> = text.lines().map(|line| line.split(' ').collect()).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0][..3], ["file", "report.txt", "10"]);
+        assert_eq!(lines[1][..3], ["file", "../../evil.sh", "30000"]);
+        for (line, data) in lines.iter().zip(&["first file", large.as_str()]) {
+            let path = Path::new(line[3]);
+            assert_eq!(path.parent(), Some(dir.as_path()));
+            assert_eq!(fs::read_to_string(path).unwrap(), *data);
+        }
+        assert!(lines[0][3].ends_with(".txt"));
+        assert!(lines[1][3].ends_with(".sh"));
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+        // Too large a file removes what was saved before, partial files too
+        let too_large = "x".repeat(60_000);
+        let response = upload(&[("a.txt", "fine"), ("b.txt", &too_large)]);
+        assert_eq!(response.status(), 400);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+        let response = server
+            .test_request("POST", "/upload/")
+            .header("Content-Type", "text/plain")
+            .body("no files")
+            .send();
+        assert_eq!(response.status(), 400);
+        let _ = fs::remove_dir_all(&dir);
+    }
 
     #[test]
-    fn test_get() {
+    fn test_debug_dump() {
+        let root = temp_document_root("debug_dump");
+        let log = root.join("server.log");
         let mut server = Server::new();
-        server.get("/", |request, mut response| {
-            let parameters = request.get_query_parameters();
-            assert_eq!(parameters.get("param1"), Some(&String::from("hello")));
-            assert_eq!(parameters.get("param2"), Some(&String::from("1234")));
-            let _ = response.set_status_code(200);
-            let _ = response.write("123456789");
+        server.set_logger(&log.to_string_lossy());
+        server.post("/login/", |_request, mut response| {
+            let _ = response.set_header("Set-Cookie", "session=s3cr3t");
+            let _ = response.write("welcome back");
         });
+        let login = |server: &Server| {
+            server
+                .test_request("POST", "/login/")
+                .header("Authorization", "Basic dXNlcjpwYXNz")
+                .header("Cookie", "theme=dark")
+                .body("user=max&password=hunter2")
+                .send()
+        };
 
-        thread::spawn(move || {
-            server.start_server(7878);
+        login(&server);
+        assert!(!fs::read_to_string(&log).unwrap().contains("DUMP"));
+
+        server.set_debug_dump(true);
+        server.set_debug_dump_body_limit(16);
+        assert_eq!(login(&server).text(), "welcome back");
+        let contents = fs::read_to_string(&log).unwrap();
+        assert!(contents.contains("DEBUG ("), "{}", contents);
+        assert!(
+            contents.contains("DUMP Request from unknown\nPOST /login/ HTTP/1.1\n"),
+            "{}",
+            contents
+        );
+        assert!(contents.contains("Authorization: [redacted]\n"));
+        assert!(contents.contains("Cookie: [redacted]\n"));
+        assert!(contents.contains("Body of 25 bytes, the first 16:\n00000000  75 73 65 72"));
+        assert!(contents.contains("|user=max&passwor|"));
+        assert!(contents.contains("DUMP Response\nHTTP/1.1 200 OK\n"));
+        assert!(contents.contains("\nX-Connection-Id: "));
+        assert!(contents.contains("\nX-Request-Id: "));
+        assert!(contents.contains("Set-Cookie: [redacted]\n"));
+        assert!(contents.contains("Body of 12 bytes:\n"));
+        assert!(contents.contains("|welcome back|"));
+        assert!(!contents.contains("dXNlcjpwYXNz"));
+        assert!(!contents.contains("s3cr3t"));
+
+        server.set_debug_dump_unredacted(true);
+        login(&server);
+        let contents = fs::read_to_string(&log).unwrap();
+        assert!(contents.contains("Authorization: Basic dXNlcjpwYXNz\n"));
+        assert!(contents.contains("Set-Cookie: session=s3cr3t\n"));
+    }
+
+    #[test]
+    fn test_handler_panic() {
+        let root = temp_document_root("handler_panic");
+        let log = root.join("server.log");
+        let mut server = Server::new();
+        server.set_logger(&log.to_string_lossy());
+        server.set_workers(1);
+        server.get("/users/:id/", |request, mut response| {
+            let _ = response.write("partial");
+            let _ = response.write(request.get_header("X-Missing").unwrap());
+        });
+        server.get("/up/", |_request, mut response| {
+            let _ = response.write("up");
         });
 
-        loop {
-            if let Ok(mut resp) = reqwest::get("http://localhost:7878/?param1=hello&param2=1234") {
-                assert_eq!(resp.status().is_success(), true);
-                assert_eq!(resp.text().unwrap(), String::from("123456789"));
-                break;
-            }
+        let response = server.test_request("GET", "/users/42/").send();
+        assert_eq!(response.status(), 500);
+        assert_eq!(response.text(), "");
+        let contents = fs::read_to_string(&log).unwrap();
+        let entry = contents
+            .split("\nINFO")
+            .find(|entry| entry.contains("ERROR ("))
+            .unwrap();
+        assert!(
+            entry.contains("Handler of GET /users/:id/ panicked serving /users/42/: "),
+            "{}",
+            entry
+        );
+        assert!(entry.contains("called `Option::unwrap()` on a `None` value\nat src/server.rs:"));
+
+        // The only worker survives, however often its handler panics
+        let port = spawn_server(server);
+        for _ in 0..9 {
+            let response = raw_request(port, "GET /users/42/ HTTP/1.1\r\nHost: localhost\r\n\r\n");
+            assert!(response.starts_with("HTTP/1.1 500"), "{}", response);
+            let response = raw_request(port, "GET /up/ HTTP/1.1\r\nHost: localhost\r\n\r\n");
+            assert!(response.ends_with("up"));
         }
     }
 
     #[test]
-    fn test_post() {
+    fn test_error_handler() {
         let mut server = Server::new();
-        server.post("/post/", |_request, mut response| {
-            let _ = response.set_status_code(200);
-            let _ = response.write("123456789");
+        server.get("/broken/", |_request, mut response| {
+            let _ = response.add_header("X-Lost", "yes");
+            panic!("broken");
         });
-
-        thread::spawn(move || {
-            server.start_server(7879);
+        server.on_error(|request, mut response| {
+            let _ = response.write(&format!("{} failed", request.path()));
         });
 
-        loop {
-            let client = reqwest::Client::new();
-            if let Ok(mut resp) = client.post("http://localhost:7879/post/").send() {
-                assert_eq!(resp.status().is_success(), true);
-                assert_eq!(resp.text().unwrap(), String::from("123456789"));
-                break;
+        let response = server.test_request("GET", "/broken/").send();
+        assert_eq!(response.status(), 500);
+        assert_eq!(response.text(), "/broken/ failed");
+        assert_eq!(response.header("X-Lost"), None);
+
+        let port = spawn_server(server.clone());
+        let response = raw_request(port, "GET /broken/ HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 500"), "{}", response);
+        assert!(response.ends_with("/broken/ failed"), "{}", response);
+
+        // Handlers with a timeout panic on a thread of their own
+        server.set_handler_timeout(Duration::from_secs(5));
+        let response = server.test_request("GET", "/broken/").send();
+        assert_eq!(response.status(), 500);
+        assert_eq!(response.text(), "/broken/ failed");
+    }
+
+    #[test]
+    fn test_asset_fingerprinting() {
+        let root = temp_document_root("asset_fingerprinting");
+        fs::create_dir(root.join("static")).unwrap();
+        fs::write(root.join("static/app.css"), "body {}").unwrap();
+        fs::write(root.join("plain.css"), "p {}").unwrap();
+        let mut server = Server::new();
+        server.set_document_root(&format!("{}/", root.display()));
+        assert!(!server.enable_asset_fingerprinting("static/"));
+        assert_eq!(server.asset_url("/static/app.css"), "/static/app.css");
+        assert!(server.enable_asset_fingerprinting("/static/"));
+        let handle = server.handle();
+
+        let url = server.asset_url("/static/app.css");
+        assert_eq!(handle.asset_url("/static/app.css"), url);
+        let hash = url
+            .strip_prefix("/static/app.")
+            .and_then(|rest| rest.strip_suffix(".css"))
+            .unwrap();
+        assert!(hash.len() == 10 && hash.bytes().all(|byte| byte.is_ascii_hexdigit()));
+        assert_eq!(server.asset_url("/plain.css"), "/plain.css");
+        assert_eq!(
+            server.asset_url("/static/missing.css"),
+            "/static/missing.css"
+        );
+
+        let asset = server.test_request("GET", &url).send();
+        assert_eq!((asset.status(), asset.text().as_str()), (200, "body {}"));
+        assert_eq!(
+            asset.header("Cache-Control"),
+            Some("public, max-age=31536000, immutable")
+        );
+        let plain = server.test_request("GET", "/static/app.css").send();
+        assert_eq!((plain.status(), plain.header("Cache-Control")), (200, None));
+
+        // A changed file gets a new URL, the old one is stale
+        fs::write(root.join("static/app.css"), "body { margin: 0 }").unwrap();
+        let new_url = server.asset_url("/static/app.css");
+        assert_ne!(new_url, url);
+        assert_eq!(server.test_request("GET", &url).send().status(), 404);
+        assert_eq!(server.test_request("GET", &new_url).send().status(), 200);
+        server.set_stale_asset_policy(StaleAssetPolicy::Redirect);
+        let stale = server.test_request("GET", &format!("{}?v=1", url)).send();
+        assert_eq!(stale.status(), 302);
+        assert_eq!(
+            stale.header("Location"),
+            Some(format!("{}?v=1", new_url).as_str())
+        );
+        let missing = server
+            .test_request("GET", "/static/missing.0123456789.css")
+            .send();
+        assert_eq!(missing.status(), 404);
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_static_precedence() {
+        let root = temp_document_root("static_precedence");
+        fs::write(root.join("index.html"), "file").unwrap();
+        let log = root.join("server.log");
+        let precedence_server = |precedence| {
+            let mut server = Server::new();
+            server.set_document_root(&format!("{}/", root.display()));
+            server.set_logger(&log.to_string_lossy());
+            server.set_static_precedence(precedence);
+            for route in ["/index.html", "/:page", "/missing.html"] {
+                server.get(route, |_request, mut response| {
+                    let _ = response.write("route");
+                });
             }
-        }
+            server.post("/index.html", |_request, mut response| {
+                let _ = response.write("route");
+            });
+            server
+        };
+        let text = |server: &Server, method: &str, path: &str| {
+            server.test_request(method, path).send().text()
+        };
+
+        let server = precedence_server(StaticPrecedence::RoutesFirst);
+        assert_eq!(text(&server, "GET", "/index.html"), "route");
+        spawn_server(server);
+        let contents = fs::read_to_string(&log).unwrap();
+        let warnings: Vec<&str> = contents
+            .lines()
+            .filter(|line| line.starts_with("WARNING"))
+            .collect();
+        assert_eq!(warnings.len(), 1, "{}", contents);
+        assert!(warnings[0].contains("Route GET /index.html has the path of the file "));
+        assert!(warnings[0].ends_with("index.html, the route answers"));
+
+        let server = precedence_server(StaticPrecedence::StaticFirst);
+        assert_eq!(text(&server, "GET", "/index.html"), "file");
+        assert_eq!(text(&server, "POST", "/index.html"), "route");
+        assert_eq!(text(&server, "GET", "/missing.html"), "route");
+        assert_eq!(text(&server, "GET", "/other.html"), "route");
+        spawn_server(server);
+        let contents = fs::read_to_string(&log).unwrap();
+        assert!(contents.contains("index.html, the file answers\n"));
+    }
+
+    #[test]
+    fn test_trailing_slash_policy() {
+        let root = temp_document_root("trailing_slash");
+        fs::create_dir(root.join("docs")).unwrap();
+        fs::write(root.join("docs").join("index.html"), "docs").unwrap();
+        let slash_server = |policy| {
+            let mut server = Server::new();
+            server.set_document_root(&format!("{}/", root.display()));
+            server.set_trailing_slash_policy(policy);
+            server.route("GET", "/counter/", |request, mut response| {
+                let _ = response.write(request.path());
+            });
+            server.post("/counter/", |_request, _response| {});
+            server.get("/plain", |request, mut response| {
+                let _ = response.write(request.path());
+            });
+            server.get("/users/:id/", |request, mut response| {
+                let _ = response.write(request.path_param_str("id").unwrap());
+            });
+            server
+        };
+        let answer = |server: &Server, method: &str, path: &str| {
+            let response = server.test_request(method, path).send();
+            let location = response.header("Location").map(String::from);
+            (response.status(), location, response.text())
+        };
+        let ok = |text: &str| (200, None, String::from(text));
+
+        let server = slash_server(TrailingSlashPolicy::Match);
+        assert_eq!(answer(&server, "GET", "/counter"), ok("/counter/"));
+        assert_eq!(answer(&server, "GET", "/counter/"), ok("/counter/"));
+        assert_eq!(answer(&server, "GET", "/plain/"), ok("/plain"));
+        assert_eq!(answer(&server, "GET", "/users/7"), ok("7"));
+        assert_eq!(answer(&server, "GET", "/docs"), ok("docs"));
+        assert_eq!(answer(&server, "GET", "/other").0, 404);
+
+        let server = slash_server(TrailingSlashPolicy::Redirect);
+        let redirect = |status, location: &str| (status, Some(String::from(location)));
+        let redirected = |server: &Server, method: &str, path: &str| {
+            let (status, location, _) = answer(server, method, path);
+            (status, location)
+        };
+        assert_eq!(
+            redirected(&server, "GET", "/counter?a=1"),
+            redirect(301, "/counter/?a=1")
+        );
+        assert_eq!(
+            redirected(&server, "POST", "/counter"),
+            redirect(308, "/counter/")
+        );
+        assert_eq!(
+            redirected(&server, "GET", "/plain/"),
+            redirect(301, "/plain")
+        );
+        assert_eq!(redirected(&server, "GET", "/docs"), redirect(301, "/docs/"));
+        assert_eq!(answer(&server, "GET", "/docs/"), ok("docs"));
+        assert_eq!(answer(&server, "GET", "/counter/"), ok("/counter/"));
+        // The root never becomes the empty path
+        let response = server.test_request("GET", "/").send();
+        assert_eq!(response.header("Location"), None);
+
+        let server = slash_server(TrailingSlashPolicy::Strict);
+        assert_eq!(answer(&server, "GET", "/counter").0, 404);
+        assert_eq!(answer(&server, "GET", "/plain/").0, 404);
+        assert_eq!(answer(&server, "GET", "/docs").0, 404);
+        assert_eq!(answer(&server, "GET", "/docs/"), ok("docs"));
     }
 }
@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The cookie carrying the session id
+pub(crate) const COOKIE_NAME: &str = "corroded_session";
+
+/// How long a session lives without requests unless configured otherwise
+pub(crate) const DEFAULT_EXPIRY: Duration = Duration::from_secs(30 * 60);
+
+/// The values a session keeps
+type Values = Arc<Mutex<HashMap<String, String>>>;
+
+/// The values of one client, kept on the server across its requests, see
+/// `Server::enable_sessions`
+///
+/// Values are stored as strings, `get` parses them back into the type
+/// asked for. Changes are seen by all later requests of the session right
+/// away.
+///
+/// A client without a session gets an empty one that is only kept, and
+/// its cookie only set, once a value is inserted. The cookie goes out with
+/// the head of the response, so insert before streaming the body.
+#[derive(Clone)]
+pub struct Session {
+    id: String,
+    values: Values,
+    /// Set for a session the client did not have before
+    new: Option<Arc<NewSession>>,
+}
+
+/// Where a new session is kept once it has values
+struct NewSession {
+    store: SessionStore,
+    stored: AtomicBool,
+}
+
+impl Session {
+    /// Returns the value of `key` parsed into `T`, `None` if it is not set
+    /// or does not parse
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut s = corrodedweb::Server::new();
+    /// s.enable_sessions();
+    /// s.get("/visits/", |request, mut response| {
+    ///     if let Some(session) = request.session() {
+    ///         let visits = session.get::<u32>("visits").unwrap_or(0) + 1;
+    ///         session.insert("visits", visits);
+    ///         let _ = response.write(&format!("visit {}", visits));
+    ///     }
+    /// });
+    /// ```
+    pub fn get<T: FromStr>(&self, key: &str) -> Option<T> {
+        self.values.lock().unwrap().get(key)?.parse().ok()
+    }
+    /// Sets `key` to `value`, replacing what it was before
+    pub fn insert<T: ToString>(&self, key: &str, value: T) {
+        self.values
+            .lock()
+            .unwrap()
+            .insert(String::from(key), value.to_string());
+        if let Some(new) = &self.new {
+            if !new.stored.swap(true, Ordering::AcqRel) {
+                new.store.keep(self);
+            }
+        }
+    }
+    /// Removes `key`, returning what it was
+    pub fn remove(&self, key: &str) -> Option<String> {
+        self.values.lock().unwrap().remove(key)
+    }
+
+    /// Returns whether the client did not have the session before
+    pub(crate) fn is_new(&self) -> bool {
+        self.new.is_some()
+    }
+
+    /// Returns whether the session is new and was kept, so the client has
+    /// to be given its cookie
+    pub(crate) fn started(&self) -> bool {
+        self.new
+            .as_ref()
+            .is_some_and(|new| new.stored.load(Ordering::Acquire))
+    }
+}
+
+/// Shows the keys only, values and the id may be credentials
+impl fmt::Debug for Session {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let values = self.values.lock().unwrap();
+        let mut keys: Vec<&String> = values.keys().collect();
+        keys.sort();
+        f.debug_struct("Session").field("keys", &keys).finish()
+    }
+}
+
+/// The sessions of all clients, shared by all workers
+#[derive(Clone)]
+pub(crate) struct SessionStore {
+    expiry: Duration,
+    sessions: Arc<Mutex<Sessions>>,
+}
+
+struct Sessions {
+    /// Keyed by id, with when the session was last used
+    by_id: HashMap<String, (Instant, Values)>,
+    /// When expired sessions were last removed
+    purged: Instant,
+}
+
+impl SessionStore {
+    pub fn new(expiry: Duration) -> Self {
+        SessionStore {
+            expiry,
+            sessions: Arc::new(Mutex::new(Sessions {
+                by_id: HashMap::new(),
+                purged: Instant::now(),
+            })),
+        }
+    }
+
+    /// Returns the session with the id `cookie`, or a new one if there is
+    /// none or it expired, which is kept once a value is inserted
+    ///
+    /// Fails if the system has no random numbers for a new id.
+    pub fn session(&self, cookie: Option<&str>) -> Result<Session, getrandom::Error> {
+        if let Some(id) = cookie {
+            let mut sessions = self.sessions.lock().unwrap();
+            let now = Instant::now();
+            match sessions.by_id.get_mut(id) {
+                Some((last_used, values)) if now.duration_since(*last_used) < self.expiry => {
+                    *last_used = now;
+                    return Ok(Session {
+                        id: String::from(id),
+                        values: values.clone(),
+                        new: None,
+                    });
+                }
+                _ => {}
+            }
+        }
+        Ok(Session {
+            id: new_id()?,
+            values: Values::default(),
+            new: Some(Arc::new(NewSession {
+                store: self.clone(),
+                stored: AtomicBool::new(false),
+            })),
+        })
+    }
+
+    /// Keeps the new `session`
+    fn keep(&self, session: &Session) {
+        let mut sessions = self.sessions.lock().unwrap();
+        let now = Instant::now();
+        // Expired sessions go once per expiry, not on every new one
+        if now.duration_since(sessions.purged) >= self.expiry {
+            let expiry = self.expiry;
+            sessions
+                .by_id
+                .retain(|_, (last_used, _)| now.duration_since(*last_used) < expiry);
+            sessions.purged = now;
+        }
+        sessions
+            .by_id
+            .insert(session.id.clone(), (now, session.values.clone()));
+    }
+
+    /// Returns the value of the `Set-Cookie` header starting `session`
+    ///
+    /// The cookie lasts as long as the browser runs, the server forgets the
+    /// session once it was not used for the expiry.
+    pub fn cookie(session: &Session, secure: bool) -> String {
+        let mut cookie = format!(
+            "{}={}; Path=/; HttpOnly; SameSite=Lax",
+            COOKIE_NAME, session.id
+        );
+        if secure {
+            cookie.push_str("; Secure");
+        }
+        cookie
+    }
+
+    /// Returns how many sessions are kept, expired ones not purged yet
+    /// included
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.sessions.lock().unwrap().by_id.len()
+    }
+}
+
+/// Returns 128 random bits from the operating system as hex
+fn new_id() -> Result<String, getrandom::Error> {
+    let mut bytes = [0; 16];
+    getrandom::getrandom(&mut bytes)?;
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_sessions() {
+        let store = SessionStore::new(Duration::from_millis(200));
+        let session = store.session(None).unwrap();
+        assert_eq!(session.id.len(), 32);
+        assert!(session.id.bytes().all(|byte| byte.is_ascii_hexdigit()));
+        // Only kept once it has a value
+        assert!(!session.started());
+        assert_eq!(store.len(), 0);
+        session.insert("count", 1);
+        assert!(session.started());
+        assert_eq!(store.len(), 1);
+
+        let same = store.session(Some(&session.id)).unwrap();
+        assert!(!same.started());
+        assert_eq!(same.get::<u32>("count"), Some(1));
+        assert_eq!(same.get::<bool>("count"), None);
+        assert_eq!(same.remove("count"), Some(String::from("1")));
+        assert_eq!(session.get::<u32>("count"), None);
+
+        let other = store.session(Some("made-up")).unwrap();
+        assert_ne!(other.id, session.id);
+        other.insert("count", 2);
+        other.insert("count", 3);
+        assert_eq!(store.len(), 2);
+
+        thread::sleep(Duration::from_millis(250));
+        let renewed = store.session(Some(&session.id)).unwrap();
+        assert_ne!(renewed.id, session.id);
+        renewed.insert("count", 1);
+        // Both expired sessions were purged
+        assert_eq!(store.len(), 1);
+
+        assert_eq!(
+            SessionStore::cookie(&renewed, true),
+            format!(
+                "corroded_session={}; Path=/; HttpOnly; SameSite=Lax; Secure",
+                renewed.id
+            )
+        );
+    }
+}
@@ -0,0 +1,199 @@
+use std::io;
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+#[cfg(not(feature = "tokio"))]
+use std::time::Instant;
+
+/// How long waking up an accept loop may take
+const WAKE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Stops a running server, obtained with `Server::shutdown_handle`
+///
+/// The handle can be cloned and moved to other threads, e.g. into a signal
+/// handler.
+///
+/// # Example
+///
+/// ```
+/// use corrodedweb::Server;
+/// let s = Server::new();
+/// let handle = s.shutdown_handle();
+/// std::thread::spawn(move || {
+///     std::thread::sleep(std::time::Duration::from_secs(60));
+///     handle.shutdown();
+/// });
+/// // Returns once the handle was used and requests finished
+/// // s.start_server(7878).unwrap();
+/// ```
+#[derive(Clone, Default)]
+pub struct ShutdownHandle {
+    state: Arc<ShutdownState>,
+}
+
+impl ShutdownHandle {
+    /// Makes the server stop accepting connections, finish the requests it
+    /// is handling and return from `start_server`
+    pub fn shutdown(&self) {
+        self.state.requested.store(true, Ordering::SeqCst);
+        // Accept loops block until the next connection, so give them one
+        let listeners = self.state.listeners.lock().unwrap().clone();
+        for address in listeners {
+            let _ = TcpStream::connect_timeout(&address, WAKE_TIMEOUT);
+        }
+    }
+
+    /// Returns whether `shutdown` was called
+    pub fn is_shutting_down(&self) -> bool {
+        self.state.requested.load(Ordering::SeqCst)
+    }
+
+    /// Returns the error that made the server stop on its own, e.g. because
+    /// a listening socket stopped working
+    ///
+    /// After `start_server` returned, `None` means the server was shut down
+    /// through `shutdown`.
+    pub fn error(&self) -> Option<io::Error> {
+        let error = self.state.error.lock().unwrap();
+        error
+            .as_ref()
+            .map(|(kind, message)| io::Error::new(*kind, message.as_str()))
+    }
+
+    /// Shuts down because of `error`, only the first error is kept
+    pub(crate) fn fail(&self, error: &io::Error) {
+        self.state
+            .error
+            .lock()
+            .unwrap()
+            .get_or_insert_with(|| (error.kind(), error.to_string()));
+        self.shutdown();
+    }
+
+    /// Lets `shutdown` wake up the accept loop listening on `address`
+    pub(crate) fn register_listener(&self, address: SocketAddr) {
+        self.state.listeners.lock().unwrap().push(address);
+    }
+
+    /// Counts a request as in flight until the returned guard is dropped
+    pub(crate) fn begin_request(&self) -> InFlight {
+        *self.state.active.lock().unwrap() += 1;
+        InFlight(self.state.clone())
+    }
+
+    /// Returns how many requests are in flight
+    #[cfg(any(test, feature = "tokio"))]
+    pub(crate) fn active_requests(&self) -> usize {
+        *self.state.active.lock().unwrap()
+    }
+
+    /// Waits until no request is in flight anymore, but no longer than
+    /// `deadline`
+    ///
+    /// Returns how many requests are still running.
+    #[cfg(not(feature = "tokio"))]
+    pub(crate) fn wait_for_requests(&self, deadline: Duration) -> usize {
+        let end = Instant::now() + deadline;
+        let mut active = self.state.active.lock().unwrap();
+        while *active > 0 {
+            let remaining = end.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            active = self
+                .state
+                .finished
+                .wait_timeout(active, remaining)
+                .unwrap()
+                .0;
+        }
+        *active
+    }
+}
+
+#[derive(Default)]
+struct ShutdownState {
+    requested: AtomicBool,
+    error: Mutex<Option<(io::ErrorKind, String)>>,
+    listeners: Mutex<Vec<SocketAddr>>,
+    active: Mutex<usize>,
+    finished: Condvar,
+}
+
+/// A request that is being handled, see `ShutdownHandle::begin_request`
+pub(crate) struct InFlight(Arc<ShutdownState>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        *self.0.active.lock().unwrap() -= 1;
+        self.0.finished.notify_all();
+    }
+}
+
+/// Calls `shutdown` on `handle` once the process receives SIGINT or SIGTERM,
+/// or Ctrl-C on Windows
+///
+/// Only one such handler can exist per process.
+#[cfg(feature = "signals")]
+pub(crate) fn shutdown_on_signals(handle: ShutdownHandle) -> Result<(), ctrlc::Error> {
+    ctrlc::set_handler(move || handle.shutdown())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[cfg(not(feature = "tokio"))]
+    #[test]
+    fn test_wait_for_requests() {
+        let handle = ShutdownHandle::default();
+        assert_eq!(handle.wait_for_requests(Duration::from_secs(1)), 0);
+
+        let request = handle.begin_request();
+        let stuck = handle.begin_request();
+        assert_eq!(handle.active_requests(), 2);
+        let finisher = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            drop(request);
+        });
+        let start = Instant::now();
+        assert_eq!(handle.wait_for_requests(Duration::from_millis(300)), 1);
+        assert!(start.elapsed() >= Duration::from_millis(300));
+        finisher.join().unwrap();
+        drop(stuck);
+        assert_eq!(handle.active_requests(), 0);
+    }
+
+    #[test]
+    fn test_shutdown_wakes_listener() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let handle = ShutdownHandle::default();
+        handle.register_listener(listener.local_addr().unwrap());
+        assert!(!handle.is_shutting_down());
+
+        handle.clone().shutdown();
+        assert!(handle.is_shutting_down());
+        assert!(listener.accept().is_ok());
+        assert!(handle.error().is_none());
+    }
+
+    #[test]
+    fn test_fail() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let handle = ShutdownHandle::default();
+        handle.register_listener(listener.local_addr().unwrap());
+
+        handle.fail(&io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "not listening",
+        ));
+        handle.fail(&io::Error::other("later"));
+        assert!(handle.is_shutting_down());
+        assert!(listener.accept().is_ok());
+        let error = handle.error().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(error.to_string(), "not listening");
+    }
+}
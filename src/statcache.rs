@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+/// How long an entry is trusted unless configured otherwise
+pub(crate) const DEFAULT_TTL: Duration = Duration::from_secs(1);
+
+/// How many paths are remembered unless configured otherwise
+pub(crate) const DEFAULT_CAPACITY: usize = 1024;
+
+/// The parts of a file's metadata static serving needs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct FileStat {
+    pub len: u64,
+    pub is_dir: bool,
+    pub modified: Option<SystemTime>,
+}
+
+impl From<&Metadata> for FileStat {
+    fn from(metadata: &Metadata) -> Self {
+        FileStat {
+            len: metadata.len(),
+            is_dir: metadata.is_dir(),
+            modified: metadata.modified().ok(),
+        }
+    }
+}
+
+/// Remembers that files do not exist for a short time, so requests for
+/// them skip the file system
+///
+/// The cache is shared by all workers. Entries older than the TTL are never
+/// returned, so a created file is served within one TTL at most. Metadata of
+/// existing files is not kept, it is read from the open file, which is as
+/// cheap and never stale.
+pub(crate) struct StatCache {
+    ttl: Duration,
+    capacity: usize,
+    /// When each path was found missing
+    entries: Mutex<HashMap<PathBuf, Instant>>,
+}
+
+impl StatCache {
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        StatCache {
+            ttl,
+            capacity,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns whether `path` was found missing within the TTL
+    pub fn is_missing(&self, path: &Path) -> bool {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(path)
+            .is_some_and(|stored| stored.elapsed() < self.ttl)
+    }
+
+    /// Remembers that `path` does not exist
+    pub fn insert_missing(&self, path: &Path) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.capacity && !entries.contains_key(path) {
+            let ttl = self.ttl;
+            entries.retain(|_, stored| stored.elapsed() < ttl);
+            if entries.len() >= self.capacity {
+                let oldest = entries
+                    .iter()
+                    .min_by_key(|(_, stored)| **stored)
+                    .map(|(path, _)| path.clone());
+                if let Some(oldest) = oldest {
+                    entries.remove(&oldest);
+                }
+            }
+        }
+        entries.insert(path.to_path_buf(), Instant::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_expiry() {
+        let cache = StatCache::new(Duration::from_millis(50), 8);
+        let path = Path::new("/srv/index.html");
+        assert!(!cache.is_missing(path));
+
+        cache.insert_missing(path);
+        assert!(cache.is_missing(path));
+
+        thread::sleep(Duration::from_millis(60));
+        assert!(!cache.is_missing(path));
+    }
+
+    #[test]
+    fn test_capacity() {
+        let cache = StatCache::new(Duration::from_secs(60), 2);
+        cache.insert_missing(Path::new("/a"));
+        thread::sleep(Duration::from_millis(2));
+        cache.insert_missing(Path::new("/b"));
+        cache.insert_missing(Path::new("/c"));
+
+        assert!(!cache.is_missing(Path::new("/a")));
+        assert!(cache.is_missing(Path::new("/b")));
+        assert!(cache.is_missing(Path::new("/c")));
+    }
+}
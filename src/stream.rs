@@ -0,0 +1,371 @@
+use crate::accesslog::Tally;
+#[cfg(feature = "tokio")]
+use crate::asynclistener::BodyFeed;
+use crate::reader;
+#[cfg(feature = "tls")]
+use crate::tls::ClientCertificate;
+use std::io;
+use std::io::prelude::*;
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+
+/// A client connection, either plain or encrypted
+///
+/// Requests are read from and responses written to this, so the rest of the
+/// server does not need to care whether TLS is involved.
+pub(crate) enum Stream {
+    Plain(TcpStream),
+    /// A connection the blocking server keeps open for further requests,
+    /// see `Server::set_keep_alive`
+    KeptOpen(KeptOpen),
+    #[cfg(feature = "tls")]
+    Tls(Box<rustls::StreamOwned<rustls::ServerConnection, TcpStream>>),
+    #[cfg(feature = "native-tls")]
+    NativeTls(Box<native_tls::TlsStream<TcpStream>>),
+    Memory(MemoryStream),
+    /// A connection the body of the request is still read from while the
+    /// response is written, see `Request::body_reader`
+    Shared(Arc<Mutex<Stream>>),
+    /// A connection whose response is noted for the access log, see
+    /// `Server::set_access_log`
+    Tallied(Tallied),
+}
+
+/// A request on a connection the blocking server keeps open
+///
+/// The server holds on to the connection itself, this handle to it notes the
+/// head of the response, so the server can tell whether the client knows
+/// where the response ends.
+pub(crate) struct KeptOpen {
+    pub tcp: TcpStream,
+    /// The TLS session on `tcp`, which reads and writes go through if the
+    /// connection is encrypted
+    pub tls: Option<Arc<Mutex<Stream>>>,
+    /// The hostname the client sent via SNI, see `Stream::sni_hostname`
+    pub sni_hostname: Option<String>,
+    /// How many more requests the connection takes after this one if the
+    /// client wants it to stay open
+    pub remaining_requests: usize,
+    /// The head of the response once it was written, interim 1xx responses
+    /// left out
+    pub response_head: Arc<Mutex<Vec<u8>>>,
+}
+
+/// A connection the response of a request is tallied on
+pub(crate) struct Tallied {
+    pub inner: Box<Stream>,
+    pub tally: Arc<Mutex<Tally>>,
+}
+
+/// How much of a response is noted while looking for the end of its head
+const MAX_NOTED_HEAD: usize = 64 * 1024;
+
+/// A request replayed from memory whose response is captured
+///
+/// HTTP/2 streams, connections served by the tokio listener and test
+/// requests are run through the usual handling this way.
+pub(crate) struct MemoryStream {
+    pub input: io::Cursor<Vec<u8>>,
+    pub output: Arc<Mutex<Vec<u8>>>,
+    pub encrypted: bool,
+    /// How many more requests the connection takes after this one if the
+    /// client wants it to stay open, 0 if it closes after the response
+    pub remaining_requests: usize,
+    pub sni_hostname: Option<String>,
+    #[cfg(feature = "tls")]
+    pub client_certificate: Option<ClientCertificate>,
+    /// Where the rest of a request body comes from once `input` is read,
+    /// for bodies longer than the tokio listener reads up front
+    #[cfg(feature = "tokio")]
+    pub body_feed: Option<BodyFeed>,
+}
+
+impl Stream {
+    /// Returns whether the connection is encrypted
+    pub fn is_encrypted(&self) -> bool {
+        match self {
+            Stream::Plain(_) => false,
+            Stream::KeptOpen(stream) => stream.tls.is_some(),
+            Stream::Memory(stream) => stream.encrypted,
+            Stream::Shared(stream) => stream.lock().unwrap().is_encrypted(),
+            Stream::Tallied(stream) => stream.inner.is_encrypted(),
+            #[allow(unreachable_patterns)]
+            _ => true,
+        }
+    }
+
+    /// Returns how many more requests can follow on the connection
+    ///
+    /// Only connections the blocking server keeps open and those of the
+    /// tokio listener take more than one.
+    pub fn remaining_requests(&self) -> usize {
+        match self {
+            Stream::KeptOpen(stream) => stream.remaining_requests,
+            Stream::Memory(stream) => stream.remaining_requests,
+            Stream::Shared(stream) => stream.lock().unwrap().remaining_requests(),
+            Stream::Tallied(stream) => stream.inner.remaining_requests(),
+            _ => 0,
+        }
+    }
+
+    /// Returns whether the response is captured in memory instead of going
+    /// to a client directly
+    pub fn is_memory(&self) -> bool {
+        match self {
+            Stream::Memory(_) => true,
+            Stream::Shared(stream) => stream.lock().unwrap().is_memory(),
+            Stream::Tallied(stream) => stream.inner.is_memory(),
+            _ => false,
+        }
+    }
+
+    /// Returns the TCP connection underneath, `None` for requests held in
+    /// memory and shared streams
+    pub fn tcp(&self) -> Option<&TcpStream> {
+        match self {
+            Stream::Plain(stream) => Some(stream),
+            Stream::KeptOpen(stream) => Some(&stream.tcp),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => Some(&stream.sock),
+            #[cfg(feature = "native-tls")]
+            Stream::NativeTls(stream) => Some(stream.get_ref()),
+            Stream::Tallied(stream) => stream.inner.tcp(),
+            Stream::Memory(_) | Stream::Shared(_) => None,
+        }
+    }
+
+    /// Returns the hostname the client sent via SNI, if it did and the TLS
+    /// library tells
+    pub fn sni_hostname(&self) -> Option<&str> {
+        match self {
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.conn.server_name(),
+            Stream::KeptOpen(stream) => stream.sni_hostname.as_deref(),
+            Stream::Memory(stream) => stream.sni_hostname.as_deref(),
+            Stream::Tallied(stream) => stream.inner.sni_hostname(),
+            _ => None,
+        }
+    }
+
+    /// Returns the verified certificate the client authenticated with
+    #[cfg(feature = "tls")]
+    pub fn client_certificate(&self) -> Option<ClientCertificate> {
+        match self {
+            Stream::Tls(stream) => ClientCertificate::from_connection(&stream.conn),
+            Stream::KeptOpen(KeptOpen { tls: Some(tls), .. }) => {
+                tls.lock().unwrap().client_certificate()
+            }
+            Stream::Memory(stream) => stream.client_certificate.clone(),
+            Stream::Shared(stream) => stream.lock().unwrap().client_certificate(),
+            Stream::Tallied(stream) => stream.inner.client_certificate(),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the TLS session has decrypted bytes the connection
+    /// will not show as readable any more
+    ///
+    /// Only rustls tells, native TLS sessions are taken to have none, which
+    /// holds for clients that wait for a response before the next request.
+    pub fn has_buffered_input(&mut self) -> bool {
+        match self {
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream
+                .conn
+                .process_new_packets()
+                .is_ok_and(|state| state.plaintext_bytes_to_read() > 0),
+            _ => false,
+        }
+    }
+
+    /// Returns the connection a response is tallied on, or this one
+    pub fn untallied(&self) -> &Stream {
+        match self {
+            Stream::Tallied(stream) => stream.inner.untallied(),
+            stream => stream,
+        }
+    }
+
+    /// Returns the connection a response is tallied on, or this one
+    #[cfg(feature = "tokio")]
+    pub fn untallied_mut(&mut self) -> &mut Stream {
+        match self {
+            Stream::Tallied(stream) => stream.inner.untallied_mut(),
+            stream => stream,
+        }
+    }
+}
+
+impl KeptOpen {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &self.tls {
+            Some(tls) => tls.lock().unwrap().read(buf),
+            None => self.tcp.read(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &self.tls {
+            Some(tls) => tls.lock().unwrap().flush(),
+            None => self.tcp.flush(),
+        }
+    }
+
+    /// Notes the bytes of the response that were written, until its head is
+    /// complete
+    fn note(&self, written: &[u8]) {
+        let mut head = self.response_head.lock().unwrap();
+        if reader::head_len(&head).is_some() {
+            return;
+        }
+        let len = written.len().min(MAX_NOTED_HEAD.saturating_sub(head.len()));
+        head.extend_from_slice(&written[..len]);
+        while let Some(len) = reader::head_len(&head) {
+            if head.get(9) != Some(&b'1') {
+                head.truncate(len);
+                return;
+            }
+            head.drain(..len);
+        }
+    }
+}
+
+impl MemoryStream {
+    /// Returns the bytes of `input` that were not read yet
+    #[cfg(feature = "tokio")]
+    pub fn unread_input(&self) -> &[u8] {
+        let position = (self.input.position() as usize).min(self.input.get_ref().len());
+        &self.input.get_ref()[position..]
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.input.read(buf)?;
+        #[cfg(feature = "tokio")]
+        if read == 0 && !buf.is_empty() {
+            if let Some(feed) = &mut self.body_feed {
+                self.input = io::Cursor::new(feed.next_chunk()?);
+                return self.input.read(buf);
+            }
+        }
+        Ok(read)
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(stream) => stream.read(buf),
+            Stream::KeptOpen(stream) => stream.read(buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.read(buf),
+            #[cfg(feature = "native-tls")]
+            Stream::NativeTls(stream) => stream.read(buf),
+            Stream::Memory(stream) => stream.read(buf),
+            Stream::Shared(stream) => stream.lock().unwrap().read(buf),
+            Stream::Tallied(stream) => stream.inner.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(stream) => stream.write(buf),
+            Stream::KeptOpen(stream) => {
+                let written = match &stream.tls {
+                    Some(tls) => tls.lock().unwrap().write(buf)?,
+                    None => stream.tcp.write(buf)?,
+                };
+                stream.note(&buf[..written]);
+                Ok(written)
+            }
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.write(buf),
+            #[cfg(feature = "native-tls")]
+            Stream::NativeTls(stream) => stream.write(buf),
+            Stream::Memory(stream) => stream.output.lock().unwrap().write(buf),
+            Stream::Shared(stream) => stream.lock().unwrap().write(buf),
+            Stream::Tallied(stream) => {
+                let written = stream.inner.write(buf).inspect_err(|e| {
+                    stream.tally.lock().unwrap().note_error(e);
+                })?;
+                stream.tally.lock().unwrap().note(&buf[..written]);
+                Ok(written)
+            }
+        }
+    }
+
+    fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(stream) => stream.write_vectored(bufs),
+            Stream::KeptOpen(stream) => {
+                let written = match &stream.tls {
+                    Some(tls) => tls.lock().unwrap().write_vectored(bufs)?,
+                    None => stream.tcp.write_vectored(bufs)?,
+                };
+                let mut left = written;
+                for buf in bufs {
+                    let len = left.min(buf.len());
+                    stream.note(&buf[..len]);
+                    left -= len;
+                }
+                Ok(written)
+            }
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.write_vectored(bufs),
+            #[cfg(feature = "native-tls")]
+            Stream::NativeTls(stream) => stream.write_vectored(bufs),
+            Stream::Memory(stream) => stream.output.lock().unwrap().write_vectored(bufs),
+            Stream::Shared(stream) => stream.lock().unwrap().write_vectored(bufs),
+            Stream::Tallied(stream) => {
+                let written = stream.inner.write_vectored(bufs).inspect_err(|e| {
+                    stream.tally.lock().unwrap().note_error(e);
+                })?;
+                let mut tally = stream.tally.lock().unwrap();
+                let mut left = written;
+                for buf in bufs {
+                    let len = left.min(buf.len());
+                    tally.note(&buf[..len]);
+                    left -= len;
+                }
+                Ok(written)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Plain(stream) => stream.flush(),
+            Stream::KeptOpen(stream) => stream.flush(),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.flush(),
+            #[cfg(feature = "native-tls")]
+            Stream::NativeTls(stream) => stream.flush(),
+            Stream::Memory(_) => Ok(()),
+            Stream::Shared(stream) => stream.lock().unwrap().flush(),
+            Stream::Tallied(stream) => stream.inner.flush().inspect_err(|e| {
+                stream.tally.lock().unwrap().note_error(e);
+            }),
+        }
+    }
+}
+
+#[cfg(any(feature = "tls", feature = "native-tls"))]
+impl Drop for Stream {
+    fn drop(&mut self) {
+        // Tell the client the response is complete, so it can tell a clean
+        // end apart from a truncation attack
+        match self {
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => {
+                stream.conn.send_close_notify();
+                let _ = stream.conn.complete_io(&mut stream.sock);
+            }
+            #[cfg(feature = "native-tls")]
+            Stream::NativeTls(stream) => {
+                let _ = stream.shutdown();
+            }
+            _ => {}
+        }
+    }
+}
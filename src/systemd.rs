@@ -0,0 +1,167 @@
+use crate::logger::Logger;
+use std::env;
+use std::fs::File;
+use std::io;
+use std::net::TcpListener;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::io::{FromRawFd, IntoRawFd, RawFd};
+use std::os::unix::net::UnixDatagram;
+
+/// The first file descriptor systemd passes, after stdin, stdout and stderr
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Returns the listening sockets systemd passed to this process, or `None`
+/// if it was not socket activated
+///
+/// The variables describing them are removed, so child processes do not
+/// try to take them over as well.
+pub(crate) fn listeners(logger: &Option<Logger>) -> Option<Vec<TcpListener>> {
+    listeners_from(LISTEN_FDS_START, logger)
+}
+
+fn listeners_from(start: RawFd, logger: &Option<Logger>) -> Option<Vec<TcpListener>> {
+    // Meant for another process if the PID does not match, e.g. the parent
+    // that forgot to remove them
+    let pid: u32 = env::var("LISTEN_PID").ok()?.parse().ok()?;
+    if pid != std::process::id() {
+        return None;
+    }
+    let count = env::var("LISTEN_FDS").ok()?.parse::<RawFd>().ok()?;
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    let mut listeners = Vec::new();
+    for fd in start..start.saturating_add(count) {
+        match adopt(fd) {
+            Ok(listener) => {
+                if let Ok(address) = listener.local_addr() {
+                    Logger::info(logger, &format!("Serving {} passed by systemd", address));
+                }
+                listeners.push(listener);
+            }
+            Err(e) => Logger::warning(
+                logger,
+                &format!("Ignoring file descriptor {} passed by systemd: {}", fd, e),
+            ),
+        }
+    }
+    if listeners.is_empty() {
+        Logger::warning(logger, "systemd passed no usable sockets");
+        return None;
+    }
+    Some(listeners)
+}
+
+/// Takes ownership of `fd` if it is a TCP socket
+fn adopt(fd: RawFd) -> io::Result<TcpListener> {
+    // Borrowed only to ask what it is, anything but a socket stays open
+    let file = unsafe { File::from_raw_fd(fd) };
+    let is_socket = file
+        .metadata()
+        .map(|metadata| metadata.file_type().is_socket());
+    let fd = file.into_raw_fd();
+    if !is_socket? {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a socket"));
+    }
+
+    let listener = unsafe { TcpListener::from_raw_fd(fd) };
+    // Fails for Unix domain sockets
+    listener.local_addr()?;
+    // systemd only makes them non-blocking if asked to
+    listener.set_nonblocking(false)?;
+    Ok(listener)
+}
+
+/// Tells the service manager about a state change, e.g. `READY=1`, if the
+/// process was started by one that wants to know
+pub(crate) fn notify(state: &str) -> io::Result<()> {
+    let path = match env::var_os("NOTIFY_SOCKET") {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    let socket = UnixDatagram::unbound()?;
+    match path.as_bytes() {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        [b'@', name @ ..] => {
+            #[cfg(target_os = "android")]
+            use std::os::android::net::SocketAddrExt;
+            #[cfg(target_os = "linux")]
+            use std::os::linux::net::SocketAddrExt;
+            let address = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &address)?;
+        }
+        _ => {
+            socket.send_to(state.as_bytes(), &path)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::Server;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::os::unix::io::AsRawFd;
+
+    #[test]
+    fn test_socket_activation() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let fd = listener.into_raw_fd();
+
+        env::set_var("LISTEN_PID", "1");
+        env::set_var("LISTEN_FDS", "1");
+        assert!(listeners_from(fd, &None).is_none());
+
+        env::set_var("LISTEN_PID", std::process::id().to_string());
+        let adopted = listeners_from(fd, &None).unwrap();
+        assert_eq!(adopted.len(), 1);
+        assert_eq!(adopted[0].local_addr().unwrap(), address);
+        assert!(env::var("LISTEN_PID").is_err());
+        assert!(env::var("LISTEN_FDS").is_err());
+
+        let mut server = Server::new();
+        server.get("/", |_, mut response| {
+            let _ = response.write("activated");
+        });
+        let handle = server.shutdown_handle();
+        let serving = std::thread::spawn(move || server.serve_listeners(adopted));
+
+        let mut client = TcpStream::connect(address).unwrap();
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.ends_with("activated"), "{}", response);
+
+        handle.shutdown();
+        serving.join().unwrap();
+    }
+
+    #[test]
+    fn test_adopt_rejects_files() {
+        let file = File::open("Cargo.toml").unwrap();
+        assert!(adopt(file.as_raw_fd()).is_err());
+        // Still open
+        assert!(file.metadata().is_ok());
+    }
+
+    #[test]
+    fn test_notify() {
+        let path = env::temp_dir().join("corrodedweb_notify.sock");
+        let _ = std::fs::remove_file(&path);
+        let receiver = UnixDatagram::bind(&path).unwrap();
+        env::set_var("NOTIFY_SOCKET", &path);
+        notify("READY=1").unwrap();
+        env::remove_var("NOTIFY_SOCKET");
+
+        let mut buffer = [0; 16];
+        let len = receiver.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..len], b"READY=1");
+    }
+}
@@ -0,0 +1,230 @@
+use crate::server::{escape_html, stays_inside};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+/// The templates in a directory, read on first use and kept, see
+/// `Server::set_template_root`
+pub(crate) struct Templates {
+    root: PathBuf,
+    /// Reads templates on every use instead, see `Server::reload_templates`
+    reload: bool,
+    cache: RwLock<HashMap<String, Arc<Template>>>,
+}
+
+/// A parsed template
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    /// `{{ name }}`, HTML-escaped
+    Escaped(String),
+    /// `{{{ name }}}`, inserted as it is
+    Raw(String),
+}
+
+/// Why a template cannot be rendered
+#[derive(Debug)]
+pub(crate) enum TemplateError {
+    /// The name is empty or leads out of the template root
+    InvalidName,
+    Io(io::Error),
+    /// A tag is not closed or names no variable
+    Syntax {
+        line: usize,
+        problem: &'static str,
+    },
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TemplateError::InvalidName => write!(f, "the name leads out of the template root"),
+            TemplateError::Io(e) => write!(f, "{}", e),
+            TemplateError::Syntax { line, problem } => write!(f, "line {}: {}", line, problem),
+        }
+    }
+}
+
+impl Templates {
+    pub fn new(root: PathBuf, reload: bool) -> Self {
+        Templates {
+            root,
+            reload,
+            cache: RwLock::default(),
+        }
+    }
+
+    pub fn root(&self) -> &PathBuf {
+        &self.root
+    }
+
+    /// Returns the template `name`, a path relative to the root
+    pub fn get(&self, name: &str) -> Result<Arc<Template>, TemplateError> {
+        if name.is_empty() || !stays_inside(name) {
+            return Err(TemplateError::InvalidName);
+        }
+        if !self.reload {
+            if let Some(template) = self.cache.read().unwrap().get(name) {
+                return Ok(template.clone());
+            }
+        }
+        let source = fs::read_to_string(self.root.join(name)).map_err(TemplateError::Io)?;
+        let template = Arc::new(parse(&source)?);
+        if !self.reload {
+            self.cache
+                .write()
+                .unwrap()
+                .insert(String::from(name), template.clone());
+        }
+        Ok(template)
+    }
+}
+
+impl Template {
+    /// Returns the template with the values of `context` filled in, and the
+    /// names it uses that `context` has no value for, which are left empty
+    pub fn render<'a>(&'a self, context: &HashMap<String, String>) -> (String, Vec<&'a str>) {
+        let mut page = String::new();
+        let mut missing = Vec::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => page.push_str(text),
+                Part::Escaped(name) | Part::Raw(name) => match context.get(name) {
+                    Some(value) if matches!(part, Part::Raw(_)) => page.push_str(value),
+                    Some(value) => page.push_str(&escape_html(value)),
+                    None => missing.push(name.as_str()),
+                },
+            }
+        }
+        (page, missing)
+    }
+}
+
+/// Splits `source` into text and the `{{ name }}` and `{{{ name }}}` tags
+/// between it
+fn parse(source: &str) -> Result<Template, TemplateError> {
+    let mut parts = Vec::new();
+    let mut rest = source;
+    while let Some(start) = rest.find("{{") {
+        if start > 0 {
+            parts.push(Part::Text(String::from(&rest[..start])));
+        }
+        let tag = &rest[start..];
+        let line = source[..source.len() - tag.len()].matches('\n').count() + 1;
+        let (raw, open, close) = if tag.starts_with("{{{") {
+            (true, 3, "}}}")
+        } else {
+            (false, 2, "}}")
+        };
+        let len = tag[open..].find(close).ok_or(TemplateError::Syntax {
+            line,
+            problem: "the tag is not closed",
+        })?;
+        let name = String::from(tag[open..open + len].trim());
+        if name.is_empty() {
+            return Err(TemplateError::Syntax {
+                line,
+                problem: "the tag names no variable",
+            });
+        }
+        parts.push(if raw {
+            Part::Raw(name)
+        } else {
+            Part::Escaped(name)
+        });
+        rest = &tag[open + len + close.len()..];
+    }
+    if !rest.is_empty() {
+        parts.push(Part::Text(String::from(rest)));
+    }
+    Ok(Template { parts })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse("<h1>{{ title }}</h1>{{{body}}}!").unwrap().parts,
+            vec![
+                Part::Text(String::from("<h1>")),
+                Part::Escaped(String::from("title")),
+                Part::Text(String::from("</h1>")),
+                Part::Raw(String::from("body")),
+                Part::Text(String::from("!")),
+            ]
+        );
+        assert_eq!(parse("").unwrap().parts, vec![]);
+        assert!(matches!(
+            parse("a\nb {{ open"),
+            Err(TemplateError::Syntax { line: 2, .. })
+        ));
+        assert!(matches!(
+            parse("{{ }}"),
+            Err(TemplateError::Syntax { line: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_render() {
+        let template = parse("<p>{{ name }}</p>{{{ html }}}<i>{{ missing }}</i>").unwrap();
+        let context: HashMap<String, String> = [
+            ("name", "<script>alert('x')</script>"),
+            ("html", "<b>bold</b>"),
+        ]
+        .iter()
+        .map(|(name, value)| (String::from(*name), String::from(*value)))
+        .collect();
+        assert_eq!(
+            template.render(&context),
+            (
+                String::from(
+                    "<p>&lt;script&gt;alert(&#39;x&#39;)&lt;/script&gt;</p><b>bold</b><i></i>"
+                ),
+                vec!["missing"]
+            )
+        );
+    }
+
+    #[test]
+    fn test_templates() {
+        let dir = std::env::temp_dir().join("corrodedweb_templates");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("page.html"), "one").unwrap();
+        let cached = Templates::new(dir.clone(), false);
+        let reloaded = Templates::new(dir.clone(), true);
+        let render = |templates: &Templates| {
+            templates
+                .get("page.html")
+                .unwrap()
+                .render(&HashMap::new())
+                .0
+        };
+        assert_eq!(render(&cached), "one");
+        assert_eq!(render(&reloaded), "one");
+        fs::write(dir.join("page.html"), "two").unwrap();
+        assert_eq!(render(&cached), "one");
+        assert_eq!(render(&reloaded), "two");
+
+        assert!(matches!(
+            cached.get("missing.html"),
+            Err(TemplateError::Io(_))
+        ));
+        assert!(matches!(
+            cached.get("../page.html"),
+            Err(TemplateError::InvalidName)
+        ));
+        assert!(matches!(cached.get(""), Err(TemplateError::InvalidName)));
+    }
+}
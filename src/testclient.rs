@@ -0,0 +1,341 @@
+use crate::headers::Headers;
+use crate::server::{Request, Server};
+use std::collections::HashMap;
+use std::str;
+use std::sync::{Arc, Mutex};
+
+/// A request run through a server without a network, created with
+/// `Server::test_request`
+///
+/// The request goes through the same parsing, routing and response writing
+/// as one from a real client, on the calling thread. A `Host: localhost`
+/// header is added unless one is set, as every HTTP/1.1 client sends it.
+///
+/// # Example
+///
+/// ```
+/// use corrodedweb::Server;
+/// let mut s = Server::new();
+/// s.post("/echo/", |request, mut response| {
+///     let _ = response.write(request.form("name").unwrap_or("nobody"));
+/// });
+///
+/// let response = s
+///     .test_request("POST", "/echo/")
+///     .header("Content-Type", "application/x-www-form-urlencoded")
+///     .body("name=Max")
+///     .send();
+/// assert_eq!(response.status(), 200);
+/// assert_eq!(response.text(), "Max");
+/// ```
+pub struct TestRequest<'a> {
+    server: &'a Server,
+    method: String,
+    target: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl<'a> TestRequest<'a> {
+    pub(crate) fn new(server: &'a Server, method: &str, target: &str) -> Self {
+        TestRequest {
+            server,
+            method: method.to_uppercase(),
+            target: String::from(target),
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    /// Adds a request header
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((String::from(name), String::from(value)));
+        self
+    }
+
+    /// Sets the request body, a `Content-Length` header is added for it
+    pub fn body(mut self, body: &str) -> Self {
+        self.body = body.as_bytes().to_vec();
+        self
+    }
+
+    /// Runs the request through the server and returns what it answered
+    pub fn send(self) -> TestResponse {
+        let mut request = format!("{} {} HTTP/1.1\r\n", self.method, self.target);
+        let has_header = |name: &str| {
+            self.headers
+                .iter()
+                .any(|(header, _)| header.eq_ignore_ascii_case(name))
+        };
+        if !has_header("Host") {
+            request.push_str("Host: localhost\r\n");
+        }
+        for (name, value) in &self.headers {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        if !self.body.is_empty() && !has_header("Content-Length") {
+            request.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+        request.push_str("\r\n");
+        let mut request = request.into_bytes();
+        request.extend_from_slice(&self.body);
+
+        TestResponse::parse(&self.server.exchange_in_memory(request))
+    }
+}
+
+/// What the server answered to a `TestRequest`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestResponse {
+    status: u32,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl TestResponse {
+    /// Splits the raw response into status, headers and body. A server
+    /// that wrote nothing is reported with status 0.
+    fn parse(raw: &[u8]) -> Self {
+        let (head, body) = match raw.windows(4).position(|w| w == b"\r\n\r\n") {
+            Some(end) => (&raw[..end], &raw[end + 4..]),
+            None => (raw, &raw[raw.len()..]),
+        };
+        let head = String::from_utf8_lossy(head);
+        let mut lines = head.split("\r\n");
+        let status = lines
+            .next()
+            .and_then(|line| line.split(' ').nth(1))
+            .and_then(|status| status.parse().ok())
+            .unwrap_or(0);
+        let headers = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (String::from(name.trim()), String::from(value.trim())))
+            .collect();
+        TestResponse {
+            status,
+            headers,
+            body: body.to_vec(),
+        }
+    }
+
+    /// Returns the status code, e.g. `200`
+    pub fn status(&self) -> u32 {
+        self.status
+    }
+
+    /// Returns the value of a response header, the name is case-insensitive
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns all response headers in the order they were sent
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+
+    /// Returns the body as it was sent
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// Returns the body as text, invalid UTF-8 is replaced
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+/// Creates a `Request` for calling a handler function directly, obtained
+/// with `Request::builder`
+///
+/// Nothing is routed, so path parameters are set by hand. The request is
+/// HTTP/1.1 over plain HTTP with a `Host: localhost` header unless one is
+/// set.
+///
+/// # Example
+///
+/// ```
+/// use corrodedweb::{Request, Response};
+/// fn greet(request: Request, mut response: Response) {
+///     let _ = response.write(&format!("Hello {}", request.query("name").unwrap_or("you")));
+/// }
+///
+/// let request = Request::builder()
+///     .method("GET")
+///     .path("/greet/")
+///     .query("name=Max")
+///     .header("X-Test", "1")
+///     .build();
+/// let (response, captured) = Response::capture();
+/// greet(request, response);
+/// let (status, _headers, body) = captured.into_parts();
+/// assert_eq!(status, 200);
+/// assert_eq!(body, b"Hello Max");
+/// ```
+pub struct RequestBuilder {
+    method: String,
+    path: String,
+    query: Option<String>,
+    headers: Headers,
+    body: Vec<u8>,
+    path_parameters: HashMap<String, String>,
+}
+
+impl RequestBuilder {
+    pub(crate) fn new() -> Self {
+        RequestBuilder {
+            method: String::from("GET"),
+            path: String::from("/"),
+            query: None,
+            headers: Headers::new(),
+            body: Vec::new(),
+            path_parameters: HashMap::new(),
+        }
+    }
+
+    /// Sets the method, `GET` by default
+    pub fn method(mut self, method: &str) -> Self {
+        self.method = method.to_uppercase();
+        self
+    }
+
+    /// Sets the path without the query, `/` by default
+    pub fn path(mut self, path: &str) -> Self {
+        self.path = String::from(path);
+        self
+    }
+
+    /// Sets the query string without the leading `?`, e.g. `a=1&b=2`
+    pub fn query(mut self, query: &str) -> Self {
+        self.query = Some(String::from(query));
+        self
+    }
+
+    /// Adds a request header
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.append(name, value);
+        self
+    }
+
+    /// Sets the body, form parameters are parsed from it
+    pub fn body(mut self, body: &[u8]) -> Self {
+        self.body = body.to_vec();
+        self
+    }
+
+    /// Sets a parameter the route would have captured, e.g. `id` of
+    /// `/users/:id/`
+    pub fn path_param(mut self, name: &str, value: &str) -> Self {
+        self.path_parameters
+            .insert(String::from(name), String::from(value));
+        self
+    }
+
+    /// Returns the request
+    pub fn build(mut self) -> Request {
+        if !self.headers.contains("Host") {
+            self.headers.set("Host", "localhost");
+        }
+        Request::from_parts(
+            &self.method,
+            &self.path,
+            self.query.as_deref(),
+            self.headers,
+            self.body,
+            self.path_parameters,
+        )
+    }
+}
+
+/// What a `Response` created with `Response::capture` sent, readable once
+/// the handler dropped the response
+pub struct CapturedResponse {
+    pub(crate) output: Arc<Mutex<Vec<u8>>>,
+}
+
+impl CapturedResponse {
+    /// Returns the status, the headers in the order they were sent and the
+    /// body
+    ///
+    /// The status is 0 while the response was not dropped yet.
+    pub fn into_parts(self) -> (u32, Vec<(String, String)>, Vec<u8>) {
+        let response = TestResponse::parse(&self.output.lock().unwrap());
+        (response.status, response.headers, response.body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::Response;
+
+    #[test]
+    fn test_parse() {
+        let response = TestResponse::parse(
+            b"HTTP/1.1 404 NOT FOUND\r\nAllow: GET\r\nContent-Length: 4\r\n\r\ngone",
+        );
+        assert_eq!(response.status(), 404);
+        assert_eq!(response.header("allow"), Some("GET"));
+        assert_eq!(response.headers().len(), 2);
+        assert_eq!(response.text(), "gone");
+
+        assert_eq!(TestResponse::parse(b"").status(), 0);
+    }
+
+    #[test]
+    fn test_request_builder() {
+        let request = Request::builder()
+            .method("post")
+            .path("/users/7/")
+            .query("page=2&page=3")
+            .header("Cookie", "session=abc")
+            .header("X-Test", "1")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(b"name=Max")
+            .path_param("id", "7")
+            .build();
+        assert_eq!(request.method(), "POST");
+        assert_eq!(request.path(), "/users/7/");
+        assert_eq!(request.raw_query(), Some("page=2&page=3"));
+        assert_eq!(request.query_pairs().len(), 2);
+        assert_eq!(request.query("page"), Some("2"));
+        assert_eq!(request.form("name"), Some("Max"));
+        assert_eq!(request.get_header("x-test"), Some("1"));
+        assert_eq!(request.get_cookie("session"), Some("abc"));
+        assert_eq!(request.path_param::<u32>("id"), Ok(7));
+        assert_eq!(request.host(), Some("localhost"));
+        assert_eq!(request.raw_body(), b"name=Max");
+        assert!(request
+            .raw_head()
+            .starts_with("POST /users/7/?page=2&page=3 HTTP/1.1\r\n"));
+    }
+
+    #[test]
+    fn test_capture() {
+        fn handler(request: Request, mut response: Response) {
+            let _ = response.set_status_code(201);
+            let _ = response.set_header("X-Id", request.path_param_str("id").unwrap_or(""));
+            let _ = response.write("created");
+        }
+        let (response, captured) = Response::capture();
+        handler(Request::builder().path_param("id", "7").build(), response);
+        let (status, headers, body) = captured.into_parts();
+        assert_eq!(status, 201);
+        assert_eq!(
+            headers,
+            [
+                (String::from("X-Id"), String::from("7")),
+                (String::from("Content-Length"), String::from("7")),
+            ]
+        );
+        assert_eq!(body, b"created");
+
+        // Nothing was sent while the handler holds the response
+        let (response, captured) = Response::capture();
+        let (status, _, _) = captured.into_parts();
+        assert_eq!(status, 0);
+        drop(response);
+    }
+}
@@ -1,6 +1,7 @@
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::mpsc;
 use std::sync::Arc;
-use std::sync::Mutex;
+use std::sync::{Condvar, Mutex};
 use std::thread;
 
 enum Message {
@@ -8,9 +9,51 @@ enum Message {
     Terminate,
 }
 
+/// Runs jobs on a fixed number of worker threads, like the requests of a
+/// `Server`
 pub struct ThreadPool {
     workers: Vec<Worker>,
     sender: mpsc::Sender<Message>,
+    jobs: Arc<JobCounts>,
+}
+
+/// How many jobs of a pool are running and how many wait for a worker,
+/// shared with `Metrics`
+#[derive(Debug, Default)]
+pub(crate) struct JobCounts {
+    counts: Mutex<Counts>,
+    /// Notified whenever a job started or finished
+    changed: Condvar,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Counts {
+    active: usize,
+    queued: usize,
+}
+
+impl JobCounts {
+    pub fn active(&self) -> usize {
+        self.counts.lock().unwrap().active
+    }
+
+    pub fn queued(&self) -> usize {
+        self.counts.lock().unwrap().queued
+    }
+
+    fn update(&self, update: impl FnOnce(&mut Counts)) {
+        update(&mut self.counts.lock().unwrap());
+        self.changed.notify_all();
+    }
+}
+
+/// Counts a job as running until it is dropped, even if the job panics
+struct Running(Arc<JobCounts>);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.0.update(|counts| counts.active -= 1);
+    }
 }
 
 trait FnBox {
@@ -34,6 +77,11 @@ impl ThreadPool {
     ///
     /// The `new` function will panic if the size is zero.
     pub fn new(size: usize) -> ThreadPool {
+        ThreadPool::with_counts(size, Arc::default())
+    }
+
+    /// Creates a pool of `size` threads that keeps `jobs` up to date
+    pub(crate) fn with_counts(size: usize, jobs: Arc<JobCounts>) -> ThreadPool {
         assert!(size > 0);
 
         let (sender, receiver) = mpsc::channel();
@@ -43,22 +91,61 @@ impl ThreadPool {
         let mut workers = Vec::with_capacity(size);
 
         for id in 0..size {
-            workers.push(Worker::new(id, receiver.clone()));
+            workers.push(Worker::new(id, receiver.clone(), jobs.clone()));
         }
 
-        ThreadPool { workers, sender }
+        ThreadPool {
+            workers,
+            sender,
+            jobs,
+        }
     }
 
+    /// Runs `f` on the next free worker, queuing it until there is one
     pub fn execute<F>(&self, f: F)
     where
         F: FnOnce() + Send + 'static,
     {
         let job = Box::new(f);
 
+        self.jobs.update(|counts| counts.queued += 1);
         if let Err(e) = self.sender.send(Message::NewJob(job)) {
+            self.jobs.update(|counts| counts.queued -= 1);
             println!("Error: {}", e);
         }
     }
+
+    /// Returns how many jobs are running on a worker
+    pub fn active_jobs(&self) -> usize {
+        self.jobs.active()
+    }
+
+    /// Returns how many jobs wait for a free worker
+    pub fn queued_jobs(&self) -> usize {
+        self.jobs.queued()
+    }
+
+    /// Returns whether another job would find a free worker or a place
+    /// among no more than `max_queued` waiting jobs
+    #[cfg(not(feature = "tokio"))]
+    pub fn has_room(&self, max_queued: usize) -> bool {
+        let counts = *self.jobs.counts.lock().unwrap();
+        self.fits(counts, max_queued)
+    }
+
+    /// Blocks until `has_room` is true
+    #[cfg(not(feature = "tokio"))]
+    pub fn wait_for_room(&self, max_queued: usize) {
+        let mut counts = self.jobs.counts.lock().unwrap();
+        while !self.fits(*counts, max_queued) {
+            counts = self.jobs.changed.wait(counts).unwrap();
+        }
+    }
+
+    #[cfg(not(feature = "tokio"))]
+    fn fits(&self, counts: Counts, max_queued: usize) -> bool {
+        counts.active + counts.queued < self.workers.len() + max_queued
+    }
 }
 
 impl Drop for ThreadPool {
@@ -87,14 +174,27 @@ struct Worker {
 }
 
 impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Message>>>) -> Worker {
+    fn new(
+        id: usize,
+        receiver: Arc<Mutex<mpsc::Receiver<Message>>>,
+        jobs: Arc<JobCounts>,
+    ) -> Worker {
         let thread = thread::spawn(move || loop {
             let message = receiver.lock().unwrap().recv().unwrap();
 
             match message {
                 Message::NewJob(job) => {
                     //println!("Worker {} got a job; executing.", id);
-                    job.call_box();
+                    jobs.update(|counts| {
+                        counts.queued -= 1;
+                        counts.active += 1;
+                    });
+                    let _running = Running(jobs.clone());
+                    // Handlers catch their own panics, this keeps the worker
+                    // alive whatever else goes wrong
+                    if panic::catch_unwind(AssertUnwindSafe(|| job.call_box())).is_err() {
+                        println!("Worker {} survived a panicking job.", id);
+                    }
                 }
                 Message::Terminate => {
                     println!("Worker {} was told to terminate.", id);
@@ -109,3 +209,74 @@ impl Worker {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Barrier;
+    use std::time::Duration;
+
+    #[test]
+    fn test_workers_run_concurrently() {
+        let pool = ThreadPool::new(4);
+        let barrier = Arc::new(Barrier::new(4));
+        let (done, finished) = mpsc::channel();
+        for _ in 0..4 {
+            let barrier = barrier.clone();
+            let done = done.clone();
+            // Each job only gets past the barrier once all four run
+            pool.execute(move || {
+                barrier.wait();
+                done.send(()).unwrap();
+            });
+        }
+        for _ in 0..4 {
+            finished.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_panicking_job() {
+        let pool = ThreadPool::new(1);
+        pool.execute(|| panic!("job failed"));
+        let (done, finished) = mpsc::channel();
+        pool.execute(move || done.send(()).unwrap());
+        finished.recv_timeout(Duration::from_secs(5)).unwrap();
+        let jobs = pool.jobs.clone();
+        drop(pool);
+        assert_eq!(jobs.active(), 0);
+    }
+
+    #[test]
+    fn test_job_counts() {
+        let pool = ThreadPool::new(2);
+        let (release, released) = mpsc::channel::<()>();
+        let released = Arc::new(Mutex::new(released));
+        let (started, running) = mpsc::channel();
+        for _ in 0..3 {
+            let released = released.clone();
+            let started = started.clone();
+            pool.execute(move || {
+                started.send(()).unwrap();
+                released.lock().unwrap().recv().unwrap();
+            });
+        }
+        running.recv().unwrap();
+        running.recv().unwrap();
+        // The first job holds the lock while waiting, the second waits for
+        // the lock, the third for a worker
+        assert_eq!(pool.active_jobs(), 2);
+        assert_eq!(pool.queued_jobs(), 1);
+        #[cfg(not(feature = "tokio"))]
+        {
+            assert!(!pool.has_room(1));
+            assert!(pool.has_room(2));
+        }
+
+        for _ in 0..3 {
+            release.send(()).unwrap();
+        }
+        running.recv().unwrap();
+        drop(pool);
+    }
+}
@@ -0,0 +1,168 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How long the phases of handling one request took, see
+/// `Server::set_phase_timings`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PhaseTimings {
+    /// Parsing the request once it was read
+    pub parse: Duration,
+    /// Rewriting the target and finding the route, proxy or file answering it
+    pub route: Duration,
+    /// Running the handler, without the time its response took to send
+    pub handler: Duration,
+    /// Sending the response to the client
+    pub write: Duration,
+}
+
+/// Shows the phases like `parse=0.1ms route=0.02ms handler=45ms write=3ms`
+impl fmt::Display for PhaseTimings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "parse={} route={} handler={} write={}",
+            millis(self.parse),
+            millis(self.route),
+            millis(self.handler),
+            millis(self.write)
+        )
+    }
+}
+
+/// Shows a duration in milliseconds with at most two decimals
+fn millis(duration: Duration) -> String {
+    let millis = format!("{:.2}", duration.as_secs_f64() * 1000.0);
+    format!("{}ms", millis.trim_end_matches('0').trim_end_matches('.'))
+}
+
+/// Adds up the time a response spends sending, shared between the response
+/// and the `PhaseTimer` of its request
+#[derive(Debug, Clone, Default)]
+pub(crate) struct WriteClock(Arc<AtomicU64>);
+
+impl WriteClock {
+    /// Adds the time since `started`
+    pub fn add(&self, started: Instant) {
+        let nanos = started.elapsed().as_nanos() as u64;
+        self.0.fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    fn total(&self) -> Duration {
+        Duration::from_nanos(self.0.load(Ordering::Relaxed))
+    }
+}
+
+/// Measures the phases of one request, doing nothing unless enabled
+pub(crate) struct PhaseTimer {
+    running: Option<Running>,
+}
+
+struct Running {
+    /// When the current phase began
+    lap: Instant,
+    timings: PhaseTimings,
+    writes: WriteClock,
+}
+
+impl PhaseTimer {
+    /// Starts the parse phase if `enabled`
+    pub fn start(enabled: bool) -> Self {
+        PhaseTimer {
+            running: enabled.then(|| Running {
+                lap: Instant::now(),
+                timings: PhaseTimings::default(),
+                writes: WriteClock::default(),
+            }),
+        }
+    }
+
+    /// Returns whether the timer measures anything
+    pub fn is_running(&self) -> bool {
+        self.running.is_some()
+    }
+
+    /// Ends the parse phase
+    pub fn parsed(&mut self) {
+        if let Some(running) = &mut self.running {
+            let lap = running.lap();
+            running.timings.parse += lap;
+        }
+    }
+
+    /// Counts the time since the last phase ended as routing
+    pub fn routed(&mut self) {
+        if let Some(running) = &mut self.running {
+            let lap = running.lap();
+            running.timings.route += lap;
+        }
+    }
+
+    /// Counts the time since the last phase ended as sending, for responses
+    /// written without a `Response`, e.g. static files
+    pub fn wrote(&mut self) {
+        if let Some(running) = &mut self.running {
+            let lap = running.lap();
+            running.timings.write += lap;
+        }
+    }
+
+    /// Returns the clock for the response to count its sending on
+    pub fn write_clock(&self) -> Option<WriteClock> {
+        self.running.as_ref().map(|running| running.writes.clone())
+    }
+
+    /// Counts the time since the last phase ended as the handler's, except
+    /// what the response took to send, and returns the timings
+    pub fn finish(self) -> Option<PhaseTimings> {
+        let mut running = self.running?;
+        let writes = running.writes.total();
+        let lap = running.lap();
+        running.timings.handler += lap.saturating_sub(writes);
+        running.timings.write += writes;
+        Some(running.timings)
+    }
+}
+
+impl Running {
+    /// Returns the time since the last call and starts the next phase
+    fn lap(&mut self) -> Duration {
+        let now = Instant::now();
+        let elapsed = now - self.lap;
+        self.lap = now;
+        elapsed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let timings = PhaseTimings {
+            parse: Duration::from_micros(100),
+            route: Duration::from_micros(20),
+            handler: Duration::from_millis(45),
+            write: Duration::from_micros(3004),
+        };
+        assert_eq!(
+            timings.to_string(),
+            "parse=0.1ms route=0.02ms handler=45ms write=3ms"
+        );
+        assert_eq!(
+            PhaseTimings::default().to_string(),
+            "parse=0ms route=0ms handler=0ms write=0ms"
+        );
+    }
+
+    #[test]
+    fn test_disabled() {
+        let mut timer = PhaseTimer::start(false);
+        timer.parsed();
+        timer.routed();
+        assert!(timer.write_clock().is_none());
+        assert_eq!(timer.finish(), None);
+    }
+}
@@ -0,0 +1,732 @@
+use crate::stream::Stream;
+#[cfg(feature = "tls")]
+use rustls::pki_types::pem::PemObject;
+#[cfg(feature = "tls")]
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+#[cfg(feature = "tls")]
+use rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
+#[cfg(feature = "tls")]
+use rustls::sign::CertifiedKey;
+#[cfg(feature = "tls")]
+use rustls::{RootCertStore, ServerConfig, ServerConnection, StreamOwned};
+#[cfg(feature = "tls")]
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+/// How long a client may stall the handshake before it is dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Certificate chain and private key the server presents to clients
+///
+/// Everything is parsed and checked when the config is created, so a broken
+/// certificate is noticed at startup instead of at the first connection.
+///
+/// Clones share their certificates: `reload` on any clone changes what all
+/// of them present to new clients.
+///
+/// The constructor decides which TLS library serves the connections:
+/// `from_pem_files` uses rustls (`tls` feature) and `from_pkcs12_file` uses
+/// the system's library (`native-tls` feature). If both features are enabled
+/// either can be picked at runtime.
+#[derive(Clone)]
+pub struct TlsConfig {
+    backend: Arc<Backend>,
+}
+
+/// The TLS library in use, with the files to reload its configuration from
+enum Backend {
+    #[cfg(feature = "tls")]
+    Rustls {
+        sources: TlsConfigBuilder,
+        current: RwLock<Arc<ServerConfig>>,
+    },
+    #[cfg(feature = "native-tls")]
+    Native {
+        path: PathBuf,
+        password: String,
+        current: RwLock<native_tls::TlsAcceptor>,
+    },
+}
+
+impl TlsConfig {
+    /// Reads a PEM encoded certificate chain and private key for rustls
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use corrodedweb::{Server, TlsConfig};
+    /// let mut s = Server::new();
+    /// s.set_tls(TlsConfig::from_pem_files("cert.pem", "key.pem").unwrap());
+    /// ```
+    #[cfg(feature = "tls")]
+    pub fn from_pem_files<P: AsRef<Path>, Q: AsRef<Path>>(
+        cert_path: P,
+        key_path: Q,
+    ) -> Result<TlsConfig, TlsError> {
+        TlsConfig::new().default_cert(cert_path, key_path).build()
+    }
+
+    /// Starts a rustls configuration presenting different certificates
+    /// depending on the hostname the client asks for via SNI
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use corrodedweb::{Server, TlsConfig};
+    /// let mut s = Server::new();
+    /// let tls = TlsConfig::new()
+    ///     .add_cert("a.example.com", "a/cert.pem", "a/key.pem")
+    ///     .add_cert("b.example.com", "b/cert.pem", "b/key.pem")
+    ///     .default_cert("a/cert.pem", "a/key.pem")
+    ///     .build()
+    ///     .unwrap();
+    /// s.set_tls(tls);
+    /// ```
+    #[cfg(feature = "tls")]
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new() -> TlsConfigBuilder {
+        TlsConfigBuilder {
+            certs: Vec::new(),
+            default_cert: None,
+            reject_unknown_hostnames: false,
+            client_auth: None,
+        }
+    }
+
+    /// Reads a PKCS#12 archive holding the certificate chain and private key
+    /// for the system's TLS library
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use corrodedweb::{Server, TlsConfig};
+    /// let mut s = Server::new();
+    /// s.set_tls(TlsConfig::from_pkcs12_file("identity.p12", "secret").unwrap());
+    /// ```
+    #[cfg(feature = "native-tls")]
+    pub fn from_pkcs12_file<P: AsRef<Path>>(
+        path: P,
+        password: &str,
+    ) -> Result<TlsConfig, TlsError> {
+        let path = path.as_ref();
+        let acceptor = load_pkcs12(path, password)?;
+        Ok(TlsConfig {
+            backend: Arc::new(Backend::Native {
+                path: path.to_path_buf(),
+                password: String::from(password),
+                current: RwLock::new(acceptor),
+            }),
+        })
+    }
+
+    /// Reads all certificate and key files again and presents the new
+    /// certificates to clients connecting from now on
+    ///
+    /// Established connections keep the certificates they were started with.
+    /// If anything cannot be read or does not fit, nothing changes.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use corrodedweb::{Server, TlsConfig};
+    /// let mut s = Server::new();
+    /// let tls = TlsConfig::from_pem_files("cert.pem", "key.pem").unwrap();
+    /// s.set_tls(tls.clone());
+    /// // Later, after the files were renewed
+    /// tls.reload().unwrap();
+    /// ```
+    pub fn reload(&self) -> Result<(), TlsError> {
+        match &*self.backend {
+            #[cfg(feature = "tls")]
+            Backend::Rustls { sources, current } => {
+                let config = sources.server_config()?;
+                *current.write().unwrap() = Arc::new(config);
+            }
+            #[cfg(feature = "native-tls")]
+            Backend::Native {
+                path,
+                password,
+                current,
+            } => {
+                let acceptor = load_pkcs12(path, password)?;
+                *current.write().unwrap() = acceptor;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the latest modification time of the files the config was read
+    /// from, `None` if none of them can be inspected
+    pub(crate) fn files_modified(&self) -> Option<SystemTime> {
+        let paths: Vec<&Path> = match &*self.backend {
+            #[cfg(feature = "tls")]
+            Backend::Rustls { sources, .. } => sources.paths(),
+            #[cfg(feature = "native-tls")]
+            Backend::Native { path, .. } => vec![path],
+        };
+        paths
+            .into_iter()
+            .filter_map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+            .max()
+    }
+
+    /// Performs the handshake with a freshly accepted client, restoring its
+    /// write timeout afterwards
+    pub(crate) fn accept(&self, tcp: TcpStream) -> io::Result<Accepted> {
+        let write_timeout = tcp.write_timeout()?;
+        tcp.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        tcp.set_write_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        match &*self.backend {
+            #[cfg(feature = "tls")]
+            Backend::Rustls { current, .. } => {
+                let config = current.read().unwrap().clone();
+                let mut tcp = tcp;
+                let mut connection = ServerConnection::new(config).map_err(io::Error::other)?;
+                while connection.is_handshaking() {
+                    connection.complete_io(&mut tcp)?;
+                }
+                tcp.set_read_timeout(None)?;
+                tcp.set_write_timeout(write_timeout)?;
+                #[cfg(feature = "http2")]
+                {
+                    if connection.alpn_protocol() == Some(b"h2") {
+                        return Ok(Accepted::Http2(Box::new(connection), tcp));
+                    }
+                }
+                Ok(Accepted::Http1(Stream::Tls(Box::new(StreamOwned::new(
+                    connection, tcp,
+                )))))
+            }
+            #[cfg(feature = "native-tls")]
+            Backend::Native { current, .. } => {
+                let acceptor = current.read().unwrap().clone();
+                let stream = acceptor
+                    .accept(tcp)
+                    .map_err(|e| io::Error::other(e.to_string()))?;
+                stream.get_ref().set_read_timeout(None)?;
+                stream.get_ref().set_write_timeout(write_timeout)?;
+                Ok(Accepted::Http1(Stream::NativeTls(Box::new(stream))))
+            }
+        }
+    }
+}
+
+/// A connection after a successful handshake
+pub(crate) enum Accepted {
+    /// The client speaks HTTP/1.1
+    Http1(Stream),
+    /// The client negotiated HTTP/2 via ALPN
+    #[cfg(feature = "http2")]
+    Http2(Box<ServerConnection>, TcpStream),
+}
+
+/// Collects the certificates of a rustls [`TlsConfig`]
+#[cfg(feature = "tls")]
+#[derive(Clone)]
+pub struct TlsConfigBuilder {
+    certs: Vec<(String, PathBuf, PathBuf)>,
+    default_cert: Option<(PathBuf, PathBuf)>,
+    reject_unknown_hostnames: bool,
+    client_auth: Option<ClientAuth>,
+}
+
+/// Whether and how clients authenticate with a certificate
+#[cfg(feature = "tls")]
+#[derive(Clone)]
+enum ClientAuth {
+    /// Clients without a certificate signed by the CAs fail the handshake
+    Required(PathBuf),
+    /// Clients may go without a certificate, but one they present must be
+    /// signed by the CAs
+    Optional(PathBuf),
+}
+
+#[cfg(feature = "tls")]
+impl TlsConfigBuilder {
+    /// Presents this certificate chain to clients asking for `hostname`
+    pub fn add_cert<P: AsRef<Path>, Q: AsRef<Path>>(
+        mut self,
+        hostname: &str,
+        cert_path: P,
+        key_path: Q,
+    ) -> Self {
+        self.certs.push((
+            hostname.to_lowercase(),
+            cert_path.as_ref().to_path_buf(),
+            key_path.as_ref().to_path_buf(),
+        ));
+        self
+    }
+
+    /// Presents this certificate chain to clients that do not send a
+    /// hostname, and to those asking for an unknown one unless they are
+    /// rejected
+    pub fn default_cert<P: AsRef<Path>, Q: AsRef<Path>>(
+        mut self,
+        cert_path: P,
+        key_path: Q,
+    ) -> Self {
+        self.default_cert = Some((
+            cert_path.as_ref().to_path_buf(),
+            key_path.as_ref().to_path_buf(),
+        ));
+        self
+    }
+
+    /// Sets whether clients asking for a hostname without a certificate fail
+    /// the handshake instead of getting the default certificate
+    pub fn reject_unknown_hostnames(mut self, reject: bool) -> Self {
+        self.reject_unknown_hostnames = reject;
+        self
+    }
+
+    /// Requires clients to present a certificate signed by one of the CAs in
+    /// the PEM encoded `ca_bundle`, otherwise the handshake fails
+    ///
+    /// Handlers see the verified certificate via `Request::client_certificate`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use corrodedweb::{Server, TlsConfig};
+    /// let mut s = Server::new();
+    /// let tls = TlsConfig::new()
+    ///     .default_cert("cert.pem", "key.pem")
+    ///     .require_client_cert("clients-ca.pem")
+    ///     .build()
+    ///     .unwrap();
+    /// s.set_tls(tls);
+    /// ```
+    pub fn require_client_cert<P: AsRef<Path>>(mut self, ca_bundle: P) -> Self {
+        self.client_auth = Some(ClientAuth::Required(ca_bundle.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Asks clients for a certificate signed by one of the CAs in the PEM
+    /// encoded `ca_bundle`, but lets them connect without one
+    ///
+    /// A certificate that is presented but not signed by the CAs still fails
+    /// the handshake.
+    pub fn request_client_cert<P: AsRef<Path>>(mut self, ca_bundle: P) -> Self {
+        self.client_auth = Some(ClientAuth::Optional(ca_bundle.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Reads and checks all certificates and keys
+    pub fn build(self) -> Result<TlsConfig, TlsError> {
+        let config = self.server_config()?;
+        Ok(TlsConfig {
+            backend: Arc::new(Backend::Rustls {
+                sources: self,
+                current: RwLock::new(Arc::new(config)),
+            }),
+        })
+    }
+
+    /// Returns every file the configuration is read from
+    fn paths(&self) -> Vec<&Path> {
+        let mut paths = Vec::new();
+        for (_, cert_path, key_path) in &self.certs {
+            paths.push(cert_path.as_path());
+            paths.push(key_path.as_path());
+        }
+        if let Some((cert_path, key_path)) = &self.default_cert {
+            paths.push(cert_path.as_path());
+            paths.push(key_path.as_path());
+        }
+        match &self.client_auth {
+            Some(ClientAuth::Required(ca_bundle)) | Some(ClientAuth::Optional(ca_bundle)) => {
+                paths.push(ca_bundle.as_path())
+            }
+            None => {}
+        }
+        paths
+    }
+
+    /// Reads the files into a rustls configuration
+    fn server_config(&self) -> Result<ServerConfig, TlsError> {
+        let mut resolver = SniResolver {
+            certs: HashMap::new(),
+            default_cert: None,
+            reject_unknown_hostnames: self.reject_unknown_hostnames,
+        };
+        for (hostname, cert_path, key_path) in &self.certs {
+            let key = load_certified_key(cert_path, key_path)?;
+            resolver.certs.insert(hostname.clone(), key);
+        }
+        if let Some((cert_path, key_path)) = &self.default_cert {
+            resolver.default_cert = Some(load_certified_key(cert_path, key_path)?);
+        }
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| TlsError::Rejected {
+                message: e.to_string(),
+            })?;
+        let builder = match &self.client_auth {
+            Some(ClientAuth::Required(ca_bundle)) | Some(ClientAuth::Optional(ca_bundle)) => {
+                let roots = Arc::new(load_ca_bundle(ca_bundle)?);
+                let mut verifier = WebPkiClientVerifier::builder_with_provider(roots, provider);
+                if let Some(ClientAuth::Optional(_)) = self.client_auth {
+                    verifier = verifier.allow_unauthenticated();
+                }
+                let verifier = verifier.build().map_err(|e| TlsError::Rejected {
+                    message: e.to_string(),
+                })?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        #[allow(unused_mut)]
+        let mut config = builder.with_cert_resolver(Arc::new(resolver));
+        #[cfg(feature = "http2")]
+        {
+            config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        }
+        Ok(config)
+    }
+}
+
+/// Reads a PEM encoded certificate chain and the matching private key
+#[cfg(feature = "tls")]
+fn load_certified_key(cert_path: &Path, key_path: &Path) -> Result<Arc<CertifiedKey>, TlsError> {
+    let read_error = |path: &Path, error: rustls::pki_types::pem::Error| TlsError::Read {
+        path: path.to_path_buf(),
+        message: error.to_string(),
+    };
+    let rejected = |error: rustls::Error| TlsError::Rejected {
+        message: error.to_string(),
+    };
+
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| read_error(cert_path, e))?;
+    if certs.is_empty() {
+        return Err(TlsError::NoCertificate {
+            path: cert_path.to_path_buf(),
+        });
+    }
+    let key = PrivateKeyDer::from_pem_file(key_path).map_err(|e| match e {
+        rustls::pki_types::pem::Error::NoItemsFound => TlsError::NoPrivateKey {
+            path: key_path.to_path_buf(),
+        },
+        e => read_error(key_path, e),
+    })?;
+
+    let key = rustls::crypto::ring::sign::any_supported_type(&key).map_err(rejected)?;
+    let certified = CertifiedKey::new(certs, key);
+    certified.keys_match().map_err(rejected)?;
+    Ok(Arc::new(certified))
+}
+
+/// Reads a PKCS#12 archive into an acceptor of the system's TLS library
+#[cfg(feature = "native-tls")]
+fn load_pkcs12(path: &Path, password: &str) -> Result<native_tls::TlsAcceptor, TlsError> {
+    let archive = std::fs::read(path).map_err(|e| TlsError::Read {
+        path: path.to_path_buf(),
+        message: e.to_string(),
+    })?;
+    native_tls::Identity::from_pkcs12(&archive, password)
+        .and_then(native_tls::TlsAcceptor::new)
+        .map_err(|e| TlsError::Rejected {
+            message: e.to_string(),
+        })
+}
+
+/// Reads the PEM encoded CA certificates client certificates are checked
+/// against
+#[cfg(feature = "tls")]
+fn load_ca_bundle(path: &Path) -> Result<RootCertStore, TlsError> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| TlsError::Read {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        })?;
+    if certs.is_empty() {
+        return Err(TlsError::NoCertificate {
+            path: path.to_path_buf(),
+        });
+    }
+    let mut roots = RootCertStore::empty();
+    for cert in certs {
+        roots.add(cert).map_err(|e| TlsError::Rejected {
+            message: e.to_string(),
+        })?;
+    }
+    Ok(roots)
+}
+
+/// A certificate a client authenticated with, see
+/// `TlsConfigBuilder::require_client_cert`
+#[cfg(feature = "tls")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientCertificate {
+    /// The distinguished name of the subject, e.g. `CN=admin, O=Example`
+    pub subject: String,
+    /// DNS names, email addresses and URIs of the subject alternative name
+    /// extension
+    pub subject_alt_names: Vec<String>,
+    /// The DER encoded certificate
+    pub der: Vec<u8>,
+}
+
+#[cfg(feature = "tls")]
+impl ClientCertificate {
+    /// Extracts the names of the leaf certificate a client presented
+    pub(crate) fn from_connection(connection: &ServerConnection) -> Option<Self> {
+        use x509_parser::extensions::GeneralName;
+
+        let der = connection.peer_certificates()?.first()?;
+        let (_, cert) = x509_parser::parse_x509_certificate(der).ok()?;
+        let subject_alt_names = match cert.subject_alternative_name() {
+            Ok(Some(extension)) => extension
+                .value
+                .general_names
+                .iter()
+                .filter_map(|name| match name {
+                    GeneralName::DNSName(name)
+                    | GeneralName::RFC822Name(name)
+                    | GeneralName::URI(name) => Some(String::from(*name)),
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        Some(ClientCertificate {
+            subject: cert.subject().to_string(),
+            subject_alt_names,
+            der: der.to_vec(),
+        })
+    }
+}
+
+/// Picks the certificate for the hostname the client sent via SNI
+#[cfg(feature = "tls")]
+#[derive(Debug)]
+struct SniResolver {
+    certs: HashMap<String, Arc<CertifiedKey>>,
+    default_cert: Option<Arc<CertifiedKey>>,
+    reject_unknown_hostnames: bool,
+}
+
+#[cfg(feature = "tls")]
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        match client_hello.server_name() {
+            Some(hostname) => match self.certs.get(&hostname.to_lowercase()) {
+                Some(cert) => Some(cert.clone()),
+                None if self.reject_unknown_hostnames => None,
+                None => self.default_cert.clone(),
+            },
+            None => self.default_cert.clone(),
+        }
+    }
+}
+
+/// Why a TLS configuration could not be created
+#[derive(Debug)]
+pub enum TlsError {
+    /// A file could not be read or is not valid PEM
+    Read { path: PathBuf, message: String },
+    /// The certificate file contains no certificate
+    NoCertificate { path: PathBuf },
+    /// The key file contains no private key
+    NoPrivateKey { path: PathBuf },
+    /// The certificate and key were refused, e.g. because they do not belong
+    /// together
+    Rejected { message: String },
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TlsError::Read { path, message } => {
+                write!(f, "could not read {}: {}", path.display(), message)
+            }
+            TlsError::NoCertificate { path } => {
+                write!(f, "no certificate found in {}", path.display())
+            }
+            TlsError::NoPrivateKey { path } => {
+                write!(f, "no private key found in {}", path.display())
+            }
+            TlsError::Rejected { message } => write!(f, "invalid certificate or key: {}", message),
+        }
+    }
+}
+
+impl Error for TlsError {}
+
+/// Writes a fresh self-signed certificate for `localhost` and its key to the
+/// temp directory and returns both paths
+#[cfg(all(test, feature = "tls"))]
+pub(crate) fn write_self_signed(name: &str) -> (PathBuf, PathBuf) {
+    write_self_signed_for(name, "localhost")
+}
+
+/// Writes a fresh self-signed certificate for `hostname` and its key to the
+/// temp directory and returns both paths
+#[cfg(all(test, feature = "tls"))]
+pub(crate) fn write_self_signed_for(name: &str, hostname: &str) -> (PathBuf, PathBuf) {
+    let rcgen::CertifiedKey { cert, key_pair } =
+        rcgen::generate_simple_self_signed(vec![String::from(hostname)]).unwrap();
+    let cert_path = std::env::temp_dir().join(format!("corrodedweb_{}_cert.pem", name));
+    let key_path = std::env::temp_dir().join(format!("corrodedweb_{}_key.pem", name));
+    std::fs::write(&cert_path, cert.pem()).unwrap();
+    std::fs::write(&key_path, key_pair.serialize_pem()).unwrap();
+    (cert_path, key_path)
+}
+
+/// Client certificates written by `write_client_certs`
+#[cfg(all(test, feature = "tls"))]
+pub(crate) struct ClientCertFiles {
+    /// The CA bundle the server trusts
+    pub ca: PathBuf,
+    /// Certificate and key signed by the trusted CA
+    pub valid: (PathBuf, PathBuf),
+    /// Certificate and key signed by an unrelated CA
+    pub untrusted: (PathBuf, PathBuf),
+}
+
+/// Writes a fresh CA and two client certificates for `admin`, one signed by
+/// that CA and one by another, to the temp directory
+#[cfg(all(test, feature = "tls"))]
+pub(crate) fn write_client_certs(name: &str) -> ClientCertFiles {
+    use rcgen::{
+        BasicConstraints, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa, KeyPair,
+    };
+
+    let write = |file: &str, content: String| {
+        let path = std::env::temp_dir().join(format!("corrodedweb_{}_{}.pem", name, file));
+        std::fs::write(&path, content).unwrap();
+        path
+    };
+    let ca = || {
+        let mut params = CertificateParams::new(Vec::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "Test CA");
+        let key = KeyPair::generate().unwrap();
+        (params.self_signed(&key).unwrap(), key)
+    };
+    let client = |file: &str, (ca_cert, ca_key): &(rcgen::Certificate, KeyPair)| {
+        let mut params = CertificateParams::new(vec![String::from("admin.example.com")]).unwrap();
+        params.distinguished_name.push(DnType::CommonName, "admin");
+        params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+        let key = KeyPair::generate().unwrap();
+        let cert = params.signed_by(&key, ca_cert, ca_key).unwrap();
+        (
+            write(&format!("{}_cert", file), cert.pem()),
+            write(&format!("{}_key", file), key.serialize_pem()),
+        )
+    };
+
+    let trusted = ca();
+    let other = ca();
+    ClientCertFiles {
+        ca: write("ca", trusted.0.pem()),
+        valid: client("valid", &trusted),
+        untrusted: client("untrusted", &other),
+    }
+}
+
+/// Writes a fresh self-signed certificate for `localhost` and its key as a
+/// PKCS#12 archive protected by `secret` to the temp directory
+#[cfg(all(test, feature = "native-tls"))]
+pub(crate) fn write_self_signed_pkcs12(name: &str) -> PathBuf {
+    use openssl::pkcs12::Pkcs12;
+    use openssl::pkey::PKey;
+    use openssl::x509::X509;
+
+    let rcgen::CertifiedKey { cert, key_pair } =
+        rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap();
+    let cert = X509::from_pem(cert.pem().as_bytes()).unwrap();
+    let key = PKey::private_key_from_pem(key_pair.serialize_pem().as_bytes()).unwrap();
+    let archive = Pkcs12::builder()
+        .name("localhost")
+        .pkey(&key)
+        .cert(&cert)
+        .build2("secret")
+        .unwrap();
+    let path = std::env::temp_dir().join(format!("corrodedweb_{}.p12", name));
+    std::fs::write(&path, archive.to_der().unwrap()).unwrap();
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "native-tls")]
+    #[test]
+    fn test_from_pkcs12_file() {
+        let path = write_self_signed_pkcs12("from_pkcs12_file");
+        let config = TlsConfig::from_pkcs12_file(&path, "secret").unwrap();
+        assert!(config.reload().is_ok());
+        assert!(matches!(
+            TlsConfig::from_pkcs12_file(&path, "wrong"),
+            Err(TlsError::Rejected { .. })
+        ));
+        let missing = std::env::temp_dir().join("corrodedweb_missing.p12");
+        assert!(matches!(
+            TlsConfig::from_pkcs12_file(&missing, "secret"),
+            Err(TlsError::Read { .. })
+        ));
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(config.reload(), Err(TlsError::Read { .. })));
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn test_from_pem_files() {
+        let (cert, key) = write_self_signed("from_pem_files");
+        assert!(TlsConfig::from_pem_files(&cert, &key).is_ok());
+
+        let missing = std::env::temp_dir().join("corrodedweb_missing.pem");
+        assert!(matches!(
+            TlsConfig::from_pem_files(&missing, &key),
+            Err(TlsError::Read { .. })
+        ));
+        assert!(matches!(
+            TlsConfig::from_pem_files(&key, &key),
+            Err(TlsError::NoCertificate { .. })
+        ));
+        assert!(matches!(
+            TlsConfig::from_pem_files(&cert, &cert),
+            Err(TlsError::NoPrivateKey { .. })
+        ));
+
+        let (_, other_key) = write_self_signed("from_pem_files_other");
+        assert!(matches!(
+            TlsConfig::from_pem_files(&cert, &other_key),
+            Err(TlsError::Rejected { .. })
+        ));
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn test_client_cert_bundle() {
+        let (cert, key) = write_self_signed("client_cert_bundle");
+        let files = write_client_certs("client_cert_bundle");
+        let builder = || TlsConfig::new().default_cert(&cert, &key);
+        assert!(builder().require_client_cert(&files.ca).build().is_ok());
+        assert!(builder().request_client_cert(&files.ca).build().is_ok());
+        assert!(matches!(
+            builder().require_client_cert(&key).build(),
+            Err(TlsError::NoCertificate { .. })
+        ));
+        let missing = std::env::temp_dir().join("corrodedweb_missing_ca.pem");
+        assert!(matches!(
+            builder().require_client_cert(&missing).build(),
+            Err(TlsError::Read { .. })
+        ));
+    }
+}
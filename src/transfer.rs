@@ -0,0 +1,310 @@
+use crate::stream::Stream;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::{IoSlice, SeekFrom};
+
+/// Size of the userspace buffer used by the portable copy
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// How much of a file goes out together with the response head
+const FIRST_CHUNK_SIZE: u64 = 16 * 1024;
+
+/// Returns whether a write failed because the client closed the connection
+pub(crate) fn is_disconnect(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::BrokenPipe
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+    )
+}
+
+/// Why a file could not be sent
+#[derive(Debug)]
+pub(crate) enum SendError {
+    /// Reading the file failed before anything was written, so the client
+    /// can still be answered with an error
+    Unsent(io::Error),
+    /// Failed after the head was written, e.g. because the client went away
+    Partial(io::Error),
+}
+
+/// Writes the response head followed by the body, in a single syscall if the
+/// writer takes both at once
+pub(crate) fn write_head_and_body<W: Write>(
+    writer: &mut W,
+    head: &[u8],
+    body: &[u8],
+) -> io::Result<()> {
+    let written = loop {
+        match writer.write_vectored(&[IoSlice::new(head), IoSlice::new(body)]) {
+            Ok(written) => break written,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    };
+    // Short writes continue sequentially with whatever is left
+    if written < head.len() {
+        writer.write_all(&head[written..])?;
+        writer.write_all(body)?;
+    } else {
+        writer.write_all(&body[written - head.len()..])?;
+    }
+    writer.flush()
+}
+
+/// Writes the response head followed by `len` bytes of `file`, starting at
+/// `offset`, to the client
+///
+/// The head goes out together with the first chunk of the file. On Linux the
+/// kernel moves the rest of a plain connection with `sendfile(2)` so it never
+/// passes through userspace. For TLS, on other systems, or if the kernel
+/// refuses, the file is copied in chunks. Returns the number of file bytes written.
+pub(crate) fn send_file(
+    file: &mut File,
+    stream: &mut Stream,
+    head: &[u8],
+    offset: u64,
+    len: u64,
+) -> Result<u64, SendError> {
+    let mut first = Vec::new();
+    file.seek(SeekFrom::Start(offset))
+        .and_then(|_| file.take(len.min(FIRST_CHUNK_SIZE)).read_to_end(&mut first))
+        .map_err(SendError::Unsent)?;
+    send_rest(file, stream, head, &first, offset, len).map_err(SendError::Partial)
+}
+
+/// Writes the head and the first chunk of the file, then the rest of it
+fn send_rest(
+    file: &mut File,
+    stream: &mut Stream,
+    head: &[u8],
+    first: &[u8],
+    offset: u64,
+    len: u64,
+) -> io::Result<u64> {
+    write_head_and_body(stream, head, first)?;
+
+    let sent = first.len() as u64;
+    if sent == len || sent < len.min(FIRST_CHUNK_SIZE) {
+        return Ok(sent);
+    }
+    // Encrypted streams need to see the bytes, only plain ones can skip
+    // userspace
+    #[cfg(target_os = "linux")]
+    #[allow(irrefutable_let_patterns)]
+    {
+        if let Stream::Plain(tcp)
+        | Stream::KeptOpen(crate::stream::KeptOpen { tcp, tls: None, .. }) = stream
+        {
+            if let Some(rest) = linux::sendfile(file, tcp, offset + sent, len - sent)? {
+                return Ok(sent + rest);
+            }
+        }
+    }
+    Ok(sent + copy_chunked(file, stream, offset + sent, len - sent)?)
+}
+
+/// Copies `len` bytes of `file`, starting at `offset`, through a userspace
+/// buffer
+///
+/// This is the path to use whenever the bytes have to be seen on their way
+/// out, e.g. when they get encrypted or compressed.
+pub(crate) fn copy_chunked<W: Write>(
+    file: &mut File,
+    writer: &mut W,
+    offset: u64,
+    len: u64,
+) -> io::Result<u64> {
+    file.seek(SeekFrom::Start(offset))?;
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let mut remaining = len;
+    while remaining > 0 {
+        let wanted = remaining.min(CHUNK_SIZE as u64) as usize;
+        let read = file.read(&mut buffer[..wanted])?;
+        if read == 0 {
+            break;
+        }
+        writer.write_all(&buffer[..read])?;
+        remaining -= read as u64;
+    }
+    writer.flush()?;
+    Ok(len - remaining)
+}
+
+/// Smallest file that is worth the cost of setting up a mapping
+#[cfg(feature = "mmap")]
+const MMAP_MIN_LEN: u64 = 128 * 1024;
+
+/// Largest file that gets mapped as a whole
+#[cfg(feature = "mmap")]
+const MMAP_MAX_LEN: u64 = 256 * 1024 * 1024;
+
+/// Writes the response head followed by `len` bytes of `file`, starting at
+/// `offset`, straight from a memory map
+///
+/// Returns `None` without writing anything if the file is empty, outside the
+/// size window or cannot be mapped, so the caller can fall back to
+/// `send_file`. The file must not be truncated while it is mapped, otherwise
+/// reading the missing pages raises `SIGBUS`.
+#[cfg(feature = "mmap")]
+pub(crate) fn send_mapped<W: Write>(
+    file: &File,
+    writer: &mut W,
+    head: &[u8],
+    offset: u64,
+    len: u64,
+) -> Result<Option<u64>, SendError> {
+    let file_len = file.metadata().map_err(SendError::Unsent)?.len();
+    if !(MMAP_MIN_LEN..=MMAP_MAX_LEN).contains(&file_len) {
+        return Ok(None);
+    }
+    // Safety: the mapping is only read and is dropped before returning. A
+    // concurrent truncation is documented as unsupported.
+    let map = match unsafe { memmap2::Mmap::map(file) } {
+        Ok(map) => map,
+        Err(_) => return Ok(None),
+    };
+    let start = offset.min(file_len) as usize;
+    let end = offset.saturating_add(len).min(file_len) as usize;
+    write_head_and_body(writer, head, &map[start..end]).map_err(SendError::Partial)?;
+    Ok(Some((end - start) as u64))
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::fs::File;
+    use std::io;
+    use std::net::TcpStream;
+    use std::os::unix::io::AsRawFd;
+
+    /// Largest count a single `sendfile` call transfers on Linux
+    const MAX_CHUNK: u64 = 0x7fff_f000;
+
+    /// Sends the file with `sendfile(2)`, returning `None` if the kernel does
+    /// not support it for these descriptors before anything was sent
+    pub(super) fn sendfile(
+        file: &File,
+        stream: &TcpStream,
+        offset: u64,
+        len: u64,
+    ) -> io::Result<Option<u64>> {
+        let mut position = offset as libc::off_t;
+        let mut remaining = len;
+        while remaining > 0 {
+            let count = remaining.min(MAX_CHUNK) as usize;
+            // Safety: both descriptors stay open for the duration of the call
+            // and `position` is a valid, exclusively borrowed offset
+            let sent = unsafe {
+                libc::sendfile(stream.as_raw_fd(), file.as_raw_fd(), &mut position, count)
+            };
+            if sent < 0 {
+                let error = io::Error::last_os_error();
+                match error.raw_os_error() {
+                    Some(libc::EINTR) => continue,
+                    Some(libc::EINVAL) | Some(libc::ENOSYS) if remaining == len => return Ok(None),
+                    _ => return Err(error),
+                }
+            }
+            if sent == 0 {
+                // The file got shorter than its metadata claimed
+                break;
+            }
+            remaining -= sent as u64;
+        }
+        Ok(Some(len - remaining))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    #[test]
+    fn test_send_file_range() {
+        let path = std::env::temp_dir().join("corrodedweb_send_file_range.txt");
+        fs::write(&path, "0123456789abcdefghij").unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            let mut received = String::new();
+            stream.read_to_string(&mut received).unwrap();
+            received
+        });
+
+        let (stream, _) = listener.accept().unwrap();
+        let mut stream = Stream::Plain(stream);
+        let mut file = File::open(&path).unwrap();
+        assert_eq!(
+            send_file(&mut file, &mut stream, b"head:", 5, 10).unwrap(),
+            10
+        );
+        assert_eq!(copy_chunked(&mut file, &mut stream, 15, 10).unwrap(), 5);
+        drop(stream);
+
+        assert_eq!(client.join().unwrap(), "head:56789abcdefghij");
+    }
+
+    /// Accepts at most `limit` bytes per write to force short writes
+    struct Trickle {
+        written: Vec<u8>,
+        limit: usize,
+    }
+
+    impl Write for Trickle {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            let len = data.len().min(self.limit);
+            self.written.extend_from_slice(&data[..len]);
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_head_and_body() {
+        for limit in 1..12 {
+            let mut writer = Trickle {
+                written: Vec::new(),
+                limit,
+            };
+            write_head_and_body(&mut writer, b"head\r\n", b"body").unwrap();
+            assert_eq!(writer.written, b"head\r\nbody");
+        }
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_send_mapped() {
+        let path = std::env::temp_dir().join("corrodedweb_send_mapped.bin");
+        let content: Vec<u8> = (0..MMAP_MIN_LEN as u32).map(|i| (i % 251) as u8).collect();
+        fs::write(&path, &content).unwrap();
+        let file = File::open(&path).unwrap();
+
+        let mut whole = Vec::new();
+        let sent = send_mapped(&file, &mut whole, b"", 0, MMAP_MIN_LEN).unwrap();
+        assert_eq!(sent, Some(MMAP_MIN_LEN));
+        assert!(whole == content);
+
+        let mut range = Vec::new();
+        let sent = send_mapped(&file, &mut range, b"head:", 1000, 500).unwrap();
+        assert_eq!(sent, Some(500));
+        assert!(range[..5] == b"head:"[..]);
+        assert!(range[5..] == content[1000..1500]);
+
+        let empty = std::env::temp_dir().join("corrodedweb_send_mapped_empty.bin");
+        fs::write(&empty, "").unwrap();
+        let file = File::open(&empty).unwrap();
+        let mut nothing = Vec::new();
+        assert_eq!(send_mapped(&file, &mut nothing, b"", 0, 0).unwrap(), None);
+        assert!(nothing.is_empty());
+    }
+}